@@ -4,7 +4,7 @@
 //! with different configurations to track performance regressions.
 
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
-use mopaq::{compression::flags, Archive, ArchiveBuilder, FormatVersion};
+use mopaq::{compression::flags, Archive, ArchiveBuilder, FormatVersion, OpenOptions};
 use std::hint::black_box;
 use std::path::Path;
 use tempfile::TempDir;
@@ -89,7 +89,7 @@ fn bench_single_file_extraction(c: &mut Criterion) {
             &archive_path,
             |b, path| {
                 b.iter(|| {
-                    let mut archive = Archive::open(black_box(path)).unwrap();
+                    let archive = Archive::open(black_box(path)).unwrap();
                     let extracted = archive.read_file("test_file.dat").unwrap();
                     black_box(extracted);
                 });
@@ -130,7 +130,7 @@ fn bench_compressed_extraction(c: &mut Criterion) {
             &archive_path,
             |b, path| {
                 b.iter(|| {
-                    let mut archive = Archive::open(black_box(path)).unwrap();
+                    let archive = Archive::open(black_box(path)).unwrap();
                     let extracted = archive.read_file("test.dat").unwrap();
                     black_box(extracted);
                 });
@@ -235,7 +235,7 @@ fn bench_random_access(c: &mut Criterion) {
         &(&archive_path, &sequential),
         |b, (path, filenames)| {
             b.iter(|| {
-                let mut archive = Archive::open(black_box(path)).unwrap();
+                let archive = Archive::open(black_box(path)).unwrap();
                 for filename in filenames.iter() {
                     let data = archive.read_file(filename).unwrap();
                     black_box(data);
@@ -249,7 +249,7 @@ fn bench_random_access(c: &mut Criterion) {
         &(&archive_path, &random),
         |b, (path, filenames)| {
             b.iter(|| {
-                let mut archive = Archive::open(black_box(path)).unwrap();
+                let archive = Archive::open(black_box(path)).unwrap();
                 for filename in filenames.iter() {
                     let data = archive.read_file(filename).unwrap();
                     black_box(data);
@@ -291,7 +291,7 @@ fn bench_version_extraction(c: &mut Criterion) {
             &archive_path,
             |b, path| {
                 b.iter(|| {
-                    let mut archive = Archive::open(black_box(path)).unwrap();
+                    let archive = Archive::open(black_box(path)).unwrap();
                     let extracted = archive.read_file("test.dat").unwrap();
                     black_box(extracted);
                 });
@@ -332,7 +332,7 @@ fn bench_parallel_extraction(c: &mut Criterion) {
     // Single-threaded baseline
     group.bench_function("single_thread", |b| {
         b.iter(|| {
-            let mut archive = Archive::open(black_box(&archive_path)).unwrap();
+            let archive = Archive::open(black_box(&archive_path)).unwrap();
             for i in 0..file_count {
                 let filename = format!("file_{:02}.dat", i);
                 let data = archive.read_file(&filename).unwrap();
@@ -428,6 +428,73 @@ fn bench_metadata_operations(c: &mut Criterion) {
         });
     });
 
+    group.bench_function("find_files_bulk", |b| {
+        let archive = Archive::open(&archive_path).unwrap();
+        let names: Vec<String> = (0..100)
+            .map(|i| {
+                format!(
+                    "folder{}/subfolder{}/file_{:04}.dat",
+                    i / 100,
+                    (i / 10) % 10,
+                    i
+                )
+            })
+            .collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        b.iter(|| {
+            let found = archive.find_files(black_box(&names)).unwrap();
+            black_box(found.iter().flatten().count());
+        });
+    });
+
+    group.finish();
+}
+
+/// Benchmark reading a sectored file with different I/O buffer sizes
+///
+/// Small sectors are where reading ahead pays off; this is what
+/// `mopaq::default_io_buffer_size` is tuned against.
+fn bench_io_buffer_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("archive_extraction/io_buffer_size");
+
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("sectors.mpq");
+    let size = 16 * 1024 * 1024;
+    ArchiveBuilder::new()
+        .block_size(3) // 4KB sectors
+        .add_file_data_with_options(
+            generate_test_data(size, "low"),
+            "test_file.dat",
+            flags::ZLIB,
+            false,
+            0,
+        )
+        .build(&archive_path)
+        .unwrap();
+
+    group.throughput(Throughput::Bytes(size as u64));
+    for (name, buffer_size) in [
+        ("8KB", 8 * 1024),
+        ("64KB", 64 * 1024),
+        ("256KB", 256 * 1024),
+        ("1MB", 1024 * 1024),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            &buffer_size,
+            |b, &buffer_size| {
+                let archive = OpenOptions::new()
+                    .io_buffer_size(buffer_size)
+                    .open(&archive_path)
+                    .unwrap();
+                b.iter(|| {
+                    let extracted = archive.read_file("test_file.dat").unwrap();
+                    black_box(extracted);
+                });
+            },
+        );
+    }
+
     group.finish();
 }
 
@@ -439,6 +506,7 @@ criterion_group!(
     bench_random_access,
     bench_version_extraction,
     bench_parallel_extraction,
-    bench_metadata_operations
+    bench_metadata_operations,
+    bench_io_buffer_size
 );
 criterion_main!(benches);
@@ -54,11 +54,10 @@ fn main() -> Result<(), Box<dyn Error>> {
     file_attributes.push(attrs);
 
     // Create the attributes structure
-    let attributes = Attributes {
-        version: Attributes::EXPECTED_VERSION,
-        flags: AttributeFlags::new(AttributeFlags::ALL),
-        file_attributes: file_attributes.clone(),
-    };
+    let attributes = Attributes::new(
+        AttributeFlags::new(AttributeFlags::ALL),
+        file_attributes.clone(),
+    );
 
     // Convert attributes to bytes
     let attributes_data = attributes.to_bytes()?;
@@ -68,11 +67,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     file_attributes[files.len()].md5 = Some(md5(&attributes_data));
 
     // Recreate attributes with updated data
-    let attributes = Attributes {
-        version: Attributes::EXPECTED_VERSION,
-        flags: AttributeFlags::new(AttributeFlags::ALL),
-        file_attributes,
-    };
+    let attributes = Attributes::new(AttributeFlags::new(AttributeFlags::ALL), file_attributes);
     let attributes_data = attributes.to_bytes()?;
 
     // Create the archive
@@ -93,13 +93,13 @@ fn main() -> Result<()> {
             .build("test3_bzip2.mpq")?;
 
         // Verify ZLIB
-        let mut archive = Archive::open("test3_zlib.mpq")?;
+        let archive = Archive::open("test3_zlib.mpq")?;
         let data = archive.read_file("zlib.dat")?;
         assert_eq!(data, test_data);
         println!("✓ ZLIB + encryption works");
 
         // Verify BZIP2
-        let mut archive = Archive::open("test3_bzip2.mpq")?;
+        let archive = Archive::open("test3_bzip2.mpq")?;
         let data = archive.read_file("bzip2.dat")?;
         assert_eq!(data, test_data);
         println!("✓ BZIP2 + encryption works");
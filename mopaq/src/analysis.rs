@@ -0,0 +1,321 @@
+//! Archive content analysis
+//!
+//! Helpers that inspect an open archive and summarize how its files are
+//! stored, without extracting them. The results are plain data so that
+//! command line tools, GUIs and tests can present them however they like.
+
+use crate::{compression::CompressionMethod, Archive, Result};
+use std::collections::{BTreeMap, HashMap};
+
+/// Extension key used for files whose name has no extension
+pub const NO_EXTENSION: &str = "(no extension)";
+
+/// How a single file in the archive is compressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCompression {
+    /// File name as listed in the archive
+    pub name: String,
+    /// Compression method of the file's data
+    pub method: CompressionMethod,
+    /// Uncompressed size
+    pub file_size: u64,
+    /// Size stored in the archive
+    pub compressed_size: u64,
+}
+
+impl FileCompression {
+    /// Lowercase extension of the file name, or [`NO_EXTENSION`]
+    pub fn extension(&self) -> String {
+        std::path::Path::new(&self.name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .unwrap_or_else(|| NO_EXTENSION.to_string())
+    }
+}
+
+/// Number of files using one compression method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MethodCount {
+    /// Compression method
+    pub method: CompressionMethod,
+    /// Number of files using it
+    pub count: usize,
+}
+
+/// Result of [`analyze_compression`]
+#[derive(Debug, Clone, Default)]
+pub struct CompressionAnalysis {
+    /// Every listed file, in listing order
+    pub files: Vec<FileCompression>,
+    /// Files per compression method, most used first
+    pub methods: Vec<MethodCount>,
+    /// Files per compression method for each lowercase file extension
+    pub by_extension: BTreeMap<String, Vec<MethodCount>>,
+    /// Sum of the uncompressed sizes of all files
+    pub total_uncompressed_size: u64,
+    /// Sum of the stored sizes of all files
+    pub total_compressed_size: u64,
+}
+
+impl CompressionAnalysis {
+    /// Files whose compression method this crate cannot decompress
+    pub fn unsupported_files(&self) -> impl Iterator<Item = &FileCompression> {
+        self.files.iter().filter(|file| !file.method.is_supported())
+    }
+
+    /// Files per unsupported compression method, most used first
+    pub fn unsupported_methods(&self) -> Vec<MethodCount> {
+        self.methods
+            .iter()
+            .copied()
+            .filter(|entry| !entry.method.is_supported())
+            .collect()
+    }
+
+    /// Stored size as a percentage of the uncompressed size
+    ///
+    /// Returns 100 for archives without any file data.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.total_uncompressed_size > 0 {
+            self.total_compressed_size as f64 / self.total_uncompressed_size as f64 * 100.0
+        } else {
+            100.0
+        }
+    }
+}
+
+/// Summarize the compression methods used by the files in an archive
+///
+/// Each listed file's method is read from its first compressed sector with
+/// [`Archive::compression_method`], so the archive is not extracted. Files
+/// that show up in the listing but cannot be looked up are skipped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use mopaq::{analysis, Archive};
+///
+/// let mut archive = Archive::open("game.mpq")?;
+/// let analysis = analysis::analyze_compression(&mut archive)?;
+///
+/// for entry in &analysis.methods {
+///     println!("{}: {} files", entry.method, entry.count);
+/// }
+/// for file in analysis.unsupported_files() {
+///     println!("cannot decompress {} ({})", file.name, file.method);
+/// }
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+pub fn analyze_compression(archive: &mut Archive) -> Result<CompressionAnalysis> {
+    let mut analysis = CompressionAnalysis::default();
+
+    for entry in archive.list_with_hashes()? {
+        let Some(file_info) = archive.find_file(&entry.name)? else {
+            continue;
+        };
+        let method = archive.compression_method(&entry.name)?;
+
+        analysis.total_uncompressed_size += file_info.file_size;
+        analysis.total_compressed_size += file_info.compressed_size;
+        analysis.files.push(FileCompression {
+            name: entry.name,
+            method,
+            file_size: file_info.file_size,
+            compressed_size: file_info.compressed_size,
+        });
+    }
+
+    analysis.methods = histogram(analysis.files.iter());
+
+    let mut extensions: BTreeMap<String, Vec<&FileCompression>> = BTreeMap::new();
+    for file in &analysis.files {
+        extensions.entry(file.extension()).or_default().push(file);
+    }
+    analysis.by_extension = extensions
+        .into_iter()
+        .map(|(ext, files)| (ext, histogram(files.into_iter())))
+        .collect();
+
+    Ok(analysis)
+}
+
+/// Share of files a suggested sector size holds in a single sector
+pub const SINGLE_SECTOR_SHARE: f64 = 0.9;
+
+/// Block sizes [`suggest_sector_size`] chooses from: 4 KB to 128 KB sectors
+pub const SUGGESTED_BLOCK_SIZES: std::ops::RangeInclusive<u16> = 3..=8;
+
+/// What storing a set of files with one sector size costs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorSizeEstimate {
+    /// Block size, the sector size is `512 << block_size`
+    pub block_size: u16,
+    /// Sector size in bytes
+    pub sector_size: u64,
+    /// Files no larger than one sector
+    pub single_sector_files: usize,
+    /// Bytes of sector offset tables, as if every file were compressed
+    pub offset_table_bytes: u64,
+    /// Unused bytes in the last sector of every file
+    ///
+    /// Readers decompress whole sectors, so this is buffer space allocated
+    /// and not filled when every file is read.
+    pub slack_bytes: u64,
+}
+
+impl SectorSizeEstimate {
+    /// Estimate the cost of storing files of the given sizes with `block_size`
+    pub fn new(block_size: u16, file_sizes: &[u64]) -> Self {
+        let sector_size = crate::calculate_sector_size(block_size) as u64;
+        let mut estimate = Self {
+            block_size,
+            sector_size,
+            single_sector_files: 0,
+            offset_table_bytes: 0,
+            slack_bytes: 0,
+        };
+        for &size in file_sizes {
+            let sectors = size.div_ceil(sector_size).max(1);
+            if sectors == 1 {
+                estimate.single_sector_files += 1;
+            }
+            estimate.offset_table_bytes += (sectors + 1) * 4;
+            estimate.slack_bytes += sectors * sector_size - size;
+        }
+        estimate
+    }
+}
+
+/// Result of [`suggest_sector_size`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorSizeSuggestion {
+    /// Number of files the suggestion is based on
+    pub file_count: usize,
+    /// The archive's current sector size
+    pub current: SectorSizeEstimate,
+    /// The recommended sector size
+    pub suggested: SectorSizeEstimate,
+    /// Every block size in [`SUGGESTED_BLOCK_SIZES`], smallest first
+    pub candidates: Vec<SectorSizeEstimate>,
+}
+
+impl SectorSizeSuggestion {
+    /// Suggest a sector size for files of the given sizes
+    ///
+    /// The suggestion is the smallest sector size that holds at least
+    /// [`SINGLE_SECTOR_SHARE`] of the files in one sector, or the largest
+    /// candidate if none does. Smaller sectors waste less buffer space on
+    /// small files but need larger offset tables and compress worse, so
+    /// larger files are left to span several sectors.
+    pub fn from_file_sizes(current_block_size: u16, file_sizes: &[u64]) -> Self {
+        let candidates: Vec<SectorSizeEstimate> = SUGGESTED_BLOCK_SIZES
+            .map(|block_size| SectorSizeEstimate::new(block_size, file_sizes))
+            .collect();
+        let wanted = (file_sizes.len() as f64 * SINGLE_SECTOR_SHARE).ceil() as usize;
+        let suggested = candidates
+            .iter()
+            .find(|estimate| estimate.single_sector_files >= wanted)
+            .or(candidates.last())
+            .copied()
+            .expect("SUGGESTED_BLOCK_SIZES is not empty");
+
+        Self {
+            file_count: file_sizes.len(),
+            current: SectorSizeEstimate::new(current_block_size, file_sizes),
+            suggested,
+            candidates,
+        }
+    }
+
+    /// Whether the suggested sector size differs from the current one
+    pub fn is_change(&self) -> bool {
+        self.suggested.block_size != self.current.block_size
+    }
+}
+
+/// Recommend a sector size for an archive from its file size distribution
+///
+/// Uses the sizes of the listed files; see
+/// [`SectorSizeSuggestion::from_file_sizes`] for how the size is chosen.
+/// Apply a suggestion by rebuilding the archive with
+/// [`ArchiveBuilder::block_size`](crate::ArchiveBuilder::block_size).
+///
+/// # Examples
+///
+/// ```no_run
+/// use mopaq::{analysis, Archive};
+///
+/// let mut archive = Archive::open("game.mpq")?;
+/// let suggestion = analysis::suggest_sector_size(&mut archive)?;
+/// if suggestion.is_change() {
+///     println!(
+///         "use block size {} instead of {}",
+///         suggestion.suggested.block_size,
+///         suggestion.current.block_size
+///     );
+/// }
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+pub fn suggest_sector_size(archive: &mut Archive) -> Result<SectorSizeSuggestion> {
+    let file_sizes: Vec<u64> = archive.list()?.iter().map(|entry| entry.size).collect();
+    Ok(SectorSizeSuggestion::from_file_sizes(
+        archive.header().block_size,
+        &file_sizes,
+    ))
+}
+
+/// Count files per method, ordered by count and then by first appearance
+fn histogram<'a>(files: impl Iterator<Item = &'a FileCompression>) -> Vec<MethodCount> {
+    let mut counts: Vec<MethodCount> = Vec::new();
+    let mut index: HashMap<CompressionMethod, usize> = HashMap::new();
+
+    for file in files {
+        match index.get(&file.method) {
+            Some(&i) => counts[i].count += 1,
+            None => {
+                index.insert(file.method, counts.len());
+                counts.push(MethodCount {
+                    method: file.method,
+                    count: 1,
+                });
+            }
+        }
+    }
+
+    counts.sort_by(|a, b| b.count.cmp(&a.count));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sector_size_suggestion() {
+        // 90 files of 10 KB and 10 of 1 MB
+        let mut sizes = vec![10_000u64; 90];
+        sizes.extend([1 << 20; 10]);
+
+        let suggestion = SectorSizeSuggestion::from_file_sizes(7, &sizes);
+        assert_eq!(suggestion.file_count, 100);
+        assert_eq!(suggestion.current.sector_size, 65536);
+        assert_eq!(suggestion.current.single_sector_files, 90);
+        assert_eq!(suggestion.current.slack_bytes, 90 * (65536 - 10_000));
+        assert_eq!(suggestion.current.offset_table_bytes, 90 * 8 + 10 * 17 * 4);
+
+        // 16 KB is the smallest sector size holding 90% of the files
+        assert_eq!(suggestion.suggested.block_size, 5);
+        assert!(suggestion.is_change());
+        assert!(suggestion.suggested.slack_bytes < suggestion.current.slack_bytes);
+        assert_eq!(suggestion.candidates.len(), SUGGESTED_BLOCK_SIZES.count());
+
+        // Without a block size holding enough files, the largest is suggested
+        let large = SectorSizeSuggestion::from_file_sizes(3, &[1 << 24; 4]);
+        assert_eq!(large.suggested.block_size, *SUGGESTED_BLOCK_SIZES.end());
+
+        let empty = SectorSizeSuggestion::from_file_sizes(3, &[]);
+        assert_eq!(empty.suggested.block_size, 3);
+        assert!(!empty.is_change());
+    }
+}
@@ -10,16 +10,38 @@
 
 use crate::{
     builder::ArchiveBuilder,
-    compression,
-    crypto::{decrypt_block, decrypt_dword, hash_string, hash_type},
+    checksum::SectorChecksum,
+    compression::{
+        self, output_buffer, CompressionMethod, DecodeDiagnostics, FallbackPolicy,
+        SizeMismatchPolicy,
+    },
+    crypto::{
+        decrypt_block, decrypt_dword, detect_key_by_known_plaintext, hash_string, hash_string_with,
+        hash_type, NameHashingPolicy, PrecomputedName,
+    },
+    extract::{
+        CollisionPolicy, ExtractFailure, ExtractOptions, ExtractSummary, ExtractionOrder,
+        OutputDir, Placement,
+    },
     header::{self, MpqHeader, UserDataHeader},
-    special_files,
-    tables::{BetTable, BlockTable, HashTable, HetTable, HiBlockTable},
+    io::{FileCursor, PositionedFile, Readahead},
+    locale::Locale,
+    metrics::{Metrics, MetricsSink},
+    mpq_header::{self, HeaderReconciliation},
+    pattern::Pattern,
+    special_files::{self, SpecialFile},
+    tables::{
+        BetTable, BlockEntry, BlockTable, HashEntry, HashTable, HetTable, HiBlockTable,
+        PlatformPolicy, TableKey, TableOffsetPolicy,
+    },
+    tree::DirNode,
     Error, Result,
 };
+use rsa::RsaPublicKey;
 use std::fs::File;
 use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 /// Helper trait for reading little-endian integers
 trait ReadLittleEndian: Read {
@@ -34,6 +56,7 @@ impl<R: Read> ReadLittleEndian for R {}
 
 /// Detailed information about an MPQ archive
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ArchiveInfo {
     /// Path to the archive file
     pub path: PathBuf,
@@ -73,10 +96,18 @@ pub struct ArchiveInfo {
     pub user_data_info: Option<UserDataInfo>,
     /// MD5 checksums status (v4)
     pub md5_status: Option<Md5Status>,
+    /// The file ends before the archive does
+    pub is_truncated: bool,
+    /// How a header whose version and size disagree was read
+    pub header_reconciliation: Option<HeaderReconciliation>,
+    /// Archive size derived from its tables and file data, which protected
+    /// maps may not declare correctly
+    pub computed_archive_size: u64,
 }
 
 /// Information about a table in the archive
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TableInfo {
     /// Table size in entries (None if table failed to load)
     pub size: Option<u32>,
@@ -90,6 +121,7 @@ pub struct TableInfo {
 
 /// User data information
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UserDataInfo {
     /// User data header size
     pub header_size: u32,
@@ -99,6 +131,7 @@ pub struct UserDataInfo {
 
 /// Digital signature status
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SignatureStatus {
     /// No signature present
     None,
@@ -116,6 +149,7 @@ pub enum SignatureStatus {
 
 /// MD5 checksum verification status for v4 archives
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Md5Status {
     /// Hash table MD5 valid
     pub hash_table_valid: bool,
@@ -167,6 +201,44 @@ pub struct OpenOptions {
     /// This field is only used when creating new archives via `create()`.
     /// If `None`, defaults to MPQ version 1 for maximum compatibility.
     version: Option<crate::header::FormatVersion>,
+
+    /// How nonzero platform codes in the hash table are handled.
+    platform_policy: PlatformPolicy,
+
+    /// Sector checksum algorithm, or `None` to detect it per checksum.
+    sector_checksum: Option<SectorChecksum>,
+
+    /// How decompressed data of the wrong size is handled.
+    size_mismatch_policy: SizeMismatchPolicy,
+
+    /// How many times its expected size data may decompress to.
+    max_expansion: usize,
+
+    /// How guesses about ambiguous compression masks are handled.
+    fallback_policy: FallbackPolicy,
+
+    /// Whether to keep a record of how each piece of data was decompressed.
+    record_decode_diagnostics: bool,
+
+    /// Keys used to decrypt the hash and block tables.
+    hash_table_key: TableKey,
+    block_table_key: TableKey,
+
+    /// How table positions outside the file are read.
+    table_offsets: TableOffsetPolicy,
+
+    /// How names are normalized before they are hashed for lookups.
+    name_hashing: NameHashingPolicy,
+
+    /// Bytes read ahead per system call, or `None` to size by sector size.
+    io_buffer_size: Option<usize>,
+
+    /// Sink for I/O counters, or `None` to not report them.
+    metrics: Option<MetricsSink>,
+
+    /// Directory for cached archive metadata, or `None` to not cache.
+    #[cfg(feature = "cache")]
+    cache_dir: Option<PathBuf>,
 }
 
 impl OpenOptions {
@@ -175,10 +247,36 @@ impl OpenOptions {
     /// Returns an `OpenOptions` instance with default settings:
     /// - `load_tables = true` (immediate table loading)
     /// - `version = None` (defaults to MPQ v1 for new archives)
+    /// - `platform_policy = PlatformPolicy::Lenient`
+    /// - `sector_checksum = None` (accept ADLER32 or CRC32)
+    /// - `size_mismatch_policy = SizeMismatchPolicy::Warn`
+    /// - `max_expansion = compression::DEFAULT_MAX_EXPANSION`
+    /// - `fallback_policy = FallbackPolicy::Allow`
+    /// - `record_decode_diagnostics = false`
+    /// - `TableKey::Standard` for the hash and block tables
+    /// - `table_offsets = TableOffsetPolicy::Strict`
+    /// - `name_hashing = NameHashingPolicy::BLIZZARD`
+    /// - `io_buffer_size = None` (see [`default_io_buffer_size`](crate::default_io_buffer_size))
+    /// - `metrics = None` (no I/O counters reported)
+    /// - `cache_dir = None` (no metadata cache, with the `cache` feature)
     pub fn new() -> Self {
         Self {
             load_tables: true,
             version: None,
+            platform_policy: PlatformPolicy::default(),
+            sector_checksum: None,
+            size_mismatch_policy: SizeMismatchPolicy::default(),
+            max_expansion: compression::DEFAULT_MAX_EXPANSION,
+            fallback_policy: FallbackPolicy::default(),
+            record_decode_diagnostics: false,
+            hash_table_key: TableKey::Standard,
+            block_table_key: TableKey::Standard,
+            table_offsets: TableOffsetPolicy::default(),
+            name_hashing: NameHashingPolicy::default(),
+            io_buffer_size: None,
+            metrics: None,
+            #[cfg(feature = "cache")]
+            cache_dir: None,
         }
     }
 
@@ -210,6 +308,230 @@ impl OpenOptions {
         self
     }
 
+    /// Set how nonzero platform codes in the hash table are handled
+    ///
+    /// With [`PlatformPolicy::Strict`], loading the hash table fails if any
+    /// valid entry carries a nonzero platform code. The other policies accept
+    /// such archives; the platform is reported in [`FileInfo::platform`].
+    ///
+    /// # Parameters
+    /// - `policy`: The platform validation policy
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn platform_policy(mut self, policy: PlatformPolicy) -> Self {
+        self.platform_policy = policy;
+        self
+    }
+
+    /// Set the algorithm used to verify sector checksums
+    ///
+    /// By default each stored checksum is accepted if it matches either
+    /// ADLER32 (the canonical algorithm) or CRC32. Pass an explicit algorithm
+    /// for archives known to use one of them, so a checksum that happens to
+    /// match the other algorithm is treated as a mismatch.
+    ///
+    /// # Parameters
+    /// - `algorithm`: The algorithm to require, or `None` to auto-detect
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn sector_checksum(mut self, algorithm: Option<SectorChecksum>) -> Self {
+        self.sector_checksum = algorithm;
+        self
+    }
+
+    /// Set how files that decompress to an unexpected size are handled
+    ///
+    /// Legacy archives sometimes record sizes that are slightly off, so by
+    /// default a mismatch is logged and the data returned as decompressed.
+    /// Validators should pass [`SizeMismatchPolicy::Strict`] to have
+    /// [`Archive::read_file`] fail instead.
+    ///
+    /// # Parameters
+    /// - `policy`: The policy for file data
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn size_mismatch_policy(mut self, policy: SizeMismatchPolicy) -> Self {
+        self.size_mismatch_policy = policy;
+        self
+    }
+
+    /// Set how many times its expected size file data may decompress to
+    ///
+    /// Reading fails with `Error::ExpansionLimit` as soon as a sector's
+    /// output passes this multiple of its recorded size, so a crafted
+    /// archive cannot make a server decompress gigabytes from a few bytes.
+    /// Defaults to [`compression::DEFAULT_MAX_EXPANSION`].
+    ///
+    /// # Parameters
+    /// - `factor`: The maximum expansion, at least 1
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn max_expansion(mut self, factor: usize) -> Self {
+        self.max_expansion = factor;
+        self
+    }
+
+    /// Set how guesses about ambiguous compression masks are handled
+    ///
+    /// Masks such as both ADPCM flags together are decoded by picking one
+    /// reading, logged at debug level. [`FallbackPolicy::Warn`] logs each
+    /// guess as a warning, and [`FallbackPolicy::Strict`] makes the read
+    /// fail instead, so corrupt data is not mistaken for a successful read.
+    /// Strict mode also parses the `(attributes)` file as stored, without
+    /// trying to decompress it a second time.
+    ///
+    /// # Parameters
+    /// - `policy`: The policy for guesses
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn fallback_policy(mut self, policy: FallbackPolicy) -> Self {
+        self.fallback_policy = policy;
+        self
+    }
+
+    /// Set whether to record how each piece of file data was decompressed
+    ///
+    /// Meant for format debugging: every file or sector decompressed adds a
+    /// [`DecodeDiagnostics`] to a list that grows until it is collected with
+    /// [`Archive::take_decode_diagnostics`].
+    ///
+    /// # Parameters
+    /// - `record`: If `true`, diagnostics are recorded
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn record_decode_diagnostics(mut self, record: bool) -> Self {
+        self.record_decode_diagnostics = record;
+        self
+    }
+
+    /// Set how file names are normalized before they are hashed for lookups
+    ///
+    /// Blizzard archives fold case and treat `/` as `\`. Archives made by
+    /// third-party tools that hash names case-sensitively or with `/`
+    /// separators only find their files with the matching policy.
+    ///
+    /// # Parameters
+    /// - `policy`: The normalization the archive's names were hashed with
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn name_hashing(mut self, policy: NameHashingPolicy) -> Self {
+        self.name_hashing = policy;
+        self
+    }
+
+    /// Set how many bytes are read from the archive file per system call
+    ///
+    /// Tables are read through a buffer of this size, and the stored sectors
+    /// of a file are read ahead up to this many bytes at once, so large files
+    /// are read in a few big reads rather than one per sector. By default the
+    /// size follows the archive's sector size, see
+    /// [`default_io_buffer_size`](crate::default_io_buffer_size). Sizes up
+    /// to the sector size read one sector at a time.
+    ///
+    /// # Parameters
+    /// - `bytes`: The buffer size in bytes
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn io_buffer_size(mut self, bytes: usize) -> Self {
+        self.io_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Report I/O counters of the archive to `metrics`
+    ///
+    /// Bytes read, sectors decompressed, files read and metadata cache
+    /// lookups are reported as they happen, by the archive and by its
+    /// clones. See the [`metrics`](crate::metrics) module.
+    ///
+    /// # Parameters
+    /// - `metrics`: The sink, which may be shared with other archives
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(MetricsSink(metrics));
+        self
+    }
+
+    /// Cache parsed tables and `(listfile)` names in a directory
+    ///
+    /// The first open of an archive writes its hash, block and hi-block
+    /// tables and its listfile names to a file in `dir`; later opens, from
+    /// any process, read them from there instead of decrypting and parsing
+    /// them again. A cache file is ignored once the archive's size or
+    /// modification time changes, or when the archive is opened with other
+    /// table keys, table offset policy or name hashing policy than it was
+    /// written with. Problems with the cache are logged and
+    /// never fail the open. Only used when tables are loaded on open.
+    ///
+    /// # Parameters
+    /// - `dir`: The cache directory, created if missing
+    ///
+    /// # Returns
+    /// Self for method chaining
+    #[cfg(feature = "cache")]
+    pub fn cache_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Override the keys used to decrypt the hash and block tables
+    ///
+    /// Protected maps sometimes encrypt these tables with a key other than the
+    /// one derived from `"(hash table)"` and `"(block table)"`. Pass
+    /// [`TableKey::Fixed`] when the key is known, or [`TableKey::Recover`] to
+    /// fall back to recovering it from known plaintext when the standard key
+    /// produces an implausible table. HET and BET tables are not affected.
+    ///
+    /// # Parameters
+    /// - `hash_table`: How to obtain the hash table key
+    /// - `block_table`: How to obtain the block table key
+    ///
+    /// # Returns
+    /// Self for method chaining
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mopaq::{OpenOptions, TableKey};
+    ///
+    /// let archive = OpenOptions::new()
+    ///     .table_key_override(TableKey::Recover, TableKey::Recover)
+    ///     .open("protected.w3x")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn table_key_override(mut self, hash_table: TableKey, block_table: TableKey) -> Self {
+        self.hash_table_key = hash_table;
+        self.block_table_key = block_table;
+        self
+    }
+
+    /// Set how hash, block and hi-block table positions are resolved
+    ///
+    /// Protected archives sometimes store table positions that point past
+    /// the end of the file and only reach the table after wrapping around
+    /// 32 bits or the archive size. [`TableOffsetPolicy::Lenient`] wraps
+    /// such positions back into the file the way StormLib does; positions
+    /// that already point into the file are never changed.
+    ///
+    /// # Parameters
+    /// - `policy`: How to treat table positions outside the file
+    ///
+    /// # Returns
+    /// Self for method chaining
+    pub fn table_offsets(mut self, policy: TableOffsetPolicy) -> Self {
+        self.table_offsets = policy;
+        self
+    }
+
     /// Open an existing MPQ archive with these options
     ///
     /// # Parameters
@@ -226,6 +548,19 @@ impl OpenOptions {
         Archive::open_with_options(path, self)
     }
 
+    /// Open an existing MPQ archive from a file that is already open
+    ///
+    /// See [`Archive::from_file`].
+    ///
+    /// # Parameters
+    /// - `file`: The archive file, which the archive takes ownership of
+    ///
+    /// # Returns
+    /// `Ok(Archive)` on success, `Err(Error)` on failure
+    pub fn open_file(self, file: File) -> Result<Archive> {
+        Archive::from_file_with_options(file, self)
+    }
+
     /// Create a new empty MPQ archive with these options
     ///
     /// Creates a new MPQ archive file with the specified format version.
@@ -244,14 +579,19 @@ impl OpenOptions {
         let path = path.as_ref();
 
         // Create an empty archive with the specified version
-        let builder =
-            ArchiveBuilder::new().version(self.version.unwrap_or(crate::header::FormatVersion::V1));
+        let builder = ArchiveBuilder::new()
+            .version(self.version.unwrap_or(crate::header::FormatVersion::V1))
+            .name_hashing(self.name_hashing);
 
         // Build the empty archive
         builder.build(path)?;
 
         // Open the newly created archive
-        Self::new().load_tables(self.load_tables).open(path)
+        let mut options = Self::new()
+            .load_tables(self.load_tables)
+            .name_hashing(self.name_hashing);
+        options.metrics = self.metrics;
+        options.open(path)
     }
 }
 
@@ -261,13 +601,135 @@ impl Default for OpenOptions {
     }
 }
 
+/// Order of the entries returned by [`Archive::list_with`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListSort {
+    /// Keep the order of the listfile or tables
+    #[default]
+    Unsorted,
+    /// By name, case-insensitively
+    Name,
+    /// By uncompressed size
+    Size,
+    /// By compression ratio, see [`FileEntry::compression_ratio`]
+    Ratio,
+    /// By position in the block table, i.e. roughly by position in the file
+    BlockIndex,
+}
+
+/// Sorting and filtering for [`Archive::list_with`]
+///
+/// The defaults list every file except the special files, in listing order.
+///
+/// # Examples
+///
+/// ```no_run
+/// use mopaq::{Archive, ListOptions, ListSort};
+/// use mopaq::tables::BlockEntry;
+///
+/// let mut archive = Archive::open("data.mpq")?;
+///
+/// // The ten largest encrypted files
+/// let options = ListOptions::new()
+///     .sort_by(ListSort::Size)
+///     .descending(true)
+///     .filter_flags(BlockEntry::FLAG_ENCRYPTED);
+/// for entry in archive.list_with(&options)?.iter().take(10) {
+///     println!("{} ({} bytes)", entry.name, entry.size);
+/// }
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    sort_by: ListSort,
+    descending: bool,
+    filter_flags: u32,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    include_special: bool,
+    pattern: Option<Pattern>,
+}
+
+impl ListOptions {
+    /// Options that list every file in listing order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the sort order
+    pub fn sort_by(mut self, sort: ListSort) -> Self {
+        self.sort_by = sort;
+        self
+    }
+
+    /// Sort in descending order, e.g. largest files first
+    ///
+    /// Has no effect with [`ListSort::Unsorted`].
+    pub fn descending(mut self, descending: bool) -> Self {
+        self.descending = descending;
+        self
+    }
+
+    /// Only list files that have all of these block table flags set
+    ///
+    /// See the `FLAG_*` constants on [`BlockEntry`](crate::tables::BlockEntry).
+    pub fn filter_flags(mut self, flags: u32) -> Self {
+        self.filter_flags = flags;
+        self
+    }
+
+    /// Only list files with at least this uncompressed size
+    pub fn min_size(mut self, size: u64) -> Self {
+        self.min_size = Some(size);
+        self
+    }
+
+    /// Only list files with at most this uncompressed size
+    pub fn max_size(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// List special files such as `(listfile)` and `(attributes)` as well
+    ///
+    /// They describe the archive rather than being part of its contents, so
+    /// they are left out by default. See [`SpecialFile`] for the names this
+    /// covers.
+    pub fn include_special(mut self, include: bool) -> Self {
+        self.include_special = include;
+        self
+    }
+
+    /// Only list files whose names match `pattern`
+    ///
+    /// Names match the way lookups do, ignoring case and whether `/` or `\`
+    /// separates their parts.
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.pattern = Some(pattern);
+        self
+    }
+
+    fn accepts(&self, entry: &FileEntry) -> bool {
+        (self.include_special || entry.special_file().is_none())
+            && self
+                .pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(&entry.name))
+            && entry.flags & self.filter_flags == self.filter_flags
+            && self.min_size.is_none_or(|min| entry.size >= min)
+            && self.max_size.is_none_or(|max| entry.size <= max)
+    }
+}
+
 /// An MPQ archive
 #[derive(Debug)]
 pub struct Archive {
     /// Path to the archive file
     path: PathBuf,
     /// Archive file reader
-    reader: BufReader<File>,
+    reader: BufReader<FileCursor>,
+    /// Handle for reading file data through `&self`, shared with clones
+    data: Arc<PositionedFile>,
     /// Offset where the MPQ data starts in the file
     archive_offset: u64,
     /// Optional user data header
@@ -275,17 +737,69 @@ pub struct Archive {
     /// MPQ header
     header: MpqHeader,
     /// Hash table (optional, loaded on demand)
-    hash_table: Option<HashTable>,
+    hash_table: Option<Arc<HashTable>>,
     /// Block table (optional, loaded on demand)
-    block_table: Option<BlockTable>,
+    block_table: Option<Arc<BlockTable>>,
     /// Hi-block table for v2+ archives (optional)
-    hi_block_table: Option<HiBlockTable>,
+    hi_block_table: Option<Arc<HiBlockTable>>,
     /// HET table for v3+ archives
-    het_table: Option<HetTable>,
+    het_table: Option<Arc<HetTable>>,
     /// BET table for v3+ archives
-    bet_table: Option<BetTable>,
+    bet_table: Option<Arc<BetTable>>,
     /// File attributes from (attributes) file
-    attributes: Option<special_files::Attributes>,
+    attributes: Option<Arc<special_files::Attributes>>,
+    /// Names from the (listfile), when taken from the metadata cache
+    listfile_names: Option<Arc<Vec<String>>>,
+    /// Policy for nonzero platform codes in the hash table
+    platform_policy: PlatformPolicy,
+    /// Sector checksum algorithm, or `None` to auto-detect
+    sector_checksum: Option<SectorChecksum>,
+    /// How decompressed data of the wrong size is handled
+    size_mismatch_policy: SizeMismatchPolicy,
+    /// How many times its expected size data may decompress to
+    max_expansion: usize,
+    /// How guesses about ambiguous compression masks are handled
+    fallback_policy: FallbackPolicy,
+    /// How data was decompressed, when recording is enabled
+    decode_diagnostics: Option<Mutex<Vec<DecodeDiagnostics>>>,
+    /// How the hash table key is obtained
+    hash_table_key: TableKey,
+    /// How the block table key is obtained
+    block_table_key: TableKey,
+    /// How table positions outside the file are read
+    table_offsets: TableOffsetPolicy,
+    /// How names are normalized before they are hashed for lookups
+    name_hashing: NameHashingPolicy,
+    /// Bytes read from the archive file per system call
+    io_buffer_size: usize,
+    /// Sink for I/O counters, shared with clones
+    metrics: Option<MetricsSink>,
+    /// Whether the file ends before the archive or its tables do
+    truncated: bool,
+    /// How the header was read if its version and size disagree
+    header_reconciliation: Option<HeaderReconciliation>,
+}
+
+/// The sector tables of a sectored file, and its stored bytes read ahead
+/// of the sector being decoded
+#[derive(Debug)]
+struct SectorReader {
+    offsets: Vec<u32>,
+    checksums: Option<Vec<u32>>,
+    /// Offset of `window` from the file's position
+    window_start: u64,
+    window: Vec<u8>,
+}
+
+impl SectorReader {
+    fn new(offsets: Vec<u32>, checksums: Option<Vec<u32>>) -> Self {
+        Self {
+            offsets,
+            checksums,
+            window_start: 0,
+            window: Vec::new(),
+        }
+    }
 }
 
 impl Archive {
@@ -297,15 +811,59 @@ impl Archive {
     /// Open an archive with specific options
     pub fn open_with_options<P: AsRef<Path>>(path: P, options: OpenOptions) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
+        crate::io::check_device_path(&path)?;
         let file = File::open(&path)?;
-        let mut reader = BufReader::new(file);
+        Self::from_open_file(path, file, options)
+    }
+
+    /// Open an archive from a file that is already open
+    ///
+    /// The archive takes ownership of `file` and reads it from the start,
+    /// whatever its current position. This suits embedders that open the
+    /// file themselves, for example with custom sharing flags on Windows.
+    /// The archive has no path: [`path`](Self::path) is empty and the
+    /// metadata cache is not used.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    /// use std::fs::File;
+    ///
+    /// let file = File::open("example.mpq")?;
+    /// let archive = Archive::from_file(file)?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn from_file(file: File) -> Result<Self> {
+        Self::from_file_with_options(file, OpenOptions::default())
+    }
+
+    /// Open an archive from a file that is already open, with specific options
+    pub fn from_file_with_options(file: File, options: OpenOptions) -> Result<Self> {
+        Self::from_open_file(PathBuf::new(), file, options)
+    }
+
+    fn from_open_file(path: PathBuf, file: File, options: OpenOptions) -> Result<Self> {
+        let data = Arc::new(PositionedFile::new(file).with_metrics(options.metrics.clone()));
+        let mut reader = BufReader::new(FileCursor::new(Arc::clone(&data)));
 
         // Find and read the MPQ header
         let (archive_offset, user_data, header) = header::find_header(&mut reader)?;
+        reader.seek(SeekFrom::Start(archive_offset))?;
+        let (_, header_reconciliation) = mpq_header::read_header_reconciled(&mut reader)?;
+        if let Some(reconciliation) = &header_reconciliation {
+            log::warn!("Reconciled MPQ header: {}", reconciliation);
+        }
+
+        // The rest is read with a buffer sized for the archive's sectors
+        let io_buffer_size = options
+            .io_buffer_size
+            .unwrap_or_else(|| crate::default_io_buffer_size(header.sector_size()));
+        let reader = BufReader::with_capacity(io_buffer_size, reader.into_inner());
 
         let mut archive = Archive {
             path,
             reader,
+            data,
             archive_offset,
             user_data,
             header,
@@ -315,17 +873,123 @@ impl Archive {
             bet_table: None,
             het_table: None,
             attributes: None,
+            listfile_names: None,
+            platform_policy: options.platform_policy,
+            sector_checksum: options.sector_checksum,
+            size_mismatch_policy: options.size_mismatch_policy,
+            max_expansion: options.max_expansion,
+            fallback_policy: options.fallback_policy,
+            decode_diagnostics: options
+                .record_decode_diagnostics
+                .then(|| Mutex::new(Vec::new())),
+            hash_table_key: options.hash_table_key,
+            block_table_key: options.block_table_key,
+            table_offsets: options.table_offsets,
+            name_hashing: options.name_hashing,
+            io_buffer_size,
+            metrics: options.metrics.clone(),
+            truncated: false,
+            header_reconciliation,
         };
 
+        let file_size = archive.data.len()?;
+        if file_size < archive_offset + archive.header.get_archive_size() {
+            log::warn!(
+                "Archive is truncated: {} of {} bytes present",
+                file_size.saturating_sub(archive_offset),
+                archive.header.get_archive_size()
+            );
+            archive.truncated = true;
+        }
+
         // Load tables if requested
         if options.load_tables {
+            #[cfg(feature = "cache")]
+            if let Some(cache_dir) = &options.cache_dir {
+                archive.load_tables_cached(cache_dir)?;
+                return Ok(archive);
+            }
             archive.load_tables()?;
         }
 
         Ok(archive)
     }
 
+    /// Load tables through the metadata cache in `cache_dir`
+    ///
+    /// On a hit the classic tables and listfile names come from the cache
+    /// and only the rest is read from the archive. On a miss the tables are
+    /// loaded normally and written to the cache, unless the archive is
+    /// truncated.
+    #[cfg(feature = "cache")]
+    fn load_tables_cached(&mut self, cache_dir: &Path) -> Result<()> {
+        if self.path.as_os_str().is_empty() {
+            log::debug!("Not using the metadata cache for an archive without a path");
+            return self.load_tables();
+        }
+
+        let options = crate::cache::TableOptions {
+            hash_table_key: self.hash_table_key,
+            block_table_key: self.block_table_key,
+            table_offsets: self.table_offsets,
+            name_hashing: self.name_hashing,
+        };
+        let key = crate::cache::CacheKey::for_metadata(&self.data.metadata()?, &options)?;
+        let cache_path = crate::cache::cache_path(cache_dir, &self.path)?;
+        let counts = crate::cache::TableCounts {
+            hash_table: self.header.hash_table_size,
+            block_table: self.header.block_table_size,
+        };
+
+        let cached = crate::cache::load(&cache_path, key, counts);
+        self.report(|m| m.on_cache_lookup(cached.is_some()));
+        if let Some(cached) = cached {
+            log::debug!("Using cached metadata from {}", cache_path.display());
+            if let Some(hash_table) = &cached.hash_table {
+                hash_table.validate_platforms(self.platform_policy)?;
+            }
+            self.hash_table = cached.hash_table;
+            self.block_table = cached.block_table;
+            self.hi_block_table = cached.hi_block_table;
+            self.listfile_names = cached.listfile_names;
+            return self.load_tables();
+        }
+
+        self.load_tables()?;
+        if self.truncated {
+            return Ok(());
+        }
+
+        let listfile_names = match self.find_file("(listfile)")? {
+            Some(_) => match self
+                .read_file("(listfile)")
+                .and_then(|data| special_files::parse_listfile(&data))
+            {
+                Ok(names) => Some(Arc::new(names)),
+                Err(e) => {
+                    log::warn!("Not caching metadata, unreadable (listfile): {}", e);
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+        self.listfile_names = listfile_names.clone();
+
+        let metadata = crate::cache::CachedMetadata {
+            hash_table: self.hash_table.clone(),
+            block_table: self.block_table.clone(),
+            hi_block_table: self.hi_block_table.clone(),
+            listfile_names,
+        };
+        if let Err(e) = crate::cache::store(&cache_path, key, &metadata) {
+            log::warn!("Failed to write cache file {}: {}", cache_path.display(), e);
+        }
+        Ok(())
+    }
+
     /// Load hash and block tables
+    ///
+    /// Tables that are already loaded are kept.
     pub fn load_tables(&mut self) -> Result<()> {
         log::debug!(
             "Loading tables for archive version {:?}",
@@ -337,29 +1001,7 @@ impl Archive {
             // Try to load HET table
             if let Some(het_pos) = self.header.het_table_pos {
                 if het_pos != 0 {
-                    let mut het_size = self
-                        .header
-                        .v4_data
-                        .as_ref()
-                        .map(|v4| v4.het_table_size_64)
-                        .unwrap_or(0);
-
-                    // For V3 without V4 data, we need to determine the size
-                    if het_size == 0 && self.header.format_version == header::FormatVersion::V3 {
-                        log::debug!(
-                            "V3 archive without V4 data, reading HET table size from header"
-                        );
-                        // Try to read the table size from the HET header
-                        match self.read_het_table_size(het_pos) {
-                            Ok(size) => {
-                                log::debug!("Determined HET table size: 0x{:X}", size);
-                                het_size = size;
-                            }
-                            Err(e) => {
-                                log::warn!("Failed to determine HET table size: {}", e);
-                            }
-                        }
-                    }
+                    let het_size = self.header.het_table_size().unwrap_or(0);
 
                     if het_size > 0 {
                         log::debug!(
@@ -380,7 +1022,7 @@ impl Archive {
                             Ok(het) => {
                                 let file_count = het.header.max_file_count;
                                 log::info!("Loaded HET table with {} max files", file_count);
-                                self.het_table = Some(het);
+                                self.het_table = Some(Arc::new(het));
                             }
                             Err(e) => {
                                 log::warn!("Failed to load HET table: {}", e);
@@ -393,29 +1035,7 @@ impl Archive {
             // Try to load BET table
             if let Some(bet_pos) = self.header.bet_table_pos {
                 if bet_pos != 0 {
-                    let mut bet_size = self
-                        .header
-                        .v4_data
-                        .as_ref()
-                        .map(|v4| v4.bet_table_size_64)
-                        .unwrap_or(0);
-
-                    // For V3 without V4 data, we need to determine the size
-                    if bet_size == 0 && self.header.format_version == header::FormatVersion::V3 {
-                        log::debug!(
-                            "V3 archive without V4 data, reading BET table size from header"
-                        );
-                        // Try to read the table size from the BET header
-                        match self.read_bet_table_size(bet_pos) {
-                            Ok(size) => {
-                                log::debug!("Determined BET table size: 0x{:X}", size);
-                                bet_size = size;
-                            }
-                            Err(e) => {
-                                log::warn!("Failed to determine BET table size: {}", e);
-                            }
-                        }
-                    }
+                    let bet_size = self.header.bet_table_size().unwrap_or(0);
 
                     if bet_size > 0 {
                         log::debug!(
@@ -436,7 +1056,7 @@ impl Archive {
                             Ok(bet) => {
                                 let file_count = bet.header.file_count;
                                 log::info!("Loaded BET table with {} files", file_count);
-                                self.bet_table = Some(bet);
+                                self.bet_table = Some(Arc::new(bet));
                             }
                             Err(e) => {
                                 log::warn!("Failed to load BET table: {}", e);
@@ -456,76 +1076,413 @@ impl Archive {
             _ => false,
         };
 
+        // An empty archive written without classic tables has empty HET/BET tables
+        let het_bet_only = self.het_table.is_some()
+            && self.bet_table.is_some()
+            && self.header.hash_table_size == 0;
+
         // Only load hash/block tables if:
         // 1. We don't have valid HET/BET tables, OR
         // 2. The hash table size is non-zero (indicating they exist and may be needed for compatibility)
-        if !has_valid_het_bet || self.header.hash_table_size > 0 {
+        if self.hash_table.is_some() && self.block_table.is_some() {
+            log::debug!("Hash and block tables already loaded");
+        } else if (!has_valid_het_bet && !het_bet_only) || self.header.hash_table_size > 0 {
             // Load hash table
-            let hash_table_offset = self.archive_offset + self.header.get_hash_table_pos();
-            self.hash_table = Some(HashTable::read(
-                &mut self.reader,
-                hash_table_offset,
-                self.header.hash_table_size,
-            )?);
+            let hash_table = self.read_hash_table()?;
+            hash_table.validate_platforms(self.platform_policy)?;
+            self.hash_table = Some(Arc::new(hash_table));
 
             // Load block table
-            let block_table_offset = self.archive_offset + self.header.get_block_table_pos();
-            self.block_table = Some(BlockTable::read(
-                &mut self.reader,
-                block_table_offset,
-                self.header.block_table_size,
-            )?);
+            self.block_table = Some(Arc::new(self.read_block_table()?));
         } else {
             log::info!("Skipping hash/block table loading - valid HET/BET tables present");
         }
 
         // Load hi-block table if present (v2+)
         if let Some(hi_block_pos) = self.header.hi_block_table_pos {
-            if hi_block_pos != 0 {
-                let hi_block_offset = self.archive_offset + hi_block_pos;
-                self.hi_block_table = Some(HiBlockTable::read(
-                    &mut self.reader,
-                    hi_block_offset,
-                    self.header.block_table_size,
-                )?);
+            if hi_block_pos != 0 && self.hi_block_table.is_none() {
+                let size = self.header.block_table_size;
+                let hi_block_offset = self.table_offset(hi_block_pos, size as u64 * 2)?;
+                let readable = self.readable_table_bytes("hi-block", hi_block_offset, size, 2)?;
+                let mut data = vec![0u8; size as usize * 2];
+                self.reader.seek(SeekFrom::Start(hi_block_offset))?;
+                self.reader.read_exact(&mut data[..readable])?;
+                self.hi_block_table = Some(Arc::new(HiBlockTable::read(
+                    &mut std::io::Cursor::new(data),
+                    0,
+                    size,
+                )?));
             }
         }
 
-        // Load attributes if present
-        self.load_attributes()?;
+        // Load attributes if present; in a truncated archive they may be lost
+        if let Err(e) = self.load_attributes() {
+            if !self.truncated {
+                return Err(e);
+            }
+            log::warn!(
+                "Ignoring unreadable (attributes) of truncated archive: {}",
+                e
+            );
+        }
 
         Ok(())
     }
 
-    /// Get the archive header
-    pub fn header(&self) -> &MpqHeader {
-        &self.header
-    }
+    /// Read the hash table, resolving its key according to the open options
+    ///
+    /// Entries past the end of a truncated file are read as unused.
+    fn read_hash_table(&mut self) -> Result<HashTable> {
+        let size = self.header.hash_table_size;
+        let offset = self.table_offset(self.header.get_hash_table_pos(), size as u64 * 16)?;
+        let (mut reader, readable) = self.read_table_data("hash", offset, size)?;
+
+        let mut table = match self.hash_table_key {
+            TableKey::Standard => HashTable::read(&mut reader, 0, size)?,
+            TableKey::Fixed(key) => HashTable::read_with_key(&mut reader, 0, size, key)?,
+            TableKey::Recover => {
+                let block_table_size = self.header.block_table_size;
+                let table = HashTable::read(&mut reader, 0, size)?;
+                if table.is_plausible(block_table_size) {
+                    table
+                } else {
+                    let key = HashTable::recover_key(&mut reader, 0, size, block_table_size)?
+                        .ok_or_else(|| Error::hash_table("Unable to recover hash table key"))?;
+                    log::info!("Recovered non-standard hash table key 0x{:08X}", key);
+                    HashTable::read_with_key(&mut reader, 0, size, key)?
+                }
+            }
+        };
 
-    /// Get the user data header if present
-    pub fn user_data(&self) -> Option<&UserDataHeader> {
-        self.user_data.as_ref()
+        table.entries_mut()[readable..].fill(HashEntry::empty());
+        Ok(table)
     }
 
-    /// Get the archive offset in the file
-    pub fn archive_offset(&self) -> u64 {
+    /// Read the block table, resolving its key according to the open options
+    ///
+    /// Entries past the end of a truncated file are read as nonexistent
+    /// blocks, which [`find_file`](Self::find_file) treats as missing files.
+    fn read_block_table(&mut self) -> Result<BlockTable> {
+        let size = self.header.block_table_size;
+        let offset = self.table_offset(self.header.get_block_table_pos(), size as u64 * 16)?;
+        let (mut reader, readable) = self.read_table_data("block", offset, size)?;
+
+        let mut table = match self.block_table_key {
+            TableKey::Standard => BlockTable::read(&mut reader, 0, size)?,
+            TableKey::Fixed(key) => BlockTable::read_with_key(&mut reader, 0, size, key)?,
+            TableKey::Recover => {
+                let archive_size = self.data.len()?.saturating_sub(self.archive_offset);
+                let table = BlockTable::read(&mut reader, 0, size)?;
+                if table.is_plausible(archive_size) {
+                    table
+                } else {
+                    let first_file_pos = self.header.header_size;
+                    let key = BlockTable::recover_key(
+                        &mut reader,
+                        0,
+                        size,
+                        first_file_pos,
+                        archive_size,
+                    )?
+                    .ok_or_else(|| Error::block_table("Unable to recover block table key"))?;
+                    log::info!("Recovered non-standard block table key 0x{:08X}", key);
+                    BlockTable::read_with_key(&mut reader, 0, size, key)?
+                }
+            }
+        };
+
+        table.entries_mut()[readable..].fill(BlockEntry {
+            file_pos: 0,
+            compressed_size: 0,
+            file_size: 0,
+            flags: 0,
+        });
+        Ok(table)
+    }
+
+    /// File offset of a classic table of `table_bytes` bytes at header
+    /// position `pos`, according to the table offset policy
+    fn table_offset(&self, pos: u64, table_bytes: u64) -> Result<u64> {
+        let file_size = self.data.len()?;
+        Ok(self.table_offsets.resolve(
+            self.archive_offset,
+            pos,
+            table_bytes,
+            self.header.get_archive_size(),
+            file_size,
+        ))
+    }
+
+    /// Read the raw data of a hash or block table of `entries` entries
+    ///
+    /// Returns the data, zero-filled where the file ends early, and the
+    /// number of entries actually present.
+    fn read_table_data(
+        &mut self,
+        name: &str,
+        offset: u64,
+        entries: u32,
+    ) -> Result<(std::io::Cursor<Vec<u8>>, usize)> {
+        let readable = self.readable_table_bytes(name, offset, entries, 16)?;
+        let mut data = vec![0u8; entries as usize * 16];
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(&mut data[..readable])?;
+        Ok((std::io::Cursor::new(data), readable / 16))
+    }
+
+    /// Number of bytes of a table at `offset` that lie within the file
+    ///
+    /// A table cut off by the end of the file, or missing altogether,
+    /// marks the archive as truncated.
+    fn readable_table_bytes(
+        &mut self,
+        name: &str,
+        offset: u64,
+        entries: u32,
+        entry_size: usize,
+    ) -> Result<usize> {
+        let size = entries as usize * entry_size;
+        let file_size = self.data.len()?;
+        let available = file_size.saturating_sub(offset);
+        if available >= size as u64 {
+            return Ok(size);
+        }
+        let readable = available as usize / entry_size * entry_size;
+        log::warn!(
+            "The {} table is cut off after {} of {} entries",
+            name,
+            readable / entry_size,
+            entries
+        );
+        self.truncated = true;
+        Ok(readable)
+    }
+
+    /// Create an independent reader for the same archive
+    ///
+    /// Reading file data only needs `&self`, so threads can share one
+    /// archive by reference. A clone is for work that needs `&mut self`,
+    /// such as listing or loading attributes, on another thread. The clone
+    /// reads the same file handle through its own cursor, and parsed tables
+    /// and attributes are shared with `self` through reference counting
+    /// rather than being re-read. This works for archives opened with
+    /// [`from_file`](Self::from_file) as well.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let archive = Archive::open("example.mpq")?;
+    /// let mut reader = archive.try_clone()?;
+    /// std::thread::spawn(move || reader.list());
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// Does not currently fail; the `Result` is kept for compatibility with
+    /// versions that reopened the archive file.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            path: self.path.clone(),
+            reader: BufReader::with_capacity(
+                self.io_buffer_size,
+                FileCursor::new(Arc::clone(&self.data)),
+            ),
+            data: Arc::clone(&self.data),
+            archive_offset: self.archive_offset,
+            user_data: self.user_data.clone(),
+            header: self.header.clone(),
+            hash_table: self.hash_table.clone(),
+            block_table: self.block_table.clone(),
+            hi_block_table: self.hi_block_table.clone(),
+            het_table: self.het_table.clone(),
+            bet_table: self.bet_table.clone(),
+            attributes: self.attributes.clone(),
+            listfile_names: self.listfile_names.clone(),
+            platform_policy: self.platform_policy,
+            sector_checksum: self.sector_checksum,
+            size_mismatch_policy: self.size_mismatch_policy,
+            max_expansion: self.max_expansion,
+            fallback_policy: self.fallback_policy,
+            decode_diagnostics: self
+                .decode_diagnostics
+                .as_ref()
+                .map(|_| Mutex::new(Vec::new())),
+            hash_table_key: self.hash_table_key,
+            block_table_key: self.block_table_key,
+            table_offsets: self.table_offsets,
+            name_hashing: self.name_hashing,
+            io_buffer_size: self.io_buffer_size,
+            metrics: self.metrics.clone(),
+            truncated: self.truncated,
+            header_reconciliation: self.header_reconciliation,
+        })
+    }
+
+    /// Get the archive header
+    pub fn header(&self) -> &MpqHeader {
+        &self.header
+    }
+
+    /// How names are normalized before they are hashed for lookups
+    pub fn name_hashing(&self) -> NameHashingPolicy {
+        self.name_hashing
+    }
+
+    /// Bytes read from the archive file per system call
+    ///
+    /// See [`OpenOptions::io_buffer_size`].
+    pub fn io_buffer_size(&self) -> usize {
+        self.io_buffer_size
+    }
+
+    /// Check whether the file ends before the archive does
+    ///
+    /// Truncated archives, typically from interrupted downloads, are opened
+    /// with whatever part of their tables is present. Files whose table
+    /// entries were lost are not found, and files whose data was lost fail
+    /// [`is_file_intact`](Self::is_file_intact); everything else reads
+    /// normally.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Archive size derived from where its tables and file data end
+    ///
+    /// Protected maps often zero or scramble the `archive_size` in their
+    /// header. This is the end of the furthest table or stored file instead,
+    /// relative to the archive start; see
+    /// [`MpqHeader::tables_end`](crate::MpqHeader::tables_end). File data
+    /// only counts once the block or BET table is loaded.
+    pub fn computed_archive_size(&self) -> u64 {
+        let files_end = if let Some(block_table) = &self.block_table {
+            block_table
+                .entries()
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.exists())
+                .map(|(index, entry)| {
+                    let high = self
+                        .hi_block_table
+                        .as_ref()
+                        .map_or(0, |hi_block| hi_block.get_file_pos_high(index));
+                    let pos = (high << 32) | entry.file_pos as u64;
+                    pos.saturating_add(self.block_sizes(index, entry).1)
+                })
+                .max()
+        } else if let Some(bet) = &self.bet_table {
+            (0..bet.header.file_count)
+                .filter_map(|index| bet.get_file_info(index))
+                .filter(|info| info.flags & BlockEntry::FLAG_EXISTS != 0)
+                .map(|info| info.file_pos.saturating_add(info.compressed_size))
+                .max()
+        } else {
+            None
+        };
+        files_end.map_or(self.header.tables_end(), |end| {
+            end.max(self.header.tables_end())
+        })
+    }
+
+    /// Archive size to locate a strong signature with
+    ///
+    /// The declared size, unless it falls short of the tables and files or
+    /// runs past the end of the file.
+    fn signed_archive_size(&self) -> Result<u64> {
+        let declared = self.header.get_archive_size();
+        let computed = self.computed_archive_size();
+        let available = self.data.len()?.saturating_sub(self.archive_offset);
+        Ok(if declared < computed || declared > available {
+            log::debug!(
+                "Declared archive size {} is malformed, using computed size {}",
+                declared,
+                computed
+            );
+            computed
+        } else {
+            declared
+        })
+    }
+
+    /// Take the diagnostics recorded since they were last taken
+    ///
+    /// Each entry describes how one file or sector was decompressed, in the
+    /// order they were read. Always empty unless the archive was opened with
+    /// [`OpenOptions::record_decode_diagnostics`]. Clones of the archive
+    /// keep their own records.
+    pub fn take_decode_diagnostics(&self) -> Vec<DecodeDiagnostics> {
+        self.decode_diagnostics
+            .as_ref()
+            .map(|recorded| {
+                std::mem::take(&mut *recorded.lock().unwrap_or_else(|e| e.into_inner()))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Check whether all of a file's stored data is present in the archive file
+    ///
+    /// Only ever false for a [truncated](Self::is_truncated) archive. Such a
+    /// file cannot be read with [`read_file`](Self::read_file), but
+    /// [`read_file_partial`](Self::read_file_partial) salvages the sectors
+    /// that are left.
+    pub fn is_file_intact(&self, file_info: &FileInfo) -> Result<bool> {
+        if !self.truncated {
+            return Ok(true);
+        }
+        let file_size = self.data.len()?;
+        Ok(file_info.file_pos + file_info.compressed_size <= file_size)
+    }
+
+    /// Get the user data header if present
+    pub fn user_data(&self) -> Option<&UserDataHeader> {
+        self.user_data.as_ref()
+    }
+
+    /// Read the raw user data that precedes the MPQ header
+    ///
+    /// Returns the `user_data_size` bytes that follow the user data header,
+    /// or `None` if the archive has no user data. The returned bytes can be
+    /// passed to [`ArchiveBuilder::user_data()`] to preserve them when
+    /// rebuilding the archive.
+    pub fn user_data_bytes(&mut self) -> Result<Option<Vec<u8>>> {
+        let user_data = match &self.user_data {
+            Some(user_data) => user_data,
+            None => return Ok(None),
+        };
+
+        let user_data_offset = self
+            .archive_offset
+            .checked_sub(user_data.header_offset as u64)
+            .ok_or_else(|| Error::InvalidHeader("User data offset underflow".to_string()))?;
+
+        let mut data = vec![0u8; user_data.user_data_size as usize];
+        self.reader.seek(SeekFrom::Start(
+            user_data_offset + UserDataHeader::SIZE as u64,
+        ))?;
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some(data))
+    }
+
+    /// Get the archive offset in the file
+    pub fn archive_offset(&self) -> u64 {
         self.archive_offset
     }
 
     /// Get the path to the archive
+    ///
+    /// Empty for archives opened with [`from_file`](Self::from_file).
     pub fn path(&self) -> &Path {
         &self.path
     }
 
     /// Get the hi-block table if present (v2+ archives)
     pub fn hi_block_table(&self) -> Option<&HiBlockTable> {
-        self.hi_block_table.as_ref()
+        self.hi_block_table.as_deref()
     }
 
     /// Validate MD5 checksums for v4 archives
     fn validate_v4_md5_checksums(&mut self) -> Result<Option<Md5Status>> {
-        use md5::{Digest, Md5};
-
         let v4_data = match &self.header.v4_data {
             Some(data) => data,
             None => return Ok(None),
@@ -544,12 +1501,7 @@ impl Archive {
                 let mut table_data = vec![0u8; size as usize];
                 self.reader.read_exact(&mut table_data)?;
 
-                // Calculate MD5
-                let mut hasher = Md5::new();
-                hasher.update(&table_data);
-                let actual_md5: [u8; 16] = hasher.finalize().into();
-
-                Ok(actual_md5 == *expected)
+                Ok(crate::checksum::md5(&table_data) == *expected)
             };
 
         // Validate hash table MD5
@@ -612,11 +1564,7 @@ impl Archive {
             let mut header_data = vec![0u8; 192];
             self.reader.read_exact(&mut header_data)?;
 
-            let mut hasher = Md5::new();
-            hasher.update(&header_data);
-            let actual_md5: [u8; 16] = hasher.finalize().into();
-
-            actual_md5 == v4_data.md5_mpq_header
+            crate::checksum::md5(&header_data) == v4_data.md5_mpq_header
         };
 
         Ok(Some(Md5Status {
@@ -637,7 +1585,7 @@ impl Archive {
         }
 
         // Get file size
-        let file_size = self.reader.get_ref().metadata()?.len();
+        let file_size = self.data.len()?;
 
         // Count files
         let file_count = if let Some(bet) = &self.bet_table {
@@ -713,40 +1661,10 @@ impl Archive {
                 return None;
             }
 
-            // For v4, use the size from v4 data
-            let mut compressed_size = self.header.v4_data.as_ref().map(|v4| v4.het_table_size_64);
-
-            // For v3 without v4 data, try to determine the size
-            if compressed_size.is_none() && self.header.format_version == header::FormatVersion::V3
-            {
-                // Make a copy of the reader to avoid interfering with the main archive
-                if let Ok(temp_reader) =
-                    std::fs::File::open(&self.path).map(std::io::BufReader::new)
-                {
-                    let mut temp_archive = Self {
-                        path: self.path.clone(),
-                        reader: temp_reader,
-                        archive_offset: self.archive_offset,
-                        user_data: self.user_data.clone(),
-                        header: self.header.clone(),
-                        hash_table: None,
-                        block_table: None,
-                        hi_block_table: None,
-                        het_table: None,
-                        bet_table: None,
-                        attributes: None,
-                    };
-
-                    if let Ok(size) = temp_archive.read_het_table_size(pos) {
-                        compressed_size = Some(size);
-                    }
-                }
-            }
-
             Some(TableInfo {
                 size: self.het_table.as_ref().map(|het| het.header.max_file_count),
                 offset: pos,
-                compressed_size,
+                compressed_size: self.header.het_table_size(),
                 failed_to_load: self.het_table.is_none(),
             })
         });
@@ -756,40 +1674,10 @@ impl Archive {
                 return None;
             }
 
-            // For v4, use the size from v4 data
-            let mut compressed_size = self.header.v4_data.as_ref().map(|v4| v4.bet_table_size_64);
-
-            // For v3 without v4 data, try to determine the size
-            if compressed_size.is_none() && self.header.format_version == header::FormatVersion::V3
-            {
-                // Make a copy of the reader to avoid interfering with the main archive
-                if let Ok(temp_reader) =
-                    std::fs::File::open(&self.path).map(std::io::BufReader::new)
-                {
-                    let mut temp_archive = Self {
-                        path: self.path.clone(),
-                        reader: temp_reader,
-                        archive_offset: self.archive_offset,
-                        user_data: self.user_data.clone(),
-                        header: self.header.clone(),
-                        hash_table: None,
-                        block_table: None,
-                        hi_block_table: None,
-                        het_table: None,
-                        bet_table: None,
-                        attributes: None,
-                    };
-
-                    if let Ok(size) = temp_archive.read_bet_table_size(pos) {
-                        compressed_size = Some(size);
-                    }
-                }
-            }
-
             Some(TableInfo {
                 size: self.bet_table.as_ref().map(|bet| bet.header.file_count),
                 offset: pos,
-                compressed_size,
+                compressed_size: self.header.bet_table_size(),
                 failed_to_load: self.bet_table.is_none(),
             })
         });
@@ -847,37 +1735,81 @@ impl Archive {
             has_listfile,
             user_data_info,
             md5_status,
+            is_truncated: self.truncated,
+            header_reconciliation: self.header_reconciliation,
+            computed_archive_size: self.computed_archive_size(),
         })
     }
 
     /// Get the hash table
     pub fn hash_table(&self) -> Option<&HashTable> {
-        self.hash_table.as_ref()
+        self.hash_table.as_deref()
     }
 
     /// Get the block table
     pub fn block_table(&self) -> Option<&BlockTable> {
-        self.block_table.as_ref()
+        self.block_table.as_deref()
     }
 
     /// Get HET table reference
     pub fn het_table(&self) -> Option<&HetTable> {
-        self.het_table.as_ref()
+        self.het_table.as_deref()
     }
 
     /// Get BET table reference
     pub fn bet_table(&self) -> Option<&BetTable> {
-        self.bet_table.as_ref()
+        self.bet_table.as_deref()
     }
 
     /// Find a file in the archive
     pub fn find_file(&self, filename: &str) -> Result<Option<FileInfo>> {
+        self.find_file_pre(&PrecomputedName::with_policy(filename, self.name_hashing))
+    }
+
+    /// Find a file by a name whose hashes were computed up front
+    ///
+    /// Behaves like [`find_file`](Self::find_file) without hashing the name
+    /// again, which adds up when the same names are looked up in many
+    /// archives. Names hashed with a different [`NameHashingPolicy`] than
+    /// the archive uses are rehashed.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{Archive, PrecomputedName};
+    ///
+    /// let name = PrecomputedName::new("war3map.j");
+    /// for path in ["base.mpq", "patch.mpq"] {
+    ///     if let Some(info) = Archive::open(path)?.find_file_pre(&name)? {
+    ///         println!("{path}: {} bytes", info.file_size);
+    ///     }
+    /// }
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn find_file_pre(&self, name: &PrecomputedName) -> Result<Option<FileInfo>> {
+        let rehashed;
+        let name = if name.policy() == self.name_hashing {
+            name
+        } else {
+            rehashed = PrecomputedName::with_policy(name.name(), self.name_hashing);
+            &rehashed
+        };
+        let filename = name.name();
+
         // For v3+ archives, prioritize HET/BET tables if they exist and are valid
         if let (Some(het), Some(bet)) = (&self.het_table, &self.bet_table) {
             // Check if tables have actual entries
             if het.header.max_file_count > 0 && bet.header.file_count > 0 {
-                if let Some(file_index) = het.find_file(filename) {
+                if let Some(file_index) = het.find_file_pre(name, bet) {
                     if let Some(bet_info) = bet.get_file_info(file_index) {
+                        // HET/BET don't store the platform, take it from the
+                        // classic hash table when one is present
+                        let platform = self
+                            .hash_table
+                            .as_ref()
+                            .and_then(|hash| hash.find_file_pre(name, 0))
+                            .map(|(_, entry)| entry.platform)
+                            .unwrap_or(0);
+
                         return Ok(Some(FileInfo {
                             filename: filename.to_string(),
                             hash_index: 0, // Not applicable for HET/BET
@@ -886,16 +1818,17 @@ impl Archive {
                             compressed_size: bet_info.compressed_size,
                             file_size: bet_info.file_size,
                             flags: bet_info.flags,
-                            locale: 0, // HET/BET don't store locale separately
+                            locale: Locale::NEUTRAL, // HET/BET don't store locale separately
+                            platform,
                         }));
                     }
                 }
+            }
 
-                // If HET/BET tables are valid but file not found, only fall back if hash tables exist
-                // Some v3+ archives may have both table types for compatibility
-                if self.hash_table.is_none() || self.block_table.is_none() {
-                    return Ok(None);
-                }
+            // If the file is not in HET/BET, only fall back if hash tables exist
+            // Some v3+ archives may have both table types for compatibility
+            if self.hash_table.is_none() || self.block_table.is_none() {
+                return Ok(None);
             }
         }
 
@@ -903,11 +1836,57 @@ impl Archive {
         // 1. HET/BET tables don't exist
         // 2. HET/BET tables are empty/invalid
         // 3. File wasn't found in HET/BET but hash/block tables exist
-        self.find_file_classic(filename)
+        self.find_file_classic(name)
+    }
+
+    /// Find many files at once
+    ///
+    /// Returns what [`find_file`](Self::find_file) would for each name, in
+    /// the order given. All names are hashed first and then looked up in
+    /// the order of their hash table slots, so large batches, such as
+    /// verifying every file of a game installation, walk the tables front
+    /// to back instead of jumping around them.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let archive = Archive::open("War3.mpq")?;
+    /// let found = archive.find_files(&["war3map.j", "war3map.w3e"])?;
+    /// let missing = found.iter().filter(|info| info.is_none()).count();
+    /// println!("{missing} files missing");
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// Any error [`find_file`](Self::find_file) reports for one of the names
+    pub fn find_files(&self, names: &[&str]) -> Result<Vec<Option<FileInfo>>> {
+        let hashed: Vec<PrecomputedName> = names
+            .iter()
+            .map(|name| PrecomputedName::with_policy(name, self.name_hashing))
+            .collect();
+
+        let mut order: Vec<usize> = (0..hashed.len()).collect();
+        match (&self.het_table, &self.hash_table) {
+            (Some(het), _) if het.header.max_file_count > 0 => {
+                order.sort_by_key(|&i| het.start_slot(&hashed[i]));
+            }
+            (_, Some(hash_table)) => {
+                let mask = hash_table.size().max(1) - 1;
+                order.sort_by_key(|&i| hashed[i].table_offset() as usize & mask);
+            }
+            _ => {}
+        }
+
+        let mut found: Vec<Option<FileInfo>> = (0..hashed.len()).map(|_| None).collect();
+        for i in order {
+            found[i] = self.find_file_pre(&hashed[i])?;
+        }
+        Ok(found)
     }
 
     /// Classic file lookup using hash/block tables
-    fn find_file_classic(&self, filename: &str) -> Result<Option<FileInfo>> {
+    fn find_file_classic(&self, name: &PrecomputedName) -> Result<Option<FileInfo>> {
         let hash_table = self
             .hash_table
             .as_ref()
@@ -918,43 +1897,155 @@ impl Archive {
             .ok_or_else(|| Error::invalid_format("Block table not loaded"))?;
 
         // Try to find the file with default locale
-        if let Some((hash_index, hash_entry)) = hash_table.find_file(filename, 0) {
-            let block_entry = block_table
-                .get(hash_entry.block_index as usize)
-                .ok_or_else(|| Error::block_table("Invalid block index"))?;
-
-            // Calculate full file position for v2+ archives
-            let file_pos = if let Some(hi_block) = &self.hi_block_table {
-                let high_bits = hi_block.get_file_pos_high(hash_entry.block_index as usize);
-                (high_bits << 32) | (block_entry.file_pos as u64)
-            } else {
-                block_entry.file_pos as u64
-            };
+        match hash_table.find_file_pre(name, 0) {
+            Some((hash_index, hash_entry)) => {
+                self.classic_file_info(name.name(), hash_index, hash_entry, block_table)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// File information for a hash table entry, or `None` if its block was
+    /// lost to truncation
+    fn classic_file_info(
+        &self,
+        filename: &str,
+        hash_index: usize,
+        hash_entry: &HashEntry,
+        block_table: &BlockTable,
+    ) -> Result<Option<FileInfo>> {
+        let block_entry = block_table
+            .get(hash_entry.block_index as usize)
+            .ok_or_else(|| Error::block_table("Invalid block index"))?;
+        if self.truncated && !block_entry.exists() {
+            log::debug!("Block of '{}' was lost to truncation", filename);
+            return Ok(None);
+        }
 
-            Ok(Some(FileInfo {
-                filename: filename.to_string(),
-                hash_index,
-                block_index: hash_entry.block_index as usize,
-                file_pos: self.archive_offset + file_pos,
-                compressed_size: block_entry.compressed_size as u64,
-                file_size: block_entry.file_size as u64,
-                flags: block_entry.flags,
-                locale: hash_entry.locale,
-            }))
+        // Calculate full file position for v2+ archives
+        let file_pos = if let Some(hi_block) = &self.hi_block_table {
+            let high_bits = hi_block.get_file_pos_high(hash_entry.block_index as usize);
+            (high_bits << 32) | (block_entry.file_pos as u64)
         } else {
-            Ok(None)
+            block_entry.file_pos as u64
+        };
+
+        let (file_size, compressed_size) =
+            self.block_sizes(hash_entry.block_index as usize, block_entry);
+        Ok(Some(FileInfo {
+            filename: filename.to_string(),
+            hash_index,
+            block_index: hash_entry.block_index as usize,
+            file_pos: self.archive_offset + file_pos,
+            compressed_size,
+            file_size,
+            flags: block_entry.flags,
+            locale: hash_entry.locale.into(),
+            platform: hash_entry.platform,
+        }))
+    }
+
+    /// File and stored size of a block
+    ///
+    /// The block table only keeps the low 32 bits of the sizes of files over
+    /// 4 GiB, so the full sizes are taken from the BET table when it has
+    /// the same block.
+    fn block_sizes(&self, block_index: usize, block_entry: &BlockEntry) -> (u64, u64) {
+        self.bet_table
+            .as_ref()
+            .and_then(|bet| bet.get_file_info(u32::try_from(block_index).ok()?))
+            .filter(|info| {
+                info.file_size as u32 == block_entry.file_size
+                    && info.compressed_size as u32 == block_entry.compressed_size
+            })
+            .map_or(
+                (
+                    block_entry.file_size as u64,
+                    block_entry.compressed_size as u64,
+                ),
+                |info| (info.file_size, info.compressed_size),
+            )
+    }
+
+    /// Find the version of a file stored for `locale`
+    ///
+    /// Falls back to the neutral version when the file has none for
+    /// `locale`, the way games resolve localized files. Unlike
+    /// [`find_file`](Self::find_file), versions of other locales are never
+    /// returned. HET/BET-only archives store no locales, so every file in
+    /// them counts as neutral.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{Archive, Locale};
+    ///
+    /// let archive = Archive::open("War3x.mpq")?;
+    /// if let Some(info) = archive.find_file_with_locale("war3map.wts", Locale::FR_FR)? {
+    ///     println!("{} ({})", info.filename, info.locale);
+    /// }
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn find_file_with_locale(
+        &self,
+        filename: &str,
+        locale: impl Into<Locale>,
+    ) -> Result<Option<FileInfo>> {
+        let locale = locale.into().code();
+        let (Some(hash_table), Some(block_table)) = (&self.hash_table, &self.block_table) else {
+            return self.find_file(filename);
+        };
+
+        let entries = hash_table.find_all_with(filename, self.name_hashing);
+        let chosen = entries
+            .iter()
+            .find(|(_, entry)| entry.locale == locale)
+            .or_else(|| entries.iter().find(|(_, entry)| entry.locale == 0));
+        match chosen {
+            Some(&(hash_index, hash_entry)) => {
+                self.classic_file_info(filename, hash_index, hash_entry, block_table)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Locales a file is stored in, sorted by code
+    ///
+    /// Empty if the archive has no such file. HET/BET-only archives store no
+    /// locales, so their files only have the neutral one.
+    pub fn file_locales(&self, filename: &str) -> Result<Vec<Locale>> {
+        let Some(hash_table) = &self.hash_table else {
+            return Ok(match self.find_file(filename)? {
+                Some(_) => vec![Locale::NEUTRAL],
+                None => Vec::new(),
+            });
+        };
+
+        let mut locales: Vec<Locale> = hash_table
+            .find_all_with(filename, self.name_hashing)
+            .into_iter()
+            .map(|(_, entry)| Locale::from(entry.locale))
+            .collect();
+        locales.sort();
+        locales.dedup();
+        Ok(locales)
+    }
+
+    /// Names in the `(listfile)`, taken from the metadata cache if it has them
+    fn listfile_names(&self) -> Result<Vec<String>> {
+        match &self.listfile_names {
+            Some(names) => Ok(names.to_vec()),
+            None => special_files::parse_listfile(&self.read_file("(listfile)")?),
         }
     }
 
     /// List files in the archive
+    ///
+    /// Special files such as `(listfile)` are included; use
+    /// [`list_with`](Self::list_with) for a listing without them.
     pub fn list(&mut self) -> Result<Vec<FileEntry>> {
         // Try to find and read (listfile)
         if let Some(_listfile_info) = self.find_file("(listfile)")? {
-            // Read the listfile
-            let listfile_data = self.read_file("(listfile)")?;
-
-            // Parse the listfile
-            let filenames = special_files::parse_listfile(&listfile_data)?;
+            let filenames = self.listfile_names()?;
 
             let mut entries = Vec::new();
 
@@ -1010,6 +2101,9 @@ impl Archive {
                         return Ok(entries);
                     }
                 }
+                if self.hash_table.is_none() {
+                    return Ok(entries);
+                }
             }
 
             // Fall back to classic hash/block tables
@@ -1045,8 +2139,57 @@ impl Archive {
         }
     }
 
+    /// List files in the archive, filtered and sorted
+    ///
+    /// Files are enumerated like [`list`](Self::list), then filtered and
+    /// sorted according to `options`. Special files are left out unless
+    /// [`ListOptions::include_special`] is set. Sorting is stable, so entries that
+    /// compare equal keep their listing order. When sorting by
+    /// [`ListSort::BlockIndex`], entries that cannot be looked up by name
+    /// sort after all others in ascending order.
+    pub fn list_with(&mut self, options: &ListOptions) -> Result<Vec<FileEntry>> {
+        let mut entries = self.list()?;
+        entries.retain(|entry| options.accepts(entry));
+
+        let order = |ordering: std::cmp::Ordering| {
+            if options.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        };
+        match options.sort_by {
+            ListSort::Unsorted => {}
+            ListSort::Name => {
+                let mut keyed: Vec<_> = entries
+                    .into_iter()
+                    .map(|entry| (entry.name.to_ascii_lowercase(), entry))
+                    .collect();
+                keyed.sort_by(|a, b| order(a.0.cmp(&b.0)));
+                entries = keyed.into_iter().map(|(_, entry)| entry).collect();
+            }
+            ListSort::Size => entries.sort_by(|a, b| order(a.size.cmp(&b.size))),
+            ListSort::Ratio => entries
+                .sort_by(|a, b| order(a.compression_ratio().total_cmp(&b.compression_ratio()))),
+            ListSort::BlockIndex => {
+                let mut keyed = Vec::with_capacity(entries.len());
+                for entry in entries {
+                    let index = self
+                        .find_file(&entry.name)?
+                        .map_or(usize::MAX, |info| info.block_index);
+                    keyed.push((index, entry));
+                }
+                keyed.sort_by(|a, b| order(a.0.cmp(&b.0)));
+                entries = keyed.into_iter().map(|(_, entry)| entry).collect();
+            }
+        }
+
+        Ok(entries)
+    }
+
     /// List all files in the archive by enumerating tables
-    /// This shows all entries, using generic names for files not in listfile
+    /// This shows all entries, using generic names for files not in listfile,
+    /// and keeps special files
     pub fn list_all(&mut self) -> Result<Vec<FileEntry>> {
         let mut entries = Vec::new();
 
@@ -1128,8 +2271,8 @@ impl Archive {
 
         // Calculate hashes for each entry
         for entry in &mut entries {
-            let hash1 = crate::crypto::hash_string(&entry.name, crate::crypto::hash_type::NAME_A);
-            let hash2 = crate::crypto::hash_string(&entry.name, crate::crypto::hash_type::NAME_B);
+            let hash1 = hash_string_with(&entry.name, hash_type::NAME_A, self.name_hashing);
+            let hash2 = hash_string_with(&entry.name, hash_type::NAME_B, self.name_hashing);
             entry.hashes = Some((hash1, hash2));
         }
 
@@ -1209,12 +2352,1174 @@ impl Archive {
         Ok(entries)
     }
 
-    /// Read a file from the archive
-    pub fn read_file(&mut self, name: &str) -> Result<Vec<u8>> {
-        let file_info = self
-            .find_file(name)?
-            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+    /// Entries that the `(listfile)` does not name, with their name hashes
+    ///
+    /// Every existing file in the hash table, or in the HET table for
+    /// archives without one, is returned unless it can be found under a name
+    /// from the `(listfile)` or one of the special file names. Without a
+    /// listfile that is every file. Entries are sorted by block index.
+    ///
+    /// Unlike the generated names of [`list_all_with_hashes`](Self::list_all_with_hashes),
+    /// the hashes let name-recovery tools and databases match entries
+    /// against hashed candidate names directly.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::crypto::{hash_string, hash_type};
+    /// use mopaq::Archive;
+    ///
+    /// let archive = Archive::open("war3.mpq")?;
+    /// let guess = "Scripts\\Blizzard.j";
+    /// let hash = (hash_string(guess, hash_type::NAME_A), hash_string(guess, hash_type::NAME_B));
+    /// for entry in archive.anonymous_entries()? {
+    ///     if (entry.hash_a, entry.hash_b) == (Some(hash.0), Some(hash.1)) {
+    ///         println!("block {} is {}", entry.block_index, guess);
+    ///     }
+    /// }
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - Any error from reading the `(listfile)` or looking up its names
+    pub fn anonymous_entries(&self) -> Result<Vec<AnonymousEntry>> {
+        let mut names: Vec<String> = SpecialFile::ALL
+            .iter()
+            .map(|special| special.as_str().to_string())
+            .collect();
+        if self.find_file("(listfile)")?.is_some() {
+            names.extend(self.listfile_names()?);
+        }
+        let mut named = std::collections::HashSet::new();
+        for name in &names {
+            for locale in self.file_locales(name)? {
+                if let Some(file_info) = self.find_file_with_locale(name, locale)? {
+                    named.insert(file_info.block_index);
+                }
+            }
+        }
+
+        let het_hashes: std::collections::HashMap<usize, u64> =
+            match (&self.het_table, &self.bet_table) {
+                (Some(het), Some(bet)) => het
+                    .name_hashes(bet)
+                    .map(|(index, hash)| (index as usize, hash))
+                    .collect(),
+                _ => Default::default(),
+            };
+
+        let mut entries = Vec::new();
+        let mut push = |block_index: usize, hashes: Option<(u32, u32)>, locale: u16| {
+            if named.contains(&block_index) {
+                return;
+            }
+            let Some(file_info) = self.file_info_by_index(block_index) else {
+                return;
+            };
+            if file_info.flags & BlockEntry::FLAG_EXISTS == 0 {
+                return;
+            }
+            entries.push(AnonymousEntry {
+                hash_a: hashes.map(|(hash_a, _)| hash_a),
+                hash_b: hashes.map(|(_, hash_b)| hash_b),
+                het_hash: het_hashes.get(&block_index).copied(),
+                block_index,
+                locale: locale.into(),
+                size: file_info.file_size,
+                compressed_size: file_info.compressed_size,
+                flags: file_info.flags,
+            });
+        };
+
+        if let Some(hash_table) = &self.hash_table {
+            for hash_entry in hash_table.entries().iter().filter(|entry| entry.is_valid()) {
+                push(
+                    hash_entry.block_index as usize,
+                    Some((hash_entry.name_1, hash_entry.name_2)),
+                    hash_entry.locale,
+                );
+            }
+        } else {
+            let mut indices: Vec<usize> = het_hashes.keys().copied().collect();
+            indices.sort_unstable();
+            for block_index in indices {
+                push(block_index, None, 0);
+            }
+        }
+
+        entries.sort_by_key(|entry| (entry.block_index, entry.locale));
+        Ok(entries)
+    }
+
+    /// Look up a file by its index in the block or BET table
+    ///
+    /// The result has no name. Returns `None` if the index is out of range.
+    fn file_info_by_index(&self, block_index: usize) -> Option<FileInfo> {
+        if let (Some(bet), true) = (&self.bet_table, self.block_table.is_none()) {
+            let bet_info = bet.get_file_info(u32::try_from(block_index).ok()?)?;
+            return Some(FileInfo {
+                filename: String::new(),
+                hash_index: 0,
+                block_index,
+                file_pos: self.archive_offset + bet_info.file_pos,
+                compressed_size: bet_info.compressed_size,
+                file_size: bet_info.file_size,
+                flags: bet_info.flags,
+                locale: Locale::NEUTRAL,
+                platform: 0,
+            });
+        }
+
+        let block_entry = self.block_table.as_ref()?.get(block_index)?;
+        let high_bits = self
+            .hi_block_table
+            .as_ref()
+            .map_or(0, |hi_block| hi_block.get_file_pos_high(block_index));
+        let (file_size, compressed_size) = self.block_sizes(block_index, block_entry);
+        Some(FileInfo {
+            filename: String::new(),
+            hash_index: 0,
+            block_index,
+            file_pos: self.archive_offset + ((high_bits << 32) | block_entry.file_pos as u64),
+            compressed_size,
+            file_size,
+            flags: block_entry.flags,
+            locale: Locale::NEUTRAL,
+            platform: 0,
+        })
+    }
+
+    /// Directory tree of the files returned by [`list`](Self::list)
+    ///
+    /// Names are split on `\` and `/` into nested [`DirNode`]s carrying file
+    /// counts and sizes for everything below them.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let mut archive = Archive::open("war3.mpq")?;
+    /// let tree = archive.tree()?;
+    /// if let Some(units) = tree.find("units") {
+    ///     println!("{} files, {} bytes", units.file_count, units.total_size);
+    /// }
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn tree(&mut self) -> Result<DirNode> {
+        Ok(DirNode::from_entries(&self.list()?))
+    }
+
+    /// Kind of content stored in a file
+    ///
+    /// The file is read and its contents passed to
+    /// [`detect::file_kind`](crate::detect::file_kind); the name plays no
+    /// part, so this also works for entries found without a listfile.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if `name` is not in the archive
+    /// - Any error from reading the file
+    pub fn file_kind(&mut self, name: &str) -> Result<crate::detect::FileKind> {
+        Ok(crate::detect::file_kind(&self.read_file(name)?))
+    }
+
+    /// Write the archive's file names as a listfile
+    ///
+    /// Names come from [`list`](Self::list), one per line with `\r\n`
+    /// endings, optionally followed by the metadata `format` asks for. The
+    /// output can be fed back to
+    /// [`MutableArchive::import_listfile`](crate::MutableArchive::import_listfile)
+    /// or shared as a community listfile. Returns the number of lines written.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::special_files::ListfileFormat;
+    /// use mopaq::Archive;
+    ///
+    /// let mut archive = Archive::open("war3.mpq")?;
+    /// let out = std::fs::File::create("war3.txt")?;
+    /// archive.export_listfile(out, ListfileFormat::NameSizeCrc)?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - Any error from listing the archive or from writing to `writer`
+    /// - With a CRC format, any error from reading a file
+    pub fn export_listfile<W: std::io::Write>(
+        &mut self,
+        mut writer: W,
+        format: crate::special_files::ListfileFormat,
+    ) -> Result<usize> {
+        let entries = self.list()?;
+        for entry in &entries {
+            let mut crc = crc32fast::Hasher::new();
+            if format.needs_crc() {
+                self.read_file_chunks(&entry.name, |chunk| {
+                    crc.update(chunk);
+                    Ok(true)
+                })?;
+            }
+            let line = format.line(&entry.name, entry.size, crc.finalize());
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(b"\r\n")?;
+        }
+        writer.flush()?;
+        Ok(entries.len())
+    }
+
+    /// Snapshot the archive's contents for later comparison
+    ///
+    /// Every file in the `(listfile)` is recorded with its sizes, flags,
+    /// locale, position and the CRC32 and MD5 of its contents. Contents are
+    /// hashed as [`read_file_chunks`](Self::read_file_chunks) streams them.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let mut archive = Archive::open("patch.mpq")?;
+    /// archive.export_manifest()?.save("patch.mpqmanifest")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - Any error from listing or reading the files
+    pub fn export_manifest(&mut self) -> Result<crate::Manifest> {
+        use md5::{Digest, Md5};
+
+        let mut entries = Vec::new();
+        for entry in self.list()? {
+            let file_info = self
+                .find_file(&entry.name)?
+                .ok_or_else(|| Error::FileNotFound(entry.name.clone()))?;
+
+            let mut crc = crc32fast::Hasher::new();
+            let mut md5 = Md5::new();
+            self.read_file_chunks(&entry.name, |chunk| {
+                crc.update(chunk);
+                md5.update(chunk);
+                Ok(true)
+            })?;
+
+            entries.push(crate::ManifestEntry {
+                name: entry.name,
+                size: file_info.file_size,
+                compressed_size: file_info.compressed_size,
+                flags: file_info.flags,
+                locale: file_info.locale,
+                offset: file_info.file_pos,
+                crc32: crc.finalize(),
+                md5: md5.finalize().into(),
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(crate::Manifest {
+            entries,
+            name_hashing: self.name_hashing,
+        })
+    }
+
+    /// Extract every file in the archive below `dir`
+    ///
+    /// Shorthand for [`extract_all_with`](Self::extract_all_with) with
+    /// default [`ExtractOptions`].
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let mut archive = Archive::open("war3.mpq")?;
+    /// let summary = archive.extract_all("war3")?;
+    /// for failure in &summary.failed {
+    ///     eprintln!("{}: {}", failure.name, failure.error);
+    /// }
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - Any error from creating `dir` or listing the archive
+    pub fn extract_all<P: AsRef<Path>>(&mut self, dir: P) -> Result<ExtractSummary> {
+        self.extract_all_with(dir, &ExtractOptions::new())
+    }
+
+    /// Extract every file in the archive below `dir`, with options
+    ///
+    /// Files are taken from [`list`](Self::list), visited in the
+    /// [`ExtractionOrder`] of `options`, and written to paths built from
+    /// their names, creating directories as needed. Each file is
+    /// streamed to disk as [`read_file_chunks`](Self::read_file_chunks)
+    /// decompresses it. A file that cannot be read or written, or whose
+    /// name would leave `dir`, is recorded in [`ExtractSummary::failed`]
+    /// and extraction carries on with the next one. Paths that are already
+    /// taken are handled by the
+    /// [`CollisionPolicy`](crate::CollisionPolicy) of `options`; with
+    /// [`CollisionPolicy::IfDifferent`](crate::CollisionPolicy::IfDifferent)
+    /// or [`resume`](ExtractOptions::resume), files already on disk that
+    /// match are recorded in [`ExtractSummary::unchanged`] and not written.
+    ///
+    /// # Errors
+    /// - Any error from creating `dir` or listing the archive
+    pub fn extract_all_with<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        options: &ExtractOptions,
+    ) -> Result<ExtractSummary> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let mut summary = ExtractSummary::default();
+        let mut work = Vec::new();
+        for entry in self.list()? {
+            match self.find_file(&entry.name)? {
+                Some(file_info) => work.push((entry.name, file_info)),
+                None => summary.failed.push(ExtractFailure {
+                    error: Error::FileNotFound(entry.name.clone()),
+                    name: entry.name,
+                }),
+            }
+        }
+
+        summary.listing_seek_distance = crate::extract::seek_distance(
+            work.iter()
+                .map(|(_, info)| (info.file_pos, info.compressed_size)),
+        );
+        if options.order == ExtractionOrder::ArchiveLayout {
+            work.sort_by_key(|(_, info)| info.file_pos);
+        }
+        summary.seek_distance = crate::extract::seek_distance(
+            work.iter()
+                .map(|(_, info)| (info.file_pos, info.compressed_size)),
+        );
+
+        let readahead = options
+            .prefetch
+            .then(|| Readahead::spawn(Arc::clone(&self.data)));
+        if let (Some(readahead), Some((_, first))) = (&readahead, work.first()) {
+            readahead.request(first.file_pos, first.compressed_size);
+        }
+
+        let if_different = options.collision_policy == CollisionPolicy::IfDifferent;
+        if if_different {
+            // Checksums spare decompressing files to compare them
+            if let Err(e) = self.load_attributes() {
+                log::debug!("Comparing without (attributes): {e}");
+            }
+        }
+
+        let mut output = OutputDir::new(dir, options.collision_policy);
+        for (i, (name, file_info)) in work.iter().enumerate() {
+            if let (Some(readahead), Some((_, next))) = (&readahead, work.get(i + 1)) {
+                readahead.request(next.file_pos, next.compressed_size);
+            }
+            if options.resume || if_different {
+                if let Some(existing) = output.existing(name) {
+                    match self.output_unchanged(&existing, name, file_info, options.resume) {
+                        Ok(true) => {
+                            output.keep(&existing);
+                            summary.unchanged.push(name.clone());
+                            continue;
+                        }
+                        Ok(false) => {}
+                        Err(error) => {
+                            summary.failed.push(ExtractFailure {
+                                name: name.clone(),
+                                error,
+                            });
+                            continue;
+                        }
+                    }
+                }
+            }
+            let path = match output.place(name) {
+                Ok(Placement::Path(path)) => path,
+                Ok(Placement::Renamed(path)) => {
+                    summary.renamed.push((name.clone(), path.clone()));
+                    path
+                }
+                Ok(Placement::Skipped) => {
+                    summary.skipped.push(name.clone());
+                    continue;
+                }
+                Err(error) => {
+                    summary.failed.push(ExtractFailure {
+                        name: name.clone(),
+                        error,
+                    });
+                    continue;
+                }
+            };
+            match self.extract_file_to(&path, name) {
+                Ok(size) => {
+                    summary.extracted += 1;
+                    summary.bytes_written += size;
+                }
+                Err(error) => summary.failed.push(ExtractFailure {
+                    name: name.clone(),
+                    error,
+                }),
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Whether the file at `path` already holds the archived file `name`
+    ///
+    /// Sizes are compared first; with `size_only` that is all. Otherwise
+    /// the file on disk is checked against the `(attributes)` CRC32 or MD5
+    /// if there is one, or else against the decompressed file.
+    fn output_unchanged(
+        &self,
+        path: &Path,
+        name: &str,
+        file_info: &FileInfo,
+        size_only: bool,
+    ) -> Result<bool> {
+        use md5::{Digest, Md5};
+
+        if path.metadata()?.len() != file_info.file_size {
+            return Ok(false);
+        }
+        if size_only {
+            return Ok(true);
+        }
+
+        let attributes = self.get_file_attributes(file_info.block_index);
+        if let Some(expected) = attributes.and_then(|attributes| attributes.crc32) {
+            let mut crc = crc32fast::Hasher::new();
+            hash_file(path, |chunk| crc.update(chunk))?;
+            return Ok(crc.finalize() == expected);
+        }
+        if let Some(expected) = attributes.and_then(|attributes| attributes.md5) {
+            let mut md5 = Md5::new();
+            hash_file(path, |chunk| md5.update(chunk))?;
+            return Ok(<[u8; 16]>::from(md5.finalize()) == expected);
+        }
+        self.file_matches(name, File::open(path)?)
+    }
+
+    /// Write one file to `path`, returning the number of bytes written
+    fn extract_file_to(&self, path: &Path, name: &str) -> Result<u64> {
+        use std::io::Write;
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut writer = std::io::BufWriter::new(File::create(path)?);
+        let mut written = 0u64;
+        self.read_file_chunks(name, |chunk| {
+            writer.write_all(chunk)?;
+            written += chunk.len() as u64;
+            Ok(true)
+        })?;
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Check whether a file in the archive has exactly the contents of `reader`
+    ///
+    /// The archived file is read with [`read_file_chunks`](Self::read_file_chunks)
+    /// and each piece is compared against the same number of bytes from
+    /// `reader`, stopping at the first difference, so neither side is held
+    /// in memory in full.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if `name` is not in the archive
+    /// - Any error from reading the archived file or from `reader`
+    pub fn file_matches<R: Read>(&self, name: &str, mut reader: R) -> Result<bool> {
+        let mut buffer = Vec::new();
+        let mut matches = true;
+        self.read_file_chunks(name, |chunk| {
+            buffer.resize(chunk.len(), 0);
+            match reader.read_exact(&mut buffer) {
+                Ok(()) => matches = buffer == chunk,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => matches = false,
+                Err(e) => return Err(e.into()),
+            }
+            Ok(matches)
+        })?;
+        if !matches {
+            return Ok(false);
+        }
+
+        // The reader has to end where the archived file does
+        loop {
+            match reader.read(&mut [0u8; 1]) {
+                Ok(read) => return Ok(read == 0),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Read a file from the archive
+    ///
+    /// File data is read at explicit offsets rather than through a shared
+    /// seek position, so any number of threads can read from the same
+    /// archive at once.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let archive = Archive::open("war3.mpq")?;
+    /// std::thread::scope(|scope| {
+    ///     scope.spawn(|| archive.read_file("units\\unitdata.slk"));
+    ///     scope.spawn(|| archive.read_file("units\\unitui.slk"));
+    /// });
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+        let file_info = self
+            .find_file(name)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+        self.read_found_file(name, &file_info)
+    }
+
+    /// Read the version of a file stored for `locale`
+    ///
+    /// Falls back to the neutral version like
+    /// [`find_file_with_locale`](Self::find_file_with_locale).
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if the file has neither a version for
+    ///   `locale` nor a neutral one
+    /// - Any error from reading the file
+    pub fn read_file_with_locale(&self, name: &str, locale: impl Into<Locale>) -> Result<Vec<u8>> {
+        let file_info = self
+            .find_file_with_locale(name, locale)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+        self.read_found_file(name, &file_info)
+    }
+
+    /// Read a file found by name
+    fn read_found_file(&self, name: &str, file_info: &FileInfo) -> Result<Vec<u8>> {
+        if !self.is_file_intact(file_info)? {
+            return Err(Error::invalid_format(format!(
+                "'{}' extends past the end of the truncated archive",
+                name
+            )));
+        }
+
+        let (actual_file_size, key) = self.file_size_and_key(name, file_info)?;
+        self.read_file_with_key(file_info, actual_file_size, key)
+    }
+
+    /// Read a file by its index in the block table
+    ///
+    /// For files whose name is unknown, such as the entries returned by
+    /// [`anonymous_entries`](Self::anonymous_entries). For HET/BET-only
+    /// archives the index is into the BET table.
+    ///
+    /// The key of an encrypted file is derived from its name, so without one
+    /// it is recovered from the file's sector offset table, whose first
+    /// entry is known. That works for compressed files stored in sectors;
+    /// other encrypted files can only be read by name.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let archive = Archive::open("protected.mpq")?;
+    /// for entry in archive.anonymous_entries()? {
+    ///     let data = archive.read_file_by_index(entry.block_index)?;
+    ///     std::fs::write(format!("block_{:05}.bin", entry.block_index), data)?;
+    /// }
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if there is no existing file at `block_index`
+    /// - `Error::Crypto` if the file is encrypted and its key cannot be
+    ///   recovered
+    /// - Any error from reading the file
+    pub fn read_file_by_index(&self, block_index: usize) -> Result<Vec<u8>> {
+        let mut file_info = self
+            .file_info_by_index(block_index)
+            .filter(|file_info| file_info.flags & BlockEntry::FLAG_EXISTS != 0)
+            .ok_or_else(|| Error::FileNotFound(format!("block {}", block_index)))?;
+        file_info.filename = format!("block {}", block_index);
+        if !self.is_file_intact(&file_info)? {
+            return Err(Error::invalid_format(format!(
+                "{} extends past the end of the truncated archive",
+                file_info.filename
+            )));
+        }
+
+        let key = if file_info.is_encrypted() {
+            self.recover_file_key(&file_info)?
+        } else {
+            0
+        };
+        self.read_file_with_key(&file_info, file_info.file_size, key)
+    }
+
+    /// Recover the key of an encrypted file without knowing its name
+    ///
+    /// The sector offset table starts with its own size, which is known from
+    /// the file size, so the key can be found from the first encrypted
+    /// DWORD. Candidates are accepted only if the whole table decrypts to
+    /// ascending offsets within the stored data.
+    fn recover_file_key(&self, file_info: &FileInfo) -> Result<u32> {
+        let unresolvable = || {
+            Error::crypto(format!(
+                "Cannot recover the key of encrypted {} without its name",
+                file_info.filename
+            ))
+        };
+        if file_info.is_single_unit() || !file_info.is_compressed() {
+            return Err(unresolvable());
+        }
+
+        let sector_count = (file_info.file_size as usize).div_ceil(self.header.sector_size());
+        let table_size = (sector_count + 1) * 4;
+        if table_size as u64 > file_info.compressed_size {
+            return Err(unresolvable());
+        }
+        let mut table = vec![0u8; table_size];
+        self.data.read_exact_at(&mut table, file_info.file_pos)?;
+        let encrypted: Vec<u32> = table
+            .chunks_exact(4)
+            .map(|dword| u32::from_le_bytes(dword.try_into().unwrap()))
+            .collect();
+
+        // With sector checksums the table has one more entry, for the checksum table
+        for first in [table_size as u32, table_size as u32 + 4] {
+            let key = detect_key_by_known_plaintext(&encrypted, first, |offsets| {
+                offsets.windows(2).all(|pair| pair[0] <= pair[1])
+                    && offsets
+                        .last()
+                        .is_some_and(|&end| end as u64 <= file_info.compressed_size)
+            });
+            if let Some(key) = key {
+                // The offset table is encrypted with the file key minus one
+                return Ok(key.wrapping_add(1));
+            }
+        }
+        Err(unresolvable())
+    }
+
+    /// Read a file whose lookup and key are already known
+    fn read_file_with_key(
+        &self,
+        file_info: &FileInfo,
+        actual_file_size: u64,
+        key: u32,
+    ) -> Result<Vec<u8>> {
+        let data = self.read_file_data(file_info, actual_file_size, key)?;
+        self.report(|m| m.on_file_read(&file_info.filename, data.len() as u64));
+        Ok(data)
+    }
+
+    /// Read and decode the data of a file whose lookup and key are known
+    fn read_file_data(
+        &self,
+        file_info: &FileInfo,
+        actual_file_size: u64,
+        key: u32,
+    ) -> Result<Vec<u8>> {
+        if file_info.is_single_unit() || !file_info.is_compressed() {
+            // Single unit or uncompressed file - read directly. A stored
+            // size past the end of the archive is refused before it is
+            // allocated.
+            if file_info.file_pos.saturating_add(file_info.compressed_size) > self.data.len()? {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            let mut data = vec![0u8; file_info.compressed_size as usize];
+            self.data.read_exact_at(&mut data, file_info.file_pos)?;
+
+            // Decrypt if needed
+            if file_info.is_encrypted() {
+                log::debug!(
+                    "Decrypting file data: key=0x{:08X}, size={}",
+                    key,
+                    data.len()
+                );
+                if data.len() <= 64 {
+                    log::debug!("Before decrypt: {:02X?}", &data);
+                }
+                if file_info.is_single_unit() {
+                    decrypt_file_data(&mut data, key);
+                } else {
+                    // Uncompressed files keep their sectors, each with its own key
+                    let sector_size = self.header.sector_size();
+                    for (i, sector) in data.chunks_mut(sector_size).enumerate() {
+                        decrypt_file_data(sector, key.wrapping_add(i as u32));
+                    }
+                }
+                if data.len() <= 64 {
+                    log::debug!("After decrypt: {:02X?}", &data);
+                }
+            }
+
+            // Validate CRC if present for single unit files
+            if file_info.has_sector_crc() && file_info.is_single_unit() {
+                // For single unit files, there's one CRC after the data
+                let expected_crc = self
+                    .data
+                    .read_u32_at(file_info.file_pos + file_info.compressed_size)?;
+
+                // CRC is calculated on the decompressed data
+                let data_to_check = if file_info.is_compressed() {
+                    // We need to decompress first to check CRC
+                    self.decompress_stored(file_info.flags, &data, actual_file_size as usize)?
+                } else {
+                    data.clone()
+                };
+
+                if let Err(actual_crc) =
+                    SectorChecksum::verify(self.sector_checksum, &data_to_check, expected_crc)
+                {
+                    return Err(Error::ChecksumMismatch {
+                        file: file_info.filename.clone(),
+                        expected: expected_crc,
+                        actual: actual_crc,
+                    });
+                }
+
+                log::debug!("Single unit file CRC validated: 0x{:08X}", expected_crc);
+            }
+
+            // Decompress if needed
+            if file_info.is_compressed() {
+                if !data.is_empty() {
+                    self.decompress_stored(file_info.flags, &data, actual_file_size as usize)
+                } else {
+                    Err(Error::compression("Empty compressed data"))
+                }
+            } else {
+                Ok(data)
+            }
+        } else {
+            // Multi-sector compressed file
+            self.read_sectored_file(file_info, key)
+        }
+    }
+
+    /// Read a file in pieces, without holding all of it in memory
+    ///
+    /// Sectored files are decompressed one sector at a time and each sector
+    /// is passed to `visit` in order; uncompressed, unencrypted files are
+    /// passed in sector-sized pieces straight from the archive. Files stored
+    /// in one piece are read with [`read_file`](Self::read_file) and passed
+    /// whole. `visit` returns whether to continue, so a search can stop at
+    /// its first hit.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if `name` is not in the archive
+    /// - Any error from reading the file or returned by `visit`
+    pub fn read_file_chunks<F>(&self, name: &str, mut visit: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<bool>,
+    {
+        let file_info = self
+            .find_file(name)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+
+        if file_info.is_compressed() && !file_info.is_single_unit() {
+            let (_, key) = self.file_size_and_key(name, &file_info)?;
+            let mut visited = 0u64;
+            let mut finished = false;
+            self.for_each_sector(&file_info, key, |sector| {
+                visited += sector.len() as u64;
+                finished = visited >= file_info.file_size;
+                visit(sector)
+            })?;
+            if finished {
+                self.report(|m| m.on_file_read(name, visited));
+            }
+            return Ok(());
+        }
+        if file_info.is_compressed() || file_info.is_encrypted() || file_info.is_single_unit() {
+            let data = self.read_file(name)?;
+            visit(&data)?;
+            return Ok(());
+        }
+
+        // Uncompressed and unencrypted: the stored bytes are the file
+        let buffer_size = file_info
+            .compressed_size
+            .min(self.header.sector_size() as u64);
+        let mut buffer = vec![0u8; buffer_size as usize];
+        let mut remaining = file_info.compressed_size;
+        let mut offset = file_info.file_pos;
+        while remaining > 0 {
+            let len = remaining.min(buffer.len() as u64) as usize;
+            self.data.read_exact_at(&mut buffer[..len], offset)?;
+            remaining -= len as u64;
+            offset += len as u64;
+            if !visit(&buffer[..len])? {
+                return Ok(());
+            }
+        }
+        self.report(|m| m.on_file_read(name, file_info.compressed_size));
+        Ok(())
+    }
+
+    /// Read as much of a damaged file as possible
+    ///
+    /// Sectored files are read sector by sector, and a sector that cannot
+    /// be read or decompressed is replaced by zeros and reported instead of
+    /// failing the whole file. The returned data therefore always has the
+    /// file's full size, with every byte outside the reported sectors
+    /// intact. A sector whose checksum does not match but which still
+    /// decompresses keeps its data and is reported with
+    /// [`SectorError::recovered`] set.
+    ///
+    /// Files stored in one piece are recovered whole or not at all: if they
+    /// cannot be read, the error is returned.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let mut archive = Archive::open("damaged.mpq")?;
+    /// let (data, errors) = archive.read_file_partial("Sound\\Music\\intro.wav")?;
+    /// for error in &errors {
+    ///     eprintln!("bytes {}..{}: {}", error.offset, error.offset + error.size as u64, error.error);
+    /// }
+    /// std::fs::write("intro.wav", data)?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if `name` is not in the archive
+    /// - Any error from reading the sector offset table, without which no
+    ///   sector can be located
+    /// - Any error from reading a file stored in one piece
+    pub fn read_file_partial(&self, name: &str) -> Result<(Vec<u8>, Vec<SectorError>)> {
+        let file_info = self
+            .find_file(name)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+        let file_size = file_info.file_size as usize;
+
+        if file_info.is_single_unit() || !file_info.is_compressed() {
+            return self.read_file(name).map(|data| (data, Vec::new()));
+        }
+
+        let (_, key) = self.file_size_and_key(name, &file_info)?;
+        let sector_size = self.header.sector_size();
+        let sector_count = file_size.div_ceil(sector_size);
+        let sector_offsets = self.read_sector_offsets(&file_info, key, sector_count)?;
+        let sector_crcs = self
+            .read_sector_checksums(&file_info, &sector_offsets)
+            .unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable sector checksums of {}: {}", name, e);
+                None
+            });
+
+        let mut reader = SectorReader::new(sector_offsets, sector_crcs);
+        let mut data = output_buffer(file_size);
+        let mut errors = Vec::new();
+        for i in 0..sector_count {
+            let offset = data.len();
+            let expected_size = (file_size - offset).min(sector_size);
+            let sector_error = |error, recovered| SectorError {
+                index: i,
+                offset: offset as u64,
+                size: expected_size,
+                error,
+                recovered,
+            };
+
+            match self.read_sector(&file_info, key, &mut reader, i, expected_size) {
+                Ok((sector, checksum_error)) => {
+                    data.extend_from_slice(&sector[..expected_size.min(sector.len())]);
+                    if let Some(error) = checksum_error {
+                        errors.push(sector_error(error, true));
+                    }
+                }
+                Err(error) => errors.push(sector_error(error, false)),
+            }
+            // Keep later sectors at their offsets whatever this one yielded
+            data.resize(offset + expected_size, 0);
+        }
+
+        self.report(|m| m.on_file_read(name, data.len() as u64));
+        Ok((data, errors))
+    }
+
+    /// Visit each line of a file, reading it in pieces
+    ///
+    /// Lines are split on `\n` with a trailing `\r` removed, and numbered
+    /// from 1. The file is read with [`read_file_chunks`](Self::read_file_chunks),
+    /// so memory use is bounded by the sector size and the longest line
+    /// rather than by the size of the file. `visit` returns whether to
+    /// continue.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let mut archive = Archive::open("war3map.w3x")?;
+    /// archive.scan_lines("war3map.j", |number, line| {
+    ///     if line.starts_with(b"function") {
+    ///         println!("{}: {}", number, String::from_utf8_lossy(line));
+    ///     }
+    ///     true
+    /// })?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if `name` is not in the archive
+    /// - Any error from reading the file
+    pub fn scan_lines<F>(&mut self, name: &str, mut visit: F) -> Result<()>
+    where
+        F: FnMut(usize, &[u8]) -> bool,
+    {
+        fn trim_cr(line: &[u8]) -> &[u8] {
+            line.strip_suffix(b"\r").unwrap_or(line)
+        }
+
+        let mut pending = Vec::new();
+        let mut line_number = 0;
+        let mut stopped = false;
+
+        self.read_file_chunks(name, |mut chunk| {
+            while let Some(end) = chunk.iter().position(|&b| b == b'\n') {
+                line_number += 1;
+                let keep_going = if pending.is_empty() {
+                    visit(line_number, trim_cr(&chunk[..end]))
+                } else {
+                    pending.extend_from_slice(&chunk[..end]);
+                    let keep_going = visit(line_number, trim_cr(&pending));
+                    pending.clear();
+                    keep_going
+                };
+                if !keep_going {
+                    stopped = true;
+                    return Ok(false);
+                }
+                chunk = &chunk[end + 1..];
+            }
+            pending.extend_from_slice(chunk);
+            Ok(true)
+        })?;
+
+        if !stopped && !pending.is_empty() {
+            visit(line_number + 1, trim_cr(&pending));
+        }
+        Ok(())
+    }
+
+    /// Compression method a file was stored with
+    ///
+    /// The method is taken from the mask byte of the first compressed
+    /// sector, so only a few bytes are read and nothing is decompressed.
+    /// Files stored without compression, or whose sectors all ended up
+    /// stored raw, report `CompressionMethod::None`. Files with the legacy
+    /// `FLAG_IMPLODE` flag carry no mask byte and report
+    /// `CompressionMethod::PKWare`.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if `name` is not in the archive
+    /// - Any error from reading the file's sector offset table
+    pub fn compression_method(&self, name: &str) -> Result<CompressionMethod> {
+        let file_info = self
+            .find_file(name)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+        self.compression_method_of(name, &file_info)
+    }
+
+    /// Compression method of one locale's version of a file
+    ///
+    /// Like [`compression_method`](Self::compression_method), for the
+    /// version [`find_file_with_locale`](Self::find_file_with_locale) picks.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if `name` is not in the archive
+    /// - Any error from reading the file's sector offset table
+    pub fn compression_method_with_locale(
+        &self,
+        name: &str,
+        locale: impl Into<Locale>,
+    ) -> Result<CompressionMethod> {
+        let file_info = self
+            .find_file_with_locale(name, locale)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+        self.compression_method_of(name, &file_info)
+    }
+
+    fn compression_method_of(&self, name: &str, file_info: &FileInfo) -> Result<CompressionMethod> {
+        use crate::tables::BlockEntry;
+
+        if file_info.flags & BlockEntry::FLAG_IMPLODE != 0 {
+            return Ok(CompressionMethod::PKWare);
+        }
+        if !file_info.is_compressed() || file_info.compressed_size == 0 {
+            return Ok(CompressionMethod::None);
+        }
+
+        let (file_size, key) = self.file_size_and_key(name, file_info)?;
+
+        if file_info.is_single_unit() {
+            let mask = self.read_sector_mask(file_info, 0, file_info.compressed_size, key)?;
+            return Ok(CompressionMethod::from_flags(mask));
+        }
+
+        let sector_size = self.header.sector_size();
+        let sector_count = (file_size as usize).div_ceil(sector_size);
+        let offsets = self.read_sector_offsets(file_info, key, sector_count)?;
+
+        // Sectors that did not shrink are stored raw, so the first sector that
+        // did is the one carrying the mask byte
+        for i in 0..sector_count {
+            let stored = offsets[i + 1].saturating_sub(offsets[i]) as u64;
+            let expected = (file_size as usize - i * sector_size).min(sector_size) as u64;
+            if stored == 0 || stored >= expected {
+                continue;
+            }
+
+            let mask = self.read_sector_mask(
+                file_info,
+                offsets[i] as u64,
+                stored,
+                key.wrapping_add(i as u32),
+            )?;
+            return Ok(CompressionMethod::from_flags(mask));
+        }
+
+        Ok(CompressionMethod::None)
+    }
+
+    /// Map out how a file's data is laid out in sectors
+    ///
+    /// Reads the sector offset table, the sector checksum table if the file
+    /// has one, and the mask byte of each compressed sector; sector contents
+    /// are not decompressed. Uncompressed files have no offset table, so
+    /// their sectors are derived from the archive's sector size. Single-unit
+    /// files are reported as one sector, with the checksum stored after
+    /// their data.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use mopaq::Archive;
+    ///
+    /// let mut archive = Archive::open("game.mpq")?;
+    /// let map = archive.sector_map("units.dat")?;
+    ///
+    /// for (i, sector) in map.sectors.iter().enumerate() {
+    ///     println!(
+    ///         "sector {}: {} bytes at +{} (mask {:?})",
+    ///         i, sector.compressed_size, sector.offset, sector.compression_mask
+    ///     );
+    /// }
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if `name` is not in the archive
+    /// - `Error::InvalidFormat` if the sector offsets are not ascending
+    /// - Any I/O error from reading the tables
+    pub fn sector_map(&self, name: &str) -> Result<SectorMap> {
+        use crate::tables::BlockEntry;
+
+        let file_info = self
+            .find_file(name)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+        let (file_size, key) = self.file_size_and_key(name, &file_info)?;
+        let sector_size = self.header.sector_size();
+        let has_mask = file_info.flags & BlockEntry::FLAG_COMPRESS != 0;
+
+        if file_info.is_single_unit() {
+            let compressed = file_info.is_compressed() && file_info.compressed_size > 0;
+            let compression_mask = if compressed && has_mask {
+                Some(self.read_sector_mask(&file_info, 0, file_info.compressed_size, key)?)
+            } else {
+                None
+            };
+            let checksum = if file_info.has_sector_crc() {
+                Some(
+                    self.data
+                        .read_u32_at(file_info.file_pos + file_info.compressed_size)?,
+                )
+            } else {
+                None
+            };
+
+            return Ok(SectorMap {
+                file_pos: file_info.file_pos,
+                sector_size,
+                single_unit: true,
+                header_size: 0,
+                sectors: vec![SectorInfo {
+                    offset: 0,
+                    compressed_size: file_info.compressed_size,
+                    uncompressed_size: file_size,
+                    checksum,
+                    compressed,
+                    compression_mask,
+                }],
+            });
+        }
+
+        let sector_count = (file_size as usize).div_ceil(sector_size);
+        let uncompressed_size =
+            |i: usize| (file_size as usize - i * sector_size).min(sector_size) as u64;
+
+        if !file_info.is_compressed() {
+            let sectors = (0..sector_count)
+                .map(|i| SectorInfo {
+                    offset: (i * sector_size) as u64,
+                    compressed_size: uncompressed_size(i),
+                    uncompressed_size: uncompressed_size(i),
+                    checksum: None,
+                    compressed: false,
+                    compression_mask: None,
+                })
+                .collect();
+            return Ok(SectorMap {
+                file_pos: file_info.file_pos,
+                sector_size,
+                single_unit: false,
+                header_size: 0,
+                sectors,
+            });
+        }
+
+        let offsets = self.read_sector_offsets(&file_info, key, sector_count)?;
+        let checksums = self.read_sector_checksums(&file_info, &offsets)?;
+
+        let mut sectors = Vec::with_capacity(sector_count);
+        for i in 0..sector_count {
+            let (start, stored) = self.sector_bounds(&file_info, &offsets, i)?;
+            let compressed = stored > 0 && stored < uncompressed_size(i);
+            let compression_mask = if compressed && has_mask {
+                Some(self.read_sector_mask(
+                    &file_info,
+                    start,
+                    stored,
+                    key.wrapping_add(i as u32),
+                )?)
+            } else {
+                None
+            };
+
+            sectors.push(SectorInfo {
+                offset: start,
+                compressed_size: stored,
+                uncompressed_size: uncompressed_size(i),
+                checksum: checksums.as_ref().map(|crcs| crcs[i]),
+                compressed,
+                compression_mask,
+            });
+        }
+
+        Ok(SectorMap {
+            file_pos: file_info.file_pos,
+            sector_size,
+            single_unit: false,
+            header_size: offsets[0] as u64,
+            sectors,
+        })
+    }
 
+    /// Uncompressed size and encryption key of a file
+    ///
+    /// The key is zero for unencrypted files.
+    fn file_size_and_key(&self, name: &str, file_info: &FileInfo) -> Result<(u64, u32)> {
         // For v3+ archives with HET/BET tables, we already have all the info we need in FileInfo
         // For classic archives, we need to get additional info from the block table
         let (file_size_for_key, actual_file_size) =
@@ -1247,96 +3552,180 @@ impl Archive {
             0
         };
 
-        // Read the file data
-        self.reader.seek(SeekFrom::Start(file_info.file_pos))?;
+        Ok((actual_file_size, key))
+    }
 
-        if file_info.is_single_unit() || !file_info.is_compressed() {
-            // Single unit or uncompressed file - read directly
-            let mut data = vec![0u8; file_info.compressed_size as usize];
-            self.reader.read_exact(&mut data)?;
+    /// Read and decrypt the sector offset table of a multi-sector file
+    ///
+    /// Returns `sector_count + 1` offsets relative to the file's position.
+    fn read_sector_offsets(
+        &self,
+        file_info: &FileInfo,
+        key: u32,
+        sector_count: usize,
+    ) -> Result<Vec<u32>> {
+        // A damaged file size must not turn into a huge allocation
+        let table_size = (sector_count as u64 + 1) * 4;
+        if table_size > file_info.compressed_size {
+            return Err(Error::invalid_format(format!(
+                "Sector offset table of {} ({} bytes) is larger than its stored data ({} bytes)",
+                file_info.filename, table_size, file_info.compressed_size
+            )));
+        }
 
-            // Decrypt if needed
-            if file_info.is_encrypted() {
-                log::debug!(
-                    "Decrypting file data: key=0x{:08X}, size={}",
-                    key,
-                    data.len()
-                );
-                if data.len() <= 64 {
-                    log::debug!("Before decrypt: {:02X?}", &data);
-                }
-                decrypt_file_data(&mut data, key);
-                if data.len() <= 64 {
-                    log::debug!("After decrypt: {:02X?}", &data);
-                }
-            }
+        let mut offset_data = vec![0u8; table_size as usize];
+        self.data
+            .read_exact_at(&mut offset_data, file_info.file_pos)?;
 
-            // Validate CRC if present for single unit files
-            if file_info.has_sector_crc() && file_info.is_single_unit() {
-                // For single unit files, there's one CRC after the data
-                let mut crc_bytes = [0u8; 4];
-                self.reader.read_exact(&mut crc_bytes)?;
-                let expected_crc = u32::from_le_bytes(crc_bytes);
+        if file_info.is_encrypted() {
+            let offset_key = key.wrapping_sub(1);
+            decrypt_file_data(&mut offset_data, offset_key);
+        }
 
-                // CRC is calculated on the decompressed data
-                let data_to_check = if file_info.is_compressed() {
-                    // We need to decompress first to check CRC
-                    let compression_type = data[0];
-                    let compressed_data = &data[1..];
-                    compression::decompress(
-                        compressed_data,
-                        compression_type,
-                        actual_file_size as usize,
-                    )?
-                } else {
-                    data.clone()
-                };
+        let mut sector_offsets = Vec::with_capacity(sector_count + 1);
+        let mut cursor = std::io::Cursor::new(&offset_data);
+        for _ in 0..=sector_count {
+            sector_offsets.push(cursor.read_u32_le()?);
+        }
+        Ok(sector_offsets)
+    }
 
-                // MPQ uses ADLER32 for sector checksums, not CRC32 despite the name
-                let actual_crc = adler::adler32_slice(&data_to_check);
-                if actual_crc != expected_crc {
-                    return Err(Error::ChecksumMismatch {
-                        file: name.to_string(),
-                        expected: expected_crc,
-                        actual: actual_crc,
-                    });
-                }
+    /// Read the sector checksum table that follows the sector offset table
+    ///
+    /// Returns `None` if the file has no `FLAG_SECTOR_CRC` flag or the data
+    /// starts too early for a checksum table to fit.
+    fn read_sector_checksums(
+        &self,
+        file_info: &FileInfo,
+        sector_offsets: &[u32],
+    ) -> Result<Option<Vec<u32>>> {
+        if !file_info.has_sector_crc() {
+            return Ok(None);
+        }
 
-                log::debug!("Single unit file CRC validated: 0x{:08X}", actual_crc);
-            }
+        // The first sector offset tells us where the data starts
+        // If it's large enough to accommodate a CRC table, then CRCs are present
+        let sector_count = sector_offsets.len() - 1;
+        let first_data_offset = sector_offsets[0] as usize;
+        let expected_crc_table_start = sector_offsets.len() * 4;
+        let expected_crc_table_size = sector_count * 4;
+
+        if first_data_offset < expected_crc_table_start + expected_crc_table_size {
+            log::debug!(
+                "File has SECTOR_CRC flag but insufficient space for CRC table (offset_table_size={}, first_data_offset={}, needed={}). This is common in some MPQ implementations.",
+                expected_crc_table_start,
+                first_data_offset,
+                expected_crc_table_start + expected_crc_table_size
+            );
+            return Ok(None);
+        }
 
-            // Decompress if needed
-            if file_info.is_compressed() {
-                // All compressed files should have a compression type byte prefix
-                // This matches StormLib's behavior
-                if !data.is_empty() {
-                    let compression_type = data[0];
-                    let compressed_data = &data[1..];
-                    log::debug!(
-                        "Decompressing file: type=0x{:02X}, compressed_size={}, expected_size={}",
-                        compression_type,
-                        compressed_data.len(),
-                        actual_file_size
-                    );
-                    compression::decompress(
-                        compressed_data,
-                        compression_type,
-                        actual_file_size as usize,
-                    )
-                } else {
-                    Err(Error::compression("Empty compressed data"))
-                }
-            } else {
-                Ok(data)
-            }
+        // CRC table follows the offset table and is not encrypted
+        let mut crc_data = vec![0u8; expected_crc_table_size];
+        self.data.read_exact_at(
+            &mut crc_data,
+            file_info.file_pos + expected_crc_table_start as u64,
+        )?;
+
+        let mut crcs = Vec::with_capacity(sector_count);
+        let mut cursor = std::io::Cursor::new(&crc_data);
+        for _ in 0..sector_count {
+            crcs.push(cursor.read_u32_le()?);
+        }
+
+        log::debug!(
+            "Read {} sector CRCs, first few: {:?}",
+            sector_count,
+            &crcs[..5.min(crcs.len())]
+        );
+        Ok(Some(crcs))
+    }
+
+    /// Read the compression mask byte at the start of a stored sector
+    ///
+    /// `offset` is relative to the file's position and `sector_key` is the
+    /// key of that sector, used only if the file is encrypted.
+    fn read_sector_mask(
+        &self,
+        file_info: &FileInfo,
+        offset: u64,
+        stored_size: u64,
+        sector_key: u32,
+    ) -> Result<u8> {
+        let mut mask = [0u8; 4];
+        let len = (stored_size as usize).min(mask.len());
+        self.data
+            .read_exact_at(&mut mask[..len], file_info.file_pos + offset)?;
+        if file_info.is_encrypted() {
+            decrypt_file_data(&mut mask[..len], sector_key);
+        }
+        Ok(mask[0])
+    }
+
+    /// Decompress a file stored in one unit or one sector of a sectored file
+    ///
+    /// Data of `FLAG_IMPLODE` files is a bare PKWare DCL stream, as Diablo
+    /// wrote them; everything else starts with the compression mask byte,
+    /// as StormLib does.
+    fn decompress_stored(&self, flags: u32, data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
+        use crate::tables::BlockEntry;
+
+        let (compression_type, compressed_data) = if flags & BlockEntry::FLAG_IMPLODE != 0 {
+            (compression::flags::PKWARE, data)
         } else {
-            // Multi-sector compressed file
-            self.read_sectored_file(&file_info, key)
+            (data[0], &data[1..])
+        };
+        log::debug!(
+            "Decompressing: type=0x{:02X}, compressed_size={}, expected_size={}",
+            compression_type,
+            compressed_data.len(),
+            expected_size
+        );
+        let (result, diagnostics) = compression::decompress_with_diagnostics(
+            compressed_data,
+            compression_type,
+            expected_size,
+            self.size_mismatch_policy,
+            self.max_expansion,
+            self.fallback_policy,
+        );
+        if let Some(recorded) = &self.decode_diagnostics {
+            recorded
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push(diagnostics);
+        }
+        if let Ok(decompressed) = &result {
+            self.report(|m| m.on_sector_decompressed(data.len() as u64, decompressed.len() as u64));
+        }
+        result
+    }
+
+    /// Deliver a counter to the metrics sink, if any
+    fn report(&self, event: impl FnOnce(&dyn Metrics)) {
+        if let Some(sink) = &self.metrics {
+            event(sink.0.as_ref());
         }
     }
 
     /// Read a file that is split into sectors
-    fn read_sectored_file(&mut self, file_info: &FileInfo, key: u32) -> Result<Vec<u8>> {
+    fn read_sectored_file(&self, file_info: &FileInfo, key: u32) -> Result<Vec<u8>> {
+        let mut decompressed_data = output_buffer(file_info.file_size as usize);
+        self.for_each_sector(file_info, key, |sector| {
+            decompressed_data.extend_from_slice(sector);
+            Ok(true)
+        })?;
+        Ok(decompressed_data)
+    }
+
+    /// Decompress the sectors of a sectored file one at a time
+    ///
+    /// `visit` receives each decompressed sector in order and returns
+    /// whether to continue.
+    fn for_each_sector<F>(&self, file_info: &FileInfo, key: u32, mut visit: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<bool>,
+    {
         let sector_size = self.header.sector_size();
         let sector_count = (file_info.file_size as usize).div_ceil(sector_size);
 
@@ -1346,24 +3735,7 @@ impl Archive {
             sector_size
         );
 
-        // Read sector offset table
-        self.reader.seek(SeekFrom::Start(file_info.file_pos))?;
-        let offset_table_size = (sector_count + 1) * 4;
-        let mut offset_data = vec![0u8; offset_table_size];
-        self.reader.read_exact(&mut offset_data)?;
-
-        // Decrypt sector offset table if needed
-        if file_info.is_encrypted() {
-            let offset_key = key.wrapping_sub(1);
-            decrypt_file_data(&mut offset_data, offset_key);
-        }
-
-        // Parse sector offsets
-        let mut sector_offsets = Vec::with_capacity(sector_count + 1);
-        let mut cursor = std::io::Cursor::new(&offset_data);
-        for _ in 0..=sector_count {
-            sector_offsets.push(cursor.read_u32_le()?);
-        }
+        let sector_offsets = self.read_sector_offsets(file_info, key, sector_count)?;
 
         log::debug!(
             "Sector offsets: first={}, last={}",
@@ -1371,127 +3743,182 @@ impl Archive {
             sector_offsets.last().copied().unwrap_or(0)
         );
 
-        // Check if we have sector CRCs
-        let mut sector_crcs = None;
-        if file_info.has_sector_crc() {
-            // The first sector offset tells us where the data starts
-            // If it's large enough to accommodate a CRC table, then CRCs are present
-            let first_data_offset = sector_offsets[0] as usize;
-            let expected_crc_table_start = offset_table_size;
-            let expected_crc_table_size = sector_count * 4;
-
-            if first_data_offset >= expected_crc_table_start + expected_crc_table_size {
-                // CRC table follows the offset table
-                let mut crc_data = vec![0u8; expected_crc_table_size];
-                self.reader.read_exact(&mut crc_data)?;
-
-                // CRC table is not encrypted
-                let mut crcs = Vec::with_capacity(sector_count);
-                let mut cursor = std::io::Cursor::new(&crc_data);
-                for _ in 0..sector_count {
-                    crcs.push(cursor.read_u32_le()?);
-                }
-
-                // Log before moving
-                log::debug!(
-                    "Read {} sector CRCs, first few: {:?}",
-                    sector_count,
-                    &crcs[..5.min(crcs.len())]
-                );
-
-                sector_crcs = Some(crcs);
-            } else {
-                log::debug!(
-                    "File has SECTOR_CRC flag but insufficient space for CRC table (offset_table_size={}, first_data_offset={}, needed={}). This is common in some MPQ implementations.",
-                    offset_table_size,
-                    first_data_offset,
-                    expected_crc_table_start + expected_crc_table_size
-                );
-            }
-        }
+        let sector_crcs = self.read_sector_checksums(file_info, &sector_offsets)?;
 
         // Read and decompress each sector
-        let mut decompressed_data = Vec::with_capacity(file_info.file_size as usize);
+        let mut remaining = file_info.file_size as usize;
+        let mut reader = SectorReader::new(sector_offsets, sector_crcs);
 
         for i in 0..sector_count {
-            let sector_start = sector_offsets[i] as u64;
-            let sector_end = sector_offsets[i + 1] as u64;
-
-            if sector_end < sector_start {
-                return Err(Error::invalid_format(format!(
-                    "Invalid sector offsets: start={}, end={} for sector {}",
-                    sector_start, sector_end, i
-                )));
+            let expected_size = remaining.min(sector_size);
+            let (decompressed_sector, checksum_error) =
+                self.read_sector(file_info, key, &mut reader, i, expected_size)?;
+            if let Some(e) = checksum_error {
+                // Some MPQ files have incorrect CRCs, so only log the mismatch
+                log::error!("Sector {}: {}", i, e);
             }
 
-            let sector_size_compressed = (sector_end - sector_start) as usize;
+            remaining -= expected_size;
+            if !visit(&decompressed_sector)? {
+                break;
+            }
+        }
 
-            // Calculate expected decompressed size for this sector
-            let remaining = file_info.file_size as usize - decompressed_data.len();
-            let expected_size = remaining.min(sector_size);
+        Ok(())
+    }
 
-            // Seek to sector data - offsets are absolute from file position
-            self.reader
-                .seek(SeekFrom::Start(file_info.file_pos + sector_start))?;
+    /// Offset and stored size of sector `i`, checked against the file's data
+    ///
+    /// Offsets come straight from the archive, so they are validated before
+    /// they are used to size a read.
+    fn sector_bounds(
+        &self,
+        file_info: &FileInfo,
+        sector_offsets: &[u32],
+        i: usize,
+    ) -> Result<(u64, u64)> {
+        let start = sector_offsets[i] as u64;
+        let end = sector_offsets[i + 1] as u64;
+        let corrupt = |reason: String| Error::corrupt_sector(&file_info.filename, i, reason);
+
+        if end < start {
+            return Err(corrupt(format!(
+                "ends at offset {} before it starts at {}",
+                end, start
+            )));
+        }
+        // Older builders left the sector checksum table out of the compressed
+        // size, so sectors may end that much past it
+        let checksum_slack = if file_info.has_sector_crc() {
+            (sector_offsets.len() as u64 - 1) * 4
+        } else {
+            0
+        };
+        if end > file_info.compressed_size + checksum_slack {
+            return Err(corrupt(format!(
+                "ends at offset {} past the file's {} stored bytes",
+                end, file_info.compressed_size
+            )));
+        }
+        // A sector that does not compress is stored raw, so nothing valid is larger
+        let sector_size = self.header.sector_size() as u64;
+        if end - start > sector_size {
+            return Err(corrupt(format!(
+                "stored size {} exceeds the sector size {}",
+                end - start,
+                sector_size
+            )));
+        }
 
-            // Read sector data
-            let mut sector_data = vec![0u8; sector_size_compressed];
-            self.reader.read_exact(&mut sector_data)?;
+        Ok((start, end - start))
+    }
 
-            if i == 0 {
-                log::debug!(
-                    "First sector: offset={}, size={}, first 16 bytes: {:02X?}",
-                    sector_start,
-                    sector_size_compressed,
-                    &sector_data[..16.min(sector_data.len())]
-                );
+    /// Stored bytes `start..start + len` of a file, relative to its position
+    ///
+    /// Reads go through the reader's window, which is refilled with up to
+    /// the I/O buffer size of the bytes from `start` on whenever the range is
+    /// not already in it, so consecutive sectors share one system call. The
+    /// window stops at the end of the file's stored data and of the archive
+    /// file, so a truncated archive only fails the sectors it cuts off.
+    fn read_stored<'a>(
+        &self,
+        file_info: &FileInfo,
+        reader: &'a mut SectorReader,
+        start: u64,
+        len: u64,
+    ) -> Result<&'a [u8]> {
+        let end = start + len;
+        if start < reader.window_start || end > reader.window_start + reader.window.len() as u64 {
+            let available = self
+                .data
+                .len()?
+                .saturating_sub(file_info.file_pos.saturating_add(start));
+            let window_len = (self.io_buffer_size as u64)
+                .min(file_info.compressed_size.saturating_sub(start))
+                .min(available)
+                .max(len);
+            reader.window.resize(window_len as usize, 0);
+            if let Err(e) = self
+                .data
+                .read_exact_at(&mut reader.window, file_info.file_pos + start)
+            {
+                reader.window.clear();
+                return Err(e.into());
             }
+            reader.window_start = start;
+        }
 
-            // Decrypt sector if needed
-            if file_info.is_encrypted() {
-                let sector_key = key.wrapping_add(i as u32);
-                decrypt_file_data(&mut sector_data, sector_key);
-            }
+        let offset = (start - reader.window_start) as usize;
+        Ok(&reader.window[offset..offset + len as usize])
+    }
 
-            // Validate CRC if present - MUST be done AFTER decryption but BEFORE decompression
-            if let Some(ref crcs) = sector_crcs {
-                let expected_crc = crcs[i];
-                // MPQ uses ADLER32 for sector checksums, calculated on the raw (possibly compressed) data
-                let actual_crc = adler::adler32_slice(&sector_data);
+    /// Read, decrypt and decompress one sector of a sectored file
+    ///
+    /// A checksum mismatch does not stop the sector from being decompressed;
+    /// it is returned alongside the data for the caller to judge.
+    fn read_sector(
+        &self,
+        file_info: &FileInfo,
+        key: u32,
+        reader: &mut SectorReader,
+        i: usize,
+        expected_size: usize,
+    ) -> Result<(Vec<u8>, Option<Error>)> {
+        let (sector_start, stored_size) = self.sector_bounds(file_info, &reader.offsets, i)?;
+        let sector_size_compressed = stored_size as usize;
+
+        // Read sector data - offsets are relative to the file position
+        let mut sector_data = self
+            .read_stored(file_info, reader, sector_start, stored_size)?
+            .to_vec();
+
+        if i == 0 {
+            log::debug!(
+                "First sector: offset={}, size={}, first 16 bytes: {:02X?}",
+                sector_start,
+                sector_size_compressed,
+                &sector_data[..16.min(sector_data.len())]
+            );
+        }
 
-                if actual_crc != expected_crc {
-                    log::error!(
-                        "CRC mismatch for sector {}: expected {:08x}, got {:08x}",
-                        i,
-                        expected_crc,
-                        actual_crc
-                    );
-                    // For now, just log the error and continue
-                    // Some MPQ files have incorrect CRCs
-                }
-            }
+        // Decrypt sector if needed
+        if file_info.is_encrypted() {
+            let sector_key = key.wrapping_add(i as u32);
+            decrypt_file_data(&mut sector_data, sector_key);
+        }
 
-            // Decompress sector
-            let decompressed_sector =
-                if file_info.is_compressed() && sector_size_compressed < expected_size {
-                    // All compressed sectors should have compression type byte prefix
-                    // This matches StormLib's behavior
-                    if !sector_data.is_empty() {
-                        let compression_type = sector_data[0];
-                        let compressed_data = &sector_data[1..];
-                        compression::decompress(compressed_data, compression_type, expected_size)?
-                    } else {
-                        return Err(Error::compression("Empty compressed sector data"));
-                    }
-                } else {
-                    // Sector is not compressed
-                    sector_data[..expected_size.min(sector_data.len())].to_vec()
-                };
+        // Validate CRC if present - MUST be done AFTER decryption but BEFORE decompression
+        // Checksums are calculated on the raw (possibly compressed) data
+        let checksum_error = reader.checksums.as_deref().and_then(|crcs| {
+            let expected_crc = crcs[i];
+            SectorChecksum::verify(self.sector_checksum, &sector_data, expected_crc)
+                .err()
+                .map(|actual_crc| Error::ChecksumMismatch {
+                    file: file_info.filename.clone(),
+                    expected: expected_crc,
+                    actual: actual_crc,
+                })
+        });
 
-            decompressed_data.extend_from_slice(&decompressed_sector);
-        }
+        // Decompress sector
+        let decompressed_sector =
+            if file_info.is_compressed() && sector_size_compressed < expected_size {
+                if !sector_data.is_empty() {
+                    self.decompress_stored(file_info.flags, &sector_data, expected_size)?
+                } else {
+                    return Err(Error::corrupt_sector(
+                        &file_info.filename,
+                        i,
+                        "compressed sector is empty",
+                    ));
+                }
+            } else {
+                // Sector is not compressed
+                sector_data.truncate(expected_size);
+                sector_data
+            };
 
-        Ok(decompressed_data)
+        Ok((decompressed_sector, checksum_error))
     }
 
     /// Load attributes from the (attributes) file if present
@@ -1521,7 +3948,10 @@ impl Archive {
                     let first_dword = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
 
                     // Check if this looks like compressed data instead of version 100
-                    if first_dword != 100 && data[0] != 0x64 {
+                    if first_dword != 100
+                        && data[0] != 0x64
+                        && self.fallback_policy != FallbackPolicy::Strict
+                    {
                         log::debug!(
                             "Attributes file may be compressed, first dword: 0x{:08X} ({}), first byte: 0x{:02X}",
                             first_dword,
@@ -1531,11 +3961,20 @@ impl Archive {
 
                         // Try to decompress if it looks like compression flags
                         if data[0] & 0x0F != 0 || data[0] == 0x02 {
+                            if self.fallback_policy == FallbackPolicy::Warn {
+                                log::warn!("Decompressing (attributes) a second time");
+                            }
                             log::info!(
                                 "Attempting to decompress attributes file with method 0x{:02X}",
                                 data[0]
                             );
-                            match compression::decompress(&data[1..], data[0], block_count * 100) {
+                            // The size is only an upper bound here
+                            match compression::decompress_with_policy(
+                                &data[1..],
+                                data[0],
+                                block_count * 100,
+                                SizeMismatchPolicy::Ignore,
+                            ) {
                                 Ok(decompressed) => {
                                     log::info!("Successfully decompressed attributes file");
                                     data = decompressed;
@@ -1551,9 +3990,12 @@ impl Archive {
 
                 // Parse attributes
                 let attributes = special_files::Attributes::parse(&data.into(), block_count)?;
-                self.attributes = Some(attributes);
-
-                log::info!("Loaded (attributes) file with {} entries", block_count);
+                log::info!(
+                    "Loaded (attributes) file with {} of {} entries",
+                    attributes.entry_count,
+                    block_count
+                );
+                self.attributes = Some(Arc::new(attributes));
                 Ok(())
             }
             Err(Error::FileNotFound(_)) => {
@@ -1574,7 +4016,25 @@ impl Archive {
 
     /// Get all loaded attributes
     pub fn attributes(&self) -> Option<&special_files::Attributes> {
-        self.attributes.as_ref()
+        self.attributes.as_deref()
+    }
+
+    /// Read the key-value information stored in the `(metadata)` file
+    ///
+    /// Returns `None` for archives without one. Only available with the
+    /// `metadata` feature.
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if the file is not valid metadata
+    /// - Any error from reading the file
+    #[cfg(feature = "metadata")]
+    pub fn metadata(&self) -> Result<Option<special_files::Metadata>> {
+        match self.read_file(SpecialFile::Metadata.as_str()) {
+            Ok(data) => special_files::Metadata::parse(&data)
+                .map(|metadata| Some(metadata.with_name_hashing(self.name_hashing()))),
+            Err(Error::FileNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
     }
 
     /// Add a file to the archive
@@ -1584,66 +4044,37 @@ impl Archive {
         ))
     }
 
-    /// Read HET table size from the table header for V3 archives
-    fn read_het_table_size(&mut self, het_pos: u64) -> Result<u64> {
-        // For compressed tables, calculate the actual size based on the next table position
-        log::debug!("Determining HET table size from file structure");
-
-        // Calculate the actual size based on what comes after HET table
-        let actual_size = if let Some(bet_pos) = self.header.bet_table_pos {
-            if bet_pos > het_pos {
-                // BET table comes after HET
-                bet_pos - het_pos
-            } else {
-                // Calculate from hash table position
-                self.header.get_hash_table_pos() - het_pos
-            }
-        } else {
-            // Calculate from hash table position
-            self.header.get_hash_table_pos() - het_pos
-        };
-
-        log::debug!(
-            "HET table position: 0x{:X}, calculated size: {} bytes",
-            het_pos,
-            actual_size
-        );
-
-        Ok(actual_size)
+    /// Verify the digital signature of the archive
+    pub fn verify_signature(&mut self) -> Result<SignatureStatus> {
+        self.verify_signature_using(None)
     }
 
-    /// Read BET table size from the table header for V3 archives
-    fn read_bet_table_size(&mut self, bet_pos: u64) -> Result<u64> {
-        // For compressed tables, calculate the actual size based on the next table position
-        log::debug!("Determining BET table size from file structure");
-
-        // Calculate the actual size based on what comes after BET table (usually hash table)
-        let actual_size = self.header.get_hash_table_pos() - bet_pos;
-
-        log::debug!(
-            "BET table position: 0x{:X}, calculated size: {} bytes",
-            bet_pos,
-            actual_size
-        );
-
-        Ok(actual_size)
+    /// Verify the digital signature of the archive against `key`
+    ///
+    /// [`verify_signature`](Self::verify_signature) checks signatures
+    /// against Blizzard's public keys; this checks archives signed with
+    /// another key, such as ones re-signed with
+    /// [`SignaturePolicy::Resign`](crate::crypto::SignaturePolicy::Resign).
+    /// The key is tried for both the strong and the weak signature.
+    pub fn verify_signature_with_key(&mut self, key: &RsaPublicKey) -> Result<SignatureStatus> {
+        self.verify_signature_using(Some(key))
     }
 
-    /// Verify the digital signature of the archive
-    pub fn verify_signature(&mut self) -> Result<SignatureStatus> {
+    /// Verify the signature with `key`, or Blizzard's keys if `None`
+    fn verify_signature_using(&mut self, key: Option<&RsaPublicKey>) -> Result<SignatureStatus> {
         // First check for strong signature (external to archive)
-        if let Ok(strong_status) = self.verify_strong_signature() {
+        if let Ok(strong_status) = self.verify_strong_signature(key) {
             if strong_status != SignatureStatus::None {
                 return Ok(strong_status);
             }
         }
 
         // Then check for weak signature (inside archive)
-        self.verify_weak_signature()
+        self.verify_weak_signature(key)
     }
 
     /// Verify weak signature from (signature) file inside the archive
-    fn verify_weak_signature(&mut self) -> Result<SignatureStatus> {
+    fn verify_weak_signature(&mut self, key: Option<&RsaPublicKey>) -> Result<SignatureStatus> {
         // Check if (signature) file exists
         let signature_info = match self.find_file("(signature)")? {
             Some(info) => info,
@@ -1670,11 +4101,20 @@ impl Archive {
                 self.reader.seek(SeekFrom::Start(self.archive_offset))?;
 
                 // Verify the weak signature using StormLib-compatible approach
-                match crate::crypto::verify_weak_signature_stormlib(
-                    &mut self.reader,
-                    &weak_sig,
-                    &sig_info,
-                ) {
+                let result = match key {
+                    Some(key) => crate::crypto::verify_weak_signature_with_key(
+                        &mut self.reader,
+                        &weak_sig,
+                        &sig_info,
+                        key,
+                    ),
+                    None => crate::crypto::verify_weak_signature_stormlib(
+                        &mut self.reader,
+                        &weak_sig,
+                        &sig_info,
+                    ),
+                };
+                match result {
                     Ok(true) => Ok(SignatureStatus::WeakValid),
                     Ok(false) => Ok(SignatureStatus::WeakInvalid),
                     Err(e) => {
@@ -1691,75 +4131,101 @@ impl Archive {
         }
     }
 
-    /// Verify strong signature appended after the archive
-    fn verify_strong_signature(&mut self) -> Result<SignatureStatus> {
-        use crate::crypto::{
-            parse_strong_signature, verify_strong_signature, STRONG_SIGNATURE_SIZE,
-        };
+    /// Keys the hash and block tables were opened with, as
+    /// [`OpenOptions::table_key_override`] set them
+    pub(crate) fn table_keys(&self) -> (TableKey, TableKey) {
+        (self.hash_table_key, self.block_table_key)
+    }
+
+    /// Read the strong signature appended after the archive, header included
+    ///
+    /// Returns `None` if the bytes after the archive are not a strong
+    /// signature.
+    pub(crate) fn strong_signature_data(&mut self) -> Result<Option<Vec<u8>>> {
+        use crate::crypto::{parse_strong_signature, STRONG_SIGNATURE_SIZE};
 
         // Get total file size
-        let file_size = self.reader.get_ref().metadata()?.len();
+        let file_size = self.data.len()?;
 
         // Calculate expected archive end position
-        let archive_end = self.archive_offset + self.header.get_archive_size();
+        let archive_end = self.archive_offset + self.signed_archive_size()?;
 
         // Check if there's enough space for a strong signature after the archive
         if file_size < archive_end + STRONG_SIGNATURE_SIZE as u64 {
             log::debug!("File too small for strong signature");
-            return Ok(SignatureStatus::None);
+            return Ok(None);
         }
 
-        // Seek to where the strong signature should be
-        let signature_pos = archive_end;
-        self.reader.seek(SeekFrom::Start(signature_pos))?;
-
         // Read potential strong signature data
         let mut signature_data = vec![0u8; STRONG_SIGNATURE_SIZE];
-        match self.reader.read_exact(&mut signature_data) {
-            Ok(()) => {
-                // Try to parse as strong signature
-                match parse_strong_signature(&signature_data) {
-                    Ok(strong_sig) => {
-                        log::debug!("Found strong signature at offset 0x{:X}", signature_pos);
-
-                        // Seek to beginning of archive for verification
-                        self.reader.seek(SeekFrom::Start(self.archive_offset))?;
-
-                        // Verify the strong signature
-                        match verify_strong_signature(
-                            &mut self.reader,
-                            &strong_sig,
-                            archive_end - self.archive_offset,
-                        ) {
-                            Ok(true) => {
-                                log::info!("Strong signature verification successful");
-                                Ok(SignatureStatus::StrongValid)
-                            }
-                            Ok(false) => {
-                                log::warn!("Strong signature verification failed");
-                                Ok(SignatureStatus::StrongInvalid)
-                            }
-                            Err(e) => {
-                                log::warn!("Failed to verify strong signature: {}", e);
-                                Ok(SignatureStatus::StrongInvalid)
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        // Not a strong signature
-                        log::debug!("No valid strong signature found");
-                        Ok(SignatureStatus::None)
-                    }
-                }
+        self.reader.seek(SeekFrom::Start(archive_end))?;
+        if let Err(e) = self.reader.read_exact(&mut signature_data) {
+            log::debug!("Failed to read potential strong signature: {}", e);
+            return Ok(None);
+        }
+
+        if parse_strong_signature(&signature_data).is_err() {
+            log::debug!("No valid strong signature found");
+            return Ok(None);
+        }
+        log::debug!("Found strong signature at offset 0x{:X}", archive_end);
+        Ok(Some(signature_data))
+    }
+
+    /// Verify strong signature appended after the archive
+    fn verify_strong_signature(&mut self, key: Option<&RsaPublicKey>) -> Result<SignatureStatus> {
+        use crate::crypto::{
+            parse_strong_signature, verify_strong_signature, verify_strong_signature_with_key,
+        };
+
+        let signature_data = match self.strong_signature_data()? {
+            Some(data) => data,
+            None => return Ok(SignatureStatus::None),
+        };
+        let strong_sig = parse_strong_signature(&signature_data)?;
+        let archive_size = self.signed_archive_size()?;
+
+        // Seek to beginning of archive for verification
+        self.reader.seek(SeekFrom::Start(self.archive_offset))?;
+
+        // Verify the strong signature
+        let result = match key {
+            Some(key) => {
+                verify_strong_signature_with_key(&mut self.reader, &strong_sig, archive_size, key)
+            }
+            None => verify_strong_signature(&mut self.reader, &strong_sig, archive_size),
+        };
+        match result {
+            Ok(true) => {
+                log::info!("Strong signature verification successful");
+                Ok(SignatureStatus::StrongValid)
+            }
+            Ok(false) => {
+                log::warn!("Strong signature verification failed");
+                Ok(SignatureStatus::StrongInvalid)
             }
             Err(e) => {
-                log::debug!("Failed to read potential strong signature: {}", e);
-                Ok(SignatureStatus::None)
+                log::warn!("Failed to verify strong signature: {}", e);
+                Ok(SignatureStatus::StrongInvalid)
             }
         }
     }
 }
 
+/// Feed the contents of the file at `path` to `update` in chunks
+fn hash_file(path: &Path, mut update: impl FnMut(&[u8])) -> Result<()> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => update(&buffer[..read]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
 /// Decrypt file data in-place
 fn decrypt_file_data(data: &mut [u8], key: u32) {
     if data.is_empty() || key == 0 {
@@ -1835,7 +4301,9 @@ pub struct FileInfo {
     /// File flags
     pub flags: u32,
     /// File locale
-    pub locale: u16,
+    pub locale: Locale,
+    /// Platform code from the hash table (0 unless written by third-party tools)
+    pub platform: u16,
 }
 
 impl FileInfo {
@@ -1870,8 +4338,89 @@ impl FileInfo {
     }
 }
 
+/// Layout of a file's data in the archive, as returned by [`Archive::sector_map`]
+#[derive(Debug, Clone)]
+pub struct SectorMap {
+    /// Absolute position of the file's data in the archive file
+    pub file_pos: u64,
+    /// Sector size of the archive
+    pub sector_size: usize,
+    /// Whether the file is stored as a single unit rather than in sectors
+    pub single_unit: bool,
+    /// Size of the sector offset table plus checksum table, zero for files
+    /// that have neither
+    pub header_size: u64,
+    /// Sectors in file order
+    pub sectors: Vec<SectorInfo>,
+}
+
+/// A sector that [`Archive::read_file_partial`] could not read cleanly
+#[derive(Debug)]
+pub struct SectorError {
+    /// Index of the sector within the file
+    pub index: usize,
+    /// Offset of the sector's data in the file
+    pub offset: u64,
+    /// Number of bytes of the file the sector holds
+    pub size: usize,
+    /// What went wrong
+    pub error: Error,
+    /// Whether the sector's data was still returned
+    ///
+    /// Only set for checksum mismatches on sectors that decompressed; the
+    /// data is returned but may be corrupt. Otherwise the sector is zeros.
+    pub recovered: bool,
+}
+
+/// One sector of a file, as found in its [`SectorMap`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorInfo {
+    /// Offset of the stored sector relative to the file's position
+    pub offset: u64,
+    /// Number of bytes stored in the archive
+    pub compressed_size: u64,
+    /// Number of bytes the sector holds once decompressed
+    pub uncompressed_size: u64,
+    /// Checksum stored in the file's sector checksum table, if it has one
+    pub checksum: Option<u32>,
+    /// Whether the stored data is compressed
+    pub compressed: bool,
+    /// Compression mask byte at the start of a compressed sector
+    ///
+    /// `None` for sectors stored raw and for files with the legacy
+    /// `FLAG_IMPLODE` flag, which have no mask byte.
+    pub compression_mask: Option<u8>,
+}
+
+/// A file entry found in the tables, as returned by [`Archive::anonymous_entries`]
+///
+/// Carries the name hashes the tables store for the entry, so that names
+/// can be recovered by hashing candidates and comparing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnonymousEntry {
+    /// First name hash from the classic hash table, see
+    /// [`hash_type::NAME_A`](crate::crypto::hash_type::NAME_A)
+    pub hash_a: Option<u32>,
+    /// Second name hash from the classic hash table
+    pub hash_b: Option<u32>,
+    /// Jenkins name hash from the HET table, masked to the table's hash size
+    pub het_hash: Option<u64>,
+    /// Index in the block table, or in the BET table for HET/BET-only archives
+    pub block_index: usize,
+    /// Locale from the classic hash table, neutral for HET/BET-only archives
+    pub locale: Locale,
+    /// Uncompressed size
+    pub size: u64,
+    /// Size of the stored data
+    pub compressed_size: u64,
+    /// Block table flags
+    pub flags: u32,
+}
+
 /// Information about a file in the archive (for listing)
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileEntry {
     /// File name
     pub name: String,
@@ -1886,6 +4435,22 @@ pub struct FileEntry {
 }
 
 impl FileEntry {
+    /// The special file this entry is, if it is one
+    pub fn special_file(&self) -> Option<SpecialFile> {
+        SpecialFile::from_name(&self.name)
+    }
+
+    /// Stored size as a fraction of the uncompressed size
+    ///
+    /// Lower is better compression; empty files report 1.0.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.size == 0 {
+            1.0
+        } else {
+            self.compressed_size as f64 / self.size as f64
+        }
+    }
+
     /// Check if the file is compressed
     pub fn is_compressed(&self) -> bool {
         use crate::tables::BlockEntry;
@@ -1933,6 +4498,22 @@ mod tests {
         let opts = OpenOptions::new().load_tables(false);
 
         assert!(!opts.load_tables);
+        assert_eq!(opts.size_mismatch_policy, SizeMismatchPolicy::Warn);
+
+        let opts = opts.size_mismatch_policy(SizeMismatchPolicy::Strict);
+        assert_eq!(opts.size_mismatch_policy, SizeMismatchPolicy::Strict);
+
+        assert_eq!(opts.max_expansion, compression::DEFAULT_MAX_EXPANSION);
+        let opts = opts.max_expansion(10);
+        assert_eq!(opts.max_expansion, 10);
+
+        assert_eq!(opts.fallback_policy, FallbackPolicy::Allow);
+        assert!(!opts.record_decode_diagnostics);
+        let opts = opts
+            .fallback_policy(FallbackPolicy::Strict)
+            .record_decode_diagnostics(true);
+        assert_eq!(opts.fallback_policy, FallbackPolicy::Strict);
+        assert!(opts.record_decode_diagnostics);
     }
 
     #[test]
@@ -1947,7 +4528,8 @@ mod tests {
             compressed_size: 100,
             file_size: 200,
             flags: BlockEntry::FLAG_COMPRESS | BlockEntry::FLAG_ENCRYPTED,
-            locale: 0,
+            locale: Locale::NEUTRAL,
+            platform: 0,
         };
 
         assert!(info.is_compressed());
@@ -0,0 +1,672 @@
+//! Declarative archive builds from TOML manifests
+//!
+//! A [`BuildManifest`] lists the files of an archive together with where
+//! their contents come from and how each one is stored, so an archive can
+//! be described in a reviewable text file instead of code:
+//!
+//! ```toml
+//! [archive]
+//! version = 2
+//! compression = "zlib"
+//!
+//! [[file]]
+//! name = "scripts\\war3map.j"
+//! path = "build/war3map.j"
+//! compression = "bzip2"
+//!
+//! [[file]]
+//! name = "war3map.wts"
+//! archive = "base.w3x"
+//! locale = "frFR"
+//!
+//! [[file]]
+//! name = "readme.txt"
+//! data = "SGVsbG8sIHdvcmxkIQ=="
+//! ```
+//!
+//! Every file names exactly one source: `path` for a file on disk, `archive`
+//! for a file copied out of another MPQ (by `source_name`, defaulting to
+//! `name`), or `data` for contents inlined as base64. Relative paths are
+//! resolved against the manifest's directory.
+//!
+//! [`ArchiveBuilder::from_manifest`] loads a manifest into a builder, and
+//! [`BuildManifest::from_archive`] goes the other way, describing an
+//! existing archive so it can be unpacked, edited and rebuilt. This module
+//! is only available with the `build-manifest` feature.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::compression::{flags, CompressionMethod};
+use crate::extract::output_path;
+use crate::special_files::SpecialFile;
+use crate::tables::BlockEntry;
+use crate::{
+    Archive, ArchiveBuilder, Error, FormatVersion, ListfileOption, Locale, NameHashingPolicy,
+    Result,
+};
+
+/// An archive described file by file, see the [module docs](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildManifest {
+    /// Settings for the archive as a whole, the `[archive]` table
+    #[serde(default)]
+    pub archive: BuildSettings,
+    /// Files in the order they are added, the `[[file]]` tables
+    #[serde(default, rename = "file", skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<BuildFile>,
+}
+
+/// The `[archive]` table of a [`BuildManifest`]
+///
+/// Settings left out keep the [`ArchiveBuilder`] defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildSettings {
+    /// Format version, 1 to 4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u16>,
+    /// Sector size shift, the sector size being 512 << `block_size`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_size: Option<u16>,
+    /// Compression for files that do not set their own, see
+    /// [`parse_compression`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// Whether to write a `(listfile)`, true by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listfile: Option<bool>,
+    /// Whether files get sector checksums, false by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sector_crc: Option<bool>,
+    /// Whether PKWare-compressed files are stored imploded, see
+    /// [`ArchiveBuilder::use_implode`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub implode: Option<bool>,
+}
+
+/// One `[[file]]` table of a [`BuildManifest`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildFile {
+    /// Name of the file in the archive
+    pub name: String,
+    /// File on disk to read the contents from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Archive to copy the contents from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<PathBuf>,
+    /// Name of the file in `archive`, if it differs from `name`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_name: Option<String>,
+    /// Contents encoded as base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// Compression, see [`parse_compression`]; the archive default if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// Whether to encrypt the file; files copied from an archive keep their
+    /// encryption if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<bool>,
+    /// Whether the encryption key is adjusted by the file's position
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fix_key: Option<bool>,
+    /// Locale, such as `"frFR"` or `"0x40C"`; also picks the version copied
+    /// from `archive`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+}
+
+impl BuildManifest {
+    /// Parse a manifest from TOML
+    ///
+    /// # Errors
+    /// `Error::InvalidFormat` if the text is not a valid manifest, including
+    /// tables or keys the manifest does not know
+    pub fn from_toml(text: &str) -> Result<Self> {
+        toml::from_str(text).map_err(|e| Error::invalid_format(format!("Invalid manifest: {e}")))
+    }
+
+    /// Read a manifest from a TOML file
+    ///
+    /// # Errors
+    /// - Any I/O error from reading `path`
+    /// - `Error::InvalidFormat` as for [`from_toml`](Self::from_toml)
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Describe an existing archive as a manifest
+    ///
+    /// Every listed file becomes a `[[file]]` copied from `source`, once
+    /// for each locale it is stored in, with its compression, encryption
+    /// and locale spelled out. The most common compression becomes the
+    /// archive default, so only files that differ name their own; files
+    /// flagged as compressed whose data did not shrink keep the default. Files
+    /// the builder generates, such as `(listfile)` and `(attributes)`, are
+    /// left out. `source` is written as given, so a relative path should be
+    /// relative to where the manifest will be saved.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{Archive, BuildManifest};
+    /// use std::path::Path;
+    ///
+    /// let mut archive = Archive::open("map.w3x")?;
+    /// let manifest = BuildManifest::from_archive(&mut archive, Path::new("map.w3x"))?;
+    /// std::fs::write("manifest.toml", manifest.to_toml()?)?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if the archive has no `(listfile)`
+    /// - Any error from reading the archive's tables or sectors
+    pub fn from_archive(archive: &mut Archive, source: &Path) -> Result<Self> {
+        if archive.find_file("(listfile)")?.is_none() {
+            return Err(Error::invalid_format(
+                "archive has no (listfile); its files cannot be enumerated for a manifest",
+            ));
+        }
+
+        let header = archive.header();
+        let mut settings = BuildSettings {
+            version: Some(header.format_version as u16 + 1),
+            block_size: Some(header.block_size),
+            ..BuildSettings::default()
+        };
+
+        let mut files = Vec::new();
+        let mut compressions = Vec::new();
+        for entry in archive.list()? {
+            if SpecialFile::from_name(&entry.name).is_some_and(SpecialFile::is_derived) {
+                continue;
+            }
+            for locale in archive.file_locales(&entry.name)? {
+                let info = archive
+                    .find_file_with_locale(&entry.name, locale)?
+                    .ok_or_else(|| Error::FileNotFound(entry.name.clone()))?;
+                // Compressed files whose sectors all ended up stored raw do
+                // not say which method was tried, so they take the default
+                let method = archive.compression_method_with_locale(&entry.name, locale)?;
+                compressions.push(match method {
+                    CompressionMethod::None if info.is_compressed() => None,
+                    method => Some(method.to_flags()),
+                });
+                if info.flags & BlockEntry::FLAG_IMPLODE != 0 {
+                    settings.implode = Some(true);
+                }
+                if info.has_sector_crc() {
+                    settings.sector_crc = Some(true);
+                }
+                files.push(BuildFile {
+                    name: entry.name.clone(),
+                    archive: Some(source.to_path_buf()),
+                    encrypt: info.is_encrypted().then_some(true),
+                    fix_key: info.has_fix_key().then_some(true),
+                    locale: (locale != Locale::NEUTRAL).then(|| locale.to_string()),
+                    ..BuildFile::default()
+                });
+            }
+        }
+
+        let mut counts: BTreeMap<u8, usize> = BTreeMap::new();
+        for &compression in compressions.iter().flatten() {
+            *counts.entry(compression).or_default() += 1;
+        }
+        if let Some((&default, _)) = counts.iter().max_by_key(|&(_, count)| count) {
+            settings.compression = Some(compression_name(default));
+            for (file, &compression) in files.iter_mut().zip(&compressions) {
+                if compression.is_some_and(|compression| compression != default) {
+                    file.compression = compression.map(compression_name);
+                }
+            }
+        }
+
+        Ok(Self {
+            archive: settings,
+            files,
+        })
+    }
+
+    /// Read neutral-locale files copied from an archive from `dir` instead
+    ///
+    /// Each such file is pointed at the path [`output_path`] gives it below
+    /// `dir`, which is where [`Archive::extract_all`] writes it, so an
+    /// archive can be extracted, edited on disk and rebuilt from the
+    /// manifest. Other locales keep reading from the archive, since only one
+    /// version of each name can be extracted to the same path.
+    pub fn with_data_dir(mut self, dir: &Path) -> Self {
+        for file in &mut self.files {
+            if file.archive.is_none() || file.source_name.is_some() || file.locale.is_some() {
+                continue;
+            }
+            if let Some(path) = output_path(dir, &file.name) {
+                file.archive = None;
+                file.path = Some(path);
+            }
+        }
+        self
+    }
+
+    /// Write the manifest as TOML
+    ///
+    /// # Errors
+    /// `Error::InvalidFormat` if the manifest cannot be represented, such as
+    /// a path that is not valid UTF-8
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string_pretty(self)
+            .map_err(|e| Error::invalid_format(format!("Cannot write manifest: {e}")))
+    }
+
+    /// Check the manifest without reading any of its sources
+    ///
+    /// Catches everything [`to_builder`](Self::to_builder) rejects before it
+    /// opens a file: settings out of range, unknown compression or locale
+    /// names, files with no source or more than one, a `source_name`
+    /// without an `archive`, `data` that is not base64, and the same name
+    /// listed twice for one locale.
+    ///
+    /// # Errors
+    /// `Error::InvalidFormat` describing the first problem found
+    pub fn validate(&self) -> Result<()> {
+        let settings = &self.archive;
+        if let Some(version) = settings.version {
+            format_version(version)?;
+        }
+        if let Some(block_size) = settings.block_size {
+            check_block_size(block_size)?;
+        }
+        if let Some(name) = &settings.compression {
+            parse_compression(name)?;
+        }
+
+        let mut seen = HashSet::new();
+        for file in &self.files {
+            let context = |e: Error| match e {
+                Error::InvalidFormat(message) => {
+                    Error::invalid_format(format!("{}: {message}", file.name))
+                }
+                e => e,
+            };
+            if let Some(name) = &file.compression {
+                parse_compression(name).map_err(context)?;
+            }
+            let locale = match &file.locale {
+                Some(locale) => locale.parse::<Locale>().map_err(context)?,
+                None => Locale::NEUTRAL,
+            };
+            let sources = [
+                file.path.is_some(),
+                file.archive.is_some(),
+                file.data.is_some(),
+            ];
+            if sources.into_iter().filter(|&source| source).count() != 1 {
+                return Err(context(Error::invalid_format(
+                    "a file needs exactly one of path, archive and data",
+                )));
+            }
+            if file.source_name.is_some() && file.archive.is_none() {
+                return Err(context(Error::invalid_format(
+                    "source_name needs an archive",
+                )));
+            }
+            if let Some(data) = &file.data {
+                decode_base64(data).map_err(context)?;
+            }
+            let key = (
+                NameHashingPolicy::default().normalize_name(&file.name),
+                locale,
+            );
+            if !seen.insert(key) {
+                return Err(context(Error::invalid_format(format!(
+                    "listed twice for locale {locale}"
+                ))));
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue every file of the manifest in a new builder
+    ///
+    /// The manifest is [validated](Self::validate) first. Files on disk are
+    /// read when the builder is built. Files from other archives are read
+    /// now, opening each archive once. Relative paths are resolved against
+    /// `base_dir`.
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` from [`validate`](Self::validate)
+    /// - `Error::FileNotFound` if a file is not in the archive it is copied
+    ///   from
+    /// - Any error from opening or reading a source archive
+    pub fn to_builder(&self, base_dir: &Path) -> Result<ArchiveBuilder> {
+        self.validate()?;
+
+        let settings = &self.archive;
+        let mut builder = ArchiveBuilder::new();
+        if let Some(version) = settings.version {
+            builder = builder.version(format_version(version)?);
+        }
+        if let Some(block_size) = settings.block_size {
+            builder = builder.block_size(block_size);
+        }
+        let default_compression = match &settings.compression {
+            Some(name) => parse_compression(name)?,
+            None => flags::ZLIB,
+        };
+        builder = builder.default_compression(default_compression);
+        if settings.listfile == Some(false) {
+            builder = builder.listfile_option(ListfileOption::None);
+        }
+        if let Some(sector_crc) = settings.sector_crc {
+            builder = builder.generate_crcs(sector_crc);
+        }
+        if let Some(implode) = settings.implode {
+            builder = builder.use_implode(implode);
+        }
+
+        let mut archives: HashMap<PathBuf, Archive> = HashMap::new();
+        for file in &self.files {
+            let context = |e: Error| match e {
+                Error::InvalidFormat(message) => {
+                    Error::invalid_format(format!("{}: {message}", file.name))
+                }
+                e => e,
+            };
+            let compression = match &file.compression {
+                Some(name) => parse_compression(name).map_err(context)?,
+                None => default_compression,
+            };
+            let locale = match &file.locale {
+                Some(locale) => Some(locale.parse::<Locale>().map_err(context)?),
+                None => None,
+            };
+            let encrypt = file.encrypt.unwrap_or(false) || file.fix_key == Some(true);
+            let fix_key = file.fix_key.unwrap_or(false);
+
+            match (&file.path, &file.archive, &file.data) {
+                (Some(path), None, None) => {
+                    let path = base_dir.join(path);
+                    let locale = locale.unwrap_or_default();
+                    builder = if encrypt {
+                        builder.add_file_with_encryption(
+                            path,
+                            &file.name,
+                            compression,
+                            fix_key,
+                            locale,
+                        )
+                    } else {
+                        builder.add_file_with_options(path, &file.name, compression, false, locale)
+                    };
+                }
+                (None, Some(path), None) => {
+                    let path = base_dir.join(path);
+                    if !archives.contains_key(&path) {
+                        archives.insert(path.clone(), Archive::open(&path)?);
+                    }
+                    let archive = &archives[&path];
+                    let source_name = file.source_name.as_deref().unwrap_or(&file.name);
+                    let info = archive
+                        .find_file_with_locale(source_name, locale.unwrap_or_default())?
+                        .ok_or_else(|| Error::FileNotFound(source_name.to_string()))?;
+                    let data = archive.read_file_with_locale(source_name, info.locale)?;
+                    let encrypt = file.encrypt.unwrap_or(info.is_encrypted()) || fix_key;
+                    let fix_key = file.fix_key.unwrap_or(encrypt && info.has_fix_key());
+                    let locale = locale.unwrap_or(info.locale);
+                    builder = if encrypt {
+                        builder.add_file_data_with_encryption(
+                            data,
+                            &file.name,
+                            compression,
+                            fix_key,
+                            locale,
+                        )
+                    } else {
+                        builder.add_file_data_with_options(
+                            data,
+                            &file.name,
+                            compression,
+                            false,
+                            locale,
+                        )
+                    };
+                }
+                (None, None, Some(data)) => {
+                    let data = decode_base64(data).map_err(context)?;
+                    let locale = locale.unwrap_or_default();
+                    builder = if encrypt {
+                        builder.add_file_data_with_encryption(
+                            data,
+                            &file.name,
+                            compression,
+                            fix_key,
+                            locale,
+                        )
+                    } else {
+                        builder.add_file_data_with_options(
+                            data,
+                            &file.name,
+                            compression,
+                            false,
+                            locale,
+                        )
+                    };
+                }
+                _ => unreachable!("validated to have exactly one source"),
+            }
+        }
+        Ok(builder)
+    }
+}
+
+impl ArchiveBuilder {
+    /// Start a builder from a TOML [`BuildManifest`]
+    ///
+    /// Relative paths in the manifest are resolved against its directory.
+    /// Only available with the `build-manifest` feature.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::ArchiveBuilder;
+    ///
+    /// ArchiveBuilder::from_manifest("map/manifest.toml")?.build("map.w3x")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    ///
+    /// # Errors
+    /// - Any error from [`BuildManifest::load`] or
+    ///   [`BuildManifest::to_builder`]
+    pub fn from_manifest<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        BuildManifest::load(path)?.to_builder(base_dir)
+    }
+}
+
+/// Compression flags for a name in a manifest
+///
+/// Accepts `none`, `huffman`, `zlib`, `implode`, `pkware`, `bzip2`,
+/// `sparse`, `adpcm-mono`, `adpcm-stereo` and `lzma`, case-insensitively,
+/// or the flag byte itself in decimal or as `0x..` hex.
+///
+/// # Errors
+/// `Error::InvalidFormat` for anything else
+pub fn parse_compression(name: &str) -> Result<u8> {
+    let flag = match name.to_ascii_lowercase().as_str() {
+        "none" => 0,
+        "huffman" => flags::HUFFMAN,
+        "zlib" => flags::ZLIB,
+        "implode" => flags::IMPLODE,
+        "pkware" => flags::PKWARE,
+        "bzip2" => flags::BZIP2,
+        "sparse" => flags::SPARSE,
+        "adpcm-mono" => flags::ADPCM_MONO,
+        "adpcm-stereo" => flags::ADPCM_STEREO,
+        "lzma" => flags::LZMA,
+        other => match other.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => other.parse(),
+        }
+        .map_err(|_| Error::invalid_format(format!("Unknown compression: {name}")))?,
+    };
+    Ok(flag)
+}
+
+/// Name for compression flags in a manifest, the inverse of
+/// [`parse_compression`]
+///
+/// Single methods get their name and combinations their `0x..` flag byte.
+pub fn compression_name(compression: u8) -> String {
+    let name = match compression {
+        0 => "none",
+        flags::HUFFMAN => "huffman",
+        flags::ZLIB => "zlib",
+        flags::IMPLODE => "implode",
+        flags::PKWARE => "pkware",
+        flags::BZIP2 => "bzip2",
+        flags::SPARSE => "sparse",
+        flags::ADPCM_MONO => "adpcm-mono",
+        flags::ADPCM_STEREO => "adpcm-stereo",
+        flags::LZMA => "lzma",
+        other => return format!("0x{other:02X}"),
+    };
+    name.to_string()
+}
+
+/// Format version for a manifest's 1-based `version`
+fn format_version(version: u16) -> Result<FormatVersion> {
+    version
+        .checked_sub(1)
+        .and_then(FormatVersion::from_raw)
+        .ok_or_else(|| Error::invalid_format(format!("Invalid manifest version: {version}")))
+}
+
+fn check_block_size(block_size: u16) -> Result<()> {
+    if block_size > crate::MAX_BLOCK_SIZE {
+        return Err(Error::invalid_format(format!(
+            "Invalid manifest block_size: {block_size} (at most {})",
+            crate::MAX_BLOCK_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Decode standard base64, ignoring whitespace
+fn decode_base64(text: &str) -> Result<Vec<u8>> {
+    let invalid = || Error::invalid_format("Invalid base64 data");
+    let digits: Vec<u8> = text
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    if digits.len() % 4 != 0 {
+        return Err(invalid());
+    }
+
+    let mut out = Vec::with_capacity(digits.len() / 4 * 3);
+    for (i, quad) in digits.chunks(4).enumerate() {
+        let last = i == digits.len() / 4 - 1;
+        let padding = quad.iter().rev().take_while(|&&byte| byte == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err(invalid());
+        }
+        let mut value = 0u32;
+        for &byte in &quad[..4 - padding] {
+            let digit = match byte {
+                b'A'..=b'Z' => byte - b'A',
+                b'a'..=b'z' => byte - b'a' + 26,
+                b'0'..=b'9' => byte - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                _ => return Err(invalid()),
+            };
+            value = value << 6 | digit as u32;
+        }
+        value <<= 6 * padding;
+        out.extend_from_slice(&value.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_base64() {
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("Zg==").unwrap(), b"f");
+        assert_eq!(decode_base64("Zm8=").unwrap(), b"fo");
+        assert_eq!(decode_base64("Zm9v").unwrap(), b"foo");
+        assert_eq!(decode_base64("Zm9v\n YmFy").unwrap(), b"foobar");
+        assert_eq!(decode_base64("//79").unwrap(), [0xFF, 0xFE, 0xFD]);
+
+        for bad in ["Zg", "Zg=a", "Z===", "Zg==Zm9v", "Zm9*"] {
+            assert!(decode_base64(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!(parse_compression("none").unwrap(), 0);
+        assert_eq!(parse_compression("BZip2").unwrap(), flags::BZIP2);
+        assert_eq!(parse_compression("0x22").unwrap(), 0x22);
+        assert_eq!(parse_compression("18").unwrap(), flags::LZMA);
+        assert!(parse_compression("gzip").is_err());
+    }
+
+    #[test]
+    fn test_manifest_errors() {
+        assert!(BuildManifest::from_toml("[[file]]\nname = \"a\"\npaht = \"a\"").is_err());
+
+        let manifest = BuildManifest::from_toml("[[file]]\nname = \"a\"").unwrap();
+        let error = manifest.to_builder(Path::new(".")).unwrap_err();
+        assert!(error.to_string().contains("exactly one of"), "{error}");
+
+        let manifest = BuildManifest::from_toml("[archive]\nversion = 5").unwrap();
+        assert!(manifest.to_builder(Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = "[[file]]\nname = \"a\"\ndata = \"Zg==\"\n\
+                     [[file]]\nname = \"A\"\ndata = \"\"\nlocale = \"frFR\"";
+        BuildManifest::from_toml(valid).unwrap().validate().unwrap();
+
+        for (manifest, problem) in [
+            ("[archive]\nblock_size = 23", "block_size: 23 (at most 22)"),
+            ("[archive]\ncompression = \"gzip\"", "gzip"),
+            ("[[file]]\nname = \"a\"\ndata = \"Zg\"", "a: Invalid base64"),
+            (
+                "[[file]]\nname = \"a\"\ndata = \"\"\nlocale = \"xx\"",
+                "a: ",
+            ),
+            (
+                "[[file]]\nname = \"a\"\ndata = \"\"\nsource_name = \"b\"",
+                "source_name",
+            ),
+            (
+                "[[file]]\nname = \"a/b\"\ndata = \"\"\n[[file]]\nname = \"A\\\\B\"\ndata = \"\"",
+                "listed twice",
+            ),
+        ] {
+            let error = BuildManifest::from_toml(manifest)
+                .unwrap()
+                .validate()
+                .unwrap_err();
+            assert!(error.to_string().contains(problem), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_compression_name() {
+        for flag in [0, flags::ZLIB, flags::BZIP2, flags::LZMA, 0x22] {
+            assert_eq!(parse_compression(&compression_name(flag)).unwrap(), flag);
+        }
+        assert_eq!(compression_name(0x22), "0x22");
+    }
+}
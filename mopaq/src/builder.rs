@@ -1,16 +1,31 @@
 //! Archive builder for creating MPQ archives
 
 use crate::{
+    checksum::{self, SectorChecksum},
+    compatibility::Compatibility,
     compression::{compress, flags as compression_flags},
-    crypto::{encrypt_block, hash_string, hash_type, jenkins_hash},
-    header::{FormatVersion, MpqHeaderV4Data},
-    tables::{BetHeader, BlockEntry, BlockTable, HashEntry, HashTable, HetHeader, HiBlockTable},
-    Error, Result,
+    crypto::{
+        encrypt_block, hash_string, hash_string_with, hash_type, NameHashingPolicy,
+        SignaturePolicy, SignatureType, WEAK_SIGNATURE_FILE_SIZE,
+    },
+    header::{FormatVersion, MpqHeader, MpqHeaderV4Data},
+    locale::Locale,
+    modification::AddFileOptions,
+    mpq_header,
+    special_files::SpecialFile,
+    tables::{
+        name_hash, BetFileInfo, BetHeader, BlockEntry, BlockTable, HashEntry, HashTable, HetHeader,
+        HiBlockTable, PlatformPolicy,
+    },
+    Archive, Error, FileInfo, Result,
 };
-use md5::{Digest, Md5};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fs::{self};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
 
 /// Helper trait for writing little-endian integers
@@ -24,11 +39,6 @@ trait WriteLittleEndian: Write {
         self.write_all(&value.to_le_bytes())?;
         Ok(())
     }
-
-    fn write_u64_le(&mut self, value: u64) -> Result<()> {
-        self.write_all(&value.to_le_bytes())?;
-        Ok(())
-    }
 }
 
 impl<W: Write> WriteLittleEndian for W {}
@@ -47,7 +57,9 @@ struct PendingFile {
     /// Whether to use FIX_KEY encryption (adjusts key by block position)
     use_fix_key: bool,
     /// Locale code
-    locale: u16,
+    locale: Locale,
+    /// Platform code (written according to the builder's platform policy)
+    platform: u16,
 }
 
 #[derive(Debug)]
@@ -56,24 +68,120 @@ enum FileSource {
     Data(Vec<u8>),
 }
 
+impl PendingFile {
+    /// The file's contents, borrowed when they are held in memory
+    fn data(&self) -> Result<Cow<'_, [u8]>> {
+        Ok(match &self.source {
+            FileSource::Path(path) => Cow::Owned(fs::read(path)?),
+            FileSource::Data(data) => Cow::Borrowed(data),
+        })
+    }
+
+    /// Size of the file's contents, without reading them
+    fn size(&self) -> Result<u64> {
+        Ok(match &self.source {
+            FileSource::Path(path) => fs::metadata(path)?.len(),
+            FileSource::Data(data) => data.len() as u64,
+        })
+    }
+}
+
 /// Parameters for writing a file to the archive
 struct FileWriteParams<'a> {
-    /// File data to write
+    /// File data to write, empty when a stored file is copied from disk
     file_data: &'a [u8],
+    /// Size of the file
+    file_size: u64,
     /// Archive name for the file
     archive_name: &'a str,
-    /// Compression method
+    /// Compression method of a single-unit file or a file's first sector
     compression: u8,
+    /// Compression method of every sector after the first
+    compression_next: u8,
     /// Whether to encrypt
     encrypt: bool,
     /// Whether to use FIX_KEY encryption
     use_fix_key: bool,
     /// Sector size
     sector_size: usize,
+    /// Whether to store the file in one piece whatever its size
+    single_unit: bool,
     /// File position in archive (64-bit for large archives)
     file_pos: u64,
 }
 
+impl FileWriteParams<'_> {
+    /// Whether any part of the file is to be compressed
+    fn compressed(&self) -> bool {
+        self.compression != 0 || self.compression_next != 0
+    }
+}
+
+/// How a file's data is laid out in the archive
+///
+/// | Layout       | Flags                          | Data                                        |
+/// |--------------|--------------------------------|---------------------------------------------|
+/// | `SingleUnit` | `SINGLE_UNIT`, `COMPRESS`¹     | the file, then its checksum²                |
+/// | `Sectored`   | `COMPRESS`³                    | offset table, checksum table², sectors¹     |
+/// | `Stored`     | none                           | raw sectors                                 |
+///
+/// ¹ compressed only where that makes the data smaller.
+/// ² with `SECTOR_CRC`, when checksums are generated.
+/// ³ `IMPLODE` for imploded files, whose compressed data has no mask byte.
+///
+/// `ENCRYPTED` and `FIX_KEY` combine with every layout. Sectors and offset
+/// tables are encrypted one sector at a time; checksums never are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileLayout {
+    SingleUnit,
+    Sectored,
+    Stored,
+}
+
+impl FileLayout {
+    /// Layout of a file of `file_size` bytes
+    ///
+    /// `compressed` is whether any of its sectors is to be compressed.
+    /// Readers find the sector checksum table through the sector offset
+    /// table, so an uncompressed file with checksums is written sectored,
+    /// with every sector stored raw. Files that fit in a sector, and those
+    /// asked for with `single_unit`, are stored in one piece.
+    fn choose(
+        file_size: u64,
+        sector_size: usize,
+        compressed: bool,
+        generate_crcs: bool,
+        single_unit: bool,
+    ) -> Self {
+        if single_unit || file_size <= sector_size as u64 {
+            FileLayout::SingleUnit
+        } else if !compressed && !generate_crcs {
+            FileLayout::Stored
+        } else {
+            FileLayout::Sectored
+        }
+    }
+
+    /// Flags the layout implies, before compression and encryption
+    ///
+    /// `compress_flag` is `FLAG_COMPRESS`, or `FLAG_IMPLODE` for imploded
+    /// files.
+    fn flags(self, generate_crcs: bool, compress_flag: u32) -> u32 {
+        let layout = match self {
+            FileLayout::SingleUnit => BlockEntry::FLAG_SINGLE_UNIT,
+            // Readers only look for a sector offset table in compressed
+            // files, so the flag is set even if every sector is stored raw
+            FileLayout::Sectored => compress_flag,
+            FileLayout::Stored => return 0,
+        };
+        if generate_crcs {
+            layout | BlockEntry::FLAG_SECTOR_CRC
+        } else {
+            layout
+        }
+    }
+}
+
 /// Parameters for writing the MPQ header
 struct HeaderWriteParams {
     archive_size: u64,
@@ -90,6 +198,28 @@ struct HeaderWriteParams {
     v4_data: Option<MpqHeaderV4Data>,
 }
 
+/// Classic tables written next to HET/BET, all zero when they are omitted
+#[derive(Debug, Default)]
+struct ClassicTables {
+    hash_table_pos: u64,
+    hash_table_size: u32,
+    hash_table_md5: [u8; 16],
+    block_table_pos: u64,
+    block_table_size: u32,
+    block_table_md5: [u8; 16],
+    hi_block_table_pos: Option<u64>,
+    hi_block_table_md5: [u8; 16],
+}
+
+/// Signature of the archive a builder was created from
+#[derive(Debug, Clone, Default)]
+struct SourceSignature {
+    /// Contents of the `(signature)` file
+    weak: Option<Vec<u8>>,
+    /// Strong signature after the archive, header included
+    strong: Option<Vec<u8>>,
+}
+
 /// Options for listfile generation
 #[derive(Debug, Clone)]
 pub enum ListfileOption {
@@ -101,6 +231,258 @@ pub enum ListfileOption {
     None,
 }
 
+/// Estimated layout of a single file in a [`BuildPlan`]
+#[derive(Debug, Clone)]
+pub struct PlannedFile {
+    /// Name of the file inside the archive
+    pub name: String,
+    /// Uncompressed file size
+    pub file_size: u64,
+    /// Compression method that will be used
+    pub compression: u8,
+    /// Whether the file will be encrypted
+    pub encrypted: bool,
+    /// Estimated size in the archive, including sector offset and CRC tables
+    pub estimated_size: u64,
+}
+
+impl PlannedFile {
+    /// Estimated stored size divided by the file size (1.0 for empty files)
+    pub fn estimated_ratio(&self) -> f64 {
+        if self.file_size == 0 {
+            1.0
+        } else {
+            self.estimated_size as f64 / self.file_size as f64
+        }
+    }
+}
+
+/// Archive layout computed by [`ArchiveBuilder::plan`] without writing anything
+///
+/// Compressed sizes are estimated from a sample at the start of each file,
+/// so the totals are approximations. Table sizes are exact for uncompressed
+/// tables and an upper bound when table compression is enabled.
+#[derive(Debug, Clone)]
+pub struct BuildPlan {
+    /// Target format version
+    pub version: FormatVersion,
+    /// Sector size in bytes
+    pub sector_size: usize,
+    /// Files in the order they will be written, including the listfile
+    pub files: Vec<PlannedFile>,
+    /// Number of hash table entries
+    pub hash_table_entries: u32,
+    /// Number of block table entries
+    pub block_table_entries: u32,
+    /// Estimated size of all tables in bytes
+    pub table_size: u64,
+    /// Total uncompressed size of all files
+    pub total_file_size: u64,
+    /// Estimated size of the resulting archive file
+    pub estimated_archive_size: u64,
+}
+
+impl BuildPlan {
+    /// Fraction of hash table entries that will be occupied
+    pub fn hash_table_occupancy(&self) -> f64 {
+        self.block_table_entries as f64 / self.hash_table_entries as f64
+    }
+
+    /// Whether the archive is likely to exceed what the format version supports
+    ///
+    /// Version 1 archives are limited to 4 GiB, since all offsets are 32-bit.
+    pub fn exceeds_format_limit(&self) -> bool {
+        self.version == FormatVersion::V1 && self.estimated_archive_size > u32::MAX as u64
+    }
+}
+
+/// Number of bytes sampled per file to estimate compression in [`ArchiveBuilder::plan`]
+const PLAN_SAMPLE_SIZE: usize = 256 * 1024;
+
+/// Bits of the Jenkins name hash stored across the HET and BET tables
+const NAME_HASH_BITS: u32 = 64;
+
+/// Sectors compressed together before they are written, which bounds the
+/// compressed data held in memory while still keeping a thread pool busy
+const SECTOR_BATCH: usize = 64;
+
+/// Tables reported to [`BuildObserver::on_table_write`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildTable {
+    /// Classic hash table
+    Hash,
+    /// Classic block table
+    Block,
+    /// Hi-block table (v2+)
+    HiBlock,
+    /// HET table (v3+)
+    Het,
+    /// BET table (v3+)
+    Bet,
+}
+
+/// A file as written by [`ArchiveBuilder::build`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuiltFile {
+    /// Name of the file in the archive
+    pub name: String,
+    /// Uncompressed size in bytes
+    pub file_size: u64,
+    /// Size stored in the archive, including sector tables
+    pub compressed_size: u64,
+    /// Block table flags the file was written with
+    pub flags: u32,
+}
+
+impl BuiltFile {
+    /// Stored size divided by the original size (1.0 for empty files)
+    pub fn ratio(&self) -> f64 {
+        if self.file_size == 0 {
+            1.0
+        } else {
+            self.compressed_size as f64 / self.file_size as f64
+        }
+    }
+}
+
+/// Layout and timings of an archive written by [`ArchiveBuilder::build`]
+#[derive(Debug, Clone)]
+pub struct BuildSummary {
+    /// Format version of the archive
+    pub version: FormatVersion,
+    /// Files in the order they were written, including generated ones such
+    /// as the listfile
+    pub files: Vec<BuiltFile>,
+    /// Tables in the order they were written, with their size on disk
+    pub tables: Vec<(BuildTable, u64)>,
+    /// Size of the archive file in bytes
+    pub archive_size: u64,
+    /// Time spent reading, compressing and writing files
+    pub file_time: Duration,
+    /// Time spent writing the tables and the header
+    pub table_time: Duration,
+    /// Time the whole build took
+    pub total_time: Duration,
+}
+
+impl BuildSummary {
+    /// Total uncompressed size of all files
+    pub fn total_file_size(&self) -> u64 {
+        self.files.iter().map(|file| file.file_size).sum()
+    }
+
+    /// Total stored size of all files
+    pub fn total_compressed_size(&self) -> u64 {
+        self.files.iter().map(|file| file.compressed_size).sum()
+    }
+
+    /// Stored size of all files divided by their original size (1.0 if
+    /// there is no file data)
+    pub fn ratio(&self) -> f64 {
+        match self.total_file_size() {
+            0 => 1.0,
+            total => self.total_compressed_size() as f64 / total as f64,
+        }
+    }
+
+    /// Size on disk of a table, or `None` if it was not written
+    pub fn table_size(&self, table: BuildTable) -> Option<u64> {
+        self.tables
+            .iter()
+            .find(|(written, _)| *written == table)
+            .map(|&(_, size)| size)
+    }
+}
+
+/// Files and tables recorded while the archive is written
+#[derive(Debug, Default)]
+struct BuildLog {
+    files: Vec<BuiltFile>,
+    tables: Vec<(BuildTable, u64)>,
+    file_time: Duration,
+    table_time: Duration,
+}
+
+/// Receives progress events while [`ArchiveBuilder::build`] runs
+///
+/// All methods have empty default implementations, so an observer only needs
+/// to implement the events it cares about. Events are delivered on the thread
+/// calling `build()`, in archive order.
+///
+/// # Examples
+///
+/// ```no_run
+/// use mopaq::{ArchiveBuilder, BuildObserver};
+///
+/// struct Printer;
+///
+/// impl BuildObserver for Printer {
+///     fn on_file_done(&mut self, name: &str, compressed_size: u64, ratio: f64) {
+///         println!("{name}: {compressed_size} bytes ({:.0}%)", ratio * 100.0);
+///     }
+/// }
+///
+/// ArchiveBuilder::new()
+///     .observer(Printer)
+///     .add_file_data(b"Hello".to_vec(), "hello.txt")
+///     .build("observed.mpq")?;
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+pub trait BuildObserver {
+    /// Called before a file is read and written
+    ///
+    /// `index` is the zero-based position of the file and `total` the number
+    /// of files (including generated special files such as the listfile).
+    fn on_file_start(&mut self, _name: &str, _index: usize, _total: usize) {}
+
+    /// Called after each sector of a multi-sector file has been processed
+    ///
+    /// `processed` counts the uncompressed bytes handled so far out of
+    /// `total`. Files stored as a single unit only report start and done.
+    fn on_file_progress(&mut self, _name: &str, _processed: u64, _total: u64) {}
+
+    /// Called after a file has been written
+    ///
+    /// `ratio` is the stored size divided by the original size (1.0 for
+    /// empty files).
+    fn on_file_done(&mut self, _name: &str, _compressed_size: u64, _ratio: f64) {}
+
+    /// Called after a table has been written, with its size on disk in bytes
+    fn on_table_write(&mut self, _table: BuildTable, _size: u64) {}
+
+    /// Called once the build is over, whether it succeeded or not
+    ///
+    /// A failed build passes the error that ended it, with an
+    /// `archive_size` of 0; no archive was written in that case.
+    fn on_finish(&mut self, _archive_size: u64, _file_count: usize, _error: Option<&Error>) {}
+}
+
+/// Holder for the boxed observer so the builder can stay `Debug`
+pub(crate) struct ObserverSlot(RefCell<Box<dyn BuildObserver + Send>>);
+
+impl ObserverSlot {
+    pub(crate) fn new<O: BuildObserver + Send + 'static>(observer: O) -> Self {
+        Self(RefCell::new(Box::new(observer)))
+    }
+}
+
+impl std::fmt::Debug for ObserverSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ObserverSlot(..)")
+    }
+}
+
+/// Compress one sector, keeping the original bytes when that is not smaller
+fn compress_sector(sector: &[u8], compression: u8) -> Result<Vec<u8>> {
+    if compression == 0 || sector.is_empty() {
+        return Ok(sector.to_vec());
+    }
+
+    // compress() prefixes the method byte and only returns compressed data
+    // when it is beneficial
+    compress(sector, compression)
+}
+
 /// Builder for creating new MPQ archives
 ///
 /// `ArchiveBuilder` provides a fluent interface for creating MPQ archives with
@@ -156,10 +538,37 @@ pub struct ArchiveBuilder {
     default_compression: u8,
     /// Whether to generate sector CRCs for files
     generate_crcs: bool,
+    /// Whether PKWare-compressed files are stored with `FLAG_IMPLODE`
+    use_implode: bool,
+    /// Algorithm for generated sector checksums
+    sector_checksum: SectorChecksum,
     /// Whether to compress HET/BET tables (v3+ only)
     compress_tables: bool,
+    /// Whether v3+ archives also get classic hash and block tables
+    classic_tables: bool,
     /// Compression method for tables
     table_compression: u8,
+    /// How nonzero platform codes of pending files are written
+    platform_policy: PlatformPolicy,
+    /// How names are normalized before they are hashed into the tables
+    name_hashing: NameHashingPolicy,
+    /// User data written before the MPQ header, with its header size
+    user_data: Option<(Vec<u8>, u32)>,
+    /// Progress observer
+    observer: Option<ObserverSlot>,
+    /// Files and tables written so far, reported by `build()`
+    build_log: RefCell<BuildLog>,
+    /// Worker threads for sector compression (0 = one per core)
+    threads: usize,
+    /// Game the archive has to stay readable by
+    compatibility: Option<Compatibility>,
+    /// What happens to the signature of a rebuilt archive
+    signature_policy: SignaturePolicy,
+    /// Signature of the archive passed to `from_archive`
+    source_signature: SourceSignature,
+    /// Pool the sectors are compressed on, created by `build()`
+    #[cfg(feature = "parallel")]
+    thread_pool: Option<rayon::ThreadPool>,
 }
 
 impl ArchiveBuilder {
@@ -172,8 +581,22 @@ impl ArchiveBuilder {
             listfile_option: ListfileOption::Generate,
             default_compression: compression_flags::ZLIB,
             generate_crcs: false,
+            use_implode: false,
+            sector_checksum: SectorChecksum::default(),
             compress_tables: false, // Default to uncompressed for compatibility
+            classic_tables: true,
             table_compression: compression_flags::ZLIB,
+            platform_policy: PlatformPolicy::default(),
+            name_hashing: NameHashingPolicy::default(),
+            user_data: None,
+            observer: None,
+            build_log: RefCell::default(),
+            threads: 1,
+            compatibility: None,
+            signature_policy: SignaturePolicy::Strip,
+            source_signature: SourceSignature::default(),
+            #[cfg(feature = "parallel")]
+            thread_pool: None,
         }
     }
 
@@ -183,6 +606,35 @@ impl ArchiveBuilder {
         self
     }
 
+    /// Build an archive that a specific game can read
+    ///
+    /// Sets the version to the newest format `target` opens, the block size
+    /// to 3 (4 KiB sectors) and the default compression to the one the
+    /// game's own archives use. These can still be changed afterwards, but
+    /// `build()` fails with `Error::Incompatible` if the version, block size
+    /// or the compression of any file or table is one the game cannot read.
+    ///
+    /// Files take the default compression when they are added, so set the
+    /// target before adding files.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{ArchiveBuilder, Compatibility};
+    ///
+    /// ArchiveBuilder::new()
+    ///     .compatibility(Compatibility::Diablo2)
+    ///     .add_file("excel/armor.txt", "data\\global\\excel\\armor.txt")
+    ///     .build("patch_d2.mpq")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn compatibility(mut self, target: Compatibility) -> Self {
+        self.version = target.max_version();
+        self.block_size = 3;
+        self.default_compression = target.default_compression();
+        self.compatibility = Some(target);
+        self
+    }
+
     /// Set the block size (sector size = 512 * 2^block_size)
     ///
     /// The block size determines the sector size used for file storage.
@@ -190,7 +642,8 @@ impl ArchiveBuilder {
     /// but increase overhead for small files.
     ///
     /// # Parameters
-    /// - `block_size`: Power of 2 exponent (0-31). Final sector size = 512 * 2^block_size
+    /// - `block_size`: Power of 2 exponent (0-22, see [`MAX_BLOCK_SIZE`](crate::MAX_BLOCK_SIZE)).
+    ///   Final sector size = 512 * 2^block_size
     ///   - Common values: 3 (4KB sectors), 4 (8KB), 5 (16KB), 6 (32KB), 7 (64KB)
     ///
     /// # Examples
@@ -241,12 +694,64 @@ impl ArchiveBuilder {
     ///
     /// # Notes
     /// CRC generation is recommended for archives containing critical data
-    /// where integrity verification is important.
+    /// where integrity verification is important. Sector checksums cover each
+    /// sector as stored, after compression and before encryption.
     pub fn generate_crcs(mut self, generate: bool) -> Self {
         self.generate_crcs = generate;
         self
     }
 
+    /// Store PKWare-compressed files as imploded, the way Diablo did
+    ///
+    /// Files added with [`compression::flags::PKWARE`](crate::compression::flags::PKWARE)
+    /// get `FLAG_IMPLODE` instead of `FLAG_COMPRESS`, and their data is a
+    /// bare PKWare DCL stream without the compression mask byte. Readers
+    /// that predate multi-codec compression, such as Diablo I and the
+    /// original StarCraft, only understand this form. Files using any other
+    /// compression method are not affected.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{compression, ArchiveBuilder};
+    ///
+    /// ArchiveBuilder::new()
+    ///     .use_implode(true)
+    ///     .add_file_data_with_options(
+    ///         std::fs::read("sector1s.dun")?,
+    ///         "levels\\towndata\\sector1s.dun",
+    ///         compression::flags::PKWARE,
+    ///         false,
+    ///         0,
+    ///     )
+    ///     .build("diabdat.mpq")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn use_implode(mut self, implode: bool) -> Self {
+        self.use_implode = implode;
+        self
+    }
+
+    /// Set the algorithm used for generated sector checksums
+    ///
+    /// MPQ archives canonically use ADLER32, which is the default. Choose
+    /// [`SectorChecksum::Crc32`] only for tooling that expects genuine CRC32
+    /// values; readers need to be configured or auto-detect to accept them.
+    /// Only takes effect together with [`generate_crcs`](Self::generate_crcs).
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{ArchiveBuilder, SectorChecksum};
+    ///
+    /// let builder = ArchiveBuilder::new()
+    ///     .generate_crcs(true)
+    ///     .sector_checksum(SectorChecksum::Crc32);
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn sector_checksum(mut self, algorithm: SectorChecksum) -> Self {
+        self.sector_checksum = algorithm;
+        self
+    }
+
     /// Enable or disable HET/BET table compression (v3+ only)
     ///
     /// For MPQ format version 3 and 4, the HET (Hash Extended Table) and BET
@@ -275,6 +780,32 @@ impl ArchiveBuilder {
         self
     }
 
+    /// Write classic hash and block tables alongside HET/BET (v3+ only)
+    ///
+    /// Version 3 and 4 archives locate files through their HET and BET
+    /// tables, and carry classic hash and block tables as well for readers
+    /// that predate them. Disabling them leaves `hash_table_size` and
+    /// `block_table_size` at 0 in the header, which saves 32 bytes or more
+    /// per file but makes the archive unreadable for anything that only
+    /// understands the classic tables, including the games themselves. v1/v2
+    /// archives ignore this setting. Enabled by default.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{ArchiveBuilder, FormatVersion};
+    ///
+    /// ArchiveBuilder::new()
+    ///     .version(FormatVersion::V4)
+    ///     .classic_tables(false)
+    ///     .add_file("readme.txt", "readme.txt")
+    ///     .build("modern.mpq")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn classic_tables(mut self, write: bool) -> Self {
+        self.classic_tables = write;
+        self
+    }
+
     /// Set compression method for tables (default: zlib)
     ///
     /// Specifies which compression algorithm to use when compressing HET/BET tables
@@ -302,6 +833,173 @@ impl ArchiveBuilder {
         self
     }
 
+    /// Set how nonzero platform codes of added files are written
+    ///
+    /// Files added through the regular methods always have platform 0. Files
+    /// added with [`add_file_data_with_platform()`](Self::add_file_data_with_platform)
+    /// or copied with [`add_file_from_archive()`](Self::add_file_from_archive)
+    /// may carry a nonzero code, which is handled according to this policy:
+    ///
+    /// - [`PlatformPolicy::Strict`]: `build()` fails
+    /// - [`PlatformPolicy::Lenient`] (default): the code is normalized to 0 with a warning
+    /// - [`PlatformPolicy::Preserve`]: the code is written unchanged
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{ArchiveBuilder, PlatformPolicy};
+    ///
+    /// let builder = ArchiveBuilder::new().platform_policy(PlatformPolicy::Preserve);
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn platform_policy(mut self, policy: PlatformPolicy) -> Self {
+        self.platform_policy = policy;
+        self
+    }
+
+    /// Set how file names are normalized before they are hashed
+    ///
+    /// Only needed for third-party MPQ derivatives that hash names
+    /// case-sensitively or with `/` separators. Such archives must be opened
+    /// with the same policy through [`OpenOptions::name_hashing`](crate::OpenOptions::name_hashing),
+    /// and no game can read them. [`from_archive()`](Self::from_archive)
+    /// takes the policy of the source archive.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{ArchiveBuilder, NameHashingPolicy};
+    ///
+    /// let builder = ArchiveBuilder::new().name_hashing(NameHashingPolicy {
+    ///     case_sensitive: true,
+    ///     separator: Some(b'/'),
+    /// });
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn name_hashing(mut self, policy: NameHashingPolicy) -> Self {
+        self.name_hashing = policy;
+        self
+    }
+
+    /// Write user data in front of the MPQ header
+    ///
+    /// The data is stored after an `MPQ\x1B` user data header, and the MPQ
+    /// header itself is placed at the next 512-byte boundary. This is how
+    /// StarCraft II maps and replays carry their metadata, and it allows user
+    /// data read with [`Archive::user_data_bytes()`] to survive a rebuild.
+    ///
+    /// # Parameters
+    /// - `data`: Raw user data (becomes `user_data_size`)
+    /// - `user_data_header_size`: Size of the application-specific header at
+    ///   the start of `data`; must not exceed `data.len()`
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{Archive, ArchiveBuilder};
+    ///
+    /// let mut source = Archive::open("map.SC2Map")?;
+    /// let header_size = source.user_data().map_or(0, |ud| ud.user_data_header_size);
+    /// let mut builder = ArchiveBuilder::new();
+    /// if let Some(data) = source.user_data_bytes()? {
+    ///     builder = builder.user_data(data, header_size);
+    /// }
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn user_data(mut self, data: Vec<u8>, user_data_header_size: u32) -> Self {
+        self.user_data = Some((data, user_data_header_size));
+        self
+    }
+
+    /// Store key-value information about files in a `(metadata)` file
+    ///
+    /// Replaces any `(metadata)` file added before, including one copied by
+    /// [`from_archive`](Self::from_archive). Games ignore the file; read it
+    /// back with [`Archive::metadata`]. Only available with the `metadata`
+    /// feature.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::ArchiveBuilder;
+    /// use mopaq::special_files::Metadata;
+    ///
+    /// let mut metadata = Metadata::new();
+    /// metadata.insert("war3map.j", "author", "Jane Doe");
+    ///
+    /// ArchiveBuilder::new()
+    ///     .add_file("war3map.j", "war3map.j")
+    ///     .metadata(metadata)
+    ///     .build("map.w3x")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    #[cfg(feature = "metadata")]
+    pub fn metadata(self, metadata: impl Into<crate::special_files::Metadata>) -> Self {
+        let name = SpecialFile::Metadata.as_str();
+        self.remove_file(name)
+            .add_file_data(metadata.into().to_bytes(), name)
+    }
+
+    /// Set the number of threads used to compress file sectors
+    ///
+    /// With more than one thread, the sectors of each multi-sector file are
+    /// compressed in parallel while the calling thread writes the results in
+    /// their original order, so the archive is byte-for-byte identical to a
+    /// single-threaded build. `0` uses one thread per CPU core. The default
+    /// is `1`.
+    ///
+    /// Has no effect unless the `parallel` feature is enabled.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::ArchiveBuilder;
+    ///
+    /// ArchiveBuilder::new()
+    ///     .threads(0)
+    ///     .add_file("assets/terrain.blp", "terrain.blp")
+    ///     .build("assets.mpq")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Set what happens to the signature when rebuilding a signed archive
+    ///
+    /// [`from_archive`](Self::from_archive) remembers the source archive's
+    /// weak `(signature)` file and strong signature.
+    /// [`SignaturePolicy::Strip`], the default, leaves both out.
+    /// [`SignaturePolicy::Preserve`] copies them unchanged, so the new
+    /// archive still carries a signature, one that no longer verifies.
+    /// [`SignaturePolicy::Resign`] signs the new archive, which also works
+    /// for archives built from scratch.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{Archive, ArchiveBuilder, SignaturePolicy};
+    /// use mopaq::integrity::{pkcs1::DecodeRsaPrivateKey, RsaPrivateKey};
+    ///
+    /// let pem = std::fs::read_to_string("weak.pem")?;
+    /// let key = RsaPrivateKey::from_pkcs1_pem(&pem).expect("512-bit RSA key");
+    /// let mut archive = Archive::open("patch.mpq")?;
+    /// let builder = ArchiveBuilder::from_archive(&mut archive)?
+    ///     .add_file("build/war3map.j", "war3map.j")
+    ///     .signature_policy(SignaturePolicy::Resign(Box::new(key)));
+    /// drop(archive);
+    /// builder.build("patch.mpq")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn signature_policy(mut self, policy: SignaturePolicy) -> Self {
+        self.signature_policy = policy;
+        self
+    }
+
+    /// Register an observer that receives progress events during `build()`
+    ///
+    /// Only one observer can be registered; a later call replaces the earlier
+    /// one. See [`BuildObserver`] for the available events.
+    pub fn observer<O: BuildObserver + Send + 'static>(mut self, observer: O) -> Self {
+        self.observer = Some(ObserverSlot::new(observer));
+        self
+    }
+
     /// Add a file from disk to the archive
     ///
     /// Reads a file from the filesystem and adds it to the archive with default
@@ -333,7 +1031,8 @@ impl ArchiveBuilder {
             compression: self.default_compression,
             encrypt: false,
             use_fix_key: false,
-            locale: 0, // Neutral locale
+            locale: Locale::NEUTRAL,
+            platform: 0,
         });
         self
     }
@@ -348,7 +1047,7 @@ impl ArchiveBuilder {
     /// - `archive_name`: Name the file will have inside the archive
     /// - `compression`: Compression method from `compression::flags` (0 = no compression)
     /// - `encrypt`: Whether to encrypt the file
-    /// - `locale`: Locale code for the file (0 = neutral locale)
+    /// - `locale`: Locale of the file, a [`Locale`] or its code (0 = neutral)
     ///
     /// # Examples
     /// ```no_run
@@ -370,7 +1069,7 @@ impl ArchiveBuilder {
         archive_name: &str,
         compression: u8,
         encrypt: bool,
-        locale: u16,
+        locale: impl Into<Locale>,
     ) -> Self {
         self.pending_files.push(PendingFile {
             source: FileSource::Path(path.as_ref().to_path_buf()),
@@ -378,7 +1077,8 @@ impl ArchiveBuilder {
             compression,
             encrypt,
             use_fix_key: false,
-            locale,
+            locale: locale.into(),
+            platform: 0,
         });
         self
     }
@@ -415,7 +1115,8 @@ impl ArchiveBuilder {
             compression: self.default_compression,
             encrypt: false,
             use_fix_key: false,
-            locale: 0,
+            locale: Locale::NEUTRAL,
+            platform: 0,
         });
         self
     }
@@ -430,7 +1131,7 @@ impl ArchiveBuilder {
     /// - `archive_name`: Name the file will have inside the archive
     /// - `compression`: Compression method from `compression::flags` (0 = no compression)
     /// - `encrypt`: Whether to encrypt the file
-    /// - `locale`: Locale code for the file (0 = neutral locale)
+    /// - `locale`: Locale of the file, a [`Locale`] or its code (0 = neutral)
     ///
     /// # Examples
     /// ```no_run
@@ -453,7 +1154,7 @@ impl ArchiveBuilder {
         archive_name: &str,
         compression: u8,
         encrypt: bool,
-        locale: u16,
+        locale: impl Into<Locale>,
     ) -> Self {
         self.pending_files.push(PendingFile {
             source: FileSource::Data(data),
@@ -461,7 +1162,8 @@ impl ArchiveBuilder {
             compression,
             encrypt,
             use_fix_key: false,
-            locale,
+            locale: locale.into(),
+            platform: 0,
         });
         self
     }
@@ -473,7 +1175,7 @@ impl ArchiveBuilder {
         archive_name: &str,
         compression: u8,
         use_fix_key: bool,
-        locale: u16,
+        locale: impl Into<Locale>,
     ) -> Self {
         self.pending_files.push(PendingFile {
             source: FileSource::Path(path.as_ref().to_path_buf()),
@@ -481,7 +1183,8 @@ impl ArchiveBuilder {
             compression,
             encrypt: true,
             use_fix_key,
-            locale,
+            locale: locale.into(),
+            platform: 0,
         });
         self
     }
@@ -493,7 +1196,7 @@ impl ArchiveBuilder {
         archive_name: &str,
         compression: u8,
         use_fix_key: bool,
-        locale: u16,
+        locale: impl Into<Locale>,
     ) -> Self {
         self.pending_files.push(PendingFile {
             source: FileSource::Data(data),
@@ -501,18 +1204,260 @@ impl ArchiveBuilder {
             compression,
             encrypt: true,
             use_fix_key,
-            locale,
+            locale: locale.into(),
+            platform: 0,
         });
         self
     }
 
-    /// Calculate optimal hash table size based on file count
-    fn calculate_hash_table_size(&self) -> u32 {
-        let file_count = self.pending_files.len()
-            + match &self.listfile_option {
-                ListfileOption::Generate | ListfileOption::External(_) => 1,
-                ListfileOption::None => 0,
-            };
+    /// Add file data with an explicit locale and platform code
+    ///
+    /// Uses the builder's default compression method and no encryption. The
+    /// platform code is written according to the builder's
+    /// [`platform_policy()`](Self::platform_policy).
+    ///
+    /// # Parameters
+    /// - `data`: Raw file data to store in the archive
+    /// - `archive_name`: Name the file will have inside the archive
+    /// - `locale`: Locale of the file, a [`Locale`] or its code (0 = neutral)
+    /// - `platform`: Platform code for the hash table entry
+    pub fn add_file_data_with_platform(
+        mut self,
+        data: Vec<u8>,
+        archive_name: &str,
+        locale: impl Into<Locale>,
+        platform: u16,
+    ) -> Self {
+        self.pending_files.push(PendingFile {
+            source: FileSource::Data(data),
+            archive_name: archive_name.to_string(),
+            compression: self.default_compression,
+            encrypt: false,
+            use_fix_key: false,
+            locale: locale.into(),
+            platform,
+        });
+        self
+    }
+
+    /// Copy a file from an existing archive
+    ///
+    /// The file is read and decompressed immediately. Its encryption settings,
+    /// locale and platform code are carried over, while the builder's default
+    /// compression method is used for the new copy.
+    ///
+    /// # Parameters
+    /// - `archive`: Source archive
+    /// - `name`: Name of the file in the source archive (kept in the new archive)
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if the file does not exist in `archive`
+    /// - Any error returned while reading the file
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{Archive, ArchiveBuilder, PlatformPolicy};
+    ///
+    /// let mut source = Archive::open("source.mpq")?;
+    /// ArchiveBuilder::new()
+    ///     .platform_policy(PlatformPolicy::Preserve)
+    ///     .add_file_from_archive(&mut source, "data/file.txt")?
+    ///     .build("copy.mpq")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn add_file_from_archive(self, archive: &mut Archive, name: &str) -> Result<Self> {
+        let info = archive
+            .find_file(name)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+        let data = archive.read_file(name)?;
+        Ok(self.add_copied_file(name, data, &info))
+    }
+
+    /// Copy the version of a file stored for `locale` from an existing archive
+    ///
+    /// Like [`add_file_from_archive`](Self::add_file_from_archive), for
+    /// archives that hold the file in several locales. Falls back to the
+    /// neutral version like [`Archive::find_file_with_locale`].
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if the file has neither a version for
+    ///   `locale` nor a neutral one
+    /// - Any error returned while reading the file
+    pub fn add_file_from_archive_with_locale(
+        self,
+        archive: &mut Archive,
+        name: &str,
+        locale: impl Into<Locale>,
+    ) -> Result<Self> {
+        let info = archive
+            .find_file_with_locale(name, locale)?
+            .ok_or_else(|| Error::FileNotFound(name.to_string()))?;
+        let data = archive.read_file_with_locale(name, info.locale)?;
+        Ok(self.add_copied_file(name, data, &info))
+    }
+
+    /// Queue a file read from another archive, keeping its settings
+    fn add_copied_file(mut self, name: &str, data: Vec<u8>, info: &FileInfo) -> Self {
+        self.pending_files.push(PendingFile {
+            source: FileSource::Data(data),
+            archive_name: name.to_string(),
+            compression: self.default_compression,
+            encrypt: info.is_encrypted(),
+            use_fix_key: info.has_fix_key(),
+            locale: info.locale,
+            platform: info.platform,
+        });
+        self
+    }
+
+    /// Start a builder pre-populated with the contents of an existing archive
+    ///
+    /// Copies the format version, sector size, user data and every file named
+    /// in the archive's `(listfile)`, in each locale it is stored in, which
+    /// makes this the starting point for
+    /// updating an archive: remove or replace entries with
+    /// [`remove_file`](Self::remove_file) and the `add_*` methods, then build
+    /// over the original path. `build()` writes to a temporary file first, so
+    /// the original is only replaced once the new archive is complete.
+    ///
+    /// `(listfile)` is regenerated rather than copied, and `(attributes)` is
+    /// dropped because it would no longer match the rebuilt contents. The
+    /// signature is dropped too unless
+    /// [`signature_policy`](Self::signature_policy) says otherwise. To change
+    /// a few files while keeping everything else as it is, including files
+    /// the listfile does not name, use
+    /// [`MutableArchive`](crate::MutableArchive) instead.
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if the archive has no `(listfile)`, or the
+    ///   listfile does not name every file, since unnamed files cannot be
+    ///   carried over
+    /// - Any error returned while reading a file
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::{Archive, ArchiveBuilder};
+    ///
+    /// let mut archive = Archive::open("map.mpq")?;
+    /// let builder = ArchiveBuilder::from_archive(&mut archive)?
+    ///     .remove_file("scripts/war3map.j")
+    ///     .add_file("build/war3map.j", "scripts/war3map.j");
+    /// drop(archive);
+    /// builder.build("map.mpq")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn from_archive(archive: &mut Archive) -> Result<Self> {
+        if archive.find_file("(listfile)")?.is_none() {
+            return Err(Error::invalid_format(
+                "archive has no (listfile); its files cannot be enumerated for copying",
+            ));
+        }
+
+        let header = archive.header();
+        let mut builder = Self::new()
+            .version(header.format_version)
+            .block_size(header.block_size)
+            .name_hashing(archive.name_hashing());
+
+        let user_data_header_size = archive.user_data().map(|ud| ud.user_data_header_size);
+        if let (Some(header_size), Some(data)) = (user_data_header_size, archive.user_data_bytes()?)
+        {
+            builder = builder.user_data(data, header_size);
+        }
+
+        if archive.find_file("(signature)")?.is_some() {
+            builder.source_signature.weak = Some(archive.read_file("(signature)")?);
+        }
+        builder.source_signature.strong = archive.strong_signature_data()?;
+
+        // Every block has to be reachable by name, or the rebuild would
+        // silently lose it
+        let unnamed = archive
+            .anonymous_entries()?
+            .iter()
+            .map(|entry| entry.block_index)
+            .collect::<HashSet<_>>()
+            .len();
+        if unnamed > 0 {
+            return Err(Error::invalid_format(format!(
+                "{} files are not named in the (listfile) and would be lost",
+                unnamed
+            )));
+        }
+
+        for entry in archive.list()? {
+            if SpecialFile::from_name(&entry.name).is_some_and(SpecialFile::is_derived) {
+                continue;
+            }
+            for locale in archive.file_locales(&entry.name)? {
+                builder =
+                    builder.add_file_from_archive_with_locale(archive, &entry.name, locale)?;
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Remove a pending file from the archive being built, in every locale
+    ///
+    /// Names are compared the way they are hashed, by default
+    /// case-insensitively and treating `/` and `\` as the same separator;
+    /// see [`name_hashing()`](Self::name_hashing). Removing a name that was
+    /// never added is not an error.
+    pub fn remove_file(mut self, archive_name: &str) -> Self {
+        let policy = self.name_hashing;
+        let target = policy.normalize_name(archive_name);
+        self.pending_files
+            .retain(|file| policy.normalize_name(&file.archive_name) != target);
+        self
+    }
+
+    /// Remove the version of a pending file stored for `locale`
+    ///
+    /// Versions of the file in other locales are kept. Names are compared
+    /// like in [`remove_file`](Self::remove_file).
+    pub fn remove_file_with_locale(
+        mut self,
+        archive_name: &str,
+        locale: impl Into<Locale>,
+    ) -> Self {
+        let policy = self.name_hashing;
+        let target = policy.normalize_name(archive_name);
+        let locale = locale.into();
+        self.pending_files.retain(|file| {
+            file.locale != locale || policy.normalize_name(&file.archive_name) != target
+        });
+        self
+    }
+
+    /// Deliver an event to the registered observer, if any
+    fn notify(&self, event: impl FnOnce(&mut dyn BuildObserver)) {
+        if let Some(slot) = &self.observer {
+            event(slot.0.borrow_mut().as_mut());
+        }
+    }
+
+    /// Record a written file and report it to the observer
+    fn file_done(&self, file: BuiltFile, started: Instant) {
+        self.notify(|o| o.on_file_done(&file.name, file.compressed_size, file.ratio()));
+        let mut log = self.build_log.borrow_mut();
+        log.file_time += started.elapsed();
+        log.files.push(file);
+    }
+
+    /// Record a written table and report it to the observer
+    fn notify_table_write(&self, table: BuildTable, size: u64) {
+        self.build_log.borrow_mut().tables.push((table, size));
+        self.notify(|o| o.on_table_write(table, size));
+    }
+
+    /// Calculate optimal hash table size based on file count
+    fn calculate_hash_table_size(&self) -> u32 {
+        let file_count = self.pending_files.len()
+            + match &self.listfile_option {
+                ListfileOption::Generate | ListfileOption::External(_) => 1,
+                ListfileOption::None => 0,
+            };
 
         // Use 2x the file count for good performance, minimum 16
         let optimal_size = (file_count * 2).max(16) as u32;
@@ -522,23 +1467,77 @@ impl ArchiveBuilder {
     }
 
     /// Build the archive and write to the specified path
-    pub fn build<P: AsRef<Path>>(mut self, path: P) -> Result<()> {
-        let path = path.as_ref();
+    ///
+    /// Returns a [`BuildSummary`] with the size and flags of every written
+    /// file, the table sizes and how long the build took.
+    ///
+    /// # Errors
+    /// `Error::InvalidHeader` if the block size is above
+    /// [`MAX_BLOCK_SIZE`](crate::MAX_BLOCK_SIZE).
+    pub fn build<P: AsRef<Path>>(mut self, path: P) -> Result<BuildSummary> {
+        let started = Instant::now();
+        let written = self.write_archive_file(path.as_ref());
+        let file_count = self.pending_files.len();
+        match &written {
+            Ok(archive_size) => self.notify(|o| o.on_finish(*archive_size, file_count, None)),
+            Err(e) => self.notify(|o| o.on_finish(0, file_count, Some(e))),
+        }
+        let archive_size = written?;
+
+        let log = self.build_log.into_inner();
+        Ok(BuildSummary {
+            version: self.version,
+            files: log.files,
+            tables: log.tables,
+            archive_size,
+            file_time: log.file_time,
+            table_time: log.table_time,
+            total_time: started.elapsed(),
+        })
+    }
+
+    /// Write the archive to a temporary file next to `path` and move it
+    /// into place, returning the archive's size
+    fn write_archive_file(&mut self, path: &Path) -> Result<u64> {
+        crate::io::check_device_path(path)?;
+        if self.block_size > crate::MAX_BLOCK_SIZE {
+            return Err(Error::InvalidHeader(format!(
+                "Block size {} exceeds {}",
+                self.block_size,
+                crate::MAX_BLOCK_SIZE
+            )));
+        }
+        self.check_compatibility()?;
 
         // Create a temporary file in the same directory
         let mut temp_file = NamedTempFile::new_in(path.parent().unwrap_or_else(|| Path::new(".")))?;
 
         // Add listfile if needed
         self.prepare_listfile()?;
+        self.prepare_signature()?;
+
+        #[cfg(feature = "parallel")]
+        if self.threads != 1 {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(self.threads)
+                .build()
+                .map_err(std::io::Error::other)?;
+            self.thread_pool = Some(pool);
+        }
 
         // Write the archive directly to the temp file
         {
             let file = temp_file.as_file_mut();
             use std::io::{Seek as _, Write as _};
 
-            // For v3+ archives that need read-back support, we need to write everything
-            // to a buffer first, then copy to file
-            if self.version >= FormatVersion::V3 {
+            if let Some((data, user_data_header_size)) = &self.user_data {
+                self.write_user_data(file, data, *user_data_header_size)?;
+            }
+
+            // Archives with user data are written to a buffer first and then
+            // copied to the file, since all offsets are relative to the MPQ
+            // header.
+            if self.user_data.is_some() {
                 // Pre-allocate buffer with header space
                 let header_size = self.version.header_size() as usize;
                 let vec = vec![0u8; header_size];
@@ -551,25 +1550,224 @@ impl ArchiveBuilder {
                 file.write_all(buffer.get_ref())?;
                 file.flush()?;
             } else {
-                // For v1/v2, we can write directly
                 self.write_archive(file)?;
                 file.flush()?;
             }
         }
 
+        self.write_signature(temp_file.as_file(), temp_file.path())?;
+        let archive_size = temp_file.as_file().metadata()?.len();
+
         // Atomically rename temp file to final destination
         temp_file.persist(path).map_err(|e| Error::Io(e.error))?;
+        Ok(archive_size)
+    }
+
+    /// Compute the resulting archive layout without writing anything
+    ///
+    /// Each file is measured and a sample from its start (up to 256 KiB) is
+    /// compressed to estimate its stored size. Generated files such as the
+    /// listfile are included, so the plan lists the same files `build()`
+    /// would write.
+    ///
+    /// # Errors
+    /// Returns `Error::Io` if a source file cannot be read.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use mopaq::ArchiveBuilder;
+    ///
+    /// let builder = ArchiveBuilder::new().add_file("big.dat", "big.dat");
+    /// let plan = builder.plan()?;
+    /// if plan.exceeds_format_limit() {
+    ///     eprintln!("archive too large for MPQ v1");
+    /// }
+    /// println!("~{} bytes", plan.estimated_archive_size);
+    /// builder.build("out.mpq")?;
+    /// # Ok::<(), mopaq::Error>(())
+    /// ```
+    pub fn plan(&self) -> Result<BuildPlan> {
+        let sector_size = crate::calculate_sector_size(self.block_size);
+        let listfile = self.listfile_data()?;
+
+        let mut files = Vec::with_capacity(self.pending_files.len() + 1);
+        for pending_file in &self.pending_files {
+            let (file_size, sample) = match &pending_file.source {
+                FileSource::Path(path) => {
+                    let file = fs::File::open(path)?;
+                    let file_size = file.metadata()?.len();
+                    let mut sample = Vec::new();
+                    file.take(PLAN_SAMPLE_SIZE as u64)
+                        .read_to_end(&mut sample)?;
+                    (file_size, sample)
+                }
+                FileSource::Data(data) => (
+                    data.len() as u64,
+                    data[..data.len().min(PLAN_SAMPLE_SIZE)].to_vec(),
+                ),
+            };
+
+            files.push(self.plan_file(
+                &pending_file.archive_name,
+                file_size,
+                &sample,
+                pending_file.compression,
+                pending_file.encrypt,
+                sector_size,
+            )?);
+        }
+        if let Some(data) = &listfile {
+            files.push(self.plan_file(
+                "(listfile)",
+                data.len() as u64,
+                data,
+                self.default_compression,
+                false,
+                sector_size,
+            )?);
+        }
+
+        let hash_table_entries = self.calculate_hash_table_size();
+        let block_table_entries = files.len() as u32;
+
+        let user_data_size = self.user_data.as_ref().map_or(0, |(data, _)| {
+            let unaligned = crate::header::UserDataHeader::SIZE as u64 + data.len() as u64;
+            unaligned.div_ceil(crate::header::HEADER_ALIGNMENT) * crate::header::HEADER_ALIGNMENT
+        });
+        let files_size: u64 = files.iter().map(|f| f.estimated_size).sum();
+        let data_end = self.version.header_size() as u64 + files_size;
+
+        let mut table_size = 0;
+        if self.writes_classic_tables() {
+            table_size += (hash_table_entries as u64 + block_table_entries as u64) * 16;
+            if self.version >= FormatVersion::V2 && data_end > u32::MAX as u64 {
+                table_size += block_table_entries as u64 * 2;
+            }
+        }
+        if self.version >= FormatVersion::V3 {
+            // Approximate file entries give the BET table its bit widths
+            let mut bet_files = Vec::with_capacity(files.len());
+            let mut file_pos = self.version.header_size() as u64;
+            for file in &files {
+                bet_files.push(BetFileInfo {
+                    file_pos,
+                    file_size: file.file_size,
+                    compressed_size: file.estimated_size,
+                    flags: BlockEntry::FLAG_EXISTS | BlockEntry::FLAG_COMPRESS,
+                });
+                file_pos += file.estimated_size;
+            }
+
+            let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+            let (het_data, _) = self.create_het_table(&names)?;
+            let (bet_data, _) = self.create_bet_table(&bet_files)?;
+            table_size += (het_data.len() + bet_data.len()) as u64;
+        }
+
+        Ok(BuildPlan {
+            version: self.version,
+            sector_size,
+            total_file_size: files.iter().map(|f| f.file_size).sum(),
+            files,
+            hash_table_entries,
+            block_table_entries,
+            table_size,
+            estimated_archive_size: user_data_size + data_end + table_size,
+        })
+    }
+
+    /// Estimate the stored size of one file from a sample of its data
+    fn plan_file(
+        &self,
+        name: &str,
+        file_size: u64,
+        sample: &[u8],
+        compression: u8,
+        encrypted: bool,
+        sector_size: usize,
+    ) -> Result<PlannedFile> {
+        // Compress the sample sector by sector, as write_file() would
+        let stored_sample: usize = if compression != 0 {
+            let mut total = 0;
+            for chunk in sample.chunks(sector_size) {
+                total += compress(chunk, compression)?.len();
+            }
+            total
+        } else {
+            sample.len()
+        };
+
+        let mut estimated_size = if sample.is_empty() {
+            0
+        } else {
+            (file_size as f64 * stored_sample as f64 / sample.len() as f64).ceil() as u64
+        };
+
+        if file_size > sector_size as u64 {
+            let sector_count = file_size.div_ceil(sector_size as u64);
+            estimated_size += (sector_count + 1) * 4;
+            if self.generate_crcs {
+                estimated_size += sector_count * 4;
+            }
+        } else if self.generate_crcs {
+            estimated_size += 4;
+        }
+
+        Ok(PlannedFile {
+            name: name.to_string(),
+            file_size,
+            compression,
+            encrypted,
+            estimated_size,
+        })
+    }
+
+    /// Write the user data header and data, padded to the MPQ header position
+    fn write_user_data<W: Write>(
+        &self,
+        writer: &mut W,
+        data: &[u8],
+        user_data_header_size: u32,
+    ) -> Result<()> {
+        use crate::header::{UserDataHeader, HEADER_ALIGNMENT};
+
+        if user_data_header_size as usize > data.len() {
+            return Err(Error::InvalidHeader(format!(
+                "User data header size {} exceeds user data size {}",
+                user_data_header_size,
+                data.len()
+            )));
+        }
+
+        let data_size = u32::try_from(data.len())
+            .map_err(|_| Error::InvalidHeader("User data too large".to_string()))?;
+        let unaligned = UserDataHeader::SIZE as u64 + data_size as u64;
+        let header_offset = unaligned.div_ceil(HEADER_ALIGNMENT) * HEADER_ALIGNMENT;
+
+        let header = UserDataHeader {
+            user_data_size: data_size,
+            header_offset: header_offset as u32,
+            user_data_header_size,
+        };
+        mpq_header::write_user_data_header(&header, writer)?;
+        writer.write_all(data)?;
+        writer.write_all(&vec![0u8; (header_offset - unaligned) as usize])?;
 
         Ok(())
     }
 
-    /// Prepare the listfile based on the option
-    fn prepare_listfile(&mut self) -> Result<()> {
+    /// Listfile content according to the listfile option, if any
+    fn listfile_data(&self) -> Result<Option<Vec<u8>>> {
         match &self.listfile_option {
             ListfileOption::Generate => {
-                // Generate listfile content from pending files
+                // Generate listfile content from pending files, naming
+                // files stored in several locales once
                 let mut content = String::new();
+                let mut listed = HashSet::new();
                 for file in &self.pending_files {
+                    if !listed.insert(self.name_hashing.normalize_name(&file.archive_name)) {
+                        continue;
+                    }
                     content.push_str(&file.archive_name);
                     content.push('\r');
                     content.push('\n');
@@ -578,34 +1776,149 @@ impl ArchiveBuilder {
                 // Add the listfile itself
                 content.push_str("(listfile)\r\n");
 
-                self.pending_files.push(PendingFile {
-                    source: FileSource::Data(content.into_bytes()),
-                    archive_name: "(listfile)".to_string(),
-                    compression: self.default_compression,
-                    encrypt: false,
-                    use_fix_key: false,
-                    locale: 0,
-                });
+                Ok(Some(content.into_bytes()))
             }
-            ListfileOption::External(path) => {
-                // Read external listfile
-                let data = fs::read(path)?;
-
-                self.pending_files.push(PendingFile {
-                    source: FileSource::Data(data),
-                    archive_name: "(listfile)".to_string(),
-                    compression: self.default_compression,
-                    encrypt: false,
-                    use_fix_key: false,
-                    locale: 0,
-                });
+            // Read external listfile
+            ListfileOption::External(path) => Ok(Some(fs::read(path)?)),
+            ListfileOption::None => Ok(None),
+        }
+    }
+
+    /// Reject settings the target set with `compatibility()` cannot read
+    fn check_compatibility(&self) -> Result<()> {
+        let Some(target) = self.compatibility else {
+            return Ok(());
+        };
+
+        if self.version > target.max_version() {
+            return Err(Error::incompatible(
+                target,
+                format!(
+                    "format version {} is newer than {}",
+                    self.version as u16 + 1,
+                    target.max_version() as u16 + 1
+                ),
+            ));
+        }
+        if let Some(max) = target.max_block_size() {
+            if self.block_size > max {
+                return Err(Error::incompatible(
+                    target,
+                    format!("block size {} exceeds {}", self.block_size, max),
+                ));
+            }
+        }
+
+        let listfile = match self.listfile_option {
+            ListfileOption::None => None,
+            _ => Some(("(listfile)", self.default_compression)),
+        };
+        let files = self
+            .pending_files
+            .iter()
+            .map(|file| (file.archive_name.as_str(), file.compression))
+            .chain(listfile);
+        for (name, compression) in files {
+            if !target.supports_compression(compression) {
+                return Err(Error::incompatible(
+                    target,
+                    format!("{} uses compression 0x{:02X}", name, compression),
+                ));
             }
-            ListfileOption::None => {}
+        }
+
+        if !self.writes_classic_tables() {
+            return Err(Error::incompatible(
+                target,
+                "no classic hash and block tables",
+            ));
+        }
+
+        if self.name_hashing != NameHashingPolicy::BLIZZARD {
+            return Err(Error::incompatible(target, "non-standard name hashing"));
+        }
+
+        if self.compress_tables
+            && self.version >= FormatVersion::V3
+            && !target.supports_compression(self.table_compression)
+        {
+            return Err(Error::incompatible(
+                target,
+                format!("table compression 0x{:02X}", self.table_compression),
+            ));
         }
 
         Ok(())
     }
 
+    /// Whether the archive gets classic hash and block tables
+    fn writes_classic_tables(&self) -> bool {
+        self.classic_tables || self.version < FormatVersion::V3
+    }
+
+    /// Prepare the listfile based on the option
+    fn prepare_listfile(&mut self) -> Result<()> {
+        if let Some(data) = self.listfile_data()? {
+            self.pending_files.push(PendingFile {
+                source: FileSource::Data(data),
+                archive_name: "(listfile)".to_string(),
+                compression: self.default_compression,
+                encrypt: false,
+                use_fix_key: false,
+                locale: Locale::NEUTRAL,
+                platform: 0,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Add the `(signature)` file the signature policy calls for
+    ///
+    /// Re-signing with a weak key reserves a zeroed file that
+    /// [`write_signature`](Self::write_signature) fills in. The file is not
+    /// added to the listfile, and is stored uncompressed and unencrypted so
+    /// that the signature sits at a known position.
+    fn prepare_signature(&mut self) -> Result<()> {
+        let data = match &self.signature_policy {
+            SignaturePolicy::Strip => None,
+            SignaturePolicy::Preserve => self.source_signature.weak.clone(),
+            SignaturePolicy::Resign(key) => (SignatureType::for_key(key)? == SignatureType::Weak)
+                .then(|| vec![0; WEAK_SIGNATURE_FILE_SIZE]),
+        };
+
+        if let Some(data) = data {
+            self.pending_files.push(PendingFile {
+                source: FileSource::Data(data),
+                archive_name: "(signature)".to_string(),
+                compression: 0,
+                encrypt: false,
+                use_fix_key: false,
+                locale: Locale::NEUTRAL,
+                platform: 0,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Sign the written archive, or append the preserved strong signature
+    fn write_signature(&self, mut file: &fs::File, path: &Path) -> Result<()> {
+        match &self.signature_policy {
+            SignaturePolicy::Strip => Ok(()),
+            SignaturePolicy::Preserve => {
+                if let Some(strong) = &self.source_signature.strong {
+                    file.seek(SeekFrom::End(0))?;
+                    file.write_all(strong)?;
+                }
+                Ok(())
+            }
+            SignaturePolicy::Resign(key) => {
+                crate::modification::sign_archive(path, key, self.name_hashing)
+            }
+        }
+    }
+
     /// Write the complete archive
     fn write_archive<W: Write + Seek + Read>(&self, writer: &mut W) -> Result<()> {
         // For v3+, we should create HET/BET tables instead of/in addition to hash/block
@@ -636,45 +1949,29 @@ impl ArchiveBuilder {
 
         // Write all files and populate tables
         for (block_index, pending_file) in self.pending_files.iter().enumerate() {
-            let file_pos = writer.stream_position()?;
-
-            // Read file data
-            let file_data = match &pending_file.source {
-                FileSource::Path(path) => fs::read(path)?,
-                FileSource::Data(data) => data.clone(),
-            };
-
-            // Write file and get sizes
-            let params = FileWriteParams {
-                file_data: &file_data,
-                archive_name: &pending_file.archive_name,
-                compression: pending_file.compression,
-                encrypt: pending_file.encrypt,
-                use_fix_key: pending_file.use_fix_key,
-                sector_size,
-                file_pos,
-            };
-            let (compressed_size, flags) = self.write_file(writer, &params)?;
+            let file = self.write_pending_file(writer, block_index, pending_file, sector_size)?;
 
             // Add to hash table
             self.add_to_hash_table(
                 &mut hash_table,
                 &pending_file.archive_name,
                 block_index as u32,
-                pending_file.locale,
+                pending_file.locale.code(),
+                self.platform_policy
+                    .resolve(&pending_file.archive_name, pending_file.platform)?,
             )?;
 
             // Add to block table and hi-block table if needed
             let block_entry = BlockEntry {
-                file_pos: file_pos as u32, // Low 32 bits
-                compressed_size: compressed_size as u32,
-                file_size: file_data.len() as u32,
-                flags: flags | BlockEntry::FLAG_EXISTS,
+                file_pos: file.file_pos as u32, // Low 32 bits
+                compressed_size: file.compressed_size as u32,
+                file_size: file.file_size as u32,
+                flags: file.flags,
             };
 
             // Store high 16 bits in hi-block table if needed
             if let Some(ref mut hi_table) = hi_block_table {
-                let high_bits = (file_pos >> 32) as u16;
+                let high_bits = (file.file_pos >> 32) as u16;
                 hi_table.set(block_index, high_bits);
             }
 
@@ -686,6 +1983,8 @@ impl ArchiveBuilder {
             }
         }
 
+        let tables_started = Instant::now();
+
         // Write hash table
         let hash_table_pos = writer.stream_position()?;
         self.write_hash_table(writer, &hash_table)?;
@@ -727,7 +2026,7 @@ impl ArchiveBuilder {
         };
         self.write_header(writer, &header_params)?;
 
-        // TODO: For V4, implement proper MD5 calculation
+        self.build_log.borrow_mut().table_time = tables_started.elapsed();
 
         Ok(())
     }
@@ -747,39 +2046,24 @@ impl ArchiveBuilder {
         let mut block_table = BlockTable::new(block_table_size as usize)?;
         let mut hi_block_table = Some(HiBlockTable::new(block_table_size as usize));
 
-        // Write all files and populate block table
+        // Write all files and populate block table. The block table keeps
+        // the low 32 bits of sizes; the BET table gets all of them.
+        let mut bet_files = Vec::with_capacity(self.pending_files.len());
         for (block_index, pending_file) in self.pending_files.iter().enumerate() {
-            let file_pos = writer.stream_position()?;
-
-            // Read file data
-            let file_data = match &pending_file.source {
-                FileSource::Path(path) => fs::read(path)?,
-                FileSource::Data(data) => data.clone(),
-            };
-
-            // Write file and get sizes
-            let params = FileWriteParams {
-                file_data: &file_data,
-                archive_name: &pending_file.archive_name,
-                compression: pending_file.compression,
-                encrypt: pending_file.encrypt,
-                use_fix_key: pending_file.use_fix_key,
-                sector_size,
-                file_pos,
-            };
-            let (compressed_size, flags) = self.write_file(writer, &params)?;
+            let file = self.write_pending_file(writer, block_index, pending_file, sector_size)?;
 
             // Add to block table
             let block_entry = BlockEntry {
-                file_pos: file_pos as u32, // Low 32 bits
-                compressed_size: compressed_size as u32,
-                file_size: file_data.len() as u32,
-                flags: flags | BlockEntry::FLAG_EXISTS,
+                file_pos: file.file_pos as u32, // Low 32 bits
+                compressed_size: file.compressed_size as u32,
+                file_size: file.file_size as u32,
+                flags: file.flags,
             };
+            bet_files.push(file);
 
             // Store high 16 bits in hi-block table
             if let Some(ref mut hi_table) = hi_block_table {
-                let high_bits = (file_pos >> 32) as u16;
+                let high_bits = (file.file_pos >> 32) as u16;
                 hi_table.set(block_index, high_bits);
             }
 
@@ -791,49 +2075,28 @@ impl ArchiveBuilder {
             }
         }
 
+        let tables_started = Instant::now();
+
         // Create HET table
         let het_table_pos = writer.stream_position()?;
-        let (het_data, _het_header) = self.create_het_table()?;
-        let (het_table_size, het_table_md5) = self.write_het_table(writer, &het_data, true)?;
+        let names: Vec<&str> = self
+            .pending_files
+            .iter()
+            .map(|f| f.archive_name.as_str())
+            .collect();
+        let (het_data, _het_header) = self.create_het_table(&names)?;
+        let (het_table_size, het_table_md5) = self.write_het_table(writer, &het_data)?;
 
         // Create BET table
         let bet_table_pos = writer.stream_position()?;
-        let (bet_data, _bet_header) = self.create_bet_table(&block_table)?;
-        let (bet_table_size, bet_table_md5) = self.write_bet_table(writer, &bet_data, true)?;
-
-        // For compatibility, also write classic tables
-        let hash_table_size = self.calculate_hash_table_size();
-        let mut hash_table = HashTable::new(hash_table_size as usize)?;
-
-        // Populate hash table
-        for (block_index, pending_file) in self.pending_files.iter().enumerate() {
-            self.add_to_hash_table(
-                &mut hash_table,
-                &pending_file.archive_name,
-                block_index as u32,
-                pending_file.locale,
-            )?;
-        }
+        let (bet_data, _bet_header) = self.create_bet_table(&bet_files)?;
+        let (bet_table_size, bet_table_md5) = self.write_bet_table(writer, &bet_data)?;
 
-        // Write hash table
-        let hash_table_pos = writer.stream_position()?;
-        let hash_table_md5 = self.write_hash_table(writer, &hash_table)?;
-
-        // Write block table
-        let block_table_pos = writer.stream_position()?;
-        let block_table_md5 = self.write_block_table(writer, &block_table)?;
-
-        // Write hi-block table if needed
-        let (hi_block_table_pos, hi_block_table_md5) = if let Some(ref hi_table) = hi_block_table {
-            if hi_table.is_needed() {
-                let pos = writer.stream_position()?;
-                let md5 = self.write_hi_block_table(writer, hi_table)?;
-                (Some(pos), md5)
-            } else {
-                (None, [0u8; 16])
-            }
+        // For compatibility, also write classic tables unless disabled
+        let classic = if self.classic_tables {
+            self.write_classic_tables(writer, &block_table, &hi_block_table)?
         } else {
-            (None, [0u8; 16])
+            ClassicTables::default()
         };
 
         // Calculate archive size
@@ -848,22 +2111,22 @@ impl ArchiveBuilder {
         // For V4, we need to use the MD5 checksums calculated during table writes
         let v4_data = if self.version == FormatVersion::V4 {
             Some(MpqHeaderV4Data {
-                hash_table_size_64: hash_table_size as u64 * 16, // 16 bytes per hash entry
-                block_table_size_64: self.pending_files.len() as u64 * 16, // 16 bytes per block entry
+                hash_table_size_64: classic.hash_table_size as u64 * 16, // 16 bytes per hash entry
+                block_table_size_64: classic.block_table_size as u64 * 16, // 16 bytes per block entry
                 hi_block_table_size_64: if hi_block_table.is_some() {
-                    self.pending_files.len() as u64 * 2 // 2 bytes per hi-block entry
+                    classic.block_table_size as u64 * 2 // 2 bytes per hi-block entry
                 } else {
                     0
                 },
                 het_table_size_64: het_table_size,
                 bet_table_size_64: bet_table_size,
                 raw_chunk_size: 0x4000, // 16KB default as per StormLib
-                md5_block_table: block_table_md5,
-                md5_hash_table: hash_table_md5,
-                md5_hi_block_table: hi_block_table_md5,
+                md5_block_table: classic.block_table_md5,
+                md5_hash_table: classic.hash_table_md5,
+                md5_hi_block_table: classic.hi_block_table_md5,
                 md5_bet_table: bet_table_md5,
                 md5_het_table: het_table_md5,
-                md5_mpq_header: [0u8; 16], // Will be calculated after header write
+                md5_mpq_header: [0u8; 16], // Calculated when the header is written
             })
         } else {
             None
@@ -871,11 +2134,11 @@ impl ArchiveBuilder {
 
         let header_params = HeaderWriteParams {
             archive_size,
-            hash_table_pos,
-            block_table_pos,
-            hash_table_size,
-            block_table_size: self.pending_files.len() as u32,
-            hi_block_table_pos,
+            hash_table_pos: classic.hash_table_pos,
+            block_table_pos: classic.block_table_pos,
+            hash_table_size: classic.hash_table_size,
+            block_table_size: classic.block_table_size,
+            hi_block_table_pos: classic.hi_block_table_pos,
             het_table_pos: Some(het_table_pos),
             bet_table_pos: Some(bet_table_pos),
             _het_table_size: Some(het_table_size),
@@ -883,230 +2146,476 @@ impl ArchiveBuilder {
             v4_data,
         };
 
-        // Write header
-        self.write_header(writer, &header_params)?;
+        // Write header
+        self.write_header(writer, &header_params)?;
+        self.build_log.borrow_mut().table_time = tables_started.elapsed();
+
+        Ok(())
+    }
+
+    /// Write the classic hash, block and hi-block tables of a v3+ archive
+    fn write_classic_tables<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        block_table: &BlockTable,
+        hi_block_table: &Option<HiBlockTable>,
+    ) -> Result<ClassicTables> {
+        let hash_table_size = self.calculate_hash_table_size();
+        let mut hash_table = HashTable::new(hash_table_size as usize)?;
+
+        // Populate hash table
+        for (block_index, pending_file) in self.pending_files.iter().enumerate() {
+            self.add_to_hash_table(
+                &mut hash_table,
+                &pending_file.archive_name,
+                block_index as u32,
+                pending_file.locale.code(),
+                self.platform_policy
+                    .resolve(&pending_file.archive_name, pending_file.platform)?,
+            )?;
+        }
+
+        // Write hash table
+        let hash_table_pos = writer.stream_position()?;
+        let hash_table_md5 = self.write_hash_table(writer, &hash_table)?;
+
+        // Write block table
+        let block_table_pos = writer.stream_position()?;
+        let block_table_md5 = self.write_block_table(writer, block_table)?;
+
+        // Write hi-block table if needed
+        let (hi_block_table_pos, hi_block_table_md5) = match hi_block_table {
+            Some(hi_table) if hi_table.is_needed() => {
+                let pos = writer.stream_position()?;
+                let md5 = self.write_hi_block_table(writer, hi_table)?;
+                (Some(pos), md5)
+            }
+            _ => (None, [0u8; 16]),
+        };
+
+        Ok(ClassicTables {
+            hash_table_pos,
+            hash_table_size,
+            hash_table_md5,
+            block_table_pos,
+            block_table_size: self.pending_files.len() as u32,
+            block_table_md5,
+            hi_block_table_pos,
+            hi_block_table_md5,
+        })
+    }
+
+    /// Write one pending file at the writer's position
+    ///
+    /// Returns where the file went and how big it is, with `FLAG_EXISTS`
+    /// set. Files that are stored without compression or checksums are
+    /// copied from disk sector by sector, so they never have to fit in
+    /// memory; everything else is read whole.
+    ///
+    /// # Errors
+    /// `Error::CapacityExceeded` for files larger than 4 GiB in v1 and v2
+    /// archives, whose tables only hold 32-bit sizes
+    fn write_pending_file<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        block_index: usize,
+        pending_file: &PendingFile,
+        sector_size: usize,
+    ) -> Result<BetFileInfo> {
+        let file_pos = writer.stream_position()?;
+        self.notify(|o| {
+            o.on_file_start(
+                &pending_file.archive_name,
+                block_index,
+                self.pending_files.len(),
+            )
+        });
+
+        let file_size = pending_file.size()?;
+        if file_size > u32::MAX as u64 && self.version < FormatVersion::V3 {
+            return Err(Error::CapacityExceeded(format!(
+                "{} is larger than 4 GiB, which needs a v3 or later archive",
+                pending_file.archive_name
+            )));
+        }
+
+        let params = FileWriteParams {
+            file_data: &[],
+            file_size,
+            archive_name: &pending_file.archive_name,
+            compression: pending_file.compression,
+            compression_next: pending_file.compression,
+            encrypt: pending_file.encrypt,
+            use_fix_key: pending_file.use_fix_key,
+            sector_size,
+            single_unit: false,
+            file_pos,
+        };
+        let layout = FileLayout::choose(
+            file_size,
+            sector_size,
+            params.compressed(),
+            self.generate_crcs,
+            params.single_unit,
+        );
+        let started = Instant::now();
+        let (file_size, (compressed_size, flags)) = match (&pending_file.source, layout) {
+            (FileSource::Path(path), FileLayout::Stored) => {
+                let flags = self.file_flags(layout, &params);
+                let source = std::io::BufReader::new(fs::File::open(path)?);
+                (
+                    file_size,
+                    self.write_stored(writer, &params, flags, source)?,
+                )
+            }
+            _ => {
+                let file_data = pending_file.data()?;
+                let params = FileWriteParams {
+                    file_data: &file_data,
+                    file_size: file_data.len() as u64,
+                    ..params
+                };
+                (params.file_size, self.write_file(writer, &params)?)
+            }
+        };
+
+        let file = BetFileInfo {
+            file_pos,
+            file_size,
+            compressed_size,
+            flags: flags | BlockEntry::FLAG_EXISTS,
+        };
+        self.file_done(
+            BuiltFile {
+                name: pending_file.archive_name.clone(),
+                file_size: file.file_size,
+                compressed_size: file.compressed_size,
+                flags: file.flags,
+            },
+            started,
+        );
+        Ok(file)
+    }
+
+    /// Write a single file to the archive
+    ///
+    /// Returns the stored size, not counting a single-unit checksum, and
+    /// the block flags. The layout is chosen by [`FileLayout::choose`];
+    /// compression and encryption then add their flags on top of it.
+    fn write_file<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        params: &FileWriteParams<'_>,
+    ) -> Result<(u64, u32)> {
+        let layout = FileLayout::choose(
+            params.file_size,
+            params.sector_size,
+            params.compressed(),
+            self.generate_crcs,
+            params.single_unit,
+        );
+        let flags = self.file_flags(layout, params);
+        log::debug!(
+            "Writing {} as {:?} with flags 0x{:08X}",
+            params.archive_name,
+            layout,
+            flags
+        );
+
+        match layout {
+            FileLayout::SingleUnit => self.write_single_unit(writer, params, flags),
+            FileLayout::Sectored => self.write_sectored(writer, params, flags),
+            FileLayout::Stored => self.write_stored(writer, params, flags, params.file_data),
+        }
+    }
 
-        // For V4, calculate and write the header MD5
-        if self.version == FormatVersion::V4 {
-            self.finalize_v4_header_md5(writer)?;
+    /// Flags of a file written with `layout`, before compression
+    fn file_flags(&self, layout: FileLayout, params: &FileWriteParams<'_>) -> u32 {
+        let mut flags = layout.flags(self.generate_crcs, self.compress_flag(params.compression));
+        if params.encrypt {
+            flags |= BlockEntry::FLAG_ENCRYPTED;
+            if params.use_fix_key {
+                flags |= BlockEntry::FLAG_FIX_KEY;
+            }
         }
+        flags
+    }
 
-        Ok(())
+    /// Flag marking data compressed with `compression`
+    ///
+    /// Imploded files carry `FLAG_IMPLODE` and no compression mask byte.
+    fn compress_flag(&self, compression: u8) -> u32 {
+        if self.use_implode && compression == compression_flags::PKWARE {
+            BlockEntry::FLAG_IMPLODE
+        } else {
+            BlockEntry::FLAG_COMPRESS
+        }
     }
 
-    /// Write a single file to the archive
-    fn write_file<W: Write>(
+    /// Encryption key of the file being written, or `None` if it is not encrypted
+    ///
+    /// The key depends on `FLAG_FIX_KEY` only, which is settled before any
+    /// data is written.
+    fn file_key(&self, params: &FileWriteParams<'_>, flags: u32) -> Option<u32> {
+        params.encrypt.then(|| {
+            self.calculate_file_key(
+                params.archive_name,
+                params.file_pos,
+                params.file_size as u32,
+                flags,
+            )
+        })
+    }
+
+    /// Write a file in one piece, followed by its checksum if enabled
+    fn write_single_unit<W: Write>(
         &self,
         writer: &mut W,
         params: &FileWriteParams<'_>,
-    ) -> Result<(usize, u32)> {
+        mut flags: u32,
+    ) -> Result<(u64, u32)> {
         let FileWriteParams {
             file_data,
             archive_name,
             compression,
-            encrypt,
-            use_fix_key,
-            sector_size,
-            file_pos,
+            ..
         } = params;
-        let mut flags = 0u32;
-
-        // For small files or if single unit is requested, write as single unit
-        let is_single_unit = file_data.len() <= *sector_size;
 
-        if is_single_unit {
-            flags |= BlockEntry::FLAG_SINGLE_UNIT;
-
-            // Set CRC flag early if enabled (needed for encryption key calculation)
-            if self.generate_crcs {
-                flags |= BlockEntry::FLAG_SECTOR_CRC;
-            }
+        // Compress if needed
+        let mut stored = Cow::Borrowed(*file_data);
+        if *compression != 0 && !file_data.is_empty() {
+            log::debug!(
+                "Compressing {} with method 0x{:02X}",
+                archive_name,
+                compression
+            );
+            let mut compressed = compress(file_data, *compression)?;
 
-            // Compress if needed
-            let compressed_data = if *compression != 0 && !file_data.is_empty() {
+            // The compress function now handles the compression byte prefix
+            // and only returns compressed data if it's beneficial
+            if compressed != *file_data {
+                // Compression was beneficial and the data now includes the method byte
                 log::debug!(
-                    "Compressing {} with method 0x{:02X}",
-                    archive_name,
-                    compression
+                    "Compression successful: {} -> {} bytes (including method byte)",
+                    file_data.len(),
+                    compressed.len()
                 );
-                let compressed = compress(file_data, *compression)?;
-
-                // The compress function now handles the compression byte prefix
-                // and only returns compressed data if it's beneficial
-                if compressed != *file_data {
-                    // Compression was beneficial and the data now includes the method byte
-                    log::debug!(
-                        "Compression successful: {} -> {} bytes (including method byte)",
-                        file_data.len(),
-                        compressed.len()
-                    );
-                    flags |= BlockEntry::FLAG_COMPRESS;
-                    compressed
-                } else {
-                    // Compression not beneficial, returned original data
-                    log::debug!("Compression not beneficial, storing uncompressed");
-                    file_data.to_vec()
-                }
-            } else {
-                file_data.to_vec()
-            };
-
-            // Encrypt if needed
-            let final_data = if *encrypt {
-                flags |= BlockEntry::FLAG_ENCRYPTED;
-                if *use_fix_key {
-                    flags |= BlockEntry::FLAG_FIX_KEY;
+                let compress_flag = self.compress_flag(*compression);
+                flags |= compress_flag;
+                if compress_flag == BlockEntry::FLAG_IMPLODE {
+                    compressed.remove(0);
                 }
-                let key =
-                    self.calculate_file_key(archive_name, *file_pos, file_data.len() as u32, flags);
-                let mut encrypted = compressed_data;
-                self.encrypt_data(&mut encrypted, key);
-                encrypted
+                stored = Cow::Owned(compressed);
             } else {
-                compressed_data
-            };
-
-            // Write the data
-            writer.write_all(&final_data)?;
-
-            // Write CRC if enabled
-            if self.generate_crcs {
-                // MPQ uses ADLER32 for sector checksums
-                let crc = adler::adler32_slice(file_data);
-                writer.write_u32_le(crc)?;
-                log::debug!(
-                    "Generated CRC for single unit file {}: 0x{:08X}",
-                    archive_name,
-                    crc
-                );
+                // Compression not beneficial, returned original data
+                log::debug!("Compression not beneficial, storing uncompressed");
             }
+        }
 
-            // Return compressed size (NOT including CRC)
-            Ok((final_data.len(), flags))
-        } else {
-            // Multi-sector file
-            let sector_count = file_data.len().div_ceil(*sector_size);
+        // Encrypt if needed
+        if let Some(key) = self.file_key(params, flags) {
+            self.encrypt_data(stored.to_mut(), key);
+        }
 
-            // Set CRC flag early if enabled (needed for encryption key calculation)
-            if self.generate_crcs {
-                flags |= BlockEntry::FLAG_SECTOR_CRC;
-            }
+        // Write the data
+        writer.write_all(&stored)?;
 
-            // Reserve space for sector offset table and CRC table if enabled
-            let offset_table_size = (sector_count + 1) * 4;
-            let crc_table_size = if self.generate_crcs {
-                sector_count * 4
-            } else {
-                0
-            };
-            let data_start = offset_table_size + crc_table_size;
+        // The checksum covers the file's contents and is never encrypted
+        if self.generate_crcs {
+            let crc = self.sector_checksum.compute(file_data);
+            writer.write_u32_le(crc)?;
+            log::debug!(
+                "Generated CRC for single unit file {}: 0x{:08X}",
+                archive_name,
+                crc
+            );
+        }
 
-            let mut sector_offsets = vec![0u32; sector_count + 1];
-            let mut sector_data = Vec::new();
-            let mut sector_crcs = if self.generate_crcs {
-                Vec::with_capacity(sector_count)
-            } else {
-                Vec::new()
-            };
+        // Return compressed size (NOT including CRC)
+        Ok((stored.len() as u64, flags))
+    }
 
-            // Process each sector
-            for (i, offset) in sector_offsets.iter_mut().enumerate().take(sector_count) {
-                let sector_start = i * *sector_size;
-                let sector_end = ((i + 1) * *sector_size).min(file_data.len());
-                let sector_bytes = &file_data[sector_start..sector_end];
+    /// Write a file as a sector offset table, the checksum table if enabled,
+    /// and the sectors, each compressed if that makes it smaller
+    ///
+    /// Sectors are compressed [`SECTOR_BATCH`] at a time and written as they
+    /// are done; the tables in front of them are filled in at the end.
+    fn write_sectored<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        params: &FileWriteParams<'_>,
+        flags: u32,
+    ) -> Result<(u64, u32)> {
+        let FileWriteParams {
+            file_data,
+            archive_name,
+            compression,
+            compression_next,
+            sector_size,
+            ..
+        } = params;
+        let sector_count = file_data.len().div_ceil(*sector_size);
 
-                *offset = (data_start + sector_data.len()) as u32;
+        // Reserve space for sector offset table and CRC table if enabled
+        let offset_table_size = (sector_count + 1) * 4;
+        let crc_table_size = if self.generate_crcs {
+            sector_count * 4
+        } else {
+            0
+        };
+        let data_start = offset_table_size + crc_table_size;
+        let tables_pos = writer.stream_position()?;
+        writer.write_all(&vec![0u8; data_start])?;
 
-                // Calculate CRC for uncompressed sector if enabled
-                if self.generate_crcs {
-                    // MPQ uses ADLER32 for sector checksums
-                    let crc = adler::adler32_slice(sector_bytes);
-                    sector_crcs.push(crc);
-                }
+        let mut sector_offsets = Vec::with_capacity(sector_count + 1);
+        let mut sector_crcs = if self.generate_crcs {
+            Vec::with_capacity(sector_count)
+        } else {
+            Vec::new()
+        };
 
-                // Compress sector if needed
-                let compressed_sector = if *compression != 0 && !sector_bytes.is_empty() {
-                    // The compress function now handles the compression byte prefix
-                    // and only returns compressed data if it's beneficial
-                    let compressed = compress(sector_bytes, *compression)?;
-                    if compressed != *sector_bytes {
-                        // Compression was beneficial and the data now includes the method byte
-                        flags |= BlockEntry::FLAG_COMPRESS;
-                        compressed
-                    } else {
-                        // Compression not beneficial, returned original data
-                        sector_bytes.to_vec()
-                    }
+        // Compress each batch of sectors (possibly in parallel), then write
+        // them in order
+        let key = self.file_key(params, flags);
+        let checksum = self.generate_crcs.then_some(self.sector_checksum);
+        let implode = flags & BlockEntry::FLAG_IMPLODE != 0;
+        let sectors: Vec<(u8, &[u8])> = file_data
+            .chunks(*sector_size)
+            .enumerate()
+            .map(|(index, sector)| {
+                let method = if index == 0 {
+                    *compression
                 } else {
-                    sector_bytes.to_vec()
+                    *compression_next
                 };
+                (method, sector)
+            })
+            .collect();
+        let mut offset = data_start;
+        for (batch_index, batch) in sectors.chunks(SECTOR_BATCH).enumerate() {
+            let processed = self.map_sectors(batch, |&(method, sector_bytes)| {
+                // Sector checksums cover the stored data, before encryption,
+                // which is what readers verify
+                let mut data = compress_sector(sector_bytes, method)?;
+                if implode && data.len() < sector_bytes.len() {
+                    data.remove(0);
+                }
+                let crc = checksum.map(|algorithm| algorithm.compute(&data));
+                Ok((data, crc))
+            })?;
+
+            for (batch_offset, (mut sector, crc)) in processed.into_iter().enumerate() {
+                let index = batch_index * SECTOR_BATCH + batch_offset;
+                sector_offsets.push(sector_offset(archive_name, offset)?);
+                sector_crcs.extend(crc);
+                if let Some(key) = key {
+                    self.encrypt_data(&mut sector, key.wrapping_add(index as u32));
+                }
+                writer.write_all(&sector)?;
+                offset += sector.len();
 
-                sector_data.extend_from_slice(&compressed_sector);
+                let done = ((index + 1) * *sector_size).min(file_data.len());
+                self.notify(|o| {
+                    o.on_file_progress(archive_name, done as u64, file_data.len() as u64)
+                });
             }
+        }
 
-            // Set last offset
-            sector_offsets[sector_count] = (data_start + sector_data.len()) as u32;
+        // Set last offset
+        sector_offsets.push(sector_offset(archive_name, offset)?);
 
-            // Log CRC generation if enabled
-            if self.generate_crcs {
-                log::debug!(
-                    "Generated {} sector CRCs for file {}, first few: {:?}",
-                    sector_count,
-                    archive_name,
-                    &sector_crcs[..5.min(sector_crcs.len())]
-                );
-            }
+        // Log CRC generation if enabled
+        if self.generate_crcs {
+            log::debug!(
+                "Generated {} sector CRCs for file {}, first few: {:?}",
+                sector_count,
+                archive_name,
+                &sector_crcs[..5.min(sector_crcs.len())]
+            );
+        }
 
-            // Encrypt if needed
-            if *encrypt {
-                flags |= BlockEntry::FLAG_ENCRYPTED;
-                if *use_fix_key {
-                    flags |= BlockEntry::FLAG_FIX_KEY;
-                }
-                let key =
-                    self.calculate_file_key(archive_name, *file_pos, file_data.len() as u32, flags);
-
-                // Save original offsets for sector encryption
-                let original_offsets = sector_offsets.clone();
-
-                // Encrypt sector offset table
-                let offset_key = key.wrapping_sub(1);
-                self.encrypt_data_u32(&mut sector_offsets, offset_key);
-
-                // Encrypt each sector using the original (unencrypted) offsets
-                let mut encrypted_sectors = Vec::new();
-                for (i, offset_pair) in original_offsets.windows(2).enumerate() {
-                    let start = (offset_pair[0] - data_start as u32) as usize;
-                    let end = (offset_pair[1] - data_start as u32) as usize;
-
-                    let mut sector = sector_data[start..end].to_vec();
-                    let sector_key = key.wrapping_add(i as u32);
-                    self.encrypt_data(&mut sector, sector_key);
-                    encrypted_sectors.extend_from_slice(&sector);
-                }
+        // Encrypt sector offset table
+        if let Some(key) = key {
+            self.encrypt_data_u32(&mut sector_offsets, key.wrapping_sub(1));
+        }
 
-                sector_data = encrypted_sectors;
-            }
+        // Fill in the sector offset table and CRC table
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(tables_pos))?;
+        for offset in &sector_offsets {
+            writer.write_u32_le(*offset)?;
+        }
+        for crc in &sector_crcs {
+            writer.write_u32_le(*crc)?;
+        }
+        writer.seek(SeekFrom::Start(end))?;
 
-            // Write sector offset table
-            for offset in &sector_offsets {
-                writer.write_u32_le(*offset)?;
-            }
+        // The stored size spans the offset table, CRC table and sectors,
+        // so the last sector offset never points past the block
+        Ok((offset as u64, flags))
+    }
 
-            // Write CRC table if enabled
-            if self.generate_crcs {
-                for crc in &sector_crcs {
-                    writer.write_u32_le(*crc)?;
+    /// Write a file as raw sectors with no tables, each encrypted with its
+    /// own key
+    ///
+    /// The file's `file_size` bytes are read from `source` one sector at a
+    /// time. Sectors of unencrypted files that are all zeros are skipped
+    /// over rather than written, which leaves holes in the new archive that
+    /// read back as zeros and keeps large sparse files sparse.
+    fn write_stored<W: Write + Seek, R: Read>(
+        &self,
+        writer: &mut W,
+        params: &FileWriteParams<'_>,
+        flags: u32,
+        mut source: R,
+    ) -> Result<(u64, u32)> {
+        let FileWriteParams {
+            archive_name,
+            file_size,
+            sector_size,
+            ..
+        } = params;
+        let key = self.file_key(params, flags);
+
+        let mut sector = vec![0u8; (*file_size).min(*sector_size as u64) as usize];
+        let mut done = 0u64;
+        for i in 0..file_size.div_ceil(*sector_size as u64) {
+            let len = (file_size - done).min(*sector_size as u64) as usize;
+            let sector = &mut sector[..len];
+            source.read_exact(sector)?;
+            match key {
+                Some(key) => {
+                    self.encrypt_data(sector, key.wrapping_add(i as u32));
+                    writer.write_all(sector)?;
+                }
+                None if sector.iter().all(|&byte| byte == 0) => {
+                    writer.seek(SeekFrom::Current(len as i64))?;
                 }
+                None => writer.write_all(sector)?,
             }
+            done += len as u64;
+            self.notify(|o| o.on_file_progress(archive_name, done, *file_size));
+        }
 
-            // Write sector data
-            writer.write_all(&sector_data)?;
+        Ok((*file_size, flags))
+    }
 
-            // Return size NOT including CRC table (offset table + sector data only)
-            let total_size = offset_table_size + sector_data.len();
-            Ok((total_size, flags))
+    /// Apply `process` to every sector, on the thread pool when one is set up
+    ///
+    /// Results are returned in sector order either way.
+    fn map_sectors<S, T, F>(&self, sectors: &[S], process: F) -> Result<Vec<T>>
+    where
+        S: Sync,
+        T: Send,
+        F: Fn(&S) -> Result<T> + Send + Sync,
+    {
+        #[cfg(feature = "parallel")]
+        if let Some(pool) = &self.thread_pool {
+            use rayon::prelude::*;
+            return pool.install(|| sectors.par_iter().map(&process).collect());
         }
+
+        sectors.iter().map(process).collect()
     }
 
     /// Add a file to the hash table
@@ -1116,10 +2625,11 @@ impl ArchiveBuilder {
         filename: &str,
         block_index: u32,
         locale: u16,
+        platform: u16,
     ) -> Result<()> {
-        let table_offset = hash_string(filename, hash_type::TABLE_OFFSET);
-        let name_a = hash_string(filename, hash_type::NAME_A);
-        let name_b = hash_string(filename, hash_type::NAME_B);
+        let table_offset = hash_string_with(filename, hash_type::TABLE_OFFSET, self.name_hashing);
+        let name_a = hash_string_with(filename, hash_type::NAME_A, self.name_hashing);
+        let name_b = hash_string_with(filename, hash_type::NAME_B, self.name_hashing);
 
         let table_size = hash_table.size() as u32;
         let mut index = table_offset & (table_size - 1);
@@ -1136,7 +2646,7 @@ impl ArchiveBuilder {
                     name_1: name_a,
                     name_2: name_b,
                     locale,
-                    platform: 0, // Always 0 - platform codes are vestigial
+                    platform,
                     block_index,
                 };
                 break;
@@ -1178,10 +2688,11 @@ impl ArchiveBuilder {
         self.encrypt_data(&mut table_data, key);
 
         // Calculate MD5 of encrypted data (for v4)
-        let md5 = self.calculate_md5(&table_data);
+        let md5 = checksum::md5(&table_data);
 
         // Write encrypted table
         writer.write_all(&table_data)?;
+        self.notify_table_write(BuildTable::Hash, table_data.len() as u64);
 
         Ok(md5)
     }
@@ -1206,10 +2717,11 @@ impl ArchiveBuilder {
         self.encrypt_data(&mut table_data, key);
 
         // Calculate MD5 of encrypted data (for v4)
-        let md5 = self.calculate_md5(&table_data);
+        let md5 = checksum::md5(&table_data);
 
         // Write encrypted table
         writer.write_all(&table_data)?;
+        self.notify_table_write(BuildTable::Block, table_data.len() as u64);
 
         Ok(md5)
     }
@@ -1227,99 +2739,52 @@ impl ArchiveBuilder {
         }
 
         // Calculate MD5 (for v4)
-        let md5 = self.calculate_md5(&table_data);
+        let md5 = checksum::md5(&table_data);
 
         // Write table
         writer.write_all(&table_data)?;
+        self.notify_table_write(BuildTable::HiBlock, table_data.len() as u64);
 
         Ok(md5)
     }
 
     /// Write the MPQ header
-    fn write_header<W: Write + Seek>(
-        &self,
-        writer: &mut W,
-        params: &HeaderWriteParams,
-    ) -> Result<()> {
-        // Write signature
-        writer.write_u32_le(crate::signatures::MPQ_ARCHIVE)?;
-
-        // Write header size
-        writer.write_u32_le(self.version.header_size())?;
-
-        // Write archive size (32-bit for v1, deprecated in v2+)
-        writer.write_u32_le(params.archive_size.min(u32::MAX as u64) as u32)?;
-
-        // Write format version
-        writer.write_u16_le(self.version as u16)?;
-
-        // Write block size
-        writer.write_u16_le(self.block_size)?;
-
-        // Write table positions and sizes (low 32 bits)
-        writer.write_u32_le(params.hash_table_pos as u32)?;
-        writer.write_u32_le(params.block_table_pos as u32)?;
-        writer.write_u32_le(params.hash_table_size)?;
-        writer.write_u32_le(params.block_table_size)?;
+    ///
+    /// For v4 the header MD5 is filled in before writing.
+    fn write_header<W: Write>(&self, writer: &mut W, params: &HeaderWriteParams) -> Result<()> {
+        let v2 = self.version >= FormatVersion::V2;
+        let v3 = self.version >= FormatVersion::V3;
+        if self.version == FormatVersion::V4 && params.v4_data.is_none() {
+            return Err(Error::invalid_format("V4 format requires v4_data"));
+        }
 
-        // Write version-specific fields
-        match self.version {
-            FormatVersion::V1 => {
-                // No additional fields
-            }
-            FormatVersion::V2 => {
-                // Hi-block table position
-                writer.write_u64_le(params.hi_block_table_pos.unwrap_or(0))?;
+        let mut header = MpqHeader {
+            header_size: self.version.header_size(),
+            // Deprecated in v2+, where archive_size_64 holds the real size
+            archive_size: params.archive_size.min(u32::MAX as u64) as u32,
+            format_version: self.version,
+            block_size: self.block_size,
+            hash_table_pos: params.hash_table_pos as u32,
+            block_table_pos: params.block_table_pos as u32,
+            hash_table_size: params.hash_table_size,
+            block_table_size: params.block_table_size,
+            hi_block_table_pos: v2.then(|| params.hi_block_table_pos.unwrap_or(0)),
+            hash_table_pos_hi: v2.then_some((params.hash_table_pos >> 32) as u16),
+            block_table_pos_hi: v2.then_some((params.block_table_pos >> 32) as u16),
+            archive_size_64: v3.then_some(params.archive_size),
+            bet_table_pos: v3.then(|| params.bet_table_pos.unwrap_or(0)),
+            het_table_pos: v3.then(|| params.het_table_pos.unwrap_or(0)),
+            v4_data: params.v4_data.clone(),
+        };
 
-                // High 16 bits of positions
-                writer.write_u16_le((params.hash_table_pos >> 32) as u16)?; // hash_table_pos_hi
-                writer.write_u16_le((params.block_table_pos >> 32) as u16)?; // block_table_pos_hi
-            }
-            FormatVersion::V3 => {
-                // V2 fields
-                writer.write_u64_le(params.hi_block_table_pos.unwrap_or(0))?; // hi_block_table_pos
-                writer.write_u16_le((params.hash_table_pos >> 32) as u16)?; // hash_table_pos_hi
-                writer.write_u16_le((params.block_table_pos >> 32) as u16)?; // block_table_pos_hi
-
-                // V3 fields
-                writer.write_u64_le(params.archive_size)?; // archive_size_64
-                writer.write_u64_le(params.bet_table_pos.unwrap_or(0))?; // bet_table_pos
-                writer.write_u64_le(params.het_table_pos.unwrap_or(0))?; // het_table_pos
-            }
-            FormatVersion::V4 => {
-                // V2 fields
-                writer.write_u64_le(params.hi_block_table_pos.unwrap_or(0))?; // hi_block_table_pos
-                writer.write_u16_le((params.hash_table_pos >> 32) as u16)?; // hash_table_pos_hi
-                writer.write_u16_le((params.block_table_pos >> 32) as u16)?; // block_table_pos_hi
-
-                // V3 fields
-                writer.write_u64_le(params.archive_size)?; // archive_size_64
-                writer.write_u64_le(params.bet_table_pos.unwrap_or(0))?; // bet_table_pos
-                writer.write_u64_le(params.het_table_pos.unwrap_or(0))?; // het_table_pos
-
-                // V4 fields
-                if let Some(v4_data) = &params.v4_data {
-                    writer.write_u64_le(v4_data.hash_table_size_64)?;
-                    writer.write_u64_le(v4_data.block_table_size_64)?;
-                    writer.write_u64_le(v4_data.hi_block_table_size_64)?;
-                    writer.write_u64_le(v4_data.het_table_size_64)?;
-                    writer.write_u64_le(v4_data.bet_table_size_64)?;
-                    writer.write_u32_le(v4_data.raw_chunk_size)?;
-
-                    // Write MD5 hashes (all except header MD5 which is calculated later)
-                    writer.write_all(&v4_data.md5_block_table)?;
-                    writer.write_all(&v4_data.md5_hash_table)?;
-                    writer.write_all(&v4_data.md5_hi_block_table)?;
-                    writer.write_all(&v4_data.md5_bet_table)?;
-                    writer.write_all(&v4_data.md5_het_table)?;
-                    writer.write_all(&v4_data.md5_mpq_header)?;
-                } else {
-                    return Err(Error::invalid_format("V4 format requires v4_data"));
-                }
+        if header.v4_data.is_some() {
+            let md5 = mpq_header::header_md5(&mpq_header::encode_header(&header));
+            if let Some(v4_data) = &mut header.v4_data {
+                v4_data.md5_mpq_header = md5;
             }
         }
 
-        Ok(())
+        mpq_header::write_header(&header, writer)
     }
 
     /// Calculate file encryption key
@@ -1377,216 +2842,13 @@ impl ArchiveBuilder {
         encrypt_block(data, key);
     }
 
-    /// Calculate MD5 hash of data
-    fn calculate_md5(&self, data: &[u8]) -> [u8; 16] {
-        let mut hasher = Md5::new();
-        hasher.update(data);
-        hasher.finalize().into()
-    }
-
-    /// Finalize V4 header by calculating and writing the header MD5
-    fn finalize_v4_header_md5<W: Write + Seek + Read>(&self, writer: &mut W) -> Result<()> {
-        // Read the header data (excluding the MD5 field itself)
-        writer.seek(SeekFrom::Start(0))?;
-        let header_size = self.version.header_size() as usize;
-        let md5_size = 16;
-        let header_data_size = header_size - md5_size; // 208 - 16 = 192 bytes
-
-        let mut header_data = vec![0u8; header_data_size];
-        writer.read_exact(&mut header_data)?;
-
-        // Calculate MD5 of header data
-        let header_md5 = self.calculate_md5(&header_data);
-
-        // Write the MD5 at the end of the header (offset 0xC0 = 192)
-        writer.seek(SeekFrom::Start(192))?;
-        writer.write_all(&header_md5)?;
-
-        Ok(())
-    }
-
     /// Create HET table data
-    fn create_het_table(&self) -> Result<(Vec<u8>, HetHeader)> {
-        // Calculate required sizes
-        let max_file_count = self.pending_files.len() as u32;
-        let hash_table_entries = (max_file_count * 2).next_power_of_two();
-
-        log::debug!(
-            "Creating HET table: {} files, {} hash entries",
-            max_file_count,
-            hash_table_entries
-        );
-
-        // Calculate bit sizes
-        let hash_entry_size = Self::calculate_bits_needed(hash_table_entries as u64);
-        let index_size = Self::calculate_bits_needed(max_file_count as u64);
-
-        log::debug!(
-            "HET bit sizes: hash_entry_size={}, index_size={}",
-            hash_entry_size,
-            index_size
-        );
-
-        // Calculate table sizes
-        // The hash_table_size in the header seems to be calculated differently
-        // Based on the reading code: hash_table_entries = hash_table_size * 8 / hash_entry_size
-        // So: hash_table_size = hash_table_entries * hash_entry_size / 8
-        let hash_table_size = (hash_table_entries * hash_entry_size).div_ceil(8);
-
-        // But the actual data needs more space for hash + index
-        let total_entry_bits = hash_entry_size + index_size;
-        let actual_hash_table_size = (hash_table_entries * total_entry_bits).div_ceil(8);
-        let total_index_size = hash_table_entries * index_size;
-        let index_size_extra = 0; // No extra bits for now
-
-        // Create header (without extended header fields)
-        let header = HetHeader {
-            table_size: 0, // Will be calculated later
-            max_file_count,
-            hash_table_size,
-            hash_entry_size,
-            total_index_size,
-            index_size_extra,
-            index_size,
-            block_table_size: 0, // Not used in our implementation
-        };
-
-        // Create hash table and file indices
-        let mut hash_table = vec![0u8; actual_hash_table_size as usize];
-        let file_indices_size = (total_index_size as usize).div_ceil(8); // Round up to bytes
-        let file_indices = vec![0u8; file_indices_size];
-
-        log::debug!(
-            "HET table sizes: hash_table_size={}, actual_hash_table_size={}, file_indices_size={}",
-            hash_table_size,
-            actual_hash_table_size,
-            file_indices_size
-        );
-
-        // Build file hash map to track which hash slots map to which files
-        let mut file_map: Vec<Option<u32>> = vec![None; hash_table_entries as usize];
-
-        // Process each file
-        for (file_index, pending_file) in self.pending_files.iter().enumerate() {
-            let hash = jenkins_hash(&pending_file.archive_name);
-            let hash_mask = (1u64 << hash_entry_size) - 1;
-            let table_index = (hash & (hash_table_entries as u64 - 1)) as usize;
-
-            // Linear probing for collision resolution
-            let mut current_index = table_index;
-            loop {
-                if file_map[current_index].is_none() {
-                    file_map[current_index] = Some(file_index as u32);
-
-                    // Write hash entry (stores hash + file index in upper bits)
-                    let hash_entry = (hash & hash_mask) | ((file_index as u64) << hash_entry_size);
-                    // The actual entry size is hash_entry_size for the hash + index_size for the file index
-                    self.write_bit_entry(
-                        &mut hash_table,
-                        current_index,
-                        hash_entry,
-                        hash_entry_size + index_size,
-                    )?;
-
-                    break;
-                }
-
-                current_index = (current_index + 1) % hash_table_entries as usize;
-                if current_index == table_index {
-                    return Err(Error::invalid_format("HET table full"));
-                }
-            }
-        }
-
-        // Calculate sizes
-        let het_header_size = std::mem::size_of::<HetHeader>();
-        let data_size = het_header_size as u32 + hash_table_size + file_indices_size as u32;
-        let table_size = 12 + data_size; // Extended header (12 bytes) + data
-
-        // Update header with final size
-        let mut final_header = header;
-        final_header.table_size = table_size;
-
-        // Write extended header first
-        let mut result = Vec::with_capacity((12 + data_size) as usize);
-        result.write_u32_le(0x1A544548)?; // "HET\x1A"
-        result.write_u32_le(1)?; // version
-        result.write_u32_le(data_size)?; // data_size
-
-        // Then write the HET header
-        result.write_u32_le(final_header.table_size)?;
-        result.write_u32_le(final_header.max_file_count)?;
-        result.write_u32_le(final_header.hash_table_size)?;
-        result.write_u32_le(final_header.hash_entry_size)?;
-        result.write_u32_le(final_header.total_index_size)?;
-        result.write_u32_le(final_header.index_size_extra)?;
-        result.write_u32_le(final_header.index_size)?;
-        result.write_u32_le(final_header.block_table_size)?;
-
-        result.extend_from_slice(&hash_table);
-        result.extend_from_slice(&file_indices);
-
-        Ok((result, final_header))
-    }
-
-    /// Write a bit-packed entry to a byte array
-    fn write_bit_entry(
-        &self,
-        data: &mut [u8],
-        index: usize,
-        value: u64,
-        bit_size: u32,
-    ) -> Result<()> {
-        let bit_offset = index * bit_size as usize;
-        let byte_offset = bit_offset / 8;
-        let bit_shift = bit_offset % 8;
-
-        // Calculate how many bytes we actually need
-        let bits_needed = bit_shift + bit_size as usize;
-        let bytes_needed = bits_needed.div_ceil(8);
-
-        if byte_offset + bytes_needed > data.len() {
-            log::error!(
-                "Bit entry out of bounds: index={}, bit_size={}, bit_offset={}, byte_offset={}, bytes_needed={}, data.len()={}",
-                index,
-                bit_size,
-                bit_offset,
-                byte_offset,
-                bytes_needed,
-                data.len()
-            );
-            return Err(Error::invalid_format("Bit entry out of bounds"));
-        }
-
-        // Read existing bits (limit to 8 bytes for u64)
-        let mut existing = 0u64;
-        let max_bytes = bytes_needed.min(8);
-        for i in 0..max_bytes {
-            if byte_offset + i < data.len() && i * 8 < 64 {
-                existing |= (data[byte_offset + i] as u64) << (i * 8);
-            }
-        }
-
-        // Clear the bits we're about to write
-        let value_mask = if bit_size >= 64 {
-            u64::MAX
-        } else {
-            (1u64 << bit_size) - 1
-        };
-        let mask = value_mask << bit_shift;
-        existing &= !mask;
-
-        // Write the new value
-        existing |= (value & value_mask) << bit_shift;
-
-        // Write back (limit to 8 bytes for u64)
-        for i in 0..max_bytes {
-            if byte_offset + i < data.len() && i * 8 < 64 {
-                data[byte_offset + i] = (existing >> (i * 8)) as u8;
-            }
-        }
-
-        Ok(())
+    fn create_het_table(&self, names: &[&str]) -> Result<(Vec<u8>, HetHeader)> {
+        let hashes: Vec<Option<u64>> = names
+            .iter()
+            .map(|name| Some(name_hash(name, NAME_HASH_BITS, self.name_hashing)))
+            .collect();
+        encode_het_table(&hashes, NAME_HASH_BITS)
     }
 
     /// Calculate the number of bits needed to represent a value
@@ -1599,288 +2861,504 @@ impl ArchiveBuilder {
     }
 
     /// Write HET table to the archive, returns the written size and MD5
-    fn write_het_table<W: Write>(
-        &self,
-        writer: &mut W,
-        data: &[u8],
-        encrypt: bool,
-    ) -> Result<(u64, [u8; 16])> {
-        // HET table structure:
-        // - Extended header (12 bytes) - NEVER encrypted
-        // - Table data (rest) - can be compressed and/or encrypted
-
-        if data.len() < 12 {
-            return Err(Error::invalid_format("HET table data too small"));
-        }
-
-        // Split extended header and table data
-        let (extended_header, table_data) = data.split_at(12);
-        let mut processed_data = table_data.to_vec();
-
-        // Compress if enabled and this is a v3+ archive
-        if self.compress_tables && matches!(self.version, FormatVersion::V3 | FormatVersion::V4) {
-            log::debug!("Compressing HET table data: {} -> ", processed_data.len());
-            let compressed = compress(&processed_data, self.table_compression)?;
-            log::debug!(
-                "{} bytes ({}% reduction)",
-                compressed.len(),
-                (100 * (processed_data.len() - compressed.len()) / processed_data.len())
-            );
-
-            // Prepend compression type byte
-            let mut compressed_with_type = Vec::with_capacity(1 + compressed.len());
-            compressed_with_type.push(self.table_compression);
-            compressed_with_type.extend_from_slice(&compressed);
-            processed_data = compressed_with_type;
-        }
+    fn write_het_table<W: Write>(&self, writer: &mut W, data: &[u8]) -> Result<(u64, [u8; 16])> {
+        let final_data = encode_ext_table(data, self.ext_table_compression(), "(hash table)")?;
+        let md5 = checksum::md5(&final_data);
+        writer.write_all(&final_data)?;
+        self.notify_table_write(BuildTable::Het, final_data.len() as u64);
+        Ok((final_data.len() as u64, md5))
+    }
 
-        // Encrypt the data portion (after extended header)
-        if encrypt {
-            let key = hash_string("(hash table)", hash_type::FILE_KEY);
-            self.encrypt_data(&mut processed_data, key);
-        }
+    /// Create BET table data
+    fn create_bet_table(&self, files: &[BetFileInfo]) -> Result<(Vec<u8>, BetHeader)> {
+        let hashes: Vec<Option<u64>> = self
+            .pending_files
+            .iter()
+            .map(|file| {
+                Some(name_hash(
+                    &file.archive_name,
+                    NAME_HASH_BITS,
+                    self.name_hashing,
+                ))
+            })
+            .collect();
+        encode_bet_table(files, &hashes, NAME_HASH_BITS)
+    }
 
-        // Combine extended header with processed data
-        let mut final_data = Vec::with_capacity(extended_header.len() + processed_data.len());
-        final_data.extend_from_slice(extended_header);
-        final_data.extend_from_slice(&processed_data);
+    /// Write BET table to the archive, returns the written size and MD5
+    fn write_bet_table<W: Write>(&self, writer: &mut W, data: &[u8]) -> Result<(u64, [u8; 16])> {
+        let final_data = encode_ext_table(data, self.ext_table_compression(), "(block table)")?;
+        let md5 = checksum::md5(&final_data);
+        writer.write_all(&final_data)?;
+        self.notify_table_write(BuildTable::Bet, final_data.len() as u64);
+        Ok((final_data.len() as u64, md5))
+    }
 
-        // Calculate MD5 of final data
-        let md5 = self.calculate_md5(&final_data);
+    /// Compression for HET and BET tables, if they are compressed
+    fn ext_table_compression(&self) -> Option<u8> {
+        (self.compress_tables && self.version >= FormatVersion::V3)
+            .then_some(self.table_compression)
+    }
+}
 
-        let written_size = final_data.len() as u64;
-        writer.write_all(&final_data)?;
-        Ok((written_size, md5))
+impl Default for ArchiveBuilder {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    /// Create BET table data
-    fn create_bet_table(&self, block_table: &BlockTable) -> Result<(Vec<u8>, BetHeader)> {
-        let file_count = self.pending_files.len() as u32;
-
-        // Analyze block table to determine optimal bit widths
-        let mut max_file_pos = 0u64;
-        let mut max_file_size = 0u64;
-        let mut max_compressed_size = 0u64;
-        let mut unique_flags = std::collections::HashSet::new();
-
-        for i in 0..file_count as usize {
-            if let Some(entry) = block_table.get(i) {
-                max_file_pos = max_file_pos.max(entry.file_pos as u64);
-                max_file_size = max_file_size.max(entry.file_size as u64);
-                max_compressed_size = max_compressed_size.max(entry.compressed_size as u64);
-                unique_flags.insert(entry.flags);
+/// Encode a HET table for files with these name hashes, by block index
+///
+/// Hashes are the top `hash_entry_size` bits of the Jenkins name hash, as
+/// [`name_hash`] computes them; `None` leaves a block out of the table, as
+/// for deleted files.
+pub(crate) fn encode_het_table(
+    hashes: &[Option<u64>],
+    hash_entry_size: u32,
+) -> Result<(Vec<u8>, HetHeader)> {
+    // Calculate required sizes
+    let max_file_count = hashes.len() as u32;
+    let hash_table_entries = (max_file_count * 2).next_power_of_two();
+
+    log::debug!(
+        "Creating HET table: {} files, {} hash entries",
+        max_file_count,
+        hash_table_entries
+    );
+
+    // Slots keep the top 8 bits of a 64-bit name hash, the BET table
+    // the rest; file indices are a separate bit-packed array
+    let index_size = ArchiveBuilder::calculate_bits_needed(max_file_count as u64);
+    let total_index_size = index_size;
+    let index_size_extra = 0; // No extra bits for now
+    let hash_table_size = hash_table_entries;
+
+    log::debug!(
+        "HET bit sizes: hash_entry_size={}, index_size={}",
+        hash_entry_size,
+        index_size
+    );
+
+    // Create header (without extended header fields)
+    let header = HetHeader {
+        table_size: 0, // Will be calculated later
+        max_file_count,
+        hash_table_size,
+        hash_entry_size,
+        total_index_size,
+        index_size_extra,
+        index_size,
+        block_table_size: 0, // Not used in our implementation
+    };
+
+    // Create hash table and file indices
+    let mut hash_table = vec![0u8; hash_table_size as usize];
+    let file_indices_size = (hash_table_entries * total_index_size).div_ceil(8) as usize;
+    let mut file_indices = vec![0u8; file_indices_size];
+
+    // Process each file
+    for (file_index, hash) in hashes.iter().enumerate() {
+        let Some(hash) = *hash else {
+            continue;
+        };
+        let table_index = (hash % hash_table_entries as u64) as usize;
+
+        // Linear probing for collision resolution
+        let mut current_index = table_index;
+        while hash_table[current_index] != 0 {
+            current_index = (current_index + 1) % hash_table_entries as usize;
+            if current_index == table_index {
+                return Err(Error::invalid_format("HET table full"));
             }
         }
 
-        // Calculate bit counts for each field
-        let bit_count_file_pos = Self::calculate_bits_needed(max_file_pos);
-        let bit_count_file_size = Self::calculate_bits_needed(max_file_size);
-        let bit_count_cmp_size = Self::calculate_bits_needed(max_compressed_size);
-        let bit_count_flag_index = if unique_flags.is_empty() {
-            0
-        } else {
-            Self::calculate_bits_needed(unique_flags.len() as u64 - 1)
-        };
-        let bit_count_unknown = 0; // Not used
-
-        // Calculate bit positions
-        let bit_index_file_pos = 0;
-        let bit_index_file_size = bit_index_file_pos + bit_count_file_pos;
-        let bit_index_cmp_size = bit_index_file_size + bit_count_file_size;
-        let bit_index_flag_index = bit_index_cmp_size + bit_count_cmp_size;
-        let bit_index_unknown = bit_index_flag_index + bit_count_flag_index;
-
-        // Calculate table entry size
-        let table_entry_size = bit_index_unknown + bit_count_unknown;
-
-        // Create flag array
-        let mut flag_array: Vec<u32> = unique_flags.into_iter().collect();
-        flag_array.sort();
-        let flag_count = flag_array.len() as u32;
-
-        // Create flag index map
-        let mut flag_index_map = std::collections::HashMap::new();
-        for (index, &flags) in flag_array.iter().enumerate() {
-            flag_index_map.insert(flags, index as u32);
-        }
-
-        // Calculate table sizes
-        let file_table_bits = file_count * table_entry_size;
-        let file_table_size = file_table_bits.div_ceil(8); // Round up to bytes
-
-        // BET hash information (simplified - we'll use 64-bit hashes)
-        let bet_hash_size = 64;
-        let total_bet_hash_size = file_count * bet_hash_size;
-        let bet_hash_size_extra = 0;
-        let bet_hash_array_size = total_bet_hash_size.div_ceil(8);
-
-        // Create header (without extended header fields)
-        let header = BetHeader {
-            table_size: 0, // Will be calculated later
-            file_count,
-            unknown_08: 0x10,
-            table_entry_size,
-            bit_index_file_pos,
-            bit_index_file_size,
-            bit_index_cmp_size,
-            bit_index_flag_index,
-            bit_index_unknown,
-            bit_count_file_pos,
-            bit_count_file_size,
-            bit_count_cmp_size,
-            bit_count_flag_index,
-            bit_count_unknown,
-            total_bet_hash_size,
-            bet_hash_size_extra,
-            bet_hash_size,
-            bet_hash_array_size,
-            flag_count,
-        };
-
-        // Create file table
-        let mut file_table = vec![0u8; file_table_size as usize];
-
-        // Create BET hashes
-        let mut bet_hashes = Vec::with_capacity(file_count as usize);
+        hash_table[current_index] = (hash >> (hash_entry_size - 8)) as u8;
+        write_bit_entry(
+            &mut file_indices,
+            current_index,
+            file_index as u64,
+            total_index_size,
+        )?;
+    }
 
-        // Fill tables
-        for (i, pending_file) in self.pending_files.iter().enumerate() {
-            if let Some(entry) = block_table.get(i) {
-                // Get flag index
-                let flag_index = flag_index_map.get(&entry.flags).unwrap();
+    // Calculate sizes
+    let het_header_size = std::mem::size_of::<HetHeader>();
+    let data_size = het_header_size as u32 + hash_table_size + file_indices_size as u32;
+    let table_size = 12 + data_size; // Extended header (12 bytes) + data
+
+    // Update header with final size
+    let mut final_header = header;
+    final_header.table_size = table_size;
+
+    // Write extended header first
+    let mut result = Vec::with_capacity((12 + data_size) as usize);
+    result.write_u32_le(0x1A544548)?; // "HET\x1A"
+    result.write_u32_le(1)?; // version
+    result.write_u32_le(data_size)?; // data_size
+
+    // Then write the HET header
+    result.write_u32_le(final_header.table_size)?;
+    result.write_u32_le(final_header.max_file_count)?;
+    result.write_u32_le(final_header.hash_table_size)?;
+    result.write_u32_le(final_header.hash_entry_size)?;
+    result.write_u32_le(final_header.total_index_size)?;
+    result.write_u32_le(final_header.index_size_extra)?;
+    result.write_u32_le(final_header.index_size)?;
+    result.write_u32_le(final_header.block_table_size)?;
+
+    result.extend_from_slice(&hash_table);
+    result.extend_from_slice(&file_indices);
+
+    Ok((result, final_header))
+}
 
-                // Pack entry data
-                let mut entry_bits = 0u64;
-                entry_bits |= (entry.file_pos as u64) << bit_index_file_pos;
-                entry_bits |= (entry.file_size as u64) << bit_index_file_size;
-                entry_bits |= (entry.compressed_size as u64) << bit_index_cmp_size;
-                entry_bits |= (*flag_index as u64) << bit_index_flag_index;
+/// Encode a BET table for the files of an archive and their name hashes
+///
+/// `files` hold the full 64-bit positions, relative to the MPQ header, and
+/// sizes. `hashes` are as for [`encode_het_table`]; blocks without one get
+/// a zero hash.
+pub(crate) fn encode_bet_table(
+    files: &[BetFileInfo],
+    hashes: &[Option<u64>],
+    hash_bits: u32,
+) -> Result<(Vec<u8>, BetHeader)> {
+    let file_count = files.len() as u32;
+
+    // Analyze the files to determine optimal bit widths
+    let mut max_file_pos = 0u64;
+    let mut max_file_size = 0u64;
+    let mut max_compressed_size = 0u64;
+    let mut unique_flags = std::collections::HashSet::new();
+
+    for entry in files {
+        max_file_pos = max_file_pos.max(entry.file_pos);
+        max_file_size = max_file_size.max(entry.file_size);
+        max_compressed_size = max_compressed_size.max(entry.compressed_size);
+        unique_flags.insert(entry.flags);
+    }
 
-                // Write to file table
-                self.write_bit_entry(&mut file_table, i, entry_bits, table_entry_size)?;
+    // Calculate bit counts for each field
+    let bit_count_file_pos = ArchiveBuilder::calculate_bits_needed(max_file_pos);
+    let bit_count_file_size = ArchiveBuilder::calculate_bits_needed(max_file_size);
+    let bit_count_cmp_size = ArchiveBuilder::calculate_bits_needed(max_compressed_size);
+    let bit_count_flag_index = if unique_flags.is_empty() {
+        0
+    } else {
+        ArchiveBuilder::calculate_bits_needed(unique_flags.len() as u64 - 1)
+    };
+    let bit_count_unknown = 0; // Not used
+
+    // Calculate bit positions
+    let bit_index_file_pos = 0;
+    let bit_index_file_size = bit_index_file_pos + bit_count_file_pos;
+    let bit_index_cmp_size = bit_index_file_size + bit_count_file_size;
+    let bit_index_flag_index = bit_index_cmp_size + bit_count_cmp_size;
+    let bit_index_unknown = bit_index_flag_index + bit_count_flag_index;
+
+    // Calculate table entry size
+    let table_entry_size = bit_index_unknown + bit_count_unknown;
+
+    // Create flag array
+    let mut flag_array: Vec<u32> = unique_flags.into_iter().collect();
+    flag_array.sort();
+    let flag_count = flag_array.len() as u32;
+
+    // Create flag index map
+    let mut flag_index_map = std::collections::HashMap::new();
+    for (index, &flags) in flag_array.iter().enumerate() {
+        flag_index_map.insert(flags, index as u32);
+    }
 
-                // Generate BET hash (Jenkins hash of filename)
-                let hash = jenkins_hash(&pending_file.archive_name);
-                bet_hashes.push(hash);
-            }
+    // Calculate table sizes
+    let file_table_bits = file_count * table_entry_size;
+    let file_table_size = file_table_bits.div_ceil(8); // Round up to bytes
+
+    // Name hashes without the 8 bits kept in the HET table
+    let bet_hash_size = hash_bits - 8;
+    let total_bet_hash_size = bet_hash_size;
+    let bet_hash_size_extra = 0;
+    let bet_hash_array_size = (file_count * total_bet_hash_size).div_ceil(8);
+
+    // Create header (without extended header fields)
+    let header = BetHeader {
+        table_size: 0, // Will be calculated later
+        file_count,
+        unknown_08: 0x10,
+        table_entry_size,
+        bit_index_file_pos,
+        bit_index_file_size,
+        bit_index_cmp_size,
+        bit_index_flag_index,
+        bit_index_unknown,
+        bit_count_file_pos,
+        bit_count_file_size,
+        bit_count_cmp_size,
+        bit_count_flag_index,
+        bit_count_unknown,
+        total_bet_hash_size,
+        bet_hash_size_extra,
+        bet_hash_size,
+        bet_hash_array_size,
+        flag_count,
+    };
+
+    // Create file table
+    let mut file_table = vec![0u8; file_table_size as usize];
+
+    // Create BET hashes
+    let mut bet_hashes = Vec::with_capacity(file_count as usize);
+
+    // Fill tables, one field at a time since an entry may be wider than 64 bits
+    for (i, entry) in files.iter().enumerate() {
+        let flag_index = flag_index_map[&entry.flags];
+        let entry_start = i * table_entry_size as usize;
+        for (bit_index, bit_count, value) in [
+            (bit_index_file_pos, bit_count_file_pos, entry.file_pos),
+            (bit_index_file_size, bit_count_file_size, entry.file_size),
+            (
+                bit_index_cmp_size,
+                bit_count_cmp_size,
+                entry.compressed_size,
+            ),
+            (
+                bit_index_flag_index,
+                bit_count_flag_index,
+                flag_index as u64,
+            ),
+        ] {
+            write_bits(
+                &mut file_table,
+                entry_start + bit_index as usize,
+                value,
+                bit_count,
+            )?;
         }
 
-        // Calculate final sizes
-        let bet_header_size = std::mem::size_of::<BetHeader>();
-        let flag_array_size = flag_count * 4;
-        let data_size =
-            bet_header_size as u32 + flag_array_size + file_table_size + bet_hash_array_size;
-        let table_size = 12 + data_size; // Extended header (12 bytes) + data
-
-        // Update header with final size
-        let mut final_header = header;
-        final_header.table_size = table_size;
-
-        // Serialize everything
-        let mut result = Vec::with_capacity((12 + data_size) as usize);
-
-        // Write extended header first
-        result.write_u32_le(0x1A544542)?; // "BET\x1A"
-        result.write_u32_le(1)?; // version
-        result.write_u32_le(data_size)?; // data_size
-
-        // Then write the BET header
-        result.write_u32_le(final_header.table_size)?;
-        result.write_u32_le(final_header.file_count)?;
-        result.write_u32_le(final_header.unknown_08)?;
-        result.write_u32_le(final_header.table_entry_size)?;
-        result.write_u32_le(final_header.bit_index_file_pos)?;
-        result.write_u32_le(final_header.bit_index_file_size)?;
-        result.write_u32_le(final_header.bit_index_cmp_size)?;
-        result.write_u32_le(final_header.bit_index_flag_index)?;
-        result.write_u32_le(final_header.bit_index_unknown)?;
-        result.write_u32_le(final_header.bit_count_file_pos)?;
-        result.write_u32_le(final_header.bit_count_file_size)?;
-        result.write_u32_le(final_header.bit_count_cmp_size)?;
-        result.write_u32_le(final_header.bit_count_flag_index)?;
-        result.write_u32_le(final_header.bit_count_unknown)?;
-        result.write_u32_le(final_header.total_bet_hash_size)?;
-        result.write_u32_le(final_header.bet_hash_size_extra)?;
-        result.write_u32_le(final_header.bet_hash_size)?;
-        result.write_u32_le(final_header.bet_hash_array_size)?;
-        result.write_u32_le(final_header.flag_count)?;
-
-        // Write flag array
-        for &flags in &flag_array {
-            result.write_u32_le(flags)?;
-        }
-
-        // Write file table
-        result.extend_from_slice(&file_table);
-
-        // Write BET hashes (bit-packed)
-        let mut hash_bytes = vec![0u8; bet_hash_array_size as usize];
-        for (i, &hash) in bet_hashes.iter().enumerate() {
-            self.write_bit_entry(&mut hash_bytes, i, hash, bet_hash_size)?;
-        }
-        result.extend_from_slice(&hash_bytes);
-
-        Ok((result, final_header))
+        // The BET hash is the low bits of the HET name hash
+        let hash = hashes.get(i).copied().flatten().unwrap_or(0);
+        bet_hashes.push(hash & ((1u64 << bet_hash_size) - 1));
     }
 
-    /// Write BET table to the archive, returns the written size and MD5
-    fn write_bet_table<W: Write>(
-        &self,
-        writer: &mut W,
-        data: &[u8],
-        encrypt: bool,
-    ) -> Result<(u64, [u8; 16])> {
-        // BET table structure:
-        // - Extended header (12 bytes) - NEVER encrypted
-        // - Table data (rest) - can be compressed and/or encrypted
+    // Calculate final sizes
+    let bet_header_size = std::mem::size_of::<BetHeader>();
+    let flag_array_size = flag_count * 4;
+    let data_size =
+        bet_header_size as u32 + flag_array_size + file_table_size + bet_hash_array_size;
+    let table_size = 12 + data_size; // Extended header (12 bytes) + data
+
+    // Update header with final size
+    let mut final_header = header;
+    final_header.table_size = table_size;
+
+    // Serialize everything
+    let mut result = Vec::with_capacity((12 + data_size) as usize);
+
+    // Write extended header first
+    result.write_u32_le(0x1A544542)?; // "BET\x1A"
+    result.write_u32_le(1)?; // version
+    result.write_u32_le(data_size)?; // data_size
+
+    // Then write the BET header
+    result.write_u32_le(final_header.table_size)?;
+    result.write_u32_le(final_header.file_count)?;
+    result.write_u32_le(final_header.unknown_08)?;
+    result.write_u32_le(final_header.table_entry_size)?;
+    result.write_u32_le(final_header.bit_index_file_pos)?;
+    result.write_u32_le(final_header.bit_index_file_size)?;
+    result.write_u32_le(final_header.bit_index_cmp_size)?;
+    result.write_u32_le(final_header.bit_index_flag_index)?;
+    result.write_u32_le(final_header.bit_index_unknown)?;
+    result.write_u32_le(final_header.bit_count_file_pos)?;
+    result.write_u32_le(final_header.bit_count_file_size)?;
+    result.write_u32_le(final_header.bit_count_cmp_size)?;
+    result.write_u32_le(final_header.bit_count_flag_index)?;
+    result.write_u32_le(final_header.bit_count_unknown)?;
+    result.write_u32_le(final_header.total_bet_hash_size)?;
+    result.write_u32_le(final_header.bet_hash_size_extra)?;
+    result.write_u32_le(final_header.bet_hash_size)?;
+    result.write_u32_le(final_header.bet_hash_array_size)?;
+    result.write_u32_le(final_header.flag_count)?;
+
+    // Write flag array
+    for &flags in &flag_array {
+        result.write_u32_le(flags)?;
+    }
 
-        if data.len() < 12 {
-            return Err(Error::invalid_format("BET table data too small"));
-        }
+    // Write file table
+    result.extend_from_slice(&file_table);
 
-        // Split extended header and table data
-        let (extended_header, table_data) = data.split_at(12);
-        let mut processed_data = table_data.to_vec();
+    // Write BET hashes (bit-packed)
+    let mut hash_bytes = vec![0u8; bet_hash_array_size as usize];
+    for (i, &hash) in bet_hashes.iter().enumerate() {
+        write_bit_entry(&mut hash_bytes, i, hash, total_bet_hash_size)?;
+    }
+    result.extend_from_slice(&hash_bytes);
 
-        // Compress if enabled and this is a v3+ archive
-        if self.compress_tables && matches!(self.version, FormatVersion::V3 | FormatVersion::V4) {
-            log::debug!("Compressing BET table data: {} -> ", processed_data.len());
-            let compressed = compress(&processed_data, self.table_compression)?;
-            log::debug!(
-                "{} bytes ({}% reduction)",
-                compressed.len(),
-                (100 * (processed_data.len() - compressed.len()) / processed_data.len())
-            );
+    Ok((result, final_header))
+}
 
-            // Prepend compression type byte
-            let mut compressed_with_type = Vec::with_capacity(1 + compressed.len());
-            compressed_with_type.push(self.table_compression);
-            compressed_with_type.extend_from_slice(&compressed);
-            processed_data = compressed_with_type;
-        }
+/// Compress and encrypt a HET or BET table for writing
+///
+/// The 12-byte extended header stays as it is. The rest is compressed with
+/// `compression` if given, then encrypted with the key of `key_name`;
+/// trailing bytes that do not fill a DWORD stay plain, as readers expect.
+pub(crate) fn encode_ext_table(
+    data: &[u8],
+    compression: Option<u8>,
+    key_name: &str,
+) -> Result<Vec<u8>> {
+    if data.len() < 12 {
+        return Err(Error::invalid_format("Extended table data too small"));
+    }
 
-        // Encrypt the data portion (after extended header)
-        if encrypt {
-            let key = hash_string("(block table)", hash_type::FILE_KEY);
-            self.encrypt_data(&mut processed_data, key);
-        }
+    let (extended_header, table_data) = data.split_at(12);
+    let mut processed_data = match compression {
+        // compress() already leads with the compression type byte, or
+        // returns the data as is when it would not shrink
+        Some(compression) => compress(table_data, compression)?,
+        None => table_data.to_vec(),
+    };
+
+    let key = hash_string(key_name, hash_type::FILE_KEY);
+    let aligned = processed_data.len() & !3;
+    let mut words: Vec<u32> = processed_data[..aligned]
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    encrypt_block(&mut words, key);
+    for (chunk, word) in processed_data[..aligned].chunks_exact_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
 
-        // Combine extended header with processed data
-        let mut final_data = Vec::with_capacity(extended_header.len() + processed_data.len());
-        final_data.extend_from_slice(extended_header);
-        final_data.extend_from_slice(&processed_data);
+    let mut final_data = Vec::with_capacity(extended_header.len() + processed_data.len());
+    final_data.extend_from_slice(extended_header);
+    final_data.extend_from_slice(&processed_data);
+    Ok(final_data)
+}
 
-        // Calculate MD5 of final data
-        let md5 = self.calculate_md5(&final_data);
+/// A file encoded by [`encode_file`]
+pub(crate) struct EncodedFile {
+    /// Bytes to write, including the checksum of a single-unit file
+    pub(crate) data: Vec<u8>,
+    /// Stored size for the block table, not counting that checksum
+    pub(crate) compressed_size: u64,
+    /// Block flags, including `FLAG_EXISTS`
+    pub(crate) flags: u32,
+}
 
-        let written_size = final_data.len() as u64;
-        writer.write_all(&final_data)?;
-        Ok((written_size, md5))
+/// Encode one file the way a build writes it, to be placed at `file_pos`
+///
+/// This is how [`MutableArchive`](crate::MutableArchive) stores new files
+/// with the same layouts as [`ArchiveBuilder`]. `file_pos` is relative to
+/// the archive start and only matters for `FLAG_FIX_KEY` encryption. The
+/// observer, if any, sees the file as the only one of a build and is handed
+/// back afterwards.
+pub(crate) fn encode_file(
+    file_data: &[u8],
+    archive_name: &str,
+    options: &AddFileOptions,
+    block_size: u16,
+    file_pos: u64,
+    observer: &mut Option<ObserverSlot>,
+) -> Result<EncodedFile> {
+    let mut builder = ArchiveBuilder::new()
+        .block_size(block_size)
+        .generate_crcs(options.sector_crc)
+        .use_implode(options.implode);
+    builder.observer = observer.take();
+    let params = FileWriteParams {
+        file_data,
+        file_size: file_data.len() as u64,
+        archive_name,
+        compression: options.compression,
+        compression_next: options.compression_next.unwrap_or(options.compression),
+        encrypt: options.encrypt,
+        use_fix_key: options.fix_key,
+        sector_size: crate::calculate_sector_size(block_size),
+        single_unit: options.single_unit,
+        file_pos,
+    };
+
+    let mut writer = std::io::Cursor::new(Vec::new());
+    let started = Instant::now();
+    builder.notify(|o| o.on_file_start(archive_name, 0, 1));
+    let written = builder.write_file(&mut writer, &params);
+    if let Ok((compressed_size, flags)) = written {
+        builder.file_done(
+            BuiltFile {
+                name: archive_name.to_string(),
+                file_size: params.file_size,
+                compressed_size,
+                flags: flags | BlockEntry::FLAG_EXISTS,
+            },
+            started,
+        );
     }
+    *observer = builder.observer.take();
+    let (compressed_size, flags) = written?;
+    // Zero sectors of stored files are skipped over rather than written
+    let end = writer.position() as usize;
+    let mut data = writer.into_inner();
+    data.resize(end, 0);
+
+    Ok(EncodedFile {
+        data,
+        compressed_size,
+        flags: flags | BlockEntry::FLAG_EXISTS,
+    })
 }
 
-impl Default for ArchiveBuilder {
-    fn default() -> Self {
-        Self::new()
+/// Sector offset table entry for a sector `offset` bytes into a file
+///
+/// # Errors
+/// `Error::CapacityExceeded` once the stored file passes 4 GiB, which its
+/// 32-bit offsets cannot reach
+fn sector_offset(archive_name: &str, offset: usize) -> Result<u32> {
+    u32::try_from(offset).map_err(|_| {
+        Error::CapacityExceeded(format!(
+            "{archive_name} is stored larger than 4 GiB, which compressed files cannot be"
+        ))
+    })
+}
+
+/// Write a bit-packed entry to a byte array
+fn write_bit_entry(data: &mut [u8], index: usize, value: u64, bit_size: u32) -> Result<()> {
+    write_bits(data, index * bit_size as usize, value, bit_size)
+}
+
+/// Write `bit_count` bits of `value` at `bit_offset` of a byte array
+///
+/// The counterpart of the table readers' `read_bits`; values of up to 64
+/// bits may start at any bit.
+fn write_bits(data: &mut [u8], bit_offset: usize, value: u64, bit_count: u32) -> Result<()> {
+    let byte_offset = bit_offset / 8;
+    let bit_shift = bit_offset % 8;
+    let bytes_needed = (bit_shift + bit_count as usize).div_ceil(8);
+
+    let Some(bytes) = data.get_mut(byte_offset..byte_offset + bytes_needed) else {
+        log::error!(
+            "Bit entry out of bounds: bit_offset={}, bit_count={}, data.len()={}",
+            bit_offset,
+            bit_count,
+            data.len()
+        );
+        return Err(Error::invalid_format("Bit entry out of bounds"));
+    };
+
+    let value_mask = if bit_count >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << bit_count) - 1
+    };
+    let mask = (value_mask as u128) << bit_shift;
+    let mut existing = bytes
+        .iter()
+        .rev()
+        .fold(0u128, |existing, &byte| (existing << 8) | byte as u128);
+    existing = (existing & !mask) | (((value & value_mask) as u128) << bit_shift);
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = (existing >> (i * 8)) as u8;
     }
+
+    Ok(())
 }
@@ -0,0 +1,427 @@
+//! On-disk cache of archive metadata
+//!
+//! Opening a large archive means reading and decrypting its hash and block
+//! tables, and listing it means decompressing and parsing its `(listfile)`.
+//! With [`OpenOptions::cache_dir`](crate::OpenOptions::cache_dir), the
+//! parsed tables and listfile names are stored in a cache file after the
+//! first open and read back on later ones, even from other processes. A
+//! cache file is only used while the archive's size and modification time,
+//! and the open options that change how tables are read, are the ones it
+//! was written for.
+//!
+//! HET and BET tables and `(attributes)` are always read from the archive.
+
+use crate::crypto::NameHashingPolicy;
+use crate::tables::{
+    BlockEntry, BlockTable, HashEntry, HashTable, HiBlockTable, ReadLittleEndian, TableKey,
+    TableOffsetPolicy,
+};
+use crate::{Error, Result};
+use md5::{Digest, Md5};
+use std::fs::{self, File, Metadata};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+/// Identifies a cache file's format and version
+const CACHE_MAGIC: &[u8; 8] = b"MPQCACH2";
+
+/// What a cache file holds for one archive
+#[derive(Debug, Default)]
+pub(crate) struct CachedMetadata {
+    pub(crate) hash_table: Option<Arc<HashTable>>,
+    pub(crate) block_table: Option<Arc<BlockTable>>,
+    pub(crate) hi_block_table: Option<Arc<HiBlockTable>>,
+    /// Names from the `(listfile)`, `None` if the archive has none
+    pub(crate) listfile_names: Option<Arc<Vec<String>>>,
+}
+
+/// Open options that change which tables and listfile names are read
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TableOptions {
+    pub(crate) hash_table_key: TableKey,
+    pub(crate) block_table_key: TableKey,
+    pub(crate) table_offsets: TableOffsetPolicy,
+    pub(crate) name_hashing: NameHashingPolicy,
+}
+
+impl TableOptions {
+    /// MD5 of a fixed encoding of the options
+    fn digest(&self) -> [u8; 16] {
+        let mut hasher = Md5::new();
+        for key in [self.hash_table_key, self.block_table_key] {
+            match key {
+                TableKey::Standard => hasher.update([0]),
+                TableKey::Fixed(key) => {
+                    hasher.update([1]);
+                    hasher.update(key.to_le_bytes());
+                }
+                TableKey::Recover => hasher.update([2]),
+            }
+        }
+        hasher.update([match self.table_offsets {
+            TableOffsetPolicy::Strict => 0,
+            TableOffsetPolicy::Lenient => 1,
+        }]);
+        hasher.update([self.name_hashing.case_sensitive as u8]);
+        match self.name_hashing.separator {
+            Some(separator) => hasher.update([1, separator]),
+            None => hasher.update([0]),
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// Number of entries the archive header declares for the classic tables
+///
+/// Cached tables of any other size are rejected rather than trusted.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TableCounts {
+    pub(crate) hash_table: u32,
+    pub(crate) block_table: u32,
+}
+
+/// The state of an archive file that a cache file is valid for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CacheKey {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+    options: [u8; 16],
+}
+
+impl CacheKey {
+    /// Key for the current state of a file with `metadata`, opened with
+    /// `options`
+    pub(crate) fn for_metadata(metadata: &Metadata, options: &TableOptions) -> Result<Self> {
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Self {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            options: options.digest(),
+        })
+    }
+}
+
+/// Cache file for the archive at `archive_path`
+///
+/// Named after the MD5 of the canonical path, so every archive gets its
+/// own file in a shared directory.
+pub(crate) fn cache_path(dir: &Path, archive_path: &Path) -> Result<PathBuf> {
+    let canonical = archive_path.canonicalize()?;
+    let digest = Md5::digest(canonical.to_string_lossy().as_bytes());
+    let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(dir.join(format!("{}.mpqcache", name)))
+}
+
+/// Read the cached metadata at `path` if it was written for `key`
+///
+/// Missing, stale and unreadable cache files all return `None`, as do cache
+/// files whose tables are not the sizes in `counts`.
+pub(crate) fn load(path: &Path, key: CacheKey, counts: TableCounts) -> Option<CachedMetadata> {
+    let file = File::open(path).ok()?;
+    match read_cache(&mut BufReader::new(file), key, counts) {
+        Ok(cached) => cached,
+        Err(e) => {
+            log::warn!("Ignoring unreadable cache file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Write `metadata` for the archive state `key` to `path`
+///
+/// The file is written next to its final location and renamed into place,
+/// so concurrent readers never see a partial cache file.
+pub(crate) fn store(path: &Path, key: CacheKey, metadata: &CachedMetadata) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| Error::invalid_format("Cache path has no directory"))?;
+    fs::create_dir_all(dir)?;
+    let mut temp_file = tempfile::NamedTempFile::new_in(dir)?;
+    {
+        let mut writer = BufWriter::new(temp_file.as_file_mut());
+        write_cache(&mut writer, key, metadata)?;
+        writer.flush()?;
+    }
+    temp_file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+fn read_cache<R: Read>(
+    reader: &mut R,
+    key: CacheKey,
+    counts: TableCounts,
+) -> Result<Option<CachedMetadata>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CACHE_MAGIC {
+        return Err(Error::invalid_format("Not a cache file of this version"));
+    }
+    let cached_key = CacheKey {
+        size: read_u64(reader)?,
+        modified_secs: read_u64(reader)?,
+        modified_nanos: reader.read_u32_le()?,
+        options: {
+            let mut options = [0u8; 16];
+            reader.read_exact(&mut options)?;
+            options
+        },
+    };
+    if cached_key != key {
+        log::debug!("Cache file is stale");
+        return Ok(None);
+    }
+
+    let hash_table = match read_present(reader)? {
+        false => None,
+        true => {
+            let count = read_count(reader, counts.hash_table, "hash")?;
+            let mut table = HashTable::new(count)?;
+            for entry in table.entries_mut() {
+                *entry = HashEntry {
+                    name_1: reader.read_u32_le()?,
+                    name_2: reader.read_u32_le()?,
+                    locale: reader.read_u16_le()?,
+                    platform: reader.read_u16_le()?,
+                    block_index: reader.read_u32_le()?,
+                };
+            }
+            Some(table)
+        }
+    };
+
+    let block_table = match read_present(reader)? {
+        false => None,
+        true => {
+            let count = read_count(reader, counts.block_table, "block")?;
+            let mut table = BlockTable::new(count)?;
+            for entry in table.entries_mut() {
+                *entry = BlockEntry {
+                    file_pos: reader.read_u32_le()?,
+                    compressed_size: reader.read_u32_le()?,
+                    file_size: reader.read_u32_le()?,
+                    flags: reader.read_u32_le()?,
+                };
+            }
+            Some(table)
+        }
+    };
+
+    let hi_block_table = match read_present(reader)? {
+        false => None,
+        true => {
+            let count = read_count(reader, counts.block_table, "hi-block")?;
+            let mut table = HiBlockTable::new(count);
+            for index in 0..count {
+                table.set(index, reader.read_u16_le()?);
+            }
+            Some(table)
+        }
+    };
+
+    let listfile_names = match read_present(reader)? {
+        false => None,
+        true => {
+            let count = reader.read_u32_le()? as usize;
+            let mut names = Vec::with_capacity(count.min(1 << 20));
+            for _ in 0..count {
+                let mut name = vec![0u8; reader.read_u32_le()? as usize];
+                reader.read_exact(&mut name)?;
+                names.push(
+                    String::from_utf8(name)
+                        .map_err(|_| Error::invalid_format("Cached name is not UTF-8"))?,
+                );
+            }
+            Some(names)
+        }
+    };
+
+    Ok(Some(CachedMetadata {
+        hash_table: hash_table.map(Arc::new),
+        block_table: block_table.map(Arc::new),
+        hi_block_table: hi_block_table.map(Arc::new),
+        listfile_names: listfile_names.map(Arc::new),
+    }))
+}
+
+fn write_cache<W: Write>(writer: &mut W, key: CacheKey, metadata: &CachedMetadata) -> Result<()> {
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_all(&key.size.to_le_bytes())?;
+    writer.write_all(&key.modified_secs.to_le_bytes())?;
+    writer.write_all(&key.modified_nanos.to_le_bytes())?;
+    writer.write_all(&key.options)?;
+
+    write_present(writer, metadata.hash_table.is_some())?;
+    if let Some(table) = &metadata.hash_table {
+        writer.write_all(&(table.size() as u32).to_le_bytes())?;
+        for entry in table.entries() {
+            writer.write_all(&entry.name_1.to_le_bytes())?;
+            writer.write_all(&entry.name_2.to_le_bytes())?;
+            writer.write_all(&entry.locale.to_le_bytes())?;
+            writer.write_all(&entry.platform.to_le_bytes())?;
+            writer.write_all(&entry.block_index.to_le_bytes())?;
+        }
+    }
+
+    write_present(writer, metadata.block_table.is_some())?;
+    if let Some(table) = &metadata.block_table {
+        writer.write_all(&(table.size() as u32).to_le_bytes())?;
+        for entry in table.entries() {
+            writer.write_all(&entry.file_pos.to_le_bytes())?;
+            writer.write_all(&entry.compressed_size.to_le_bytes())?;
+            writer.write_all(&entry.file_size.to_le_bytes())?;
+            writer.write_all(&entry.flags.to_le_bytes())?;
+        }
+    }
+
+    write_present(writer, metadata.hi_block_table.is_some())?;
+    if let Some(table) = &metadata.hi_block_table {
+        writer.write_all(&(table.entries().len() as u32).to_le_bytes())?;
+        for entry in table.entries() {
+            writer.write_all(&entry.to_le_bytes())?;
+        }
+    }
+
+    write_present(writer, metadata.listfile_names.is_some())?;
+    if let Some(names) = &metadata.listfile_names {
+        writer.write_all(&(names.len() as u32).to_le_bytes())?;
+        for name in names.iter() {
+            writer.write_all(&(name.len() as u32).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read a cached table's entry count, which has to be `expected`
+fn read_count<R: Read>(reader: &mut R, expected: u32, table: &str) -> Result<usize> {
+    let count = reader.read_u32_le()?;
+    if count != expected {
+        return Err(Error::invalid_format(format!(
+            "Cached {} table has {} entries, the archive header declares {}",
+            table, count, expected
+        )));
+    }
+    Ok(count as usize)
+}
+
+fn read_present<R: Read>(reader: &mut R) -> Result<bool> {
+    let mut flag = [0u8; 1];
+    reader.read_exact(&mut flag)?;
+    Ok(flag[0] != 0)
+}
+
+fn write_present<W: Write>(writer: &mut W, present: bool) -> Result<()> {
+    writer.write_all(&[present as u8])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_round_trip() {
+        let options = TableOptions {
+            hash_table_key: TableKey::Standard,
+            block_table_key: TableKey::Standard,
+            table_offsets: TableOffsetPolicy::Strict,
+            name_hashing: NameHashingPolicy::BLIZZARD,
+        };
+        let key = CacheKey {
+            size: 1234,
+            modified_secs: 1_700_000_000,
+            modified_nanos: 42,
+            options: options.digest(),
+        };
+        let counts = TableCounts {
+            hash_table: 4,
+            block_table: 1,
+        };
+        let mut hash_table = HashTable::new(4).unwrap();
+        hash_table.entries_mut()[1] = HashEntry {
+            name_1: 0x1111_2222,
+            name_2: 0x3333_4444,
+            locale: 0x409,
+            platform: 0,
+            block_index: 0,
+        };
+        let mut block_table = BlockTable::new(1).unwrap();
+        block_table.entries_mut()[0] = BlockEntry {
+            file_pos: 32,
+            compressed_size: 10,
+            file_size: 20,
+            flags: BlockEntry::FLAG_EXISTS,
+        };
+        let mut hi_block_table = HiBlockTable::new(1);
+        hi_block_table.set(0, 1);
+        let metadata = CachedMetadata {
+            hash_table: Some(Arc::new(hash_table)),
+            block_table: Some(Arc::new(block_table)),
+            hi_block_table: Some(Arc::new(hi_block_table)),
+            listfile_names: Some(Arc::new(vec![
+                "a.txt".to_string(),
+                "Data\\b.bin".to_string(),
+            ])),
+        };
+
+        let mut data = Vec::new();
+        write_cache(&mut data, key, &metadata).unwrap();
+        let cached = read_cache(&mut data.as_slice(), key, counts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cached.hash_table.unwrap().entries(),
+            metadata.hash_table.as_ref().unwrap().entries()
+        );
+        assert_eq!(
+            cached.block_table.unwrap().entries(),
+            metadata.block_table.as_ref().unwrap().entries()
+        );
+        assert_eq!(cached.hi_block_table.unwrap().entries(), [1]);
+        assert_eq!(cached.listfile_names, metadata.listfile_names);
+
+        // Any change to the archive file makes the cache stale
+        let touched = CacheKey {
+            modified_nanos: 43,
+            ..key
+        };
+        assert!(read_cache(&mut data.as_slice(), touched, counts)
+            .unwrap()
+            .is_none());
+        assert!(read_cache(&mut &data[..20], key, counts).is_err());
+
+        // So does opening it with options that change how tables are read
+        let recovering = CacheKey {
+            options: TableOptions {
+                hash_table_key: TableKey::Recover,
+                ..options
+            }
+            .digest(),
+            ..key
+        };
+        assert!(read_cache(&mut data.as_slice(), recovering, counts)
+            .unwrap()
+            .is_none());
+
+        // Tables of another size than the header declares are rejected
+        let grown = TableCounts {
+            block_table: 2,
+            ..counts
+        };
+        assert!(read_cache(&mut data.as_slice(), key, grown).is_err());
+    }
+}
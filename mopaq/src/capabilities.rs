@@ -0,0 +1,135 @@
+//! Features compiled into this build
+//!
+//! Some codecs and I/O backends sit behind cargo features. [`capabilities`]
+//! reports which of them this build of the crate has, so a tool can
+//! explain up front why an archive cannot be read instead of failing on
+//! the first compressed sector.
+
+use std::fmt;
+
+use crate::compression::CompressionMethod;
+
+/// Codecs and optional features available in this build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// zlib (deflate) compression
+    pub zlib: bool,
+    /// BZip2 compression, feature `compression-bzip2`
+    pub bzip2: bool,
+    /// LZMA compression, feature `compression-lzma`
+    pub lzma: bool,
+    /// PKWare DCL and implode compression
+    pub pkware: bool,
+    /// Huffman compression
+    pub huffman: bool,
+    /// IMA ADPCM audio compression
+    pub adpcm: bool,
+    /// Sparse (run-length) compression
+    pub sparse: bool,
+    /// Memory-mapped I/O, feature `mmap`
+    pub mmap: bool,
+    /// Parallel compression in the builder, feature `parallel`
+    pub parallel: bool,
+    /// Async support, feature `async`
+    pub async_io: bool,
+    /// On-disk metadata cache, feature `cache`
+    pub cache: bool,
+}
+
+impl Capabilities {
+    /// Check whether data compressed with `method` can be decompressed
+    pub fn supports(&self, method: CompressionMethod) -> bool {
+        method.is_supported()
+    }
+
+    /// Name and availability of each codec, in a fixed order
+    pub fn codecs(&self) -> [(&'static str, bool); 7] {
+        [
+            ("zlib", self.zlib),
+            ("bzip2", self.bzip2),
+            ("lzma", self.lzma),
+            ("pkware", self.pkware),
+            ("huffman", self.huffman),
+            ("adpcm", self.adpcm),
+            ("sparse", self.sparse),
+        ]
+    }
+
+    /// Name and availability of each optional feature, in a fixed order
+    pub fn features(&self) -> [(&'static str, bool); 4] {
+        [
+            ("mmap", self.mmap),
+            ("parallel", self.parallel),
+            ("async", self.async_io),
+            ("cache", self.cache),
+        ]
+    }
+}
+
+impl fmt::Display for Capabilities {
+    /// Lists what is available, e.g. `codecs: zlib, bzip2; features: mmap`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let enabled = |items: &[(&str, bool)]| {
+            let names: Vec<&str> = items
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| *name)
+                .collect();
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        };
+        write!(
+            f,
+            "codecs: {}; features: {}",
+            enabled(&self.codecs()),
+            enabled(&self.features())
+        )
+    }
+}
+
+/// Report the codecs and features compiled into this build
+///
+/// # Examples
+///
+/// ```
+/// use mopaq::compression::CompressionMethod;
+///
+/// let caps = mopaq::capabilities();
+/// assert!(caps.zlib);
+/// if !caps.supports(CompressionMethod::Lzma) {
+///     eprintln!("LZMA archives need the compression-lzma feature");
+/// }
+/// ```
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        zlib: true,
+        bzip2: cfg!(feature = "compression-bzip2"),
+        lzma: cfg!(feature = "compression-lzma"),
+        pkware: true,
+        huffman: true,
+        adpcm: true,
+        sparse: true,
+        mmap: cfg!(feature = "mmap"),
+        parallel: cfg!(feature = "parallel"),
+        async_io: cfg!(feature = "async"),
+        cache: cfg!(feature = "cache"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_match_features() {
+        let caps = capabilities();
+        assert_eq!(caps.bzip2, cfg!(feature = "compression-bzip2"));
+        assert_eq!(caps.supports(CompressionMethod::BZip2), caps.bzip2);
+        assert_eq!(caps.supports(CompressionMethod::Lzma), caps.lzma);
+        assert!(caps.supports(CompressionMethod::Zlib));
+        assert!(caps.to_string().starts_with("codecs: zlib"));
+    }
+}
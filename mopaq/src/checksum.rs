@@ -0,0 +1,142 @@
+//! Sector checksum algorithms and MD5 helpers
+//!
+//! Files with the `FLAG_SECTOR_CRC` flag store one 32-bit checksum per sector.
+//! Despite the flag's name, Blizzard's tools compute these with ADLER32, and
+//! that is what this crate writes by default. Some third-party tools write a
+//! genuine CRC32 instead, so readers can either be told which algorithm an
+//! archive uses or detect it from the stored values.
+//!
+//! The free functions are the ones the archive reader and writer use, for
+//! tools that produce raw sectors or check dumps themselves.
+
+use md5::{Digest, Md5};
+
+/// Checksum of one sector, as written to a file's sector checksum table
+///
+/// `data` is the sector as stored but not encrypted: compressed sectors are
+/// checksummed after compression, and encrypted files before encryption.
+/// This is ADLER32, the same as [`SectorChecksum::Adler32`].
+pub fn sector_checksum(data: &[u8]) -> u32 {
+    SectorChecksum::Adler32.compute(data)
+}
+
+/// MD5 digest of `data`
+///
+/// MPQ archives store MD5s of whole files in `(attributes)`, and v4 headers
+/// store MD5s of each table as written. The header's own MD5 only covers
+/// part of it; see [`header_md5`](crate::mpq_header::header_md5).
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    Md5::digest(data).into()
+}
+
+/// Algorithm used for per-sector checksums
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SectorChecksum {
+    /// ADLER32, as written by Blizzard's tools and StormLib
+    #[default]
+    Adler32,
+    /// CRC32 (IEEE), as written by some third-party tools
+    Crc32,
+}
+
+impl SectorChecksum {
+    /// Compute the checksum of `data` with this algorithm
+    pub fn compute(self, data: &[u8]) -> u32 {
+        match self {
+            SectorChecksum::Adler32 => adler::adler32_slice(data),
+            SectorChecksum::Crc32 => crc32fast::hash(data),
+        }
+    }
+
+    /// Find the algorithm that produced `expected` for `data`
+    ///
+    /// ADLER32 is tried first since it is the canonical algorithm. Returns
+    /// `None` if neither algorithm matches.
+    pub fn detect(data: &[u8], expected: u32) -> Option<Self> {
+        [SectorChecksum::Adler32, SectorChecksum::Crc32]
+            .into_iter()
+            .find(|algorithm| algorithm.compute(data) == expected)
+    }
+
+    /// Check `data` against a stored checksum
+    ///
+    /// With `algorithm` set, only that algorithm is accepted; with `None`,
+    /// either algorithm is. A stored value of zero means the writer left the
+    /// checksum out, which StormLib also accepts, so it always verifies.
+    ///
+    /// On mismatch, returns the checksum actually computed for `data` (with
+    /// ADLER32 when auto-detecting) so callers can report it.
+    pub fn verify(algorithm: Option<Self>, data: &[u8], expected: u32) -> Result<(), u32> {
+        if expected == 0 {
+            return Ok(());
+        }
+        match algorithm {
+            Some(algorithm) => {
+                let actual = algorithm.compute(data);
+                if actual == expected {
+                    Ok(())
+                } else {
+                    Err(actual)
+                }
+            }
+            None => Self::detect(data, expected)
+                .map(|_| ())
+                .ok_or_else(|| SectorChecksum::Adler32.compute(data)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_known_values() {
+        assert_eq!(SectorChecksum::Adler32.compute(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(SectorChecksum::Crc32.compute(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_helpers() {
+        let sector = b"raw sector bytes";
+        assert_eq!(
+            sector_checksum(sector),
+            SectorChecksum::Adler32.compute(sector)
+        );
+        assert_eq!(
+            md5(b""),
+            [
+                0xD4, 0x1D, 0x8C, 0xD9, 0x8F, 0x00, 0xB2, 0x04, 0xE9, 0x80, 0x09, 0x98, 0xEC, 0xF8,
+                0x42, 0x7E
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_and_verify() {
+        let data = b"sector payload";
+        let adler = SectorChecksum::Adler32.compute(data);
+        let crc = SectorChecksum::Crc32.compute(data);
+
+        assert_eq!(
+            SectorChecksum::detect(data, adler),
+            Some(SectorChecksum::Adler32)
+        );
+        assert_eq!(
+            SectorChecksum::detect(data, crc),
+            Some(SectorChecksum::Crc32)
+        );
+        assert_eq!(SectorChecksum::detect(data, 0x1234_5678), None);
+
+        // Auto-detection accepts either algorithm, explicit modes only their own
+        assert!(SectorChecksum::verify(None, data, crc).is_ok());
+        assert!(SectorChecksum::verify(Some(SectorChecksum::Crc32), data, crc).is_ok());
+        assert_eq!(
+            SectorChecksum::verify(Some(SectorChecksum::Adler32), data, crc),
+            Err(adler)
+        );
+
+        // A zero checksum means "not stored"
+        assert!(SectorChecksum::verify(Some(SectorChecksum::Crc32), data, 0).is_ok());
+    }
+}
@@ -0,0 +1,153 @@
+//! Presets for building archives a specific game can read
+//!
+//! Every game ships its own build of Storm, Blizzard's MPQ library, which only
+//! understands the format versions and codecs that existed when it was
+//! released. Passing a [`Compatibility`] target to
+//! [`ArchiveBuilder::compatibility`](crate::ArchiveBuilder::compatibility)
+//! picks defaults the game reads and makes `build()` refuse settings it
+//! would not.
+
+use std::fmt;
+
+use crate::compression::flags;
+use crate::header::FormatVersion;
+
+/// Codecs understood by every game that reads compressed sectors
+const CLASSIC_CODECS: u8 = flags::PKWARE | flags::HUFFMAN | flags::ADPCM_MONO | flags::ADPCM_STEREO;
+
+/// zlib and BZip2 were added with Warcraft III
+const WARCRAFT3_CODECS: u8 = CLASSIC_CODECS | flags::ZLIB | flags::BZIP2;
+
+/// Sparse (and LZMA, which is not a combinable flag) came with Cataclysm
+const CATACLYSM_CODECS: u8 = WARCRAFT3_CODECS | flags::SPARSE;
+
+/// A game client an archive is built for
+///
+/// # Examples
+///
+/// ```
+/// use mopaq::compression::flags;
+/// use mopaq::{Compatibility, FormatVersion};
+///
+/// let target = Compatibility::WowTBC;
+/// assert_eq!(target.max_version(), FormatVersion::V2);
+/// assert!(target.supports_compression(flags::BZIP2));
+/// assert!(!target.supports_compression(flags::LZMA));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compatibility {
+    /// StarCraft and Brood War
+    StarCraft,
+    /// Diablo II and Lord of Destruction
+    Diablo2,
+    /// Warcraft III and The Frozen Throne
+    Warcraft3,
+    /// World of Warcraft before The Burning Crusade
+    WowClassic,
+    /// World of Warcraft: The Burning Crusade
+    WowTBC,
+    /// World of Warcraft: Wrath of the Lich King
+    WowWotLK,
+    /// World of Warcraft: Cataclysm and later expansions
+    WowCataclysm,
+}
+
+impl Compatibility {
+    /// All targets, oldest first
+    pub const ALL: [Compatibility; 7] = [
+        Compatibility::StarCraft,
+        Compatibility::Diablo2,
+        Compatibility::Warcraft3,
+        Compatibility::WowClassic,
+        Compatibility::WowTBC,
+        Compatibility::WowWotLK,
+        Compatibility::WowCataclysm,
+    ];
+
+    /// Newest format version the game opens
+    pub fn max_version(self) -> FormatVersion {
+        match self {
+            Compatibility::StarCraft
+            | Compatibility::Diablo2
+            | Compatibility::Warcraft3
+            | Compatibility::WowClassic => FormatVersion::V1,
+            Compatibility::WowTBC | Compatibility::WowWotLK => FormatVersion::V2,
+            Compatibility::WowCataclysm => FormatVersion::V4,
+        }
+    }
+
+    /// Largest block size the game reads, if it is limited
+    ///
+    /// The pre-WoW games were only ever shipped with 4 KiB sectors
+    /// (block size 3).
+    pub fn max_block_size(self) -> Option<u16> {
+        match self {
+            Compatibility::StarCraft | Compatibility::Diablo2 | Compatibility::Warcraft3 => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Compression the game's own archives use for ordinary files
+    pub fn default_compression(self) -> u8 {
+        match self {
+            Compatibility::StarCraft | Compatibility::Diablo2 => flags::PKWARE,
+            _ => flags::ZLIB,
+        }
+    }
+
+    /// Check whether the game can decompress data stored with `compression`
+    ///
+    /// `compression` is a value from [`compression::flags`](crate::compression::flags),
+    /// possibly several codecs combined as in `ADPCM_MONO | HUFFMAN`. Data
+    /// stored without compression (0) is always readable.
+    pub fn supports_compression(self, compression: u8) -> bool {
+        if compression == flags::LZMA {
+            return self == Compatibility::WowCataclysm;
+        }
+        let codecs = match self {
+            Compatibility::StarCraft | Compatibility::Diablo2 => CLASSIC_CODECS,
+            Compatibility::WowCataclysm => CATACLYSM_CODECS,
+            _ => WARCRAFT3_CODECS,
+        };
+        compression & !codecs == 0
+    }
+}
+
+impl fmt::Display for Compatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compatibility::StarCraft => "StarCraft",
+            Compatibility::Diablo2 => "Diablo II",
+            Compatibility::Warcraft3 => "Warcraft III",
+            Compatibility::WowClassic => "World of Warcraft (classic)",
+            Compatibility::WowTBC => "World of Warcraft: The Burning Crusade",
+            Compatibility::WowWotLK => "World of Warcraft: Wrath of the Lich King",
+            Compatibility::WowCataclysm => "World of Warcraft: Cataclysm",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_support() {
+        let d2 = Compatibility::Diablo2;
+        assert!(d2.supports_compression(0));
+        assert!(d2.supports_compression(flags::ADPCM_STEREO | flags::HUFFMAN));
+        assert!(!d2.supports_compression(flags::ZLIB));
+        assert!(d2.supports_compression(d2.default_compression()));
+
+        // LZMA shares its bits with zlib and BZip2 but is a codec of its own
+        assert!(Compatibility::WowWotLK.supports_compression(flags::ZLIB));
+        assert!(Compatibility::WowWotLK.supports_compression(flags::BZIP2));
+        assert!(!Compatibility::WowWotLK.supports_compression(flags::LZMA));
+        assert!(Compatibility::WowCataclysm.supports_compression(flags::LZMA));
+        assert!(!Compatibility::WowWotLK.supports_compression(flags::SPARSE));
+
+        for target in Compatibility::ALL {
+            assert!(target.supports_compression(target.default_compression()));
+        }
+    }
+}
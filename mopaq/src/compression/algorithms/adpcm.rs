@@ -4,6 +4,7 @@
 //! Differential Pulse Code Modulation) compression algorithm used in MPQ archives
 //! for compressing audio data, particularly WAV files.
 
+use crate::compression::output_buffer;
 use crate::error::{Error, Result};
 
 /// Maximum number of channels supported
@@ -219,7 +220,7 @@ fn decompress_internal(input: &[u8], output_size: usize, channel_count: usize) -
     let mut step_indexes = vec![INITIAL_ADPCM_STEP_INDEX; channel_count];
 
     // Allocate output buffer
-    let mut output = Vec::with_capacity(output_size);
+    let mut output = output_buffer(output_size);
 
     // Read initial samples for each channel
     for predicted_sample in predicted_samples.iter_mut().take(channel_count) {
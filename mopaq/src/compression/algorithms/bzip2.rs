@@ -1,29 +1,21 @@
 //! BZip2 compression and decompression
 
+use super::LimitedOutput;
 use crate::{Error, Result};
 use bzip2::read::BzDecoder;
 use bzip2::write::BzEncoder;
 use bzip2::Compression;
-use std::io::{Read, Write};
+use std::io::{self, Write};
 
-/// Decompress using BZip2
-pub(crate) fn decompress(data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
+/// Decompress using BZip2, producing at most `limit` bytes
+pub(crate) fn decompress(data: &[u8], expected_size: usize, limit: usize) -> Result<Vec<u8>> {
     let mut decoder = BzDecoder::new(data);
-    let mut decompressed = Vec::with_capacity(expected_size);
-
-    decoder
-        .read_to_end(&mut decompressed)
-        .map_err(|e| Error::compression(format!("BZip2 decompression failed: {}", e)))?;
-
-    if decompressed.len() != expected_size {
-        return Err(Error::compression(format!(
-            "Decompressed size mismatch: expected {}, got {}",
-            expected_size,
-            decompressed.len()
-        )));
-    }
+    let mut output = LimitedOutput::new(expected_size, limit);
+    let result = io::copy(&mut decoder, &mut output);
 
-    Ok(decompressed)
+    output.finish(result, |e| {
+        Error::compression(format!("BZip2 decompression failed: {}", e))
+    })
 }
 
 /// Compress using BZip2
@@ -55,7 +47,8 @@ mod tests {
             compressed.len()
         );
 
-        let decompressed = decompress(&compressed, original.len()).expect("Decompression failed");
+        let decompressed =
+            decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
 
         assert_eq!(decompressed, original);
     }
@@ -3,6 +3,7 @@
 //! This is a simplified port of the StormLib Huffman implementation, primarily used for WAVE files.
 //! Based on the algorithm from Ladislav Zezula's StormLib.
 
+use crate::compression::output_buffer;
 use crate::{Error, Result};
 
 // Huffman tree constants
@@ -375,7 +376,7 @@ pub(crate) fn decompress(data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
     );
 
     let mut reader = BitReader::new(data);
-    let mut output = Vec::with_capacity(expected_size);
+    let mut output = output_buffer(expected_size);
 
     // Get compression type from the first byte
     let compression_type = reader.get_8_bits()?;
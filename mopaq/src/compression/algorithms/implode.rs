@@ -1,17 +1,19 @@
 //! PKWare Implode compression/decompression implementation
 //!
-//! This implements the PKWare Implode algorithm using the pklib crate.
-//! Based on the StormLib implementation.
+//! IMPLODE data is a PKWare DCL stream without its header, decoded with
+//! the explode in [`pkware`](super::pkware). Based on the StormLib
+//! implementation.
 
+use super::pkware::explode;
 use crate::{Error, Result};
-use pklib::explode_bytes;
 
 /// PKWare Implode decompression
 ///
 /// This is used specifically for HET/BET table decompression in newer MPQ archives.
-/// IMPLODE data in MPQ files is raw compressed data without the header that explode_bytes expects,
+/// IMPLODE data in MPQ files is raw compressed data without the header that explode expects,
 /// so we need to prepend the appropriate header based on StormLib's algorithm.
-pub(crate) fn decompress(data: &[u8], _expected_size: usize) -> Result<Vec<u8>> {
+/// Each attempt produces at most `limit` bytes.
+pub(crate) fn decompress(data: &[u8], expected_size: usize, limit: usize) -> Result<Vec<u8>> {
     if data.is_empty() {
         return Ok(Vec::new());
     }
@@ -25,6 +27,7 @@ pub(crate) fn decompress(data: &[u8], _expected_size: usize) -> Result<Vec<u8>>
     // Try all combinations of compression type and dictionary size
     let compression_types = [0u8, 1u8]; // Binary, ASCII
     let dict_sizes = [4u8, 5u8, 6u8]; // 1KB, 2KB, 4KB dictionaries
+    let mut limit_hit = None;
 
     for &compression_type in &compression_types {
         for &dict_size_bits in &dict_sizes {
@@ -47,7 +50,7 @@ pub(crate) fn decompress(data: &[u8], _expected_size: usize) -> Result<Vec<u8>>
             );
 
             // Try this combination
-            match explode_bytes(&header_data) {
+            match explode(&header_data, expected_size, limit) {
                 Ok(result) => {
                     log::info!(
                         "IMPLODE decompress SUCCESS: mode={}, dict={}KB, output={} bytes",
@@ -65,6 +68,11 @@ pub(crate) fn decompress(data: &[u8], _expected_size: usize) -> Result<Vec<u8>>
                     );
                     return Ok(result);
                 }
+                Err(e @ Error::ExpansionLimit { .. }) => {
+                    // A wrong header can decode to garbage that runs past the
+                    // limit, so the remaining headers are still tried
+                    limit_hit = Some(e);
+                }
                 Err(e) => {
                     log::debug!(
                         "IMPLODE failed with mode={}, dict={}KB: {}",
@@ -83,6 +91,9 @@ pub(crate) fn decompress(data: &[u8], _expected_size: usize) -> Result<Vec<u8>>
     }
 
     // If we get here, all attempts failed
+    if let Some(e) = limit_hit {
+        return Err(e);
+    }
     log::error!("IMPLODE decompression failed with all attempted modes");
     Err(Error::compression(
         "IMPLODE decompression failed with all attempted compression modes",
@@ -107,7 +118,7 @@ mod tests {
 
     #[test]
     fn test_implode_empty_data() {
-        assert!(decompress(&[], 0).is_ok());
+        assert!(decompress(&[], 0, 0).is_ok());
         assert!(compress(&[]).is_ok());
     }
 
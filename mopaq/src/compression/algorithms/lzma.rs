@@ -1,49 +1,40 @@
 //! LZMA compression and decompression
 
+use super::LimitedOutput;
 use crate::{Error, Result};
 use std::io::{BufReader, Cursor};
 
-/// Decompress using LZMA
-pub(crate) fn decompress(data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
-    let cursor = Cursor::new(data);
-    let mut input = BufReader::new(cursor);
-    let mut output = Vec::with_capacity(expected_size);
-
-    // Try LZMA format first
-    match lzma_rs::lzma_decompress(&mut input, &mut output) {
-        Ok(()) => {
-            if expected_size > 0 && output.len() != expected_size {
-                log::warn!(
-                    "LZMA decompressed size mismatch: expected {}, got {}",
-                    expected_size,
-                    output.len()
-                );
-            }
-            Ok(output)
-        }
-        Err(e) => {
-            // If LZMA fails, try XZ format
-            let cursor = Cursor::new(data);
-            let mut input = BufReader::new(cursor);
-            let mut output = Vec::with_capacity(expected_size);
-
-            match lzma_rs::xz_decompress(&mut input, &mut output) {
-                Ok(()) => Ok(output),
-                Err(xz_err) => {
-                    log::error!("LZMA decompression failed: {:?}", e);
-                    log::error!("XZ decompression also failed: {:?}", xz_err);
-                    log::debug!(
-                        "First 16 bytes of data: {:02X?}",
-                        &data[..16.min(data.len())]
-                    );
-                    Err(Error::compression(format!(
-                        "LZMA/XZ decompression failed: LZMA: {:?}, XZ: {:?}",
-                        e, xz_err
-                    )))
-                }
-            }
+/// Decompress using LZMA, producing at most `limit` bytes
+pub(crate) fn decompress(data: &[u8], expected_size: usize, limit: usize) -> Result<Vec<u8>> {
+    let mut input = BufReader::new(Cursor::new(data));
+    let mut output = LimitedOutput::new(expected_size, limit);
+
+    // Try LZMA format first; hitting the limit is final either way
+    let lzma_err = match lzma_rs::lzma_decompress(&mut input, &mut output) {
+        Err(e) if !output.exceeded() => e,
+        result => {
+            return output.finish(result, |e| {
+                Error::compression(format!("LZMA decompression failed: {:?}", e))
+            })
         }
-    }
+    };
+
+    // If LZMA fails, try XZ format
+    let mut input = BufReader::new(Cursor::new(data));
+    let mut output = LimitedOutput::new(expected_size, limit);
+    let result = lzma_rs::xz_decompress(&mut input, &mut output);
+    output.finish(result, |xz_err| {
+        log::error!("LZMA decompression failed: {:?}", lzma_err);
+        log::error!("XZ decompression also failed: {:?}", xz_err);
+        log::debug!(
+            "First 16 bytes of data: {:02X?}",
+            &data[..16.min(data.len())]
+        );
+        Error::compression(format!(
+            "LZMA/XZ decompression failed: LZMA: {:?}, XZ: {:?}",
+            lzma_err, xz_err
+        ))
+    })
 }
 
 /// Compress using LZMA
@@ -79,7 +70,8 @@ mod tests {
             compressed.len()
         );
 
-        let decompressed = decompress(&compressed, original.len()).expect("Decompression failed");
+        let decompressed =
+            decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
 
         assert_eq!(decompressed, original);
     }
@@ -96,7 +88,8 @@ mod tests {
         lzma_rs::xz_compress(&mut input, &mut compressed).expect("XZ compression failed");
 
         // Our decompress function should handle XZ format as fallback
-        let decompressed = decompress(&compressed, original.len()).expect("Decompression failed");
+        let decompressed =
+            decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
 
         assert_eq!(decompressed, original);
     }
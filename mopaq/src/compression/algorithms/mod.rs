@@ -1,10 +1,112 @@
 //! Compression algorithm implementations
 
 pub(super) mod adpcm;
+#[cfg(feature = "compression-bzip2")]
 pub(super) mod bzip2;
 pub(super) mod huffman;
 pub(super) mod implode;
+#[cfg(feature = "compression-lzma")]
 pub(super) mod lzma;
 pub(super) mod pkware;
 pub(super) mod sparse;
 pub(super) mod zlib;
+
+use super::output_buffer;
+use crate::{Error, Result};
+use std::io::{self, Write};
+
+/// Decompression output that refuses to grow beyond a limit
+///
+/// Streaming codecs write into this instead of an unbounded `Vec`, so a
+/// stream that keeps producing data is cut off as soon as it passes the
+/// limit rather than after it has exhausted memory.
+pub(super) struct LimitedOutput {
+    data: Vec<u8>,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl LimitedOutput {
+    pub(super) fn new(expected_size: usize, limit: usize) -> Self {
+        Self {
+            data: output_buffer(expected_size.min(limit)),
+            limit,
+            exceeded: false,
+        }
+    }
+
+    /// Whether a write was refused because of the limit
+    #[cfg(feature = "compression-lzma")]
+    pub(super) fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    /// The decompressed data, given the codec's result for the stream
+    ///
+    /// A codec failure caused by the limit is reported as
+    /// `Error::ExpansionLimit`; other failures go through `on_error`.
+    pub(super) fn finish<T, E>(
+        self,
+        result: std::result::Result<T, E>,
+        on_error: impl FnOnce(E) -> Error,
+    ) -> Result<Vec<u8>> {
+        if self.exceeded {
+            return Err(Error::ExpansionLimit {
+                limit: self.limit as u64,
+            });
+        }
+        result.map_err(on_error)?;
+        Ok(self.data)
+    }
+}
+
+impl Write for LimitedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.limit - self.data.len() {
+            self.exceeded = true;
+            return Err(io::Error::other("decompression expansion limit exceeded"));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stand-in for a codec whose feature is disabled, failing every call
+#[allow(unused_macros)]
+macro_rules! disabled_codec {
+    ($name:ident, $codec:literal, $feature:literal) => {
+        pub(super) mod $name {
+            use crate::{Error, Result};
+
+            fn disabled() -> Error {
+                Error::compression(concat!(
+                    $codec,
+                    " support is not compiled in (enable the `",
+                    $feature,
+                    "` feature)"
+                ))
+            }
+
+            pub(crate) fn decompress(
+                _data: &[u8],
+                _expected_size: usize,
+                _limit: usize,
+            ) -> Result<Vec<u8>> {
+                Err(disabled())
+            }
+
+            pub(crate) fn compress(_data: &[u8]) -> Result<Vec<u8>> {
+                Err(disabled())
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "compression-bzip2"))]
+disabled_codec!(bzip2, "BZip2", "compression-bzip2");
+#[cfg(not(feature = "compression-lzma"))]
+disabled_codec!(lzma, "LZMA", "compression-lzma");
@@ -0,0 +1,426 @@
+//! PKWare Data Compression Library (DCL) implode and explode
+//!
+//! A stream starts with two bytes: the literal mode (0 for binary, 1 for
+//! ASCII) and the dictionary size as a number of bits (4, 5 or 6 for 1, 2
+//! or 4 KiB). Then follow literals and copies of earlier output, read from
+//! the least significant bit of each byte, ending with a copy of length 519.
+
+mod tables;
+
+use super::super::output_buffer;
+use crate::{Error, Result};
+use std::sync::LazyLock;
+use tables::{
+    CH_BITS_ASC, CH_CODE_ASC, DIST_BITS, DIST_CODE, EX_LEN_BITS, LEN_BASE, LEN_BITS, LEN_CODE,
+};
+
+/// Length value that marks the end of the stream instead of a copy
+const END_OF_STREAM: usize = 0x205;
+
+/// Longest copy a stream can encode
+const MAX_COPY: usize = END_OF_STREAM + 1;
+
+/// How literal bytes are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LiteralMode {
+    /// Eight bits per byte
+    #[allow(dead_code)]
+    Binary,
+    /// Shorter codes for common text characters
+    Ascii,
+}
+
+/// Compress data using PKWare DCL
+///
+/// Uses ASCII literals and a 2 KiB dictionary.
+pub(crate) fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    // Handle empty data
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(implode(data, LiteralMode::Ascii, 5))
+}
+
+/// Decompress PKWare DCL data, producing at most `limit` bytes
+pub(crate) fn decompress(data: &[u8], expected_size: usize, limit: usize) -> Result<Vec<u8>> {
+    // Handle empty data
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    explode(data, expected_size, limit).map_err(|e| {
+        log::error!(
+            "PKWare decompression failed with input size {}: {}",
+            data.len(),
+            e
+        );
+        e
+    })
+}
+
+/// Compress data using PKWare DCL with a given literal mode and dictionary
+/// size in bits (4 to 6)
+pub(crate) fn implode(data: &[u8], mode: LiteralMode, dict_bits: u8) -> Vec<u8> {
+    debug_assert!((4..=6).contains(&dict_bits));
+    let window = 64 << dict_bits;
+    let mut writer = BitWriter {
+        data: Vec::with_capacity(data.len() / 2 + 4),
+        buffer: 0,
+        count: 0,
+    };
+    writer.data.push((mode == LiteralMode::Ascii) as u8);
+    writer.data.push(dict_bits);
+
+    // Chains of earlier positions starting with the same two bytes
+    let pair = |pos: usize| usize::from(data[pos]) | usize::from(data[pos + 1]) << 8;
+    let mut head = vec![usize::MAX; 1 << 16];
+    let mut previous = vec![usize::MAX; data.len()];
+    let insert = |pos: usize, head: &mut [usize], previous: &mut [usize]| {
+        if pos + 1 < data.len() {
+            previous[pos] = head[pair(pos)];
+            head[pair(pos)] = pos;
+        }
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut length, mut distance) = (0, 0);
+        if pos + 1 < data.len() {
+            let longest = MAX_COPY.min(data.len() - pos);
+            let mut candidate = head[pair(pos)];
+            let mut tries = 256;
+            while candidate != usize::MAX && pos - candidate <= window && tries > 0 {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + longest])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                // Copies of two bytes only have room for short distances
+                if len > length && (len > 2 || pos - candidate <= 0x100) {
+                    length = len;
+                    distance = pos - candidate;
+                    if len == longest {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                tries -= 1;
+            }
+        }
+
+        if length >= 2 {
+            writer.write_length(length - 2);
+            let distance = distance - 1;
+            let low_bits = if length == 2 { 2 } else { dict_bits };
+            let slot = distance >> low_bits;
+            writer.write(u32::from(DIST_CODE[slot]), DIST_BITS[slot]);
+            writer.write((distance & ((1 << low_bits) - 1)) as u32, low_bits);
+            for copied in pos..pos + length {
+                insert(copied, &mut head, &mut previous);
+            }
+            pos += length;
+        } else {
+            let byte = usize::from(data[pos]);
+            writer.write(0, 1);
+            match mode {
+                LiteralMode::Binary => writer.write(byte as u32, 8),
+                LiteralMode::Ascii => writer.write(u32::from(CH_CODE_ASC[byte]), CH_BITS_ASC[byte]),
+            }
+            insert(pos, &mut head, &mut previous);
+            pos += 1;
+        }
+    }
+
+    writer.write_length(END_OF_STREAM);
+    if writer.count > 0 {
+        writer.data.push(writer.buffer as u8);
+    }
+    writer.data
+}
+
+/// Decompress a PKWare DCL stream, producing at most `limit` bytes
+pub(crate) fn explode(data: &[u8], expected_size: usize, limit: usize) -> Result<Vec<u8>> {
+    let [mode, dict_bits, ref stream @ ..] = *data else {
+        return Err(Error::compression("PKWare data is missing its header"));
+    };
+    if mode > 1 || !(4..=6).contains(&dict_bits) {
+        return Err(Error::compression(format!(
+            "Invalid PKWare header: mode {}, dictionary {}",
+            mode, dict_bits
+        )));
+    }
+
+    let mut reader = BitReader {
+        data: stream,
+        buffer: 0,
+        count: 0,
+    };
+    let mut output = output_buffer(expected_size.min(limit));
+    loop {
+        if reader.read(1)? == 0 {
+            let byte = if mode == 0 {
+                reader.read(8)? as u8
+            } else {
+                reader.decode(&ASCII_CODES)? as u8
+            };
+            if output.len() == limit {
+                return Err(Error::ExpansionLimit {
+                    limit: limit as u64,
+                });
+            }
+            output.push(byte);
+            continue;
+        }
+
+        let code = reader.decode(&LENGTH_CODES)?;
+        let length = usize::from(LEN_BASE[code]) + reader.read(EX_LEN_BITS[code])? as usize;
+        if length == END_OF_STREAM {
+            return Ok(output);
+        }
+        let length = length + 2;
+
+        let low_bits = if length == 2 { 2 } else { dict_bits };
+        let slot = reader.decode(&DISTANCE_CODES)?;
+        let distance = (slot << low_bits | reader.read(low_bits)? as usize) + 1;
+        if distance > output.len() {
+            return Err(Error::compression(
+                "PKWare data refers to data before the start of the output",
+            ));
+        }
+        if length > limit - output.len() {
+            return Err(Error::ExpansionLimit {
+                limit: limit as u64,
+            });
+        }
+        // Copies may overlap the bytes they produce
+        let start = output.len() - distance;
+        for index in start..start + length {
+            output.push(output[index]);
+        }
+    }
+}
+
+/// Lookup table from the next bits of a stream to a symbol and its length
+struct CodeTable {
+    entries: Vec<(u16, u8)>,
+    bits: u8,
+}
+
+impl CodeTable {
+    fn new(codes: impl Iterator<Item = u16>, lengths: &[u8]) -> Self {
+        let bits = lengths.iter().copied().max().unwrap_or(0);
+        let mut entries = vec![(0, 0); 1 << bits];
+        for (symbol, (code, &length)) in codes.zip(lengths).enumerate() {
+            // Every index whose low bits are the code maps to the symbol
+            for index in (usize::from(code)..entries.len()).step_by(1 << length) {
+                entries[index] = (symbol as u16, length);
+            }
+        }
+        Self { entries, bits }
+    }
+}
+
+static LENGTH_CODES: LazyLock<CodeTable> =
+    LazyLock::new(|| CodeTable::new(LEN_CODE.iter().map(|&c| u16::from(c)), &LEN_BITS));
+static DISTANCE_CODES: LazyLock<CodeTable> =
+    LazyLock::new(|| CodeTable::new(DIST_CODE.iter().map(|&c| u16::from(c)), &DIST_BITS));
+static ASCII_CODES: LazyLock<CodeTable> =
+    LazyLock::new(|| CodeTable::new(CH_CODE_ASC.iter().copied(), &CH_BITS_ASC));
+
+/// Reads a stream from the least significant bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    buffer: u64,
+    count: u8,
+}
+
+impl BitReader<'_> {
+    fn refill(&mut self) {
+        while self.count <= 56 {
+            let Some((&byte, rest)) = self.data.split_first() else {
+                break;
+            };
+            self.buffer |= u64::from(byte) << self.count;
+            self.count += 8;
+            self.data = rest;
+        }
+    }
+
+    fn consume(&mut self, bits: u8) -> Result<()> {
+        if bits > self.count {
+            return Err(Error::compression("PKWare data ends before its end marker"));
+        }
+        self.buffer >>= bits;
+        self.count -= bits;
+        Ok(())
+    }
+
+    fn read(&mut self, bits: u8) -> Result<u32> {
+        if self.count < bits {
+            self.refill();
+        }
+        let value = (self.buffer & ((1 << bits) - 1)) as u32;
+        self.consume(bits)?;
+        Ok(value)
+    }
+
+    fn decode(&mut self, table: &CodeTable) -> Result<usize> {
+        if self.count < table.bits {
+            self.refill();
+        }
+        let (symbol, length) = table.entries[(self.buffer & ((1 << table.bits) - 1)) as usize];
+        self.consume(length)?;
+        Ok(usize::from(symbol))
+    }
+}
+
+/// Writes a stream from the least significant bit of each byte
+struct BitWriter {
+    data: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u8) {
+        self.buffer |= value << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.data.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a copy flag and length value, 2 less than the copy length
+    fn write_length(&mut self, value: usize) {
+        let code = LEN_BASE
+            .iter()
+            .rposition(|&base| usize::from(base) <= value)
+            .unwrap_or(0);
+        self.write(1, 1);
+        self.write(u32::from(LEN_CODE[code]), LEN_BITS[code]);
+        self.write(
+            (value - usize::from(LEN_BASE[code])) as u32,
+            EX_LEN_BITS[code],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkware_roundtrip() {
+        let original = b"This is a test of PKWare compression and decompression.";
+
+        let compressed = compress(original).expect("Compression failed");
+        let decompressed =
+            decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
+
+        assert_eq!(decompressed, original);
+        assert!(
+            compressed.len() < original.len(),
+            "Compression should reduce size"
+        );
+    }
+
+    #[test]
+    fn test_pkware_with_options() {
+        let original = b"Testing PKWare with binary mode and different dictionary sizes.";
+
+        // Test binary mode with every dictionary size
+        for dict_bits in 4..=6 {
+            let compressed = implode(original, LiteralMode::Binary, dict_bits);
+            let decompressed =
+                decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
+            assert_eq!(decompressed, original);
+        }
+    }
+
+    #[test]
+    fn test_pkware_empty_data() {
+        let original = b"";
+
+        let compressed = compress(original).expect("Compression should handle empty data");
+        let decompressed =
+            decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
+
+        assert_eq!(decompressed, original);
+
+        // A stream holding only the end marker
+        let compressed = implode(original, LiteralMode::Binary, 4);
+        assert_eq!(decompress(&compressed, 0, usize::MAX).unwrap(), original);
+    }
+
+    #[test]
+    fn test_pkware_large_data() {
+        // Create a simple repeating pattern that compresses well
+        let pattern = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        let mut original = Vec::new();
+        for _ in 0..10 {
+            original.extend_from_slice(pattern);
+        }
+
+        let compressed = compress(&original).expect("Compression failed");
+        let decompressed =
+            decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
+
+        assert_eq!(decompressed, original);
+        assert!(
+            compressed.len() < original.len(),
+            "Repeating pattern should compress well"
+        );
+    }
+
+    #[test]
+    fn test_pkware_beyond_dictionary() {
+        // Inputs larger than the 4 KiB dictionary, with matches at every
+        // distance and copies up to the longest length
+        let mut original: Vec<u8> = (0..70_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        original.extend(std::iter::repeat_n(b'x', 2_000));
+        original.extend_from_within(1_000..9_000);
+
+        for mode in [LiteralMode::Binary, LiteralMode::Ascii] {
+            for dict_bits in 4..=6 {
+                for len in [1, 4_097, 8_192, 16_384, 65_536, original.len()] {
+                    let compressed = implode(&original[..len], mode, dict_bits);
+                    let decompressed = decompress(&compressed, len, usize::MAX).unwrap();
+                    assert!(
+                        decompressed == original[..len],
+                        "{:?} {} {}",
+                        mode,
+                        dict_bits,
+                        len
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_pkware_rejects_bad_streams() {
+        let compressed = implode(b"abcabcabcabc", LiteralMode::Binary, 6);
+        assert!(decompress(&compressed[..compressed.len() - 1], 12, usize::MAX).is_err());
+        assert!(decompress(&[0, 7, 0], 12, usize::MAX).is_err());
+        // A copy from before the start of the output
+        assert!(decompress(&[0, 4, 0x03, 0x00], 12, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_stops_at_limit() {
+        let compressed = compress(&vec![0u8; 1 << 20]).unwrap();
+        assert!(matches!(
+            decompress(&compressed, 100, 1000),
+            Err(Error::ExpansionLimit { limit: 1000 })
+        ));
+        assert_eq!(
+            decompress(&compressed, 100, 1 << 20).unwrap().len(),
+            1 << 20
+        );
+    }
+}
@@ -1,11 +1,16 @@
 //! Sparse/RLE compression and decompression
 
+use crate::compression::output_buffer;
 use crate::{Error, Result};
 
+/// Most bytes a single byte of sparse data can stand for: a control byte
+/// for a run of 127 zeros
+const MAX_EXPANSION: usize = 0x7F;
+
 /// Decompress sparse/RLE compressed data
 pub(crate) fn decompress(data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
     // Sparse compression is a simple RLE format
-    let mut output = Vec::with_capacity(expected_size);
+    let mut output = output_buffer(expected_size);
     let mut pos = 0;
 
     while pos < data.len() && output.len() < expected_size {
@@ -35,8 +40,16 @@ pub(crate) fn decompress(data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
         }
     }
 
-    // Pad with zeros if needed
+    // Pad with zeros if needed, but no further than the data could expand:
+    // the expected size is untrusted and sizes the allocation
     if output.len() < expected_size {
+        if expected_size > data.len().saturating_mul(MAX_EXPANSION) {
+            return Err(Error::compression(format!(
+                "Sparse decompression: {} bytes of data cannot expand to {} bytes",
+                data.len(),
+                expected_size
+            )));
+        }
         output.resize(expected_size, 0);
     }
 
@@ -125,4 +138,11 @@ mod tests {
         let decompressed = decompress(&compressed, original.len()).expect("Decompression failed");
         assert_eq!(decompressed, original);
     }
+
+    #[test]
+    fn test_rejects_impossible_size() {
+        // Padding is allowed within what the data could expand to
+        assert_eq!(decompress(&[0x85, 0xFF], 200).unwrap(), vec![0; 200]);
+        assert!(decompress(&[0x85, 0xFF], 1 << 40).is_err());
+    }
 }
@@ -1,13 +1,14 @@
 //! Zlib compression and decompression
 
+use super::LimitedOutput;
 use crate::{Error, Result};
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
-use std::io::{Read, Write};
+use std::io::{self, Write};
 
-/// Decompress using zlib/deflate
-pub(crate) fn decompress(data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
+/// Decompress using zlib/deflate, producing at most `limit` bytes
+pub(crate) fn decompress(data: &[u8], expected_size: usize, limit: usize) -> Result<Vec<u8>> {
     // Some MPQ implementations use raw deflate without zlib headers
     // Standard zlib header starts with 0x78 (deflate with 32K window)
     let has_zlib_header = !data.is_empty() && data[0] == 0x78;
@@ -22,35 +23,20 @@ pub(crate) fn decompress(data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
 
     // ZlibDecoder can handle both zlib-wrapped and raw deflate data
     let mut decoder = ZlibDecoder::new(data);
-    let mut decompressed = Vec::with_capacity(expected_size);
-
-    match decoder.read_to_end(&mut decompressed) {
-        Ok(_) => {
-            if decompressed.len() != expected_size {
-                log::debug!(
-                    "Decompressed size mismatch: expected {}, got {} (this is common in some MPQ files)",
-                    expected_size,
-                    decompressed.len()
-                );
-                // Some MPQ files have incorrect size info, so we'll allow this
-            }
-            Ok(decompressed)
-        }
-        Err(e) => {
-            log::error!("Zlib decompression failed: {}", e);
-            log::debug!(
-                "First 16 bytes of data: {:02X?}",
-                &data[..16.min(data.len())]
-            );
-            if data.len() <= 64 {
-                log::debug!("Full data ({} bytes): {:02X?}", data.len(), data);
-            }
-            Err(Error::compression(format!(
-                "Zlib decompression failed: {}",
-                e
-            )))
+    let mut output = LimitedOutput::new(expected_size, limit);
+    let result = io::copy(&mut decoder, &mut output);
+
+    output.finish(result, |e| {
+        log::error!("Zlib decompression failed: {}", e);
+        log::debug!(
+            "First 16 bytes of data: {:02X?}",
+            &data[..16.min(data.len())]
+        );
+        if data.len() <= 64 {
+            log::debug!("Full data ({} bytes): {:02X?}", data.len(), data);
         }
-    }
+        Error::compression(format!("Zlib decompression failed: {}", e))
+    })
 }
 
 /// Compress using zlib/deflate
@@ -82,7 +68,8 @@ mod tests {
             compressed.len()
         );
 
-        let decompressed = decompress(&compressed, original.len()).expect("Decompression failed");
+        let decompressed =
+            decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
 
         assert_eq!(decompressed, original);
     }
@@ -100,7 +87,8 @@ mod tests {
             "Highly repetitive data should compress to less than 50% of original size"
         );
 
-        let decompressed = decompress(&compressed, original.len()).expect("Decompression failed");
+        let decompressed =
+            decompress(&compressed, original.len(), usize::MAX).expect("Decompression failed");
 
         assert_eq!(decompressed, original);
     }
@@ -13,9 +13,10 @@ pub fn compress(data: &[u8], method: u8) -> Result<Vec<u8>> {
     // Check if compression actually reduces size
     let compressed = compress_internal(data, method)?;
 
-    // MPQ format requires that compression saves at least 2 bytes
+    // Readers treat data as compressed only when it is smaller than the
+    // original, so the method byte has to fit into the savings as well.
     // If it doesn't, we return the original data uncompressed
-    if compressed.len() >= data.len() {
+    if compressed.len() + 1 >= data.len() {
         // Return uncompressed data (no compression byte prefix)
         Ok(data.to_vec())
     } else {
@@ -137,6 +138,31 @@ mod tests {
     }
 
     #[test]
+    fn test_compress_keeps_one_byte_savings_raw() {
+        // A run of zeros in front of noise shrinks the zlib output one byte
+        // at a time, so one prefix length saves exactly one byte
+        let mut state = 0x9E37_79B9u32;
+        let noise: Vec<u8> = (0..64)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect();
+        let data = (0..64)
+            .map(|zeros| {
+                let mut data = vec![0u8; zeros];
+                data.extend_from_slice(&noise);
+                data
+            })
+            .find(|data| compress_internal(data, flags::ZLIB).unwrap().len() + 1 == data.len())
+            .expect("no prefix length saves exactly one byte");
+
+        // The method byte would use up the savings, so the data stays raw
+        assert_eq!(compress(&data, flags::ZLIB).unwrap(), data);
+    }
+
+    #[test]
+    #[cfg(feature = "compression-lzma")]
     fn test_lzma_api() {
         let original = b"Test data for LZMA compression through the public API";
 
@@ -4,8 +4,262 @@ use super::algorithms;
 use super::methods::{flags, CompressionMethod};
 use crate::{Error, Result};
 
+/// What to do when decompressed data does not have the expected size
+///
+/// Sizes recorded in archives are not always accurate; some tools wrote
+/// archives whose sectors decompress to a few bytes more or less than the
+/// block table says. Readers of such archives want to carry on, while
+/// validators want to know.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SizeMismatchPolicy {
+    /// Fail with `Error::InvalidFileSize`
+    Strict,
+    /// Log a warning and return the data as decompressed
+    #[default]
+    Warn,
+    /// Return the data as decompressed, for callers that only have an
+    /// estimate of the size
+    Ignore,
+}
+
+impl SizeMismatchPolicy {
+    /// Apply the policy to decompressed `data`
+    pub fn check(self, data: Vec<u8>, expected_size: usize) -> Result<Vec<u8>> {
+        if data.len() == expected_size {
+            return Ok(data);
+        }
+        match self {
+            SizeMismatchPolicy::Strict => Err(Error::InvalidFileSize {
+                expected: expected_size as u64,
+                actual: data.len() as u64,
+            }),
+            SizeMismatchPolicy::Warn => {
+                log::warn!(
+                    "Decompressed size mismatch: expected {}, got {}",
+                    expected_size,
+                    data.len()
+                );
+                Ok(data)
+            }
+            SizeMismatchPolicy::Ignore => Ok(data),
+        }
+    }
+}
+
+/// How guesses about data that cannot be decoded as recorded are handled
+///
+/// Some compression masks do not say unambiguously how to undo them, for
+/// example when both ADPCM flags or several primary methods are set. The
+/// decoder then picks one reading and carries on, which can turn corrupt
+/// data into a successful read of garbage. Format debugging wants to know
+/// when that happens, or to not have it happen at all.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FallbackPolicy {
+    /// Fail with `Error::Compression` instead of guessing
+    Strict,
+    /// Log a warning for each guess and carry on
+    Warn,
+    /// Log each guess at debug level and carry on
+    #[default]
+    Allow,
+}
+
+impl FallbackPolicy {
+    /// Record `fallback` and apply the policy to it
+    fn apply(self, fallback: DecodeFallback, diagnostics: &mut DecodeDiagnostics) -> Result<()> {
+        let message = fallback.to_string();
+        diagnostics.fallbacks.push(fallback);
+        match self {
+            FallbackPolicy::Strict => {
+                return Err(Error::compression(format!(
+                    "Strict decoding refuses to guess: {message}"
+                )))
+            }
+            FallbackPolicy::Warn => log::warn!("Decompression fallback: {message}"),
+            FallbackPolicy::Allow => log::debug!("Decompression fallback: {message}"),
+        }
+        Ok(())
+    }
+}
+
+/// A guess made while decoding data whose compression mask is ambiguous
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeFallback {
+    /// Both ADPCM flags were set and the data was decoded as stereo
+    BothAdpcm,
+    /// Several primary methods were set and only `used` was undone
+    IgnoredMethods {
+        /// The method that was undone
+        used: CompressionMethod,
+        /// The flags of the methods that were not
+        ignored: u8,
+    },
+}
+
+impl std::fmt::Display for DecodeFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeFallback::BothAdpcm => {
+                write!(f, "both ADPCM flags set, decoded as stereo")
+            }
+            DecodeFallback::IgnoredMethods { used, ignored } => {
+                write!(f, "decoded with {used}, ignoring methods 0x{ignored:02X}")
+            }
+        }
+    }
+}
+
+/// How one piece of data was decompressed
+///
+/// Returned by [`decompress_with_diagnostics`], and collected by archives
+/// opened with [`OpenOptions::record_decode_diagnostics`].
+///
+/// [`OpenOptions::record_decode_diagnostics`]: crate::OpenOptions::record_decode_diagnostics
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodeDiagnostics {
+    /// The compression mask the data was stored with
+    pub method: u8,
+    /// The size the data was expected to decompress to
+    pub expected_size: usize,
+    /// The size it did decompress to, or `None` if decompression failed
+    pub output_size: Option<usize>,
+    /// The methods undone, in the order they were undone
+    pub steps: Vec<CompressionMethod>,
+    /// The guesses made along the way
+    pub fallbacks: Vec<DecodeFallback>,
+}
+
+/// Default for how many times its expected size data may decompress to
+///
+/// Well-formed sectors decompress to exactly their expected size, so the
+/// limit only matters for data that claims a small size but keeps on
+/// expanding. See [`decompress_with_limit`].
+pub const DEFAULT_MAX_EXPANSION: usize = 1000;
+
 /// Decompress data using the specified compression method
+///
+/// A result whose size differs from `decompressed_size` is handled with
+/// [`SizeMismatchPolicy::Warn`]; use [`decompress_with_policy`] to choose.
+/// Decompression stops once the output exceeds [`DEFAULT_MAX_EXPANSION`]
+/// times `decompressed_size`.
 pub fn decompress(data: &[u8], method: u8, decompressed_size: usize) -> Result<Vec<u8>> {
+    decompress_with_policy(
+        data,
+        method,
+        decompressed_size,
+        SizeMismatchPolicy::default(),
+    )
+}
+
+/// Decompress data, handling a size mismatch according to `policy`
+///
+/// # Errors
+/// - `Error::Compression` if the data cannot be decompressed
+/// - `Error::InvalidFileSize` if the size differs and `policy` is
+///   [`SizeMismatchPolicy::Strict`]
+/// - `Error::ExpansionLimit` if the output exceeds
+///   [`DEFAULT_MAX_EXPANSION`] times `decompressed_size`
+pub fn decompress_with_policy(
+    data: &[u8],
+    method: u8,
+    decompressed_size: usize,
+    policy: SizeMismatchPolicy,
+) -> Result<Vec<u8>> {
+    decompress_with_limit(
+        data,
+        method,
+        decompressed_size,
+        policy,
+        DEFAULT_MAX_EXPANSION,
+    )
+}
+
+/// Decompress data, failing once the output grows past
+/// `max_expansion * decompressed_size` bytes
+///
+/// Sizes in an archive are only claims; a crafted sector can record a tiny
+/// size and decompress to gigabytes. Streaming codecs (zlib, bzip2, LZMA,
+/// PKWare DCL and IMPLODE) are cut off as soon as they pass the limit, so
+/// the damage is bounded by the limit rather than by available memory. The
+/// other codecs are checked once they finish. `max_expansion` is at least 1.
+///
+/// # Errors
+/// - `Error::ExpansionLimit` if the output passes the limit
+/// - Any error from [`decompress_with_policy`]
+pub fn decompress_with_limit(
+    data: &[u8],
+    method: u8,
+    decompressed_size: usize,
+    policy: SizeMismatchPolicy,
+    max_expansion: usize,
+) -> Result<Vec<u8>> {
+    decompress_with_diagnostics(
+        data,
+        method,
+        decompressed_size,
+        policy,
+        max_expansion,
+        FallbackPolicy::default(),
+    )
+    .0
+}
+
+/// Decompress data and report how it was decoded
+///
+/// Behaves like [`decompress_with_limit`], with guesses about ambiguous
+/// compression masks handled according to `fallbacks`. The diagnostics are
+/// returned whether or not decompression succeeded, so a failed read still
+/// shows how far decoding got.
+///
+/// # Errors
+/// - `Error::Compression` if a guess was needed and `fallbacks` is
+///   [`FallbackPolicy::Strict`]
+/// - Any error from [`decompress_with_limit`]
+pub fn decompress_with_diagnostics(
+    data: &[u8],
+    method: u8,
+    decompressed_size: usize,
+    policy: SizeMismatchPolicy,
+    max_expansion: usize,
+    fallbacks: FallbackPolicy,
+) -> (Result<Vec<u8>>, DecodeDiagnostics) {
+    let mut diagnostics = DecodeDiagnostics {
+        method,
+        expected_size: decompressed_size,
+        ..Default::default()
+    };
+    let limit = decompressed_size
+        .max(1)
+        .saturating_mul(max_expansion.max(1));
+    let result = decompress_data(
+        data,
+        method,
+        decompressed_size,
+        limit,
+        fallbacks,
+        &mut diagnostics,
+    )
+    .and_then(|decompressed| {
+        diagnostics.output_size = Some(decompressed.len());
+        // Codecs that don't stream are only checked once they finish
+        if decompressed.len() > limit {
+            return Err(Error::ExpansionLimit {
+                limit: limit as u64,
+            });
+        }
+        policy.check(decompressed, decompressed_size)
+    });
+    (result, diagnostics)
+}
+
+fn decompress_data(
+    data: &[u8],
+    method: u8,
+    decompressed_size: usize,
+    limit: usize,
+    fallbacks: FallbackPolicy,
+    diagnostics: &mut DecodeDiagnostics,
+) -> Result<Vec<u8>> {
     if data.is_empty() {
         return Err(Error::compression("Empty compressed data"));
     }
@@ -25,15 +279,20 @@ pub fn decompress(data: &[u8], method: u8, decompressed_size: usize) -> Result<V
     );
 
     let compression = CompressionMethod::from_flags(method);
+    if !compression.is_multiple() {
+        diagnostics.steps.push(compression);
+    }
 
     match compression {
         CompressionMethod::None => Ok(data.to_vec()),
-        CompressionMethod::Zlib => algorithms::zlib::decompress(data, decompressed_size),
-        CompressionMethod::BZip2 => algorithms::bzip2::decompress(data, decompressed_size),
-        CompressionMethod::Lzma => algorithms::lzma::decompress(data, decompressed_size),
+        CompressionMethod::Zlib => algorithms::zlib::decompress(data, decompressed_size, limit),
+        CompressionMethod::BZip2 => algorithms::bzip2::decompress(data, decompressed_size, limit),
+        CompressionMethod::Lzma => algorithms::lzma::decompress(data, decompressed_size, limit),
         CompressionMethod::Sparse => algorithms::sparse::decompress(data, decompressed_size),
-        CompressionMethod::Implode => algorithms::implode::decompress(data, decompressed_size),
-        CompressionMethod::PKWare => algorithms::pkware::decompress(data, decompressed_size),
+        CompressionMethod::Implode => {
+            algorithms::implode::decompress(data, decompressed_size, limit)
+        }
+        CompressionMethod::PKWare => algorithms::pkware::decompress(data, decompressed_size, limit),
         CompressionMethod::Huffman => algorithms::huffman::decompress(data, decompressed_size),
         CompressionMethod::AdpcmMono => algorithms::adpcm::decompress_mono(data, decompressed_size),
         CompressionMethod::AdpcmStereo => {
@@ -41,13 +300,27 @@ pub fn decompress(data: &[u8], method: u8, decompressed_size: usize) -> Result<V
         }
         CompressionMethod::Multiple(flags) => {
             log::debug!("Multiple compression with flags 0x{:02X}", flags);
-            decompress_multiple(data, flags, decompressed_size)
+            decompress_multiple(
+                data,
+                flags,
+                decompressed_size,
+                limit,
+                fallbacks,
+                diagnostics,
+            )
         }
     }
 }
 
 /// Handle multiple compression methods
-fn decompress_multiple(data: &[u8], flags: u8, expected_size: usize) -> Result<Vec<u8>> {
+fn decompress_multiple(
+    data: &[u8],
+    flags: u8,
+    expected_size: usize,
+    limit: usize,
+    fallbacks: FallbackPolicy,
+    diagnostics: &mut DecodeDiagnostics,
+) -> Result<Vec<u8>> {
     if data.is_empty() {
         return Err(Error::compression("Empty compressed data"));
     }
@@ -73,7 +346,7 @@ fn decompress_multiple(data: &[u8], flags: u8, expected_size: usize) -> Result<V
     let adpcm_type = if has_adpcm_mono && has_adpcm_stereo {
         // Both flags set - this is unusual but appears in WoW 4.3.4
         // We'll assume stereo since it's more complex
-        log::debug!("Both ADPCM_MONO and ADPCM_STEREO flags set, assuming stereo");
+        fallbacks.apply(DecodeFallback::BothAdpcm, diagnostics)?;
         Some("stereo")
     } else if has_adpcm_stereo {
         Some("stereo")
@@ -88,6 +361,34 @@ fn decompress_multiple(data: &[u8], flags: u8, expected_size: usize) -> Result<V
     // 2. Then decompress PKWare if present
     // 3. Finally decompress ADPCM if present (ADPCM is applied first during compression)
 
+    // Only one primary method is undone; the order below decides which
+    let primary = [
+        (flags::HUFFMAN, CompressionMethod::Huffman),
+        (flags::ZLIB, CompressionMethod::Zlib),
+        (flags::BZIP2, CompressionMethod::BZip2),
+        (flags::SPARSE, CompressionMethod::Sparse),
+        (flags::IMPLODE, CompressionMethod::Implode),
+    ];
+    let mut present = primary.iter().filter(|(flag, _)| flags & flag != 0);
+    if let Some(&(_, used)) = present.next() {
+        let ignored = present.fold(0, |mask, (flag, _)| mask | flag);
+        if ignored != 0 {
+            fallbacks.apply(
+                DecodeFallback::IgnoredMethods { used, ignored },
+                diagnostics,
+            )?;
+        }
+        diagnostics.steps.push(used);
+    }
+    if has_pkware {
+        diagnostics.steps.push(CompressionMethod::PKWare);
+    }
+    match adpcm_type {
+        Some("stereo") => diagnostics.steps.push(CompressionMethod::AdpcmStereo),
+        Some(_) => diagnostics.steps.push(CompressionMethod::AdpcmMono),
+        None => {}
+    }
+
     let mut current_data = data.to_vec();
 
     // Step 1: Decompress the primary compression method
@@ -98,16 +399,16 @@ fn decompress_multiple(data: &[u8], flags: u8, expected_size: usize) -> Result<V
         current_data = algorithms::huffman::decompress(&current_data, huffman_output_size)?;
     } else if has_zlib {
         log::debug!("Decompressing Zlib");
-        current_data = algorithms::zlib::decompress(&current_data, expected_size * 4)?;
+        current_data = algorithms::zlib::decompress(&current_data, expected_size, limit)?;
     } else if has_bzip2 {
         log::debug!("Decompressing BZip2");
-        current_data = algorithms::bzip2::decompress(&current_data, expected_size * 4)?;
+        current_data = algorithms::bzip2::decompress(&current_data, expected_size, limit)?;
     } else if has_sparse {
         log::debug!("Decompressing Sparse");
         current_data = algorithms::sparse::decompress(&current_data, expected_size * 4)?;
     } else if has_implode {
         log::debug!("Decompressing Implode");
-        current_data = algorithms::implode::decompress(&current_data, expected_size * 4)?;
+        current_data = algorithms::implode::decompress(&current_data, expected_size * 4, limit)?;
     }
 
     // Step 2: Decompress PKWare if present
@@ -115,7 +416,7 @@ fn decompress_multiple(data: &[u8], flags: u8, expected_size: usize) -> Result<V
         log::debug!("Decompressing PKWare");
         // PKWare expected size should be estimated based on current data size
         let pkware_output_size = std::cmp::max(expected_size, current_data.len() * 2);
-        current_data = algorithms::pkware::decompress(&current_data, pkware_output_size)?;
+        current_data = algorithms::pkware::decompress(&current_data, pkware_output_size, limit)?;
     }
 
     // Step 3: Decompress ADPCM if present (applied last since it was first during compression)
@@ -140,9 +441,9 @@ fn decompress_multiple(data: &[u8], flags: u8, expected_size: usize) -> Result<V
         if has_huffman {
             return algorithms::huffman::decompress(data, expected_size);
         } else if has_zlib {
-            return algorithms::zlib::decompress(data, expected_size);
+            return algorithms::zlib::decompress(data, expected_size, limit);
         } else if has_bzip2 {
-            return algorithms::bzip2::decompress(data, expected_size);
+            return algorithms::bzip2::decompress(data, expected_size, limit);
         } else if has_sparse {
             return algorithms::sparse::decompress(data, expected_size);
         }
@@ -173,4 +474,155 @@ mod tests {
             decompress(&compressed, flags::ZLIB, original.len()).expect("Decompression failed");
         assert_eq!(result, original);
     }
+
+    #[test]
+    fn test_size_mismatch_policy() {
+        let original = b"Test data for compression";
+        let compressed = algorithms::zlib::compress(original).expect("Compression failed");
+        let wrong_size = original.len() + 7;
+
+        let result = decompress_with_policy(
+            &compressed,
+            flags::ZLIB,
+            wrong_size,
+            SizeMismatchPolicy::Strict,
+        );
+        assert!(matches!(
+            result,
+            Err(Error::InvalidFileSize { expected, actual })
+                if expected == wrong_size as u64 && actual == original.len() as u64
+        ));
+
+        for policy in [SizeMismatchPolicy::Warn, SizeMismatchPolicy::Ignore] {
+            let result = decompress_with_policy(&compressed, flags::ZLIB, wrong_size, policy)
+                .expect("Decompression failed");
+            assert_eq!(result, original);
+        }
+
+        // Matching sizes pass under every policy
+        let result = decompress_with_policy(
+            &compressed,
+            flags::ZLIB,
+            original.len(),
+            SizeMismatchPolicy::Strict,
+        )
+        .expect("Decompression failed");
+        assert_eq!(result, original);
+    }
+
+    #[test]
+    fn test_fallback_policy() {
+        let original = b"Data stored with an ambiguous mask".repeat(8);
+        let compressed = algorithms::zlib::compress(&original).expect("Compression failed");
+        let ambiguous = flags::ZLIB | flags::SPARSE;
+        let decode = |fallbacks| {
+            decompress_with_diagnostics(
+                &compressed,
+                ambiguous,
+                original.len(),
+                SizeMismatchPolicy::Strict,
+                DEFAULT_MAX_EXPANSION,
+                fallbacks,
+            )
+        };
+        let fallback = DecodeFallback::IgnoredMethods {
+            used: CompressionMethod::Zlib,
+            ignored: flags::SPARSE,
+        };
+
+        for policy in [FallbackPolicy::Allow, FallbackPolicy::Warn] {
+            let (result, diagnostics) = decode(policy);
+            assert_eq!(result.expect("Decompression failed"), original);
+            assert_eq!(diagnostics.method, ambiguous);
+            assert_eq!(diagnostics.steps, [CompressionMethod::Zlib]);
+            assert_eq!(diagnostics.fallbacks, [fallback]);
+            assert_eq!(diagnostics.output_size, Some(original.len()));
+        }
+
+        // Strict mode refuses the guess but still says what it was
+        let (result, diagnostics) = decode(FallbackPolicy::Strict);
+        assert!(matches!(result, Err(Error::Compression(_))));
+        assert_eq!(diagnostics.fallbacks, [fallback]);
+        assert_eq!(diagnostics.output_size, None);
+
+        // Unambiguous masks need no guess
+        let (result, diagnostics) = decompress_with_diagnostics(
+            &compressed,
+            flags::ZLIB,
+            original.len(),
+            SizeMismatchPolicy::Strict,
+            DEFAULT_MAX_EXPANSION,
+            FallbackPolicy::Strict,
+        );
+        assert_eq!(result.expect("Decompression failed"), original);
+        assert!(diagnostics.fallbacks.is_empty());
+    }
+
+    #[test]
+    fn test_forged_size_is_not_preallocated() {
+        use crate::compression::{output_buffer, MAX_PREALLOCATION};
+
+        // A size no allocation could satisfy only limits the output
+        let forged = usize::MAX / 4;
+        let original = b"Honest data behind a forged size".repeat(4);
+        let streams = [
+            (flags::ZLIB, algorithms::zlib::compress(&original).unwrap()),
+            #[cfg(feature = "compression-bzip2")]
+            (
+                flags::BZIP2,
+                algorithms::bzip2::compress(&original).unwrap(),
+            ),
+        ];
+        for (method, compressed) in &streams {
+            let result =
+                decompress_with_policy(compressed, *method, forged, SizeMismatchPolicy::Ignore)
+                    .expect("Decompression failed");
+            assert_eq!(result, original, "method 0x{method:02X}");
+        }
+        assert!(output_buffer(forged).capacity() <= MAX_PREALLOCATION);
+    }
+
+    #[test]
+    fn test_expansion_limit() {
+        // 1 MiB of zeros that claims to be 100 bytes
+        let bomb = vec![0u8; 1 << 20];
+        let claimed = 100;
+        let imploded =
+            algorithms::pkware::implode(&bomb, algorithms::pkware::LiteralMode::Binary, 4);
+        let streams = [
+            (flags::ZLIB, algorithms::zlib::compress(&bomb).unwrap()),
+            (flags::PKWARE, algorithms::pkware::compress(&bomb).unwrap()),
+            (flags::IMPLODE, imploded[2..].to_vec()),
+            #[cfg(feature = "compression-bzip2")]
+            (flags::BZIP2, algorithms::bzip2::compress(&bomb).unwrap()),
+            #[cfg(feature = "compression-lzma")]
+            (flags::LZMA, algorithms::lzma::compress(&bomb).unwrap()),
+        ];
+
+        for (method, compressed) in &streams {
+            let result =
+                decompress_with_limit(compressed, *method, claimed, SizeMismatchPolicy::Ignore, 10);
+            assert!(
+                matches!(result, Err(Error::ExpansionLimit { limit: 1000 })),
+                "method 0x{method:02X}: {result:?}"
+            );
+
+            // The default ratio leaves room for 100 KB
+            assert!(matches!(
+                decompress(compressed, *method, claimed),
+                Err(Error::ExpansionLimit { .. })
+            ));
+
+            // Within the limit the data decompresses as usual
+            let result = decompress_with_limit(
+                compressed,
+                *method,
+                bomb.len(),
+                SizeMismatchPolicy::Strict,
+                1,
+            )
+            .expect("Decompression failed");
+            assert_eq!(result, bomb);
+        }
+    }
 }
@@ -23,7 +23,7 @@ pub mod flags {
 }
 
 /// Compression methods enum
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CompressionMethod {
     /// No compression
     None,
@@ -72,10 +72,71 @@ impl CompressionMethod {
         }
     }
 
+    /// Compression mask byte that stores data this way, the inverse of
+    /// [`from_flags`](Self::from_flags)
+    pub fn to_flags(&self) -> u8 {
+        match *self {
+            CompressionMethod::None => 0,
+            CompressionMethod::Huffman => flags::HUFFMAN,
+            CompressionMethod::Zlib => flags::ZLIB,
+            CompressionMethod::Implode => flags::IMPLODE,
+            CompressionMethod::PKWare => flags::PKWARE,
+            CompressionMethod::BZip2 => flags::BZIP2,
+            CompressionMethod::Sparse => flags::SPARSE,
+            CompressionMethod::AdpcmMono => flags::ADPCM_MONO,
+            CompressionMethod::AdpcmStereo => flags::ADPCM_STEREO,
+            CompressionMethod::Lzma => flags::LZMA,
+            CompressionMethod::Multiple(mask) => mask,
+        }
+    }
+
     /// Check if this is a multi-compression method
     pub fn is_multiple(&self) -> bool {
         matches!(self, CompressionMethod::Multiple(_))
     }
+
+    /// Check if data compressed this way can be decompressed by this crate
+    ///
+    /// Every single method is supported, except BZip2 and LZMA when their
+    /// `compression-bzip2` and `compression-lzma` features are disabled.
+    /// Combinations are supported when they use at most one of Huffman,
+    /// zlib, BZip2, sparse and implode, optionally followed by PKWare and
+    /// ADPCM, which is the order the decompressor unwinds them in.
+    pub fn is_supported(&self) -> bool {
+        match *self {
+            CompressionMethod::BZip2 => cfg!(feature = "compression-bzip2"),
+            CompressionMethod::Lzma => cfg!(feature = "compression-lzma"),
+            CompressionMethod::Multiple(mask) => {
+                let primary = mask
+                    & (flags::HUFFMAN
+                        | flags::ZLIB
+                        | flags::BZIP2
+                        | flags::SPARSE
+                        | flags::IMPLODE);
+                primary.count_ones() <= 1
+                    && (mask & flags::BZIP2 == 0 || cfg!(feature = "compression-bzip2"))
+            }
+            _ => true,
+        }
+    }
+}
+
+impl std::fmt::Display for CompressionMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompressionMethod::None => write!(f, "None"),
+            CompressionMethod::Huffman => write!(f, "Huffman"),
+            CompressionMethod::Zlib => write!(f, "Zlib"),
+            CompressionMethod::Implode => write!(f, "PKWare Implode"),
+            CompressionMethod::PKWare => write!(f, "PKWare DCL"),
+            CompressionMethod::BZip2 => write!(f, "BZip2"),
+            CompressionMethod::Sparse => write!(f, "Sparse"),
+            CompressionMethod::AdpcmMono => write!(f, "ADPCM Mono"),
+            CompressionMethod::AdpcmStereo => write!(f, "ADPCM Stereo"),
+            CompressionMethod::Lzma => write!(f, "LZMA"),
+            CompressionMethod::Multiple(mask) => write!(f, "Multiple (0x{:02X})", mask),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -101,5 +162,21 @@ mod tests {
         // Multiple compression
         let multi = flags::ZLIB | flags::PKWARE;
         assert!(CompressionMethod::from_flags(multi).is_multiple());
+
+        for mask in [0, flags::HUFFMAN, flags::ADPCM_STEREO, flags::LZMA, multi] {
+            assert_eq!(CompressionMethod::from_flags(mask).to_flags(), mask);
+        }
+    }
+
+    #[test]
+    fn test_compression_method_support() {
+        assert!(CompressionMethod::Huffman.is_supported());
+        assert!(CompressionMethod::from_flags(flags::ZLIB | flags::PKWARE).is_supported());
+        assert!(CompressionMethod::from_flags(flags::HUFFMAN | flags::ADPCM_STEREO).is_supported());
+        assert!(!CompressionMethod::from_flags(flags::SPARSE | flags::ZLIB).is_supported());
+        assert_eq!(
+            CompressionMethod::from_flags(flags::SPARSE | flags::ZLIB).to_string(),
+            "Multiple (0x22)"
+        );
     }
 }
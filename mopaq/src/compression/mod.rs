@@ -7,5 +7,21 @@ mod methods;
 
 // Re-export the main public API
 pub use compress::compress;
-pub use decompress::decompress;
+pub use decompress::{
+    decompress, decompress_with_diagnostics, decompress_with_limit, decompress_with_policy,
+    DecodeDiagnostics, DecodeFallback, FallbackPolicy, SizeMismatchPolicy, DEFAULT_MAX_EXPANSION,
+};
 pub use methods::{flags, CompressionMethod};
+
+/// Most memory reserved up front for output of a size read from an archive
+pub(crate) const MAX_PREALLOCATION: usize = 16 << 20;
+
+/// An empty buffer for `expected_size` bytes of output
+///
+/// Sizes read from an archive are not trusted to reserve memory: at most
+/// [`MAX_PREALLOCATION`] bytes are reserved and the buffer grows as data
+/// arrives, so a forged size fails on the data it lacks instead of on an
+/// allocation of the size it claims.
+pub(crate) fn output_buffer(expected_size: usize) -> Vec<u8> {
+    Vec::with_capacity(expected_size.min(MAX_PREALLOCATION))
+}
@@ -0,0 +1,850 @@
+//! Converting between MPQ archives and ZIP or TAR files
+//!
+//! Mods are often distributed as ZIP or TAR files while the games load
+//! MPQs. [`zip_to_mpq`] and [`tar_to_mpq`] build an archive from the
+//! entries of either, and [`mpq_to_zip`] writes the files of an archive to
+//! a ZIP file. Entries are streamed one at a time, so neither side has to
+//! fit in memory. [`ConvertOptions`] maps names between the two, for example
+//! to drop the top-level folder a ZIP was packed with, and picks the
+//! compression of the output.
+//!
+//! Only what the two formats share is converted. ZIP entries compressed with
+//! anything other than deflate, encrypted entries and TAR links are skipped
+//! and listed in the returned [`ConvertSummary`]. ZIP64 is not supported.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use mopaq::convert::{self, ConvertOptions};
+//! use mopaq::Archive;
+//!
+//! let options = ConvertOptions::new().strip_prefix("MyMod/");
+//! let summary = convert::zip_to_mpq("MyMod.zip", "patch-4.mpq", &options)?;
+//! println!("{} files imported", summary.files);
+//!
+//! let mut archive = Archive::open("patch-4.mpq")?;
+//! convert::mpq_to_zip(&mut archive, "patch-4.zip", &ConvertOptions::new())?;
+//! # Ok::<(), mopaq::Error>(())
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::write::DeflateEncoder;
+
+use crate::compression::flags as compression_flags;
+use crate::special_files::SpecialFile;
+use crate::{Archive, ArchiveBuilder, Error, FormatVersion, ListOptions, Result};
+
+/// Signature of a ZIP local file header
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+
+/// Signature of a ZIP central directory entry
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+
+/// Signature of the ZIP end of central directory record
+const ZIP_END_OF_DIRECTORY: u32 = 0x0605_4b50;
+
+/// Size of the end of central directory record without its comment
+const ZIP_END_OF_DIRECTORY_SIZE: usize = 22;
+
+/// ZIP flag marking names as UTF-8
+const ZIP_FLAG_UTF8: u16 = 0x0800;
+
+/// ZIP flag marking an entry as encrypted
+const ZIP_FLAG_ENCRYPTED: u16 = 0x0001;
+
+/// Version 2.0, the first with deflate and folders
+const ZIP_VERSION: u16 = 20;
+
+/// DOS date of 1980-01-01, the earliest a ZIP can hold
+///
+/// MPQs keep no modification times, so every exported entry gets this date
+/// and converting the same archive twice gives identical files.
+const ZIP_DOS_DATE: u16 = (1 << 5) | 1;
+
+/// Size of a TAR header and of the blocks data is padded to
+const TAR_BLOCK: usize = 512;
+
+/// Largest GNU long name or pax record accepted, which are read into memory
+const TAR_MAX_NAME_RECORD: u64 = 1024 * 1024;
+
+/// How file data is stored in a ZIP written by [`mpq_to_zip`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZipMethod {
+    /// Files are stored as they are
+    Stored,
+    /// Files are compressed with deflate
+    #[default]
+    Deflated,
+}
+
+impl ZipMethod {
+    /// The method number in ZIP headers
+    fn code(self) -> u16 {
+        match self {
+            ZipMethod::Stored => 0,
+            ZipMethod::Deflated => 8,
+        }
+    }
+}
+
+/// Options for converting between archives
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    compression: u8,
+    version: FormatVersion,
+    zip_method: ZipMethod,
+    strip_prefix: Option<String>,
+    add_prefix: Option<String>,
+    include_special: bool,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            compression: compression_flags::ZLIB,
+            version: FormatVersion::V1,
+            zip_method: ZipMethod::default(),
+            strip_prefix: None,
+            add_prefix: None,
+            include_special: false,
+        }
+    }
+}
+
+impl ConvertOptions {
+    /// Options that keep names as they are, build v1 archives with zlib
+    /// compression and deflate ZIP entries
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the compression of files added to MPQs (0 for none)
+    pub fn compression(mut self, compression: u8) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the format version of MPQs that are built
+    pub fn version(mut self, version: FormatVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Set how file data is stored in ZIPs that are written
+    pub fn zip_method(mut self, method: ZipMethod) -> Self {
+        self.zip_method = method;
+        self
+    }
+
+    /// Remove this prefix from names that start with it
+    ///
+    /// Matched ignoring case and whether `/` or `\` separates the parts, so
+    /// `"MyMod/"` strips both `MyMod/units.txt` and `mymod\units.txt`.
+    /// Names that do not start with it are kept as they are.
+    pub fn strip_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.strip_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Put this prefix in front of every name, after stripping
+    pub fn add_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.add_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Copy special files such as `(listfile)` and `(attributes)` as well
+    ///
+    /// They describe the archive they came from, and a built MPQ generates
+    /// its own listfile, so they are left out by default.
+    pub fn include_special(mut self, include: bool) -> Self {
+        self.include_special = include;
+        self
+    }
+
+    /// Map a source name to its name in the output, using `separator`
+    ///
+    /// Returns `None` for names that map to nothing or to a special file
+    /// that is left out.
+    fn map_name(&self, name: &str, separator: char) -> Option<String> {
+        let mut name = name.replace(['/', '\\'], "/");
+        if let Some(prefix) = &self.strip_prefix {
+            let prefix = prefix.replace('\\', "/");
+            if name.len() >= prefix.len()
+                && name.is_char_boundary(prefix.len())
+                && name[..prefix.len()].eq_ignore_ascii_case(&prefix)
+            {
+                name.drain(..prefix.len());
+            }
+        }
+        let name = name.trim_start_matches('/');
+        if name.is_empty() {
+            return None;
+        }
+
+        let name = match &self.add_prefix {
+            Some(prefix) => format!("{}{}", prefix.replace('\\', "/"), name),
+            None => name.to_string(),
+        };
+        if !self.include_special && SpecialFile::from_name(&name).is_some() {
+            return None;
+        }
+        Some(name.replace('/', &separator.to_string()))
+    }
+}
+
+/// What a conversion did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConvertSummary {
+    /// Files written to the output
+    pub files: usize,
+    /// Uncompressed bytes of the files written
+    pub bytes: u64,
+    /// Names of entries that could not be converted and were left out
+    pub skipped: Vec<String>,
+}
+
+/// Build an MPQ at `mpq` from the files of the ZIP at `zip`
+///
+/// Folders are left out, and stored and deflated entries are added with
+/// the options' compression after their CRC-32 is checked. Names are mapped
+/// through the options and get `\` as separator.
+///
+/// # Errors
+/// - `Error::InvalidFormat` if `zip` is not a ZIP file or uses ZIP64
+/// - `Error::InvalidFileSize` if an entry does not have its declared size
+/// - `Error::ChecksumMismatch` if an entry does not match its CRC-32
+/// - Any error from reading `zip` or building the archive
+pub fn zip_to_mpq<P: AsRef<Path>, Q: AsRef<Path>>(
+    zip: P,
+    mpq: Q,
+    options: &ConvertOptions,
+) -> Result<ConvertSummary> {
+    let mut reader = BufReader::new(File::open(zip)?);
+    let entries = read_zip_directory(&mut reader)?;
+    let mut spool = Spool::new(options)?;
+
+    for entry in entries {
+        if entry.name.ends_with('/') {
+            continue;
+        }
+        let Some(name) = options.map_name(&entry.name, '\\') else {
+            continue;
+        };
+        if entry.flags & ZIP_FLAG_ENCRYPTED != 0 || !matches!(entry.method, 0 | 8) {
+            log::warn!(
+                "Skipping {}: encrypted or compressed with unsupported method {}",
+                entry.name,
+                entry.method
+            );
+            spool.summary.skipped.push(entry.name);
+            continue;
+        }
+
+        reader.seek(SeekFrom::Start(entry.local_offset))?;
+        let header = read_array::<30>(&mut reader)?;
+        if le_u32(&header, 0) != ZIP_LOCAL_HEADER {
+            return Err(Error::invalid_format(format!(
+                "missing local header for ZIP entry {}",
+                entry.name
+            )));
+        }
+        let skip = u64::from(le_u16(&header, 26)) + u64::from(le_u16(&header, 28));
+        reader.seek(SeekFrom::Current(skip as i64))?;
+
+        // Inflating stops one byte past the declared size, which is enough
+        // to reject an entry that expands further without spooling all of it
+        let data = (&mut reader).take(u64::from(entry.compressed_size));
+        let (size, crc) = if entry.method == 8 {
+            spool.add(
+                DeflateDecoder::new(data).take(u64::from(entry.size) + 1),
+                &name,
+            )?
+        } else {
+            spool.add(data, &name)?
+        };
+        if size != u64::from(entry.size) {
+            return Err(Error::InvalidFileSize {
+                expected: u64::from(entry.size),
+                actual: size,
+            });
+        }
+        if crc != entry.crc {
+            return Err(Error::ChecksumMismatch {
+                file: entry.name,
+                expected: entry.crc,
+                actual: crc,
+            });
+        }
+    }
+
+    spool.build(mpq)
+}
+
+/// Build an MPQ at `mpq` from the files of the TAR at `tar`
+///
+/// Gzip-compressed TARs are recognized and decompressed on the fly. Both
+/// ustar and GNU long names and pax `path` records are understood. Folders
+/// are left out, and links, devices and other special entries are skipped.
+/// Names are mapped through the options and get `\` as separator.
+///
+/// # Errors
+/// - `Error::InvalidFormat` if a header is malformed, a long name or pax
+///   record is larger than 1 MiB, or the TAR is cut short
+/// - Any error from reading `tar` or building the archive
+pub fn tar_to_mpq<P: AsRef<Path>, Q: AsRef<Path>>(
+    tar: P,
+    mpq: Q,
+    options: &ConvertOptions,
+) -> Result<ConvertSummary> {
+    let mut file = BufReader::new(File::open(tar)?);
+    let mut reader: Box<dyn Read> = if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut spool = Spool::new(options)?;
+    let mut long_name = None;
+
+    loop {
+        let mut header = [0u8; TAR_BLOCK];
+        if !read_full(&mut reader, &mut header)? {
+            break;
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let entry = parse_tar_header(&header)?;
+        let padding = (TAR_BLOCK as u64 - entry.size % TAR_BLOCK as u64) % TAR_BLOCK as u64;
+
+        match entry.kind {
+            b'L' | b'x' => {
+                if entry.size > TAR_MAX_NAME_RECORD {
+                    return Err(Error::invalid_format(format!(
+                        "TAR {} record of {} bytes is too large",
+                        if entry.kind == b'L' {
+                            "long name"
+                        } else {
+                            "pax"
+                        },
+                        entry.size
+                    )));
+                }
+                let mut data = Vec::new();
+                (&mut reader).take(entry.size).read_to_end(&mut data)?;
+                if data.len() as u64 != entry.size {
+                    return Err(tar_truncated());
+                }
+                long_name = if entry.kind == b'L' {
+                    Some(c_string(&data))
+                } else {
+                    pax_path(&data).or(long_name)
+                };
+            }
+            b'0' | b'\0' | b'7' => {
+                let name = long_name.take().unwrap_or(entry.name);
+                match options.map_name(&name, '\\') {
+                    Some(mapped) => {
+                        let (size, _) = spool.add((&mut reader).take(entry.size), &mapped)?;
+                        if size != entry.size {
+                            return Err(tar_truncated());
+                        }
+                    }
+                    None => skip(&mut reader, entry.size)?,
+                }
+            }
+            kind => {
+                let name = long_name.take().unwrap_or(entry.name);
+                if !matches!(kind, b'5' | b'g') {
+                    log::warn!(
+                        "Skipping {}: unsupported TAR entry type {:?}",
+                        name,
+                        kind as char
+                    );
+                    spool.summary.skipped.push(name);
+                }
+                skip(&mut reader, entry.size)?;
+            }
+        }
+        skip(&mut reader, padding)?;
+    }
+
+    spool.build(mpq)
+}
+
+/// Write the files of `archive` to a ZIP at `zip`
+///
+/// Files are taken from the listing, so the archive needs a `(listfile)`.
+/// Names are mapped through the options and get `/` as separator. Every
+/// entry is dated 1980-01-01, as MPQs keep no modification times.
+///
+/// # Errors
+/// - `Error::InvalidFormat` if `archive` has no `(listfile)`
+/// - `Error::CapacityExceeded` if there are more than 65535 files or the
+///   ZIP would reach 4 GiB, which needs ZIP64
+/// - Any error from reading `archive` or writing `zip`
+pub fn mpq_to_zip<P: AsRef<Path>>(
+    archive: &mut Archive,
+    zip: P,
+    options: &ConvertOptions,
+) -> Result<ConvertSummary> {
+    if archive.find_file("(listfile)")?.is_none() {
+        return Err(Error::invalid_format(
+            "archive has no (listfile); its files cannot be enumerated for conversion",
+        ));
+    }
+
+    let files: Vec<(String, String)> = archive
+        .list_with(&ListOptions::new().include_special(options.include_special))?
+        .into_iter()
+        .filter_map(|entry| {
+            let mapped = options.map_name(&entry.name, '/')?;
+            Some((entry.name, mapped))
+        })
+        .collect();
+    if files.len() > usize::from(u16::MAX) {
+        return Err(Error::CapacityExceeded(format!(
+            "{} files do not fit in a ZIP without ZIP64",
+            files.len()
+        )));
+    }
+
+    let mut writer = BufWriter::new(File::create(zip)?);
+    let mut central = Vec::new();
+    let mut summary = ConvertSummary::default();
+    let method = options.zip_method;
+
+    for (source, name) in &files {
+        let offset = zip_offset(writer.stream_position()?)?;
+        let mut header = ZipHeader {
+            method: method.code(),
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            name: name.as_bytes(),
+        };
+        header.write_local(&mut writer)?;
+        let data_start = writer.stream_position()?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size = 0u64;
+        match method {
+            ZipMethod::Stored => archive.read_file_chunks(source, |chunk| {
+                hasher.update(chunk);
+                size += chunk.len() as u64;
+                writer.write_all(chunk)?;
+                Ok(true)
+            })?,
+            ZipMethod::Deflated => {
+                let mut encoder = DeflateEncoder::new(&mut writer, flate2::Compression::default());
+                archive.read_file_chunks(source, |chunk| {
+                    hasher.update(chunk);
+                    size += chunk.len() as u64;
+                    encoder.write_all(chunk)?;
+                    Ok(true)
+                })?;
+                encoder.finish()?;
+            }
+        }
+
+        let end = writer.stream_position()?;
+        header.crc = hasher.finalize();
+        header.compressed_size = zip_offset(end - data_start)?;
+        header.size = zip_offset(size)?;
+        writer.seek(SeekFrom::Start(u64::from(offset) + 14))?;
+        writer.write_all(&header.crc.to_le_bytes())?;
+        writer.write_all(&header.compressed_size.to_le_bytes())?;
+        writer.write_all(&header.size.to_le_bytes())?;
+        writer.seek(SeekFrom::Start(end))?;
+
+        header.write_central(&mut central, offset)?;
+        summary.files += 1;
+        summary.bytes += size;
+    }
+
+    let directory_offset = zip_offset(writer.stream_position()?)?;
+    writer.write_all(&central)?;
+    let count = files.len() as u16;
+    writer.write_all(&ZIP_END_OF_DIRECTORY.to_le_bytes())?;
+    writer.write_all(&[0; 4])?; // disk numbers
+    writer.write_all(&count.to_le_bytes())?;
+    writer.write_all(&count.to_le_bytes())?;
+    writer.write_all(&zip_offset(central.len() as u64)?.to_le_bytes())?;
+    writer.write_all(&directory_offset.to_le_bytes())?;
+    writer.write_all(&0u16.to_le_bytes())?; // comment length
+    writer.flush()?;
+
+    Ok(summary)
+}
+
+/// Files written to a temporary directory until the archive is built
+///
+/// Entries get numbered file names, so names from the source never touch
+/// the file system.
+struct Spool {
+    dir: tempfile::TempDir,
+    builder: ArchiveBuilder,
+    compression: u8,
+    summary: ConvertSummary,
+}
+
+impl Spool {
+    fn new(options: &ConvertOptions) -> Result<Self> {
+        Ok(Self {
+            dir: tempfile::tempdir()?,
+            builder: ArchiveBuilder::new().version(options.version),
+            compression: options.compression,
+            summary: ConvertSummary::default(),
+        })
+    }
+
+    /// Copy `data` to the spool and queue it as `name`, returning its size
+    /// and CRC-32
+    fn add(&mut self, data: impl Read, name: &str) -> Result<(u64, u32)> {
+        let path = self.dir.path().join(self.summary.files.to_string());
+        let mut writer = CrcWriter {
+            inner: BufWriter::new(File::create(&path)?),
+            hasher: crc32fast::Hasher::new(),
+        };
+        let size = io::copy(&mut BufReader::new(data), &mut writer)?;
+        writer.inner.flush()?;
+
+        let builder = std::mem::take(&mut self.builder);
+        self.builder = builder.add_file_with_options(&path, name, self.compression, false, 0);
+        self.summary.files += 1;
+        self.summary.bytes += size;
+        Ok((size, writer.hasher.finalize()))
+    }
+
+    fn build<P: AsRef<Path>>(self, path: P) -> Result<ConvertSummary> {
+        self.builder.build(path)?;
+        Ok(self.summary)
+    }
+}
+
+/// Writer that computes the CRC-32 of what passes through
+struct CrcWriter<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An entry of a ZIP central directory
+struct ZipEntry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    local_offset: u64,
+}
+
+/// Read the central directory of a ZIP
+fn read_zip_directory<R: Read + Seek>(reader: &mut R) -> Result<Vec<ZipEntry>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = len.min((ZIP_END_OF_DIRECTORY_SIZE + 0xFFFF) as u64);
+    reader.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+
+    if tail.len() < ZIP_END_OF_DIRECTORY_SIZE {
+        return Err(Error::invalid_format("file is too small to be a ZIP"));
+    }
+    let end = (0..=tail.len() - ZIP_END_OF_DIRECTORY_SIZE)
+        .rev()
+        .find(|&pos| le_u32(&tail, pos) == ZIP_END_OF_DIRECTORY)
+        .ok_or_else(|| Error::invalid_format("no ZIP end of central directory record found"))?;
+    let record = &tail[end..];
+    let count = le_u16(record, 10);
+    let directory_size = le_u32(record, 12);
+    let directory_offset = le_u32(record, 16);
+    if count == u16::MAX || directory_offset == u32::MAX {
+        return Err(Error::invalid_format("ZIP64 archives are not supported"));
+    }
+    if u64::from(directory_offset) + u64::from(directory_size) > len {
+        return Err(Error::invalid_format(
+            "ZIP central directory extends past the end of the file",
+        ));
+    }
+
+    reader.seek(SeekFrom::Start(u64::from(directory_offset)))?;
+    let mut directory = vec![0u8; directory_size as usize];
+    reader.read_exact(&mut directory)?;
+
+    let mut entries = Vec::with_capacity(usize::from(count));
+    let mut pos = 0;
+    for _ in 0..count {
+        if directory.len() < pos + 46 || le_u32(&directory, pos) != ZIP_CENTRAL_HEADER {
+            return Err(Error::invalid_format("malformed ZIP central directory"));
+        }
+        let name_len = usize::from(le_u16(&directory, pos + 28));
+        let extra_len = usize::from(le_u16(&directory, pos + 30));
+        let comment_len = usize::from(le_u16(&directory, pos + 32));
+        let name = directory
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or_else(|| Error::invalid_format("malformed ZIP central directory"))?;
+        let local_offset = le_u32(&directory, pos + 42);
+        if local_offset == u32::MAX {
+            return Err(Error::invalid_format("ZIP64 archives are not supported"));
+        }
+
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags: le_u16(&directory, pos + 8),
+            method: le_u16(&directory, pos + 10),
+            crc: le_u32(&directory, pos + 16),
+            compressed_size: le_u32(&directory, pos + 20),
+            size: le_u32(&directory, pos + 24),
+            local_offset: u64::from(local_offset),
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// Fields shared by the local and central headers of an exported entry
+struct ZipHeader<'a> {
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    name: &'a [u8],
+}
+
+impl ZipHeader<'_> {
+    /// Write the part both headers share, from the version needed on
+    fn write_common<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&ZIP_VERSION.to_le_bytes())?;
+        writer.write_all(&ZIP_FLAG_UTF8.to_le_bytes())?;
+        writer.write_all(&self.method.to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes())?; // time
+        writer.write_all(&ZIP_DOS_DATE.to_le_bytes())?;
+        writer.write_all(&self.crc.to_le_bytes())?;
+        writer.write_all(&self.compressed_size.to_le_bytes())?;
+        writer.write_all(&self.size.to_le_bytes())?;
+        writer.write_all(&(self.name.len() as u16).to_le_bytes())?;
+        writer.write_all(&0u16.to_le_bytes()) // extra field length
+    }
+
+    fn write_local<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&ZIP_LOCAL_HEADER.to_le_bytes())?;
+        self.write_common(writer)?;
+        writer.write_all(self.name)
+    }
+
+    fn write_central<W: Write>(&self, writer: &mut W, offset: u32) -> io::Result<()> {
+        writer.write_all(&ZIP_CENTRAL_HEADER.to_le_bytes())?;
+        writer.write_all(&ZIP_VERSION.to_le_bytes())?; // version made by
+        self.write_common(writer)?;
+        writer.write_all(&0u16.to_le_bytes())?; // comment length
+        writer.write_all(&0u16.to_le_bytes())?; // disk number
+        writer.write_all(&0u16.to_le_bytes())?; // internal attributes
+        writer.write_all(&0u32.to_le_bytes())?; // external attributes
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(self.name)
+    }
+}
+
+/// Check that a size or offset fits a ZIP without ZIP64
+fn zip_offset(value: u64) -> Result<u32> {
+    u32::try_from(value)
+        .ok()
+        .filter(|&value| value != u32::MAX)
+        .ok_or_else(|| {
+            Error::CapacityExceeded("ZIP would reach 4 GiB, which needs ZIP64".to_string())
+        })
+}
+
+/// The fields of a TAR header that conversion uses
+struct TarHeader {
+    name: String,
+    size: u64,
+    kind: u8,
+}
+
+/// Parse and check a TAR header block
+fn parse_tar_header(header: &[u8; TAR_BLOCK]) -> Result<TarHeader> {
+    let stored = parse_octal(&header[148..156])
+        .ok_or_else(|| Error::invalid_format("malformed TAR header checksum"))?;
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                u64::from(b)
+            }
+        })
+        .sum();
+    if sum != stored {
+        return Err(Error::invalid_format(format!(
+            "TAR header checksum mismatch: expected {}, got {}",
+            stored, sum
+        )));
+    }
+
+    let size_field = &header[124..136];
+    let size = if size_field[0] & 0x80 != 0 {
+        // GNU base-256 encoding for sizes of 8 GiB and over
+        size_field[1..]
+            .iter()
+            .fold(u64::from(size_field[0] & 0x7f), |acc, &b| {
+                (acc << 8) | u64::from(b)
+            })
+    } else {
+        parse_octal(size_field).ok_or_else(|| Error::invalid_format("malformed TAR entry size"))?
+    };
+
+    let mut name = c_string(&header[..100]);
+    if &header[257..262] == b"ustar" {
+        let prefix = c_string(&header[345..500]);
+        if !prefix.is_empty() {
+            name = format!("{}/{}", prefix, name);
+        }
+    }
+
+    Ok(TarHeader {
+        name,
+        size,
+        kind: header[156],
+    })
+}
+
+/// Parse a NUL or space terminated octal field
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// The `path` record of a pax extended header
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .filter_map(|record| record.split_once(' ')?.1.strip_prefix("path="))
+        .next_back()
+        .map(str::to_string)
+}
+
+/// Text up to the first NUL
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn tar_truncated() -> Error {
+    Error::invalid_format("TAR file ends in the middle of an entry")
+}
+
+/// Fill `buf`, returning false if the reader was already at its end
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(tar_truncated()),
+            n => filled += n,
+        }
+    }
+    Ok(true)
+}
+
+/// Read past `len` bytes
+fn skip<R: Read>(reader: &mut R, len: u64) -> Result<()> {
+    if io::copy(&mut reader.take(len), &mut io::sink())? != len {
+        return Err(tar_truncated());
+    }
+    Ok(())
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn le_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn le_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_name() {
+        let options = ConvertOptions::new()
+            .strip_prefix("MyMod/")
+            .add_prefix("Interface\\");
+        assert_eq!(
+            options.map_name("mymod\\AddOns/x.lua", '\\').as_deref(),
+            Some("Interface\\AddOns\\x.lua")
+        );
+        assert_eq!(options.map_name("MyMod/", '\\'), None);
+        assert_eq!(
+            options.map_name("other/y.txt", '/').as_deref(),
+            Some("Interface/other/y.txt")
+        );
+
+        let plain = ConvertOptions::new();
+        assert_eq!(plain.map_name("(listfile)", '\\'), None);
+        assert_eq!(
+            plain
+                .clone()
+                .include_special(true)
+                .map_name("(listfile)", '\\')
+                .as_deref(),
+            Some("(listfile)")
+        );
+    }
+
+    #[test]
+    fn test_parse_octal() {
+        assert_eq!(parse_octal(b"0000644\0"), Some(0o644));
+        assert_eq!(parse_octal(b"00000000012 "), Some(10));
+        assert_eq!(parse_octal(b"\0\0\0\0"), Some(0));
+        assert_eq!(parse_octal(b"12x"), None);
+    }
+
+    #[test]
+    fn test_pax_path() {
+        let data = b"20 comment=testing\n27 path=very/long/name.txt\n";
+        assert_eq!(pax_path(data).as_deref(), Some("very/long/name.txt"));
+        assert_eq!(pax_path(b"12 uid=1000\n"), None);
+    }
+
+    #[test]
+    fn test_zip_directory_past_end() {
+        // An end record claiming a 4 GiB directory in a 22 byte file
+        let mut data = vec![0u8; ZIP_END_OF_DIRECTORY_SIZE];
+        data[..4].copy_from_slice(&ZIP_END_OF_DIRECTORY.to_le_bytes());
+        data[10..12].copy_from_slice(&1u16.to_le_bytes());
+        data[12..16].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+
+        let result = read_zip_directory(&mut std::io::Cursor::new(data));
+        assert!(matches!(result, Err(Error::InvalidFormat(_))));
+    }
+}
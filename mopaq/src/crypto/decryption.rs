@@ -40,3 +40,32 @@ pub fn decrypt_dword(value: u32, key: u32) -> u32 {
 
     value ^ (key.wrapping_add(seed))
 }
+
+/// Recover the key of an encrypted block from its first plaintext DWORD
+///
+/// The first DWORD of a block is only mixed with the key and one entry of the
+/// encryption table selected by the key's low byte, so at most 256 keys can
+/// turn `encrypted[0]` into `first`. Each such candidate decrypts a copy of
+/// the whole block, which is handed to `accept`; the first key it accepts is
+/// returned.
+///
+/// This is how keys are recovered for tables that protectors encrypted with
+/// something other than the standard name-derived key.
+pub fn detect_key_by_known_plaintext<F>(encrypted: &[u32], first: u32, mut accept: F) -> Option<u32>
+where
+    F: FnMut(&[u32]) -> bool,
+{
+    let key_plus_seed = *encrypted.first()? ^ first;
+
+    (0..0x100u32).find_map(|low_byte| {
+        let seed = 0xEEEE_EEEEu32.wrapping_add(ENCRYPTION_TABLE[0x400 + low_byte as usize]);
+        let key = key_plus_seed.wrapping_sub(seed);
+        if key & 0xFF != low_byte || key == 0 {
+            return None;
+        }
+
+        let mut decrypted = encrypted.to_vec();
+        decrypt_block(&mut decrypted, key);
+        accept(&decrypted).then_some(key)
+    })
+}
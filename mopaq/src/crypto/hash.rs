@@ -2,22 +2,85 @@
 
 use super::keys::{ASCII_TO_LOWER, ASCII_TO_UPPER, ENCRYPTION_TABLE};
 
+/// How file names are normalized before they are hashed for lookups
+///
+/// Blizzard archives hash names case-insensitively with `/` treated as `\`,
+/// which is the default. Some third-party MPQ derivatives hash names as
+/// written, or use `/` as their separator. The policy applies to the hash
+/// table and HET table lookups; encryption keys are always derived with the
+/// default normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NameHashingPolicy {
+    /// Hash names without folding ASCII letters to one case
+    pub case_sensitive: bool,
+    /// Separator both `/` and `\` are converted to, or `None` to hash
+    /// separators as written
+    pub separator: Option<u8>,
+}
+
+impl NameHashingPolicy {
+    /// The normalization used by Blizzard's games and StormLib
+    pub const BLIZZARD: Self = Self {
+        case_sensitive: false,
+        separator: Some(b'\\'),
+    };
+
+    /// Hash names exactly as written
+    pub const VERBATIM: Self = Self {
+        case_sensitive: true,
+        separator: None,
+    };
+
+    /// Canonical form of a name for comparisons
+    ///
+    /// Names with the same canonical form hash the same under this policy.
+    pub fn normalize_name(self, name: &str) -> String {
+        name.chars()
+            .map(|ch| {
+                if ch.is_ascii() {
+                    self.normalize_byte(ch as u8, &ASCII_TO_UPPER) as char
+                } else {
+                    ch
+                }
+            })
+            .collect()
+    }
+
+    /// Normalize one name byte, folding case with `case_table`
+    #[inline]
+    fn normalize_byte(self, byte: u8, case_table: &[u8; 256]) -> u8 {
+        let byte = match self.separator {
+            Some(separator) if byte == b'/' || byte == b'\\' => separator,
+            _ => byte,
+        };
+        if self.case_sensitive {
+            byte
+        } else {
+            case_table[byte as usize]
+        }
+    }
+}
+
+impl Default for NameHashingPolicy {
+    fn default() -> Self {
+        Self::BLIZZARD
+    }
+}
+
 /// Hash a string using the MPQ hash algorithm
 pub fn hash_string(filename: &str, hash_type: u32) -> u32 {
+    hash_string_with(filename, hash_type, NameHashingPolicy::BLIZZARD)
+}
+
+/// Hash a string using the MPQ hash algorithm, normalized by `policy`
+pub fn hash_string_with(filename: &str, hash_type: u32, policy: NameHashingPolicy) -> u32 {
     let mut seed1: u32 = 0x7FED7FED;
     let mut seed2: u32 = 0xEEEEEEEE;
 
     for &byte in filename.as_bytes() {
         // Get the next character and normalize it
-        let mut ch = byte;
-
-        // Convert path separators to backslash
-        if ch == b'/' {
-            ch = b'\\';
-        }
-
-        // Convert to uppercase using the table
-        ch = ASCII_TO_UPPER[ch as usize];
+        let ch = policy.normalize_byte(byte, &ASCII_TO_UPPER);
 
         // Update the hash
         let table_idx = (hash_type * 0x100 + ch as u32) as usize;
@@ -34,19 +97,16 @@ pub fn hash_string(filename: &str, hash_type: u32) -> u32 {
 
 /// Jenkins hash function for HET tables
 pub fn jenkins_hash(filename: &str) -> u64 {
+    jenkins_hash_with(filename, NameHashingPolicy::BLIZZARD)
+}
+
+/// Jenkins hash function for HET tables, normalized by `policy`
+pub fn jenkins_hash_with(filename: &str, policy: NameHashingPolicy) -> u64 {
     let mut hash: u64 = 0;
 
     for &byte in filename.as_bytes() {
         // Get the next character and normalize it
-        let mut ch = byte;
-
-        // Convert path separators to backslash
-        if ch == b'/' {
-            ch = b'\\';
-        }
-
-        // Convert to lowercase using the table
-        ch = ASCII_TO_LOWER[ch as usize];
+        let ch = policy.normalize_byte(byte, &ASCII_TO_LOWER);
 
         // Jenkins one-at-a-time hash algorithm
         hash = hash.wrapping_add(ch as u64);
@@ -61,6 +121,82 @@ pub fn jenkins_hash(filename: &str) -> u64 {
     hash
 }
 
+/// A file name with its lookup hashes computed once
+///
+/// Finding a file hashes its name three times for the classic hash table
+/// and once more for the HET table. Callers looking the same names up again
+/// and again, such as in every archive of a patch chain, can hash them once
+/// and use [`Archive::find_file_pre`](crate::Archive::find_file_pre).
+///
+/// # Examples
+/// ```
+/// use mopaq::{hash_string, hash_type, PrecomputedName};
+///
+/// let name = PrecomputedName::new("war3map.j");
+/// assert_eq!(name.name_a(), hash_string("war3map.j", hash_type::NAME_A));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PrecomputedName {
+    name: String,
+    policy: NameHashingPolicy,
+    table_offset: u32,
+    name_a: u32,
+    name_b: u32,
+    jenkins: u64,
+}
+
+impl PrecomputedName {
+    /// Hash `name` the way Blizzard archives do
+    pub fn new(name: &str) -> Self {
+        Self::with_policy(name, NameHashingPolicy::BLIZZARD)
+    }
+
+    /// Hash `name` for archives using `policy`
+    pub fn with_policy(name: &str, policy: NameHashingPolicy) -> Self {
+        use super::types::hash_type;
+
+        Self {
+            name: name.to_string(),
+            policy,
+            table_offset: hash_string_with(name, hash_type::TABLE_OFFSET, policy),
+            name_a: hash_string_with(name, hash_type::NAME_A, policy),
+            name_b: hash_string_with(name, hash_type::NAME_B, policy),
+            jenkins: jenkins_hash_with(name, policy),
+        }
+    }
+
+    /// The name as given
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Policy the hashes were computed with
+    pub fn policy(&self) -> NameHashingPolicy {
+        self.policy
+    }
+
+    /// Hash table start index, before masking to the table size
+    pub fn table_offset(&self) -> u32 {
+        self.table_offset
+    }
+
+    /// First name check hash stored in hash table entries
+    pub fn name_a(&self) -> u32 {
+        self.name_a
+    }
+
+    /// Second name check hash stored in hash table entries
+    pub fn name_b(&self) -> u32 {
+        self.name_b
+    }
+
+    /// Jenkins hash used by HET and BET tables, before masking to their
+    /// hash size
+    pub fn jenkins(&self) -> u64 {
+        self.jenkins
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,4 +343,73 @@ mod tests {
         let key = hash_string(filename, hash_type::FILE_KEY);
         assert_eq!(key, 0xEC83B3A3);
     }
+
+    #[test]
+    fn test_name_hashing_policy() {
+        let name = "Data/Units/Footman.MDX";
+        let blizzard = NameHashingPolicy::default();
+        assert_eq!(
+            hash_string_with(name, hash_type::NAME_A, blizzard),
+            hash_string("data\\units\\footman.mdx", hash_type::NAME_A)
+        );
+        assert_eq!(
+            jenkins_hash_with(name, blizzard),
+            jenkins_hash("DATA\\UNITS\\FOOTMAN.MDX")
+        );
+
+        // Case-sensitive names only match with the same case
+        let case_sensitive = NameHashingPolicy {
+            case_sensitive: true,
+            ..blizzard
+        };
+        assert_eq!(
+            hash_string_with("Data\\A.txt", hash_type::NAME_A, case_sensitive),
+            hash_string_with("Data/A.txt", hash_type::NAME_A, case_sensitive)
+        );
+        assert_ne!(
+            hash_string_with("Data\\A.txt", hash_type::NAME_A, case_sensitive),
+            hash_string_with("Data\\a.txt", hash_type::NAME_A, case_sensitive)
+        );
+        assert_ne!(
+            jenkins_hash_with("A.txt", case_sensitive),
+            jenkins_hash_with("a.txt", case_sensitive)
+        );
+
+        // Slash-separated names hash both separators as '/'
+        let slash = NameHashingPolicy {
+            separator: Some(b'/'),
+            ..blizzard
+        };
+        assert_eq!(
+            hash_string_with("data\\a.txt", hash_type::NAME_B, slash),
+            hash_string_with("DATA/A.TXT", hash_type::NAME_B, NameHashingPolicy::VERBATIM)
+        );
+        assert_ne!(
+            hash_string_with("data/a.txt", hash_type::NAME_B, slash),
+            hash_string("data/a.txt", hash_type::NAME_B)
+        );
+    }
+
+    #[test]
+    fn test_precomputed_name() {
+        let policy = NameHashingPolicy::VERBATIM;
+        let name = PrecomputedName::with_policy("Units/Footman.mdx", policy);
+        assert_eq!(name.name(), "Units/Footman.mdx");
+        assert_eq!(
+            name.table_offset(),
+            hash_string_with("Units/Footman.mdx", hash_type::TABLE_OFFSET, policy)
+        );
+        assert_eq!(
+            name.name_b(),
+            hash_string_with("Units/Footman.mdx", hash_type::NAME_B, policy)
+        );
+        assert_eq!(
+            name.jenkins(),
+            jenkins_hash_with("Units/Footman.mdx", policy)
+        );
+        assert_ne!(
+            name.name_a(),
+            PrecomputedName::new("Units/Footman.mdx").name_a()
+        );
+    }
 }
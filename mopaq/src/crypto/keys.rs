@@ -27,8 +27,29 @@ const fn generate_encryption_table() -> [u32; 0x500] {
     table
 }
 
+/// The table as a compile-time constant, kept private so the checks below can
+/// inspect it without taking a reference to the static.
+const TABLE: [u32; 0x500] = generate_encryption_table();
+
+// Known-answer checks against StormLib's `StormBuffer`. A regression in the
+// generator fails the build instead of producing unreadable archives.
+const _: () = {
+    assert!(TABLE[0x000] == 0x55C6_36E2);
+    assert!(TABLE[0x100] == 0x76F8_C1B1);
+    assert!(TABLE[0x200] == 0x3DF6_965D);
+    assert!(TABLE[0x300] == 0x15F2_61D3);
+    assert!(TABLE[0x400] == 0x193A_A698);
+    assert!(TABLE[0x4FF] == 0x7303_286C);
+};
+
 /// The static encryption table used by all MPQ operations
-pub const ENCRYPTION_TABLE: [u32; 0x500] = generate_encryption_table();
+///
+/// The table is evaluated entirely at compile time and lives in read-only
+/// data, so there is no initialization on first use and no locking on the
+/// hashing and decryption paths. It is a `static` rather than a `const` so
+/// every use shares one instance with a stable address, which is what C
+/// callers expecting StormLib's 0x500-entry `StormBuffer` need.
+pub static ENCRYPTION_TABLE: [u32; 0x500] = TABLE;
 
 /// ASCII uppercase conversion table
 pub(crate) const ASCII_TO_UPPER: [u8; 256] = [
@@ -8,14 +8,19 @@ mod signature;
 mod types;
 
 // Re-export public API
-pub use decryption::{decrypt_block, decrypt_dword};
+pub use decryption::{decrypt_block, decrypt_dword, detect_key_by_known_plaintext};
 pub use encryption::encrypt_block;
-pub use hash::{hash_string, jenkins_hash};
+pub use hash::{
+    hash_string, hash_string_with, jenkins_hash, jenkins_hash_with, NameHashingPolicy,
+    PrecomputedName,
+};
 pub use signature::{
     calculate_mpq_hash_md5, parse_strong_signature, parse_weak_signature, public_keys,
-    verify_strong_signature, verify_weak_signature, verify_weak_signature_stormlib, SignatureInfo,
-    SignatureType, DIGEST_UNIT_SIZE, STRONG_SIGNATURE_HEADER, STRONG_SIGNATURE_SIZE,
-    WEAK_SIGNATURE_FILE_SIZE, WEAK_SIGNATURE_SIZE,
+    sign_strong_signature, sign_weak_signature, verify_strong_signature,
+    verify_strong_signature_with_key, verify_weak_signature, verify_weak_signature_stormlib,
+    verify_weak_signature_with_key, SignatureInfo, SignaturePolicy, SignatureType,
+    DIGEST_UNIT_SIZE, STRONG_SIGNATURE_HEADER, STRONG_SIGNATURE_SIZE, WEAK_SIGNATURE_FILE_SIZE,
+    WEAK_SIGNATURE_SIZE,
 };
 pub use types::hash_type;
 
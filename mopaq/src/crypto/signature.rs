@@ -8,10 +8,12 @@ use crate::{Error, Result};
 use md5::{Digest, Md5};
 use num_bigint::BigUint;
 use num_traits::Num;
+use rsa::hazmat::rsa_decrypt_and_check;
+use rsa::rand_core::OsRng;
 use rsa::traits::PublicKeyParts;
-use rsa::{BigUint as RsaBigUint, RsaPublicKey};
+use rsa::{BigUint as RsaBigUint, RsaPrivateKey, RsaPublicKey};
 use sha1::Sha1;
-use std::io::Read;
+use std::io::{Read, Seek};
 
 /// Weak signature size (512-bit RSA)
 pub const WEAK_SIGNATURE_SIZE: usize = 64; // 512 bits / 8
@@ -28,6 +30,16 @@ pub const STRONG_SIGNATURE_HEADER: [u8; 4] = *b"NGIS"; // "SIGN" reversed
 /// Strong signature size (2048-bit RSA + 4 byte header)
 pub const STRONG_SIGNATURE_SIZE: usize = 256 + 4; // 2048 bits / 8 + header
 
+/// DER prefix of an MD5 `DigestInfo` in PKCS#1 v1.5 padding
+const MD5_DIGEST_INFO: [u8; 18] = [
+    0x30, 0x20, // SEQUENCE, length 32
+    0x30, 0x0C, // SEQUENCE, length 12
+    0x06, 0x08, // OID, length 8
+    0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x02, 0x05, // MD5 OID
+    0x05, 0x00, // NULL
+    0x04, 0x10, // OCTET STRING, length 16
+];
+
 /// Signature type in the archive
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SignatureType {
@@ -37,6 +49,44 @@ pub enum SignatureType {
     Strong,
 }
 
+impl SignatureType {
+    /// The kind of signature a private key of this size makes
+    ///
+    /// # Errors
+    /// - `Error::Crypto` unless the key is 512 or 2048 bits
+    pub fn for_key(key: &RsaPrivateKey) -> Result<Self> {
+        match key.size() {
+            WEAK_SIGNATURE_SIZE => Ok(SignatureType::Weak),
+            256 => Ok(SignatureType::Strong),
+            size => Err(Error::crypto(format!(
+                "{}-bit key cannot sign an MPQ archive; weak signatures need 512 bits \
+                 and strong signatures 2048",
+                size * 8
+            ))),
+        }
+    }
+}
+
+/// What happens to an archive's signature when it is rebuilt or modified
+///
+/// A signature covers the archive's bytes, so any change invalidates it.
+/// The weak signature lives in the `(signature)` file, whose contents are
+/// left out of the hash it signs; the strong signature follows the archive
+/// and covers everything before it.
+#[derive(Debug, Clone)]
+pub enum SignaturePolicy {
+    /// Leave the signature out of the new archive
+    Strip,
+    /// Keep the old signature bytes unchanged, although they no longer verify
+    Preserve,
+    /// Sign the new archive with this key
+    ///
+    /// A 512-bit key writes a weak signature into `(signature)`, creating the
+    /// file if needed; a 2048-bit key appends a strong signature. See
+    /// [`SignatureType::for_key`].
+    Resign(Box<RsaPrivateKey>),
+}
+
 /// Signature information for hash calculation (matches StormLib's MPQ_SIGNATURE_INFO)
 #[derive(Debug, Clone)]
 pub struct SignatureInfo {
@@ -257,28 +307,62 @@ pub fn verify_weak_signature_stormlib<R: Read + std::io::Seek>(
     signature: &[u8],
     signature_info: &SignatureInfo,
 ) -> Result<bool> {
-    // Get the public key
-    let public_key = public_keys::weak_public_key()?;
+    verify_weak_signature_with_key(
+        reader,
+        signature,
+        signature_info,
+        &public_keys::weak_public_key()?,
+    )
+}
 
+/// Verify a weak signature made with a key other than Blizzard's
+pub fn verify_weak_signature_with_key<R: Read + std::io::Seek>(
+    reader: R,
+    signature: &[u8],
+    signature_info: &SignatureInfo,
+    public_key: &RsaPublicKey,
+) -> Result<bool> {
     // Calculate MD5 hash using StormLib's approach
     let hash = calculate_mpq_hash_md5(reader, signature_info)?;
 
-    // Convert signature from little-endian to big-endian (StormLib's memrev operation)
-    let signature_be = reverse_bytes(signature);
-
     // Decrypt the signature using RSA
-    let signature_int = BigUint::from_bytes_be(&signature_be);
-    let n = BigUint::from_bytes_be(&public_key.n().to_bytes_be());
-    let e = BigUint::from_bytes_be(&public_key.e().to_bytes_be());
-
-    // Perform RSA operation: signature^e mod n
-    let decrypted = signature_int.modpow(&e, &n);
-    let decrypted_bytes = decrypted.to_bytes_be();
+    let decrypted_bytes = rsa_public_raw(signature, public_key);
 
     // Verify PKCS#1 v1.5 padding
     verify_pkcs1_v15_md5(&decrypted_bytes, &hash)
 }
 
+/// Create a weak signature (512-bit RSA with MD5) over an archive
+///
+/// `signature_info` describes the archive and the `(signature)` file to
+/// leave out, as for verification. Returns the [`WEAK_SIGNATURE_SIZE`]
+/// signature bytes in the order they are stored at offset 8 of the
+/// `(signature)` file.
+///
+/// # Errors
+/// - `Error::Crypto` if `key` is not a 512-bit key
+pub fn sign_weak_signature<R: Read + Seek>(
+    reader: R,
+    signature_info: &SignatureInfo,
+    key: &RsaPrivateKey,
+) -> Result<Vec<u8>> {
+    if SignatureType::for_key(key)? != SignatureType::Weak {
+        return Err(Error::crypto("Weak signatures need a 512-bit key"));
+    }
+    let hash = calculate_mpq_hash_md5(reader, signature_info)?;
+
+    // 0x00 || 0x01 || 0xFF padding || 0x00 || DigestInfo || MD5
+    let mut block = vec![0xFF; WEAK_SIGNATURE_SIZE];
+    block[0] = 0x00;
+    block[1] = 0x01;
+    let digest_start = WEAK_SIGNATURE_SIZE - MD5_DIGEST_INFO.len() - hash.len();
+    block[digest_start - 1] = 0x00;
+    block[digest_start..digest_start + MD5_DIGEST_INFO.len()].copy_from_slice(&MD5_DIGEST_INFO);
+    block[digest_start + MD5_DIGEST_INFO.len()..].copy_from_slice(&hash);
+
+    rsa_private_raw(&block, key)
+}
+
 /// Legacy verify function (kept for backward compatibility)
 pub fn verify_weak_signature<R: Read>(
     mut reader: R,
@@ -306,17 +390,8 @@ pub fn verify_weak_signature<R: Read>(
 
     let hash = hasher.finalize();
 
-    // Convert signature from little-endian to big-endian
-    let signature_be = reverse_bytes(signature);
-
     // Decrypt the signature using RSA
-    let signature_int = BigUint::from_bytes_be(&signature_be);
-    let n = BigUint::from_bytes_be(&public_key.n().to_bytes_be());
-    let e = BigUint::from_bytes_be(&public_key.e().to_bytes_be());
-
-    // Perform RSA operation: signature^e mod n
-    let decrypted = signature_int.modpow(&e, &n);
-    let decrypted_bytes = decrypted.to_bytes_be();
+    let decrypted_bytes = rsa_public_raw(signature, &public_key);
 
     // Verify PKCS#1 v1.5 padding
     verify_pkcs1_v15_md5(&decrypted_bytes, &hash)
@@ -324,21 +399,70 @@ pub fn verify_weak_signature<R: Read>(
 
 /// Verify a strong signature (2048-bit RSA with SHA-1)
 pub fn verify_strong_signature<R: Read>(
-    mut reader: R,
+    reader: R,
     signature: &[u8],
     archive_size: u64,
 ) -> Result<bool> {
-    // Get the public key
-    let public_key = public_keys::strong_public_key()?;
+    verify_strong_signature_with_key(
+        reader,
+        signature,
+        archive_size,
+        &public_keys::strong_public_key()?,
+    )
+}
+
+/// Verify a strong signature made with a key other than Blizzard's
+pub fn verify_strong_signature_with_key<R: Read>(
+    reader: R,
+    signature: &[u8],
+    archive_size: u64,
+    public_key: &RsaPublicKey,
+) -> Result<bool> {
+    let hash = calculate_sha1(reader, archive_size)?;
+
+    // Decrypt the signature using RSA
+    let decrypted_bytes = rsa_public_raw(signature, public_key);
+
+    // Verify custom MPQ strong signature padding
+    verify_mpq_strong_signature_padding(&decrypted_bytes, &hash)
+}
+
+/// Create a strong signature (2048-bit RSA with SHA-1) over an archive
+///
+/// `reader` is positioned at the start of the archive and `archive_size`
+/// bytes are signed. Returns the [`STRONG_SIGNATURE_SIZE`] bytes, header
+/// included, that are appended after the archive.
+///
+/// # Errors
+/// - `Error::Crypto` if `key` is not a 2048-bit key
+pub fn sign_strong_signature<R: Read>(
+    reader: R,
+    archive_size: u64,
+    key: &RsaPrivateKey,
+) -> Result<Vec<u8>> {
+    if SignatureType::for_key(key)? != SignatureType::Strong {
+        return Err(Error::crypto("Strong signatures need a 2048-bit key"));
+    }
+    let hash = calculate_sha1(reader, archive_size)?;
+
+    // 0x0B || 0xBB padding || SHA-1
+    let mut block = vec![0xBB; 256];
+    block[0] = 0x0B;
+    block[256 - hash.len()..].copy_from_slice(&hash);
+
+    let mut data = STRONG_SIGNATURE_HEADER.to_vec();
+    data.extend_from_slice(&rsa_private_raw(&block, key)?);
+    Ok(data)
+}
 
-    // Calculate SHA-1 hash of the archive (excluding the signature)
+/// SHA-1 of the next `size` bytes of `reader`
+fn calculate_sha1<R: Read>(mut reader: R, size: u64) -> Result<[u8; 20]> {
     let mut hasher = Sha1::new();
     let mut buffer = vec![0u8; 65536]; // 64KB buffer
     let mut bytes_read = 0u64;
 
-    // Read up to archive_size (which should exclude the signature)
-    while bytes_read < archive_size {
-        let to_read = ((archive_size - bytes_read) as usize).min(buffer.len());
+    while bytes_read < size {
+        let to_read = ((size - bytes_read) as usize).min(buffer.len());
         let n = reader.read(&mut buffer[..to_read])?;
         if n == 0 {
             break;
@@ -347,22 +471,39 @@ pub fn verify_strong_signature<R: Read>(
         bytes_read += n as u64;
     }
 
-    let hash = hasher.finalize();
+    Ok(hasher.finalize().into())
+}
 
-    // Convert signature from little-endian to big-endian
+/// RSA public-key operation on a little-endian signature
+///
+/// Returns the big-endian result padded to the modulus size, so leading
+/// zero bytes of the padding are kept.
+fn rsa_public_raw(signature: &[u8], public_key: &RsaPublicKey) -> Vec<u8> {
+    // Convert signature from little-endian to big-endian (StormLib's memrev operation)
     let signature_be = reverse_bytes(signature);
-
-    // Decrypt the signature using RSA
     let signature_int = BigUint::from_bytes_be(&signature_be);
     let n = BigUint::from_bytes_be(&public_key.n().to_bytes_be());
     let e = BigUint::from_bytes_be(&public_key.e().to_bytes_be());
 
     // Perform RSA operation: signature^e mod n
-    let decrypted = signature_int.modpow(&e, &n);
-    let decrypted_bytes = decrypted.to_bytes_be();
+    let decrypted = signature_int.modpow(&e, &n).to_bytes_be();
+    let mut padded = vec![0u8; public_key.size().saturating_sub(decrypted.len())];
+    padded.extend_from_slice(&decrypted);
+    padded
+}
 
-    // Verify custom MPQ strong signature padding
-    verify_mpq_strong_signature_padding(&decrypted_bytes, &hash)
+/// RSA private-key operation on a big-endian block, returning the result
+/// little-endian as MPQ signatures are stored
+///
+/// The operation is blinded with a random factor so its timing does not
+/// depend on the key, and the result is checked against the public key.
+fn rsa_private_raw(block: &[u8], key: &RsaPrivateKey) -> Result<Vec<u8>> {
+    let mut signature =
+        rsa_decrypt_and_check(key, Some(&mut OsRng), &RsaBigUint::from_bytes_be(block))
+            .map_err(|e| Error::crypto(format!("RSA signing failed: {}", e)))?
+            .to_bytes_le();
+    signature.resize(key.size(), 0);
+    Ok(signature)
 }
 
 /// Verify PKCS#1 v1.5 padding for MD5
@@ -395,28 +536,18 @@ fn verify_pkcs1_v15_md5(decrypted: &[u8], expected_hash: &[u8]) -> Result<bool>
     let separator_pos = separator_pos
         .ok_or_else(|| Error::invalid_format("No separator found in PKCS#1 padding"))?;
 
-    // MD5 DigestInfo (from PKCS#1)
-    let md5_digest_info = [
-        0x30, 0x20, // SEQUENCE, length 32
-        0x30, 0x0C, // SEQUENCE, length 12
-        0x06, 0x08, // OID, length 8
-        0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x02, 0x05, // MD5 OID
-        0x05, 0x00, // NULL
-        0x04, 0x10, // OCTET STRING, length 16
-    ];
-
     // Check DigestInfo
     let digest_start = separator_pos + 1;
-    if digest_start + md5_digest_info.len() + 16 != decrypted.len() {
+    if digest_start + MD5_DIGEST_INFO.len() + 16 != decrypted.len() {
         return Ok(false);
     }
 
-    if decrypted[digest_start..digest_start + md5_digest_info.len()] != md5_digest_info {
+    if decrypted[digest_start..digest_start + MD5_DIGEST_INFO.len()] != MD5_DIGEST_INFO {
         return Ok(false);
     }
 
     // Check hash
-    let hash_start = digest_start + md5_digest_info.len();
+    let hash_start = digest_start + MD5_DIGEST_INFO.len();
     Ok(&decrypted[hash_start..] == expected_hash)
 }
 
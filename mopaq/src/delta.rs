@@ -0,0 +1,606 @@
+//! Binary delta patches between archives
+//!
+//! A [`DeltaPatch`] records what it takes to turn one archive into another:
+//! files that were removed, files that were added (stored in full) and files
+//! that changed, stored as a binary delta against the old contents whenever
+//! that is smaller than the new contents. Updaters ship the patch instead of
+//! the whole new archive and apply it to the copy the user already has.
+//!
+//! Deltas are sequences of copy and insert instructions produced by
+//! [`diff`]: runs that also occur in the old file are copied from it, the
+//! rest is inserted literally. The patch container is zlib-compressed as a
+//! whole, which takes care of the inserted bytes.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use mopaq::{Archive, DeltaPatch};
+//!
+//! // Publisher side
+//! let mut old = Archive::open("game-1.0.mpq")?;
+//! let mut new = Archive::open("game-1.1.mpq")?;
+//! DeltaPatch::generate(&mut old, &mut new)?.save("1.0-to-1.1.mpqdelta")?;
+//!
+//! // Client side: upgrade the installed archive in place
+//! DeltaPatch::load("1.0-to-1.1.mpqdelta")?.apply("game.mpq")?;
+//! # Ok::<(), mopaq::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::compression::{compress, decompress, flags as compression_flags};
+use crate::io::take;
+use crate::special_files::SpecialFile;
+use crate::{AddFileOptions, Archive, Error, MutableArchive, Result};
+
+/// Signature at the start of a patch container
+const MAGIC: [u8; 4] = *b"MPQd";
+
+/// Container format version written by [`DeltaPatch::write_to`]
+const VERSION: u32 = 1;
+
+/// Largest factor zlib inflates data by, which bounds the body size a
+/// container can plausibly declare
+const MAX_EXPANSION: usize = 1032;
+
+/// Length of the runs used to find matches between old and new contents
+const BLOCK_SIZE: usize = 16;
+
+/// Delta instruction: copy a range of the old contents
+const OP_COPY: u8 = 0;
+/// Delta instruction: insert literal bytes
+const OP_INSERT: u8 = 1;
+
+/// What happens to one file when a patch is applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaChange {
+    /// The file is removed
+    Removed,
+    /// The file is added or replaced with these contents
+    Raw(Vec<u8>),
+    /// The file's current contents are transformed with a [`diff`] delta
+    Diff {
+        /// CRC32 of the contents the delta was computed against
+        source_crc: u32,
+        /// CRC32 of the contents the delta produces
+        target_crc: u32,
+        /// Delta from [`diff`]
+        delta: Vec<u8>,
+    },
+}
+
+/// A single file in a [`DeltaPatch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaEntry {
+    /// File name in the archive
+    pub name: String,
+    /// Whether the file is encrypted in the target archive
+    pub encrypted: bool,
+    /// Whether the file's key is adjusted by its position (`FLAG_FIX_KEY`)
+    pub fix_key: bool,
+    /// Compression method of the file in the target archive, one of
+    /// `compression::flags` (0 = none)
+    pub compression: u8,
+    /// Whether the file has sector checksums in the target archive
+    pub sector_crc: bool,
+    /// Change to apply
+    pub change: DeltaChange,
+}
+
+/// The differences between two archives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeltaPatch {
+    /// Changed files in the order they are applied
+    pub entries: Vec<DeltaEntry>,
+}
+
+impl DeltaPatch {
+    /// Compute the patch that turns `source` into `target`
+    ///
+    /// Files are enumerated through each archive's `(listfile)` and matched
+    /// by name. Unchanged files are left out; changed files are stored as a
+    /// delta when it is smaller than the new contents and in full otherwise.
+    /// `(listfile)`, `(attributes)` and `(signature)` are skipped since
+    /// applying a patch regenerates or drops them.
+    ///
+    /// # Errors
+    /// - Any error from listing or reading either archive
+    pub fn generate(source: &mut Archive, target: &mut Archive) -> Result<Self> {
+        let mut entries = Vec::new();
+
+        for entry in source.list()? {
+            if is_special(&entry.name) || target.find_file(&entry.name)?.is_some() {
+                continue;
+            }
+            entries.push(DeltaEntry {
+                name: entry.name,
+                encrypted: false,
+                fix_key: false,
+                compression: 0,
+                sector_crc: false,
+                change: DeltaChange::Removed,
+            });
+        }
+
+        for entry in target.list()? {
+            if is_special(&entry.name) {
+                continue;
+            }
+            let info = target
+                .find_file(&entry.name)?
+                .ok_or_else(|| Error::FileNotFound(entry.name.clone()))?;
+            let new_data = target.read_file(&entry.name)?;
+
+            let change = if source.find_file(&entry.name)?.is_some() {
+                let old_data = source.read_file(&entry.name)?;
+                if old_data == new_data {
+                    continue;
+                }
+                let delta = diff(&old_data, &new_data);
+                if delta.len() < new_data.len() {
+                    DeltaChange::Diff {
+                        source_crc: crc32fast::hash(&old_data),
+                        target_crc: crc32fast::hash(&new_data),
+                        delta,
+                    }
+                } else {
+                    DeltaChange::Raw(new_data)
+                }
+            } else {
+                DeltaChange::Raw(new_data)
+            };
+
+            // Files whose sectors are all stored raw report no method, and
+            // storing them without compression lays them out the same way
+            let compression = target.compression_method(&entry.name)?.to_flags();
+            entries.push(DeltaEntry {
+                name: entry.name,
+                encrypted: info.is_encrypted(),
+                fix_key: info.has_fix_key(),
+                compression,
+                sector_crc: info.has_sector_crc(),
+                change,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Check whether the patch changes nothing
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Upgrade the archive at `path` in place
+    ///
+    /// Every delta is checked against the file it applies to before anything
+    /// is written, so a patch made for a different base leaves the archive
+    /// untouched. The changes are then made in place with
+    /// [`MutableArchive`], storing each file with the compression,
+    /// encryption and sector checksums it has in the target archive; files
+    /// the patch does not touch are left as they are.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if a file that a delta applies to is missing
+    /// - `Error::ChecksumMismatch` if a file's contents are not the ones the
+    ///   delta was computed against, or the result is not the expected one
+    /// - `Error::InvalidFormat` if a delta is malformed
+    /// - Any error from [`MutableArchive::open`], such as
+    ///   `Error::OperationNotSupported` for archives without classic tables
+    pub fn apply<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let archive = Archive::open(path)?;
+
+        // Resolve every change first so a bad base fails before writing
+        let mut updates = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let data = match &entry.change {
+                DeltaChange::Removed => None,
+                DeltaChange::Raw(data) => Some(data.clone()),
+                DeltaChange::Diff {
+                    source_crc,
+                    target_crc,
+                    delta,
+                } => {
+                    let old_data = archive.read_file(&entry.name)?;
+                    check_crc(&entry.name, *source_crc, &old_data)?;
+                    let new_data = patch(&old_data, delta)?;
+                    check_crc(&entry.name, *target_crc, &new_data)?;
+                    Some(new_data)
+                }
+            };
+            updates.push((entry, data));
+        }
+
+        drop(archive);
+
+        let mut archive = MutableArchive::open(path)?;
+        for (entry, data) in updates {
+            let Some(data) = data else {
+                match archive.remove_file(&entry.name) {
+                    Ok(()) | Err(Error::FileNotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+                continue;
+            };
+            let options = AddFileOptions::new()
+                .compression(entry.compression)
+                .encrypt(entry.encrypted)
+                .fix_key(entry.fix_key)
+                .sector_crc(entry.sector_crc);
+            archive.add_file_data(&data, &entry.name, &options)?;
+        }
+
+        archive.flush()
+    }
+
+    /// Serialize the patch into the compact container format
+    ///
+    /// # Errors
+    /// - Any I/O error from `writer`
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut body = Vec::new();
+        write_varint(&mut body, self.entries.len() as u64);
+        for entry in &self.entries {
+            write_bytes(&mut body, entry.name.as_bytes());
+            let flags = u8::from(entry.encrypted)
+                | (u8::from(entry.fix_key) << 1)
+                | (u8::from(entry.sector_crc) << 2);
+            match &entry.change {
+                DeltaChange::Removed => body.extend_from_slice(&[0, flags, entry.compression]),
+                DeltaChange::Raw(data) => {
+                    body.extend_from_slice(&[1, flags, entry.compression]);
+                    write_bytes(&mut body, data);
+                }
+                DeltaChange::Diff {
+                    source_crc,
+                    target_crc,
+                    delta,
+                } => {
+                    body.extend_from_slice(&[2, flags, entry.compression]);
+                    body.extend_from_slice(&source_crc.to_le_bytes());
+                    body.extend_from_slice(&target_crc.to_le_bytes());
+                    write_bytes(&mut body, delta);
+                }
+            }
+        }
+
+        let stored = compress(&body, compression_flags::ZLIB)?;
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(body.len() as u64).to_le_bytes())?;
+        writer.write_all(&(stored.len() as u64).to_le_bytes())?;
+        writer.write_all(&stored)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Parse a patch written by [`write_to`](Self::write_to)
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if the data is not a patch container, is
+    ///   truncated, or declares a body its data cannot hold
+    /// - `Error::UnsupportedVersion` for containers of any other version
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(Error::invalid_format("not an MPQ delta patch"));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version as u16));
+        }
+        let body_size = u64::from_le_bytes(header[8..16].try_into().unwrap()) as usize;
+        let stored_size = u64::from_le_bytes(header[16..24].try_into().unwrap());
+
+        let mut stored = Vec::new();
+        reader.take(stored_size).read_to_end(&mut stored)?;
+        if stored.len() as u64 != stored_size {
+            return Err(Error::invalid_format("truncated delta patch"));
+        }
+        // The body size is untrusted and sizes the decompression buffer
+        if body_size > stored.len().saturating_mul(MAX_EXPANSION) {
+            return Err(Error::invalid_format(format!(
+                "delta patch declares a {} byte body for {} bytes of data",
+                body_size,
+                stored.len()
+            )));
+        }
+        let body = match stored.split_first() {
+            Some((&method, data)) if stored.len() < body_size => {
+                decompress(data, method, body_size)?
+            }
+            _ => stored,
+        };
+
+        let mut cursor = body.as_slice();
+        let count = read_varint(&mut cursor)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let name = String::from_utf8(read_bytes(&mut cursor)?.to_vec())
+                .map_err(|_| Error::InvalidUtf8)?;
+            let [kind, flags] = take::<2>(&mut cursor)?;
+            let [compression] = take::<1>(&mut cursor)?;
+            let change = match kind {
+                0 => DeltaChange::Removed,
+                1 => DeltaChange::Raw(read_bytes(&mut cursor)?.to_vec()),
+                2 => DeltaChange::Diff {
+                    source_crc: u32::from_le_bytes(take::<4>(&mut cursor)?),
+                    target_crc: u32::from_le_bytes(take::<4>(&mut cursor)?),
+                    delta: read_bytes(&mut cursor)?.to_vec(),
+                },
+                other => {
+                    return Err(Error::invalid_format(format!(
+                        "unknown delta entry kind {}",
+                        other
+                    )))
+                }
+            };
+            entries.push(DeltaEntry {
+                name,
+                encrypted: flags & 1 != 0,
+                fix_key: flags & 2 != 0,
+                compression,
+                sector_crc: flags & 4 != 0,
+                change,
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Write the patch to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Read a patch from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Compute a delta that turns `source` into `target`
+///
+/// The source is indexed in blocks of 16 bytes; the target is scanned for
+/// those blocks and every hit is extended as far as both sides agree.
+/// Anything not covered by a match is inserted literally. Apply the result
+/// with [`patch`].
+pub fn diff(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    for (block, chunk) in source.chunks_exact(BLOCK_SIZE).enumerate() {
+        index.entry(chunk).or_insert(block * BLOCK_SIZE);
+    }
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len() as u64);
+
+    let mut pos = 0;
+    let mut literal_start = 0;
+    while pos + BLOCK_SIZE <= target.len() {
+        let Some(&found) = index.get(&target[pos..pos + BLOCK_SIZE]) else {
+            pos += 1;
+            continue;
+        };
+
+        // Grow the match backwards into pending literals, then forwards
+        let (mut start, mut source_start) = (pos, found);
+        while start > literal_start
+            && source_start > 0
+            && target[start - 1] == source[source_start - 1]
+        {
+            start -= 1;
+            source_start -= 1;
+        }
+        let mut end = pos + BLOCK_SIZE;
+        let mut source_end = found + BLOCK_SIZE;
+        while end < target.len() && source_end < source.len() && target[end] == source[source_end] {
+            end += 1;
+            source_end += 1;
+        }
+
+        write_insert(&mut delta, &target[literal_start..start]);
+        delta.push(OP_COPY);
+        write_varint(&mut delta, source_start as u64);
+        write_varint(&mut delta, (end - start) as u64);
+
+        pos = end;
+        literal_start = end;
+    }
+    write_insert(&mut delta, &target[literal_start..]);
+
+    delta
+}
+
+/// Apply a delta from [`diff`] to `source`
+///
+/// # Errors
+/// - `Error::InvalidFormat` if the delta is malformed or refers to data
+///   outside `source`
+/// - `Error::InvalidFileSize` if the delta does not produce the length it
+///   declares
+pub fn patch(source: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut cursor = delta;
+    let target_len = read_varint(&mut cursor)? as usize;
+    let mut target = Vec::with_capacity(target_len.min(source.len() + delta.len()));
+
+    while let Some((&op, rest)) = cursor.split_first() {
+        cursor = rest;
+        match op {
+            OP_COPY => {
+                let offset = read_varint(&mut cursor)? as usize;
+                let len = read_varint(&mut cursor)? as usize;
+                let range = offset
+                    .checked_add(len)
+                    .and_then(|end| source.get(offset..end))
+                    .ok_or_else(|| Error::invalid_format("delta copies beyond the source"))?;
+                extend_target(&mut target, target_len, range)?;
+            }
+            OP_INSERT => extend_target(&mut target, target_len, read_bytes(&mut cursor)?)?,
+            other => {
+                return Err(Error::invalid_format(format!(
+                    "unknown delta instruction {}",
+                    other
+                )))
+            }
+        }
+    }
+
+    if target.len() != target_len {
+        return Err(Error::InvalidFileSize {
+            expected: target_len as u64,
+            actual: target.len() as u64,
+        });
+    }
+    Ok(target)
+}
+
+/// Append `data` to a patch target, failing once it would outgrow the
+/// length the delta declares
+fn extend_target(target: &mut Vec<u8>, target_len: usize, data: &[u8]) -> Result<()> {
+    let len = target.len() + data.len();
+    if len > target_len {
+        return Err(Error::InvalidFileSize {
+            expected: target_len as u64,
+            actual: len as u64,
+        });
+    }
+    target.extend_from_slice(data);
+    Ok(())
+}
+
+/// Files that are regenerated by the builder and never patched
+fn is_special(name: &str) -> bool {
+    SpecialFile::from_name(name).is_some_and(SpecialFile::is_derived)
+}
+
+fn check_crc(name: &str, expected: u32, data: &[u8]) -> Result<()> {
+    let actual = crc32fast::hash(data);
+    if actual != expected {
+        return Err(Error::ChecksumMismatch {
+            file: name.to_string(),
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn write_insert(delta: &mut Vec<u8>, literal: &[u8]) {
+    if !literal.is_empty() {
+        delta.push(OP_INSERT);
+        write_bytes(delta, literal);
+    }
+}
+
+/// Append a LEB128-encoded integer
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(cursor: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let [byte] = take::<1>(cursor)?;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::invalid_format("varint too long"))
+}
+
+/// Append a length-prefixed byte string
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn read_bytes<'a>(cursor: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = read_varint(cursor)? as usize;
+    if cursor.len() < len {
+        return Err(Error::invalid_format("truncated delta data"));
+    }
+    let (bytes, rest) = cursor.split_at(len);
+    *cursor = rest;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_patch_roundtrip() {
+        let source: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target = source.clone();
+        target[5_000..5_010].copy_from_slice(b"0123456789");
+        target.splice(100..100, b"inserted".iter().copied());
+        target.truncate(9_000);
+        target.extend_from_slice(&source[..2_000]);
+
+        let delta = diff(&source, &target);
+        assert!(delta.len() < 200, "delta is {} bytes", delta.len());
+        assert_eq!(patch(&source, &delta).unwrap(), target);
+
+        // Degenerate inputs
+        assert_eq!(patch(b"", &diff(b"", b"abc")).unwrap(), b"abc");
+        assert_eq!(patch(b"abc", &diff(b"abc", b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_patch_rejects_bad_delta() {
+        let mut delta = Vec::new();
+        write_varint(&mut delta, 4);
+        delta.push(OP_COPY);
+        write_varint(&mut delta, 2);
+        write_varint(&mut delta, 4);
+        assert!(patch(b"abc", &delta).is_err());
+
+        let delta = diff(b"abc", b"abcd");
+        assert!(patch(b"abc", &delta[..delta.len() - 1]).is_err());
+
+        // Copies past the declared length fail before the target grows
+        let mut delta = Vec::new();
+        write_varint(&mut delta, 3);
+        for _ in 0..1_000 {
+            delta.push(OP_COPY);
+            write_varint(&mut delta, 0);
+            write_varint(&mut delta, 3);
+        }
+        assert!(matches!(
+            patch(b"abc", &delta),
+            Err(Error::InvalidFileSize {
+                expected: 3,
+                actual: 6
+            })
+        ));
+    }
+
+    #[test]
+    fn test_read_rejects_bad_body_size() {
+        let container = |body_size: u64, stored: &[u8]| {
+            let mut data = MAGIC.to_vec();
+            data.extend_from_slice(&VERSION.to_le_bytes());
+            data.extend_from_slice(&body_size.to_le_bytes());
+            data.extend_from_slice(&(stored.len() as u64).to_le_bytes());
+            data.extend_from_slice(stored);
+            data
+        };
+
+        for data in [container(100, b""), container(u64::MAX, &[0x02, 0x78])] {
+            assert!(matches!(
+                DeltaPatch::read_from(data.as_slice()),
+                Err(Error::InvalidFormat(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Content type detection for archived files
+//!
+//! MPQ archives carry no type information beyond file names, and names are
+//! not always available. [`file_kind`] looks at the first bytes of a file
+//! instead, recognizing the asset formats found in Blizzard archives by
+//! their signatures and falling back to a text heuristic for scripts and
+//! configuration files.
+//!
+//! Detection only ever looks at a prefix of the data, so callers can pass
+//! the first sector of a file rather than the whole thing.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How many leading bytes the text heuristic inspects
+const TEXT_SAMPLE: usize = 4096;
+
+/// Kind of content found in a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    /// BLP texture (`BLP0`, `BLP1`, `BLP2`)
+    Blp,
+    /// DirectDraw Surface texture
+    Dds,
+    /// Truevision TGA image
+    Tga,
+    /// PNG image
+    Png,
+    /// JPEG image
+    Jpeg,
+    /// Windows bitmap
+    Bmp,
+    /// Warcraft III model (`MDLX`)
+    Mdx,
+    /// World of Warcraft model (`MD20`, or chunked `MD21`)
+    M2,
+    /// Client database (`WDBC`)
+    Dbc,
+    /// Client database, newer layouts (`WDB2` and later)
+    Db2,
+    /// RIFF WAVE audio
+    Wav,
+    /// MPEG audio
+    Mp3,
+    /// Ogg container (usually Vorbis audio)
+    Ogg,
+    /// Nested MPQ archive
+    Mpq,
+    /// Lua script
+    Lua,
+    /// JASS script (Warcraft III)
+    Jass,
+    /// XML, including WoW interface files
+    Xml,
+    /// Other plain text
+    Text,
+    /// Nothing recognized
+    Unknown,
+}
+
+impl FileKind {
+    /// Every kind, in declaration order
+    pub const ALL: [FileKind; 19] = [
+        FileKind::Blp,
+        FileKind::Dds,
+        FileKind::Tga,
+        FileKind::Png,
+        FileKind::Jpeg,
+        FileKind::Bmp,
+        FileKind::Mdx,
+        FileKind::M2,
+        FileKind::Dbc,
+        FileKind::Db2,
+        FileKind::Wav,
+        FileKind::Mp3,
+        FileKind::Ogg,
+        FileKind::Mpq,
+        FileKind::Lua,
+        FileKind::Jass,
+        FileKind::Xml,
+        FileKind::Text,
+        FileKind::Unknown,
+    ];
+
+    /// Short lowercase identifier, as accepted by [`FromStr`]
+    pub fn id(self) -> &'static str {
+        match self {
+            FileKind::Blp => "blp",
+            FileKind::Dds => "dds",
+            FileKind::Tga => "tga",
+            FileKind::Png => "png",
+            FileKind::Jpeg => "jpeg",
+            FileKind::Bmp => "bmp",
+            FileKind::Mdx => "mdx",
+            FileKind::M2 => "m2",
+            FileKind::Dbc => "dbc",
+            FileKind::Db2 => "db2",
+            FileKind::Wav => "wav",
+            FileKind::Mp3 => "mp3",
+            FileKind::Ogg => "ogg",
+            FileKind::Mpq => "mpq",
+            FileKind::Lua => "lua",
+            FileKind::Jass => "jass",
+            FileKind::Xml => "xml",
+            FileKind::Text => "text",
+            FileKind::Unknown => "unknown",
+        }
+    }
+
+    /// Human-readable description
+    pub fn description(self) -> &'static str {
+        match self {
+            FileKind::Blp => "BLP texture",
+            FileKind::Dds => "DDS texture",
+            FileKind::Tga => "TGA image",
+            FileKind::Png => "PNG image",
+            FileKind::Jpeg => "JPEG image",
+            FileKind::Bmp => "Bitmap image",
+            FileKind::Mdx => "MDX model",
+            FileKind::M2 => "M2 model",
+            FileKind::Dbc => "DBC database",
+            FileKind::Db2 => "DB2 database",
+            FileKind::Wav => "WAVE audio",
+            FileKind::Mp3 => "MP3 audio",
+            FileKind::Ogg => "Ogg audio",
+            FileKind::Mpq => "MPQ archive",
+            FileKind::Lua => "Lua script",
+            FileKind::Jass => "JASS script",
+            FileKind::Xml => "XML document",
+            FileKind::Text => "Text",
+            FileKind::Unknown => "Unknown",
+        }
+    }
+
+    /// Check whether this kind is a text format
+    pub fn is_text(self) -> bool {
+        matches!(
+            self,
+            FileKind::Lua | FileKind::Jass | FileKind::Xml | FileKind::Text
+        )
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+impl FromStr for FileKind {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        FileKind::ALL
+            .into_iter()
+            .find(|kind| kind.id() == s || (s == "jpg" && *kind == FileKind::Jpeg))
+            .ok_or_else(|| crate::Error::invalid_format(format!("unknown file kind: {}", s)))
+    }
+}
+
+/// Detect the kind of content in `data`
+///
+/// Binary formats are recognized by their signatures. Data that is not one
+/// of them but looks like UTF-8 text is classified as Lua, JASS, XML or
+/// plain text by its first few kilobytes.
+///
+/// # Examples
+///
+/// ```
+/// use mopaq::detect::{file_kind, FileKind};
+///
+/// assert_eq!(file_kind(b"BLP2\x01\x02\x00\x00"), FileKind::Blp);
+/// assert_eq!(file_kind(b"local frame = CreateFrame()\nend\n"), FileKind::Lua);
+/// assert_eq!(file_kind(&[0, 1, 2, 3]), FileKind::Unknown);
+/// ```
+pub fn file_kind(data: &[u8]) -> FileKind {
+    let magic = |signature: &[u8]| data.starts_with(signature);
+
+    if magic(b"BLP0") || magic(b"BLP1") || magic(b"BLP2") {
+        FileKind::Blp
+    } else if magic(b"DDS ") {
+        FileKind::Dds
+    } else if magic(b"\x89PNG\r\n\x1a\n") {
+        FileKind::Png
+    } else if magic(b"\xFF\xD8\xFF") {
+        FileKind::Jpeg
+    } else if magic(b"MDLX") {
+        FileKind::Mdx
+    } else if magic(b"MD20") || magic(b"MD21") {
+        FileKind::M2
+    } else if magic(b"WDBC") {
+        FileKind::Dbc
+    } else if data.len() >= 4 && magic(b"WDB") && data[3].is_ascii_digit() {
+        FileKind::Db2
+    } else if magic(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        FileKind::Wav
+    } else if magic(b"OggS") {
+        FileKind::Ogg
+    } else if magic(b"ID3") || is_mpeg_frame(data) {
+        FileKind::Mp3
+    } else if magic(b"MPQ\x1A") || magic(b"MPQ\x1B") {
+        FileKind::Mpq
+    } else if magic(b"BM") && data.len() >= 14 && data[6..10] == [0; 4] {
+        FileKind::Bmp
+    } else if is_tga(data) {
+        FileKind::Tga
+    } else if let Some(text) = as_text(data) {
+        classify_text(text)
+    } else {
+        FileKind::Unknown
+    }
+}
+
+/// MPEG audio frame sync with a valid layer, bitrate and sample rate
+fn is_mpeg_frame(data: &[u8]) -> bool {
+    let [0xFF, b1, b2, ..] = *data else {
+        return false;
+    };
+    let sync = b1 & 0xE0 == 0xE0;
+    let layer = (b1 >> 1) & 0x03;
+    let bitrate = b2 >> 4;
+    let sample_rate = (b2 >> 2) & 0x03;
+    sync && layer != 0 && bitrate != 0 && bitrate != 0x0F && sample_rate != 0x03
+}
+
+/// TGA has no signature, so check the header fields for plausible values
+fn is_tga(data: &[u8]) -> bool {
+    if data.len() < 18 {
+        return false;
+    }
+    let color_map_type = data[1];
+    let image_type = data[2];
+    let width = u16::from_le_bytes([data[12], data[13]]);
+    let height = u16::from_le_bytes([data[14], data[15]]);
+    let bits_per_pixel = data[16];
+
+    matches!(image_type, 1 | 2 | 3 | 9 | 10 | 11)
+        && color_map_type <= 1
+        && (color_map_type == 1) == matches!(image_type, 1 | 9)
+        && width > 0
+        && height > 0
+        && matches!(bits_per_pixel, 8 | 15 | 16 | 24 | 32)
+}
+
+/// Leading text of `data`, if it looks like text
+fn as_text(data: &[u8]) -> Option<&str> {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    if data.is_empty() {
+        return None;
+    }
+    let sample = &data[..data.len().min(TEXT_SAMPLE)];
+    let text = match std::str::from_utf8(sample) {
+        Ok(text) => text,
+        // A multi-byte character cut off at the end of the sample is fine
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&sample[..e.valid_up_to()]).ok()?
+        }
+        Err(_) => return None,
+    };
+
+    let control = text
+        .chars()
+        .filter(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0C'))
+        .count();
+    (control == 0).then_some(text)
+}
+
+/// Tell scripts and markup apart from other text
+fn classify_text(text: &str) -> FileKind {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with("<?xml") || trimmed.starts_with("<Ui") {
+        return FileKind::Xml;
+    }
+    if text.contains("endfunction") || (text.contains(" takes ") && text.contains(" returns ")) {
+        return FileKind::Jass;
+    }
+
+    let mut lines = text.lines().map(str::trim);
+    let has_lua_keyword = lines
+        .clone()
+        .any(|line| line.starts_with("local ") || line.starts_with("function "));
+    let has_lua_end =
+        lines.any(|line| line == "end" || line.starts_with("end ") || line.ends_with(" then"));
+    if has_lua_keyword && has_lua_end {
+        FileKind::Lua
+    } else {
+        FileKind::Text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_signatures() {
+        assert_eq!(file_kind(b"MDLXVERS"), FileKind::Mdx);
+        assert_eq!(file_kind(b"MD20\x08\x01\x00\x00"), FileKind::M2);
+        assert_eq!(file_kind(b"WDBC\x10\x00\x00\x00"), FileKind::Dbc);
+        assert_eq!(file_kind(b"WDB5\x10\x00\x00\x00"), FileKind::Db2);
+        assert_eq!(file_kind(b"RIFF\x24\x08\x00\x00WAVEfmt "), FileKind::Wav);
+        assert_eq!(file_kind(b"ID3\x03\x00"), FileKind::Mp3);
+        assert_eq!(file_kind(&[0xFF, 0xFB, 0x90, 0x64]), FileKind::Mp3);
+        assert_eq!(file_kind(b"MPQ\x1A\x20\x00\x00\x00"), FileKind::Mpq);
+
+        // Uncompressed 2x2 true-color TGA header
+        let mut tga = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 32, 8];
+        tga.extend_from_slice(&[0; 16]);
+        assert_eq!(file_kind(&tga), FileKind::Tga);
+        tga[16] = 7;
+        assert_eq!(file_kind(&tga), FileKind::Unknown);
+    }
+
+    #[test]
+    fn test_text_classification() {
+        assert_eq!(
+            file_kind(b"<?xml version=\"1.0\"?>\n<Ui></Ui>"),
+            FileKind::Xml
+        );
+        assert_eq!(
+            file_kind(b"function main takes nothing returns nothing\nendfunction\n"),
+            FileKind::Jass
+        );
+        assert_eq!(
+            file_kind(b"-- addon\nlocal x = 1\nif x then\n  print(x)\nend\n"),
+            FileKind::Lua
+        );
+        assert_eq!(file_kind("Ürün listesi\r\n".as_bytes()), FileKind::Text);
+        assert_eq!(file_kind(b""), FileKind::Unknown);
+    }
+
+    #[test]
+    fn test_kind_ids_round_trip() {
+        for kind in FileKind::ALL {
+            assert_eq!(kind.id().parse::<FileKind>().unwrap(), kind);
+        }
+        assert_eq!("JPG".parse::<FileKind>().unwrap(), FileKind::Jpeg);
+        assert!("exe".parse::<FileKind>().is_err());
+    }
+}
@@ -3,6 +3,8 @@
 use std::io;
 use thiserror::Error;
 
+use crate::compatibility::Compatibility;
+
 /// Result type alias for MPQ operations
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -100,6 +102,37 @@ pub enum Error {
         /// Table name
         table: String,
     },
+
+    /// Decompressed data grew past the allowed expansion of its expected size
+    #[error("Decompressed data exceeds the limit of {limit} bytes")]
+    ExpansionLimit {
+        /// Maximum number of bytes the data was allowed to decompress to
+        limit: u64,
+    },
+
+    /// A sector of a file is damaged beyond reading
+    #[error("Corrupt sector {sector} of {file}: {reason}")]
+    CorruptSector {
+        /// File name
+        file: String,
+        /// Index of the sector within the file
+        sector: usize,
+        /// What is wrong with it
+        reason: String,
+    },
+
+    /// A glob or regular expression for names could not be compiled
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    /// The archive being built would not be readable by the targeted game
+    #[error("Not readable by {target}: {reason}")]
+    Incompatible {
+        /// The game the archive is built for
+        target: String,
+        /// The setting the game does not support
+        reason: String,
+    },
 }
 
 impl Error {
@@ -128,6 +161,27 @@ impl Error {
         Error::BlockTable(msg.into())
     }
 
+    /// Create a new CorruptSector error
+    pub fn corrupt_sector<F: Into<String>, S: Into<String>>(
+        file: F,
+        sector: usize,
+        reason: S,
+    ) -> Self {
+        Error::CorruptSector {
+            file: file.into(),
+            sector,
+            reason: reason.into(),
+        }
+    }
+
+    /// Create a new Incompatible error
+    pub fn incompatible<S: Into<String>>(target: Compatibility, reason: S) -> Self {
+        Error::Incompatible {
+            target: target.to_string(),
+            reason: reason.into(),
+        }
+    }
+
     /// Check if this error indicates the archive is corrupted
     pub fn is_corruption(&self) -> bool {
         matches!(
@@ -135,6 +189,8 @@ impl Error {
             Error::InvalidFormat(_)
                 | Error::ChecksumMismatch { .. }
                 | Error::MD5Mismatch { .. }
+                | Error::CorruptSector { .. }
+                | Error::ExpansionLimit { .. }
                 | Error::SignatureVerification(_)
                 | Error::InvalidHeader(_)
         )
@@ -172,6 +228,13 @@ mod tests {
         assert!(corruption_err.is_corruption());
         assert!(!corruption_err.is_recoverable());
 
+        let sector_err = Error::corrupt_sector("war3map.j", 3, "offset past end of file data");
+        assert_eq!(
+            sector_err.to_string(),
+            "Corrupt sector 3 of war3map.j: offset past end of file data"
+        );
+        assert!(sector_err.is_corruption());
+
         let recoverable_err = Error::FileNotFound("missing.txt".to_string());
         assert!(!recoverable_err.is_corruption());
         assert!(recoverable_err.is_recoverable());
@@ -0,0 +1,409 @@
+//! Extracting whole archives to disk
+//!
+//! [`Archive::extract_all`](crate::Archive::extract_all) writes every named
+//! file in an archive below a directory. Archive names use `\` as separator
+//! and are turned into relative paths; names that would leave the output
+//! directory, such as `..\boot.ini` or `C:\autoexec.bat`, are refused rather
+//! than written, and so are Windows device names such as `con.txt` when
+//! extracting on Windows. Two files whose paths differ only in case, or a
+//! file that is already on disk, are handled by a [`CollisionPolicy`].
+//! Repeating an extraction into the same directory can leave files that are
+//! already there untouched, with [`CollisionPolicy::IfDifferent`] or
+//! [`ExtractOptions::resume`].
+
+use crate::{Error, Result};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// Options for [`Archive::extract_all_with`](crate::Archive::extract_all_with)
+///
+/// # Examples
+///
+/// ```no_run
+/// use mopaq::{Archive, ExtractOptions};
+///
+/// let mut archive = Archive::open("data.mpq")?;
+/// let summary = archive.extract_all_with("out", &ExtractOptions::new().prefetch(false))?;
+/// println!("{} files, {} bytes", summary.extracted, summary.bytes_written);
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    pub(crate) prefetch: bool,
+    pub(crate) order: ExtractionOrder,
+    pub(crate) collision_policy: CollisionPolicy,
+    pub(crate) resume: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            prefetch: true,
+            order: ExtractionOrder::default(),
+            collision_policy: CollisionPolicy::default(),
+            resume: false,
+        }
+    }
+}
+
+/// Order in which [`Archive::extract_all_with`](crate::Archive::extract_all_with) visits files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExtractionOrder {
+    /// The order of [`Archive::list`](crate::Archive::list), usually the listfile's
+    #[default]
+    Listing,
+    /// By position of the file's data in the archive
+    ///
+    /// Reading files front to back turns the scattered seeks of listfile
+    /// order into mostly sequential reads, which matters on spinning disks
+    /// and network storage.
+    ArchiveLayout,
+}
+
+/// What happens when a file's output path is already taken
+///
+/// A path is taken when a file exists there, or when an earlier file of the
+/// same extraction went to a path that differs only in case. Archive names
+/// are case-insensitive, and so are the file systems of Windows and macOS by
+/// default, so `Units\Footman.mdx` and `units\footman.mdx` would otherwise
+/// end up in one file on some systems and in two on others.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Write to the path regardless, replacing any file there
+    #[default]
+    Overwrite,
+    /// Write to the first free `name (1).ext`, `name (2).ext`, ... instead
+    Rename,
+    /// Leave whatever is there and do not write the file
+    Skip,
+    /// Replace a file on disk only if its contents differ
+    ///
+    /// A file of another size is replaced right away. One of the same size
+    /// is compared against the CRC32 or MD5 from the archive's
+    /// `(attributes)`, without decompressing the archived file, or byte by
+    /// byte when the archive has no checksums for it. Identical files are
+    /// left alone, keeping their modification times.
+    IfDifferent,
+}
+
+impl ExtractOptions {
+    /// Options that extract every file with prefetching enabled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the next file's data ahead while the current one is extracted
+    ///
+    /// A background thread reads the stored bytes of the upcoming file so
+    /// that they are in the OS page cache by the time they are needed,
+    /// overlapping disk reads with decompression and writing. This mostly
+    /// helps cold-cache extraction of large archives. Enabled by default.
+    pub fn prefetch(mut self, prefetch: bool) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    /// Set the order files are extracted in
+    pub fn order(mut self, order: ExtractionOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Set what happens when a file's output path is already taken
+    ///
+    /// Defaults to [`CollisionPolicy::Overwrite`].
+    pub fn collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collision_policy = policy;
+        self
+    }
+
+    /// Continue an interrupted extraction into the same directory
+    ///
+    /// A file already on disk with the size of the archived file is taken
+    /// as extracted by an earlier run and left alone, whatever the
+    /// [`CollisionPolicy`]. Only sizes are compared, so resuming a large
+    /// extraction reads nothing but the files still missing. Disabled by
+    /// default.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+}
+
+/// Outcome of extracting an archive
+#[derive(Debug, Default)]
+pub struct ExtractSummary {
+    /// Number of files written
+    pub extracted: usize,
+    /// Total uncompressed bytes written
+    pub bytes_written: u64,
+    /// Files that could not be extracted, in the order they were attempted
+    pub failed: Vec<ExtractFailure>,
+    /// Files not written because their path was taken, with
+    /// [`CollisionPolicy::Skip`]
+    pub skipped: Vec<String>,
+    /// Files written under another name because their path was taken, with
+    /// [`CollisionPolicy::Rename`], and the path they were written to
+    pub renamed: Vec<(String, PathBuf)>,
+    /// Files not written because the file on disk already matched, with
+    /// [`CollisionPolicy::IfDifferent`] or [`ExtractOptions::resume`]
+    pub unchanged: Vec<String>,
+    /// Bytes skipped over or back between consecutive files, in the order
+    /// they were extracted
+    pub seek_distance: u64,
+    /// What [`seek_distance`](Self::seek_distance) would have been in
+    /// [`ExtractionOrder::Listing`]
+    pub listing_seek_distance: u64,
+}
+
+impl ExtractSummary {
+    /// Seek distance avoided by the chosen order compared to listing order
+    pub fn seek_distance_saved(&self) -> u64 {
+        self.listing_seek_distance
+            .saturating_sub(self.seek_distance)
+    }
+}
+
+/// A file that [`Archive::extract_all`](crate::Archive::extract_all) could not extract
+#[derive(Debug)]
+pub struct ExtractFailure {
+    /// Name of the file in the archive
+    pub name: String,
+    /// What went wrong
+    pub error: Error,
+}
+
+/// Total distance between the end of each range and the start of the next
+///
+/// `ranges` are `(position, length)` pairs in the order they are read.
+pub(crate) fn seek_distance(ranges: impl IntoIterator<Item = (u64, u64)>) -> u64 {
+    let mut total = 0u64;
+    let mut end = None;
+    for (position, length) in ranges {
+        if let Some(end) = end {
+            total = total.saturating_add(position.abs_diff(end));
+        }
+        end = Some(position.saturating_add(length));
+    }
+    total
+}
+
+/// Path below `dir` that the archived file `name` is extracted to
+///
+/// Returns `None` for names that are empty or would resolve outside `dir`,
+/// and on Windows for names with a reserved device name such as `aux.txt`
+/// as one of their parts.
+pub fn output_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(name.replace('\\', "/"));
+    let mut path = dir.to_path_buf();
+    let mut depth = 0;
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => {
+                if cfg!(windows) && crate::io::is_reserved_name(&part.to_string_lossy()) {
+                    return None;
+                }
+                path.push(part);
+                depth += 1;
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    // A drive prefix such as `C:` parses as a normal component on Unix
+    if depth == 0 || name.contains(':') {
+        return None;
+    }
+    Some(path)
+}
+
+/// Output paths for the files of one extraction
+///
+/// Turns archive names into paths below a directory like [`output_path`]
+/// and resolves collisions with a [`CollisionPolicy`]. The paths handed out
+/// are remembered, so names differing only in case collide on
+/// case-sensitive file systems as well.
+///
+/// # Examples
+///
+/// ```
+/// use mopaq::extract::{CollisionPolicy, OutputDir, Placement};
+/// use std::path::Path;
+///
+/// let mut out = OutputDir::new("out", CollisionPolicy::Rename);
+/// let first = out.place("Units\\Footman.mdx")?;
+/// assert_eq!(first, Placement::Path(Path::new("out/Units/Footman.mdx").into()));
+/// let second = out.place("units\\footman.mdx")?;
+/// assert_eq!(second, Placement::Renamed(Path::new("out/units/footman (1).mdx").into()));
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct OutputDir {
+    dir: PathBuf,
+    policy: CollisionPolicy,
+    used: HashSet<String>,
+}
+
+/// Where [`OutputDir::place`] puts a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placement {
+    /// Write the file to this path
+    Path(PathBuf),
+    /// Write the file to this path, because the path of its name was taken
+    Renamed(PathBuf),
+    /// Do not write the file, because the path of its name was taken
+    Skipped,
+}
+
+impl OutputDir {
+    /// Place files below `dir`, resolving collisions with `policy`
+    pub fn new(dir: impl Into<PathBuf>, policy: CollisionPolicy) -> Self {
+        Self {
+            dir: dir.into(),
+            policy,
+            used: HashSet::new(),
+        }
+    }
+
+    /// The directory files are placed below
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Decide where the archived file `name` is written
+    ///
+    /// # Errors
+    /// `Error::InvalidFormat` if [`output_path`] refuses `name`
+    pub fn place(&mut self, name: &str) -> Result<Placement> {
+        let path = output_path(&self.dir, name).ok_or_else(|| {
+            Error::invalid_format(format!("Unsafe name for an output file: {name}"))
+        })?;
+
+        let replaces = matches!(
+            self.policy,
+            CollisionPolicy::Overwrite | CollisionPolicy::IfDifferent
+        );
+        if replaces || !self.is_taken(&path) {
+            self.used.insert(fold_case(&path));
+            return Ok(Placement::Path(path));
+        }
+        // A file already on disk also takes the paths differing only in case
+        self.used.insert(fold_case(&path));
+        if self.policy == CollisionPolicy::Skip {
+            return Ok(Placement::Skipped);
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = path
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        let renamed = (1u64..)
+            .map(|n| path.with_file_name(format!("{stem} ({n}){extension}")))
+            .find(|candidate| !self.is_taken(candidate))
+            .expect("some numbered name is free");
+        self.used.insert(fold_case(&renamed));
+        Ok(Placement::Renamed(renamed))
+    }
+
+    /// Path of a file already on disk for the archived file `name`
+    ///
+    /// Returns `None` if `name` is refused, nothing is at its path, or an
+    /// earlier file of this extraction was placed at a path differing only
+    /// in case. Pass the path to [`keep`](Self::keep) if the file is kept.
+    pub fn existing(&self, name: &str) -> Option<PathBuf> {
+        let path = output_path(&self.dir, name)?;
+        let on_disk = path.metadata().is_ok_and(|metadata| metadata.is_file());
+        (on_disk && !self.used.contains(&fold_case(&path))).then_some(path)
+    }
+
+    /// Keep the file at `path`, so later files collide with it
+    pub fn keep(&mut self, path: &Path) {
+        self.used.insert(fold_case(path));
+    }
+
+    fn is_taken(&self, path: &Path) -> bool {
+        self.used.contains(&fold_case(path)) || path.symlink_metadata().is_ok()
+    }
+}
+
+/// Key under which paths differing only in case are equal
+fn fold_case(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_path() {
+        let dir = Path::new("out");
+        assert_eq!(
+            output_path(dir, "units\\human\\footman.mdx"),
+            Some(dir.join("units").join("human").join("footman.mdx"))
+        );
+        assert_eq!(
+            output_path(dir, ".\\war3map.j"),
+            Some(dir.join("war3map.j"))
+        );
+
+        for name in ["", "..\\boot.ini", "a\\..\\..\\b", "/etc/passwd", "C:\\x"] {
+            assert_eq!(output_path(dir, name), None, "{name}");
+        }
+
+        // Device names are only refused where they are devices
+        assert_eq!(output_path(dir, "sound\\aux.wav").is_none(), cfg!(windows));
+    }
+
+    #[test]
+    fn test_collision_policies() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        std::fs::write(dir.join("existing.txt"), b"").unwrap();
+
+        let mut overwrite = OutputDir::new(dir, CollisionPolicy::Overwrite);
+        assert_eq!(
+            overwrite.place("existing.txt").unwrap(),
+            Placement::Path(dir.join("existing.txt"))
+        );
+
+        let mut skip = OutputDir::new(dir, CollisionPolicy::Skip);
+        assert_eq!(skip.place("existing.txt").unwrap(), Placement::Skipped);
+        assert_eq!(
+            skip.place("Maps\\A.w3x").unwrap(),
+            Placement::Path(dir.join("Maps").join("A.w3x"))
+        );
+        assert_eq!(skip.place("maps\\a.w3x").unwrap(), Placement::Skipped);
+
+        let mut rename = OutputDir::new(dir, CollisionPolicy::Rename);
+        assert_eq!(
+            rename.place("existing.txt").unwrap(),
+            Placement::Renamed(dir.join("existing (1).txt"))
+        );
+        assert_eq!(
+            rename.place("Existing.txt").unwrap(),
+            Placement::Renamed(dir.join("Existing (2).txt"))
+        );
+        assert_eq!(
+            rename.place("README").unwrap(),
+            Placement::Path(dir.join("README"))
+        );
+        assert_eq!(
+            rename.place("readme").unwrap(),
+            Placement::Renamed(dir.join("readme (1)"))
+        );
+
+        assert!(rename.place("..\\escape.txt").is_err());
+    }
+
+    #[test]
+    fn test_seek_distance() {
+        assert_eq!(seek_distance([]), 0);
+        assert_eq!(seek_distance([(100, 50)]), 0);
+        // Back to back, then a 50 byte gap
+        assert_eq!(seek_distance([(0, 100), (100, 20), (170, 10)]), 50);
+        // Jumping back counts as much as jumping forward
+        assert_eq!(seek_distance([(200, 100), (0, 100)]), 300);
+    }
+}
@@ -5,23 +5,11 @@ use std::io::{Read, Seek, SeekFrom};
 
 /// Helper trait for reading little-endian integers
 trait ReadLittleEndian: Read {
-    fn read_u16_le(&mut self) -> Result<u16> {
-        let mut buf = [0u8; 2];
-        self.read_exact(&mut buf)?;
-        Ok(u16::from_le_bytes(buf))
-    }
-
     fn read_u32_le(&mut self) -> Result<u32> {
         let mut buf = [0u8; 4];
         self.read_exact(&mut buf)?;
         Ok(u32::from_le_bytes(buf))
     }
-
-    fn read_u64_le(&mut self) -> Result<u64> {
-        let mut buf = [0u8; 8];
-        self.read_exact(&mut buf)?;
-        Ok(u64::from_le_bytes(buf))
-    }
 }
 
 impl<R: Read> ReadLittleEndian for R {}
@@ -38,6 +26,7 @@ pub const HEADER_ALIGNMENT: u64 = 0x200;
 /// MPQ format version
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FormatVersion {
     /// Version 1 - Original format (32-byte header)
     V1 = 0,
@@ -83,6 +72,51 @@ pub struct UserDataHeader {
     pub user_data_header_size: u32,
 }
 
+impl UserDataHeader {
+    /// Size of the user data header structure itself (signature and three fields)
+    pub const SIZE: u32 = 0x10;
+
+    /// Check the declared sizes against each other and the file size
+    ///
+    /// `offset` is the position of the user data header in the file and
+    /// `file_size` the total file size. The user data must fit into the file,
+    /// the user data header must fit into the user data, and the MPQ header
+    /// must not start inside the user data header.
+    pub fn validate(&self, offset: u64, file_size: u64) -> Result<()> {
+        if self.user_data_header_size > self.user_data_size {
+            return Err(Error::InvalidHeader(format!(
+                "User data header size 0x{:X} exceeds user data size 0x{:X}",
+                self.user_data_header_size, self.user_data_size
+            )));
+        }
+
+        if self.header_offset < Self::SIZE {
+            return Err(Error::InvalidHeader(format!(
+                "MPQ header offset 0x{:X} overlaps the user data header",
+                self.header_offset
+            )));
+        }
+
+        let data_end = offset + Self::SIZE as u64 + self.user_data_size as u64;
+        if data_end > file_size {
+            return Err(Error::InvalidHeader(format!(
+                "User data ends at 0x{:X}, beyond the end of the file (0x{:X})",
+                data_end, file_size
+            )));
+        }
+
+        if Self::SIZE as u64 + self.user_data_size as u64 > self.header_offset as u64 {
+            log::warn!(
+                "User data (0x{:X} bytes) overlaps the MPQ header at offset 0x{:X}",
+                self.user_data_size,
+                self.header_offset
+            );
+        }
+
+        Ok(())
+    }
+}
+
 /// Main MPQ header structure
 #[derive(Debug, Clone)]
 pub struct MpqHeader {
@@ -125,7 +159,7 @@ pub struct MpqHeader {
 }
 
 /// Version 4 specific header data
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct MpqHeaderV4Data {
     /// Compressed size of hash table
     pub hash_table_size_64: u64,
@@ -155,95 +189,10 @@ pub struct MpqHeaderV4Data {
 
 impl MpqHeader {
     /// Read an MPQ header from the given reader
+    ///
+    /// See [`mpq_header::read_header`](crate::mpq_header::read_header).
     pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
-        // Read the signature
-        let signature = reader.read_u32_le()?;
-        if signature != MPQ_HEADER_SIGNATURE {
-            return Err(Error::invalid_format("Invalid MPQ header signature"));
-        }
-
-        // Read basic header fields
-        let header_size = reader.read_u32_le()?;
-        let archive_size = reader.read_u32_le()?;
-        let format_version_raw = reader.read_u16_le()?;
-        let block_size = reader.read_u16_le()?;
-        let hash_table_pos = reader.read_u32_le()?;
-        let block_table_pos = reader.read_u32_le()?;
-        let hash_table_size = reader.read_u32_le()?;
-        let block_table_size = reader.read_u32_le()?;
-
-        let format_version = FormatVersion::from_raw(format_version_raw)
-            .ok_or(Error::UnsupportedVersion(format_version_raw))?;
-
-        // Validate header size
-        if header_size < format_version.header_size() {
-            return Err(Error::invalid_format(format!(
-                "Header size {} too small for version {:?}",
-                header_size, format_version
-            )));
-        }
-
-        let mut header = MpqHeader {
-            header_size,
-            archive_size,
-            format_version,
-            block_size,
-            hash_table_pos,
-            block_table_pos,
-            hash_table_size,
-            block_table_size,
-            hi_block_table_pos: None,
-            hash_table_pos_hi: None,
-            block_table_pos_hi: None,
-            archive_size_64: None,
-            bet_table_pos: None,
-            het_table_pos: None,
-            v4_data: None,
-        };
-
-        // Read version-specific fields
-        if format_version >= FormatVersion::V2 {
-            // Version 2+ fields
-            header.hi_block_table_pos = Some(reader.read_u64_le()?);
-            header.hash_table_pos_hi = Some(reader.read_u16_le()?);
-            header.block_table_pos_hi = Some(reader.read_u16_le()?);
-        }
-
-        if format_version >= FormatVersion::V3 {
-            // Version 3+ fields
-            header.archive_size_64 = Some(reader.read_u64_le()?);
-            header.bet_table_pos = Some(reader.read_u64_le()?);
-            header.het_table_pos = Some(reader.read_u64_le()?);
-        }
-
-        if format_version >= FormatVersion::V4 {
-            // Version 4 fields
-            let mut v4_data = MpqHeaderV4Data {
-                hash_table_size_64: reader.read_u64_le()?,
-                block_table_size_64: reader.read_u64_le()?,
-                hi_block_table_size_64: reader.read_u64_le()?,
-                het_table_size_64: reader.read_u64_le()?,
-                bet_table_size_64: reader.read_u64_le()?,
-                raw_chunk_size: reader.read_u32_le()?,
-                md5_block_table: [0; 16],
-                md5_hash_table: [0; 16],
-                md5_hi_block_table: [0; 16],
-                md5_bet_table: [0; 16],
-                md5_het_table: [0; 16],
-                md5_mpq_header: [0; 16],
-            };
-
-            reader.read_exact(&mut v4_data.md5_block_table)?;
-            reader.read_exact(&mut v4_data.md5_hash_table)?;
-            reader.read_exact(&mut v4_data.md5_hi_block_table)?;
-            reader.read_exact(&mut v4_data.md5_bet_table)?;
-            reader.read_exact(&mut v4_data.md5_het_table)?;
-            reader.read_exact(&mut v4_data.md5_mpq_header)?;
-
-            header.v4_data = Some(v4_data);
-        }
-
-        Ok(header)
+        crate::mpq_header::read_header(reader)
     }
 
     /// Get the actual archive size (using 64-bit value if available)
@@ -269,9 +218,110 @@ impl MpqHeader {
         }
     }
 
+    /// Size in bytes of the HET table, `None` if there is none
+    ///
+    /// Taken from the v4 header fields. V3 headers don't record it, so it is
+    /// bounded by the next table or the archive end instead, which needs
+    /// nothing but the header.
+    pub fn het_table_size(&self) -> Option<u64> {
+        let pos = self.het_table_pos.filter(|&pos| pos != 0)?;
+        Some(match &self.v4_data {
+            Some(v4_data) => v4_data.het_table_size_64,
+            None => self.next_table_pos(pos) - pos,
+        })
+    }
+
+    /// Size in bytes of the BET table, `None` if there is none
+    ///
+    /// Determined like [`het_table_size`](Self::het_table_size).
+    pub fn bet_table_size(&self) -> Option<u64> {
+        let pos = self.bet_table_pos.filter(|&pos| pos != 0)?;
+        Some(match &self.v4_data {
+            Some(v4_data) => v4_data.bet_table_size_64,
+            None => self.next_table_pos(pos) - pos,
+        })
+    }
+
+    /// Position of the first table after `pos`, or the archive end
+    ///
+    /// Tables are usually written back to back, so this bounds a table whose
+    /// size the header does not record. Classic tables only count if the
+    /// archive has them.
+    fn next_table_pos(&self, pos: u64) -> u64 {
+        self.later_table_pos(pos)
+            .unwrap_or_else(|| self.get_archive_size().max(pos))
+    }
+
+    /// Position of the first table after `pos`, if any
+    fn later_table_pos(&self, pos: u64) -> Option<u64> {
+        let hash_table_pos = (self.hash_table_size > 0).then(|| self.get_hash_table_pos());
+        let block_table_pos = (self.block_table_size > 0).then(|| self.get_block_table_pos());
+        [
+            self.het_table_pos,
+            self.bet_table_pos,
+            hash_table_pos,
+            block_table_pos,
+            self.hi_block_table_pos,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|&table_pos| table_pos > pos)
+        .min()
+    }
+
+    /// End of the header and of the furthest table it locates
+    ///
+    /// Unlike [`get_archive_size`](Self::get_archive_size), this does not
+    /// trust the declared size. Classic tables are sized from their entry
+    /// counts unless a v4 header records their sizes. A HET or BET table of
+    /// unrecorded size reaches to the next table, or ends where it starts
+    /// if it is the last one.
+    pub fn tables_end(&self) -> u64 {
+        let v4 = self.v4_data.as_ref();
+        let entries = |count: u32, entry_size: u64| count as u64 * entry_size;
+        let mut tables = Vec::new();
+        if self.hash_table_size > 0 {
+            let size = v4.map_or(entries(self.hash_table_size, 16), |v4| {
+                v4.hash_table_size_64
+            });
+            tables.push((self.get_hash_table_pos(), size));
+        }
+        if self.block_table_size > 0 {
+            let size = v4.map_or(entries(self.block_table_size, 16), |v4| {
+                v4.block_table_size_64
+            });
+            tables.push((self.get_block_table_pos(), size));
+            if let Some(pos) = self.hi_block_table_pos.filter(|&pos| pos != 0) {
+                let size = v4.map_or(entries(self.block_table_size, 2), |v4| {
+                    v4.hi_block_table_size_64
+                });
+                tables.push((pos, size));
+            }
+        }
+        for (pos, recorded) in [
+            (self.het_table_pos, v4.map(|v4| v4.het_table_size_64)),
+            (self.bet_table_pos, v4.map(|v4| v4.bet_table_size_64)),
+        ] {
+            let Some(pos) = pos.filter(|&pos| pos != 0) else {
+                continue;
+            };
+            let size =
+                recorded.unwrap_or_else(|| self.later_table_pos(pos).map_or(0, |next| next - pos));
+            tables.push((pos, size));
+        }
+        tables
+            .into_iter()
+            .map(|(pos, size)| pos.saturating_add(size))
+            .fold(self.format_version.header_size() as u64, u64::max)
+    }
+
     /// Calculate the sector size from block size
+    ///
+    /// Headers read from archives are checked against
+    /// [`MAX_BLOCK_SIZE`](crate::MAX_BLOCK_SIZE); this panics for a larger
+    /// block size set by hand.
     pub fn sector_size(&self) -> usize {
-        512 << self.block_size
+        crate::calculate_sector_size(self.block_size)
     }
 }
 
@@ -308,18 +358,18 @@ pub fn find_header<R: Read + Seek>(
             }
             MPQ_USERDATA_SIGNATURE => {
                 // Found user data header
-                let user_data_size = reader.read_u32_le()?;
-                let header_offset = reader.read_u32_le()?;
-                let user_data_header_size = reader.read_u32_le()?;
-
-                let user_data = UserDataHeader {
-                    user_data_size,
-                    header_offset,
-                    user_data_header_size,
-                };
+                reader.seek(SeekFrom::Start(offset))?;
+                let user_data = crate::mpq_header::read_user_data_header(reader)?;
+                // Like StormLib, keep scanning past user data that does not
+                // add up, since the archive may still follow it
+                if let Err(e) = user_data.validate(offset, file_size) {
+                    log::warn!("Ignoring user data header at 0x{:X}: {}", offset, e);
+                    offset += HEADER_ALIGNMENT;
+                    continue;
+                }
 
                 // Calculate actual header position
-                let mpq_offset = offset + header_offset as u64;
+                let mpq_offset = offset + user_data.header_offset as u64;
                 if mpq_offset < file_size {
                     reader.seek(SeekFrom::Start(mpq_offset))?;
 
@@ -339,3 +389,82 @@ pub fn find_header<R: Read + Seek>(
         offset += HEADER_ALIGNMENT;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v3_header() -> MpqHeader {
+        MpqHeader {
+            header_size: 0x44,
+            archive_size: 0x5000,
+            format_version: FormatVersion::V3,
+            block_size: 3,
+            hash_table_pos: 0x4000,
+            block_table_pos: 0x4800,
+            hash_table_size: 0x80,
+            block_table_size: 0x40,
+            hi_block_table_pos: Some(0),
+            hash_table_pos_hi: Some(0),
+            block_table_pos_hi: Some(0),
+            archive_size_64: Some(0x5000),
+            het_table_pos: Some(0x3000),
+            bet_table_pos: Some(0x3400),
+            v4_data: None,
+        }
+    }
+
+    #[test]
+    fn test_het_bet_table_sizes() {
+        // V3 sizes are bounded by the next table
+        let mut header = v3_header();
+        assert_eq!(header.het_table_size(), Some(0x400));
+        assert_eq!(header.bet_table_size(), Some(0xC00));
+
+        // Without classic tables, the last table runs to the archive end
+        header.hash_table_size = 0;
+        header.block_table_size = 0;
+        assert_eq!(header.bet_table_size(), Some(0x1C00));
+
+        // V4 headers record the sizes
+        header.format_version = FormatVersion::V4;
+        header.v4_data = Some(MpqHeaderV4Data {
+            het_table_size_64: 0x123,
+            bet_table_size_64: 0x456,
+            ..Default::default()
+        });
+        assert_eq!(header.het_table_size(), Some(0x123));
+        assert_eq!(header.bet_table_size(), Some(0x456));
+
+        header.het_table_pos = Some(0);
+        header.bet_table_pos = None;
+        assert_eq!(header.het_table_size(), None);
+        assert_eq!(header.bet_table_size(), None);
+    }
+
+    #[test]
+    fn test_tables_end() {
+        // The block table's 0x40 entries end the archive, whatever it declares
+        let mut header = v3_header();
+        header.archive_size = 0;
+        header.archive_size_64 = Some(0);
+        assert_eq!(header.tables_end(), 0x4C00);
+
+        // A last BET table of unknown size counts from its start
+        header.hash_table_size = 0;
+        header.block_table_size = 0;
+        assert_eq!(header.tables_end(), 0x3400);
+
+        header.format_version = FormatVersion::V4;
+        header.v4_data = Some(MpqHeaderV4Data {
+            het_table_size_64: 0x123,
+            bet_table_size_64: 0x456,
+            ..Default::default()
+        });
+        assert_eq!(header.tables_end(), 0x3856);
+
+        header.het_table_pos = None;
+        header.bet_table_pos = None;
+        assert_eq!(header.tables_end(), 0xD0);
+    }
+}
@@ -0,0 +1,400 @@
+//! Signed checksum manifests for checking game installations
+//!
+//! Server operators run [`build_manifest`] over a reference installation to
+//! record the SHA-1 of every file in a set of archives, signed with their
+//! RSA private key. Clients or launchers ship the matching public key and
+//! run [`verify_installation`] to find archives and files that are missing,
+//! modified or were added, e.g. by cheat tools dropping files into a patch
+//! archive.
+//!
+//! Like MPQ strong signatures, the manifest is signed with PKCS#1 v1.5 over
+//! a SHA-1 digest. Files are hashed as
+//! [`Archive::read_file_chunks`](crate::Archive::read_file_chunks) streams
+//! them, so no file is held in memory in full. Only files that
+//! [`Archive::list`](crate::Archive::list) can name are covered.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use mopaq::integrity::pkcs1::DecodeRsaPublicKey;
+//! use mopaq::integrity::{self, IntegrityManifest, RsaPublicKey};
+//!
+//! let key = RsaPublicKey::from_pkcs1_pem(&std::fs::read_to_string("server.pub")?)
+//!     .map_err(|e| mopaq::Error::crypto(e.to_string()))?;
+//! let manifest = IntegrityManifest::load("client.mpqintegrity")?;
+//! let report = integrity::verify_installation("C:\\Games\\WoW\\Data", &manifest, &key)?;
+//! for (archive, name) in &report.modified_files {
+//!     println!("{archive}: {name} was modified");
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use rsa::Pkcs1v15Sign;
+use sha1::{Digest, Sha1};
+
+pub use rsa::{pkcs1, RsaPrivateKey, RsaPublicKey};
+
+use crate::io::take;
+use crate::{Archive, Error, Result};
+
+/// Signature at the start of an integrity manifest container
+const MAGIC: [u8; 4] = *b"MPQi";
+
+/// Container format version written by [`IntegrityManifest::write_to`]
+const VERSION: u32 = 1;
+
+/// DER prefix of a SHA-1 `DigestInfo`, as PKCS#1 v1.5 signs it
+const SHA1_DIGEST_INFO: [u8; 15] = [
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2B, 0x0E, 0x03, 0x02, 0x1A, 0x05, 0x00, 0x04, 0x14,
+];
+
+/// A single file in an [`IntegrityManifest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityEntry {
+    /// Path of the archive relative to the installation root, with `/`
+    /// as separator
+    pub archive: String,
+    /// File name in the archive
+    pub name: String,
+    /// Uncompressed size
+    pub size: u64,
+    /// SHA-1 of the uncompressed contents
+    pub sha1: [u8; 20],
+}
+
+/// Signed per-file hashes of a set of archives, as returned by [`build_manifest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityManifest {
+    /// Files sorted by archive, then name
+    pub entries: Vec<IntegrityEntry>,
+    /// PKCS#1 v1.5 signature of the serialized entries
+    signature: Vec<u8>,
+}
+
+impl IntegrityManifest {
+    /// The signature over [`entries`](Self::entries)
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    /// Check that the entries are exactly those the key's owner signed
+    ///
+    /// # Errors
+    /// - `Error::SignatureVerification` if the signature does not match
+    pub fn verify_signature(&self, key: &RsaPublicKey) -> Result<()> {
+        let digest = Sha1::digest(encode_entries(&self.entries)?);
+        key.verify(sha1_padding(), &digest, &self.signature)
+            .map_err(|e| Error::SignatureVerification(format!("integrity manifest: {e}")))
+    }
+
+    /// Serialize the manifest and its signature
+    ///
+    /// # Errors
+    /// - Any I/O error from `writer`
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let body = encode_entries(&self.entries)?;
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(body.len() as u64).to_le_bytes())?;
+        writer.write_all(&body)?;
+        writer.write_all(&(self.signature.len() as u32).to_le_bytes())?;
+        writer.write_all(&self.signature)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Parse a manifest written by [`write_to`](Self::write_to)
+    ///
+    /// The signature is not checked; see
+    /// [`verify_signature`](Self::verify_signature).
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if the data is not an integrity manifest or
+    ///   is truncated
+    /// - `Error::UnsupportedVersion` for containers from a newer version
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 16];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(Error::invalid_format("not an MPQ integrity manifest"));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version as u16));
+        }
+        let body_size = u64::from_le_bytes(header[8..16].try_into().unwrap());
+
+        let mut body = Vec::new();
+        (&mut reader).take(body_size).read_to_end(&mut body)?;
+        if body.len() as u64 != body_size {
+            return Err(Error::invalid_format("truncated integrity manifest"));
+        }
+        let mut signature_size = [0u8; 4];
+        reader.read_exact(&mut signature_size)?;
+        let signature_size = u32::from_le_bytes(signature_size) as u64;
+        let mut signature = Vec::new();
+        reader.take(signature_size).read_to_end(&mut signature)?;
+        if signature.len() as u64 != signature_size {
+            return Err(Error::invalid_format("truncated integrity manifest"));
+        }
+
+        Ok(Self {
+            entries: decode_entries(&body)?,
+            signature,
+        })
+    }
+
+    /// Write the manifest to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Read a manifest from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Differences between an installation and its manifest, as returned by
+/// [`verify_installation`]
+///
+/// Files are given as `(archive, name)` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Number of files whose contents were checked
+    pub checked: usize,
+    /// Archives in the manifest that are not installed
+    pub missing_archives: Vec<String>,
+    /// Files in the manifest that their archive does not contain
+    pub missing_files: Vec<(String, String)>,
+    /// Files whose size or hash differs from the manifest
+    pub modified_files: Vec<(String, String)>,
+    /// Files in an installed archive that the manifest does not list
+    pub unexpected_files: Vec<(String, String)>,
+}
+
+impl IntegrityReport {
+    /// Check whether the installation matches the manifest exactly
+    pub fn is_clean(&self) -> bool {
+        self.missing_archives.is_empty()
+            && self.missing_files.is_empty()
+            && self.modified_files.is_empty()
+            && self.unexpected_files.is_empty()
+    }
+}
+
+/// Hash every file of `archives` and sign the result
+///
+/// `archives` are paths relative to `root` and are recorded that way, so
+/// the manifest can be checked against an installation anywhere.
+///
+/// # Errors
+/// - Any error from opening, listing or reading an archive
+/// - `Error::Crypto` if signing fails, e.g. because the key is too small
+pub fn build_manifest<R, P>(
+    root: R,
+    archives: &[P],
+    key: &RsaPrivateKey,
+) -> Result<IntegrityManifest>
+where
+    R: AsRef<Path>,
+    P: AsRef<Path>,
+{
+    let root = root.as_ref();
+    let mut entries = Vec::new();
+    for relative in archives {
+        let relative = relative.as_ref();
+        let archive_name = relative.to_string_lossy().replace('\\', "/");
+        let mut archive = Archive::open(root.join(relative))?;
+        for entry in archive.list()? {
+            let sha1 = hash_file(&archive, &entry.name)?;
+            entries.push(IntegrityEntry {
+                archive: archive_name.clone(),
+                name: entry.name,
+                size: entry.size,
+                sha1,
+            });
+        }
+    }
+    entries.sort_by(|a, b| (&a.archive, &a.name).cmp(&(&b.archive, &b.name)));
+
+    let digest = Sha1::digest(encode_entries(&entries)?);
+    let signature = key
+        .sign(sha1_padding(), &digest)
+        .map_err(|e| Error::crypto(format!("cannot sign integrity manifest: {e}")))?;
+    Ok(IntegrityManifest { entries, signature })
+}
+
+/// Check the installation below `root` against a signed manifest
+///
+/// The manifest's signature is checked first. Every archive it names is
+/// then opened below `root`, each listed file is hashed and compared, and
+/// the archive's own listing is searched for files the manifest does not
+/// know. A missing archive is reported rather than failing the check, and
+/// a file that cannot be read is reported as modified.
+///
+/// # Errors
+/// - `Error::SignatureVerification` if the manifest was not signed by `key`
+/// - Any error from opening or listing an installed archive
+pub fn verify_installation<R: AsRef<Path>>(
+    root: R,
+    manifest: &IntegrityManifest,
+    key: &RsaPublicKey,
+) -> Result<IntegrityReport> {
+    manifest.verify_signature(key)?;
+
+    let root = root.as_ref();
+    let mut report = IntegrityReport::default();
+    let mut archives: Vec<&str> = manifest
+        .entries
+        .iter()
+        .map(|entry| entry.archive.as_str())
+        .collect();
+    archives.dedup();
+
+    for archive_name in archives {
+        let path = root.join(archive_name);
+        if !path.exists() {
+            report.missing_archives.push(archive_name.to_string());
+            continue;
+        }
+        let mut archive = Archive::open(&path)?;
+        let policy = archive.name_hashing();
+
+        let mut expected = HashSet::new();
+        for entry in manifest
+            .entries
+            .iter()
+            .filter(|entry| entry.archive == archive_name)
+        {
+            expected.insert(policy.normalize_name(&entry.name));
+            let file = (entry.archive.clone(), entry.name.clone());
+            match archive.find_file(&entry.name)? {
+                None => report.missing_files.push(file),
+                Some(info) if info.file_size != entry.size => report.modified_files.push(file),
+                Some(_) => {
+                    report.checked += 1;
+                    match hash_file(&archive, &entry.name) {
+                        Ok(sha1) if sha1 == entry.sha1 => {}
+                        Ok(_) => report.modified_files.push(file),
+                        // A file that no longer reads has been damaged
+                        Err(e) => {
+                            log::warn!("Cannot read {} in {}: {}", entry.name, archive_name, e);
+                            report.modified_files.push(file);
+                        }
+                    }
+                }
+            }
+        }
+
+        for entry in archive.list()? {
+            if !expected.contains(&policy.normalize_name(&entry.name)) {
+                report
+                    .unexpected_files
+                    .push((archive_name.to_string(), entry.name));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// SHA-1 of a file's uncompressed contents
+fn hash_file(archive: &Archive, name: &str) -> Result<[u8; 20]> {
+    let mut sha1 = Sha1::new();
+    archive.read_file_chunks(name, |chunk| {
+        sha1.update(chunk);
+        Ok(true)
+    })?;
+    Ok(sha1.finalize().into())
+}
+
+/// PKCS#1 v1.5 signature scheme over a SHA-1 digest
+fn sha1_padding() -> Pkcs1v15Sign {
+    Pkcs1v15Sign {
+        hash_len: Some(20),
+        prefix: SHA1_DIGEST_INFO.into(),
+    }
+}
+
+/// The signed form of a manifest's entries
+fn encode_entries(entries: &[IntegrityEntry]) -> Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(4 + entries.len() * 96);
+    body.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for entry in entries {
+        for text in [&entry.archive, &entry.name] {
+            let len = u16::try_from(text.len())
+                .map_err(|_| Error::invalid_format("name too long for an integrity manifest"))?;
+            body.extend_from_slice(&len.to_le_bytes());
+            body.extend_from_slice(text.as_bytes());
+        }
+        body.extend_from_slice(&entry.size.to_le_bytes());
+        body.extend_from_slice(&entry.sha1);
+    }
+    Ok(body)
+}
+
+fn decode_entries(body: &[u8]) -> Result<Vec<IntegrityEntry>> {
+    let mut cursor = body;
+    let count = u32::from_le_bytes(take(&mut cursor)?);
+    let mut entries = Vec::with_capacity((count as usize).min(body.len() / 32));
+    for _ in 0..count {
+        entries.push(IntegrityEntry {
+            archive: take_string(&mut cursor)?,
+            name: take_string(&mut cursor)?,
+            size: u64::from_le_bytes(take(&mut cursor)?),
+            sha1: take(&mut cursor)?,
+        });
+    }
+    Ok(entries)
+}
+
+fn take_string(cursor: &mut &[u8]) -> Result<String> {
+    let len = u16::from_le_bytes(take(cursor)?) as usize;
+    if cursor.len() < len {
+        return Err(Error::invalid_format("truncated integrity manifest"));
+    }
+    let (text, rest) = cursor.split_at(len);
+    *cursor = rest;
+    String::from_utf8(text.to_vec()).map_err(|_| Error::InvalidUtf8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrity_manifest_roundtrip() {
+        let manifest = IntegrityManifest {
+            entries: vec![
+                IntegrityEntry {
+                    archive: "Data/patch.MPQ".to_string(),
+                    name: "Interface\\FrameXML\\UIParent.lua".to_string(),
+                    size: 1234,
+                    sha1: [7; 20],
+                },
+                IntegrityEntry {
+                    archive: "Data/patch.MPQ".to_string(),
+                    name: "(listfile)".to_string(),
+                    size: 40,
+                    sha1: [9; 20],
+                },
+            ],
+            signature: vec![0xAB; 128],
+        };
+
+        let mut bytes = Vec::new();
+        manifest.write_to(&mut bytes).unwrap();
+        assert_eq!(
+            IntegrityManifest::read_from(bytes.as_slice()).unwrap(),
+            manifest
+        );
+        assert!(IntegrityManifest::read_from(&bytes[..bytes.len() - 1]).is_err());
+        assert!(IntegrityManifest::read_from(&b"MPQm\x01\0\0\0"[..]).is_err());
+    }
+}
@@ -1,7 +1,13 @@
 //! I/O abstractions for MPQ archives
 
-use crate::Result;
+use crate::metrics::MetricsSink;
+use crate::{Error, Result};
+use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
 
 /// Trait for reading from MPQ archives
 pub trait MpqRead: Read + Seek {
@@ -42,6 +48,303 @@ impl<R: Seek> Seek for BufferedMpqReader<R> {
     }
 }
 
+/// A file read at explicit offsets through a shared reference
+///
+/// Every read names its own offset instead of moving a shared cursor, so
+/// one handle serves any number of concurrent readers. Unix and Windows
+/// read positionally; elsewhere reads are serialized behind a lock.
+#[derive(Debug)]
+pub(crate) struct PositionedFile {
+    #[cfg(any(unix, windows))]
+    file: File,
+    #[cfg(not(any(unix, windows)))]
+    file: std::sync::Mutex<File>,
+    /// Sink told about every byte read
+    metrics: Option<MetricsSink>,
+}
+
+impl PositionedFile {
+    /// Read `file` positionally from now on
+    pub(crate) fn new(file: File) -> Self {
+        #[cfg(not(any(unix, windows)))]
+        let file = std::sync::Mutex::new(file);
+        Self {
+            file,
+            metrics: None,
+        }
+    }
+
+    /// Report the bytes read to `metrics`
+    pub(crate) fn with_metrics(mut self, metrics: Option<MetricsSink>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Fill `buf` with the bytes starting at `offset`
+    ///
+    /// Fails with `UnexpectedEof` if the file ends first.
+    pub(crate) fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        self.read_exact_at_unreported(buf, offset)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.0.on_bytes_read(buf.len() as u64);
+        }
+        Ok(())
+    }
+
+    fn read_exact_at_unreported(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::FileExt::read_exact_at(&self.file, buf, offset)
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::FileExt;
+
+            let mut filled = 0;
+            while filled < buf.len() {
+                match self
+                    .file
+                    .seek_read(&mut buf[filled..], offset + filled as u64)
+                {
+                    Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                    Ok(read) => filled += read,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(())
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(buf)
+        }
+    }
+
+    /// Read up to `buf.len()` bytes starting at `offset`
+    ///
+    /// Returns the number of bytes read, 0 at the end of the file.
+    pub(crate) fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let read = self.read_at_unreported(buf, offset)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.0.on_bytes_read(read as u64);
+        }
+        Ok(read)
+    }
+
+    fn read_at_unreported(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::FileExt::read_at(&self.file, buf, offset)
+        }
+        #[cfg(windows)]
+        {
+            std::os::windows::fs::FileExt::seek_read(&self.file, buf, offset)
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+            file.seek(SeekFrom::Start(offset))?;
+            file.read(buf)
+        }
+    }
+
+    /// Read a little-endian `u32` at `offset`
+    pub(crate) fn read_u32_at(&self, offset: u64) -> std::io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.read_exact_at(&mut bytes, offset)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Current length of the file
+    pub(crate) fn len(&self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    /// Metadata of the file
+    pub(crate) fn metadata(&self) -> std::io::Result<std::fs::Metadata> {
+        #[cfg(any(unix, windows))]
+        {
+            self.file.metadata()
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            self.file
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .metadata()
+        }
+    }
+}
+
+/// A cursor over a shared [`PositionedFile`]
+///
+/// Each cursor keeps its own position, so any number of them can read the
+/// same file handle without disturbing each other.
+#[derive(Debug)]
+pub(crate) struct FileCursor {
+    file: Arc<PositionedFile>,
+    position: u64,
+}
+
+impl FileCursor {
+    /// Start a cursor at the beginning of `file`
+    pub(crate) fn new(file: Arc<PositionedFile>) -> Self {
+        Self { file, position: 0 }
+    }
+}
+
+impl Read for FileCursor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.file.read_at(buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for FileCursor {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.file.len()?.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Whether `name` is one of the device names Windows reserves in every directory
+///
+/// Case and everything from the first dot on are ignored, so `nul`,
+/// `CON.txt` and `com1.tar.gz` are all reserved.
+pub(crate) fn is_reserved_name(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    if ["CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$"]
+        .iter()
+        .any(|reserved| base.eq_ignore_ascii_case(reserved))
+    {
+        return true;
+    }
+    match (base.get(..3), base.get(3..)) {
+        (Some(prefix), Some(digit)) => {
+            (prefix.eq_ignore_ascii_case("COM") || prefix.eq_ignore_ascii_case("LPT"))
+                && digit.len() == 1
+                && digit.as_bytes()[0].is_ascii_digit()
+        }
+        _ => false,
+    }
+}
+
+/// Whether opening `path` on Windows would reach a device instead of a file
+///
+/// Verbatim `\\?\` paths are passed to the file system as written, where
+/// such names are ordinary files, so they never name a device.
+pub(crate) fn names_device(path: &Path) -> bool {
+    !path.as_os_str().to_string_lossy().starts_with(r"\\?\")
+        && path
+            .file_name()
+            .is_some_and(|name| is_reserved_name(&name.to_string_lossy()))
+}
+
+/// Refuse archive paths that name a Windows device such as `NUL` or `CON`
+///
+/// Reading `CON` would wait for console input and writing `NUL` would throw
+/// the archive away. Other platforms have no reserved names.
+pub(crate) fn check_device_path(path: &Path) -> std::io::Result<()> {
+    if cfg!(windows) && names_device(path) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is a reserved device name", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Split the next `N` bytes off the front of `cursor`
+///
+/// Used by the parsers of the small containers this crate writes, such as
+/// manifests and delta patches.
+pub(crate) fn take<const N: usize>(cursor: &mut &[u8]) -> Result<[u8; N]> {
+    if cursor.len() < N {
+        return Err(Error::invalid_format("unexpected end of data"));
+    }
+    let (bytes, rest) = cursor.split_at(N);
+    *cursor = rest;
+    Ok(bytes.try_into().unwrap())
+}
+
+/// Largest range a single [`Readahead::request`] reads
+const READAHEAD_LIMIT: u64 = 8 * 1024 * 1024;
+
+/// Background thread that reads byte ranges ahead of their use
+///
+/// The data read is thrown away; the point is to have the OS pull it into
+/// its page cache while the caller is still busy with earlier data. Only
+/// one request is queued at a time and further requests are dropped until
+/// the thread catches up, so a slow disk never holds up the caller.
+#[derive(Debug)]
+pub(crate) struct Readahead {
+    sender: Option<SyncSender<(u64, u64)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Readahead {
+    /// Start a readahead thread for `file`
+    ///
+    /// If the thread cannot be spawned, requests are silently ignored.
+    pub(crate) fn spawn(file: Arc<PositionedFile>) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<(u64, u64)>(1);
+        let thread = std::thread::Builder::new()
+            .name("mopaq-readahead".to_string())
+            .spawn(move || {
+                let mut buffer = vec![0u8; 64 * 1024];
+                for (offset, len) in receiver {
+                    let end = offset.saturating_add(len.min(READAHEAD_LIMIT));
+                    let mut position = offset;
+                    while position < end {
+                        let chunk = (end - position).min(buffer.len() as u64) as usize;
+                        if file.read_exact_at(&mut buffer[..chunk], position).is_err() {
+                            break;
+                        }
+                        position += chunk as u64;
+                    }
+                }
+            })
+            .ok();
+        Self {
+            sender: thread.as_ref().map(|_| sender),
+            thread,
+        }
+    }
+
+    /// Ask for `len` bytes at `offset` to be read ahead
+    ///
+    /// Ranges longer than 8 MiB are cut short. The request is dropped if
+    /// the previous one is still waiting.
+    pub(crate) fn request(&self, offset: u64, len: u64) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.try_send((offset, len));
+        }
+    }
+}
+
+impl Drop for Readahead {
+    fn drop(&mut self) {
+        // Closing the channel ends the thread once its current range is read
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -327,4 +630,80 @@ mod tests {
         assert_eq!(reader.inner.read_count.get(), 1);
         assert_eq!(buf, [3, 4]);
     }
+
+    #[test]
+    fn test_positioned_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, [1u8, 0, 0, 0, 5, 6, 7]).unwrap();
+        let file = PositionedFile::new(File::open(&path).unwrap());
+
+        // Reads do not depend on each other's position
+        let mut buf = [0u8; 3];
+        file.read_exact_at(&mut buf, 4).unwrap();
+        assert_eq!(buf, [5, 6, 7]);
+        assert_eq!(file.read_u32_at(0).unwrap(), 1);
+        assert_eq!(file.len().unwrap(), 7);
+
+        let error = file.read_exact_at(&mut buf, 5).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_file_cursors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, [1u8, 2, 3, 4, 5, 6, 7]).unwrap();
+        let file = Arc::new(PositionedFile::new(File::open(&path).unwrap()));
+
+        // Each cursor has its own position
+        let mut first = FileCursor::new(Arc::clone(&file));
+        let mut second = FileCursor::new(file);
+        let mut buf = [0u8; 3];
+        first.seek(SeekFrom::Start(2)).unwrap();
+        first.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [3, 4, 5]);
+        second.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+
+        assert_eq!(first.seek(SeekFrom::Current(-1)).unwrap(), 4);
+        assert_eq!(first.seek(SeekFrom::End(-2)).unwrap(), 5);
+        let mut rest = Vec::new();
+        first.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, [6, 7]);
+        assert!(first.seek(SeekFrom::End(-8)).is_err());
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        for name in [
+            "CON", "nul", "Aux.txt", "com1", "LPT9.mpq", "con .txt", "CONOUT$",
+        ] {
+            assert!(is_reserved_name(name), "{name}");
+        }
+        for name in ["console", "nul_", "com10", "lpt", "com¹", "", "x.con"] {
+            assert!(!is_reserved_name(name), "{name}");
+        }
+
+        assert!(names_device(Path::new("NUL")));
+        assert!(names_device(Path::new("maps/con.mpq")));
+        assert!(!names_device(Path::new("con/maps.mpq")));
+        assert!(!names_device(Path::new(r"\\?\C:\maps\con.mpq")));
+    }
+
+    #[test]
+    fn test_readahead_stops_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, vec![0u8; 200_000]).unwrap();
+        let file = Arc::new(PositionedFile::new(File::open(&path).unwrap()));
+
+        // Ranges running past the end are read as far as they go
+        let readahead = Readahead::spawn(Arc::clone(&file));
+        readahead.request(0, 100_000);
+        readahead.request(150_000, u64::MAX);
+        drop(readahead);
+
+        assert_eq!(Arc::strong_count(&file), 1);
+    }
 }
@@ -45,32 +45,80 @@
     unreachable_pub
 )]
 
+pub mod analysis;
 pub mod archive;
+#[cfg(feature = "build-manifest")]
+pub mod build_manifest;
 pub mod builder;
+#[cfg(feature = "cache")]
+mod cache;
+pub mod capabilities;
+pub mod checksum;
+pub mod compatibility;
 pub mod compression;
+pub mod convert;
 pub mod crypto;
+pub mod delta;
+pub mod detect;
 pub mod error;
+pub mod extract;
 pub mod header;
+pub mod integrity;
 pub mod io;
+pub mod locale;
+pub mod manifest;
+pub mod metrics;
+pub mod modification;
+pub mod mpq_header;
+pub mod patch_chain;
+pub mod pattern;
 pub mod special_files;
+pub mod split;
 pub mod tables;
+pub mod tree;
+pub mod vfs;
 
 #[cfg(test)]
 pub mod test_utils;
 
 // Re-export commonly used types
 pub use archive::{
-    Archive, ArchiveInfo, FileEntry, FileInfo, Md5Status, OpenOptions, SignatureStatus, TableInfo,
-    UserDataInfo,
+    AnonymousEntry, Archive, ArchiveInfo, FileEntry, FileInfo, ListOptions, ListSort, Md5Status,
+    OpenOptions, SectorError, SectorInfo, SectorMap, SignatureStatus, TableInfo, UserDataInfo,
 };
-pub use builder::{ArchiveBuilder, ListfileOption};
+#[cfg(feature = "build-manifest")]
+pub use build_manifest::BuildManifest;
+pub use builder::{
+    ArchiveBuilder, BuildObserver, BuildPlan, BuildSummary, BuildTable, BuiltFile, ListfileOption,
+    PlannedFile,
+};
+pub use capabilities::{capabilities, Capabilities};
+pub use checksum::SectorChecksum;
+pub use compatibility::Compatibility;
+pub use delta::DeltaPatch;
+pub use detect::FileKind;
 pub use error::{Error, Result};
+pub use extract::{
+    CollisionPolicy, ExtractFailure, ExtractOptions, ExtractSummary, ExtractionOrder,
+};
 pub use header::{FormatVersion, MpqHeader};
-pub use tables::{BetFileInfo, BetTable, BlockEntry, BlockTable, HashEntry, HashTable, HetTable};
+pub use locale::Locale;
+pub use manifest::{Manifest, ManifestDiff, ManifestEntry};
+pub use metrics::{Metrics, MetricsCounters};
+pub use modification::{AddFileOptions, MutableArchive};
+pub use patch_chain::{ChainEntry, PatchChain};
+pub use tables::{
+    BetFileInfo, BetTable, BlockEntry, BlockTable, HashEntry, HashTable, HetTable, PlatformPolicy,
+    TableKey, TableOffsetPolicy,
+};
+pub use tree::{DirNode, TreeFile};
+pub use vfs::{MpqVfs, VfsEntry, VfsSource};
 
 // Re-export crypto for CLI usage
 pub use crypto::{
-    decrypt_block, decrypt_dword, encrypt_block, hash_string, hash_type, jenkins_hash,
+    decrypt_block, decrypt_dword, detect_key_by_known_plaintext, encrypt_block, hash_string,
+    hash_string_with, hash_type, jenkins_hash, jenkins_hash_with, NameHashingPolicy,
+    PrecomputedName, SignaturePolicy,
 };
 
 // Re-export compression for testing
@@ -94,10 +142,38 @@ pub mod signatures {
     pub const STRONG_SIGNATURE: [u8; 4] = *b"NGIS";
 }
 
+/// Largest block size an archive may declare
+///
+/// Sectors of `512 << 22` bytes are the largest whose size fits in 32 bits,
+/// which the sector offset tables and 32-bit targets both need.
+pub const MAX_BLOCK_SIZE: u16 = 22;
+
 /// Block size calculation
+///
+/// # Panics
+/// If `block_size_shift` is above [`MAX_BLOCK_SIZE`]. Use
+/// [`checked_sector_size`] for shifts that have not been validated.
 #[inline]
 pub fn calculate_sector_size(block_size_shift: u16) -> usize {
-    512 << block_size_shift
+    checked_sector_size(block_size_shift).expect("block size above MAX_BLOCK_SIZE")
+}
+
+/// Sector size for a block size, or `None` above [`MAX_BLOCK_SIZE`]
+#[inline]
+pub fn checked_sector_size(block_size_shift: u16) -> Option<usize> {
+    (block_size_shift <= MAX_BLOCK_SIZE).then(|| 512 << block_size_shift)
+}
+
+/// I/O buffer size an archive with sectors of `sector_size` bytes is read
+/// with by default
+///
+/// Sixteen sectors, but at least 64 KiB and at most 1 MiB: archives with
+/// small sectors read many of them per system call, while archives with
+/// huge sectors do not allocate more than a megabyte per reader. See
+/// [`OpenOptions::io_buffer_size`].
+#[inline]
+pub fn default_io_buffer_size(sector_size: usize) -> usize {
+    sector_size.saturating_mul(16).clamp(64 * 1024, 1024 * 1024)
 }
 
 /// Check if a value is a power of two
@@ -110,6 +186,21 @@ pub fn is_power_of_two(value: u32) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_default_io_buffer_size() {
+        assert_eq!(default_io_buffer_size(calculate_sector_size(0)), 64 * 1024);
+        assert_eq!(default_io_buffer_size(calculate_sector_size(3)), 64 * 1024);
+        assert_eq!(default_io_buffer_size(calculate_sector_size(5)), 256 * 1024);
+        assert_eq!(
+            default_io_buffer_size(calculate_sector_size(7)),
+            1024 * 1024
+        );
+        assert_eq!(
+            default_io_buffer_size(calculate_sector_size(MAX_BLOCK_SIZE)),
+            1024 * 1024
+        );
+    }
+
     #[test]
     fn test_calculate_sector_size() {
         // Test standard sector sizes used in MPQ archives
@@ -170,6 +261,29 @@ mod tests {
         let max_shift = 16; // Reasonable maximum to test
         let result = calculate_sector_size(max_shift);
         assert_eq!(result, 512 << 16); // 33,554,432 bytes (32 MB)
+
+        // The largest allowed shift still fits in 32 bits
+        let largest = checked_sector_size(MAX_BLOCK_SIZE).unwrap();
+        assert_eq!(largest, 1 << 31);
+        assert!(u32::try_from(largest).is_ok());
+        assert_eq!(checked_sector_size(MAX_BLOCK_SIZE + 1), None);
+        assert_eq!(checked_sector_size(u16::MAX), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "MAX_BLOCK_SIZE")]
+    fn test_calculate_sector_size_above_max() {
+        calculate_sector_size(MAX_BLOCK_SIZE + 1);
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn test_sector_sizes_on_32_bit() {
+        for shift in 0..=MAX_BLOCK_SIZE {
+            assert_eq!(checked_sector_size(shift), Some(512usize << shift));
+        }
+        // 512 << 23 would wrap to zero in a 32-bit usize
+        assert_eq!(checked_sector_size(23), None);
     }
 
     #[test]
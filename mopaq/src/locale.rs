@@ -0,0 +1,225 @@
+//! Locale codes of archive files
+//!
+//! The hash table can hold several versions of the same file that differ
+//! by locale, such as a German and a French `war3map.wts`. Locales are
+//! Windows language identifiers (LANGID); 0 is the neutral locale that
+//! every lookup falls back to.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A locale code as stored in the hash table
+///
+/// Any `u16` is a valid locale; the associated constants name the codes
+/// Blizzard games ship with.
+///
+/// # Examples
+///
+/// ```
+/// use mopaq::Locale;
+///
+/// let locale: Locale = "deDE".parse()?;
+/// assert_eq!(locale, Locale::DE_DE);
+/// assert_eq!(locale.code(), 0x407);
+/// assert_eq!(locale.to_string(), "deDE");
+/// assert_eq!(Locale::from(0x1234).to_string(), "0x1234");
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Locale(u16);
+
+impl Locale {
+    /// Neutral locale, used when no localized version exists
+    pub const NEUTRAL: Locale = Locale(0);
+    /// Chinese (Taiwan)
+    pub const ZH_TW: Locale = Locale(0x404);
+    /// Czech
+    pub const CS_CZ: Locale = Locale(0x405);
+    /// German
+    pub const DE_DE: Locale = Locale(0x407);
+    /// English (United States)
+    pub const EN_US: Locale = Locale(0x409);
+    /// Spanish (Spain)
+    pub const ES_ES: Locale = Locale(0x40A);
+    /// French
+    pub const FR_FR: Locale = Locale(0x40C);
+    /// Italian
+    pub const IT_IT: Locale = Locale(0x410);
+    /// Japanese
+    pub const JA_JP: Locale = Locale(0x411);
+    /// Korean
+    pub const KO_KR: Locale = Locale(0x412);
+    /// Polish
+    pub const PL_PL: Locale = Locale(0x415);
+    /// Portuguese (Brazil)
+    pub const PT_BR: Locale = Locale(0x416);
+    /// Russian
+    pub const RU_RU: Locale = Locale(0x419);
+    /// Chinese (China)
+    pub const ZH_CN: Locale = Locale(0x804);
+    /// English (United Kingdom)
+    pub const EN_GB: Locale = Locale(0x809);
+    /// Spanish (Mexico)
+    pub const ES_MX: Locale = Locale(0x80A);
+    /// Portuguese (Portugal)
+    pub const PT_PT: Locale = Locale(0x816);
+
+    /// All named locales, by code
+    pub const KNOWN: [Locale; 17] = [
+        Locale::NEUTRAL,
+        Locale::ZH_TW,
+        Locale::CS_CZ,
+        Locale::DE_DE,
+        Locale::EN_US,
+        Locale::ES_ES,
+        Locale::FR_FR,
+        Locale::IT_IT,
+        Locale::JA_JP,
+        Locale::KO_KR,
+        Locale::PL_PL,
+        Locale::PT_BR,
+        Locale::RU_RU,
+        Locale::ZH_CN,
+        Locale::EN_GB,
+        Locale::ES_MX,
+        Locale::PT_PT,
+    ];
+
+    /// Create a locale from its code
+    pub const fn new(code: u16) -> Self {
+        Locale(code)
+    }
+
+    /// The code stored in the hash table
+    pub const fn code(self) -> u16 {
+        self.0
+    }
+
+    /// Whether this is the neutral locale
+    pub const fn is_neutral(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Blizzard's name for the locale, such as `"enUS"`
+    ///
+    /// Returns `"neutral"` for the neutral locale and `None` for codes
+    /// without a name.
+    pub fn name(self) -> Option<&'static str> {
+        Some(match self {
+            Locale::NEUTRAL => "neutral",
+            Locale::ZH_TW => "zhTW",
+            Locale::CS_CZ => "csCZ",
+            Locale::DE_DE => "deDE",
+            Locale::EN_US => "enUS",
+            Locale::ES_ES => "esES",
+            Locale::FR_FR => "frFR",
+            Locale::IT_IT => "itIT",
+            Locale::JA_JP => "jaJP",
+            Locale::KO_KR => "koKR",
+            Locale::PL_PL => "plPL",
+            Locale::PT_BR => "ptBR",
+            Locale::RU_RU => "ruRU",
+            Locale::ZH_CN => "zhCN",
+            Locale::EN_GB => "enGB",
+            Locale::ES_MX => "esMX",
+            Locale::PT_PT => "ptPT",
+            _ => return None,
+        })
+    }
+
+    /// Human-readable description
+    pub fn description(self) -> &'static str {
+        match self {
+            Locale::NEUTRAL => "Neutral/Default",
+            Locale::ZH_TW => "Chinese (Traditional)",
+            Locale::CS_CZ => "Czech",
+            Locale::DE_DE => "German",
+            Locale::EN_US => "English (US)",
+            Locale::ES_ES => "Spanish (ES)",
+            Locale::FR_FR => "French",
+            Locale::IT_IT => "Italian",
+            Locale::JA_JP => "Japanese",
+            Locale::KO_KR => "Korean",
+            Locale::PL_PL => "Polish",
+            Locale::PT_BR => "Portuguese (BR)",
+            Locale::RU_RU => "Russian",
+            Locale::ZH_CN => "Chinese (Simplified)",
+            Locale::EN_GB => "English (UK)",
+            Locale::ES_MX => "Spanish (MX)",
+            Locale::PT_PT => "Portuguese (PT)",
+            _ => "Unknown",
+        }
+    }
+}
+
+impl From<u16> for Locale {
+    fn from(code: u16) -> Self {
+        Locale(code)
+    }
+}
+
+impl From<Locale> for u16 {
+    fn from(locale: Locale) -> Self {
+        locale.0
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "0x{:04X}", self.0),
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = crate::Error;
+
+    /// Parse a locale name such as `"enUS"` (case-insensitive), `"neutral"`,
+    /// or a code in hex (`"0x409"`) or decimal (`"1033"`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(locale) = Locale::KNOWN.into_iter().find(|locale| {
+            locale
+                .name()
+                .is_some_and(|name| name.eq_ignore_ascii_case(s))
+        }) {
+            return Ok(locale);
+        }
+
+        let code = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => s.parse(),
+        };
+        code.map(Locale)
+            .map_err(|_| crate::Error::invalid_format(format!("unknown locale: {}", s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        for locale in Locale::KNOWN {
+            assert_eq!(locale.to_string().parse::<Locale>().unwrap(), locale);
+        }
+        assert_eq!("ENUS".parse::<Locale>().unwrap(), Locale::EN_US);
+        assert_eq!("0x809".parse::<Locale>().unwrap(), Locale::EN_GB);
+        assert_eq!("1031".parse::<Locale>().unwrap(), Locale::DE_DE);
+        assert_eq!("0".parse::<Locale>().unwrap(), Locale::NEUTRAL);
+        assert!("xxXX".parse::<Locale>().is_err());
+        assert!("0x10000".parse::<Locale>().is_err());
+
+        assert_eq!(Locale::NEUTRAL.to_string(), "neutral");
+        assert_eq!(Locale::from(0x0C0A).to_string(), "0x0C0A");
+        assert_eq!(Locale::from(0x0C0A).description(), "Unknown");
+    }
+}
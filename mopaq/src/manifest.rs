@@ -0,0 +1,451 @@
+//! Snapshots of an archive's contents for update checks
+//!
+//! A [`Manifest`] lists every file of an archive with its sizes, flags,
+//! position and content hashes. Launchers keep the manifest of what is
+//! installed, fetch the publisher's current one and use [`Manifest::diff`]
+//! to find the files that need updating, without opening either archive.
+//!
+//! Manifests are written in a small zlib-compressed container with
+//! [`Manifest::write_to`], and are also serde-serializable when the `serde`
+//! feature is enabled.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use mopaq::{Archive, Manifest};
+//!
+//! let installed = Manifest::load("installed.mpqmanifest")?;
+//! let current = Archive::open("patch.mpq")?.export_manifest()?;
+//!
+//! let diff = installed.diff(&current);
+//! for name in diff.added.iter().chain(&diff.changed) {
+//!     println!("download {}", name);
+//! }
+//! current.save("installed.mpqmanifest")?;
+//! # Ok::<(), mopaq::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::compression::{compress, decompress, flags as compression_flags};
+use crate::io::take;
+use crate::special_files::SpecialFile;
+use crate::{Error, Locale, NameHashingPolicy, Result};
+
+/// Signature at the start of a manifest container
+const MAGIC: [u8; 4] = *b"MPQm";
+
+/// Container format version written by [`Manifest::write_to`]
+const VERSION: u32 = 1;
+
+/// Largest factor zlib inflates data by, which bounds the body size a
+/// container can plausibly declare
+const MAX_EXPANSION: usize = 1032;
+
+/// Size of one entry in the container, excluding its name
+const ENTRY_SIZE: usize = 8 + 8 + 4 + 2 + 8 + 4 + 16;
+
+/// A single file in a [`Manifest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestEntry {
+    /// File name in the archive
+    pub name: String,
+    /// Uncompressed size
+    pub size: u64,
+    /// Size of the stored data
+    pub compressed_size: u64,
+    /// Block table flags
+    pub flags: u32,
+    /// Locale of the entry
+    pub locale: Locale,
+    /// Absolute position of the file's data in the archive file
+    pub offset: u64,
+    /// CRC32 of the uncompressed contents
+    pub crc32: u32,
+    /// MD5 of the uncompressed contents
+    pub md5: [u8; 16],
+}
+
+impl ManifestEntry {
+    /// Check whether two entries have the same contents
+    ///
+    /// Only the size and hashes are compared; the same file stored with
+    /// other compression or at another offset is considered unchanged.
+    pub fn same_contents(&self, other: &ManifestEntry) -> bool {
+        self.size == other.size && self.crc32 == other.crc32 && self.md5 == other.md5
+    }
+
+    /// Content key of the file as TACT and CASC tooling writes it
+    ///
+    /// CASC addresses files by the MD5 of their uncompressed contents, its
+    /// CKey, which is the same hash as [`md5`](Self::md5). This is that
+    /// hash as 32 lowercase hex digits.
+    pub fn ckey(&self) -> String {
+        self.md5
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Files that differ between two manifests, as returned by [`Manifest::diff`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManifestDiff {
+    /// Files only in the newer manifest
+    pub added: Vec<String>,
+    /// Files only in the older manifest
+    pub removed: Vec<String>,
+    /// Files in both whose contents differ
+    pub changed: Vec<String>,
+}
+
+impl ManifestDiff {
+    /// Check whether the manifests describe the same contents
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The contents of an archive, as returned by
+/// [`Archive::export_manifest`](crate::Archive::export_manifest)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Manifest {
+    /// Files sorted by name
+    pub entries: Vec<ManifestEntry>,
+    /// How the archive normalizes names, which entries are matched by
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name_hashing: NameHashingPolicy,
+}
+
+impl Manifest {
+    /// Look up an entry by name
+    ///
+    /// Names are compared as the archive's lookups compare them, so by
+    /// default case and path separator do not matter.
+    pub fn get(&self, name: &str) -> Option<&ManifestEntry> {
+        let policy = self.name_hashing;
+        let name = policy.normalize_name(name);
+        self.entries
+            .iter()
+            .find(|entry| policy.normalize_name(&entry.name) == name)
+    }
+
+    /// Compare with a newer manifest of the same archive
+    ///
+    /// Entries are matched by name as in [`get`](Self::get) on this
+    /// manifest. Each list in the result is sorted by name.
+    pub fn diff(&self, newer: &Manifest) -> ManifestDiff {
+        let policy = self.name_hashing;
+        let old: HashMap<String, &ManifestEntry> = self
+            .entries
+            .iter()
+            .map(|entry| (policy.normalize_name(&entry.name), entry))
+            .collect();
+        let new: HashMap<String, &ManifestEntry> = newer
+            .entries
+            .iter()
+            .map(|entry| (policy.normalize_name(&entry.name), entry))
+            .collect();
+
+        let mut diff = ManifestDiff::default();
+        for entry in &newer.entries {
+            match old.get(&policy.normalize_name(&entry.name)) {
+                None => diff.added.push(entry.name.clone()),
+                Some(previous) if !previous.same_contents(entry) => {
+                    diff.changed.push(entry.name.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        for entry in &self.entries {
+            if !new.contains_key(&policy.normalize_name(&entry.name)) {
+                diff.removed.push(entry.name.clone());
+            }
+        }
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        diff
+    }
+
+    /// Serialize the manifest into the compact container format
+    ///
+    /// # Errors
+    /// - Any I/O error from `writer`
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut body = Vec::with_capacity(6 + self.entries.len() * (ENTRY_SIZE + 32));
+        body.push(self.name_hashing.case_sensitive as u8);
+        body.push(self.name_hashing.separator.unwrap_or(0));
+        body.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for entry in &self.entries {
+            let name = entry.name.as_bytes();
+            let name_len = u16::try_from(name.len())
+                .map_err(|_| Error::invalid_format("file name too long for a manifest"))?;
+            body.extend_from_slice(&name_len.to_le_bytes());
+            body.extend_from_slice(name);
+            body.extend_from_slice(&entry.size.to_le_bytes());
+            body.extend_from_slice(&entry.compressed_size.to_le_bytes());
+            body.extend_from_slice(&entry.flags.to_le_bytes());
+            body.extend_from_slice(&entry.locale.code().to_le_bytes());
+            body.extend_from_slice(&entry.offset.to_le_bytes());
+            body.extend_from_slice(&entry.crc32.to_le_bytes());
+            body.extend_from_slice(&entry.md5);
+        }
+
+        let stored = compress(&body, compression_flags::ZLIB)?;
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(body.len() as u64).to_le_bytes())?;
+        writer.write_all(&(stored.len() as u64).to_le_bytes())?;
+        writer.write_all(&stored)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Parse a manifest written by [`write_to`](Self::write_to)
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if the data is not a manifest container or
+    ///   is truncated
+    /// - `Error::UnsupportedVersion` for containers from a newer version
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(Error::invalid_format("not an MPQ manifest"));
+        }
+        let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version as u16));
+        }
+        let body_size = u64::from_le_bytes(header[8..16].try_into().unwrap()) as usize;
+        let stored_size = u64::from_le_bytes(header[16..24].try_into().unwrap());
+
+        let mut stored = Vec::new();
+        reader.take(stored_size).read_to_end(&mut stored)?;
+        if stored.len() as u64 != stored_size {
+            return Err(Error::invalid_format("truncated manifest"));
+        }
+        // The body size is untrusted and sizes the decompression buffer
+        if body_size > stored.len().saturating_mul(MAX_EXPANSION) {
+            return Err(Error::invalid_format(format!(
+                "manifest declares a {} byte body for {} bytes of data",
+                body_size,
+                stored.len()
+            )));
+        }
+        let body = match stored.split_first() {
+            Some((&method, data)) if stored.len() < body_size => {
+                decompress(data, method, body_size)?
+            }
+            _ => stored,
+        };
+
+        let mut cursor = body.as_slice();
+        let [case_sensitive, separator] = take(&mut cursor)?;
+        let name_hashing = NameHashingPolicy {
+            case_sensitive: case_sensitive != 0,
+            separator: (separator != 0).then_some(separator),
+        };
+        let count = u32::from_le_bytes(take(&mut cursor)?);
+        let mut entries = Vec::with_capacity((count as usize).min(body.len() / ENTRY_SIZE));
+        for _ in 0..count {
+            let name_len = u16::from_le_bytes(take(&mut cursor)?) as usize;
+            if cursor.len() < name_len {
+                return Err(Error::invalid_format("truncated manifest"));
+            }
+            let (name, rest) = cursor.split_at(name_len);
+            cursor = rest;
+            entries.push(ManifestEntry {
+                name: String::from_utf8(name.to_vec()).map_err(|_| Error::InvalidUtf8)?,
+                size: u64::from_le_bytes(take(&mut cursor)?),
+                compressed_size: u64::from_le_bytes(take(&mut cursor)?),
+                flags: u32::from_le_bytes(take(&mut cursor)?),
+                locale: u16::from_le_bytes(take(&mut cursor)?).into(),
+                offset: u64::from_le_bytes(take(&mut cursor)?),
+                crc32: u32::from_le_bytes(take(&mut cursor)?),
+                md5: take(&mut cursor)?,
+            });
+        }
+
+        Ok(Self {
+            entries,
+            name_hashing,
+        })
+    }
+
+    /// Write the content keys of the files as a CKey manifest
+    ///
+    /// The format is this crate's own. It borrows the typed column header of
+    /// Blizzard's `.build.info`: pipe-separated text with a header line
+    /// `Path!STRING:0|CKey!HEX:16|Size!DEC:8`, then one line per file with
+    /// its name, [`ckey`](ManifestEntry::ckey) and uncompressed size. Names
+    /// use `/` as separator. Special files such as `(listfile)` describe the
+    /// MPQ rather than its contents and are left out.
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if a name contains `|` or a line break
+    /// - Any I/O error from `writer`
+    pub fn write_ckeys<W: Write>(&self, mut writer: W) -> Result<()> {
+        writeln!(writer, "Path!STRING:0|CKey!HEX:16|Size!DEC:8")?;
+        for entry in &self.entries {
+            if SpecialFile::from_name(&entry.name).is_some() {
+                continue;
+            }
+            if entry.name.contains(['|', '\r', '\n']) {
+                return Err(Error::invalid_format(format!(
+                    "{:?} cannot be written to a CKey manifest",
+                    entry.name
+                )));
+            }
+            writeln!(
+                writer,
+                "{}|{}|{}",
+                entry.name.replace('\\', "/"),
+                entry.ckey(),
+                entry.size
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the content keys of the files to a CKey manifest file
+    ///
+    /// See [`write_ckeys`](Self::write_ckeys) for the format.
+    pub fn save_ckeys<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_ckeys(BufWriter::new(File::create(path)?))
+    }
+
+    /// Write the manifest to a file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    /// Read a manifest from a file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, contents: &[u8]) -> ManifestEntry {
+        ManifestEntry {
+            name: name.to_string(),
+            size: contents.len() as u64,
+            compressed_size: contents.len() as u64,
+            flags: 0x8000_0000,
+            locale: Locale::NEUTRAL,
+            offset: 32,
+            crc32: crc32fast::hash(contents),
+            md5: [contents.len() as u8; 16],
+        }
+    }
+
+    #[test]
+    fn test_manifest_roundtrip_and_diff() {
+        let old = Manifest {
+            entries: vec![
+                entry("Units\\Footman.mdx", b"footman"),
+                entry("Units\\Peasant.mdx", b"peasant"),
+                entry("war3map.j", b"function main"),
+            ],
+            ..Manifest::default()
+        };
+
+        let mut bytes = Vec::new();
+        old.write_to(&mut bytes).unwrap();
+        assert_eq!(Manifest::read_from(bytes.as_slice()).unwrap(), old);
+        assert!(Manifest::read_from(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Manifest::read_from(&b"MPQd\x01\0\0\0"[..]).is_err());
+
+        let mut moved = entry("units/footman.mdx", b"footman");
+        moved.offset = 4096;
+        let new = Manifest {
+            entries: vec![
+                moved,
+                entry("war3map.j", b"function main takes nothing"),
+                entry("war3map.w3e", b"W3E!"),
+            ],
+            ..Manifest::default()
+        };
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, ["war3map.w3e"]);
+        assert_eq!(diff.removed, ["Units\\Peasant.mdx"]);
+        assert_eq!(diff.changed, ["war3map.j"]);
+        assert!(new.diff(&new).is_empty());
+        assert!(new.get("WAR3MAP.J").is_some());
+
+        // Names are matched with the archive's name hashing
+        let verbatim = Manifest {
+            name_hashing: NameHashingPolicy::VERBATIM,
+            ..new
+        };
+        let mut bytes = Vec::new();
+        verbatim.write_to(&mut bytes).unwrap();
+        assert_eq!(Manifest::read_from(bytes.as_slice()).unwrap(), verbatim);
+        assert!(verbatim.get("WAR3MAP.J").is_none());
+        assert!(verbatim.get("war3map.j").is_some());
+    }
+
+    #[test]
+    fn test_write_ckeys() {
+        let mut footman = entry("Units\\Footman.mdx", b"footman");
+        footman.md5 = [0xab; 16];
+        let manifest = Manifest {
+            entries: vec![entry("(listfile)", b"Units\\Footman.mdx"), footman],
+            ..Manifest::default()
+        };
+        assert_eq!(manifest.entries[1].ckey(), "ab".repeat(16));
+
+        let mut text = Vec::new();
+        manifest.write_ckeys(&mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            format!(
+                "Path!STRING:0|CKey!HEX:16|Size!DEC:8\nUnits/Footman.mdx|{}|7\n",
+                "ab".repeat(16)
+            )
+        );
+
+        let piped = Manifest {
+            entries: vec![entry("a|b", b"x")],
+            ..Manifest::default()
+        };
+        assert!(piped.write_ckeys(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_read_rejects_bad_body_size() {
+        let container = |body_size: u64, stored: &[u8]| {
+            let mut data = MAGIC.to_vec();
+            data.extend_from_slice(&VERSION.to_le_bytes());
+            data.extend_from_slice(&body_size.to_le_bytes());
+            data.extend_from_slice(&(stored.len() as u64).to_le_bytes());
+            data.extend_from_slice(stored);
+            data
+        };
+
+        for data in [
+            container(100, b""),
+            container(u64::MAX, &[0x02, 0x78]),
+            container(6, &[0x02, 0x78, 0x9c]),
+        ] {
+            assert!(matches!(
+                Manifest::read_from(data.as_slice()),
+                Err(Error::InvalidFormat(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Counters for archive I/O, reported to a host application
+//!
+//! Servers and tools that read many archives often want to export how much
+//! work that takes: bytes pulled from disk, sectors decompressed, files read
+//! and how often the metadata cache saved a table parse. An archive opened
+//! with [`OpenOptions::metrics`](crate::OpenOptions::metrics) reports each
+//! of these to a [`Metrics`] sink as it happens, so they can be fed into
+//! Prometheus or any other system without patching the crate.
+//!
+//! [`MetricsCounters`] is a ready-made sink that adds everything up.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use mopaq::{MetricsCounters, OpenOptions};
+//! use std::sync::Arc;
+//!
+//! let counters = Arc::new(MetricsCounters::new());
+//! let archive = OpenOptions::new()
+//!     .metrics(counters.clone())
+//!     .open("war3.mpq")?;
+//! archive.read_file("units\\unitdata.slk")?;
+//!
+//! println!(
+//!     "{} bytes read, {} sectors decompressed",
+//!     counters.bytes_read(),
+//!     counters.sectors_decompressed()
+//! );
+//! # Ok::<(), mopaq::Error>(())
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Receives counters from the read operations of an archive
+///
+/// All methods have empty default implementations, so a sink only needs to
+/// implement the events it cares about. An archive can be read from many
+/// threads at once, and its clones report to the same sink, so events arrive
+/// concurrently on whichever thread did the work.
+pub trait Metrics: Send + Sync {
+    /// Called after bytes were read from the archive file
+    ///
+    /// Covers every read: headers, tables, file data and the readahead of
+    /// extraction.
+    fn on_bytes_read(&self, _bytes: u64) {}
+
+    /// Called after a sector was decompressed
+    ///
+    /// A file stored in a single unit counts as one sector. Sectors stored
+    /// without compression are not reported.
+    fn on_sector_decompressed(&self, _compressed_size: u64, _decompressed_size: u64) {}
+
+    /// Called after a file was read in full, with its uncompressed size
+    ///
+    /// Reported by [`Archive::read_file`](crate::Archive::read_file) and
+    /// the methods built on it, by
+    /// [`Archive::read_file_chunks`](crate::Archive::read_file_chunks) and
+    /// so by extraction, and by
+    /// [`Archive::read_file_partial`](crate::Archive::read_file_partial).
+    fn on_file_read(&self, _name: &str, _size: u64) {}
+
+    /// Called when opening an archive looked its tables up in the metadata
+    /// cache, with whether they were found there
+    fn on_cache_lookup(&self, _hit: bool) {}
+}
+
+/// A [`Metrics`] sink that adds up every counter
+///
+/// Counters are atomic, so one sink can be shared by any number of
+/// archives and threads.
+#[derive(Debug, Default)]
+pub struct MetricsCounters {
+    bytes_read: AtomicU64,
+    sectors_decompressed: AtomicU64,
+    bytes_decompressed: AtomicU64,
+    files_read: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl MetricsCounters {
+    /// Counters that all start at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes read from archive files
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Sectors decompressed
+    pub fn sectors_decompressed(&self) -> u64 {
+        self.sectors_decompressed.load(Ordering::Relaxed)
+    }
+
+    /// Bytes the decompressed sectors came to
+    pub fn bytes_decompressed(&self) -> u64 {
+        self.bytes_decompressed.load(Ordering::Relaxed)
+    }
+
+    /// Files read in full
+    pub fn files_read(&self) -> u64 {
+        self.files_read.load(Ordering::Relaxed)
+    }
+
+    /// Opens that found their tables in the metadata cache
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    /// Opens that looked in the metadata cache and had to parse the tables
+    pub fn cache_misses(&self) -> u64 {
+        self.cache_misses.load(Ordering::Relaxed)
+    }
+}
+
+impl Metrics for MetricsCounters {
+    fn on_bytes_read(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn on_sector_decompressed(&self, _compressed_size: u64, decompressed_size: u64) {
+        self.sectors_decompressed.fetch_add(1, Ordering::Relaxed);
+        self.bytes_decompressed
+            .fetch_add(decompressed_size, Ordering::Relaxed);
+    }
+
+    fn on_file_read(&self, _name: &str, _size: u64) {
+        self.files_read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_cache_lookup(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Holder for the shared sink so archives and options can stay `Debug`
+#[derive(Clone)]
+pub(crate) struct MetricsSink(pub(crate) Arc<dyn Metrics>);
+
+impl std::fmt::Debug for MetricsSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("MetricsSink(..)")
+    }
+}
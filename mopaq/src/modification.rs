@@ -0,0 +1,1140 @@
+//! In-place modification of existing archives
+//!
+//! [`MutableArchive`] edits an archive without rebuilding it: new file data is
+//! appended after the existing contents and the tables are rewritten to point
+//! at it. Space held by data that gets replaced or removed is not reclaimed.
+//! Files the `(listfile)` does not name are left alone, and `(listfile)` and
+//! `(attributes)` are kept up to date with the files that change.
+//!
+//! v3 and v4 archives need their classic hash and block tables, which are
+//! edited the same way. Their HET and BET tables are rebuilt from the classic
+//! tables on every flush and appended after the data, and the v4 table and
+//! header MD5 checksums are recomputed to match.
+//!
+//! Any change invalidates an archive's signature; what happens to it is
+//! chosen with [`MutableArchive::set_signature_policy`].
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::builder::{
+    encode_bet_table, encode_ext_table, encode_file, encode_het_table, BuildObserver, EncodedFile,
+    ObserverSlot,
+};
+use crate::checksum::md5;
+use crate::compression::flags as compression_flags;
+use crate::crypto::{
+    encrypt_block, hash_string, hash_string_with, hash_type, sign_strong_signature,
+    sign_weak_signature, SignatureInfo, SignaturePolicy, SignatureType, WEAK_SIGNATURE_FILE_SIZE,
+};
+use crate::special_files::{parse_listfile, FileAttributes};
+use crate::tables::{
+    name_hash, BetFileInfo, BlockEntry, BlockTable, HashEntry, HashTable, HiBlockTable, TableKey,
+};
+use crate::{
+    mpq_header, Archive, Error, FormatVersion, Locale, NameHashingPolicy, OpenOptions, Result,
+};
+use rsa::RsaPrivateKey;
+
+/// How [`MutableArchive::add_file_data`] stores a file
+///
+/// Files are written with the same layouts as
+/// [`ArchiveBuilder`](crate::ArchiveBuilder): in one piece when they fit in
+/// a sector, in sectors otherwise, using the archive's sector size.
+///
+/// # Examples
+/// ```no_run
+/// use mopaq::{compression, AddFileOptions, MutableArchive};
+///
+/// let mut archive = MutableArchive::open("patch.mpq")?;
+/// let options = AddFileOptions::new()
+///     .compression(compression::flags::BZIP2)
+///     .encrypt(true);
+/// archive.add_file("build/war3map.j", "scripts\\war3map.j", &options)?;
+/// archive.flush()?;
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct AddFileOptions {
+    pub(crate) compression: u8,
+    pub(crate) compression_next: Option<u8>,
+    pub(crate) encrypt: bool,
+    pub(crate) fix_key: bool,
+    pub(crate) sector_crc: bool,
+    pub(crate) single_unit: bool,
+    pub(crate) implode: bool,
+    pub(crate) locale: Locale,
+}
+
+impl Default for AddFileOptions {
+    fn default() -> Self {
+        Self {
+            compression: compression_flags::ZLIB,
+            compression_next: None,
+            encrypt: false,
+            fix_key: false,
+            sector_crc: false,
+            single_unit: false,
+            implode: false,
+            locale: Locale::NEUTRAL,
+        }
+    }
+}
+
+impl AddFileOptions {
+    /// Options that store a zlib-compressed, unencrypted, locale-neutral file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the compression method, one of `compression::flags` (0 = none)
+    pub fn compression(mut self, compression: u8) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set a different compression method for every sector after the first
+    ///
+    /// Without it all sectors use [`compression`](Self::compression). Files
+    /// stored as a single unit only use that one.
+    pub fn compression_next(mut self, compression: u8) -> Self {
+        self.compression_next = Some(compression);
+        self
+    }
+
+    /// Encrypt the file with the key derived from its name
+    pub fn encrypt(mut self, encrypt: bool) -> Self {
+        self.encrypt = encrypt;
+        self
+    }
+
+    /// Adjust the encryption key by the file's position, as `FLAG_FIX_KEY`
+    /// does
+    ///
+    /// Only takes effect for encrypted files.
+    pub fn fix_key(mut self, fix_key: bool) -> Self {
+        self.fix_key = fix_key;
+        self
+    }
+
+    /// Write sector checksums, see
+    /// [`ArchiveBuilder::generate_crcs`](crate::ArchiveBuilder::generate_crcs)
+    pub fn sector_crc(mut self, sector_crc: bool) -> Self {
+        self.sector_crc = sector_crc;
+        self
+    }
+
+    /// Store the file in one piece instead of in sectors, whatever its size
+    ///
+    /// Files no larger than a sector are always stored this way.
+    pub fn single_unit(mut self, single_unit: bool) -> Self {
+        self.single_unit = single_unit;
+        self
+    }
+
+    /// Store a PKWare-compressed file as imploded, see
+    /// [`ArchiveBuilder::use_implode`](crate::ArchiveBuilder::use_implode)
+    pub fn implode(mut self, implode: bool) -> Self {
+        self.implode = implode;
+        self
+    }
+
+    /// Set the locale the file is stored for
+    pub fn locale(mut self, locale: impl Into<Locale>) -> Self {
+        self.locale = locale.into();
+        self
+    }
+}
+
+/// An archive opened for in-place modification
+///
+/// Changes are kept in memory until [`flush`](Self::flush) writes the tables
+/// and header back to disk. Dropping a `MutableArchive` without flushing
+/// leaves the archive readable but without the pending changes, although any
+/// file data already appended stays in the file as unused space.
+///
+/// # Examples
+/// ```no_run
+/// use mopaq::MutableArchive;
+///
+/// let mut archive = MutableArchive::open("patch.mpq")?;
+/// archive.update_listfile(&["units\\human\\footman.mdx"], &["old\\name.txt"])?;
+/// archive.flush()?;
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct MutableArchive {
+    path: PathBuf,
+    archive: Archive,
+    file: File,
+    hash_table: HashTable,
+    block_table: BlockTable,
+    hi_block_table: Option<HiBlockTable>,
+    /// What the HET and BET tables need beyond the classic tables, if the
+    /// archive has them
+    ext_tables: Option<ExtTables>,
+    signature_policy: SignaturePolicy,
+    /// Options the archive is reopened with after a flush
+    options: OpenOptions,
+    /// Strong signature that followed the archive when it was opened
+    strong_signature: Option<Vec<u8>>,
+    /// Names the `(listfile)` is rewritten with on the next flush
+    listfile: Option<Vec<String>>,
+    /// `(attributes)` entries of the files changed since the last flush, by
+    /// block index
+    attributes: HashMap<usize, FileAttributes>,
+    /// Whether file data was appended since the last flush
+    appended: bool,
+    /// Progress observer for stored files
+    observer: Option<ObserverSlot>,
+    dirty: bool,
+}
+
+/// HET/BET data of a v3+ archive that the classic tables do not hold
+#[derive(Debug)]
+struct ExtTables {
+    /// Bits of the Jenkins hash the tables keep
+    bits: u32,
+    /// Hash of the file in each block, `None` for deleted files
+    hashes: Vec<Option<u64>>,
+    /// File and stored sizes of files too large for the block table, by
+    /// block index
+    large_sizes: HashMap<usize, (u64, u64)>,
+}
+
+impl MutableArchive {
+    /// Open an archive for modification
+    ///
+    /// # Errors
+    /// - `Error::OperationNotSupported` for v3 and v4 archives without
+    ///   classic hash and block tables
+    /// - Any error from opening or parsing the archive
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_options(path, OpenOptions::default())
+    }
+
+    /// Open an archive for modification with specific options
+    ///
+    /// Use this for archives that need non-default open options, such as a
+    /// [`NameHashingPolicy`](crate::NameHashingPolicy); new names are hashed
+    /// with the same policy. Hash and block tables opened with a
+    /// [`TableKey::Fixed`] key are written back encrypted with that key.
+    ///
+    /// # Errors
+    /// - `Error::OperationNotSupported` for v3 and v4 archives without
+    ///   classic hash and block tables
+    /// - `Error::InvalidFormat` if the BET table does not cover the block
+    ///   table
+    /// - Any error from opening or parsing the archive
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: OpenOptions) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let options = options.load_tables(true);
+        let mut archive = Archive::open_with_options(&path, options.clone())?;
+
+        let version = archive.header().format_version;
+        if version >= FormatVersion::V3
+            && (archive.hash_table().is_none() || archive.block_table().is_none())
+        {
+            return Err(Error::OperationNotSupported {
+                // Report the version number users know, not the raw field
+                version: version as u16 + 1,
+                operation: "in-place modification without classic tables".to_string(),
+            });
+        }
+
+        let source_hash = archive
+            .hash_table()
+            .ok_or_else(|| Error::hash_table("Archive has no hash table"))?;
+        let mut hash_table = HashTable::new_mut(source_hash.size())?;
+        hash_table
+            .entries_mut()
+            .copy_from_slice(source_hash.entries());
+
+        let source_block = archive
+            .block_table()
+            .ok_or_else(|| Error::block_table("Archive has no block table"))?;
+        let block_table = Self::copy_block_table(source_block, source_block.size())?;
+
+        let hi_block_table = archive.hi_block_table().map(|source| {
+            let mut table = HiBlockTable::new(source.entries().len());
+            for (index, &high) in source.entries().iter().enumerate() {
+                table.set(index, high);
+            }
+            table
+        });
+
+        // BET entries line up with block entries, so the hashes the tables
+        // hold can be carried over without knowing every file's name
+        let ext_tables = match (archive.het_table(), archive.bet_table()) {
+            (Some(het), Some(bet)) => {
+                if bet.header.file_count as usize != block_table.size() {
+                    return Err(Error::invalid_format(
+                        "BET table does not match the block table",
+                    ));
+                }
+                let mut hashes = vec![None; block_table.size()];
+                for (index, hash) in het.name_hashes(bet) {
+                    if let Some(slot) = hashes.get_mut(index as usize) {
+                        *slot = Some(hash);
+                    }
+                }
+                let large_sizes = (0..bet.header.file_count)
+                    .filter_map(|index| {
+                        let info = bet.get_file_info(index)?;
+                        (info.file_size > u32::MAX as u64 || info.compressed_size > u32::MAX as u64)
+                            .then_some((index as usize, (info.file_size, info.compressed_size)))
+                    })
+                    .collect();
+                Some(ExtTables {
+                    bits: het.header.hash_entry_size,
+                    hashes,
+                    large_sizes,
+                })
+            }
+            _ => None,
+        };
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        let strong_signature = archive.strong_signature_data()?;
+
+        Ok(Self {
+            path,
+            archive,
+            file,
+            hash_table,
+            block_table,
+            hi_block_table,
+            ext_tables,
+            signature_policy: SignaturePolicy::Preserve,
+            options,
+            strong_signature,
+            listfile: None,
+            attributes: HashMap::new(),
+            appended: false,
+            observer: None,
+            dirty: false,
+        })
+    }
+
+    /// Set what happens to the archive's signature when changes are flushed
+    ///
+    /// The default, [`SignaturePolicy::Preserve`], leaves the `(signature)`
+    /// file and any strong signature as they are, although they no longer
+    /// verify. [`SignaturePolicy::Strip`] removes both, and
+    /// [`SignaturePolicy::Resign`] signs the modified archive, adding a
+    /// `(signature)` file for a weak key if there is none. The policy only
+    /// takes effect when there are changes to flush.
+    pub fn set_signature_policy(&mut self, policy: SignaturePolicy) {
+        self.signature_policy = policy;
+    }
+
+    /// Register an observer that receives progress events as files are
+    /// stored
+    ///
+    /// Every file written to the archive, including a rewritten `(listfile)`
+    /// or `(attributes)`, is reported with `on_file_start`,
+    /// `on_file_progress` and `on_file_done`. Tables are written by
+    /// [`flush`](Self::flush) without events. A later call replaces the
+    /// earlier observer.
+    pub fn set_observer<O: BuildObserver + Send + 'static>(&mut self, observer: O) {
+        self.observer = Some(ObserverSlot::new(observer));
+    }
+
+    /// Names currently stored in the `(listfile)`
+    ///
+    /// Returns an empty list when the archive has no listfile. Includes
+    /// changes that have not been flushed yet.
+    pub fn listfile_names(&mut self) -> Result<Vec<String>> {
+        Ok(self.pending_listfile()?.cloned().unwrap_or_default())
+    }
+
+    /// Add a file from disk, replacing the file of the same name and locale
+    ///
+    /// Reads the file and stores it like
+    /// [`add_file_data`](Self::add_file_data).
+    ///
+    /// # Errors
+    /// - Any error from reading the file or from
+    ///   [`add_file_data`](Self::add_file_data)
+    pub fn add_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        archive_name: &str,
+        options: &AddFileOptions,
+    ) -> Result<()> {
+        let data = std::fs::read(path)?;
+        self.add_file_data(&data, archive_name, options)
+    }
+
+    /// Append a file's data, replacing the file of the same name and locale
+    ///
+    /// A replaced file keeps its block table entry, which then points at
+    /// the new data; versions of it in other locales are left alone. A new
+    /// file gets a block entry and hash table slot. Either way the name is
+    /// added to the `(listfile)` if the archive has one.
+    ///
+    /// # Errors
+    /// - `Error::CapacityExceeded` if the file is larger than 4 GiB, or it
+    ///   is new and no hash table slot is free
+    pub fn add_file_data(
+        &mut self,
+        data: &[u8],
+        archive_name: &str,
+        options: &AddFileOptions,
+    ) -> Result<()> {
+        self.store_file(archive_name, data, options)?;
+
+        let policy = self.archive.name_hashing();
+        let normalized = policy.normalize_name(archive_name);
+        if let Some(names) = self.pending_listfile()? {
+            if !names
+                .iter()
+                .any(|listed| policy.normalize_name(listed) == normalized)
+            {
+                names.push(archive_name.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a file in every locale it is stored in
+    ///
+    /// The file's hash table slots and block entries are marked deleted and
+    /// its name is dropped from the `(listfile)`. Its data stays in the
+    /// archive as unused space.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if the archive has no file of that name
+    pub fn remove_file(&mut self, archive_name: &str) -> Result<()> {
+        self.remove_versions(archive_name, None)
+    }
+
+    /// Remove the version of a file stored for `locale`
+    ///
+    /// Versions in other locales are kept, and so is the name in the
+    /// `(listfile)` while any of them are left.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if the file has no version for `locale`
+    pub fn remove_file_with_locale(
+        &mut self,
+        archive_name: &str,
+        locale: impl Into<Locale>,
+    ) -> Result<()> {
+        self.remove_versions(archive_name, Some(locale.into()))
+    }
+
+    /// Add and remove `(listfile)` entries without touching any other file
+    ///
+    /// Names in `add` must refer to files that exist in the archive, which is
+    /// how names are recovered for entries that dropped out of the listfile.
+    /// Names already listed are skipped. Names are compared the way the
+    /// archive hashes them, by default case-insensitively and treating `/`
+    /// and `\` alike. A listfile is created if the archive has none.
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if a name in `add` is not in the archive
+    pub fn update_listfile(&mut self, add: &[&str], remove: &[&str]) -> Result<()> {
+        if let Some(&missing) = add.iter().find(|&&name| !self.contains(name)) {
+            return Err(Error::FileNotFound(missing.to_string()));
+        }
+
+        let created = self.pending_listfile()?.is_none();
+        let policy = self.archive.name_hashing();
+        let names = self.listfile.get_or_insert_with(Vec::new);
+
+        let removed: Vec<String> = remove
+            .iter()
+            .map(|name| policy.normalize_name(name))
+            .collect();
+        names.retain(|name| !removed.contains(&policy.normalize_name(name)));
+
+        for &name in add {
+            let normalized = policy.normalize_name(name);
+            if !names
+                .iter()
+                .any(|listed| policy.normalize_name(listed) == normalized)
+            {
+                names.push(name.to_string());
+            }
+        }
+
+        if created {
+            names.push("(listfile)".to_string());
+        }
+
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Add the names of an external listfile that exist in the archive
+    ///
+    /// `data` is parsed like a `(listfile)`, so community listfiles with
+    /// `;` metadata work as they are. Names that are not in the archive are
+    /// ignored, which lets one large listfile serve many archives, and names
+    /// already listed are skipped. Returns the number of names added.
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if `data` is not a valid listfile
+    pub fn import_listfile(&mut self, data: &[u8]) -> Result<usize> {
+        let policy = self.archive.name_hashing();
+        let mut known: std::collections::HashSet<String> = self
+            .listfile_names()?
+            .iter()
+            .map(|name| policy.normalize_name(name))
+            .collect();
+
+        let mut found = Vec::new();
+        for name in parse_listfile(data)? {
+            let normalized = policy.normalize_name(&name);
+            if known.contains(&normalized) || !self.contains(&name) {
+                continue;
+            }
+            known.insert(normalized);
+            found.push(name);
+        }
+
+        if !found.is_empty() {
+            let add: Vec<&str> = found.iter().map(String::as_str).collect();
+            self.update_listfile(&add, &[])?;
+        }
+        Ok(found.len())
+    }
+
+    /// Write the modified tables and header to disk
+    ///
+    /// The hash table is rewritten in place. The block table is rewritten in
+    /// place when its entry count is unchanged and appended to the end of the
+    /// archive otherwise. HET and BET tables are rebuilt and appended to the
+    /// end. A changed `(listfile)` and the `(attributes)` entries of changed
+    /// files are appended before the tables. Does nothing when there are no
+    /// pending changes.
+    ///
+    /// # Errors
+    /// - `Error::CapacityExceeded` if a new `(listfile)` or `(signature)`
+    ///   needs a hash table slot and none is free
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(names) = self.listfile.take() {
+            let mut content = String::new();
+            for name in &names {
+                content.push_str(name);
+                content.push_str("\r\n");
+            }
+            self.replace_file_data("(listfile)", content.as_bytes())?;
+        }
+        self.prepare_signature()?;
+        self.update_attributes()?;
+
+        let archive_offset = self.archive.archive_offset();
+        let header = self.archive.header().clone();
+        let (hash_key, block_key) = self.archive.table_keys();
+
+        // Hash table: same size, same place
+        let hash_table_pos = archive_offset + header.get_hash_table_pos();
+        let hash_data = encrypt_table(
+            self.hash_table.entries().iter().flat_map(|entry| {
+                [
+                    entry.name_1,
+                    entry.name_2,
+                    (entry.locale as u32) | ((entry.platform as u32) << 16),
+                    entry.block_index,
+                ]
+            }),
+            table_key(hash_key, "(hash table)"),
+        );
+        self.file.seek(SeekFrom::Start(hash_table_pos))?;
+        self.file.write_all(&hash_data)?;
+
+        // Block table: in place unless it grew
+        let block_count = self.block_table.size() as u32;
+        let block_table_pos = if block_count == header.block_table_size {
+            archive_offset + header.get_block_table_pos()
+        } else {
+            self.file.seek(SeekFrom::End(0))?
+        };
+        let block_data = encrypt_table(
+            self.block_table.entries().iter().flat_map(|entry| {
+                [
+                    entry.file_pos,
+                    entry.compressed_size,
+                    entry.file_size,
+                    entry.flags,
+                ]
+            }),
+            table_key(block_key, "(block table)"),
+        );
+        self.file.seek(SeekFrom::Start(block_table_pos))?;
+        self.file.write_all(&block_data)?;
+
+        // Hi-block table: in place if it existed before with the same size
+        let existing_hi_pos = header
+            .hi_block_table_pos
+            .filter(|&pos| pos != 0 && block_count == header.block_table_size);
+        let hi_block_table = match &self.hi_block_table {
+            Some(table) if table.is_needed() => {
+                let pos = match existing_hi_pos {
+                    Some(pos) => self.file.seek(SeekFrom::Start(archive_offset + pos))?,
+                    None => self.file.seek(SeekFrom::End(0))?,
+                };
+                let data: Vec<u8> = table
+                    .entries()
+                    .iter()
+                    .flat_map(|high| high.to_le_bytes())
+                    .collect();
+                self.file.write_all(&data)?;
+                Some((pos, data))
+            }
+            _ => None,
+        };
+
+        // HET and BET tables: rebuilt from scratch at the end
+        let het_bet = match &self.ext_tables {
+            Some(ext) => {
+                let files: Vec<BetFileInfo> = self
+                    .block_table
+                    .entries()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, entry)| {
+                        let high = self
+                            .hi_block_table
+                            .as_ref()
+                            .map_or(0, |table| table.get_file_pos_high(index));
+                        let (file_size, compressed_size) = ext
+                            .large_sizes
+                            .get(&index)
+                            .copied()
+                            .unwrap_or((entry.file_size as u64, entry.compressed_size as u64));
+                        BetFileInfo {
+                            file_pos: (high << 32) | entry.file_pos as u64,
+                            file_size,
+                            compressed_size,
+                            flags: entry.flags,
+                        }
+                    })
+                    .collect();
+                let (het_data, _) = encode_het_table(&ext.hashes, ext.bits)?;
+                let (bet_data, _) = encode_bet_table(&files, &ext.hashes, ext.bits)?;
+                let het = encode_ext_table(&het_data, None, "(hash table)")?;
+                let bet = encode_ext_table(&bet_data, None, "(block table)")?;
+                let het_pos = self.file.seek(SeekFrom::End(0))?;
+                self.file.write_all(&het)?;
+                let bet_pos = self.file.seek(SeekFrom::End(0))?;
+                self.file.write_all(&bet)?;
+                Some(((het_pos, het), (bet_pos, bet)))
+            }
+            None => None,
+        };
+
+        let archive_end = self.file.seek(SeekFrom::End(0))?;
+        let archive_size_64 = archive_end - archive_offset;
+        let archive_size = if header.format_version >= FormatVersion::V3 {
+            archive_size_64.min(u32::MAX as u64) as u32
+        } else {
+            u32::try_from(archive_size_64)
+                .map_err(|_| Error::CapacityExceeded("archive larger than 4 GiB".to_string()))?
+        };
+        let relative_block_pos = block_table_pos - archive_offset;
+
+        let mut updated = header.clone();
+        updated.archive_size = archive_size;
+        updated.block_table_pos = relative_block_pos as u32;
+        updated.block_table_size = block_count;
+        if header.format_version >= FormatVersion::V2 {
+            updated.hi_block_table_pos = Some(
+                hi_block_table
+                    .as_ref()
+                    .map_or(0, |(pos, _)| pos - archive_offset),
+            );
+            updated.block_table_pos_hi = Some((relative_block_pos >> 32) as u16);
+        }
+        if header.format_version >= FormatVersion::V3 {
+            updated.archive_size_64 = Some(archive_size_64);
+            if let Some(((het_pos, _), (bet_pos, _))) = &het_bet {
+                updated.het_table_pos = Some(het_pos - archive_offset);
+                updated.bet_table_pos = Some(bet_pos - archive_offset);
+            }
+        }
+        if let Some(v4) = &mut updated.v4_data {
+            v4.block_table_size_64 = block_data.len() as u64;
+            v4.md5_hash_table = md5(&hash_data);
+            v4.md5_block_table = md5(&block_data);
+            (v4.hi_block_table_size_64, v4.md5_hi_block_table) = match &hi_block_table {
+                Some((_, data)) => (data.len() as u64, md5(data)),
+                None => (0, [0; 16]),
+            };
+            if let Some(((_, het), (_, bet))) = &het_bet {
+                v4.het_table_size_64 = het.len() as u64;
+                v4.md5_het_table = md5(het);
+                v4.bet_table_size_64 = bet.len() as u64;
+                v4.md5_bet_table = md5(bet);
+            }
+        }
+        if updated.v4_data.is_some() {
+            let header_md5 = mpq_header::header_md5(&mpq_header::encode_header(&updated));
+            if let Some(v4) = &mut updated.v4_data {
+                v4.md5_mpq_header = header_md5;
+            }
+        }
+        self.file.seek(SeekFrom::Start(archive_offset))?;
+        mpq_header::write_header(&updated, &mut self.file)?;
+        self.file.flush()?;
+
+        match &self.signature_policy {
+            SignaturePolicy::Strip => {}
+            SignaturePolicy::Preserve => {
+                if let Some(strong) = &self.strong_signature {
+                    self.file.seek(SeekFrom::End(0))?;
+                    self.file.write_all(strong)?;
+                }
+            }
+            SignaturePolicy::Resign(key) => {
+                sign_archive(&self.path, key, self.archive.name_hashing())?
+            }
+        }
+
+        // Re-read so later reads see the new contents
+        self.archive = Archive::open_with_options(&self.path, self.options.clone())?;
+        self.strong_signature = self.archive.strong_signature_data()?;
+        self.attributes.clear();
+        self.appended = false;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// The `(listfile)` names the next flush writes, loaded on first use
+    ///
+    /// `None` if the archive has no listfile and none is pending.
+    fn pending_listfile(&mut self) -> Result<Option<&mut Vec<String>>> {
+        if self.listfile.is_none()
+            && self.contains("(listfile)")
+            && self.archive.find_file("(listfile)")?.is_some()
+        {
+            self.listfile = Some(parse_listfile(&self.archive.read_file("(listfile)")?)?);
+        }
+        Ok(self.listfile.as_mut())
+    }
+
+    /// Whether `name` is in the archive in any locale, counting unflushed
+    /// changes
+    fn contains(&self, name: &str) -> bool {
+        self.hash_table
+            .find_file_with(name, 0, self.archive.name_hashing())
+            .is_some()
+    }
+
+    /// Delete the versions of `name` stored for `locale`, or all of them
+    fn remove_versions(&mut self, name: &str, locale: Option<Locale>) -> Result<()> {
+        let policy = self.archive.name_hashing();
+        let indices: Vec<usize> = self
+            .hash_table
+            .find_all_with(name, policy)
+            .into_iter()
+            .filter(|(_, entry)| locale.is_none_or(|locale| entry.locale == u16::from(locale)))
+            .map(|(index, _)| index)
+            .collect();
+        if indices.is_empty() {
+            return Err(Error::FileNotFound(name.to_string()));
+        }
+        for index in indices {
+            self.delete_file(index)?;
+        }
+
+        let normalized = policy.normalize_name(name);
+        if normalized == policy.normalize_name("(listfile)") {
+            self.listfile = None;
+        } else if !self.contains(name) {
+            if let Some(names) = self.pending_listfile()? {
+                names.retain(|listed| policy.normalize_name(listed) != normalized);
+            }
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Bring `(attributes)` in line with the files changed since the last
+    /// flush
+    ///
+    /// Entries of other files are kept as they are, and the file's own
+    /// entry is left empty. Archives without `(attributes)` do not get one.
+    fn update_attributes(&mut self) -> Result<()> {
+        let changed = std::mem::take(&mut self.attributes);
+        let policy = self.archive.name_hashing();
+        let Some((_, own)) = self.hash_table.find_file_with("(attributes)", 0, policy) else {
+            return Ok(());
+        };
+        let own = own.block_index as usize;
+        if changed.is_empty() || self.archive.find_file("(attributes)")?.is_none() {
+            return Ok(());
+        }
+
+        let mut attributes = match self.archive.load_attributes() {
+            Ok(()) => match self.archive.attributes() {
+                Some(attributes) => attributes.clone(),
+                None => return Ok(()),
+            },
+            Err(e) => {
+                log::warn!("Leaving unreadable (attributes) as it is: {}", e);
+                return Ok(());
+            }
+        };
+        let entries = &mut attributes.file_attributes;
+        entries.resize(self.block_table.size(), FileAttributes::new());
+        for (index, entry) in changed {
+            if let Some(slot) = entries.get_mut(index) {
+                *slot = entry;
+            }
+        }
+        if let Some(slot) = entries.get_mut(own) {
+            *slot = FileAttributes::new();
+        }
+
+        let data = attributes.to_bytes()?;
+        self.replace_file_data("(attributes)", &data)
+    }
+
+    /// Remove or reserve the `(signature)` file as the signature policy says
+    fn prepare_signature(&mut self) -> Result<()> {
+        let existing = self
+            .hash_table
+            .find_file_with("(signature)", 0, self.archive.name_hashing())
+            .map(|(index, _)| index);
+        match &self.signature_policy {
+            SignaturePolicy::Strip => {
+                if let Some(index) = existing {
+                    self.delete_file(index)?;
+                }
+            }
+            SignaturePolicy::Preserve => {}
+            SignaturePolicy::Resign(key) => {
+                if SignatureType::for_key(key)? == SignatureType::Weak {
+                    let reusable = self
+                        .archive
+                        .find_file("(signature)")?
+                        .is_some_and(|info| is_signature_slot(info.flags, info.compressed_size));
+                    if !reusable {
+                        let slot = EncodedFile {
+                            data: vec![0; WEAK_SIGNATURE_FILE_SIZE],
+                            compressed_size: WEAK_SIGNATURE_FILE_SIZE as u64,
+                            flags: BlockEntry::FLAG_EXISTS,
+                        };
+                        self.append_file_data(
+                            "(signature)",
+                            Locale::NEUTRAL,
+                            &slot,
+                            WEAK_SIGNATURE_FILE_SIZE as u32,
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark the file at hash table slot `index` and its block entry deleted
+    fn delete_file(&mut self, index: usize) -> Result<()> {
+        let entry = self
+            .hash_table
+            .get_mut(index)
+            .ok_or_else(|| Error::hash_table("Hash table index out of bounds"))?;
+        let block_index = entry.block_index as usize;
+        *entry = HashEntry {
+            name_1: HashEntry::EMPTY_NEVER_USED,
+            name_2: HashEntry::EMPTY_NEVER_USED,
+            locale: 0xFFFF,
+            platform: 0xFFFF,
+            block_index: HashEntry::EMPTY_DELETED,
+        };
+        self.attributes.insert(block_index, FileAttributes::new());
+
+        if let Some(block) = self.block_table.get_mut(block_index) {
+            *block = BlockEntry {
+                file_pos: 0,
+                compressed_size: 0,
+                file_size: 0,
+                flags: 0,
+            };
+        }
+        if let Some(ext) = &mut self.ext_tables {
+            if let Some(slot) = ext.hashes.get_mut(block_index) {
+                *slot = None;
+            }
+            ext.large_sizes.remove(&block_index);
+        }
+        Ok(())
+    }
+
+    /// Append new data for `name` and point its block entry at it
+    ///
+    /// The data is stored zlib-compressed when that makes it smaller, and
+    /// unencrypted. A block entry and hash table slot are created if the
+    /// file does not exist yet.
+    fn replace_file_data(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        self.store_file(name, data, &AddFileOptions::new())
+    }
+
+    /// Encode and append `data` as the file `name`, recording its
+    /// `(attributes)` entry
+    fn store_file(&mut self, name: &str, data: &[u8], options: &AddFileOptions) -> Result<()> {
+        let file_size = u32::try_from(data.len())
+            .map_err(|_| Error::CapacityExceeded(format!("{} is larger than 4 GiB", name)))?;
+        let file_pos = self.data_end()?;
+        let block_size = self.archive.header().block_size;
+        let encoded = encode_file(
+            data,
+            name,
+            options,
+            block_size,
+            file_pos,
+            &mut self.observer,
+        )?;
+        let block_index = self.append_file_data(name, options.locale, &encoded, file_size)?;
+        self.attributes.insert(block_index, file_attributes(data));
+        Ok(())
+    }
+
+    /// Where appended data starts, relative to the archive
+    ///
+    /// A strong signature after the archive is cut off before the first
+    /// append, since it would otherwise end up inside the archive;
+    /// [`flush`](Self::flush) puts it back if the policy says so.
+    fn data_end(&mut self) -> Result<u64> {
+        let archive_offset = self.archive.archive_offset();
+        if !self.appended && self.strong_signature.is_some() {
+            self.file
+                .set_len(archive_offset + self.archive.header().get_archive_size())?;
+        }
+        self.appended = true;
+        Ok(self.file.seek(SeekFrom::End(0))? - archive_offset)
+    }
+
+    /// Append `file` as the data of `name` in `locale`, a file of
+    /// `file_size` bytes, and return its block index
+    ///
+    /// The block entry of the file's version in `locale` is reused if there
+    /// is one.
+    fn append_file_data(
+        &mut self,
+        name: &str,
+        locale: Locale,
+        file: &EncodedFile,
+        file_size: u32,
+    ) -> Result<usize> {
+        let pos = self.data_end()?;
+        let high = (pos >> 32) as u16;
+        if high != 0 && self.hi_block_table.is_none() {
+            if self.archive.header().format_version < FormatVersion::V2 {
+                return Err(Error::CapacityExceeded(
+                    "file data would start beyond 4 GiB in a v1 archive".to_string(),
+                ));
+            }
+            self.hi_block_table = Some(HiBlockTable::new(self.block_table.size()));
+        }
+        let compressed_size = u32::try_from(file.compressed_size).map_err(|_| {
+            Error::CapacityExceeded(format!("{} is stored in more than 4 GiB", name))
+        })?;
+        self.file.write_all(&file.data)?;
+
+        let existing = self
+            .hash_table
+            .find_all_with(name, self.archive.name_hashing())
+            .into_iter()
+            .find(|(_, entry)| entry.locale == u16::from(locale))
+            .map(|(_, entry)| entry.block_index as usize);
+        let block_index = match existing {
+            Some(block_index) => block_index,
+            None => self.add_block(name, locale)?,
+        };
+
+        let entry = self
+            .block_table
+            .get_mut(block_index)
+            .ok_or_else(|| Error::block_table("Block index out of bounds"))?;
+        *entry = BlockEntry {
+            file_pos: pos as u32,
+            compressed_size,
+            file_size,
+            flags: file.flags,
+        };
+        if let Some(hi_table) = &mut self.hi_block_table {
+            hi_table.set(block_index, high);
+        }
+        if let Some(ext) = &mut self.ext_tables {
+            ext.large_sizes.remove(&block_index);
+        }
+
+        self.dirty = true;
+        Ok(block_index)
+    }
+
+    /// Create a block entry and hash table slot for a new file
+    fn add_block(&mut self, name: &str, locale: Locale) -> Result<usize> {
+        let block_index = self.block_table.size();
+        let table_size = self.hash_table.size() as u32;
+        let name_hashing = self.archive.name_hashing();
+        let mut index =
+            hash_string_with(name, hash_type::TABLE_OFFSET, name_hashing) & (table_size - 1);
+
+        // Linear probing from the home slot; deleted slots can be reused
+        let mut probes = 0;
+        loop {
+            let entry = self
+                .hash_table
+                .get_mut(index as usize)
+                .ok_or_else(|| Error::hash_table("Hash table index out of bounds"))?;
+            if !entry.is_valid() {
+                *entry = HashEntry {
+                    name_1: hash_string_with(name, hash_type::NAME_A, name_hashing),
+                    name_2: hash_string_with(name, hash_type::NAME_B, name_hashing),
+                    locale: locale.into(),
+                    platform: 0,
+                    block_index: block_index as u32,
+                };
+                break;
+            }
+            probes += 1;
+            if probes == table_size {
+                return Err(Error::CapacityExceeded(format!(
+                    "no free hash table slot for {}",
+                    name
+                )));
+            }
+            index = (index + 1) & (table_size - 1);
+        }
+
+        self.block_table = Self::copy_block_table(&self.block_table, block_index + 1)?;
+        if let Some(ext) = &mut self.ext_tables {
+            ext.hashes.resize(block_index, None);
+            ext.hashes
+                .push(Some(name_hash(name, ext.bits, name_hashing)));
+        }
+        if let Some(hi_table) = &self.hi_block_table {
+            let mut grown = HiBlockTable::new(block_index + 1);
+            for (index, &high) in hi_table.entries().iter().enumerate() {
+                grown.set(index, high);
+            }
+            self.hi_block_table = Some(grown);
+        }
+
+        Ok(block_index)
+    }
+
+    /// Copy a block table into a new one of `size` entries
+    fn copy_block_table(source: &BlockTable, size: usize) -> Result<BlockTable> {
+        let mut table = BlockTable::new_mut(size)?;
+        let count = source.size().min(size);
+        table.entries_mut()[..count].copy_from_slice(&source.entries()[..count]);
+        Ok(table)
+    }
+}
+
+/// Sign the archive at `path` in place with `key`
+///
+/// A weak signature is written into the archive's `(signature)` file, which
+/// has to be stored raw with room for it. A strong signature replaces
+/// anything that follows the archive.
+///
+/// # Errors
+/// - `Error::FileNotFound` if a weak key is used and there is no
+///   `(signature)` file
+/// - `Error::Crypto` if the key has the wrong size for MPQ signatures or
+///   `(signature)` has no room for a weak signature
+pub(crate) fn sign_archive(
+    path: &Path,
+    key: &RsaPrivateKey,
+    name_hashing: NameHashingPolicy,
+) -> Result<()> {
+    let archive = Archive::open_with_options(path, OpenOptions::new().name_hashing(name_hashing))?;
+    let archive_offset = archive.archive_offset();
+    let archive_size = archive.header().get_archive_size();
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    match SignatureType::for_key(key)? {
+        SignatureType::Weak => {
+            let info = archive
+                .find_file("(signature)")?
+                .ok_or_else(|| Error::FileNotFound("(signature)".to_string()))?;
+            if !is_signature_slot(info.flags, info.compressed_size) {
+                return Err(Error::crypto(
+                    "(signature) is not stored raw with room for a weak signature",
+                ));
+            }
+            let signature_info = SignatureInfo::new_weak(
+                archive_offset,
+                archive_size,
+                info.file_pos,
+                info.compressed_size,
+                Vec::new(),
+            );
+            let signature = sign_weak_signature(&mut file, &signature_info, key)?;
+            // The signature follows an 8 byte header of the file
+            file.seek(SeekFrom::Start(info.file_pos + 8))?;
+            file.write_all(&signature)?;
+        }
+        SignatureType::Strong => {
+            file.seek(SeekFrom::Start(archive_offset))?;
+            let signature = sign_strong_signature(&mut file, archive_size, key)?;
+            let archive_end = archive_offset + archive_size;
+            file.set_len(archive_end)?;
+            file.seek(SeekFrom::Start(archive_end))?;
+            file.write_all(&signature)?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Whether a `(signature)` file with these block flags and stored size can
+/// hold a weak signature
+fn is_signature_slot(flags: u32, compressed_size: u64) -> bool {
+    let transformed =
+        BlockEntry::FLAG_COMPRESS | BlockEntry::FLAG_IMPLODE | BlockEntry::FLAG_ENCRYPTED;
+    flags & transformed == 0 && compressed_size >= WEAK_SIGNATURE_FILE_SIZE as u64
+}
+
+/// `(attributes)` entry of a file holding `data`, stamped with the current
+/// time
+fn file_attributes(data: &[u8]) -> FileAttributes {
+    // FILETIME counts 100 ns intervals since 1601-01-01
+    const UNIX_EPOCH_FILETIME: u64 = 116_444_736_000_000_000;
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    FileAttributes {
+        crc32: Some(crc32fast::hash(data)),
+        filetime: Some(UNIX_EPOCH_FILETIME + (since_epoch.as_nanos() / 100) as u64),
+        md5: Some(md5(data)),
+        is_patch: Some(false),
+    }
+}
+
+/// Key a table is written back with
+///
+/// Tables read with a [`TableKey::Fixed`] key keep it, so the archive still
+/// opens with the options it was opened with; all others get the standard
+/// key derived from `key_name`, which [`TableKey::Recover`] tries first.
+fn table_key(key: TableKey, key_name: &str) -> u32 {
+    match key {
+        TableKey::Fixed(key) => key,
+        TableKey::Standard | TableKey::Recover => hash_string(key_name, hash_type::FILE_KEY),
+    }
+}
+
+/// Encrypt table words with `key`
+fn encrypt_table(words: impl Iterator<Item = u32>, key: u32) -> Vec<u8> {
+    let mut words: Vec<u32> = words.collect();
+    encrypt_block(&mut words, key);
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
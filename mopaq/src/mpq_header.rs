@@ -0,0 +1,758 @@
+//! Byte-level codec for the MPQ header and user data header
+//!
+//! The field layout of every header version is written down once, in
+//! `map_fields`, which walks the fields of an [`MpqHeader`] in file order
+//! and hands each one to a visitor. Reading, writing and [`layout`] are
+//! visitors over that single description, so the read and write paths
+//! cannot disagree about where a field lives.
+//!
+//! Fields of later versions are only present in headers of those versions.
+//! When writing, a missing optional field of the header's version is written
+//! as zero.
+
+use crate::header::{
+    FormatVersion, MpqHeader, MpqHeaderV4Data, UserDataHeader, MPQ_HEADER_SIGNATURE,
+    MPQ_USERDATA_SIGNATURE,
+};
+use crate::{Error, Result};
+use std::io::{Read, Write};
+
+/// Offset of the header MD5 in a v4 header, which covers everything before it
+pub const MD5_MPQ_HEADER_OFFSET: usize = 0xC0;
+
+/// Position and size of one header field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderField {
+    /// Field name, matching the [`MpqHeader`] or [`MpqHeaderV4Data`] member
+    pub name: &'static str,
+    /// Byte offset from the start of the header
+    pub offset: usize,
+    /// Size in bytes
+    pub size: usize,
+}
+
+/// Receives the header fields in file order
+trait FieldVisitor {
+    fn u16(&mut self, name: &'static str, value: &mut u16) -> Result<()>;
+    fn u32(&mut self, name: &'static str, value: &mut u32) -> Result<()>;
+    fn u64(&mut self, name: &'static str, value: &mut u64) -> Result<()>;
+    fn md5(&mut self, name: &'static str, value: &mut [u8; 16]) -> Result<()>;
+}
+
+/// Walk the fields of `header` in file order
+///
+/// The format version is visited before the fields that depend on it, so a
+/// reading visitor sees the right set of fields.
+fn map_fields<V: FieldVisitor>(header: &mut MpqHeader, visitor: &mut V) -> Result<()> {
+    let mut signature = MPQ_HEADER_SIGNATURE;
+    visitor.u32("signature", &mut signature)?;
+    if signature != MPQ_HEADER_SIGNATURE {
+        return Err(Error::invalid_format("Invalid MPQ header signature"));
+    }
+    visitor.u32("header_size", &mut header.header_size)?;
+    visitor.u32("archive_size", &mut header.archive_size)?;
+    let mut version = header.format_version as u16;
+    visitor.u16("format_version", &mut version)?;
+    header.format_version =
+        FormatVersion::from_raw(version).ok_or(Error::UnsupportedVersion(version))?;
+    visitor.u16("block_size", &mut header.block_size)?;
+    visitor.u32("hash_table_pos", &mut header.hash_table_pos)?;
+    visitor.u32("block_table_pos", &mut header.block_table_pos)?;
+    visitor.u32("hash_table_size", &mut header.hash_table_size)?;
+    visitor.u32("block_table_size", &mut header.block_table_size)?;
+
+    if header.format_version >= FormatVersion::V2 {
+        visitor.u64(
+            "hi_block_table_pos",
+            header.hi_block_table_pos.get_or_insert(0),
+        )?;
+        visitor.u16(
+            "hash_table_pos_hi",
+            header.hash_table_pos_hi.get_or_insert(0),
+        )?;
+        visitor.u16(
+            "block_table_pos_hi",
+            header.block_table_pos_hi.get_or_insert(0),
+        )?;
+    }
+
+    if header.format_version >= FormatVersion::V3 {
+        visitor.u64("archive_size_64", header.archive_size_64.get_or_insert(0))?;
+        visitor.u64("bet_table_pos", header.bet_table_pos.get_or_insert(0))?;
+        visitor.u64("het_table_pos", header.het_table_pos.get_or_insert(0))?;
+    }
+
+    if header.format_version >= FormatVersion::V4 {
+        let v4 = header.v4_data.get_or_insert_with(MpqHeaderV4Data::default);
+        visitor.u64("hash_table_size_64", &mut v4.hash_table_size_64)?;
+        visitor.u64("block_table_size_64", &mut v4.block_table_size_64)?;
+        visitor.u64("hi_block_table_size_64", &mut v4.hi_block_table_size_64)?;
+        visitor.u64("het_table_size_64", &mut v4.het_table_size_64)?;
+        visitor.u64("bet_table_size_64", &mut v4.bet_table_size_64)?;
+        visitor.u32("raw_chunk_size", &mut v4.raw_chunk_size)?;
+        visitor.md5("md5_block_table", &mut v4.md5_block_table)?;
+        visitor.md5("md5_hash_table", &mut v4.md5_hash_table)?;
+        visitor.md5("md5_hi_block_table", &mut v4.md5_hi_block_table)?;
+        visitor.md5("md5_bet_table", &mut v4.md5_bet_table)?;
+        visitor.md5("md5_het_table", &mut v4.md5_het_table)?;
+        visitor.md5("md5_mpq_header", &mut v4.md5_mpq_header)?;
+    }
+
+    Ok(())
+}
+
+/// Fills fields from a reader
+struct Decoder<'a, R: Read>(&'a mut R);
+
+impl<R: Read> Decoder<'_, R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.0.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl<R: Read> FieldVisitor for Decoder<'_, R> {
+    fn u16(&mut self, _: &'static str, value: &mut u16) -> Result<()> {
+        *value = u16::from_le_bytes(self.bytes()?);
+        Ok(())
+    }
+
+    fn u32(&mut self, _: &'static str, value: &mut u32) -> Result<()> {
+        *value = u32::from_le_bytes(self.bytes()?);
+        Ok(())
+    }
+
+    fn u64(&mut self, _: &'static str, value: &mut u64) -> Result<()> {
+        *value = u64::from_le_bytes(self.bytes()?);
+        Ok(())
+    }
+
+    fn md5(&mut self, _: &'static str, value: &mut [u8; 16]) -> Result<()> {
+        *value = self.bytes()?;
+        Ok(())
+    }
+}
+
+/// Writes fields to a writer
+struct Encoder<'a, W: Write>(&'a mut W);
+
+impl<W: Write> FieldVisitor for Encoder<'_, W> {
+    fn u16(&mut self, _: &'static str, value: &mut u16) -> Result<()> {
+        self.0.write_all(&value.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn u32(&mut self, _: &'static str, value: &mut u32) -> Result<()> {
+        self.0.write_all(&value.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn u64(&mut self, _: &'static str, value: &mut u64) -> Result<()> {
+        self.0.write_all(&value.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn md5(&mut self, _: &'static str, value: &mut [u8; 16]) -> Result<()> {
+        self.0.write_all(value)?;
+        Ok(())
+    }
+}
+
+/// Records the position of every field
+#[derive(Default)]
+struct LayoutRecorder(Vec<HeaderField>);
+
+impl LayoutRecorder {
+    fn push(&mut self, name: &'static str, size: usize) -> Result<()> {
+        let offset = self.0.last().map_or(0, |field| field.offset + field.size);
+        self.0.push(HeaderField { name, offset, size });
+        Ok(())
+    }
+}
+
+impl FieldVisitor for LayoutRecorder {
+    fn u16(&mut self, name: &'static str, _: &mut u16) -> Result<()> {
+        self.push(name, 2)
+    }
+
+    fn u32(&mut self, name: &'static str, _: &mut u32) -> Result<()> {
+        self.push(name, 4)
+    }
+
+    fn u64(&mut self, name: &'static str, _: &mut u64) -> Result<()> {
+        self.push(name, 8)
+    }
+
+    fn md5(&mut self, name: &'static str, _: &mut [u8; 16]) -> Result<()> {
+        self.push(name, 16)
+    }
+}
+
+/// A header of `version` with every field zero or absent
+fn blank_header(version: FormatVersion) -> MpqHeader {
+    MpqHeader {
+        header_size: version.header_size(),
+        archive_size: 0,
+        format_version: version,
+        block_size: 0,
+        hash_table_pos: 0,
+        block_table_pos: 0,
+        hash_table_size: 0,
+        block_table_size: 0,
+        hi_block_table_pos: None,
+        hash_table_pos_hi: None,
+        block_table_pos_hi: None,
+        archive_size_64: None,
+        bet_table_pos: None,
+        het_table_pos: None,
+        v4_data: None,
+    }
+}
+
+/// The fields of a header of `version`, in file order
+pub fn layout(version: FormatVersion) -> Vec<HeaderField> {
+    let mut header = blank_header(version);
+    let mut recorder = LayoutRecorder::default();
+    map_fields(&mut header, &mut recorder).expect("recording the layout cannot fail");
+    recorder.0
+}
+
+/// The rule [`read_header_reconciled`] used to pick a header's version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HeaderRule {
+    /// `header_size` is too small for the declared version, so the header
+    /// was read as the largest version that fits, or v1
+    SizeTooSmall,
+    /// `header_size` is larger than the declared version needs and the
+    /// extra bytes were ignored
+    ExtraBytesIgnored,
+    /// `header_size` is that of a later version whose table positions lie
+    /// within the archive, so the header was read as that version
+    UpgradedBySize,
+    /// The declared version is unknown, so the header was read as the
+    /// version `header_size` names, or v1
+    UnknownVersion,
+}
+
+impl std::fmt::Display for HeaderRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HeaderRule::SizeTooSmall => "header size too small for the version",
+            HeaderRule::ExtraBytesIgnored => "extra header bytes ignored",
+            HeaderRule::UpgradedBySize => "version taken from the header size",
+            HeaderRule::UnknownVersion => "unknown version",
+        })
+    }
+}
+
+/// A header whose declared version and `header_size` disagree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeaderReconciliation {
+    /// Raw format version in the header, 0 for v1
+    pub declared_version: u16,
+    /// `header_size` in the header
+    pub declared_size: u32,
+    /// Version the header was read as
+    pub format_version: FormatVersion,
+    /// Why that version was picked
+    pub rule: HeaderRule,
+}
+
+impl std::fmt::Display for HeaderReconciliation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "raw version {} with a {}-byte header read as v{} ({})",
+            self.declared_version,
+            self.declared_size,
+            self.format_version as u16 + 1,
+            self.rule
+        )
+    }
+}
+
+const VERSIONS: [FormatVersion; 4] = [
+    FormatVersion::V1,
+    FormatVersion::V2,
+    FormatVersion::V3,
+    FormatVersion::V4,
+];
+
+/// Decode `bytes` as a header of `version`, whatever version they declare
+fn decode_as(bytes: &[u8], version: FormatVersion) -> Result<MpqHeader> {
+    let mut bytes = bytes.to_vec();
+    bytes[0x0C..0x0E].copy_from_slice(&(version as u16).to_le_bytes());
+    let mut header = blank_header(FormatVersion::V1);
+    map_fields(&mut header, &mut Decoder(&mut bytes.as_slice()))?;
+    Ok(header)
+}
+
+/// Whether the tables of `header` start after it and within the archive
+fn tables_in_range(header: &MpqHeader) -> bool {
+    let start = header.format_version.header_size() as u64;
+    let end = match header.archive_size_64 {
+        Some(size) if size as u32 == header.archive_size => size,
+        Some(_) => return false,
+        None => header.archive_size as u64,
+    };
+    let in_range = |pos: u64| (start..=end).contains(&pos);
+    let optional = |pos: Option<u64>| pos.is_none_or(|pos| pos == 0 || in_range(pos));
+    (header.hash_table_size == 0 || in_range(header.get_hash_table_pos()))
+        && (header.block_table_size == 0 || in_range(header.get_block_table_pos()))
+        && optional(header.hi_block_table_pos)
+        && optional(header.het_table_pos)
+        && optional(header.bet_table_pos)
+}
+
+/// Read an MPQ header, starting at its signature
+///
+/// Only the fields of the header's version are read, even if its
+/// `header_size` declares more bytes. A header whose version and size
+/// disagree is read as [`read_header_reconciled`] describes.
+///
+/// # Errors
+/// - `Error::InvalidFormat` for a wrong signature or a block size above
+///   [`MAX_BLOCK_SIZE`](crate::MAX_BLOCK_SIZE)
+/// - `Error::Io` if the header is cut short
+pub fn read_header<R: Read>(reader: &mut R) -> Result<MpqHeader> {
+    read_header_reconciled(reader).map(|(header, _)| header)
+}
+
+/// Read an MPQ header, reporting how a version and size mismatch was settled
+///
+/// Map protectors and some old tools write headers whose `format_version`
+/// and `header_size` disagree. Like StormLib, these are not rejected:
+/// - a `header_size` too small for the version selects the largest version
+///   that fits, or v1
+/// - a larger `header_size` that is exactly that of a later version selects
+///   it if its table positions lie between the header and the archive end;
+///   otherwise the extra bytes are ignored
+/// - an unknown version selects the version `header_size` names, checked the
+///   same way, or v1
+///
+/// The header is then read as the selected version and its `header_size`
+/// set to match. At most as many bytes as the largest header are consumed.
+///
+/// # Errors
+/// As [`read_header`].
+pub fn read_header_reconciled<R: Read>(
+    reader: &mut R,
+) -> Result<(MpqHeader, Option<HeaderReconciliation>)> {
+    let mut bytes = vec![0u8; FormatVersion::V1.header_size() as usize];
+    reader.read_exact(&mut bytes)?;
+    let declared_size = u32::from_le_bytes(bytes[0x04..0x08].try_into().unwrap());
+    let declared_version = u16::from_le_bytes(bytes[0x0C..0x0E].try_into().unwrap());
+    let wanted = declared_size.clamp(
+        FormatVersion::V1.header_size(),
+        FormatVersion::V4.header_size(),
+    );
+    reader
+        .take((wanted as usize - bytes.len()) as u64)
+        .read_to_end(&mut bytes)?;
+
+    let fitting = VERSIONS
+        .into_iter()
+        .rev()
+        .find(|version| version.header_size() <= declared_size)
+        .unwrap_or(FormatVersion::V1);
+    let named_by_size = VERSIONS
+        .into_iter()
+        .find(|version| version.header_size() == declared_size)
+        .filter(|&version| decode_as(&bytes, version).is_ok_and(|h| tables_in_range(&h)));
+
+    let (version, rule) = match FormatVersion::from_raw(declared_version) {
+        Some(version) if version.header_size() == declared_size => (version, None),
+        Some(version) if version.header_size() > declared_size => {
+            (fitting, Some(HeaderRule::SizeTooSmall))
+        }
+        Some(version) => match named_by_size.filter(|&by_size| by_size > version) {
+            Some(by_size) => (by_size, Some(HeaderRule::UpgradedBySize)),
+            None => (version, Some(HeaderRule::ExtraBytesIgnored)),
+        },
+        None => (
+            named_by_size.unwrap_or(FormatVersion::V1),
+            Some(HeaderRule::UnknownVersion),
+        ),
+    };
+
+    let mut header = decode_as(&bytes, version)?;
+    let reconciliation = rule.map(|rule| {
+        header.header_size = version.header_size();
+        HeaderReconciliation {
+            declared_version,
+            declared_size,
+            format_version: version,
+            rule,
+        }
+    });
+
+    if header.block_size > crate::MAX_BLOCK_SIZE {
+        return Err(Error::invalid_format(format!(
+            "Block size {} exceeds {}",
+            header.block_size,
+            crate::MAX_BLOCK_SIZE
+        )));
+    }
+
+    Ok((header, reconciliation))
+}
+
+/// Write the fields of `header` for its format version
+///
+/// Writes exactly `header.format_version.header_size()` bytes.
+pub fn write_header<W: Write>(header: &MpqHeader, writer: &mut W) -> Result<()> {
+    map_fields(&mut header.clone(), &mut Encoder(writer))
+}
+
+/// Encode `header` into a new buffer
+pub fn encode_header(header: &MpqHeader) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(header.format_version.header_size() as usize);
+    write_header(header, &mut bytes).expect("writing to a Vec cannot fail");
+    bytes
+}
+
+/// MD5 of a v4 header's bytes before its `md5_mpq_header` field
+pub fn header_md5(header_bytes: &[u8]) -> [u8; 16] {
+    let covered = &header_bytes[..MD5_MPQ_HEADER_OFFSET.min(header_bytes.len())];
+    crate::checksum::md5(covered)
+}
+
+/// Read a user data header, starting at its signature
+///
+/// # Errors
+/// - `Error::InvalidFormat` for a wrong signature
+/// - `Error::Io` if the header is cut short
+pub fn read_user_data_header<R: Read>(reader: &mut R) -> Result<UserDataHeader> {
+    let mut fields = [0u8; UserDataHeader::SIZE as usize];
+    reader.read_exact(&mut fields)?;
+    let field = |index: usize| {
+        u32::from_le_bytes(
+            fields[index * 4..index * 4 + 4]
+                .try_into()
+                .expect("4 bytes"),
+        )
+    };
+
+    if field(0) != MPQ_USERDATA_SIGNATURE {
+        return Err(Error::invalid_format("Invalid MPQ user data signature"));
+    }
+    Ok(UserDataHeader {
+        user_data_size: field(1),
+        header_offset: field(2),
+        user_data_header_size: field(3),
+    })
+}
+
+/// Write a user data header, including its signature
+pub fn write_user_data_header<W: Write>(header: &UserDataHeader, writer: &mut W) -> Result<()> {
+    for value in [
+        MPQ_USERDATA_SIGNATURE,
+        header.user_data_size,
+        header.header_offset,
+        header.user_data_header_size,
+    ] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Little-endian bytes of the given values, concatenated
+    macro_rules! le_bytes {
+        ($($value:expr),* $(,)?) => {{
+            let mut bytes: Vec<u8> = Vec::new();
+            $(bytes.extend_from_slice(&$value.to_le_bytes());)*
+            bytes
+        }};
+    }
+
+    fn v1_fixture() -> Vec<u8> {
+        le_bytes![
+            0x1A51_504Du32, // 'MPQ\x1A'
+            0x20u32,        // header size
+            0x1234u32,      // archive size
+            0u16,           // format version
+            3u16,           // block size
+            0x1000u32,      // hash table position
+            0x1100u32,      // block table position
+            0x10u32,        // hash table entries
+            0x4u32,         // block table entries
+        ]
+    }
+
+    fn v2_fixture() -> Vec<u8> {
+        let mut bytes = v1_fixture();
+        bytes[4..8].copy_from_slice(&0x2Cu32.to_le_bytes());
+        bytes[12..14].copy_from_slice(&1u16.to_le_bytes());
+        bytes.extend(le_bytes![0x1_0000_2000u64, 0x0001u16, 0x0002u16]);
+        bytes
+    }
+
+    fn v3_fixture() -> Vec<u8> {
+        let mut bytes = v2_fixture();
+        bytes[4..8].copy_from_slice(&0x44u32.to_le_bytes());
+        bytes[12..14].copy_from_slice(&2u16.to_le_bytes());
+        bytes.extend(le_bytes![0x2_0000_0000u64, 0x3000u64, 0x2800u64]);
+        bytes
+    }
+
+    fn v4_fixture() -> Vec<u8> {
+        let mut bytes = v3_fixture();
+        bytes[4..8].copy_from_slice(&0xD0u32.to_le_bytes());
+        bytes[12..14].copy_from_slice(&3u16.to_le_bytes());
+        bytes.extend(le_bytes![
+            0x100u64, 0x40u64, 0x8u64, 0x200u64, 0x300u64, 0x4000u32
+        ]);
+        for fill in 1..=6u8 {
+            bytes.extend([fill; 16]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_layout_matches_format_spec() {
+        let offsets = |version| {
+            layout(version)
+                .into_iter()
+                .map(|field| (field.name, field.offset))
+                .collect::<Vec<_>>()
+        };
+
+        let v4 = offsets(FormatVersion::V4);
+        for (name, offset) in [
+            ("signature", 0x00),
+            ("header_size", 0x04),
+            ("archive_size", 0x08),
+            ("format_version", 0x0C),
+            ("block_size", 0x0E),
+            ("hash_table_pos", 0x10),
+            ("block_table_pos", 0x14),
+            ("hash_table_size", 0x18),
+            ("block_table_size", 0x1C),
+            ("hi_block_table_pos", 0x20),
+            ("hash_table_pos_hi", 0x28),
+            ("block_table_pos_hi", 0x2A),
+            ("archive_size_64", 0x2C),
+            ("bet_table_pos", 0x34),
+            ("het_table_pos", 0x3C),
+            ("hash_table_size_64", 0x44),
+            ("block_table_size_64", 0x4C),
+            ("hi_block_table_size_64", 0x54),
+            ("het_table_size_64", 0x5C),
+            ("bet_table_size_64", 0x64),
+            ("raw_chunk_size", 0x6C),
+            ("md5_block_table", 0x70),
+            ("md5_hash_table", 0x80),
+            ("md5_hi_block_table", 0x90),
+            ("md5_bet_table", 0xA0),
+            ("md5_het_table", 0xB0),
+            ("md5_mpq_header", MD5_MPQ_HEADER_OFFSET),
+        ] {
+            assert!(v4.contains(&(name, offset)), "{} at 0x{:X}", name, offset);
+        }
+        assert_eq!(v4.len(), 27);
+
+        // Each version is a prefix of the next and ends at its header size
+        for version in [
+            FormatVersion::V1,
+            FormatVersion::V2,
+            FormatVersion::V3,
+            FormatVersion::V4,
+        ] {
+            let fields = layout(version);
+            assert_eq!(offsets(version)[..], v4[..fields.len()]);
+            let last = fields.last().unwrap();
+            assert_eq!(
+                (last.offset + last.size) as u32,
+                version.header_size(),
+                "{:?}",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn test_header_fixtures_round_trip() {
+        for (version, fixture) in [
+            (FormatVersion::V1, v1_fixture()),
+            (FormatVersion::V2, v2_fixture()),
+            (FormatVersion::V3, v3_fixture()),
+            (FormatVersion::V4, v4_fixture()),
+        ] {
+            assert_eq!(fixture.len() as u32, version.header_size());
+            let header = read_header(&mut fixture.as_slice()).unwrap();
+            assert_eq!(header.format_version, version);
+            assert_eq!(encode_header(&header), fixture, "{:?}", version);
+        }
+    }
+
+    #[test]
+    fn test_header_fixture_fields() {
+        let v1 = read_header(&mut v1_fixture().as_slice()).unwrap();
+        assert_eq!(v1.header_size, 0x20);
+        assert_eq!(v1.archive_size, 0x1234);
+        assert_eq!(v1.block_size, 3);
+        assert_eq!(v1.hash_table_pos, 0x1000);
+        assert_eq!(v1.block_table_pos, 0x1100);
+        assert_eq!(v1.hash_table_size, 0x10);
+        assert_eq!(v1.block_table_size, 0x4);
+        assert_eq!(v1.hi_block_table_pos, None);
+        assert_eq!(v1.archive_size_64, None);
+        assert!(v1.v4_data.is_none());
+
+        let v2 = read_header(&mut v2_fixture().as_slice()).unwrap();
+        assert_eq!(v2.hi_block_table_pos, Some(0x1_0000_2000));
+        assert_eq!(v2.get_hash_table_pos(), 0x1_0000_1000);
+        assert_eq!(v2.get_block_table_pos(), 0x2_0000_1100);
+        assert_eq!(v2.het_table_pos, None);
+
+        let v3 = read_header(&mut v3_fixture().as_slice()).unwrap();
+        assert_eq!(v3.get_archive_size(), 0x2_0000_0000);
+        assert_eq!(v3.bet_table_pos, Some(0x3000));
+        assert_eq!(v3.het_table_pos, Some(0x2800));
+        assert!(v3.v4_data.is_none());
+
+        let v4 = read_header(&mut v4_fixture().as_slice()).unwrap();
+        let data = v4.v4_data.unwrap();
+        assert_eq!(data.hash_table_size_64, 0x100);
+        assert_eq!(data.block_table_size_64, 0x40);
+        assert_eq!(data.hi_block_table_size_64, 0x8);
+        assert_eq!(data.het_table_size_64, 0x200);
+        assert_eq!(data.bet_table_size_64, 0x300);
+        assert_eq!(data.raw_chunk_size, 0x4000);
+        assert_eq!(data.md5_block_table, [1; 16]);
+        assert_eq!(data.md5_hash_table, [2; 16]);
+        assert_eq!(data.md5_hi_block_table, [3; 16]);
+        assert_eq!(data.md5_bet_table, [4; 16]);
+        assert_eq!(data.md5_het_table, [5; 16]);
+        assert_eq!(data.md5_mpq_header, [6; 16]);
+    }
+
+    #[test]
+    fn test_header_write_fills_missing_fields() {
+        // Fields a version needs but the header lacks are written as zero
+        let header = blank_header(FormatVersion::V4);
+        let bytes = encode_header(&header);
+        assert_eq!(bytes.len(), 0xD0);
+        assert!(bytes[0x20..].iter().all(|&byte| byte == 0));
+
+        // Fields of later versions are left out
+        let mut v1 = read_header(&mut v3_fixture().as_slice()).unwrap();
+        v1.format_version = FormatVersion::V1;
+        v1.header_size = 0x20;
+        assert_eq!(encode_header(&v1), v1_fixture());
+    }
+
+    #[test]
+    fn test_header_md5() {
+        let fixture = v4_fixture();
+        let md5 = header_md5(&fixture);
+        assert_eq!(md5, header_md5(&fixture[..MD5_MPQ_HEADER_OFFSET]));
+        assert_ne!(md5, header_md5(&v4_fixture()[..0x20]));
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        let mut bad_signature = v1_fixture();
+        bad_signature[3] = 0x1B;
+        assert!(matches!(
+            read_header(&mut bad_signature.as_slice()),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        // Sectors of 512 << 23 bytes do not fit in 32 bits
+        let mut huge_sectors = v1_fixture();
+        huge_sectors[14] = 23;
+        assert!(matches!(
+            read_header(&mut huge_sectors.as_slice()),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        let truncated = v3_fixture();
+        assert!(matches!(
+            read_header(&mut &truncated[..0x30]),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_reconciliation() {
+        let reconcile = |bytes: &[u8]| read_header_reconciled(&mut &bytes[..]).unwrap();
+
+        let (_, reconciliation) = reconcile(&v2_fixture());
+        assert_eq!(reconciliation, None);
+
+        // A v2 header claiming the size of a v1 header is read as v1
+        let mut too_small = v2_fixture();
+        too_small[4] = 0x20;
+        let (header, reconciliation) = reconcile(&too_small);
+        assert_eq!(header.format_version, FormatVersion::V1);
+        assert_eq!(header.hi_block_table_pos, None);
+        assert_eq!(
+            reconciliation,
+            Some(HeaderReconciliation {
+                declared_version: 1,
+                declared_size: 0x20,
+                format_version: FormatVersion::V1,
+                rule: HeaderRule::SizeTooSmall,
+            })
+        );
+
+        // A v1 header with a v2 size and v2 fields that fit the archive
+        let mut upgraded = v1_fixture();
+        upgraded[4] = 0x2C;
+        upgraded.extend(le_bytes![0u64, 0u16, 0u16]);
+        let (header, reconciliation) = reconcile(&upgraded);
+        assert_eq!(header.format_version, FormatVersion::V2);
+        assert_eq!(header.hi_block_table_pos, Some(0));
+        assert_eq!(reconciliation.unwrap().rule, HeaderRule::UpgradedBySize);
+
+        // The v2 fixture's tables lie beyond a 0x1234-byte archive
+        let mut ignored = v2_fixture();
+        ignored[12] = 0;
+        let (header, reconciliation) = reconcile(&ignored);
+        assert_eq!(header.format_version, FormatVersion::V1);
+        assert_eq!(header.header_size, 0x20);
+        assert_eq!(reconciliation.unwrap().rule, HeaderRule::ExtraBytesIgnored);
+
+        // Protected maps claim huge headers that the stream does not hold
+        let mut protected = v1_fixture();
+        protected[4..8].copy_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+        let (header, reconciliation) = reconcile(&protected);
+        assert_eq!(header.format_version, FormatVersion::V1);
+        assert_eq!(header.hash_table_pos, 0x1000);
+        assert_eq!(reconciliation.unwrap().rule, HeaderRule::ExtraBytesIgnored);
+
+        let mut unknown = v1_fixture();
+        unknown[12] = 7;
+        let (header, reconciliation) = reconcile(&unknown);
+        assert_eq!(header.format_version, FormatVersion::V1);
+        let reconciliation = reconciliation.unwrap();
+        assert_eq!(reconciliation.rule, HeaderRule::UnknownVersion);
+        assert_eq!(
+            reconciliation.to_string(),
+            "raw version 7 with a 32-byte header read as v1 (unknown version)"
+        );
+    }
+
+    #[test]
+    fn test_user_data_header_fixture() {
+        let fixture = le_bytes![0x1B51_504Du32, 0x200u32, 0x400u32, 0x1Cu32];
+        let header = read_user_data_header(&mut fixture.as_slice()).unwrap();
+        assert_eq!(header.user_data_size, 0x200);
+        assert_eq!(header.header_offset, 0x400);
+        assert_eq!(header.user_data_header_size, 0x1C);
+
+        let mut bytes = Vec::new();
+        write_user_data_header(&header, &mut bytes).unwrap();
+        assert_eq!(bytes, fixture);
+
+        assert!(read_user_data_header(&mut v1_fixture().as_slice()).is_err());
+    }
+}
@@ -0,0 +1,426 @@
+//! Layered archives where later patches override earlier files
+//!
+//! Blizzard games ship their data as a set of base archives plus patch
+//! archives that replace individual files. A [`PatchChain`] opens such a set
+//! in load order and reads each file from the last archive that has it.
+//!
+//! [`PatchChain::discover`] finds the archives of a game's data directory
+//! by Blizzard's naming rules:
+//!
+//! 1. Base archives in the data directory (`common.MPQ`, `expansion.MPQ`)
+//! 2. Base archives in the locale directory (`enUS/locale-enUS.MPQ`)
+//! 3. Patches in the data directory (`patch.MPQ`, `patch-2.MPQ`, ...,
+//!    `patch-A.MPQ`)
+//! 4. Patches in the locale directory (`enUS/patch-enUS.MPQ`,
+//!    `enUS/patch-enUS-2.MPQ`)
+//! 5. Incremental updates by build number (`wow-update-13164.MPQ`,
+//!    `enUS/wow-update-enUS-13164.MPQ`), the locale one after the general
+//!    one of the same build
+//!
+//! Within a group, numbered archives follow the unnumbered one in numeric
+//! order and lettered ones come last. Names are matched case-insensitively.
+//!
+//! A patch directory of loose files, added with
+//! [`PatchChain::push_directory`], overrides every archive, as in the
+//! classic modding workflow. Loose files are looked up the way an
+//! [`MpqVfs`](crate::MpqVfs) looks them up.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use mopaq::PatchChain;
+//!
+//! let chain = PatchChain::discover("World of Warcraft/Data", "enUS")?;
+//! let data = chain.read_file("Interface\\FrameXML\\UIParent.lua")?;
+//! # Ok::<(), mopaq::Error>(())
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::special_files::get_special_file_info;
+use crate::vfs::{check_directory, find_loose_file, list_loose_files};
+use crate::{Archive, Error, FileInfo, NameHashingPolicy, PrecomputedName, Result};
+
+/// A file of a [`PatchChain`] and the archive it is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainEntry {
+    /// File name as listed by the winning archive
+    pub name: String,
+    /// Path of the last archive that has the file, or of the patch
+    /// directory it is read from
+    pub archive: PathBuf,
+    /// Uncompressed size in that archive
+    pub size: u64,
+}
+
+/// An ordered set of archives, later ones taking precedence
+#[derive(Debug, Default)]
+pub struct PatchChain {
+    /// Archives in load order
+    archives: Vec<(PathBuf, Archive)>,
+    /// Patch directories in the order they were added, searched from the
+    /// last before any archive
+    directories: Vec<PathBuf>,
+}
+
+impl PatchChain {
+    /// Create an empty chain
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open the archives of a game's data directory in load order
+    ///
+    /// `locale` names the locale subdirectory, such as `"enUS"`; archives
+    /// of other locales are ignored. Files that don't follow the naming
+    /// rules in the [module documentation](self) are loaded as base
+    /// archives.
+    ///
+    /// # Errors
+    /// - `Error::Io` if a directory cannot be read
+    /// - Any error from opening one of the archives
+    pub fn discover(data_dir: impl AsRef<Path>, locale: &str) -> Result<Self> {
+        let mut chain = Self::new();
+        for path in discover_archives(data_dir.as_ref(), locale)? {
+            chain.push(path)?;
+        }
+        Ok(chain)
+    }
+
+    /// Open the archive at `path` and load it after the current ones
+    ///
+    /// # Errors
+    /// - Any error from opening the archive
+    pub fn push(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        log::debug!("Adding {} to the patch chain", path.display());
+        self.archives
+            .push((path.to_path_buf(), Archive::open(path)?));
+        Ok(())
+    }
+
+    /// Add a patch directory whose loose files override every archive
+    ///
+    /// Directories added later override earlier ones. Names match loose
+    /// files ignoring case and separators on every platform.
+    ///
+    /// # Errors
+    /// - `Error::Io` if `path` is not a directory
+    pub fn push_directory(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        check_directory(path)?;
+        log::debug!("Adding {} to the patch chain", path.display());
+        self.directories.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Paths of the patch directories in the order they were added
+    pub fn directory_paths(&self) -> impl Iterator<Item = &Path> {
+        self.directories.iter().map(PathBuf::as_path)
+    }
+
+    /// Paths of the archives in load order
+    pub fn archive_paths(&self) -> impl Iterator<Item = &Path> {
+        self.archives.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Number of archives in the chain
+    pub fn len(&self) -> usize {
+        self.archives.len()
+    }
+
+    /// Whether the chain has no archives
+    pub fn is_empty(&self) -> bool {
+        self.archives.is_empty()
+    }
+
+    /// The archive a file is read from and its entry there
+    ///
+    /// Returns `None` if no archive in the chain has the file. Patch
+    /// directories are not searched, since their files have no entry.
+    ///
+    /// # Errors
+    /// - Any error from looking the file up in one of the archives
+    pub fn find_file(&self, name: &str) -> Result<Option<(&Path, FileInfo)>> {
+        self.find_file_pre(&PrecomputedName::new(name))
+    }
+
+    /// Like [`find_file`](Self::find_file), hashing the name only once
+    ///
+    /// # Errors
+    /// - Any error from looking the file up in one of the archives
+    pub fn find_file_pre(&self, name: &PrecomputedName) -> Result<Option<(&Path, FileInfo)>> {
+        for (path, archive) in self.archives.iter().rev() {
+            if let Some(info) = archive.find_file_pre(name)? {
+                return Ok(Some((path, info)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read a file from the last patch directory or, failing that, the last
+    /// archive that has it
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if nothing in the chain has the file
+    /// - Any error from reading the file
+    pub fn read_file(&self, name: &str) -> Result<Vec<u8>> {
+        for dir in self.directories.iter().rev() {
+            if let Some(path) = find_loose_file(dir, name) {
+                return Ok(fs::read(path)?);
+            }
+        }
+        for (_, archive) in self.archives.iter().rev() {
+            if archive.find_file(name)?.is_some() {
+                return archive.read_file(name);
+            }
+        }
+        Err(Error::FileNotFound(name.to_string()))
+    }
+
+    /// Every named file of the chain with the archive it is read from
+    ///
+    /// Names come from each archive's `(listfile)`; archives without one
+    /// contribute nothing. Patch directories contribute every file below
+    /// them. Names differing only in case or separators are the same file.
+    /// Special files such as `(listfile)` belong to their archive and are
+    /// left out. Entries are sorted by name.
+    ///
+    /// # Errors
+    /// - `Error::Io` if a patch directory cannot be read
+    /// - Any error from listing one of the archives
+    pub fn list(&mut self) -> Result<Vec<ChainEntry>> {
+        let mut files = BTreeMap::new();
+        for (path, archive) in &mut self.archives {
+            if archive.find_file("(listfile)")?.is_none() {
+                log::warn!("{} has no (listfile), skipping its files", path.display());
+                continue;
+            }
+            let policy = archive.name_hashing();
+            for entry in archive.list()? {
+                if get_special_file_info(&entry.name).is_some() {
+                    continue;
+                }
+                files.insert(
+                    policy.normalize_name(&entry.name),
+                    ChainEntry {
+                        name: entry.name,
+                        archive: path.clone(),
+                        size: entry.size,
+                    },
+                );
+            }
+        }
+
+        let policy = NameHashingPolicy::default();
+        for dir in &self.directories {
+            for (name, size) in list_loose_files(dir)? {
+                files.insert(
+                    policy.normalize_name(&name),
+                    ChainEntry {
+                        name,
+                        archive: dir.clone(),
+                        size,
+                    },
+                );
+            }
+        }
+
+        let mut entries: Vec<ChainEntry> = files.into_values().collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+}
+
+/// Paths of the archives in `data_dir` and its `locale` directory, in load
+/// order
+fn discover_archives(data_dir: &Path, locale: &str) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for path in mpq_files(data_dir)? {
+        found.push((ArchiveRank::new(&path, None), path));
+    }
+
+    let locale_dir = fs::read_dir(data_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.is_dir()
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().eq_ignore_ascii_case(locale))
+        });
+    if let Some(locale_dir) = locale_dir {
+        for path in mpq_files(&locale_dir)? {
+            found.push((ArchiveRank::new(&path, Some(locale)), path));
+        }
+    }
+
+    found.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(found.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Files with an `.mpq` extension directly in `dir`
+fn mpq_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_mpq = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mpq"));
+        if is_mpq && path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Load order groups, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Group {
+    Base,
+    LocaleBase,
+    Patch,
+    LocalePatch,
+    /// Incremental update of this build, then whether it is the locale one
+    Update(u64, bool),
+}
+
+/// Position of an archive in the load order
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ArchiveRank {
+    group: Group,
+    /// Series name without the number suffix, lowercase
+    series: String,
+    suffix: Suffix,
+}
+
+impl ArchiveRank {
+    /// Rank of the archive at `path`, with `locale` set for archives in the
+    /// locale directory
+    fn new(path: &Path, locale: Option<&str>) -> Self {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        if let Some(build) = stem.strip_prefix("wow-update-") {
+            let build = locale
+                .and_then(|locale| build.strip_prefix(&format!("{}-", locale.to_lowercase())))
+                .unwrap_or(build);
+            if let Ok(build) = build.parse() {
+                return Self {
+                    group: Group::Update(build, locale.is_some()),
+                    series: stem,
+                    suffix: Suffix::None,
+                };
+            }
+        }
+
+        let (series, suffix) = Suffix::split(&stem);
+        let is_patch = series == "patch"
+            || locale.is_some_and(|locale| series == format!("patch-{}", locale.to_lowercase()));
+        let group = match (is_patch, locale.is_some()) {
+            (false, false) => Group::Base,
+            (false, true) => Group::LocaleBase,
+            (true, false) => Group::Patch,
+            (true, true) => Group::LocalePatch,
+        };
+        Self {
+            group,
+            series: series.to_string(),
+            suffix,
+        }
+    }
+}
+
+/// The `-2` or `-A` at the end of an archive name
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Suffix {
+    None,
+    Number(u32),
+    Letter(char),
+}
+
+impl Suffix {
+    /// Split a lowercase stem into its series name and suffix
+    fn split(stem: &str) -> (&str, Self) {
+        if let Some((series, suffix)) = stem.rsplit_once('-') {
+            if let Ok(number) = suffix.parse() {
+                return (series, Suffix::Number(number));
+            }
+            let mut chars = suffix.chars();
+            if let (Some(letter), None) = (chars.next(), chars.next()) {
+                if letter.is_ascii_alphabetic() {
+                    return (series, Suffix::Letter(letter));
+                }
+            }
+        }
+        (stem, Suffix::None)
+    }
+}
+
+impl PartialOrd for Suffix {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Suffix {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let rank = |suffix: &Self| match *suffix {
+            Suffix::None => (0, 0),
+            Suffix::Number(number) => (1, number),
+            Suffix::Letter(letter) => (2, letter as u32),
+        };
+        rank(self).cmp(&rank(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_rank_order() {
+        let mut archives = vec![
+            ("wow-update-enUS-13164.MPQ", true),
+            ("patch-enUS-2.MPQ", true),
+            ("patch-A.MPQ", false),
+            ("wow-update-13205.MPQ", false),
+            ("patch-10.MPQ", false),
+            ("locale-enUS.MPQ", true),
+            ("patch.MPQ", false),
+            ("wow-update-13164.MPQ", false),
+            ("common-2.MPQ", false),
+            ("patch-enUS.MPQ", true),
+            ("patch-2.MPQ", false),
+            ("common.MPQ", false),
+            ("expansion.MPQ", false),
+        ];
+        archives.sort_by_key(|&(name, in_locale)| {
+            ArchiveRank::new(Path::new(name), in_locale.then_some("enUS"))
+        });
+        let names: Vec<_> = archives.iter().map(|&(name, _)| name).collect();
+        assert_eq!(
+            names,
+            [
+                "common.MPQ",
+                "common-2.MPQ",
+                "expansion.MPQ",
+                "locale-enUS.MPQ",
+                "patch.MPQ",
+                "patch-2.MPQ",
+                "patch-10.MPQ",
+                "patch-A.MPQ",
+                "patch-enUS.MPQ",
+                "patch-enUS-2.MPQ",
+                "wow-update-13164.MPQ",
+                "wow-update-enUS-13164.MPQ",
+                "wow-update-13205.MPQ",
+            ]
+        );
+    }
+}
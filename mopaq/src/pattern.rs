@@ -0,0 +1,192 @@
+//! Matching archive names against glob and regex patterns
+//!
+//! Names in an archive are looked up case-insensitively, and Blizzard's
+//! tools treat `/` and `\` as the same separator. A [`Pattern`] follows the
+//! same rules, so `*.BLP` matches `textures/grass.blp` and `units/*.mdx`
+//! matches `Units\Human\Footman.mdx`.
+
+use crate::{Error, Result};
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+
+/// A compiled glob or regular expression for archive names
+///
+/// # Examples
+///
+/// ```
+/// use mopaq::pattern::Pattern;
+///
+/// let glob = Pattern::glob("interface/*.blp")?;
+/// assert!(glob.is_match("Interface\\Icons\\Sword.BLP"));
+///
+/// let regex = Pattern::regex(r"^units/.*\.mdx$")?;
+/// assert!(regex.is_match("Units\\Human\\Footman.mdx"));
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Compile a glob that has to match a whole name
+    ///
+    /// `*` matches any run of characters, separators included, `?` matches
+    /// one character and `[...]` one of a set, negated with `[!...]` or
+    /// `[^...]`. Both `/` and `\` stand for a separator, so `\` does not
+    /// escape; put a special character in brackets, as in `[*]`, to match
+    /// it literally.
+    ///
+    /// # Errors
+    /// `Error::InvalidPattern` if a `[` is not closed.
+    pub fn glob(pattern: &str) -> Result<Self> {
+        let mut expr = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => {
+                    while chars.next_if_eq(&'*').is_some() {}
+                    expr.push_str(".*");
+                }
+                '?' => expr.push('.'),
+                '/' | '\\' => expr.push_str(r"\\"),
+                '[' => {
+                    expr.push('[');
+                    if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                        expr.push('^');
+                    }
+                    let mut first = true;
+                    loop {
+                        match chars.next() {
+                            None => {
+                                return Err(Error::InvalidPattern(format!(
+                                    "unclosed '[' in {pattern}"
+                                )))
+                            }
+                            Some(']') if !first => break,
+                            Some('/' | '\\') => expr.push_str(r"\\"),
+                            Some('-') => expr.push('-'),
+                            Some(c) if "[]^&~".contains(c) => {
+                                expr.push('\\');
+                                expr.push(c);
+                            }
+                            Some(c) => expr.push(c),
+                        }
+                        first = false;
+                    }
+                    expr.push(']');
+                }
+                c => expr.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        expr.push('$');
+        Self::compile(pattern, &expr)
+    }
+
+    /// Compile a regular expression that has to match part of a name
+    ///
+    /// Anchor it with `^` and `$` to match whole names. A `/` in the
+    /// expression matches either separator, as does `\\`.
+    ///
+    /// # Errors
+    /// `Error::InvalidPattern` if the expression does not compile.
+    pub fn regex(pattern: &str) -> Result<Self> {
+        let mut expr = String::with_capacity(pattern.len());
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('/') | Some('\\') => expr.push_str(r"\\"),
+                    Some(escaped) => {
+                        expr.push('\\');
+                        expr.push(escaped);
+                    }
+                    None => expr.push('\\'),
+                },
+                '/' => expr.push_str(r"\\"),
+                c => expr.push(c),
+            }
+        }
+        Self::compile(pattern, &expr)
+    }
+
+    fn compile(source: &str, expr: &str) -> Result<Self> {
+        let regex = RegexBuilder::new(expr)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| Error::InvalidPattern(format!("{source}: {e}")))?;
+        Ok(Self {
+            source: source.to_string(),
+            regex,
+        })
+    }
+
+    /// Check whether `name` matches, ignoring case and the kind of separator
+    pub fn is_match(&self, name: &str) -> bool {
+        let name = if name.contains('/') {
+            Cow::Owned(name.replace('/', "\\"))
+        } else {
+            Cow::Borrowed(name)
+        };
+        self.regex.is_match(&name)
+    }
+
+    /// The pattern as it was given
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        let glob = Pattern::glob("*.blp").unwrap();
+        assert!(glob.is_match("grass.blp"));
+        assert!(glob.is_match("Textures\\Grass.BLP"));
+        assert!(!glob.is_match("grass.blp.bak"));
+
+        let glob = Pattern::glob("units/human/foot?an.mdx").unwrap();
+        assert!(glob.is_match("Units\\Human\\Footman.mdx"));
+        assert!(glob.is_match("units/human/footman.mdx"));
+        assert!(!glob.is_match("units\\human\\footmen.mdl"));
+
+        let glob = Pattern::glob("war3map.[!j]*").unwrap();
+        assert!(glob.is_match("war3map.w3e"));
+        assert!(!glob.is_match("war3map.j"));
+
+        // Special characters match literally, in brackets or not
+        let glob = Pattern::glob("(listfile)[*]+").unwrap();
+        assert!(glob.is_match("(LISTFILE)*+"));
+        assert!(!glob.is_match("(listfile)x+"));
+
+        assert!(matches!(
+            Pattern::glob("file[ab"),
+            Err(Error::InvalidPattern(_))
+        ));
+    }
+
+    #[test]
+    fn test_regex() {
+        let regex = Pattern::regex(r"^interface/.*\.blp$").unwrap();
+        assert!(regex.is_match("Interface\\Icons\\Sword.BLP"));
+        assert!(!regex.is_match("Textures\\Interface\\Sword.blp"));
+
+        // Escaped separators of either kind match both
+        let regex = Pattern::regex(r"icons\\sword|a\/b").unwrap();
+        assert!(regex.is_match("icons/sword.blp"));
+        assert!(regex.is_match("A\\B"));
+
+        // Unanchored expressions match anywhere in the name
+        assert!(Pattern::regex("human").unwrap().is_match("units\\human\\x"));
+        assert_eq!(Pattern::regex("a+b").unwrap().as_str(), "a+b");
+
+        assert!(matches!(
+            Pattern::regex("(unclosed"),
+            Err(Error::InvalidPattern(_))
+        ));
+    }
+}
@@ -92,6 +92,17 @@ pub struct Attributes {
     pub version: u32,
     /// Flags indicating which attributes are present
     pub flags: AttributeFlags,
+    /// Sections the data actually contains
+    ///
+    /// A subset of [`flags`](Self::flags): real archives sometimes flag a
+    /// section that the file ends before. A section cut short counts as
+    /// present, with `None` for the files it does not reach.
+    pub present: AttributeFlags,
+    /// Number of files the data has entries for
+    ///
+    /// Often one or two less than the block count for archives whose
+    /// `(attributes)` leaves out itself and the `(signature)`.
+    pub entry_count: usize,
     /// Attributes for each file in the block table
     pub file_attributes: Vec<FileAttributes>,
 }
@@ -100,7 +111,28 @@ impl Attributes {
     /// Expected version for the attributes file
     pub const EXPECTED_VERSION: u32 = 100;
 
+    /// Create attributes for writing, with every flagged section present
+    pub fn new(flags: AttributeFlags, file_attributes: Vec<FileAttributes>) -> Self {
+        Self {
+            version: Self::EXPECTED_VERSION,
+            flags,
+            present: flags,
+            entry_count: file_attributes.len(),
+            file_attributes,
+        }
+    }
+
     /// Parse attributes from raw data
+    ///
+    /// The result always has `block_count` file entries. Data written for
+    /// one or two fewer files, as some tools do, is recognized by its size.
+    /// Data that ends early is not an error: files past the end of a
+    /// section get `None` for it, and sections past the end of the data are
+    /// left out of [`present`](Self::present).
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if the header is missing or the version is
+    ///   not [`EXPECTED_VERSION`](Self::EXPECTED_VERSION)
     pub fn parse(data: &Bytes, block_count: usize) -> Result<Self> {
         if data.len() < 8 {
             return Err(Error::invalid_format(
@@ -121,106 +153,63 @@ impl Attributes {
         }
 
         let flags = AttributeFlags::new(read_u32_le(&mut cursor)?);
-
-        // Calculate expected size
-        let mut expected_size = 8; // header
-        if flags.has_crc32() {
-            expected_size += block_count * 4;
-        }
-        if flags.has_filetime() {
-            expected_size += block_count * 8;
-        }
-        if flags.has_md5() {
-            expected_size += block_count * 16;
-        }
-        if flags.has_patch_bit() {
-            expected_size += block_count.div_ceil(8);
-        }
-
-        if data.len() < expected_size {
-            return Err(Error::invalid_format(format!(
-                "Attributes file too small: {} bytes (expected at least {})",
-                data.len(),
-                expected_size
-            )));
+        let mut body = &data[8..];
+        let entry_count = stored_entry_count(flags, body.len(), block_count);
+        if section_size(flags, entry_count) > body.len() {
+            log::warn!(
+                "Attributes file is {} bytes short, missing entries are left empty",
+                section_size(flags, entry_count) - body.len()
+            );
         }
 
-        // Parse attributes for each file
-        let mut file_attributes = Vec::with_capacity(block_count);
-
-        // Parse CRC32 array if present
-        let crc32_values = if flags.has_crc32() {
-            let mut values = Vec::with_capacity(block_count);
-            for _ in 0..block_count {
-                values.push(read_u32_le(&mut cursor)?);
+        let mut present = 0;
+        let mut section = |flag: u32, record_size: usize, count: usize| -> Option<Vec<&[u8]>> {
+            if flags.as_u32() & flag == 0 || (body.is_empty() && count > 0) {
+                return None;
             }
-            Some(values)
-        } else {
-            None
+            present |= flag;
+            let available = (body.len() / record_size).min(count);
+            let (records, rest) = body.split_at(available * record_size);
+            // A section cut short ends the data
+            body = if available == count { rest } else { &[] };
+            Some(records.chunks_exact(record_size).collect())
         };
 
-        // Parse timestamp array if present
-        let filetime_values = if flags.has_filetime() {
-            let mut values = Vec::with_capacity(block_count);
-            for _ in 0..block_count {
-                values.push(read_u64_le(&mut cursor)?);
-            }
-            Some(values)
-        } else {
-            None
-        };
-
-        // Parse MD5 array if present
-        let md5_values = if flags.has_md5() {
-            let mut values = Vec::with_capacity(block_count);
-            for _ in 0..block_count {
-                let mut hash = [0u8; 16];
-                read_exact(&mut cursor, &mut hash)?;
-                values.push(hash);
-            }
-            Some(values)
-        } else {
-            None
-        };
-
-        // Parse patch bits if present
-        let patch_bits = if flags.has_patch_bit() {
-            let byte_count = block_count.div_ceil(8);
-            let mut bits = vec![0u8; byte_count];
-            read_exact(&mut cursor, &mut bits)?;
-            Some(bits)
-        } else {
-            None
-        };
+        let crc32_values = section(AttributeFlags::CRC32, 4, entry_count);
+        let filetime_values = section(AttributeFlags::FILETIME, 8, entry_count);
+        let md5_values = section(AttributeFlags::MD5, 16, entry_count);
+        let patch_bits = section(AttributeFlags::PATCH_BIT, 1, entry_count.div_ceil(8));
 
         // Combine into FileAttributes structs
+        let mut file_attributes = Vec::with_capacity(block_count);
         for i in 0..block_count {
             let mut attrs = FileAttributes::new();
-
-            if let Some(ref values) = crc32_values {
-                attrs.crc32 = Some(values[i]);
+            if i < entry_count {
+                attrs.crc32 = crc32_values
+                    .as_ref()
+                    .and_then(|values| values.get(i))
+                    .map(|bytes| u32::from_le_bytes((*bytes).try_into().unwrap()));
+                attrs.filetime = filetime_values
+                    .as_ref()
+                    .and_then(|values| values.get(i))
+                    .map(|bytes| u64::from_le_bytes((*bytes).try_into().unwrap()));
+                attrs.md5 = md5_values
+                    .as_ref()
+                    .and_then(|values| values.get(i))
+                    .map(|bytes| (*bytes).try_into().unwrap());
+                attrs.is_patch = patch_bits
+                    .as_ref()
+                    .and_then(|bits| bits.get(i / 8))
+                    .map(|byte| byte[0] & (1 << (i % 8)) != 0);
             }
-
-            if let Some(ref values) = filetime_values {
-                attrs.filetime = Some(values[i]);
-            }
-
-            if let Some(ref values) = md5_values {
-                attrs.md5 = Some(values[i]);
-            }
-
-            if let Some(ref bits) = patch_bits {
-                let byte_index = i / 8;
-                let bit_index = i % 8;
-                attrs.is_patch = Some((bits[byte_index] & (1 << bit_index)) != 0);
-            }
-
             file_attributes.push(attrs);
         }
 
         Ok(Self {
             version,
             flags,
+            present: AttributeFlags::new(present),
+            entry_count,
             file_attributes,
         })
     }
@@ -283,6 +272,37 @@ impl Attributes {
     }
 }
 
+/// Size of the sections flagged in `flags` for `count` files
+fn section_size(flags: AttributeFlags, count: usize) -> usize {
+    let mut size = 0;
+    if flags.has_crc32() {
+        size += count * 4;
+    }
+    if flags.has_filetime() {
+        size += count * 8;
+    }
+    if flags.has_md5() {
+        size += count * 16;
+    }
+    if flags.has_patch_bit() {
+        size += count.div_ceil(8);
+    }
+    size
+}
+
+/// Number of files an attributes body of `len` bytes has entries for
+///
+/// Tools that write the (attributes) before adding it, and sometimes the
+/// (signature), to the block table leave out the last one or two entries.
+/// Such data is recognized by matching its size exactly; anything else is
+/// taken to be written for the whole block table.
+fn stored_entry_count(flags: AttributeFlags, len: usize, block_count: usize) -> usize {
+    (block_count.saturating_sub(2)..=block_count)
+        .rev()
+        .find(|&count| section_size(flags, count) == len)
+        .unwrap_or(block_count)
+}
+
 // Helper functions for reading from cursor
 fn read_u32_le(cursor: &mut Cursor<&Bytes>) -> Result<u32> {
     use std::io::Read;
@@ -291,19 +311,6 @@ fn read_u32_le(cursor: &mut Cursor<&Bytes>) -> Result<u32> {
     Ok(u32::from_le_bytes(bytes))
 }
 
-fn read_u64_le(cursor: &mut Cursor<&Bytes>) -> Result<u64> {
-    use std::io::Read;
-    let mut bytes = [0u8; 8];
-    cursor.read_exact(&mut bytes).map_err(Error::Io)?;
-    Ok(u64::from_le_bytes(bytes))
-}
-
-fn read_exact(cursor: &mut Cursor<&Bytes>, buf: &mut [u8]) -> Result<()> {
-    use std::io::Read;
-    cursor.read_exact(buf).map_err(Error::Io)?;
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -362,6 +369,17 @@ mod tests {
         assert_eq!(attrs.file_attributes[1].crc32, Some(0x9ABCDEF0));
     }
 
+    #[test]
+    fn test_stored_entry_count() {
+        let flags = AttributeFlags::new(AttributeFlags::CRC32 | AttributeFlags::PATCH_BIT);
+        assert_eq!(stored_entry_count(flags, 10 * 4 + 2, 10), 10);
+        assert_eq!(stored_entry_count(flags, 9 * 4 + 2, 10), 9);
+        assert_eq!(stored_entry_count(flags, 8 * 4 + 1, 10), 8);
+        // Too short for any whole layout: truncated data for every file
+        assert_eq!(stored_entry_count(flags, 20, 10), 10);
+        assert_eq!(stored_entry_count(flags, 0, 1), 0);
+    }
+
     #[test]
     fn test_roundtrip() {
         // Create attributes with all fields
@@ -381,11 +399,7 @@ mod tests {
         attr2.is_patch = Some(true);
         file_attrs.push(attr2);
 
-        let original = Attributes {
-            version: 100,
-            flags: AttributeFlags::new(AttributeFlags::ALL),
-            file_attributes: file_attrs,
-        };
+        let original = Attributes::new(AttributeFlags::new(AttributeFlags::ALL), file_attrs);
 
         // Convert to bytes and back
         let bytes = original.to_bytes().unwrap();
@@ -1,5 +1,102 @@
 //! Information about special MPQ files
 
+use std::fmt;
+
+/// A special file the MPQ format reserves a name for
+///
+/// These hold metadata about the archive rather than game data, and most
+/// tools leave them out when listing, extracting or copying files.
+///
+/// # Examples
+///
+/// ```
+/// use mopaq::special_files::SpecialFile;
+///
+/// assert_eq!(SpecialFile::Listfile.as_str(), "(listfile)");
+/// assert_eq!(SpecialFile::from_name("(ATTRIBUTES)"), Some(SpecialFile::Attributes));
+/// assert!(!SpecialFile::is_special("war3map.j"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpecialFile {
+    /// `(listfile)`, the names of the files in the archive
+    Listfile,
+    /// `(attributes)`, CRC32s, MD5s and timestamps of the files
+    Attributes,
+    /// `(signature)`, the weak digital signature
+    Signature,
+    /// `(user data)`, data placed before the archive by some tools
+    UserData,
+    /// `(patch_metadata)`, describing the files a patch archive changes
+    PatchMeta,
+    /// `(metadata)`, key-value information about files, an extension of
+    /// this crate that games ignore
+    Metadata,
+}
+
+impl SpecialFile {
+    /// Every special file
+    pub const ALL: [SpecialFile; 6] = [
+        Self::Listfile,
+        Self::Attributes,
+        Self::Signature,
+        Self::UserData,
+        Self::PatchMeta,
+        Self::Metadata,
+    ];
+
+    /// Name of the file in the archive
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Listfile => "(listfile)",
+            Self::Attributes => "(attributes)",
+            Self::Signature => "(signature)",
+            Self::UserData => "(user data)",
+            Self::PatchMeta => "(patch_metadata)",
+            Self::Metadata => "(metadata)",
+        }
+    }
+
+    /// The special file named `name`, if it is one
+    ///
+    /// Archive names are case-insensitive, so `(LISTFILE)` is the listfile
+    /// as well.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|special| special.as_str().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the file is derived from the other files of the archive
+    ///
+    /// True for the listfile, attributes and signature, which go stale as
+    /// soon as files are added, removed or changed, so copying them between
+    /// archives is wrong.
+    pub const fn is_derived(self) -> bool {
+        matches!(self, Self::Listfile | Self::Attributes | Self::Signature)
+    }
+
+    /// Whether `name` is the name of a special file
+    pub fn is_special(name: &str) -> bool {
+        Self::from_name(name).is_some()
+    }
+
+    /// How the file is stored by default
+    pub fn info(self) -> SpecialFileInfo {
+        let compressed = matches!(self, Self::Listfile | Self::Attributes | Self::Metadata);
+        SpecialFileInfo {
+            name: self.as_str(),
+            encrypted: false,
+            compressed,
+        }
+    }
+}
+
+impl fmt::Display for SpecialFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Information about a special file
 #[derive(Debug, Clone)]
 pub struct SpecialFileInfo {
@@ -13,29 +110,7 @@ pub struct SpecialFileInfo {
 
 /// Get information about known special files
 pub fn get_special_file_info(filename: &str) -> Option<SpecialFileInfo> {
-    match filename {
-        "(listfile)" => Some(SpecialFileInfo {
-            name: "(listfile)",
-            encrypted: false,
-            compressed: true,
-        }),
-        "(attributes)" => Some(SpecialFileInfo {
-            name: "(attributes)",
-            encrypted: false,
-            compressed: true,
-        }),
-        "(signature)" => Some(SpecialFileInfo {
-            name: "(signature)",
-            encrypted: false,
-            compressed: false,
-        }),
-        "(user data)" => Some(SpecialFileInfo {
-            name: "(user data)",
-            encrypted: false,
-            compressed: false,
-        }),
-        _ => None,
-    }
+    SpecialFile::from_name(filename).map(SpecialFile::info)
 }
 
 #[cfg(test)]
@@ -54,4 +129,20 @@ mod tests {
         assert!(!info.encrypted);
         assert!(info.compressed);
     }
+
+    #[test]
+    fn test_special_file_names() {
+        for special in SpecialFile::ALL {
+            assert_eq!(SpecialFile::from_name(special.as_str()), Some(special));
+            assert_eq!(special.to_string(), special.as_str());
+        }
+        assert_eq!(
+            SpecialFile::from_name("(Patch_Metadata)"),
+            Some(SpecialFile::PatchMeta)
+        );
+        assert!(SpecialFile::is_special("(signature)"));
+        assert!(!SpecialFile::Metadata.is_derived());
+        assert!(!SpecialFile::is_special("(listfile).txt"));
+        assert!(!SpecialFile::is_special("listfile"));
+    }
 }
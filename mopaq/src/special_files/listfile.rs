@@ -2,6 +2,41 @@
 
 use crate::Result;
 
+/// Line layout of a listfile written by
+/// [`Archive::export_listfile`](crate::Archive::export_listfile)
+///
+/// Metadata follows the name after a `;`, the convention community
+/// listfiles use and [`parse_listfile`] skips, so every layout can be read
+/// back as a plain list of names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListfileFormat {
+    /// `name`
+    #[default]
+    Names,
+    /// `name;size`
+    NameSize,
+    /// `name;size;crc32`, with the CRC32 of the contents as 8 hex digits
+    NameSizeCrc,
+}
+
+impl ListfileFormat {
+    /// Whether lines carry a CRC32, which requires reading every file
+    pub fn needs_crc(&self) -> bool {
+        matches!(self, ListfileFormat::NameSizeCrc)
+    }
+
+    /// Format one line, without the line terminator
+    ///
+    /// `crc` is only used by [`NameSizeCrc`](Self::NameSizeCrc).
+    pub fn line(&self, name: &str, size: u64, crc: u32) -> String {
+        match self {
+            ListfileFormat::Names => name.to_string(),
+            ListfileFormat::NameSize => format!("{};{}", name, size),
+            ListfileFormat::NameSizeCrc => format!("{};{};{:08X}", name, size, crc),
+        }
+    }
+}
+
 /// Parse a (listfile) into individual filenames
 ///
 /// The (listfile) format supports:
@@ -92,6 +127,29 @@ mod tests {
         assert_eq!(files[2], "file3.bin");
     }
 
+    #[test]
+    fn test_listfile_format_roundtrip() {
+        let lines: Vec<String> = [
+            ListfileFormat::Names,
+            ListfileFormat::NameSize,
+            ListfileFormat::NameSizeCrc,
+        ]
+        .iter()
+        .map(|format| format.line("Units\\Footman.mdx", 1024, 0xBEEF))
+        .collect();
+        assert_eq!(
+            lines,
+            [
+                "Units\\Footman.mdx",
+                "Units\\Footman.mdx;1024",
+                "Units\\Footman.mdx;1024;0000BEEF"
+            ]
+        );
+
+        let files = parse_listfile(lines.join("\r\n").as_bytes()).unwrap();
+        assert!(files.iter().all(|name| name == "Units\\Footman.mdx"));
+    }
+
     #[test]
     fn test_parse_empty_listfile() {
         let content = b"";
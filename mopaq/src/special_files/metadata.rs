@@ -0,0 +1,186 @@
+//! The `(metadata)` file: key-value information about archived files
+//!
+//! This is an extension of this crate, not part of the MPQ format. Game
+//! clients never look for the file, so it can carry notes such as a file's
+//! author, license or the build it came from without affecting them. It is
+//! stored as TOML, one table per archive path:
+//!
+//! ```toml
+//! ['units\human\footman.mdx']
+//! author = "Jane Doe"
+//! license = "CC-BY-4.0"
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, NameHashingPolicy, Result};
+
+/// Key-value information about the files of an archive
+///
+/// Paths are looked up the way archive names are: under the default
+/// [`NameHashingPolicy`], ignoring case and whether `/` or `\` separates
+/// their parts. [`Archive::metadata`](crate::Archive::metadata) uses the
+/// archive's own policy.
+///
+/// # Examples
+///
+/// ```
+/// use mopaq::special_files::Metadata;
+///
+/// let mut metadata = Metadata::new();
+/// metadata.insert("scripts\\war3map.j", "author", "Jane Doe");
+///
+/// let parsed = Metadata::parse(&metadata.to_bytes())?;
+/// assert_eq!(parsed.value("Scripts/War3map.j", "author"), Some("Jane Doe"));
+/// # Ok::<(), mopaq::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Metadata {
+    files: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(skip)]
+    name_hashing: NameHashingPolicy,
+}
+
+impl Metadata {
+    /// Metadata without any entries
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the contents of a `(metadata)` file
+    ///
+    /// # Errors
+    /// `Error::InvalidFormat` if the data is not UTF-8 TOML with a table of
+    /// strings for each path.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(data)
+            .map_err(|_| Error::invalid_format("(metadata) is not UTF-8"))?;
+        toml::from_str(text).map_err(|e| Error::invalid_format(format!("Invalid (metadata): {e}")))
+    }
+
+    /// Look paths up with `policy` instead of the default
+    pub fn with_name_hashing(mut self, policy: NameHashingPolicy) -> Self {
+        self.name_hashing = policy;
+        self
+    }
+
+    /// How paths are compared when they are looked up
+    pub fn name_hashing(&self) -> NameHashingPolicy {
+        self.name_hashing
+    }
+
+    /// The contents of a `(metadata)` file holding these entries
+    pub fn to_bytes(&self) -> Vec<u8> {
+        toml::to_string(self)
+            .expect("tables of strings always serialize")
+            .into_bytes()
+    }
+
+    /// Set `key` to `value` for the file at `path`
+    pub fn insert(&mut self, path: &str, key: &str, value: &str) {
+        let path = self.key_for(path).unwrap_or(path).to_string();
+        self.files
+            .entry(path)
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /// All keys and values for the file at `path`
+    pub fn get(&self, path: &str) -> Option<&BTreeMap<String, String>> {
+        self.files.get(self.key_for(path)?)
+    }
+
+    /// The value of `key` for the file at `path`
+    pub fn value(&self, path: &str, key: &str) -> Option<&str> {
+        self.get(path)?.get(key).map(String::as_str)
+    }
+
+    /// Drop every entry for the file at `path`
+    pub fn remove(&mut self, path: &str) -> Option<BTreeMap<String, String>> {
+        let path = self.key_for(path)?.to_string();
+        self.files.remove(&path)
+    }
+
+    /// Paths with their keys and values, ordered by path as stored
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &BTreeMap<String, String>)> {
+        self.files
+            .iter()
+            .map(|(path, values)| (path.as_str(), values))
+    }
+
+    /// Number of paths with metadata
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether there is no metadata at all
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The stored path that names the same file as `path`
+    fn key_for(&self, path: &str) -> Option<&str> {
+        let policy = self.name_hashing;
+        let path = policy.normalize_name(path);
+        self.files
+            .keys()
+            .find(|stored| policy.normalize_name(stored) == path)
+            .map(String::as_str)
+    }
+}
+
+impl From<BTreeMap<String, BTreeMap<String, String>>> for Metadata {
+    fn from(files: BTreeMap<String, BTreeMap<String, String>>) -> Self {
+        Self {
+            files,
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut metadata = Metadata::new();
+        metadata.insert("units\\human\\footman.mdx", "author", "Jane Doe");
+        metadata.insert("UNITS/HUMAN/FOOTMAN.MDX", "license", "CC-BY-4.0");
+        metadata.insert("war3map.j", "build", "a1b2c3");
+        assert_eq!(metadata.len(), 2);
+
+        let data = metadata.to_bytes();
+        let text = std::str::from_utf8(&data).unwrap();
+        assert!(text.contains(r"['units\human\footman.mdx']"));
+
+        let parsed = Metadata::parse(&data).unwrap();
+        assert_eq!(parsed, metadata);
+        assert_eq!(
+            parsed.value("Units/Human/Footman.mdx", "license"),
+            Some("CC-BY-4.0")
+        );
+        assert_eq!(parsed.value("war3map.j", "author"), None);
+        assert!(parsed.get("war3map.lua").is_none());
+
+        let verbatim = parsed
+            .clone()
+            .with_name_hashing(NameHashingPolicy::VERBATIM);
+        assert_eq!(verbatim.value("Units/Human/Footman.mdx", "license"), None);
+        assert!(verbatim.get("war3map.j").is_some());
+
+        let mut edited = parsed;
+        assert!(edited.remove("WAR3MAP.J").is_some());
+        assert_eq!(edited.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_parse_rejects_non_string_values() {
+        assert!(Metadata::parse(b"[\"a.txt\"]\nsize = 3\n").is_err());
+        assert!(Metadata::parse(&[0xFF, 0xFE]).is_err());
+        assert!(Metadata::parse(b"").unwrap().is_empty());
+    }
+}
@@ -3,7 +3,11 @@
 mod attributes;
 mod info;
 mod listfile;
+#[cfg(feature = "metadata")]
+mod metadata;
 
 pub use attributes::{AttributeFlags, Attributes, FileAttributes};
-pub use info::{get_special_file_info, SpecialFileInfo};
-pub use listfile::parse_listfile;
+pub use info::{get_special_file_info, SpecialFile, SpecialFileInfo};
+pub use listfile::{parse_listfile, ListfileFormat};
+#[cfg(feature = "metadata")]
+pub use metadata::Metadata;
@@ -0,0 +1,227 @@
+//! Splitting archives by size
+//!
+//! [`split_archive`] spreads the files of one archive across as many new
+//! archives as it takes to keep each of them under a size limit, for example
+//! to stay below the 4 GiB file size limit of FAT32 media or the 2 GiB limit
+//! of older MPQ readers. The returned [`SplitManifest`] records which archive
+//! holds each file and can be saved next to the parts for installers to use.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use mopaq::{split, Archive};
+//!
+//! let mut archive = Archive::open("content.mpq")?;
+//! let manifest = split::split_archive(&mut archive, 2 << 30, |index| {
+//!     format!("content-{:02}.mpq", index + 1).into()
+//! })?;
+//! manifest.save("content.manifest")?;
+//! # Ok::<(), mopaq::Error>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::special_files::SpecialFile;
+use crate::{Archive, ArchiveBuilder, Error, NameHashingPolicy, Result};
+
+/// Fixed cost of an archive: header plus the smallest hash table
+const ARCHIVE_OVERHEAD: u64 = 0x20 + 16 * 16;
+
+/// Per-file cost besides its data: two hash table slots and a block entry
+const FILE_OVERHEAD: u64 = 3 * 16;
+
+/// One archive produced by [`split_archive`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPart {
+    /// Where the archive was written
+    pub path: PathBuf,
+    /// Names of the files it holds, in the order they were added
+    pub files: Vec<String>,
+    /// Size of the archive in bytes
+    pub size: u64,
+}
+
+/// Which archive holds each file after a split
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SplitManifest {
+    /// Archives in the order they were written
+    pub parts: Vec<SplitPart>,
+    /// How the source archive normalizes names, which
+    /// [`part_of`](Self::part_of) matches names by
+    pub name_hashing: NameHashingPolicy,
+}
+
+impl SplitManifest {
+    /// Index into [`parts`](Self::parts) of the archive holding `name`
+    ///
+    /// Names are compared the way the source archive hashes them, which by
+    /// default is case-insensitively and treating `/` and `\` as the same
+    /// separator.
+    pub fn part_of(&self, name: &str) -> Option<usize> {
+        let policy = self.name_hashing;
+        let target = policy.normalize_name(name);
+        self.parts.iter().position(|part| {
+            part.files
+                .iter()
+                .any(|file| policy.normalize_name(file) == target)
+        })
+    }
+
+    /// Write the manifest as text
+    ///
+    /// Each line holds an archive path and a file name separated by a tab.
+    /// Part sizes are not written; [`load`](Self::load) reads them from the
+    /// archives if they exist.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for part in &self.parts {
+            for file in &part.files {
+                writeln!(writer, "{}\t{}", part.path.display(), file)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a manifest written by [`save`](Self::save)
+    ///
+    /// The text does not record name hashing, so the manifest matches names
+    /// with the default policy; set [`name_hashing`](Self::name_hashing) for
+    /// parts of an archive that hashes names differently.
+    ///
+    /// # Errors
+    /// - `Error::InvalidFormat` if a line has no tab separator
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut manifest = Self::default();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let (archive, file) = line.split_once('\t').ok_or_else(|| {
+                Error::invalid_format(format!("malformed split manifest line: {}", line))
+            })?;
+
+            let archive = PathBuf::from(archive);
+            match manifest.parts.last_mut() {
+                Some(part) if part.path == archive => part.files.push(file.to_string()),
+                _ => {
+                    let size = std::fs::metadata(&archive).map_or(0, |m| m.len());
+                    manifest.parts.push(SplitPart {
+                        path: archive,
+                        files: vec![file.to_string()],
+                        size,
+                    });
+                }
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// Spread the files of `source` across archives of at most `max_size` bytes
+///
+/// Files are taken in listing order and packed greedily, using their stored
+/// size in `source` as an estimate. Each part is then built, and if it ends
+/// up over the limit its last files are moved on to the next part and it is
+/// built again, so every archive written is within `max_size`. The parts
+/// keep the source's format version and sector size, and files keep their
+/// encryption, locale and platform. `naming` maps a zero-based part index to
+/// the path to write it to.
+///
+/// Files are enumerated through the `(listfile)`; `(attributes)` and
+/// `(signature)` are not carried over, and each part gets its own listfile.
+///
+/// # Errors
+/// - `Error::InvalidFormat` if `source` has no `(listfile)`
+/// - `Error::CapacityExceeded` if a single file does not fit in `max_size`
+/// - Any error from reading `source` or writing a part
+pub fn split_archive<F>(source: &mut Archive, max_size: u64, mut naming: F) -> Result<SplitManifest>
+where
+    F: FnMut(usize) -> PathBuf,
+{
+    if source.find_file("(listfile)")?.is_none() {
+        return Err(Error::invalid_format(
+            "archive has no (listfile); its files cannot be enumerated for splitting",
+        ));
+    }
+
+    let mut pending = VecDeque::new();
+    for entry in source.list()? {
+        if SpecialFile::from_name(&entry.name).is_some_and(SpecialFile::is_derived) {
+            continue;
+        }
+        if let Some(info) = source.find_file(&entry.name)? {
+            let estimate = info.compressed_size + FILE_OVERHEAD + entry.name.len() as u64 + 2;
+            pending.push_back((entry.name, estimate));
+        }
+    }
+
+    let mut manifest = SplitManifest {
+        name_hashing: source.name_hashing(),
+        ..SplitManifest::default()
+    };
+    while !pending.is_empty() {
+        let mut chosen: Vec<(String, u64)> = Vec::new();
+        let mut estimate = ARCHIVE_OVERHEAD;
+        while let Some((_, size)) = pending.front() {
+            if !chosen.is_empty() && estimate + size > max_size {
+                break;
+            }
+            estimate += size;
+            chosen.extend(pending.pop_front());
+        }
+
+        let path = naming(manifest.parts.len());
+        let size = loop {
+            let size = build_part(source, &chosen, &path)?;
+            if size <= max_size {
+                break size;
+            }
+            if chosen.len() == 1 {
+                let _ = std::fs::remove_file(&path);
+                return Err(Error::CapacityExceeded(format!(
+                    "{} needs a {} byte archive, over the {} byte limit",
+                    chosen[0].0, size, max_size
+                )));
+            }
+
+            // Move files on to the next part until the overshoot is covered
+            let mut excess = size - max_size;
+            while chosen.len() > 1 {
+                let Some(file) = chosen.pop() else { break };
+                let moved = file.1;
+                pending.push_front(file);
+                if moved >= excess {
+                    break;
+                }
+                excess -= moved;
+            }
+        };
+
+        manifest.parts.push(SplitPart {
+            path,
+            files: chosen.into_iter().map(|(name, _)| name).collect(),
+            size,
+        });
+    }
+
+    Ok(manifest)
+}
+
+/// Build one part from `files` of `source` and return its size
+fn build_part(source: &mut Archive, files: &[(String, u64)], path: &Path) -> Result<u64> {
+    let header = source.header();
+    let mut builder = ArchiveBuilder::new()
+        .version(header.format_version)
+        .block_size(header.block_size)
+        .name_hashing(source.name_hashing());
+    for (name, _) in files {
+        builder = builder.add_file_from_archive(source, name)?;
+    }
+    builder.build(path)?;
+    Ok(std::fs::metadata(path)?.len())
+}
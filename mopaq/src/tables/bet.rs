@@ -1,6 +1,6 @@
 //! BET (Block Extended Table) implementation for MPQ v3+ archives
 
-use super::common::{decrypt_table_data, ReadLittleEndian};
+use super::common::{decrypt_table_data, read_bits, ReadLittleEndian};
 use crate::compression::decompress;
 use crate::{Error, Result};
 use std::io::{Read, Seek, SeekFrom};
@@ -14,7 +14,7 @@ pub struct BetTable {
     pub file_flags: Vec<u32>,
     /// File table (bit-packed)
     pub file_table: Vec<u8>,
-    /// BET hash array
+    /// Name hash of each file, without the 8 bits kept in the HET table
     pub bet_hashes: Vec<u64>,
 }
 
@@ -51,11 +51,11 @@ pub struct BetHeader {
     pub bit_count_flag_index: u32,
     /// Bit count for unknown field
     pub bit_count_unknown: u32,
-    /// BET hash information
+    /// Size of one name hash entry in bits, including extra bits
     pub total_bet_hash_size: u32,
-    /// Extra bits in BET hash size
+    /// Extra bits in a name hash entry
     pub bet_hash_size_extra: u32,
-    /// Size of BET hash
+    /// Effective size of a name hash in bits
     pub bet_hash_size: u32,
     /// Size of BET hash array
     pub bet_hash_array_size: u32,
@@ -176,11 +176,24 @@ impl BetTable {
         cursor.read_exact(&mut file_table)?;
 
         // Read BET hashes
-        let hash_count = header.bet_hash_array_size / 8; // Each hash is 8 bytes
-        let mut bet_hashes = Vec::with_capacity(hash_count as usize);
-        for _ in 0..hash_count {
-            bet_hashes.push(cursor.read_u64_le()?);
+        if header.bet_hash_size > 64 {
+            return Err(Error::invalid_format(format!(
+                "Invalid BET name hash size: {} bits",
+                { header.bet_hash_size }
+            )));
         }
+        let mut hash_array = vec![0u8; header.bet_hash_array_size as usize];
+        cursor.read_exact(&mut hash_array)?;
+        let bet_hashes = (0..header.file_count as usize)
+            .map(|index| {
+                read_bits(
+                    &hash_array,
+                    index * header.total_bet_hash_size as usize,
+                    header.bet_hash_size,
+                )
+                .ok_or_else(|| Error::invalid_format("BET name hash array too small"))
+            })
+            .collect::<Result<Vec<u64>>>()?;
 
         Ok(Self {
             header,
@@ -226,30 +239,32 @@ impl BetTable {
             return None;
         }
 
-        // Read bit-packed entry
-        let entry_bits = self.read_table_entry(index as usize)?;
-
-        // Extract fields
-        let file_pos = self.extract_bits(
-            entry_bits,
+        // Fields are read one at a time, since an entry may be wider than
+        // 64 bits when positions and sizes need more than 32 bits each
+        let entry_start = index as usize * self.header.table_entry_size as usize;
+        let field = |bit_index: u32, bit_count: u32| {
+            read_bits(
+                &self.file_table,
+                entry_start + bit_index as usize,
+                bit_count,
+            )
+        };
+        let file_pos = field(
             self.header.bit_index_file_pos,
             self.header.bit_count_file_pos,
-        );
-        let file_size = self.extract_bits(
-            entry_bits,
+        )?;
+        let file_size = field(
             self.header.bit_index_file_size,
             self.header.bit_count_file_size,
-        );
-        let cmp_size = self.extract_bits(
-            entry_bits,
+        )?;
+        let cmp_size = field(
             self.header.bit_index_cmp_size,
             self.header.bit_count_cmp_size,
-        );
-        let flag_index = self.extract_bits(
-            entry_bits,
+        )?;
+        let flag_index = field(
             self.header.bit_index_flag_index,
             self.header.bit_count_flag_index,
-        ) as u32;
+        )? as u32;
 
         // Get flags
         let flags = if flag_index < self.header.flag_count {
@@ -266,42 +281,15 @@ impl BetTable {
         })
     }
 
-    /// Read a table entry from bit-packed data
-    fn read_table_entry(&self, index: usize) -> Option<u64> {
-        let bit_offset = index * self.header.table_entry_size as usize;
-        let byte_offset = bit_offset / 8;
-        let bit_shift = bit_offset % 8;
-
-        if byte_offset + 8 > self.file_table.len() {
-            return None;
-        }
-
-        // Read enough bytes to get the full entry
-        let mut value = 0u64;
-        let bytes_needed = (bit_shift + self.header.table_entry_size as usize)
-            .div_ceil(8)
-            .min(8);
-
-        for i in 0..bytes_needed {
-            if byte_offset + i < self.file_table.len() {
-                value |= (self.file_table[byte_offset + i] as u64) << (i * 8);
-            }
-        }
-
-        // Shift and mask to get the actual entry
-        let entry = (value >> bit_shift) & ((1u64 << self.header.table_entry_size) - 1);
-        Some(entry)
-    }
-
-    /// Extract bits from a value
-    fn extract_bits(&self, value: u64, bit_offset: u32, bit_count: u32) -> u64 {
-        let mask = (1u64 << bit_count) - 1;
-        (value >> bit_offset) & mask
+    /// Name hash of a file, as checked by
+    /// [`HetTable::find_file`](super::HetTable::find_file)
+    pub fn name_hash(&self, index: u32) -> Option<u64> {
+        self.bet_hashes.get(index as usize).copied()
     }
 }
 
 /// File information from BET table
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BetFileInfo {
     /// File position in archive
     pub file_pos: u64,
@@ -1,13 +1,13 @@
 //! Block table implementation for MPQ archives
 
-use super::common::ReadLittleEndian;
-use crate::crypto::{decrypt_block, hash_string, hash_type};
+use super::common::{read_table_dwords, ReadLittleEndian};
+use crate::crypto::{decrypt_block, detect_key_by_known_plaintext, hash_string, hash_type};
 use crate::{Error, Result};
 use std::io::{Read, Seek, SeekFrom};
 
 /// Block table entry (16 bytes)
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BlockEntry {
     /// Offset of the beginning of the file data, relative to the beginning of the archive
     pub file_pos: u32,
@@ -109,39 +109,78 @@ impl BlockTable {
 
     /// Read and decrypt a block table from the archive
     pub fn read<R: Read + Seek>(reader: &mut R, offset: u64, size: u32) -> Result<Self> {
-        // Seek to block table position
-        reader.seek(SeekFrom::Start(offset))?;
-
-        // Read raw data
-        let byte_size = size as usize * 16; // 16 bytes per entry
-        let mut raw_data = vec![0u8; byte_size];
-        reader.read_exact(&mut raw_data)?;
-
-        // Decrypt the table - SAFE VERSION
         let key = hash_string("(block table)", hash_type::FILE_KEY);
+        Self::read_with_key(reader, offset, size, key)
+    }
 
-        // Convert to u32s, decrypt, then convert back
-        let mut u32_buffer: Vec<u32> = raw_data
-            .chunks_exact(4)
-            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-            .collect();
+    /// Read a block table encrypted with a specific key
+    ///
+    /// Used for archives whose block table was not encrypted with the
+    /// standard key; see [`BlockTable::recover_key`].
+    pub fn read_with_key<R: Read + Seek>(
+        reader: &mut R,
+        offset: u64,
+        size: u32,
+        key: u32,
+    ) -> Result<Self> {
+        let mut dwords = read_table_dwords(reader, offset, size)?;
+        decrypt_block(&mut dwords, key);
+
+        Ok(Self::from_dwords(&dwords))
+    }
 
-        decrypt_block(&mut u32_buffer, key);
+    /// Recover the key of a block table encrypted with a non-standard key
+    ///
+    /// The first block nearly always holds the file written directly after
+    /// the archive header, so its offset `first_file_pos` is known plaintext.
+    /// A candidate key is only accepted if that block exists and every
+    /// existing block then lies within `archive_size` bytes.
+    ///
+    /// Returns `Ok(None)` if no key was found.
+    pub fn recover_key<R: Read + Seek>(
+        reader: &mut R,
+        offset: u64,
+        size: u32,
+        first_file_pos: u32,
+        archive_size: u64,
+    ) -> Result<Option<u32>> {
+        let dwords = read_table_dwords(reader, offset, size)?;
+
+        Ok(detect_key_by_known_plaintext(
+            &dwords,
+            first_file_pos,
+            |decrypted| {
+                let table = Self::from_dwords(decrypted);
+                table.entries[0].exists() && table.is_plausible(archive_size)
+            },
+        ))
+    }
 
-        // Write decrypted u32s back to bytes
-        for (chunk, &decrypted) in raw_data.chunks_exact_mut(4).zip(&u32_buffer) {
-            chunk.copy_from_slice(&decrypted.to_le_bytes());
-        }
+    /// Check whether every existing block lies within the archive
+    ///
+    /// A table decrypted with the wrong key is almost never plausible, so this
+    /// is used to tell whether the standard key was the right one. Only the
+    /// low 32 bits of block offsets are considered.
+    pub fn is_plausible(&self, archive_size: u64) -> bool {
+        self.entries
+            .iter()
+            .filter(|e| e.exists())
+            .all(|e| e.file_pos as u64 + e.compressed_size as u64 <= archive_size)
+    }
 
-        // Parse entries
-        let mut entries = Vec::with_capacity(size as usize);
-        for i in 0..size as usize {
-            let offset = i * 16;
-            let entry = BlockEntry::from_bytes(&raw_data[offset..offset + 16])?;
-            entries.push(entry);
-        }
+    /// Parse entries from decrypted table DWORDs
+    fn from_dwords(dwords: &[u32]) -> Self {
+        let entries = dwords
+            .chunks_exact(4)
+            .map(|d| BlockEntry {
+                file_pos: d[0],
+                compressed_size: d[1],
+                file_size: d[2],
+                flags: d[3],
+            })
+            .collect();
 
-        Ok(Self { entries })
+        Self { entries }
     }
 
     /// Get all entries
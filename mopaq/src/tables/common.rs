@@ -1,7 +1,84 @@
 //! Common utilities and traits for MPQ tables
 
 use crate::Result;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Key used to decrypt the classic hash or block table
+///
+/// Both tables are normally encrypted with a key derived from the names
+/// `"(hash table)"` and `"(block table)"`. Some protected maps re-encrypt them
+/// with a different key so that standard tools fail to open the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableKey {
+    /// The key derived from the table name
+    #[default]
+    Standard,
+    /// A specific key, e.g. one found by an earlier recovery
+    Fixed(u32),
+    /// Use the standard key if the table decrypts to plausible entries, and
+    /// otherwise recover the real key from known plaintext
+    Recover,
+}
+
+/// How hash, block and hi-block table positions outside the file are read
+///
+/// Some protectors store table positions that only point at the table after
+/// wrapping around: the offset of an archive embedded in another file plus
+/// the position overflows 32 bits, or the position exceeds the archive size
+/// by a multiple of it. StormLib computes these offsets in 32 bits and
+/// reduces them modulo the archive size, so such archives open there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableOffsetPolicy {
+    /// Use table positions as stored
+    #[default]
+    Strict,
+    /// Wrap positions that point past the end of the file back into it
+    Lenient,
+}
+
+impl TableOffsetPolicy {
+    /// File offset of a table of `table_bytes` bytes at header position `pos`
+    ///
+    /// `archive_offset` is the position of the MPQ header in the file and
+    /// `archive_size` the size the header declares, or 0 if unknown. A
+    /// position the policy cannot map into the file is returned as stored.
+    pub fn resolve(
+        self,
+        archive_offset: u64,
+        pos: u64,
+        table_bytes: u64,
+        archive_size: u64,
+        file_size: u64,
+    ) -> u64 {
+        let stored = archive_offset + pos;
+        let fits = |offset: u64| offset.saturating_add(table_bytes) <= file_size;
+        if self == TableOffsetPolicy::Strict || fits(stored) {
+            return stored;
+        }
+
+        let archive_size = match archive_size {
+            0 => file_size.saturating_sub(archive_offset),
+            size => size,
+        };
+        let wrapped_32 = stored & u32::MAX as u64;
+        let modulo = (archive_size > 0).then(|| archive_offset + pos % archive_size);
+        match [Some(wrapped_32), modulo]
+            .into_iter()
+            .flatten()
+            .find(|&offset| fits(offset))
+        {
+            Some(offset) => {
+                log::warn!(
+                    "Table position 0x{:X} lies outside the file, reading the table at 0x{:X}",
+                    pos,
+                    offset
+                );
+                offset
+            }
+            None => stored,
+        }
+    }
+}
 
 /// Helper trait for reading little-endian integers
 pub(crate) trait ReadLittleEndian: Read {
@@ -16,16 +93,28 @@ pub(crate) trait ReadLittleEndian: Read {
         self.read_exact(&mut buf)?;
         Ok(u32::from_le_bytes(buf))
     }
-
-    fn read_u64_le(&mut self) -> Result<u64> {
-        let mut buf = [0u8; 8];
-        self.read_exact(&mut buf)?;
-        Ok(u64::from_le_bytes(buf))
-    }
 }
 
 impl<R: Read> ReadLittleEndian for R {}
 
+/// Read `bit_count` bits (at most 64) at `bit_offset` of a bit-packed array
+pub(crate) fn read_bits(data: &[u8], bit_offset: usize, bit_count: u32) -> Option<u64> {
+    let byte_offset = bit_offset / 8;
+    let bit_shift = bit_offset % 8;
+    let bytes =
+        data.get(byte_offset..byte_offset + (bit_shift + bit_count as usize).div_ceil(8))?;
+    let value = bytes
+        .iter()
+        .rev()
+        .fold(0u128, |value, &byte| (value << 8) | byte as u128);
+    let mask = if bit_count >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << bit_count) - 1
+    };
+    Some((value >> bit_shift) as u64 & mask)
+}
+
 /// Helper function to decrypt table data
 pub(crate) fn decrypt_table_data(data: &mut [u8], key: u32) {
     use crate::crypto::decrypt_block;
@@ -44,3 +133,61 @@ pub(crate) fn decrypt_table_data(data: &mut [u8], key: u32) {
         data[i * 4..(i + 1) * 4].copy_from_slice(&bytes);
     }
 }
+
+/// Read a table's raw encrypted DWORDs
+pub(crate) fn read_table_dwords<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    entries: u32,
+) -> Result<Vec<u32>> {
+    reader.seek(SeekFrom::Start(offset))?;
+
+    let mut raw_data = vec![0u8; entries as usize * 16];
+    reader.read_exact(&mut raw_data)?;
+
+    Ok(raw_data
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_offset_policy() {
+        // Positions inside the file are used as stored
+        for policy in [TableOffsetPolicy::Strict, TableOffsetPolicy::Lenient] {
+            assert_eq!(policy.resolve(0x200, 0x1000, 0x40, 0x2000, 0x2200), 0x1200);
+        }
+
+        // The header offset plus the position overflows 32 bits
+        let pos = 0x10u32.wrapping_sub(0x200) as u64;
+        assert_eq!(
+            TableOffsetPolicy::Lenient.resolve(0x200, pos, 0x40, 0x2000, 0x2200),
+            0x10
+        );
+        assert_eq!(
+            TableOffsetPolicy::Strict.resolve(0x200, pos, 0x40, 0x2000, 0x2200),
+            0x200 + pos
+        );
+
+        // The position exceeds the archive size by a multiple of it
+        assert_eq!(
+            TableOffsetPolicy::Lenient.resolve(0, 0x1000 + 3 * 0x2000, 0x40, 0x2000, 0x2000),
+            0x1000
+        );
+        // Without a declared size, the file size after the header is used
+        assert_eq!(
+            TableOffsetPolicy::Lenient.resolve(0x200, 0x1000 + 0x2000, 0x40, 0, 0x2200),
+            0x1200
+        );
+
+        // Nothing fits: the stored position is kept
+        assert_eq!(
+            TableOffsetPolicy::Lenient.resolve(0, 0x1FF0 + 0x2000, 0x40, 0x2000, 0x2000),
+            0x3FF0
+        );
+    }
+}
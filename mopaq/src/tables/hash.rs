@@ -1,13 +1,60 @@
 //! Hash table implementation for MPQ archives
 
-use super::common::ReadLittleEndian;
-use crate::crypto::{decrypt_block, hash_string, hash_type};
+use super::common::{read_table_dwords, ReadLittleEndian};
+use crate::crypto::{
+    decrypt_block, detect_key_by_known_plaintext, hash_string, hash_type, NameHashingPolicy,
+    PrecomputedName,
+};
 use crate::{Error, Result};
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek};
+
+/// How the vestigial `platform` field of hash entries is treated
+///
+/// No shipped Blizzard game ever used a nonzero platform code, but some
+/// third-party tools and protectors write arbitrary values there. The policy
+/// decides whether such values are rejected, tolerated or carried over when
+/// archives are read and written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlatformPolicy {
+    /// Reject any valid hash entry with a nonzero platform code
+    Strict,
+    /// Accept nonzero platform codes on read (with a warning) and write 0
+    #[default]
+    Lenient,
+    /// Accept nonzero platform codes and write them back unchanged
+    Preserve,
+}
+
+impl PlatformPolicy {
+    /// Resolve the platform code that should be written for an entry
+    ///
+    /// Returns an error under [`PlatformPolicy::Strict`] if `platform` is nonzero.
+    pub fn resolve(self, filename: &str, platform: u16) -> Result<u16> {
+        if platform == 0 {
+            return Ok(0);
+        }
+
+        match self {
+            PlatformPolicy::Strict => Err(Error::hash_table(format!(
+                "Nonzero platform code 0x{:04X} for '{}'",
+                platform, filename
+            ))),
+            PlatformPolicy::Lenient => {
+                log::warn!(
+                    "Normalizing platform code 0x{:04X} to 0 for '{}'",
+                    platform,
+                    filename
+                );
+                Ok(0)
+            }
+            PlatformPolicy::Preserve => Ok(platform),
+        }
+    }
+}
 
 /// Hash table entry (16 bytes)
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct HashEntry {
     /// The hash of the full file name (part A)
     pub name_1: u32,
@@ -15,7 +62,7 @@ pub struct HashEntry {
     pub name_2: u32,
     /// The language of the file (Windows LANGID)
     pub locale: u16,
-    /// The platform the file is used for (vestigial - always 0 in practice)
+    /// The platform the file is used for (vestigial - see [`PlatformPolicy`])
     pub platform: u16,
     /// Block table index or special value
     pub block_index: u32,
@@ -90,44 +137,91 @@ impl HashTable {
 
     /// Read and decrypt a hash table from the archive
     pub fn read<R: Read + Seek>(reader: &mut R, offset: u64, size: u32) -> Result<Self> {
+        let key = hash_string("(hash table)", hash_type::FILE_KEY);
+        Self::read_with_key(reader, offset, size, key)
+    }
+
+    /// Read a hash table encrypted with a specific key
+    ///
+    /// Used for archives whose hash table was not encrypted with the standard
+    /// key; see [`HashTable::recover_key`].
+    pub fn read_with_key<R: Read + Seek>(
+        reader: &mut R,
+        offset: u64,
+        size: u32,
+        key: u32,
+    ) -> Result<Self> {
         // Validate size
         if !crate::is_power_of_two(size) {
             return Err(Error::hash_table("Hash table size must be power of 2"));
         }
 
-        // Seek to hash table position
-        reader.seek(SeekFrom::Start(offset))?;
+        let mut dwords = read_table_dwords(reader, offset, size)?;
+        decrypt_block(&mut dwords, key);
 
-        // Read raw data
-        let byte_size = size as usize * 16; // 16 bytes per entry
-        let mut raw_data = vec![0u8; byte_size];
-        reader.read_exact(&mut raw_data)?;
-
-        // Decrypt the table - SAFE VERSION
-        let key = hash_string("(hash table)", hash_type::FILE_KEY);
+        Ok(Self::from_dwords(&dwords))
+    }
 
-        // Convert to u32s, decrypt, then convert back
-        let mut u32_buffer: Vec<u32> = raw_data
-            .chunks_exact(4)
-            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-            .collect();
+    /// Recover the key of a hash table encrypted with a non-standard key
+    ///
+    /// The first entry of most hash tables is unused, which gives four DWORDs
+    /// of known plaintext: StormLib fills unused entries with `0xFFFFFFFF`,
+    /// this crate writes zero name hashes, and both store
+    /// [`HashEntry::EMPTY_NEVER_USED`] as the block index. A candidate key is
+    /// only accepted if the whole table then decrypts to entries that are
+    /// unused or point into a block table of `block_table_size` entries.
+    ///
+    /// Returns `Ok(None)` if no key was found, which also happens when the
+    /// first entry is occupied.
+    pub fn recover_key<R: Read + Seek>(
+        reader: &mut R,
+        offset: u64,
+        size: u32,
+        block_table_size: u32,
+    ) -> Result<Option<u32>> {
+        if !crate::is_power_of_two(size) {
+            return Err(Error::hash_table("Hash table size must be power of 2"));
+        }
 
-        decrypt_block(&mut u32_buffer, key);
+        let dwords = read_table_dwords(reader, offset, size)?;
+
+        Ok([HashEntry::EMPTY_NEVER_USED, 0]
+            .into_iter()
+            .find_map(|first| {
+                detect_key_by_known_plaintext(&dwords, first, |decrypted| {
+                    let table = Self::from_dwords(decrypted);
+                    let head = table.entries[0];
+                    (head.is_empty() || head.is_deleted())
+                        && head.name_2 == head.name_1
+                        && table.is_plausible(block_table_size)
+                })
+            }))
+    }
 
-        // Write decrypted u32s back to bytes
-        for (chunk, &decrypted) in raw_data.chunks_exact_mut(4).zip(&u32_buffer) {
-            chunk.copy_from_slice(&decrypted.to_le_bytes());
-        }
+    /// Check whether every entry is unused or refers to an existing block
+    ///
+    /// A table decrypted with the wrong key is almost never plausible, so this
+    /// is used to tell whether the standard key was the right one.
+    pub fn is_plausible(&self, block_table_size: u32) -> bool {
+        self.entries
+            .iter()
+            .all(|e| e.is_empty() || e.is_deleted() || e.block_index < block_table_size)
+    }
 
-        // Parse entries
-        let mut entries = Vec::with_capacity(size as usize);
-        for i in 0..size as usize {
-            let offset = i * 16;
-            let entry = HashEntry::from_bytes(&raw_data[offset..offset + 16])?;
-            entries.push(entry);
-        }
+    /// Parse entries from decrypted table DWORDs
+    fn from_dwords(dwords: &[u32]) -> Self {
+        let entries = dwords
+            .chunks_exact(4)
+            .map(|d| HashEntry {
+                name_1: d[0],
+                name_2: d[1],
+                locale: d[2] as u16,
+                platform: (d[2] >> 16) as u16,
+                block_index: d[3],
+            })
+            .collect();
 
-        Ok(Self { entries })
+        Self { entries }
     }
 
     /// Get all entries
@@ -147,10 +241,26 @@ impl HashTable {
 
     /// Find a file in the hash table
     pub fn find_file(&self, filename: &str, locale: u16) -> Option<(usize, &HashEntry)> {
-        // Calculate hash values
-        let name_a = hash_string(filename, hash_type::NAME_A);
-        let name_b = hash_string(filename, hash_type::NAME_B);
-        let start_index = hash_string(filename, hash_type::TABLE_OFFSET) as usize;
+        self.find_file_with(filename, locale, NameHashingPolicy::default())
+    }
+
+    /// Find a file in a hash table whose names were hashed with `policy`
+    pub fn find_file_with(
+        &self,
+        filename: &str,
+        locale: u16,
+        policy: NameHashingPolicy,
+    ) -> Option<(usize, &HashEntry)> {
+        self.find_file_pre(&PrecomputedName::with_policy(filename, policy), locale)
+    }
+
+    /// Find a file by its precomputed hashes
+    pub fn find_file_pre(
+        &self,
+        name: &PrecomputedName,
+        locale: u16,
+    ) -> Option<(usize, &HashEntry)> {
+        let start_index = name.table_offset() as usize;
 
         let table_size = self.entries.len();
         let mut index = start_index & (table_size - 1);
@@ -160,7 +270,7 @@ impl HashTable {
             let entry = &self.entries[index];
 
             // Check if this is our file
-            if entry.name_1 == name_a && entry.name_2 == name_b {
+            if entry.name_1 == name.name_a() && entry.name_2 == name.name_b() {
                 // Check locale (0 = default/any locale)
                 if (locale == 0 || entry.locale == 0 || entry.locale == locale) && entry.is_valid()
                 {
@@ -183,6 +293,72 @@ impl HashTable {
         }
     }
 
+    /// Find every entry of a file, one per locale and platform it is stored
+    /// in, in probe order
+    pub fn find_all_with(
+        &self,
+        filename: &str,
+        policy: NameHashingPolicy,
+    ) -> Vec<(usize, &HashEntry)> {
+        self.find_all_pre(&PrecomputedName::with_policy(filename, policy))
+    }
+
+    /// Find every entry of a file by its precomputed hashes
+    pub fn find_all_pre(&self, name: &PrecomputedName) -> Vec<(usize, &HashEntry)> {
+        let table_size = self.entries.len();
+        let start = name.table_offset() as usize & (table_size - 1);
+        let mut found = Vec::new();
+        let mut index = start;
+        loop {
+            let entry = &self.entries[index];
+            if entry.is_empty() {
+                break;
+            }
+            if entry.name_1 == name.name_a() && entry.name_2 == name.name_b() && entry.is_valid() {
+                found.push((index, entry));
+            }
+
+            index = (index + 1) & (table_size - 1);
+            if index == start {
+                break;
+            }
+        }
+        found
+    }
+
+    /// Check the platform codes of all valid entries against a policy
+    ///
+    /// Under [`PlatformPolicy::Strict`] the first valid entry with a nonzero
+    /// platform code produces an error. [`PlatformPolicy::Lenient`] only logs
+    /// a warning, and [`PlatformPolicy::Preserve`] accepts every value.
+    pub fn validate_platforms(&self, policy: PlatformPolicy) -> Result<()> {
+        let mut nonzero = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.is_valid() && e.platform != 0);
+
+        match policy {
+            PlatformPolicy::Strict => {
+                if let Some((index, entry)) = nonzero.next() {
+                    return Err(Error::hash_table(format!(
+                        "Hash entry {} has nonzero platform code 0x{:04X}",
+                        index, entry.platform
+                    )));
+                }
+            }
+            PlatformPolicy::Lenient => {
+                let count = nonzero.count();
+                if count > 0 {
+                    log::warn!("{} hash entries have nonzero platform codes", count);
+                }
+            }
+            PlatformPolicy::Preserve => {}
+        }
+
+        Ok(())
+    }
+
     /// Create a new hash table with mutable entries
     pub fn new_mut(size: usize) -> Result<Self> {
         // Validate size is power of 2
@@ -246,6 +422,27 @@ mod tests {
         assert!(valid.is_valid());
     }
 
+    #[test]
+    fn test_platform_policy() {
+        let mut table = HashTable::new(16).unwrap();
+        table.get_mut(3).unwrap().block_index = 0;
+        assert!(table.validate_platforms(PlatformPolicy::Strict).is_ok());
+
+        table.get_mut(3).unwrap().platform = 0x0002;
+        assert!(table.validate_platforms(PlatformPolicy::Strict).is_err());
+        assert!(table.validate_platforms(PlatformPolicy::Lenient).is_ok());
+        assert!(table.validate_platforms(PlatformPolicy::Preserve).is_ok());
+
+        // Nonzero platform codes in empty slots are ignored
+        table.get_mut(3).unwrap().block_index = HashEntry::EMPTY_NEVER_USED;
+        assert!(table.validate_platforms(PlatformPolicy::Strict).is_ok());
+
+        assert!(PlatformPolicy::Strict.resolve("a", 2).is_err());
+        assert_eq!(PlatformPolicy::Strict.resolve("a", 0).unwrap(), 0);
+        assert_eq!(PlatformPolicy::Lenient.resolve("a", 2).unwrap(), 0);
+        assert_eq!(PlatformPolicy::Preserve.resolve("a", 2).unwrap(), 2);
+    }
+
     #[test]
     fn test_hash_table_size_validation() {
         // Valid sizes (powers of 2)
@@ -1,8 +1,9 @@
 //! HET (Hash Extended Table) implementation for MPQ v3+ archives
 
-use super::common::{decrypt_table_data, ReadLittleEndian};
+use super::common::{decrypt_table_data, read_bits, ReadLittleEndian};
+use super::BetTable;
 use crate::compression::decompress;
-use crate::crypto::jenkins_hash;
+use crate::crypto::{jenkins_hash_with, NameHashingPolicy, PrecomputedName};
 use crate::{Error, Result};
 use std::io::{Read, Seek, SeekFrom};
 
@@ -11,9 +12,9 @@ use std::io::{Read, Seek, SeekFrom};
 pub struct HetTable {
     /// Table header data
     pub header: HetHeader,
-    /// Hash table data (variable bit entries)
+    /// Top 8 bits of each slot's name hash, 0 for a free slot
     pub hash_table: Vec<u8>,
-    /// File index data (variable bit entries)
+    /// BET index of each slot (bit-packed entries)
     pub file_indices: Vec<u8>,
 }
 
@@ -39,11 +40,11 @@ pub struct HetHeader {
     pub table_size: u32,
     /// Maximum number of files in the MPQ
     pub max_file_count: u32,
-    /// Size of the hash table in bytes
+    /// Number of hash table slots, one byte each
     pub hash_table_size: u32,
-    /// Effective size of the hash entry in bits
+    /// Bits of the Jenkins name hash used by HET and BET
     pub hash_entry_size: u32,
-    /// Total size of file index in bits
+    /// Size of one file index entry in bits, including extra bits
     pub total_index_size: u32,
     /// Extra bits in the file index
     pub index_size_extra: u32,
@@ -167,14 +168,21 @@ impl HetTable {
         // No need to validate signature/version - they're in the extended header
         // which we already validated above
 
+        if !(8..=64).contains(&hash_entry_size) {
+            return Err(Error::invalid_format(format!(
+                "Invalid HET name hash size: {} bits",
+                hash_entry_size
+            )));
+        }
+
         // Extract hash table and file indices - data starts after extended header
         let data_start = 12; // Extended header size
         let header_size = std::mem::size_of::<HetHeader>();
         let hash_table_start = data_start + header_size;
-        let hash_table_end = hash_table_start + header.hash_table_size as usize;
+        let hash_table_end = hash_table_start + hash_table_size as usize;
 
         let file_indices_start = hash_table_end;
-        let file_indices_size = (header.total_index_size as usize).div_ceil(8); // Convert bits to bytes
+        let file_indices_size = (hash_table_size as usize * total_index_size as usize).div_ceil(8); // Convert bits to bytes
         let file_indices_end = file_indices_start + file_indices_size;
 
         log::debug!(
@@ -225,68 +233,114 @@ impl HetTable {
         })
     }
 
-    /// Find a file in the HET table
-    pub fn find_file(&self, filename: &str) -> Option<u32> {
-        let hash = jenkins_hash(filename);
-        let hash_mask = if self.header.hash_entry_size >= 64 {
-            u64::MAX
-        } else {
-            (1u64 << self.header.hash_entry_size) - 1
-        };
-        let index_mask = if self.header.index_size >= 64 {
-            u64::MAX
-        } else {
-            (1u64 << self.header.index_size) - 1
-        };
-
-        // Calculate hash table index
-        let hash_table_entries = self.header.hash_table_size * 8 / self.header.hash_entry_size;
-        let hash_index = (hash & (hash_table_entries as u64 - 1)) as usize;
+    /// Find a file's index in the BET table
+    ///
+    /// Slots are probed from the one the name hashes to until a free slot.
+    /// A slot only holds the top 8 bits of the name hash, so each match is
+    /// confirmed against the rest of the hash stored in `bet` before its
+    /// index is returned.
+    pub fn find_file(&self, filename: &str, bet: &BetTable) -> Option<u32> {
+        self.find_file_with(filename, bet, NameHashingPolicy::default())
+    }
 
-        // Read hash entry
-        let hash_entry = self.read_hash_entry(hash_index)?;
-        let name_hash = hash & hash_mask;
+    /// Find a file's index in a BET table whose names were hashed with `policy`
+    pub fn find_file_with(
+        &self,
+        filename: &str,
+        bet: &BetTable,
+        policy: NameHashingPolicy,
+    ) -> Option<u32> {
+        self.find_file_pre(&PrecomputedName::with_policy(filename, policy), bet)
+    }
 
-        if (hash_entry & hash_mask) != name_hash {
-            return None; // Hash mismatch
+    /// Find a file by its precomputed hashes, returning its BET index
+    pub fn find_file_pre(&self, name: &PrecomputedName, bet: &BetTable) -> Option<u32> {
+        let slot_count = self.hash_table.len();
+        if slot_count == 0 {
+            return None;
         }
 
-        // Extract file index from hash entry
-        let file_index = (hash_entry >> self.header.hash_entry_size) & index_mask;
-
-        // Verify file index is valid
-        if file_index >= self.header.max_file_count as u64 {
-            return None;
+        let bits = self.header.hash_entry_size;
+        let hash = masked_name_hash(name.jenkins(), bits);
+        let slot_hash = (hash >> (bits - 8)) as u8;
+        let bet_hash = hash & (hash_mask(bits) >> 8);
+
+        let start = self.start_slot(name);
+        for probe in 0..slot_count {
+            let slot = (start + probe) % slot_count;
+            match self.hash_table[slot] {
+                0 => return None,
+                entry if entry == slot_hash => {
+                    let Some(index) = self.file_index(slot) else {
+                        continue;
+                    };
+                    if bet.name_hash(index) == Some(bet_hash) {
+                        return Some(index);
+                    }
+                }
+                _ => {}
+            }
         }
 
-        Some(file_index as u32)
+        None
     }
 
-    /// Read a hash entry from bit-packed data
-    fn read_hash_entry(&self, index: usize) -> Option<u64> {
-        let bit_offset = index * self.header.hash_entry_size as usize;
-        let byte_offset = bit_offset / 8;
-        let bit_shift = bit_offset % 8;
+    /// Slot the lookup of `name` starts probing at
+    pub(crate) fn start_slot(&self, name: &PrecomputedName) -> usize {
+        let hash = masked_name_hash(name.jenkins(), self.header.hash_entry_size);
+        (hash % self.hash_table.len().max(1) as u64) as usize
+    }
 
-        if byte_offset + 8 > self.hash_table.len() {
-            return None;
-        }
+    /// Full name hash of every file in the table, as `(BET index, hash)`
+    ///
+    /// The top 8 bits come from the file's slot and the rest from `bet`,
+    /// giving the value [`find_file`](Self::find_file) computes from a name.
+    pub(crate) fn name_hashes<'a>(
+        &'a self,
+        bet: &'a BetTable,
+    ) -> impl Iterator<Item = (u32, u64)> + 'a {
+        let bits = self.header.hash_entry_size;
+        self.hash_table
+            .iter()
+            .enumerate()
+            .filter(|(_, &slot_hash)| slot_hash != 0)
+            .filter_map(move |(slot, &slot_hash)| {
+                let index = self.file_index(slot)?;
+                let bet_hash = bet.name_hash(index)?;
+                Some((index, ((slot_hash as u64) << (bits - 8)) | bet_hash))
+            })
+    }
 
-        // Read 64 bits starting from byte_offset
-        let mut value = 0u64;
-        for i in 0..8 {
-            if byte_offset + i < self.hash_table.len() {
-                value |= (self.hash_table[byte_offset + i] as u64) << (i * 8);
-            }
-        }
+    /// BET index stored for a hash table slot
+    fn file_index(&self, slot: usize) -> Option<u32> {
+        let index = read_bits(
+            &self.file_indices,
+            slot * self.header.total_index_size as usize,
+            self.header.index_size,
+        )?;
+        (index < self.header.max_file_count as u64).then_some(index as u32)
+    }
+}
 
-        // Shift and mask to get the actual entry
-        let mask = if self.header.hash_entry_size >= 64 {
-            u64::MAX
-        } else {
-            (1u64 << self.header.hash_entry_size) - 1
-        };
-        let entry = (value >> bit_shift) & mask;
-        Some(entry)
+/// All-ones mask of the low `bits` bits
+fn hash_mask(bits: u32) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << bits) - 1
     }
 }
+
+/// The Jenkins hash of `filename` as HET and BET tables with `bits` hash bits
+/// store it
+///
+/// The top bit is always set, so the 8 bits kept in a HET slot are never
+/// mistaken for a free slot.
+pub(crate) fn name_hash(filename: &str, bits: u32, policy: NameHashingPolicy) -> u64 {
+    masked_name_hash(jenkins_hash_with(filename, policy), bits)
+}
+
+/// [`name_hash`] from an already computed Jenkins hash
+fn masked_name_hash(jenkins: u64, bits: u32) -> u64 {
+    (jenkins & hash_mask(bits)) | (1u64 << (bits - 1))
+}
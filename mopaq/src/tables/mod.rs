@@ -9,7 +9,12 @@ mod het;
 // Re-export all public types
 pub use bet::{BetFileInfo, BetHeader, BetTable};
 pub use block::{BlockEntry, BlockTable, HiBlockTable};
-pub use hash::{HashEntry, HashTable};
+pub use common::{TableKey, TableOffsetPolicy};
+pub use hash::{HashEntry, HashTable, PlatformPolicy};
 pub use het::{HetHeader, HetTable};
 
+#[cfg(feature = "cache")]
+pub(crate) use common::ReadLittleEndian;
+pub(crate) use het::name_hash;
+
 // Re-export common utilities if needed
@@ -0,0 +1,193 @@
+//! Directory tree view of archive contents
+//!
+//! MPQ archives have a flat namespace: directories only exist as prefixes of
+//! backslash-separated file names. [`DirNode`] rebuilds the hierarchy from a
+//! file listing so that tools can browse an archive like a file system, with
+//! file counts and sizes rolled up per directory.
+//!
+//! Like the hash table, directory and file names are matched
+//! case-insensitively and `/` is treated the same as `\`. The first spelling
+//! seen for a directory is the one kept.
+
+use crate::FileEntry;
+
+/// A file in a [`DirNode`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeFile {
+    /// Last component of the file name
+    pub name: String,
+    /// Full name as listed in the archive
+    pub path: String,
+    /// Uncompressed size
+    pub size: u64,
+    /// Size stored in the archive
+    pub compressed_size: u64,
+}
+
+/// A directory and everything below it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirNode {
+    /// Directory name, empty for the root
+    pub name: String,
+    /// Full path of the directory, without a trailing separator
+    pub path: String,
+    /// Subdirectories, sorted by name
+    pub dirs: Vec<DirNode>,
+    /// Files directly in this directory, sorted by name
+    pub files: Vec<TreeFile>,
+    /// Number of files in this directory and all subdirectories
+    pub file_count: usize,
+    /// Uncompressed size of all files in this directory and below
+    pub total_size: u64,
+    /// Stored size of all files in this directory and below
+    pub total_compressed_size: u64,
+}
+
+impl DirNode {
+    /// Build a tree from a file listing such as [`Archive::list`](crate::Archive::list)
+    pub fn from_entries<'a>(entries: impl IntoIterator<Item = &'a FileEntry>) -> Self {
+        let mut root = DirNode::default();
+        for entry in entries {
+            root.insert(entry);
+        }
+        root.sort();
+        root
+    }
+
+    /// Look up a directory by path
+    ///
+    /// An empty path returns this node.
+    pub fn find(&self, path: &str) -> Option<&DirNode> {
+        components(path).try_fold(self, |dir, component| {
+            dir.dirs
+                .iter()
+                .find(|child| child.name.eq_ignore_ascii_case(component))
+        })
+    }
+
+    /// Visit this directory and every directory below it, parents first
+    ///
+    /// The callback receives each node and its depth below this one.
+    pub fn walk<F: FnMut(&DirNode, usize)>(&self, mut visit: F) {
+        fn walk_inner<F: FnMut(&DirNode, usize)>(node: &DirNode, depth: usize, visit: &mut F) {
+            visit(node, depth);
+            for child in &node.dirs {
+                walk_inner(child, depth + 1, visit);
+            }
+        }
+        walk_inner(self, 0, &mut visit);
+    }
+
+    fn insert(&mut self, entry: &FileEntry) {
+        let parts: Vec<&str> = components(&entry.name).collect();
+        let Some((file_name, dirs)) = parts.split_last() else {
+            return;
+        };
+
+        let mut node = self;
+        node.add_totals(entry);
+        for &component in dirs {
+            let index = match node
+                .dirs
+                .iter()
+                .position(|child| child.name.eq_ignore_ascii_case(component))
+            {
+                Some(index) => index,
+                None => {
+                    let path = if node.path.is_empty() {
+                        component.to_string()
+                    } else {
+                        format!("{}\\{}", node.path, component)
+                    };
+                    node.dirs.push(DirNode {
+                        name: component.to_string(),
+                        path,
+                        ..DirNode::default()
+                    });
+                    node.dirs.len() - 1
+                }
+            };
+            node = &mut node.dirs[index];
+            node.add_totals(entry);
+        }
+
+        node.files.push(TreeFile {
+            name: file_name.to_string(),
+            path: entry.name.clone(),
+            size: entry.size,
+            compressed_size: entry.compressed_size,
+        });
+    }
+
+    fn add_totals(&mut self, entry: &FileEntry) {
+        self.file_count += 1;
+        self.total_size += entry.size;
+        self.total_compressed_size += entry.compressed_size;
+    }
+
+    fn sort(&mut self) {
+        self.dirs.sort_by_key(|dir| dir.name.to_ascii_lowercase());
+        self.files
+            .sort_by_key(|file| file.name.to_ascii_lowercase());
+        for dir in &mut self.dirs {
+            dir.sort();
+        }
+    }
+}
+
+/// Non-empty components of a backslash- or slash-separated name
+fn components(name: &str) -> impl Iterator<Item = &str> {
+    name.split(['\\', '/']).filter(|part| !part.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, size: u64) -> FileEntry {
+        FileEntry {
+            name: name.to_string(),
+            size,
+            compressed_size: size / 2,
+            flags: 0,
+            hashes: None,
+        }
+    }
+
+    #[test]
+    fn test_tree_from_entries() {
+        let entries = [
+            entry("Units\\Human\\footman.mdx", 100),
+            entry("units/human/Peasant.mdx", 50),
+            entry("units\\Orc\\grunt.mdx", 80),
+            entry("war3map.j", 10),
+        ];
+        let root = DirNode::from_entries(&entries);
+
+        assert_eq!(root.file_count, 4);
+        assert_eq!(root.total_size, 240);
+        assert_eq!(root.files.len(), 1);
+        assert_eq!(root.dirs.len(), 1);
+
+        // Directories merge case-insensitively and keep the first spelling
+        let human = root.find("UNITS/human").unwrap();
+        assert_eq!(human.path, "Units\\Human");
+        assert_eq!(human.file_count, 2);
+        assert_eq!(human.total_compressed_size, 75);
+        assert_eq!(human.files[0].name, "footman.mdx");
+        assert_eq!(human.files[1].path, "units/human/Peasant.mdx");
+        assert!(root.find("units\\elf").is_none());
+
+        let mut visited = Vec::new();
+        root.walk(|dir, depth| visited.push((dir.name.clone(), depth)));
+        assert_eq!(
+            visited,
+            [
+                (String::new(), 0),
+                ("Units".to_string(), 1),
+                ("Human".to_string(), 2),
+                ("Orc".to_string(), 2),
+            ]
+        );
+    }
+}
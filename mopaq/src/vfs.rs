@@ -0,0 +1,354 @@
+//! One file system over several archives and loose-file directories
+//!
+//! Game engines rarely read from a single archive. They mount the game's
+//! archives, mods and a directory of loose files, and look every file up in
+//! whichever of them takes precedence. An [`MpqVfs`] does that lookup:
+//!
+//! - Loose files in a mounted directory override archive contents
+//! - Among archives, and among directories, a higher priority wins
+//! - At equal priority, the source mounted later wins, as in a
+//!   [`PatchChain`](crate::PatchChain)
+//!
+//! Names use archive conventions on every platform, so
+//! `Units\Human\Footman.mdx` and `units/human/footman.mdx` name the same
+//! file, whether it is archived or loose. Loose files are matched ignoring
+//! ASCII case even on case-sensitive file systems, preferring an exact
+//! match where both exist.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use mopaq::MpqVfs;
+//!
+//! let mut vfs = MpqVfs::new();
+//! vfs.mount_archive("Data/common.MPQ", 0)?;
+//! vfs.mount_archive("Data/patch.MPQ", 1)?;
+//! vfs.mount_directory("Mods/Override", 0)?;
+//!
+//! let data = vfs.read("Interface\\FrameXML\\UIParent.lua")?;
+//! # Ok::<(), mopaq::Error>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::special_files::get_special_file_info;
+use crate::{Archive, Error, NameHashingPolicy, Result};
+
+/// Where an [`MpqVfs`] finds a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VfsSource {
+    /// Inside the archive at this path
+    Archive(PathBuf),
+    /// Under this mounted directory
+    Directory(PathBuf),
+}
+
+/// A file of an [`MpqVfs`] and the source it is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsEntry {
+    /// File name as listed by the winning source, with `\` separators
+    pub name: String,
+    /// Source the file is read from
+    pub source: VfsSource,
+    /// Uncompressed size in that source
+    pub size: u64,
+}
+
+/// A mounted archive or directory
+#[derive(Debug)]
+enum Mount {
+    Archive(PathBuf, Box<Archive>),
+    Directory(PathBuf),
+}
+
+impl Mount {
+    fn path(&self) -> &Path {
+        match self {
+            Mount::Archive(path, _) | Mount::Directory(path) => path,
+        }
+    }
+
+    fn source(&self) -> VfsSource {
+        match self {
+            Mount::Archive(path, _) => VfsSource::Archive(path.clone()),
+            Mount::Directory(path) => VfsSource::Directory(path.clone()),
+        }
+    }
+}
+
+/// Archives and loose-file directories searched as one
+///
+/// See the [module documentation](self) for the lookup order.
+#[derive(Debug, Default)]
+pub struct MpqVfs {
+    /// Mounts with their priority, searched from the last
+    mounts: Vec<(i32, Mount)>,
+}
+
+impl MpqVfs {
+    /// Create a VFS with nothing mounted
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open the archive at `path` and mount it with `priority`
+    ///
+    /// # Errors
+    /// - Any error from opening the archive
+    pub fn mount_archive(&mut self, path: impl AsRef<Path>, priority: i32) -> Result<()> {
+        let path = path.as_ref();
+        let archive = Archive::open(path)?;
+        self.mount_opened(path, archive, priority);
+        Ok(())
+    }
+
+    /// Mount an archive that is already open, known by `path`
+    pub fn mount_opened(&mut self, path: impl AsRef<Path>, archive: Archive, priority: i32) {
+        let path = path.as_ref();
+        log::debug!("Mounting {} with priority {}", path.display(), priority);
+        self.insert(
+            priority,
+            Mount::Archive(path.to_path_buf(), Box::new(archive)),
+        );
+    }
+
+    /// Mount the loose files under the directory at `path` with `priority`
+    ///
+    /// The directory is read at lookup time, so files added to it later are
+    /// found too.
+    ///
+    /// # Errors
+    /// - `Error::Io` if `path` is not a directory
+    pub fn mount_directory(&mut self, path: impl AsRef<Path>, priority: i32) -> Result<()> {
+        let path = path.as_ref();
+        check_directory(path)?;
+        log::debug!("Mounting {} with priority {}", path.display(), priority);
+        self.insert(priority, Mount::Directory(path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Keep mounts ordered so that the last one takes precedence
+    fn insert(&mut self, priority: i32, mount: Mount) {
+        let rank = |priority: i32, mount: &Mount| (matches!(mount, Mount::Directory(_)), priority);
+        let new_rank = rank(priority, &mount);
+        let index = self
+            .mounts
+            .partition_point(|(priority, mount)| rank(*priority, mount) <= new_rank);
+        self.mounts.insert(index, (priority, mount));
+    }
+
+    /// Remove every mount of `path`
+    ///
+    /// Returns whether anything was mounted there.
+    pub fn unmount(&mut self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        let before = self.mounts.len();
+        self.mounts.retain(|(_, mount)| mount.path() != path);
+        self.mounts.len() != before
+    }
+
+    /// Mounted sources with their priorities, from the one searched last to
+    /// the one searched first
+    pub fn mounts(&self) -> impl Iterator<Item = (VfsSource, i32)> + '_ {
+        self.mounts
+            .iter()
+            .map(|(priority, mount)| (mount.source(), *priority))
+    }
+
+    /// Number of mounted sources
+    pub fn len(&self) -> usize {
+        self.mounts.len()
+    }
+
+    /// Whether nothing is mounted
+    pub fn is_empty(&self) -> bool {
+        self.mounts.is_empty()
+    }
+
+    /// The source a file is read from
+    ///
+    /// Returns `None` if no source has the file.
+    ///
+    /// # Errors
+    /// - Any error from looking the file up in one of the archives
+    pub fn locate(&self, name: &str) -> Result<Option<VfsSource>> {
+        for (_, mount) in self.mounts.iter().rev() {
+            let found = match mount {
+                Mount::Archive(_, archive) => archive.find_file(name)?.is_some(),
+                Mount::Directory(dir) => find_loose_file(dir, name).is_some(),
+            };
+            if found {
+                return Ok(Some(mount.source()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check whether any source has a file
+    ///
+    /// # Errors
+    /// - Any error from looking the file up in one of the archives
+    pub fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.locate(name)?.is_some())
+    }
+
+    /// Read a file from the source that takes precedence
+    ///
+    /// # Errors
+    /// - `Error::FileNotFound` if no source has the file
+    /// - Any error from reading the file
+    pub fn read(&self, name: &str) -> Result<Vec<u8>> {
+        for (_, mount) in self.mounts.iter().rev() {
+            match mount {
+                Mount::Archive(_, archive) => {
+                    if archive.find_file(name)?.is_some() {
+                        return archive.read_file(name);
+                    }
+                }
+                Mount::Directory(dir) => {
+                    if let Some(path) = find_loose_file(dir, name) {
+                        return Ok(fs::read(path)?);
+                    }
+                }
+            }
+        }
+        Err(Error::FileNotFound(name.to_string()))
+    }
+
+    /// Every named file with the source it is read from
+    ///
+    /// Archives contribute the names in their `(listfile)`, leaving out
+    /// special files; archives without one contribute nothing. Directories
+    /// contribute every file below them. Names differing only in case or
+    /// separators are the same file. Entries are sorted by name.
+    ///
+    /// # Errors
+    /// - `Error::Io` if a mounted directory cannot be read
+    /// - Any error from listing one of the archives
+    pub fn list(&mut self) -> Result<Vec<VfsEntry>> {
+        let policy = NameHashingPolicy::default();
+        let mut files = BTreeMap::new();
+        for (_, mount) in &mut self.mounts {
+            match mount {
+                Mount::Archive(path, archive) => {
+                    if archive.find_file("(listfile)")?.is_none() {
+                        log::warn!("{} has no (listfile), skipping its files", path.display());
+                        continue;
+                    }
+                    for entry in archive.list()? {
+                        if get_special_file_info(&entry.name).is_some() {
+                            continue;
+                        }
+                        files.insert(
+                            policy.normalize_name(&entry.name),
+                            VfsEntry {
+                                name: entry.name,
+                                source: VfsSource::Archive(path.clone()),
+                                size: entry.size,
+                            },
+                        );
+                    }
+                }
+                Mount::Directory(dir) => {
+                    for (name, size) in list_loose_files(dir)? {
+                        files.insert(
+                            policy.normalize_name(&name),
+                            VfsEntry {
+                                name,
+                                source: VfsSource::Directory(dir.clone()),
+                                size,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut entries: Vec<VfsEntry> = files.into_values().collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+}
+
+/// Fail with `Error::Io` unless `path` is a directory
+pub(crate) fn check_directory(path: &Path) -> Result<()> {
+    if fs::metadata(path)?.is_dir() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotADirectory,
+        format!("{} is not a directory", path.display()),
+    )
+    .into())
+}
+
+/// Path of the loose file `name` names under `dir`, if it is a file
+///
+/// Each part of `name` matches the directory entry of that name, or else
+/// the first one equal to it ignoring ASCII case. Names that would leave
+/// `dir`, through `..` or a root, name nothing.
+pub(crate) fn find_loose_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+    for part in name.split(['\\', '/']).filter(|part| !part.is_empty()) {
+        if !matches!(
+            Path::new(part).components().next(),
+            Some(Component::Normal(_))
+        ) {
+            return None;
+        }
+        let exact = path.join(part);
+        path = if exact.exists() {
+            exact
+        } else {
+            entry_ignoring_case(&path, part)?
+        };
+    }
+    path.is_file().then_some(path)
+}
+
+/// The entry of `dir` named `part` ignoring ASCII case, the first by name
+/// if there are several
+fn entry_ignoring_case(dir: &Path, part: &str) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.eq_ignore_ascii_case(part))
+        })
+        .map(|entry| entry.path())
+        .min()
+}
+
+/// Every file below `dir` as a `\`-separated name with its size
+pub(crate) fn list_loose_files(dir: &Path) -> Result<Vec<(String, u64)>> {
+    let mut files = Vec::new();
+    collect_loose_files(dir, "", &mut files)?;
+    Ok(files)
+}
+
+/// Add the files below `dir` to `files` as `\`-separated names under
+/// `prefix`, with their sizes
+fn collect_loose_files(dir: &Path, prefix: &str, files: &mut Vec<(String, u64)>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let name = if prefix.is_empty() {
+            file_name
+        } else {
+            format!("{prefix}\\{file_name}")
+        };
+        let metadata = fs::metadata(entry.path())?;
+        if metadata.is_dir() {
+            collect_loose_files(&entry.path(), &name, files)?;
+        } else if metadata.is_file() {
+            files.push((name, metadata.len()));
+        }
+    }
+    Ok(())
+}
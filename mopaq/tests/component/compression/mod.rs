@@ -3,6 +3,7 @@
 //! Tests individual compression algorithms at the component level.
 
 mod adpcm;
+#[cfg(feature = "compression-bzip2")]
 mod bzip2;
 mod sparse;
 mod zlib;
@@ -37,7 +37,7 @@ fn test_valid_crc_extraction() {
         return;
     }
 
-    let mut archive = Archive::open(test_file).expect("Failed to open test archive");
+    let archive = Archive::open(test_file).expect("Failed to open test archive");
 
     // This should succeed with valid CRCs
     let data = archive
@@ -65,7 +65,7 @@ fn test_single_unit_crc() {
         return;
     }
 
-    let mut archive = Archive::open(test_file).expect("Failed to open test archive");
+    let archive = Archive::open(test_file).expect("Failed to open test archive");
 
     // This should succeed with valid CRC
     let data = archive
@@ -1,6 +1,8 @@
 //! Integration tests for crypto functionality
 
-use mopaq::crypto::{decrypt_block, decrypt_dword, encrypt_block, ENCRYPTION_TABLE};
+use mopaq::crypto::{
+    decrypt_block, decrypt_dword, detect_key_by_known_plaintext, encrypt_block, ENCRYPTION_TABLE,
+};
 
 #[test]
 fn test_encryption_table_is_initialized() {
@@ -107,3 +109,29 @@ fn test_single_dword_consistency() {
         assert_eq!(decrypted_block, value);
     }
 }
+
+#[test]
+fn test_detect_key_by_known_plaintext() {
+    let key = 0xC3AF_3771;
+    let plaintext = vec![
+        0xFFFF_FFFF,
+        0xFFFF_FFFF,
+        0xFFFF_FFFF,
+        0xFFFF_FFFF,
+        0x1234_5678,
+    ];
+    let mut encrypted = plaintext.clone();
+    encrypt_block(&mut encrypted, key);
+
+    let recovered = detect_key_by_known_plaintext(&encrypted, 0xFFFF_FFFF, |decrypted| {
+        decrypted[..4] == plaintext[..4]
+    });
+    assert_eq!(recovered, Some(key));
+
+    // A validator that rejects everything yields no key
+    assert_eq!(
+        detect_key_by_known_plaintext(&encrypted, 0xFFFF_FFFF, |_| false),
+        None
+    );
+    assert_eq!(detect_key_by_known_plaintext(&[], 0, |_| true), None);
+}
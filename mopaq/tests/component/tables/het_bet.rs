@@ -60,7 +60,7 @@ fn test_v3_archive_with_classic_table_compatibility() {
         .expect("Failed to create archive");
 
     // Open and verify
-    let mut archive = Archive::open(&archive_path).expect("Failed to open archive");
+    let archive = Archive::open(&archive_path).expect("Failed to open archive");
 
     // V3 archives now have HET/BET tables
     assert!(archive.het_table().is_some(), "HET table should exist");
@@ -85,6 +85,44 @@ fn test_v3_archive_with_classic_table_compatibility() {
     );
 }
 
+#[test]
+fn test_het_lookup_with_colliding_slot_hashes() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("v3_collisions.mpq");
+
+    // HET slots keep 7 significant bits of the name hash, so 300 names are
+    // bound to share slot hashes and only the BET hash tells them apart
+    let names: Vec<String> = (0..300)
+        .map(|i| format!("data\\file{:03}.txt", i))
+        .collect();
+    let mut builder = ArchiveBuilder::new().version(FormatVersion::V3);
+    for name in &names {
+        builder = builder.add_file_data(name.as_bytes().to_vec(), name);
+    }
+    builder.build(&archive_path).unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    let het = archive.het_table().unwrap();
+    let bet = archive.bet_table().unwrap();
+
+    let mut seen = std::collections::HashSet::new();
+    let used_slots: Vec<u8> = het.hash_table.iter().copied().filter(|&b| b != 0).collect();
+    assert!(used_slots.iter().any(|&b| !seen.insert(b)));
+
+    let mut indices = std::collections::HashSet::new();
+    for name in &names {
+        let index = het.find_file(name, bet).expect("file should be found");
+        assert!(indices.insert(index), "{} resolved to a shared index", name);
+    }
+    for i in 0..2000 {
+        assert_eq!(het.find_file(&format!("missing{}.txt", i), bet), None);
+    }
+
+    for name in names.iter().step_by(37) {
+        assert_eq!(archive.read_file(name).unwrap(), name.as_bytes());
+    }
+}
+
 #[test]
 fn test_jenkins_hash_lookup() {
     use mopaq::jenkins_hash;
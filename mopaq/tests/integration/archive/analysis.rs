@@ -0,0 +1,66 @@
+//! Tests for archive compression analysis
+
+use mopaq::analysis::{analyze_compression, NO_EXTENSION};
+use mopaq::compression::{flags, CompressionMethod};
+use mopaq::{Archive, ArchiveBuilder};
+
+#[test]
+fn test_analyze_compression() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("analysis.mpq");
+
+    // Compressible and spanning several sectors
+    let text: Vec<u8> = b"analysis ".repeat(4_000);
+    ArchiveBuilder::new()
+        .add_file_data_with_options(text.clone(), "docs\\readme.txt", flags::ZLIB, false, 0)
+        .add_file_data_with_options(text.clone(), "docs\\notes.TXT", flags::PKWARE, false, 0)
+        .add_file_data_with_encryption(text.clone(), "secret.bin", flags::ZLIB, true, 0)
+        .add_file_data_with_options(vec![7u8; 100], "raw", 0, false, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(
+        archive.compression_method("secret.bin").unwrap(),
+        CompressionMethod::Zlib
+    );
+
+    let analysis = analyze_compression(&mut archive).unwrap();
+    let method_of = |name: &str| {
+        analysis
+            .files
+            .iter()
+            .find(|file| file.name == name)
+            .map(|file| file.method)
+    };
+    assert_eq!(method_of("docs\\readme.txt"), Some(CompressionMethod::Zlib));
+    assert_eq!(
+        method_of("docs\\notes.TXT"),
+        Some(CompressionMethod::PKWare)
+    );
+    assert_eq!(method_of("secret.bin"), Some(CompressionMethod::Zlib));
+    assert_eq!(method_of("raw"), Some(CompressionMethod::None));
+
+    // Extensions are grouped case-insensitively
+    let txt = &analysis.by_extension["txt"];
+    assert_eq!(txt.iter().map(|entry| entry.count).sum::<usize>(), 2);
+    assert!(analysis.by_extension.contains_key(NO_EXTENSION));
+
+    // The histogram covers every file, most used method first
+    assert_eq!(
+        analysis
+            .methods
+            .iter()
+            .map(|entry| entry.count)
+            .sum::<usize>(),
+        analysis.files.len()
+    );
+    assert!(analysis
+        .methods
+        .windows(2)
+        .all(|pair| pair[0].count >= pair[1].count));
+
+    assert_eq!(analysis.unsupported_files().count(), 0);
+    assert!(analysis.unsupported_methods().is_empty());
+    assert!(analysis.compression_ratio() < 100.0);
+}
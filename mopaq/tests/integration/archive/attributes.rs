@@ -127,11 +127,7 @@ fn test_attributes_roundtrip() {
         file_attrs.push(attr);
     }
 
-    let original = Attributes {
-        version: 100,
-        flags: AttributeFlags::new(AttributeFlags::ALL),
-        file_attributes: file_attrs,
-    };
+    let original = Attributes::new(AttributeFlags::new(AttributeFlags::ALL), file_attrs);
 
     // Convert to bytes
     let bytes = original.to_bytes().unwrap();
@@ -238,3 +234,82 @@ fn test_no_attributes_archive() {
         "Empty archive should not have attributes"
     );
 }
+
+/// Header of an attributes file with the given flags
+fn attributes_header(flags: u32) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&100u32.to_le_bytes());
+    data.extend_from_slice(&flags.to_le_bytes());
+    data
+}
+
+#[test]
+fn test_attributes_without_own_entry() {
+    // Layout of archives whose (attributes) was written before it was added
+    // to the block table: CRC32, FILETIME and MD5 for all files but the last
+    let flags = AttributeFlags::CRC32 | AttributeFlags::FILETIME | AttributeFlags::MD5;
+    let mut data = attributes_header(flags);
+    for i in 0..3u32 {
+        data.extend_from_slice(&(0xC0C0_0000 + i).to_le_bytes());
+    }
+    for i in 0..3u64 {
+        data.extend_from_slice(&(0x01D0_0000_0000_0000 + i).to_le_bytes());
+    }
+    for i in 0..3u8 {
+        data.extend_from_slice(&[i; 16]);
+    }
+
+    let attributes = Attributes::parse(&data.into(), 4).unwrap();
+    assert_eq!(attributes.entry_count, 3);
+    assert_eq!(attributes.present.as_u32(), flags);
+    assert_eq!(attributes.file_attributes.len(), 4);
+    for i in 0..3 {
+        let file = attributes.get_file_attributes(i).unwrap();
+        assert_eq!(file.crc32, Some(0xC0C0_0000 + i as u32));
+        assert_eq!(file.filetime, Some(0x01D0_0000_0000_0000 + i as u64));
+        assert_eq!(file.md5, Some([i as u8; 16]));
+    }
+    assert_eq!(attributes.file_attributes[3], FileAttributes::new());
+}
+
+#[test]
+fn test_attributes_with_truncated_tail() {
+    // Every section flagged, but the data ends halfway through the MD5s
+    let mut data = attributes_header(AttributeFlags::ALL);
+    for i in 0..3u32 {
+        data.extend_from_slice(&i.to_le_bytes());
+    }
+    for i in 0..3u64 {
+        data.extend_from_slice(&i.to_le_bytes());
+    }
+    data.extend_from_slice(&[0xAA; 16]);
+    data.extend_from_slice(&[0xBB; 8]);
+
+    let attributes = Attributes::parse(&data.into(), 3).unwrap();
+    assert_eq!(attributes.entry_count, 3);
+    assert_eq!(attributes.flags.as_u32(), AttributeFlags::ALL);
+    assert_eq!(
+        attributes.present.as_u32(),
+        AttributeFlags::CRC32 | AttributeFlags::FILETIME | AttributeFlags::MD5
+    );
+
+    let files = &attributes.file_attributes;
+    assert!(files.iter().all(|file| file.crc32.is_some()));
+    assert!(files.iter().all(|file| file.filetime.is_some()));
+    assert_eq!(files[0].md5, Some([0xAA; 16]));
+    assert_eq!(files[1].md5, None);
+    assert!(files.iter().all(|file| file.is_patch.is_none()));
+}
+
+#[test]
+fn test_attributes_flagged_section_missing() {
+    // Patch bits flagged, but only the CRC32s were written
+    let mut data = attributes_header(AttributeFlags::CRC32 | AttributeFlags::PATCH_BIT);
+    data.extend_from_slice(&0x1234_5678u32.to_le_bytes());
+    data.extend_from_slice(&0x9ABC_DEF0u32.to_le_bytes());
+
+    let attributes = Attributes::parse(&data.into(), 2).unwrap();
+    assert_eq!(attributes.present.as_u32(), AttributeFlags::CRC32);
+    assert_eq!(attributes.file_attributes[1].crc32, Some(0x9ABC_DEF0));
+    assert_eq!(attributes.file_attributes[1].is_patch, None);
+}
@@ -23,3 +23,1143 @@ fn test_signatures() {
     assert_eq!(mopaq::signatures::BET_TABLE, 0x1A544542);
     assert_eq!(mopaq::signatures::STRONG_SIGNATURE, *b"NGIS");
 }
+
+#[test]
+fn test_try_clone_concurrent_readers() {
+    use mopaq::{Archive, ArchiveBuilder};
+    use std::thread;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("clone.mpq");
+
+    let mut builder = ArchiveBuilder::new();
+    for i in 0..8 {
+        builder = builder.add_file_data(vec![i as u8; 10_000 + i], &format!("file_{i}.bin"));
+    }
+    builder.build(&archive_path).unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    let handles: Vec<_> = (0..8usize)
+        .map(|i| {
+            let reader = archive.try_clone().unwrap();
+            thread::spawn(move || {
+                let data = reader.read_file(&format!("file_{i}.bin")).unwrap();
+                assert_eq!(data, vec![i as u8; 10_000 + i]);
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // Tables are shared rather than re-parsed
+    let clone = archive.try_clone().unwrap();
+    assert!(std::ptr::eq(
+        archive.hash_table().unwrap(),
+        clone.hash_table().unwrap()
+    ));
+}
+
+#[test]
+fn test_open_from_file() {
+    use mopaq::{Archive, ArchiveBuilder, OpenOptions};
+    use std::io::{Seek, SeekFrom};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("from_file.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"opened by the caller".to_vec(), "data.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    // The file position left by the caller does not matter
+    let mut file = std::fs::File::open(&archive_path).unwrap();
+    file.seek(SeekFrom::End(0)).unwrap();
+    let mut archive = Archive::from_file(file).unwrap();
+    assert!(archive.path().as_os_str().is_empty());
+    assert_eq!(
+        archive.read_file("data.txt").unwrap(),
+        b"opened by the caller"
+    );
+    assert!(archive
+        .list()
+        .unwrap()
+        .iter()
+        .any(|entry| entry.name == "data.txt"));
+
+    // Clones read through the same handle
+    let clone = archive.try_clone().unwrap();
+    drop(archive);
+    assert_eq!(
+        clone.read_file("data.txt").unwrap(),
+        b"opened by the caller"
+    );
+
+    let file = std::fs::File::open(&archive_path).unwrap();
+    let archive = OpenOptions::new()
+        .load_tables(false)
+        .open_file(file)
+        .unwrap();
+    assert!(archive.hash_table().is_none());
+}
+
+#[test]
+fn test_shared_archive_concurrent_reads() {
+    use mopaq::{Archive, ArchiveBuilder};
+    use std::thread;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("shared.mpq");
+
+    let mut builder = ArchiveBuilder::new().generate_crcs(true);
+    for i in 0..8 {
+        builder = builder.add_file_data(vec![i as u8; 20_000 + i], &format!("file_{i}.bin"));
+    }
+    builder.build(&archive_path).unwrap();
+
+    // Reads need only a shared reference, so threads can borrow one archive
+    let archive = Archive::open(&archive_path).unwrap();
+    thread::scope(|scope| {
+        for i in 0..8usize {
+            let archive = &archive;
+            scope.spawn(move || {
+                for _ in 0..10 {
+                    let data = archive.read_file(&format!("file_{i}.bin")).unwrap();
+                    assert_eq!(data, vec![i as u8; 20_000 + i]);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_file_matches() {
+    use mopaq::{Archive, ArchiveBuilder};
+    use std::io::Cursor;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("matches.mpq");
+
+    // Several sectors long so the file is compared sector by sector
+    let content: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+    ArchiveBuilder::new()
+        .add_file_data(content.clone(), "data.bin")
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    assert!(archive
+        .file_matches("data.bin", Cursor::new(&content))
+        .unwrap());
+
+    let mut changed = content.clone();
+    changed[150_000] ^= 0xFF;
+    assert!(!archive
+        .file_matches("data.bin", Cursor::new(&changed))
+        .unwrap());
+
+    // A difference in the first sector stops the comparison there
+    let mut early = content.clone();
+    early[0] ^= 0xFF;
+    let mut cursor = Cursor::new(&early);
+    assert!(!archive.file_matches("data.bin", &mut cursor).unwrap());
+    assert!(cursor.position() < content.len() as u64);
+
+    // Prefixes and extensions of the content are not matches
+    assert!(!archive
+        .file_matches("data.bin", Cursor::new(&content[..1000]))
+        .unwrap());
+    let mut longer = content.clone();
+    longer.push(0);
+    assert!(!archive
+        .file_matches("data.bin", Cursor::new(&longer))
+        .unwrap());
+
+    assert!(archive
+        .file_matches("missing.bin", Cursor::new(&content))
+        .is_err());
+}
+
+#[test]
+fn test_find_files() {
+    use mopaq::{Archive, ArchiveBuilder, FormatVersion};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let names: Vec<String> = (0..200).map(|i| format!("units\\unit{i:03}.txt")).collect();
+
+    for version in [FormatVersion::V1, FormatVersion::V4] {
+        let archive_path = temp_dir.path().join(format!("{version:?}.mpq"));
+        let mut builder = ArchiveBuilder::new().version(version);
+        for name in &names {
+            builder = builder.add_file_data(name.as_bytes().to_vec(), name);
+        }
+        builder.build(&archive_path).unwrap();
+
+        let archive = Archive::open(&archive_path).unwrap();
+        let mut queries: Vec<&str> = names.iter().rev().map(String::as_str).collect();
+        queries.insert(50, "units\\missing.txt");
+        queries.push("UNITS/UNIT007.TXT");
+
+        let found = archive.find_files(&queries).unwrap();
+        assert_eq!(found.len(), queries.len());
+        for (query, info) in queries.iter().zip(&found) {
+            let expected = archive.find_file(query).unwrap();
+            assert_eq!(
+                info.as_ref().map(|info| info.block_index),
+                expected.map(|info| info.block_index),
+                "{version:?} {query}"
+            );
+        }
+        assert!(found[50].is_none());
+        assert_eq!(
+            found.last().unwrap().as_ref().unwrap().filename,
+            "UNITS/UNIT007.TXT"
+        );
+        assert!(archive.find_files(&[]).unwrap().is_empty());
+    }
+}
+
+#[test]
+fn test_table_key_override() {
+    use mopaq::{
+        decrypt_block, encrypt_block, hash_string, hash_type, AddFileOptions, Archive,
+        ArchiveBuilder, MutableArchive, OpenOptions, TableKey,
+    };
+    use std::fs;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("protected.mpq");
+
+    ArchiveBuilder::new()
+        .add_file_data(b"war3map.j contents".to_vec(), "war3map.j")
+        .add_file_data(vec![7u8; 5000], "war3map.w3e")
+        .build(&archive_path)
+        .unwrap();
+
+    let (hash_pos, hash_size, block_pos, block_size) = {
+        let archive = Archive::open(&archive_path).unwrap();
+        // Recovery of the hash table key relies on an unused first entry
+        assert!(archive.hash_table().unwrap().entries()[0].is_empty());
+        let header = archive.header();
+        (
+            header.get_hash_table_pos() as usize,
+            header.hash_table_size as usize,
+            header.get_block_table_pos() as usize,
+            header.block_table_size as usize,
+        )
+    };
+
+    // Re-encrypt both tables with shifted keys, as protectors do
+    let hash_key = 0x1234_5678;
+    let block_key = 0x0BAD_F00D;
+    let mut bytes = fs::read(&archive_path).unwrap();
+    for (pos, entries, name, key) in [
+        (hash_pos, hash_size, "(hash table)", hash_key),
+        (block_pos, block_size, "(block table)", block_key),
+    ] {
+        let range = pos..pos + entries * 16;
+        let mut dwords: Vec<u32> = bytes[range.clone()]
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        decrypt_block(&mut dwords, hash_string(name, hash_type::FILE_KEY));
+        encrypt_block(&mut dwords, key);
+        for (chunk, dword) in bytes[range].chunks_exact_mut(4).zip(dwords) {
+            chunk.copy_from_slice(&dword.to_le_bytes());
+        }
+    }
+    fs::write(&archive_path, &bytes).unwrap();
+
+    // The standard keys no longer find anything
+    let archive = Archive::open(&archive_path).unwrap();
+    assert!(archive.read_file("war3map.j").is_err());
+
+    for (hash, block) in [
+        (TableKey::Fixed(hash_key), TableKey::Fixed(block_key)),
+        (TableKey::Recover, TableKey::Recover),
+    ] {
+        let archive = OpenOptions::new()
+            .table_key_override(hash, block)
+            .open(&archive_path)
+            .unwrap();
+        assert_eq!(
+            archive.read_file("war3map.j").unwrap(),
+            b"war3map.j contents"
+        );
+        assert_eq!(archive.read_file("war3map.w3e").unwrap(), vec![7u8; 5000]);
+    }
+
+    // Modifying the archive keeps the keys it was opened with
+    let options = OpenOptions::new()
+        .table_key_override(TableKey::Fixed(hash_key), TableKey::Fixed(block_key));
+    let mut modified = MutableArchive::open_with_options(&archive_path, options.clone()).unwrap();
+    modified
+        .add_file_data(b"new", "new.txt", &AddFileOptions::new())
+        .unwrap();
+    modified.flush().unwrap();
+    drop(modified);
+
+    let archive = options.open(&archive_path).unwrap();
+    assert_eq!(archive.read_file("new.txt").unwrap(), b"new");
+    assert_eq!(
+        archive.read_file("war3map.j").unwrap(),
+        b"war3map.j contents"
+    );
+}
+
+/// Decrypt the block table of the archive at `path`, let `edit` change its
+/// DWORDs and write it back re-encrypted
+fn rewrite_block_table(path: &std::path::Path, edit: impl FnOnce(&mut [u32])) {
+    use mopaq::{decrypt_block, encrypt_block, hash_string, hash_type, Archive};
+
+    let (block_pos, block_size) = {
+        let archive = Archive::open(path).unwrap();
+        let header = archive.header();
+        (
+            header.get_block_table_pos() as usize,
+            header.block_table_size as usize,
+        )
+    };
+    let key = hash_string("(block table)", hash_type::FILE_KEY);
+    let mut bytes = std::fs::read(path).unwrap();
+    let range = block_pos..block_pos + block_size * 16;
+    let mut dwords: Vec<u32> = bytes[range.clone()]
+        .chunks_exact(4)
+        .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+        .collect();
+    decrypt_block(&mut dwords, key);
+    edit(&mut dwords);
+    encrypt_block(&mut dwords, key);
+    for (chunk, dword) in bytes[range].chunks_exact_mut(4).zip(dwords) {
+        chunk.copy_from_slice(&dword.to_le_bytes());
+    }
+    std::fs::write(path, &bytes).unwrap();
+}
+
+#[test]
+fn test_size_mismatch_policy() {
+    use mopaq::compression::SizeMismatchPolicy;
+    use mopaq::{Archive, ArchiveBuilder, Error, OpenOptions};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("mismatch.mpq");
+    let content: Vec<u8> = (0..10_000u32).map(|i| (i % 13) as u8).collect();
+    ArchiveBuilder::new()
+        .block_size(3)
+        .add_file_data(content.clone(), "data.bin")
+        .build(&archive_path)
+        .unwrap();
+
+    // Record a file size 8 bytes short, so the last sector decompresses to
+    // more than the block table says
+    rewrite_block_table(&archive_path, |dwords| {
+        let entry = dwords
+            .chunks_exact_mut(4)
+            .find(|entry| entry[2] == content.len() as u32)
+            .unwrap();
+        entry[2] -= 8;
+    });
+
+    // The default warns and reads on, strict reads fail
+    let archive = Archive::open(&archive_path).unwrap();
+    assert!(archive.read_file("data.bin").is_ok());
+
+    let archive = OpenOptions::new()
+        .size_mismatch_policy(SizeMismatchPolicy::Strict)
+        .open(&archive_path)
+        .unwrap();
+    assert!(matches!(
+        archive.read_file("data.bin"),
+        Err(Error::InvalidFileSize { .. })
+    ));
+}
+
+#[test]
+fn test_wrapped_table_offsets() {
+    use mopaq::{Archive, ArchiveBuilder, OpenOptions, TableOffsetPolicy};
+    use std::fs;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("source.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"war3map.j contents".to_vec(), "war3map.j")
+        .add_file_data(vec![7u8; 5000], "war3map.w3e")
+        .build(&source_path)
+        .unwrap();
+    let source = fs::read(&source_path).unwrap();
+    let (archive_size, block_pos, block_bytes) = {
+        let archive = Archive::open(&source_path).unwrap();
+        let header = archive.header();
+        (
+            header.archive_size,
+            header.block_table_pos,
+            header.block_table_size as usize * 16,
+        )
+    };
+    let set_block_table_pos = |bytes: &mut Vec<u8>, header_offset: usize, pos: u32| {
+        let field = header_offset + 0x14;
+        bytes[field..field + 4].copy_from_slice(&pos.to_le_bytes());
+    };
+
+    // The position exceeds the archive size by a multiple of it
+    let beyond_end = temp_dir.path().join("beyond_end.mpq");
+    let mut bytes = source.clone();
+    set_block_table_pos(&mut bytes, 0, block_pos + 2 * archive_size);
+    fs::write(&beyond_end, &bytes).unwrap();
+
+    // The archive is embedded behind 0x200 bytes that hold a copy of its
+    // block table, which it reaches by overflowing 32 bits
+    let embedded = temp_dir.path().join("embedded.mpq");
+    let mut bytes = vec![0u8; 0x200];
+    bytes[0x10..0x10 + block_bytes]
+        .copy_from_slice(&source[block_pos as usize..block_pos as usize + block_bytes]);
+    bytes.extend_from_slice(&source);
+    set_block_table_pos(&mut bytes, 0x200, 0x10u32.wrapping_sub(0x200));
+    fs::write(&embedded, &bytes).unwrap();
+
+    for path in [&beyond_end, &embedded] {
+        // As stored, the block table lies outside the file
+        let archive = Archive::open(path).unwrap();
+        assert!(archive.is_truncated());
+        assert!(archive.read_file("war3map.j").is_err());
+
+        let archive = OpenOptions::new()
+            .table_offsets(TableOffsetPolicy::Lenient)
+            .open(path)
+            .unwrap();
+        assert!(!archive.is_truncated());
+        assert_eq!(
+            archive.read_file("war3map.j").unwrap(),
+            b"war3map.j contents"
+        );
+        assert_eq!(archive.read_file("war3map.w3e").unwrap(), vec![7u8; 5000]);
+    }
+}
+
+#[test]
+fn test_sector_map() {
+    use mopaq::compression::flags;
+    use mopaq::{Archive, ArchiveBuilder, SectorChecksum};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("sectors.mpq");
+
+    // Three and a half sectors: a compressible head and an incompressible tail
+    let mut content = vec![b'a'; 8192];
+    let mut state = 0x1234_5678u32;
+    content.extend((0..6144).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }));
+
+    ArchiveBuilder::new()
+        .generate_crcs(true)
+        .add_file_data_with_options(content.clone(), "data.bin", flags::ZLIB, false, 0)
+        .add_file_data_with_options(b"small".to_vec(), "small.txt", flags::ZLIB, false, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    let map = archive.sector_map("data.bin").unwrap();
+    assert!(!map.single_unit);
+    assert_eq!(map.sector_size, 4096);
+    assert_eq!(map.sectors.len(), 4);
+    assert_eq!(map.header_size, (5 + 4) * 4);
+    assert_eq!(map.sectors[0].offset, map.header_size);
+    assert_eq!(map.sectors[3].uncompressed_size, 14336 - 3 * 4096);
+
+    let bytes = std::fs::read(&archive_path).unwrap();
+    for (i, sector) in map.sectors.iter().enumerate() {
+        // The random tail does not shrink and is stored raw
+        assert_eq!(sector.compressed, i < 2);
+        assert_eq!(
+            sector.compression_mask,
+            sector.compressed.then_some(flags::ZLIB)
+        );
+
+        let start = (map.file_pos + sector.offset) as usize;
+        let stored = &bytes[start..start + sector.compressed_size as usize];
+        assert_eq!(
+            sector.checksum,
+            Some(SectorChecksum::Adler32.compute(stored))
+        );
+        if !sector.compressed {
+            assert_eq!(stored, &content[i * 4096..i * 4096 + stored.len()]);
+        }
+    }
+
+    let small = archive.sector_map("small.txt").unwrap();
+    assert!(small.single_unit);
+    assert_eq!(small.sectors.len(), 1);
+    assert_eq!(small.sectors[0].uncompressed_size, 5);
+    assert!(small.sectors[0].checksum.is_some());
+}
+
+#[test]
+fn test_read_file_partial() {
+    use mopaq::compression::flags;
+    use mopaq::{Archive, ArchiveBuilder, Error};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("damaged.mpq");
+
+    let mut content = vec![b'a'; 8192];
+    let mut state = 0x9E37_79B9u32;
+    content.extend((0..6144).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as u8
+    }));
+
+    ArchiveBuilder::new()
+        .generate_crcs(true)
+        .add_file_data_with_options(content.clone(), "data.bin", flags::ZLIB, false, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    let map = Archive::open(&archive_path)
+        .unwrap()
+        .sector_map("data.bin")
+        .unwrap();
+
+    // Scramble the compressed second sector and flip a byte of the raw third
+    let mut bytes = std::fs::read(&archive_path).unwrap();
+    let compressed = (map.file_pos + map.sectors[1].offset) as usize;
+    for byte in &mut bytes[compressed..compressed + map.sectors[1].compressed_size as usize] {
+        *byte = !*byte;
+    }
+    let raw = (map.file_pos + map.sectors[2].offset) as usize;
+    bytes[raw] ^= 0xFF;
+    std::fs::write(&archive_path, &bytes).unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    assert!(archive.read_file("data.bin").is_err());
+
+    let (data, errors) = archive.read_file_partial("data.bin").unwrap();
+    assert_eq!(data.len(), content.len());
+    assert_eq!(&data[..4096], &content[..4096]);
+    assert!(data[4096..8192].iter().all(|&b| b == 0));
+    assert_eq!(data[8192], content[8192] ^ 0xFF);
+    assert_eq!(&data[8193..], &content[8193..]);
+
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        (errors[0].index, errors[0].offset, errors[0].size),
+        (1, 4096, 4096)
+    );
+    assert!(!errors[0].recovered);
+    assert_eq!(errors[1].index, 2);
+    assert!(errors[1].recovered);
+    assert!(matches!(errors[1].error, Error::ChecksumMismatch { .. }));
+
+    assert!(matches!(
+        archive.read_file_partial("missing.bin"),
+        Err(Error::FileNotFound(_))
+    ));
+
+    // A file stored in one piece is not padded out to its declared size
+    let small_path = temp_dir.path().join("single_unit.mpq");
+    ArchiveBuilder::new()
+        .add_file_data_with_options(vec![b'b'; 1000], "small.bin", flags::ZLIB, false, 0)
+        .build(&small_path)
+        .unwrap();
+    let info = Archive::open(&small_path)
+        .unwrap()
+        .find_file("small.bin")
+        .unwrap()
+        .unwrap();
+    assert!(info.is_single_unit());
+    let mut bytes = std::fs::read(&small_path).unwrap();
+    let start = info.file_pos as usize + 1;
+    for byte in &mut bytes[start..start + info.compressed_size as usize - 1] {
+        *byte = !*byte;
+    }
+    std::fs::write(&small_path, &bytes).unwrap();
+    let archive = Archive::open(&small_path).unwrap();
+    assert!(archive.read_file_partial("small.bin").is_err());
+}
+
+#[test]
+fn test_export_manifest() {
+    use mopaq::{Archive, ArchiveBuilder, Manifest};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let old_path = temp_dir.path().join("old.mpq");
+    let new_path = temp_dir.path().join("new.mpq");
+    let script = b"function main takes nothing returns nothing\r\n".repeat(200);
+
+    ArchiveBuilder::new()
+        .add_file_data(script.clone(), "war3map.j")
+        .add_file_data(b"peasant".to_vec(), "Units\\Peasant.mdx")
+        .build(&old_path)
+        .unwrap();
+    ArchiveBuilder::new()
+        .add_file_data(b"footman".to_vec(), "Units\\Footman.mdx")
+        .add_file_data(b"peasant, again".to_vec(), "Units\\Peasant.mdx")
+        .add_file_data(script.clone(), "war3map.j")
+        .build(&new_path)
+        .unwrap();
+
+    let old = Archive::open(&old_path).unwrap().export_manifest().unwrap();
+    let script_entry = old.get("WAR3MAP.J").unwrap();
+    assert_eq!(script_entry.size, script.len() as u64);
+    assert_eq!(script_entry.crc32, crc32fast::hash(&script));
+    assert!(script_entry.compressed_size < script_entry.size);
+    assert!(old.get("(listfile)").is_some());
+
+    let manifest_path = temp_dir.path().join("old.mpqmanifest");
+    old.save(&manifest_path).unwrap();
+    let old = Manifest::load(&manifest_path).unwrap();
+
+    let new = Archive::open(&new_path).unwrap().export_manifest().unwrap();
+    let diff = old.diff(&new);
+    assert_eq!(diff.added, ["Units\\Footman.mdx"]);
+    assert!(diff.removed.is_empty());
+    // The listfile gained a name, the script moved but kept its contents
+    assert_eq!(diff.changed, ["(listfile)", "Units\\Peasant.mdx"]);
+}
+
+#[test]
+fn test_export_ckeys() {
+    use md5::{Digest, Md5};
+    use mopaq::{Archive, ArchiveBuilder};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("ckeys.mpq");
+    let model = b"MDLX".repeat(5000);
+    ArchiveBuilder::new()
+        .add_file_data(model.clone(), "Units\\Footman.mdx")
+        .add_file_data(model.clone(), "Units\\Footman_Copy.mdx")
+        .build(&archive_path)
+        .unwrap();
+
+    let manifest = Archive::open(&archive_path)
+        .unwrap()
+        .export_manifest()
+        .unwrap();
+    let ckey: String = Md5::digest(&model)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(manifest.get("units\\footman.mdx").unwrap().ckey(), ckey);
+
+    let ckeys_path = temp_dir.path().join("ckeys.psv");
+    manifest.save_ckeys(&ckeys_path).unwrap();
+    // Identical contents share a CKey, and the listfile is left out
+    assert_eq!(
+        std::fs::read_to_string(&ckeys_path).unwrap(),
+        format!(
+            "Path!STRING:0|CKey!HEX:16|Size!DEC:8\n\
+             Units/Footman.mdx|{ckey}|20000\n\
+             Units/Footman_Copy.mdx|{ckey}|20000\n"
+        )
+    );
+}
+
+#[test]
+fn test_malformed_sector_offsets() {
+    use mopaq::compression::flags;
+    use mopaq::{Archive, ArchiveBuilder, Error};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("offsets.mpq");
+
+    let content: Vec<u8> = (0..4 * 4096u32).map(|i| (i % 7) as u8).collect();
+    ArchiveBuilder::new()
+        .add_file_data_with_options(content.clone(), "data.bin", flags::ZLIB, false, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    let file_pos = Archive::open(&archive_path)
+        .unwrap()
+        .find_file("data.bin")
+        .unwrap()
+        .unwrap()
+        .file_pos as usize;
+
+    // Point the end of sector 1 (the start of sector 2) far past the file's data
+    let mut bytes = std::fs::read(&archive_path).unwrap();
+    bytes[file_pos + 8..file_pos + 12].copy_from_slice(&0x7FFF_0000u32.to_le_bytes());
+    std::fs::write(&archive_path, &bytes).unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    match archive.read_file("data.bin") {
+        Err(Error::CorruptSector { file, sector, .. }) => {
+            assert_eq!(file, "data.bin");
+            assert_eq!(sector, 1);
+        }
+        other => panic!(
+            "expected a corrupt sector error, got {:?}",
+            other.map(|d| d.len())
+        ),
+    }
+
+    // The damage is confined to the two sectors sharing the bad offset
+    let (data, errors) = archive.read_file_partial("data.bin").unwrap();
+    let indexes: Vec<usize> = errors.iter().map(|e| e.index).collect();
+    assert_eq!(indexes, [1, 2]);
+    assert!(errors.iter().all(|e| e.error.is_corruption()));
+    assert_eq!(&data[..4096], &content[..4096]);
+    assert_eq!(&data[3 * 4096..], &content[3 * 4096..]);
+}
+
+#[test]
+fn test_sector_offsets_past_old_compressed_size() {
+    use mopaq::{Archive, ArchiveBuilder};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("old_layout.mpq");
+
+    let content: Vec<u8> = (0..4 * 4096u32).map(|i| (i % 7) as u8).collect();
+    ArchiveBuilder::new()
+        .generate_crcs(true)
+        .add_file_data(content.clone(), "data.bin")
+        .build(&archive_path)
+        .unwrap();
+
+    // Older builders left the sector checksum table out of the compressed
+    // size, so the last sector offset points past it
+    rewrite_block_table(&archive_path, |dwords| {
+        let entry = dwords
+            .chunks_exact_mut(4)
+            .find(|entry| entry[2] == content.len() as u32)
+            .unwrap();
+        entry[1] -= 4 * 4;
+    });
+
+    let archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(archive.read_file("data.bin").unwrap(), content);
+    let (data, errors) = archive.read_file_partial("data.bin").unwrap();
+    assert!(errors.is_empty());
+    assert_eq!(data, content);
+}
+
+#[test]
+fn test_header_version_size_mismatch() {
+    use mopaq::mpq_header::HeaderRule;
+    use mopaq::{Archive, ArchiveBuilder, FormatVersion};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("mismatch.mpq");
+    let build = |version| {
+        ArchiveBuilder::new()
+            .version(version)
+            .add_file_data(b"header test".to_vec(), "test.txt")
+            .build(&archive_path)
+            .unwrap();
+        std::fs::read(&archive_path).unwrap()
+    };
+    let open = |bytes: &[u8]| {
+        std::fs::write(&archive_path, bytes).unwrap();
+        let archive = Archive::open(&archive_path).unwrap();
+        assert_eq!(archive.read_file("test.txt").unwrap(), b"header test");
+        archive
+    };
+
+    let mut archive = open(&build(FormatVersion::V2));
+    assert!(archive.get_info().unwrap().header_reconciliation.is_none());
+
+    // A v2 header that declares version 1
+    let mut bytes = build(FormatVersion::V2);
+    bytes[0x0C] = 0;
+    let mut archive = open(&bytes);
+    assert_eq!(archive.header().format_version, FormatVersion::V2);
+    let reconciliation = archive.get_info().unwrap().header_reconciliation.unwrap();
+    assert_eq!(reconciliation.declared_version, 0);
+    assert_eq!(reconciliation.rule, HeaderRule::UpgradedBySize);
+
+    // A protector's bogus header size on a v1 archive
+    let mut bytes = build(FormatVersion::V1);
+    bytes[0x04..0x08].copy_from_slice(&0xFFFFu32.to_le_bytes());
+    let mut archive = open(&bytes);
+    assert_eq!(archive.header().header_size, 0x20);
+    let info = archive.get_info().unwrap();
+    assert_eq!(info.format_version, FormatVersion::V1);
+    assert_eq!(
+        info.header_reconciliation.unwrap().rule,
+        HeaderRule::ExtraBytesIgnored
+    );
+}
+
+#[test]
+fn test_truncated_archive() {
+    use mopaq::{Archive, ArchiveBuilder, FormatVersion, ListfileOption};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("truncated.mpq");
+
+    let mut builder = ArchiveBuilder::new()
+        .version(FormatVersion::V1)
+        .listfile_option(ListfileOption::None);
+    for i in 0..8 {
+        builder = builder.add_file_data(
+            format!("contents {}", i).into_bytes(),
+            &format!("{}.txt", i),
+        );
+    }
+    builder.build(&archive_path).unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    assert!(!archive.is_truncated());
+    let header = archive.header().clone();
+    let block_table_end = header.get_block_table_pos() + header.block_table_size as u64 * 16;
+    assert_eq!(block_table_end, header.get_archive_size());
+    drop(archive);
+
+    // Lose the last three block table entries and half of the one before
+    let bytes = std::fs::read(&archive_path).unwrap();
+    std::fs::write(
+        &archive_path,
+        &bytes[..block_table_end as usize - 3 * 16 - 8],
+    )
+    .unwrap();
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    assert!(archive.is_truncated());
+    assert!(archive.get_info().unwrap().is_truncated);
+
+    let block_table = archive.block_table().unwrap();
+    assert_eq!(block_table.size(), header.block_table_size as usize);
+    let kept = block_table.entries().iter().filter(|b| b.exists()).count();
+    assert_eq!(kept, header.block_table_size as usize - 4);
+
+    let mut found = 0;
+    for i in 0..8 {
+        let name = format!("{}.txt", i);
+        if let Some(info) = archive.find_file(&name).unwrap() {
+            assert!(info.block_index < kept);
+            assert!(archive.is_file_intact(&info).unwrap());
+            assert_eq!(
+                archive.read_file(&name).unwrap(),
+                format!("contents {}", i).into_bytes()
+            );
+            found += 1;
+        } else {
+            assert!(archive.read_file(&name).is_err());
+        }
+    }
+    assert_eq!(found, kept);
+}
+
+#[test]
+fn test_read_file_chunks_and_scan_lines() {
+    use mopaq::compression::flags;
+    use mopaq::{Archive, ArchiveBuilder};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("lines.mpq");
+
+    // About five sectors of text, so lines straddle sector boundaries
+    let content: String = (1..=2000).map(|i| format!("line {}\r\n", i)).collect();
+    let content = content.into_bytes();
+
+    ArchiveBuilder::new()
+        .add_file_data_with_options(content.clone(), "zlib.txt", flags::ZLIB, false, 0)
+        .add_file_data_with_options(content.clone(), "encrypted.txt", flags::ZLIB, true, 0)
+        .add_file_data_with_options(content.clone(), "stored.txt", 0, false, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    for name in ["zlib.txt", "encrypted.txt", "stored.txt"] {
+        let mut chunks = Vec::new();
+        archive
+            .read_file_chunks(name, |chunk| {
+                chunks.push(chunk.to_vec());
+                Ok(true)
+            })
+            .unwrap();
+        assert!(chunks.len() > 1, "{} was not read in pieces", name);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 4096));
+        assert_eq!(chunks.concat(), content, "{}", name);
+
+        let mut lines = Vec::new();
+        archive
+            .scan_lines(name, |number, line| {
+                lines.push((number, String::from_utf8(line.to_vec()).unwrap()));
+                true
+            })
+            .unwrap();
+        assert_eq!(lines.len(), 2000);
+        assert_eq!(lines[0], (1, "line 1".to_string()));
+        assert_eq!(lines[1999], (2000, "line 2000".to_string()));
+    }
+
+    // Stopping early ends the scan
+    let mut seen = 0;
+    archive
+        .scan_lines("zlib.txt", |number, _| {
+            seen = number;
+            number < 10
+        })
+        .unwrap();
+    assert_eq!(seen, 10);
+}
+
+#[test]
+fn test_list_with_options() {
+    use mopaq::compression::flags;
+    use mopaq::special_files::SpecialFile;
+    use mopaq::tables::BlockEntry;
+    use mopaq::{Archive, ArchiveBuilder, ListOptions, ListSort};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("list.mpq");
+
+    ArchiveBuilder::new()
+        .add_file_data_with_options(vec![b'a'; 5000], "b.txt", flags::ZLIB, false, 0)
+        .add_file_data_with_options(vec![b'b'; 300], "C.txt", 0, false, 0)
+        .add_file_data_with_options(vec![b'c'; 2000], "a.txt", flags::ZLIB, true, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    let names = |entries: Vec<mopaq::FileEntry>| -> Vec<String> {
+        entries.into_iter().map(|entry| entry.name).collect()
+    };
+
+    let by_name = archive
+        .list_with(&ListOptions::new().sort_by(ListSort::Name))
+        .unwrap();
+    assert_eq!(names(by_name), ["a.txt", "b.txt", "C.txt"]);
+
+    let largest_first = archive
+        .list_with(
+            &ListOptions::new()
+                .sort_by(ListSort::Size)
+                .descending(true)
+                .min_size(100),
+        )
+        .unwrap();
+    assert_eq!(names(largest_first), ["b.txt", "a.txt", "C.txt"]);
+
+    // The stored file has ratio 1.0 and sorts after the compressed ones
+    let by_ratio = archive
+        .list_with(&ListOptions::new().sort_by(ListSort::Ratio).min_size(100))
+        .unwrap();
+    assert_eq!(by_ratio.last().unwrap().name, "C.txt");
+    assert!(by_ratio[0].compression_ratio() < 0.1);
+
+    let encrypted = archive
+        .list_with(&ListOptions::new().filter_flags(BlockEntry::FLAG_ENCRYPTED))
+        .unwrap();
+    assert_eq!(names(encrypted), ["a.txt"]);
+
+    let small = archive
+        .list_with(&ListOptions::new().max_size(1000).min_size(100))
+        .unwrap();
+    assert_eq!(names(small), ["C.txt"]);
+
+    // Block order follows the order files were added
+    let by_block = archive
+        .list_with(&ListOptions::new().sort_by(ListSort::BlockIndex))
+        .unwrap();
+    assert_eq!(names(by_block), ["b.txt", "C.txt", "a.txt"]);
+
+    // Special files are only listed when asked for
+    let plain = archive
+        .list_with(&ListOptions::new().sort_by(ListSort::Name))
+        .unwrap();
+    let plain: Vec<_> = plain.iter().map(|entry| entry.name.as_str()).collect();
+    assert_eq!(plain, ["a.txt", "b.txt", "C.txt"]);
+    let all = archive
+        .list_with(&ListOptions::new().include_special(true))
+        .unwrap();
+    assert!(all
+        .iter()
+        .any(|entry| entry.special_file() == Some(SpecialFile::Listfile)));
+
+    // Patterns ignore case, like lookups
+    let pattern = mopaq::pattern::Pattern::glob("[bc].TXT").unwrap();
+    let matching = archive
+        .list_with(&ListOptions::new().pattern(pattern).sort_by(ListSort::Name))
+        .unwrap();
+    assert_eq!(names(matching), ["b.txt", "C.txt"]);
+}
+
+#[test]
+fn test_anonymous_entries() {
+    use mopaq::{
+        hash_string, hash_type, jenkins_hash, Archive, ArchiveBuilder, FormatVersion,
+        ListfileOption,
+    };
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("anonymous.mpq");
+    let listfile_path = temp_dir.path().join("listfile.txt");
+    std::fs::write(&listfile_path, "known.txt\r\n").unwrap();
+
+    ArchiveBuilder::new()
+        .version(FormatVersion::V3)
+        .listfile_option(ListfileOption::External(listfile_path))
+        .add_file_data(b"known".to_vec(), "known.txt")
+        // Listed names count as named in every locale
+        .add_file_data_with_options(b"bekannt".to_vec(), "known.txt", 0, false, 0x407)
+        .add_file_data(vec![7; 3000], "Secret\\hidden.dat")
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    let entries = archive.anonymous_entries().unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let entry = &entries[0];
+    let name = "Secret\\hidden.dat";
+    assert_eq!(entry.hash_a, Some(hash_string(name, hash_type::NAME_A)));
+    assert_eq!(entry.hash_b, Some(hash_string(name, hash_type::NAME_B)));
+    assert_eq!(entry.size, 3000);
+    assert_eq!(
+        entry.block_index,
+        archive.find_file(name).unwrap().unwrap().block_index
+    );
+
+    let bits = archive.het_table().unwrap().header.hash_entry_size;
+    let mask = if bits == 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    let het_hash = (jenkins_hash(name) & mask) | (1 << (bits - 1));
+    assert_eq!(entry.het_hash, Some(het_hash));
+}
+
+#[test]
+fn test_read_file_by_index() {
+    use mopaq::compression::flags;
+    use mopaq::{Archive, ArchiveBuilder, Error, ListfileOption};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("by_index.mpq");
+
+    let text: Vec<u8> = (0..20_000u32)
+        .flat_map(|i| (i % 97).to_le_bytes())
+        .collect();
+    ArchiveBuilder::new()
+        .listfile_option(ListfileOption::None)
+        .add_file_data(b"plain".to_vec(), "plain.txt")
+        .add_file_data_with_options(text.clone(), "encrypted.bin", flags::ZLIB, true, 0)
+        .add_file_data_with_encryption(text.clone(), "fixkey.bin", flags::ZLIB, true, 0)
+        .add_file_data_with_options(vec![1; 10_000], "raw.bin", 0, true, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    let index = |name: &str| archive.find_file(name).unwrap().unwrap().block_index;
+
+    assert_eq!(
+        archive.read_file_by_index(index("plain.txt")).unwrap(),
+        b"plain"
+    );
+    // Keys of compressed sectored files are recovered from the offset table
+    assert_eq!(
+        archive.read_file_by_index(index("encrypted.bin")).unwrap(),
+        text
+    );
+    assert_eq!(
+        archive.read_file_by_index(index("fixkey.bin")).unwrap(),
+        text
+    );
+    // Raw sectors have no known plaintext to recover the key from
+    assert!(matches!(
+        archive.read_file_by_index(index("raw.bin")),
+        Err(Error::Crypto(_))
+    ));
+    assert!(matches!(
+        archive.read_file_by_index(1000),
+        Err(Error::FileNotFound(_))
+    ));
+}
+
+#[test]
+fn test_decode_diagnostics() {
+    use mopaq::compression::{flags, CompressionMethod, FallbackPolicy};
+    use mopaq::{Archive, ArchiveBuilder, OpenOptions};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("diagnostics.mpq");
+    let data = b"compressible ".repeat(1000);
+    ArchiveBuilder::new()
+        .default_compression(flags::ZLIB)
+        .listfile_option(mopaq::ListfileOption::None)
+        .add_file_data(data.clone(), "data.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    // Nothing is recorded unless asked for
+    let archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(archive.read_file("data.txt").unwrap(), data);
+    assert!(archive.take_decode_diagnostics().is_empty());
+
+    let archive = OpenOptions::new()
+        .fallback_policy(FallbackPolicy::Strict)
+        .record_decode_diagnostics(true)
+        .open(&archive_path)
+        .unwrap();
+    assert_eq!(archive.read_file("data.txt").unwrap(), data);
+
+    let diagnostics = archive.take_decode_diagnostics();
+    assert!(!diagnostics.is_empty());
+    for record in &diagnostics {
+        assert_eq!(record.method, flags::ZLIB);
+        assert_eq!(record.steps, [CompressionMethod::Zlib]);
+        assert!(record.fallbacks.is_empty());
+    }
+    let total: usize = diagnostics.iter().filter_map(|d| d.output_size).sum();
+    assert_eq!(total, data.len());
+
+    // Taking them empties the record
+    assert!(archive.take_decode_diagnostics().is_empty());
+}
+
+#[test]
+fn test_io_buffer_size() {
+    use mopaq::compression::flags;
+    use mopaq::{Archive, ArchiveBuilder, OpenOptions};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("buffered.mpq");
+
+    // Compressible and incompressible sectors, so stored sizes vary
+    let mut state = 0x9E37_79B9u32;
+    let content: Vec<u8> = (0..100_000u32)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if (i / 4096) % 2 == 0 {
+                b'a'
+            } else {
+                state as u8
+            }
+        })
+        .collect();
+    ArchiveBuilder::new()
+        .generate_crcs(true)
+        .add_file_data_with_options(content.clone(), "data.bin", flags::ZLIB, false, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(
+        archive.io_buffer_size(),
+        mopaq::default_io_buffer_size(archive.header().sector_size())
+    );
+
+    for size in [0, 4096, 10_000, 64 * 1024, 1 << 20] {
+        let archive = OpenOptions::new()
+            .io_buffer_size(size)
+            .open(&archive_path)
+            .unwrap();
+        assert_eq!(archive.io_buffer_size(), size);
+        assert_eq!(archive.read_file("data.bin").unwrap(), content);
+        assert_eq!(archive.try_clone().unwrap().io_buffer_size(), size);
+
+        let (data, errors) = archive.read_file_partial("data.bin").unwrap();
+        assert_eq!(data, content);
+        assert!(errors.is_empty());
+    }
+}
@@ -1,6 +1,9 @@
 //! Integration tests for archive creation
 
-use mopaq::{Archive, ArchiveBuilder, FormatVersion, ListfileOption, OpenOptions};
+use mopaq::{
+    compression, Archive, ArchiveBuilder, BuildTable, Compatibility, Error, FormatVersion,
+    ListfileOption, Locale, NameHashingPolicy, OpenOptions, PlatformPolicy, SectorChecksum,
+};
 use std::fs;
 use tempfile::TempDir;
 
@@ -22,6 +25,63 @@ fn test_create_empty_archive() {
     assert_eq!(archive.header().format_version, FormatVersion::V1);
 }
 
+#[test]
+fn test_create_empty_archive_all_versions() {
+    let temp_dir = TempDir::new().unwrap();
+
+    for version in [
+        FormatVersion::V1,
+        FormatVersion::V2,
+        FormatVersion::V3,
+        FormatVersion::V4,
+    ] {
+        for compress_tables in [false, true] {
+            let archive_path = temp_dir
+                .path()
+                .join(format!("empty_{:?}_{}.mpq", version, compress_tables));
+            ArchiveBuilder::new()
+                .version(version)
+                .listfile_option(ListfileOption::None)
+                .compress_tables(compress_tables)
+                .build(&archive_path)
+                .unwrap();
+
+            let mut archive = Archive::open(&archive_path).unwrap();
+            assert_eq!(archive.header().format_version, version);
+            assert_eq!(
+                archive.het_table().is_some(),
+                version >= FormatVersion::V3,
+                "{:?}",
+                version
+            );
+            assert_eq!(archive.bet_table().is_some(), version >= FormatVersion::V3);
+            assert!(archive.find_file("(listfile)").unwrap().is_none());
+            assert!(archive.list().unwrap().is_empty());
+            assert!(archive.list_all().unwrap().is_empty());
+
+            let info = archive.get_info().unwrap();
+            assert_eq!(info.file_count, 0);
+            if let Some(md5) = info.md5_status {
+                assert!(md5.header_valid && md5.het_table_valid && md5.bet_table_valid);
+            }
+        }
+
+        // OpenOptions::create keeps the default listfile
+        let created = temp_dir.path().join(format!("created_{:?}.mpq", version));
+        let mut archive = OpenOptions::new()
+            .version(version)
+            .create(&created)
+            .unwrap();
+        let names: Vec<String> = archive
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["(listfile)"]);
+    }
+}
+
 #[test]
 fn test_create_archive_with_files() {
     let temp_dir = TempDir::new().unwrap();
@@ -42,7 +102,7 @@ fn test_create_archive_with_files() {
         .unwrap();
 
     // Verify archive contents
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
 
     // Check that files exist
     assert!(archive.find_file("test/file1.txt").unwrap().is_some());
@@ -69,7 +129,7 @@ fn test_create_archive_with_memory_files() {
         .unwrap();
 
     // Verify contents
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
 
     let data1 = archive.read_file("mem1.txt").unwrap();
     assert_eq!(data1, b"Memory file 1");
@@ -92,7 +152,7 @@ fn test_listfile_generation() {
         .unwrap();
 
     // Verify listfile exists and contains expected entries
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
 
     let listfile_data = archive.read_file("(listfile)").unwrap();
     let listfile_content = String::from_utf8(listfile_data).unwrap();
@@ -124,7 +184,7 @@ fn test_external_listfile() {
         .unwrap();
 
     // Verify listfile contains external content
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
 
     let listfile_data = archive.read_file("(listfile)").unwrap();
     let listfile_content = String::from_utf8(listfile_data).unwrap();
@@ -172,7 +232,7 @@ fn test_compression_options() {
         assert!(file_info.compressed_size < file_info.file_size);
 
         // Verify we can still read it correctly
-        let mut archive = Archive::open(&archive_path).unwrap();
+        let archive = Archive::open(&archive_path).unwrap();
         let read_data = archive.read_file("compressed.txt").unwrap();
         assert_eq!(read_data, data.as_bytes());
     } else {
@@ -224,11 +284,50 @@ fn test_large_file_sectors() {
         .unwrap();
 
     // Verify we can read it back correctly
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
     let read_data = archive.read_file("large.dat").unwrap();
     assert_eq!(read_data, large_data);
 }
 
+#[test]
+fn test_incompressible_multi_sector_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("incompressible.mpq");
+
+    // Noise that zlib cannot shrink, so every sector is stored raw
+    let mut state = 0x1234_5678u32;
+    let noise: Vec<u8> = (0..12 * 1024)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as u8
+        })
+        .collect();
+
+    ArchiveBuilder::new()
+        .block_size(3)
+        .add_file_data_with_options(
+            noise.clone(),
+            "noise.bin",
+            mopaq::compression::flags::ZLIB,
+            false,
+            0,
+        )
+        .build(&archive_path)
+        .unwrap();
+
+    // The sector offset table is only looked for in compressed files, so
+    // the flag is set even though no sector was compressed
+    let archive = Archive::open(&archive_path).unwrap();
+    let file_info = archive.find_file("noise.bin").unwrap().unwrap();
+    assert!(file_info.is_compressed());
+    assert!(file_info.compressed_size > file_info.file_size);
+    let read_data = Archive::open(&archive_path)
+        .unwrap()
+        .read_file("noise.bin")
+        .unwrap();
+    assert_eq!(read_data, noise);
+}
+
 #[test]
 fn test_hash_table_sizing() {
     let temp_dir = TempDir::new().unwrap();
@@ -246,7 +345,7 @@ fn test_hash_table_sizing() {
     builder.build(&archive_path).unwrap();
 
     // Verify all files can be found
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
     for i in 0..50 {
         let filename = format!("file_{:03}.txt", i);
         assert!(archive.find_file(&filename).unwrap().is_some());
@@ -407,7 +506,7 @@ fn test_encrypted_file_round_trip() {
     }
 
     // Verify we can decrypt and read it correctly
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
     let decrypted_data = archive.read_file("secret.txt").unwrap();
     assert_eq!(decrypted_data, test_data);
 }
@@ -442,7 +541,7 @@ fn test_encrypted_file_with_fix_key() {
     }
 
     // Verify we can decrypt and read it correctly
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
     let decrypted_data = archive.read_file("fix_key.dat").unwrap();
     assert_eq!(decrypted_data, test_data);
 }
@@ -479,11 +578,248 @@ fn test_encrypted_large_file() {
     }
 
     // Verify we can decrypt and read it correctly
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
     let decrypted_data = archive.read_file("large_encrypted.bin").unwrap();
     assert_eq!(decrypted_data, large_data);
 }
 
+#[test]
+fn test_file_spanning_many_sector_batches() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("many_sectors.mpq");
+
+    // 512-byte sectors, so the file has a few hundred of them
+    let data: Vec<u8> = (0..150_000u32)
+        .map(|i| if i % 3 == 0 { (i >> 5) as u8 } else { b'x' })
+        .collect();
+    ArchiveBuilder::new()
+        .block_size(0)
+        .generate_crcs(true)
+        .add_file_data_with_options(
+            data.clone(),
+            "many_sectors.bin",
+            mopaq::compression::flags::ZLIB,
+            true,
+            0,
+        )
+        .add_file_data(data.clone(), "plain.bin")
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    for name in ["many_sectors.bin", "plain.bin"] {
+        assert_eq!(archive.read_file(name).unwrap(), data, "{}", name);
+    }
+    let (_, errors) = archive.read_file_partial("many_sectors.bin").unwrap();
+    assert!(errors.is_empty());
+}
+
+#[test]
+fn test_computed_archive_size_matches_declared() {
+    let temp_dir = TempDir::new().unwrap();
+    for version in [
+        FormatVersion::V1,
+        FormatVersion::V2,
+        FormatVersion::V3,
+        FormatVersion::V4,
+    ] {
+        let archive_path = temp_dir.path().join(format!("{version:?}.mpq"));
+        ArchiveBuilder::new()
+            .version(version)
+            .add_file_data(b"computed size ".repeat(500), "data.bin")
+            .add_file_data(b"second".to_vec(), "second.txt")
+            .build(&archive_path)
+            .unwrap();
+        let mut archive = Archive::open(&archive_path).unwrap();
+        let declared = archive.header().get_archive_size();
+        assert_eq!(declared, fs::metadata(&archive_path).unwrap().len());
+        assert_eq!(archive.computed_archive_size(), declared, "{version:?}");
+        assert_eq!(
+            archive.get_info().unwrap().computed_archive_size,
+            declared,
+            "{version:?}"
+        );
+    }
+}
+
+#[test]
+fn test_large_block_sizes() {
+    let temp_dir = TempDir::new().unwrap();
+    let data = b"large sectors ".repeat(100);
+
+    // Shifts above 15 work up to the largest sector that fits in 32 bits
+    for block_size in [16, mopaq::MAX_BLOCK_SIZE] {
+        let archive_path = temp_dir.path().join(format!("block_{block_size}.mpq"));
+        ArchiveBuilder::new()
+            .block_size(block_size)
+            .add_file_data(data.clone(), "data.bin")
+            .build(&archive_path)
+            .unwrap();
+        let archive = Archive::open(&archive_path).unwrap();
+        assert_eq!(archive.header().block_size, block_size);
+        assert_eq!(archive.read_file("data.bin").unwrap(), data);
+    }
+
+    let result = ArchiveBuilder::new()
+        .block_size(mopaq::MAX_BLOCK_SIZE + 1)
+        .add_file_data(data, "data.bin")
+        .build(temp_dir.path().join("too_large.mpq"));
+    assert!(matches!(result, Err(Error::InvalidHeader(_))));
+    assert!(!temp_dir.path().join("too_large.mpq").exists());
+}
+
+#[test]
+fn test_build_summary() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("summary.mpq");
+
+    let text = b"summary text ".repeat(1000);
+    let summary = ArchiveBuilder::new()
+        .version(FormatVersion::V3)
+        .add_file_data(text.clone(), "text.txt")
+        .add_file_data_with_options(
+            b"secret".to_vec(),
+            "secret.bin",
+            compression::flags::ZLIB,
+            true,
+            0,
+        )
+        .build(&archive_path)
+        .unwrap();
+
+    assert_eq!(summary.version, FormatVersion::V3);
+    assert_eq!(
+        summary.archive_size,
+        fs::metadata(&archive_path).unwrap().len()
+    );
+
+    // Files are listed in write order, followed by the generated listfile
+    let names: Vec<_> = summary.files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["text.txt", "secret.bin", "(listfile)"]);
+
+    let archive = Archive::open(&archive_path).unwrap();
+    for file in &summary.files {
+        let info = archive.find_file(&file.name).unwrap().unwrap();
+        assert_eq!(file.file_size, info.file_size, "{}", file.name);
+        assert_eq!(file.compressed_size, info.compressed_size, "{}", file.name);
+        assert_eq!(file.flags, info.flags, "{}", file.name);
+    }
+    assert_eq!(summary.files[0].file_size, text.len() as u64);
+    assert!(summary.files[0].ratio() < 0.1);
+    assert!(summary.ratio() < 1.0);
+
+    // HET and BET come first, then the classic tables
+    let tables: Vec<_> = summary.tables.iter().map(|&(table, _)| table).collect();
+    assert_eq!(
+        tables,
+        [
+            BuildTable::Het,
+            BuildTable::Bet,
+            BuildTable::Hash,
+            BuildTable::Block
+        ]
+    );
+    assert_eq!(summary.table_size(BuildTable::Hash), Some(16 * 16));
+    assert_eq!(summary.table_size(BuildTable::Block), Some(3 * 16));
+    assert_eq!(summary.table_size(BuildTable::HiBlock), None);
+    assert!(summary.total_time >= summary.file_time + summary.table_time);
+}
+
+#[test]
+fn test_file_locales() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("locales.mpq");
+
+    ArchiveBuilder::new()
+        .add_file_data(b"neutral".to_vec(), "neutral.txt")
+        .add_file_data_with_options(
+            b"german".to_vec(),
+            "german.txt",
+            compression::flags::ZLIB,
+            false,
+            Locale::DE_DE,
+        )
+        .add_file_data_with_options(b"raw".to_vec(), "raw.txt", 0, false, 0x0C0A)
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    let locale = |name: &str| archive.find_file(name).unwrap().unwrap().locale;
+    assert_eq!(locale("neutral.txt"), Locale::NEUTRAL);
+    assert_eq!(locale("german.txt"), Locale::DE_DE);
+    assert_eq!(locale("raw.txt").code(), 0x0C0A);
+    assert_eq!(locale("raw.txt").name(), None);
+}
+
+#[test]
+fn test_multi_locale_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("multi_locale.mpq");
+
+    let add = |builder: ArchiveBuilder, data: &[u8], locale| {
+        builder.add_file_data_with_options(data.to_vec(), "strings.txt", 0, false, locale)
+    };
+    let builder = add(ArchiveBuilder::new(), b"neutral", Locale::NEUTRAL);
+    let builder = add(builder, b"french", Locale::FR_FR);
+    add(builder, b"german", Locale::DE_DE)
+        .add_file_data_with_options(b"english".to_vec(), "only_en.txt", 0, false, Locale::EN_US)
+        .build(&archive_path)
+        .unwrap();
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(
+        archive.file_locales("strings.txt").unwrap(),
+        [Locale::NEUTRAL, Locale::DE_DE, Locale::FR_FR]
+    );
+    assert!(archive.file_locales("missing.txt").unwrap().is_empty());
+
+    let read = |name: &str, locale| archive.read_file_with_locale(name, locale);
+    assert_eq!(read("strings.txt", Locale::FR_FR).unwrap(), b"french");
+    assert_eq!(read("strings.txt", Locale::DE_DE).unwrap(), b"german");
+    assert_eq!(read("strings.txt", Locale::NEUTRAL).unwrap(), b"neutral");
+    // Locales without a version fall back to the neutral one
+    assert_eq!(read("strings.txt", Locale::KO_KR).unwrap(), b"neutral");
+    // ...but never to another locale
+    assert!(matches!(
+        read("only_en.txt", Locale::FR_FR),
+        Err(Error::FileNotFound(_))
+    ));
+    let info = archive
+        .find_file_with_locale("strings.txt", Locale::FR_FR)
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.locale, Locale::FR_FR);
+
+    // The listfile names the file once
+    let listfile = String::from_utf8(archive.read_file("(listfile)").unwrap()).unwrap();
+    assert_eq!(listfile.matches("strings.txt").count(), 1);
+
+    // Rebuilding keeps every version, and single versions can be removed
+    let builder = ArchiveBuilder::from_archive(&mut archive)
+        .unwrap()
+        .remove_file_with_locale("strings.txt", Locale::DE_DE);
+    drop(archive);
+    builder.build(&archive_path).unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(
+        archive.file_locales("strings.txt").unwrap(),
+        [Locale::NEUTRAL, Locale::FR_FR]
+    );
+    assert_eq!(
+        archive
+            .read_file_with_locale("strings.txt", Locale::FR_FR)
+            .unwrap(),
+        b"french"
+    );
+    assert_eq!(
+        archive
+            .read_file_with_locale("only_en.txt", Locale::EN_US)
+            .unwrap(),
+        b"english"
+    );
+}
+
 #[test]
 fn test_mixed_encrypted_and_plain_files() {
     let temp_dir = TempDir::new().unwrap();
@@ -510,7 +846,7 @@ fn test_mixed_encrypted_and_plain_files() {
         .unwrap();
 
     // Open archive and verify all files
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
 
     // Check plain file
     let plain_info = archive.find_file("plain.txt").unwrap().unwrap();
@@ -564,7 +900,7 @@ fn test_encrypted_compressed_file() {
     }
 
     // Verify we can decrypt and decompress correctly
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
     let decrypted_data = archive.read_file("encrypted_compressed.txt").unwrap();
     assert_eq!(decrypted_data, data.as_bytes());
 }
@@ -590,7 +926,7 @@ fn test_hi_block_table_creation_v2() {
     assert!(archive.header().hi_block_table_pos.is_some());
 
     // Verify files can be read
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
     let data1 = archive.read_file("test.txt").unwrap();
     assert_eq!(data1, b"Test data for V2");
     let data2 = archive.read_file("file2.txt").unwrap();
@@ -621,7 +957,7 @@ fn test_hi_block_table_creation_v3() {
     assert!(archive.header().archive_size_64.is_some());
 
     // Verify files
-    let mut archive = Archive::open(&archive_path).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
     let data1 = archive.read_file("test.txt").unwrap();
     assert_eq!(data1, b"Test data for V3");
     let data2 = archive.read_file("advanced.txt").unwrap();
@@ -647,3 +983,1027 @@ fn test_v1_no_hi_block_table() {
     assert!(archive.header().hash_table_pos_hi.is_none());
     assert!(archive.header().block_table_pos_hi.is_none());
 }
+
+#[test]
+fn test_platform_policy_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("platform_source.mpq");
+    let copy_path = temp_dir.path().join("platform_copy.mpq");
+
+    ArchiveBuilder::new()
+        .platform_policy(PlatformPolicy::Preserve)
+        .add_file_data_with_platform(b"Platform data".to_vec(), "platform.txt", 0, 0x0002)
+        .build(&source_path)
+        .unwrap();
+
+    // Strict readers reject the nonzero platform code
+    assert!(OpenOptions::new()
+        .platform_policy(PlatformPolicy::Strict)
+        .open(&source_path)
+        .is_err());
+
+    // Copying with Preserve keeps the platform code intact
+    let mut source = Archive::open(&source_path).unwrap();
+    let info = source.find_file("platform.txt").unwrap().unwrap();
+    assert_eq!(info.platform, 0x0002);
+
+    ArchiveBuilder::new()
+        .platform_policy(PlatformPolicy::Preserve)
+        .add_file_from_archive(&mut source, "platform.txt")
+        .unwrap()
+        .build(&copy_path)
+        .unwrap();
+
+    let copy = Archive::open(&copy_path).unwrap();
+    let info = copy.find_file("platform.txt").unwrap().unwrap();
+    assert_eq!(info.platform, 0x0002);
+    assert_eq!(copy.read_file("platform.txt").unwrap(), b"Platform data");
+
+    // Lenient (default) builders normalize the platform code to 0
+    ArchiveBuilder::new()
+        .add_file_from_archive(&mut source, "platform.txt")
+        .unwrap()
+        .build(&copy_path)
+        .unwrap();
+
+    let copy = Archive::open(&copy_path).unwrap();
+    assert_eq!(copy.find_file("platform.txt").unwrap().unwrap().platform, 0);
+
+    // Strict builders refuse to write it
+    assert!(ArchiveBuilder::new()
+        .platform_policy(PlatformPolicy::Strict)
+        .add_file_from_archive(&mut source, "platform.txt")
+        .unwrap()
+        .build(&copy_path)
+        .is_err());
+}
+
+#[test]
+fn test_user_data_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("user_data.mpq");
+    let copy_path = temp_dir.path().join("user_data_copy.mpq");
+
+    let user_data: Vec<u8> = (0..300u32).map(|i| (i % 251) as u8).collect();
+
+    for version in [FormatVersion::V1, FormatVersion::V3] {
+        ArchiveBuilder::new()
+            .version(version)
+            .user_data(user_data.clone(), 0x40)
+            .add_file_data(b"Payload".to_vec(), "payload.txt")
+            .build(&source_path)
+            .unwrap();
+
+        let mut source = Archive::open(&source_path).unwrap();
+        assert_eq!(source.archive_offset(), 0x200);
+        let header = source.user_data().unwrap().clone();
+        assert_eq!(header.user_data_size, 300);
+        assert_eq!(header.header_offset, 0x200);
+        assert_eq!(header.user_data_header_size, 0x40);
+        assert_eq!(source.user_data_bytes().unwrap().unwrap(), user_data);
+        assert_eq!(source.read_file("payload.txt").unwrap(), b"Payload");
+
+        // Rebuild, carrying the user data over
+        let data = source.user_data_bytes().unwrap().unwrap();
+        ArchiveBuilder::new()
+            .version(version)
+            .user_data(data, header.user_data_header_size)
+            .add_file_from_archive(&mut source, "payload.txt")
+            .unwrap()
+            .build(&copy_path)
+            .unwrap();
+
+        let mut copy = Archive::open(&copy_path).unwrap();
+        assert_eq!(copy.user_data_bytes().unwrap().unwrap(), user_data);
+        assert_eq!(copy.read_file("payload.txt").unwrap(), b"Payload");
+    }
+}
+
+#[test]
+fn test_user_data_size_validation() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("bad_user_data.mpq");
+
+    // Header size larger than the user data is rejected by the builder
+    assert!(ArchiveBuilder::new()
+        .user_data(vec![0u8; 16], 32)
+        .build(&archive_path)
+        .is_err());
+
+    ArchiveBuilder::new()
+        .user_data(vec![0u8; 16], 16)
+        .add_file_data(b"Payload".to_vec(), "payload.txt")
+        .build(&archive_path)
+        .unwrap();
+    assert!(Archive::open(&archive_path).is_ok());
+
+    // User data declaring more than the file holds is skipped, and the scan
+    // still finds the archive at the next header boundary
+    let mut bytes = fs::read(&archive_path).unwrap();
+    bytes[4..8].copy_from_slice(&0x0100_0000u32.to_le_bytes());
+    fs::write(&archive_path, &bytes).unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
+    assert!(archive.user_data().is_none());
+    assert_eq!(archive.archive_offset(), 0x200);
+    assert_eq!(archive.read_file("payload.txt").unwrap(), b"Payload");
+}
+
+#[test]
+fn test_build_observer_events() {
+    use mopaq::{BuildObserver, BuildTable};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BuildObserver for Recorder {
+        fn on_file_start(&mut self, name: &str, index: usize, total: usize) {
+            let event = format!("start {name} {index}/{total}");
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_file_progress(&mut self, name: &str, processed: u64, total: u64) {
+            let event = format!("progress {name} {processed}/{total}");
+            self.events.lock().unwrap().push(event);
+        }
+
+        fn on_file_done(&mut self, name: &str, _compressed_size: u64, ratio: f64) {
+            assert!(ratio > 0.0);
+            self.events.lock().unwrap().push(format!("done {name}"));
+        }
+
+        fn on_table_write(&mut self, table: BuildTable, size: u64) {
+            assert!(size > 0);
+            self.events.lock().unwrap().push(format!("table {table:?}"));
+        }
+
+        fn on_finish(&mut self, archive_size: u64, file_count: usize, error: Option<&Error>) {
+            let event = match error {
+                None => {
+                    assert!(archive_size > 0);
+                    format!("finish {file_count}")
+                }
+                Some(e) => {
+                    assert_eq!(archive_size, 0);
+                    format!("failed {file_count}: {e}")
+                }
+            };
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("observed.mpq");
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    ArchiveBuilder::new()
+        .version(FormatVersion::V3)
+        .observer(Recorder {
+            events: events.clone(),
+        })
+        .add_file_data(vec![b'a'; 4096], "a.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        &events[..4],
+        &[
+            "start a.txt 0/2",
+            "done a.txt",
+            "start (listfile) 1/2",
+            "done (listfile)"
+        ]
+    );
+    for table in ["Het", "Bet", "Hash", "Block"] {
+        assert!(events.contains(&format!("table {table}")));
+    }
+    assert_eq!(events.last().unwrap(), "finish 2");
+    drop(events);
+
+    // Multi-sector files report progress once per sector
+    let events = Arc::new(Mutex::new(Vec::new()));
+    ArchiveBuilder::new()
+        .block_size(0)
+        .listfile_option(ListfileOption::None)
+        .observer(Recorder {
+            events: events.clone(),
+        })
+        .add_file_data(vec![b'b'; 1200], "b.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        &events[..5],
+        &[
+            "start b.txt 0/1",
+            "progress b.txt 512/1200",
+            "progress b.txt 1024/1200",
+            "progress b.txt 1200/1200",
+            "done b.txt"
+        ]
+    );
+    drop(events);
+
+    // A failed build still finishes, with the error
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let result = ArchiveBuilder::new()
+        .listfile_option(ListfileOption::None)
+        .observer(Recorder {
+            events: events.clone(),
+        })
+        .add_file(temp_dir.path().join("missing.bin"), "missing.bin")
+        .build(temp_dir.path().join("failed.mpq"));
+    assert!(result.is_err());
+    let events = events.lock().unwrap();
+    assert!(
+        events.last().unwrap().starts_with("failed 1: "),
+        "{events:?}"
+    );
+}
+
+#[test]
+fn test_build_plan_estimates() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("planned.mpq");
+    let disk_path = temp_dir.path().join("disk.bin");
+    fs::write(&disk_path, vec![7u8; 50_000]).unwrap();
+
+    for version in [FormatVersion::V1, FormatVersion::V3] {
+        let builder = ArchiveBuilder::new()
+            .version(version)
+            .generate_crcs(true)
+            .add_file(&disk_path, "disk.bin")
+            .add_file_data(b"Hello, plan!".to_vec(), "small.txt")
+            .add_file_data_with_options(
+                (0..20_000u32).flat_map(|i| i.to_le_bytes()).collect(),
+                "raw.bin",
+                0,
+                false,
+                0,
+            );
+
+        let plan = builder.plan().unwrap();
+        assert_eq!(plan.files.len(), 4);
+        assert_eq!(plan.files[3].name, "(listfile)");
+        assert_eq!(plan.block_table_entries, 4);
+        assert_eq!(plan.hash_table_entries, 16);
+        assert_eq!(plan.total_file_size, 50_000 + 12 + 80_000 + 42);
+        assert!(plan.files[0].estimated_ratio() < 0.1);
+        assert!(!plan.exceeds_format_limit());
+
+        // Nothing is written by planning
+        assert!(!archive_path.exists());
+
+        builder.build(&archive_path).unwrap();
+        let actual = fs::metadata(&archive_path).unwrap().len();
+        let estimated = plan.estimated_archive_size;
+        assert!(
+            estimated.abs_diff(actual) <= actual / 10,
+            "estimated {estimated}, actual {actual}"
+        );
+        fs::remove_file(&archive_path).unwrap();
+    }
+}
+
+#[test]
+fn test_update_archive_in_place() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("update.mpq");
+
+    ArchiveBuilder::new()
+        .version(FormatVersion::V2)
+        .block_size(4)
+        .add_file_data(b"Old content".to_vec(), "data/changed.txt")
+        .add_file_data(b"Keep me".to_vec(), "data/kept.txt")
+        .add_file_data(b"Delete me".to_vec(), "data/removed.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let builder = {
+        let mut archive = Archive::open(&archive_path).unwrap();
+        ArchiveBuilder::from_archive(&mut archive)
+            .unwrap()
+            .remove_file("DATA\\CHANGED.TXT")
+            .add_file_data(b"New content".to_vec(), "data/changed.txt")
+            .remove_file("data/removed.txt")
+            .add_file_data(b"Added".to_vec(), "data/added.txt")
+    };
+    builder.build(&archive_path).unwrap();
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(archive.header().format_version, FormatVersion::V2);
+    assert_eq!(archive.header().block_size, 4);
+    assert_eq!(
+        archive.read_file("data/changed.txt").unwrap(),
+        b"New content"
+    );
+    assert_eq!(archive.read_file("data/kept.txt").unwrap(), b"Keep me");
+    assert_eq!(archive.read_file("data/added.txt").unwrap(), b"Added");
+    assert!(archive.find_file("data/removed.txt").unwrap().is_none());
+
+    // Exactly one listfile entry per file, with no stale names
+    let mut names: Vec<String> = archive
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "(listfile)",
+            "data/added.txt",
+            "data/changed.txt",
+            "data/kept.txt"
+        ]
+    );
+}
+
+#[test]
+fn test_from_archive_requires_listfile() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("no_listfile.mpq");
+
+    ArchiveBuilder::new()
+        .listfile_option(ListfileOption::None)
+        .add_file_data(b"Unnamed".to_vec(), "hidden.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    assert!(ArchiveBuilder::from_archive(&mut archive).is_err());
+
+    // A listfile that misses a file would lose it in the rebuild
+    let listfile_path = temp_dir.path().join("partial.txt");
+    fs::write(&listfile_path, "listed.txt\r\n").unwrap();
+    let partial_path = temp_dir.path().join("partial.mpq");
+    ArchiveBuilder::new()
+        .listfile_option(ListfileOption::External(listfile_path))
+        .add_file_data(b"Listed".to_vec(), "listed.txt")
+        .add_file_data(b"Unnamed".to_vec(), "hidden.txt")
+        .build(&partial_path)
+        .unwrap();
+
+    let mut archive = Archive::open(&partial_path).unwrap();
+    assert!(matches!(
+        ArchiveBuilder::from_archive(&mut archive),
+        Err(mopaq::Error::InvalidFormat(_))
+    ));
+}
+
+#[test]
+fn test_parallel_build_matches_sequential() {
+    let temp_dir = TempDir::new().unwrap();
+    let sequential_path = temp_dir.path().join("sequential.mpq");
+    let parallel_path = temp_dir.path().join("parallel.mpq");
+
+    // Mix compressible and incompressible sectors across several files
+    let text: Vec<u8> = b"parallel sector compression ".repeat(4000);
+    let noise: Vec<u8> = (0..100_000u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect();
+
+    let build = |threads: usize, path: &std::path::Path| {
+        ArchiveBuilder::new()
+            .version(FormatVersion::V2)
+            .threads(threads)
+            .generate_crcs(true)
+            .add_file_data(text.clone(), "text.txt")
+            .add_file_data(noise.clone(), "noise.bin")
+            .add_file_data_with_encryption(text.clone(), "secret.txt", 0x02, true, 0)
+            .build(path)
+            .unwrap();
+    };
+    build(1, &sequential_path);
+    build(4, &parallel_path);
+
+    assert_eq!(
+        fs::read(&sequential_path).unwrap(),
+        fs::read(&parallel_path).unwrap()
+    );
+
+    let archive = Archive::open(&parallel_path).unwrap();
+    assert_eq!(archive.read_file("text.txt").unwrap(), text);
+    assert_eq!(archive.read_file("noise.bin").unwrap(), noise);
+    assert_eq!(archive.read_file("secret.txt").unwrap(), text);
+}
+
+#[test]
+fn test_crc32_sector_checksums() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("crc32.mpq");
+
+    let data = b"Checksummed with CRC32 instead of ADLER32".to_vec();
+    ArchiveBuilder::new()
+        .generate_crcs(true)
+        .sector_checksum(SectorChecksum::Crc32)
+        .add_file_data_with_options(data.clone(), "single.txt", 0, false, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    // Auto-detection and the explicit algorithm both accept the file
+    let archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(archive.read_file("single.txt").unwrap(), data);
+
+    let options = OpenOptions::new().sector_checksum(Some(SectorChecksum::Crc32));
+    let archive = Archive::open_with_options(&archive_path, options).unwrap();
+    assert_eq!(archive.read_file("single.txt").unwrap(), data);
+
+    // Requiring ADLER32 rejects it
+    let options = OpenOptions::new().sector_checksum(Some(SectorChecksum::Adler32));
+    let archive = Archive::open_with_options(&archive_path, options).unwrap();
+    assert!(matches!(
+        archive.read_file("single.txt"),
+        Err(Error::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_sector_checksums_cover_stored_data() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("stored_crc.mpq");
+
+    let data = b"Compressible sector data. ".repeat(1024);
+    ArchiveBuilder::new()
+        .block_size(3)
+        .generate_crcs(true)
+        .add_file_data(data.clone(), "data.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let map = Archive::open(&archive_path)
+        .unwrap()
+        .sector_map("data.txt")
+        .unwrap();
+    assert!(map.sectors.len() > 1);
+
+    // Each checksum matches the sector bytes as stored, not the
+    // decompressed data
+    let raw = fs::read(&archive_path).unwrap();
+    for sector in &map.sectors {
+        assert!(sector.compressed);
+        let start = (map.file_pos + sector.offset) as usize;
+        let stored = &raw[start..start + sector.compressed_size as usize];
+        assert_eq!(
+            sector.checksum,
+            Some(SectorChecksum::Adler32.compute(stored))
+        );
+    }
+}
+
+#[test]
+fn test_compatibility_presets() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("d2.mpq");
+    let data = b"armor\tcode\n".repeat(1000);
+
+    ArchiveBuilder::new()
+        .compatibility(Compatibility::Diablo2)
+        .add_file_data(data.clone(), "data\\global\\excel\\armor.txt")
+        .build(&archive_path)
+        .unwrap();
+    let archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(archive.header().format_version, FormatVersion::V1);
+    assert_eq!(archive.header().block_size, 3);
+    assert_eq!(
+        archive.read_file("data\\global\\excel\\armor.txt").unwrap(),
+        data
+    );
+
+    let rejected = [
+        ArchiveBuilder::new()
+            .compatibility(Compatibility::Diablo2)
+            .add_file_data_with_options(data.clone(), "a.txt", compression::flags::ZLIB, false, 0),
+        ArchiveBuilder::new()
+            .compatibility(Compatibility::Warcraft3)
+            .block_size(5),
+        ArchiveBuilder::new()
+            .compatibility(Compatibility::WowTBC)
+            .version(FormatVersion::V3),
+        ArchiveBuilder::new()
+            .compatibility(Compatibility::WowWotLK)
+            .add_file_data_with_options(data.clone(), "a.txt", compression::flags::LZMA, false, 0),
+        ArchiveBuilder::new()
+            .compatibility(Compatibility::WowCataclysm)
+            .version(FormatVersion::V4)
+            .classic_tables(false),
+        ArchiveBuilder::new()
+            .compatibility(Compatibility::StarCraft)
+            .name_hashing(NameHashingPolicy::VERBATIM),
+    ];
+    for builder in rejected {
+        let result = builder.build(temp_dir.path().join("rejected.mpq"));
+        assert!(
+            matches!(result, Err(Error::Incompatible { .. })),
+            "{:?}",
+            result
+        );
+    }
+    assert!(!temp_dir.path().join("rejected.mpq").exists());
+
+    // Lower versions and uncompressed files stay allowed
+    ArchiveBuilder::new()
+        .compatibility(Compatibility::WowCataclysm)
+        .version(FormatVersion::V2)
+        .add_file_data_with_options(data, "a.txt", 0, false, 0)
+        .build(temp_dir.path().join("cata.mpq"))
+        .unwrap();
+}
+
+#[test]
+fn test_file_flag_combinations() {
+    use mopaq::compression::flags;
+    use mopaq::crypto::{decrypt_block, hash_string, hash_type};
+    use std::io::{Read, Seek, SeekFrom};
+
+    let temp_dir = TempDir::new().unwrap();
+    let sector_size = 4096;
+    // Text that every codec shrinks, long enough for several sectors
+    let multi: Vec<u8> = b"units\\human\\footman.mdx 1 2 3\r\n"
+        .iter()
+        .cycle()
+        .take(sector_size * 3 + 100)
+        .copied()
+        .collect();
+    let single = multi[..1000].to_vec();
+
+    for (size, data) in [("single", &single), ("multi", &multi)] {
+        for compression in [0, flags::ZLIB] {
+            for (encrypt, fix_key) in [(false, false), (true, false), (true, true)] {
+                for crcs in [false, true] {
+                    let case = format!(
+                        "{} compression={} encrypt={} fix_key={} crcs={}",
+                        size, compression, encrypt, fix_key, crcs
+                    );
+                    let name = "file.bin";
+                    let path = temp_dir.path().join("matrix.mpq");
+                    let builder = ArchiveBuilder::new()
+                        .block_size(3)
+                        .generate_crcs(crcs)
+                        .listfile_option(ListfileOption::None);
+                    let builder = if encrypt {
+                        builder.add_file_data_with_encryption(
+                            data.clone(),
+                            name,
+                            compression,
+                            fix_key,
+                            0,
+                        )
+                    } else {
+                        builder.add_file_data_with_options(
+                            data.clone(),
+                            name,
+                            compression,
+                            false,
+                            0,
+                        )
+                    };
+                    builder.build(&path).unwrap();
+
+                    let archive = Archive::open(&path).unwrap();
+                    let info = archive.find_file(name).unwrap().unwrap();
+                    let is_single = size == "single";
+                    assert_eq!(info.is_single_unit(), is_single, "{}", case);
+                    assert_eq!(info.is_encrypted(), encrypt, "{}", case);
+                    assert_eq!(info.has_fix_key(), fix_key, "{}", case);
+                    assert_eq!(info.has_sector_crc(), crcs, "{}", case);
+                    // Multi-sector files need the offset table for their checksums
+                    let expect_compressed = compression != 0 || (!is_single && crcs);
+                    assert_eq!(info.is_compressed(), expect_compressed, "{}", case);
+
+                    assert_eq!(archive.read_file(name).unwrap(), *data, "{}", case);
+                    let map = archive.sector_map(name).unwrap();
+                    assert!(
+                        map.sectors.iter().all(|s| s.checksum.is_some() == crcs),
+                        "{}",
+                        case
+                    );
+
+                    // Uncompressed sectors are encrypted with consecutive keys
+                    if !is_single && !expect_compressed && encrypt {
+                        let mut key = hash_string(name, hash_type::FILE_KEY);
+                        if fix_key {
+                            key = (key.wrapping_add(info.file_pos as u32)) ^ data.len() as u32;
+                        }
+                        let mut raw = vec![0u8; 16];
+                        let mut file = fs::File::open(&path).unwrap();
+                        file.seek(SeekFrom::Start(info.file_pos + sector_size as u64))
+                            .unwrap();
+                        file.read_exact(&mut raw).unwrap();
+                        let mut words: Vec<u32> = raw
+                            .chunks_exact(4)
+                            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+                            .collect();
+                        decrypt_block(&mut words, key.wrapping_add(1));
+                        let plain: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+                        assert_eq!(plain, data[sector_size..sector_size + 16], "{}", case);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_implode_files() {
+    use mopaq::compression::{flags, CompressionMethod};
+    use mopaq::tables::BlockEntry;
+
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("implode.mpq");
+    let text: Vec<u8> = b"[Monster]\r\nName=Fallen One\r\nHP=4\r\n"
+        .iter()
+        .cycle()
+        .take(10_000)
+        .copied()
+        .collect();
+    let small = text[..2000].to_vec();
+
+    for crcs in [false, true] {
+        ArchiveBuilder::new()
+            .block_size(3)
+            .generate_crcs(crcs)
+            .use_implode(true)
+            .add_file_data_with_options(small.clone(), "small.txt", flags::PKWARE, false, 0)
+            .add_file_data_with_options(text.clone(), "large.txt", flags::PKWARE, false, 0)
+            .add_file_data_with_encryption(text.clone(), "secret.txt", flags::PKWARE, true, 0)
+            .add_file_data_with_options(text.clone(), "zlib.txt", flags::ZLIB, false, 0)
+            .build(&path)
+            .unwrap();
+
+        let archive = Archive::open(&path).unwrap();
+        for (name, data) in [
+            ("small.txt", &small),
+            ("large.txt", &text),
+            ("secret.txt", &text),
+        ] {
+            let info = archive.find_file(name).unwrap().unwrap();
+            assert_ne!(info.flags & BlockEntry::FLAG_IMPLODE, 0, "{}", name);
+            assert_eq!(info.flags & BlockEntry::FLAG_COMPRESS, 0, "{}", name);
+            assert_eq!(archive.read_file(name).unwrap(), *data, "{}", name);
+            assert_eq!(
+                archive.compression_method(name).unwrap(),
+                CompressionMethod::PKWare
+            );
+        }
+
+        // Other codecs keep the mask byte and FLAG_COMPRESS
+        let info = archive.find_file("zlib.txt").unwrap().unwrap();
+        assert_eq!(info.flags & BlockEntry::FLAG_IMPLODE, 0);
+        assert_eq!(archive.read_file("zlib.txt").unwrap(), text);
+    }
+
+    // The stored data starts with the DCL header rather than a mask byte
+    let archive_bytes = fs::read(&path).unwrap();
+    let archive = Archive::open(&path).unwrap();
+    let info = archive.find_file("small.txt").unwrap().unwrap();
+    let stored = &archive_bytes[info.file_pos as usize..];
+    assert_ne!(stored[0], flags::PKWARE);
+    assert_eq!(
+        mopaq::decompress(stored, flags::PKWARE, small.len()).unwrap(),
+        small
+    );
+}
+
+#[cfg(feature = "build-manifest")]
+#[test]
+fn test_build_from_manifest() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    fs::create_dir_all(dir.join("build")).unwrap();
+    fs::write(
+        dir.join("build").join("war3map.j"),
+        "function main takes nothing",
+    )
+    .unwrap();
+    ArchiveBuilder::new()
+        .add_file_data(b"neutral".to_vec(), "war3map.wts")
+        .add_file_data_with_options(b"french".to_vec(), "war3map.wts", 0, false, Locale::FR_FR)
+        .add_file_data_with_encryption(
+            b"secret".to_vec(),
+            "old\\secret.txt",
+            0,
+            true,
+            Locale::NEUTRAL,
+        )
+        .build(dir.join("base.mpq"))
+        .unwrap();
+
+    let manifest = r#"
+        [archive]
+        version = 2
+        compression = "zlib"
+
+        [[file]]
+        name = "scripts\\war3map.j"
+        path = "build/war3map.j"
+        compression = "bzip2"
+
+        [[file]]
+        name = "war3map.wts"
+        archive = "base.mpq"
+        locale = "frFR"
+
+        [[file]]
+        name = "secret.txt"
+        archive = "base.mpq"
+        source_name = "old\\secret.txt"
+
+        [[file]]
+        name = "readme.txt"
+        data = "SGVsbG8sIHdvcmxkIQ=="
+        compression = "none"
+    "#;
+    let manifest_path = dir.join("manifest.toml");
+    fs::write(&manifest_path, manifest).unwrap();
+
+    let archive_path = dir.join("out.mpq");
+    ArchiveBuilder::from_manifest(&manifest_path)
+        .unwrap()
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(archive.header().format_version, FormatVersion::V2);
+    assert_eq!(
+        archive.read_file("scripts\\war3map.j").unwrap(),
+        b"function main takes nothing"
+    );
+    assert_eq!(
+        archive
+            .read_file_with_locale("war3map.wts", Locale::FR_FR)
+            .unwrap(),
+        b"french"
+    );
+    assert_eq!(
+        archive.file_locales("war3map.wts").unwrap(),
+        [Locale::FR_FR]
+    );
+
+    // Copies keep their encryption unless the manifest says otherwise
+    let secret = archive.find_file("secret.txt").unwrap().unwrap();
+    assert!(secret.is_encrypted() && secret.has_fix_key());
+    assert_eq!(archive.read_file("secret.txt").unwrap(), b"secret");
+
+    let readme = archive.find_file("readme.txt").unwrap().unwrap();
+    assert!(!readme.is_compressed());
+    assert_eq!(archive.read_file("readme.txt").unwrap(), b"Hello, world!");
+
+    // Files must say where their contents come from
+    fs::write(
+        &manifest_path,
+        "[[file]]\nname = \"a\"\npath = \"a\"\ndata = \"\"",
+    )
+    .unwrap();
+    assert!(ArchiveBuilder::from_manifest(&manifest_path).is_err());
+}
+
+#[cfg(feature = "build-manifest")]
+#[test]
+fn test_manifest_round_trip() {
+    use mopaq::BuildManifest;
+    use std::path::Path;
+
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    let source_path = dir.join("source.mpq");
+    ArchiveBuilder::new()
+        .version(FormatVersion::V2)
+        .block_size(4)
+        .add_file_data(b"neutral".repeat(64), "war3map.wts")
+        .add_file_data_with_options(b"french".to_vec(), "war3map.wts", 0, false, Locale::FR_FR)
+        .add_file_data(vec![b'z'; 4096], "Units\\footman.txt")
+        .add_file_data(vec![b'p'; 4096], "Units\\peasant.txt")
+        .add_file_data_with_options(
+            vec![b'b'; 4096],
+            "Units\\knight.txt",
+            compression::flags::BZIP2,
+            false,
+            Locale::NEUTRAL,
+        )
+        .add_file_data_with_encryption(
+            b"secret".repeat(64),
+            "secret.txt",
+            compression::flags::ZLIB,
+            true,
+            Locale::NEUTRAL,
+        )
+        .build(&source_path)
+        .unwrap();
+
+    let mut source = Archive::open(&source_path).unwrap();
+    let manifest = BuildManifest::from_archive(&mut source, Path::new("source.mpq")).unwrap();
+    assert_eq!(manifest.archive.version, Some(2));
+    assert_eq!(manifest.archive.block_size, Some(4));
+    assert_eq!(manifest.archive.compression.as_deref(), Some("zlib"));
+    assert!(!manifest.files.iter().any(|file| file.name == "(listfile)"));
+    let knight = manifest
+        .files
+        .iter()
+        .find(|file| file.name == "Units\\knight.txt")
+        .unwrap();
+    assert_eq!(knight.compression.as_deref(), Some("bzip2"));
+    let french = manifest
+        .files
+        .iter()
+        .find(|file| file.locale.as_deref() == Some("frFR"))
+        .unwrap();
+    assert_eq!(french.compression.as_deref(), Some("none"));
+    let secret = manifest
+        .files
+        .iter()
+        .find(|file| file.name == "secret.txt")
+        .unwrap();
+    assert_eq!(secret.compression, None);
+    assert_eq!(secret.fix_key, Some(true));
+
+    // Unpack, edit one file on disk and rebuild from the written manifest
+    source.extract_all(dir.join("data")).unwrap();
+    fs::write(dir.join("data").join("Units").join("footman.txt"), "edited").unwrap();
+    let manifest = manifest.with_data_dir(Path::new("data"));
+    manifest.validate().unwrap();
+    let manifest_path = dir.join("manifest.toml");
+    fs::write(&manifest_path, manifest.to_toml().unwrap()).unwrap();
+    assert_eq!(
+        BuildManifest::load(&manifest_path).unwrap(),
+        manifest,
+        "TOML round trip"
+    );
+
+    let rebuilt_path = dir.join("rebuilt.mpq");
+    ArchiveBuilder::from_manifest(&manifest_path)
+        .unwrap()
+        .build(&rebuilt_path)
+        .unwrap();
+
+    let rebuilt = Archive::open(&rebuilt_path).unwrap();
+    assert_eq!(rebuilt.header().format_version, FormatVersion::V2);
+    assert_eq!(rebuilt.header().block_size, 4);
+    assert_eq!(rebuilt.read_file("Units\\footman.txt").unwrap(), b"edited");
+    assert_eq!(
+        rebuilt.read_file("Units\\knight.txt").unwrap(),
+        vec![b'b'; 4096]
+    );
+    assert_eq!(
+        rebuilt.compression_method("Units\\knight.txt").unwrap(),
+        compression::CompressionMethod::BZip2
+    );
+    assert_eq!(
+        rebuilt
+            .read_file_with_locale("war3map.wts", Locale::FR_FR)
+            .unwrap(),
+        b"french"
+    );
+    assert_eq!(
+        rebuilt.read_file("war3map.wts").unwrap(),
+        b"neutral".repeat(64)
+    );
+    let secret = rebuilt.find_file("secret.txt").unwrap().unwrap();
+    assert!(secret.is_encrypted() && secret.has_fix_key());
+    assert_eq!(
+        rebuilt.read_file("secret.txt").unwrap(),
+        b"secret".repeat(64)
+    );
+}
+
+#[test]
+fn test_file_larger_than_4gib() {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let temp_dir = TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("huge.bin");
+
+    // A sparse file: only its first and last bytes take up disk space
+    let size = (1u64 << 32) + 4103;
+    let mut source = fs::File::create(&source_path).unwrap();
+    source.write_all(b"head").unwrap();
+    source.seek(SeekFrom::Start(size - 4)).unwrap();
+    source.write_all(b"tail").unwrap();
+    drop(source);
+
+    // The tables of v1 and v2 archives only hold 32-bit sizes
+    let result = ArchiveBuilder::new()
+        .version(FormatVersion::V2)
+        .default_compression(0)
+        .add_file(&source_path, "huge.bin")
+        .build(temp_dir.path().join("huge_v2.mpq"));
+    assert!(matches!(result, Err(Error::CapacityExceeded(_))));
+
+    let archive_path = temp_dir.path().join("huge.mpq");
+    ArchiveBuilder::new()
+        .version(FormatVersion::V4)
+        .default_compression(0)
+        .add_file_data(b"before".to_vec(), "before.txt")
+        .add_file(&source_path, "huge.bin")
+        .add_file_data(b"after".to_vec(), "after.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let check = |archive: &mut Archive| {
+        let info = archive.find_file("huge.bin").unwrap().unwrap();
+        assert_eq!((info.file_size, info.compressed_size), (size, size));
+        let info = archive
+            .find_file_with_locale("huge.bin", Locale::NEUTRAL)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.file_size, size);
+
+        // The file after it starts beyond 4 GiB
+        assert_eq!(archive.read_file("after.txt").unwrap(), b"after");
+
+        let mut read = 0u64;
+        let mut last = Vec::new();
+        archive
+            .read_file_chunks("huge.bin", |chunk| {
+                if read == 0 {
+                    assert!(chunk.starts_with(b"head"));
+                }
+                read += chunk.len() as u64;
+                last.clear();
+                last.extend_from_slice(chunk);
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(read, size);
+        assert!(last.ends_with(b"tail"));
+
+        let md5 = archive.get_info().unwrap().md5_status.unwrap();
+        assert!(md5.header_valid && md5.het_table_valid && md5.bet_table_valid);
+    };
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    check(&mut archive);
+    drop(archive);
+
+    // Rebuilding the HET/BET tables in place keeps the full sizes
+    let mut mutable = mopaq::MutableArchive::open(&archive_path).unwrap();
+    mutable.update_listfile(&[], &["before.txt"]).unwrap();
+    mutable.flush().unwrap();
+    drop(mutable);
+    check(&mut Archive::open(&archive_path).unwrap());
+}
+
+#[cfg(feature = "metadata")]
+#[test]
+fn test_metadata_special_file() {
+    use mopaq::special_files::Metadata;
+
+    let temp_dir = TempDir::new().unwrap();
+    let plain_path = temp_dir.path().join("plain.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"function main".to_vec(), "war3map.j")
+        .build(&plain_path)
+        .unwrap();
+    assert!(Archive::open(&plain_path)
+        .unwrap()
+        .metadata()
+        .unwrap()
+        .is_none());
+
+    let mut metadata = Metadata::new();
+    metadata.insert("war3map.j", "author", "Jane Doe");
+    metadata.insert("units\\footman.mdx", "license", "CC-BY-4.0");
+
+    let archive_path = temp_dir.path().join("metadata.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"function main".to_vec(), "war3map.j")
+        .add_file_data(b"MDLX".to_vec(), "units\\footman.mdx")
+        .metadata(Metadata::new())
+        .metadata(metadata.clone())
+        .build(&archive_path)
+        .unwrap();
+
+    let archive = Archive::open(&archive_path).unwrap();
+    let read = archive.metadata().unwrap().unwrap();
+    assert_eq!(read, metadata);
+    assert_eq!(
+        read.value("Units/Footman.mdx", "license"),
+        Some("CC-BY-4.0")
+    );
+    assert_eq!(archive.read_file("war3map.j").unwrap(), b"function main");
+
+    // Paths are looked up with the archive's name hashing
+    let verbatim_path = temp_dir.path().join("verbatim.mpq");
+    ArchiveBuilder::new()
+        .name_hashing(NameHashingPolicy::VERBATIM)
+        .metadata(metadata)
+        .build(&verbatim_path)
+        .unwrap();
+    let verbatim = OpenOptions::new()
+        .name_hashing(NameHashingPolicy::VERBATIM)
+        .open(&verbatim_path)
+        .unwrap()
+        .metadata()
+        .unwrap()
+        .unwrap();
+    assert_eq!(verbatim.value("Units/Footman.mdx", "license"), None);
+    assert_eq!(
+        verbatim.value("units\\footman.mdx", "license"),
+        Some("CC-BY-4.0")
+    );
+}
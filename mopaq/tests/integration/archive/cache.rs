@@ -0,0 +1,117 @@
+//! Tests for the on-disk archive metadata cache
+
+use mopaq::{ArchiveBuilder, MetricsCounters, NameHashingPolicy, OpenOptions, TableKey};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn build(path: &Path, names: &[&str]) {
+    let mut builder = ArchiveBuilder::new();
+    for name in names {
+        builder = builder.add_file_data(name.as_bytes().to_vec(), name);
+    }
+    builder.build(path).unwrap();
+}
+
+fn listed(archive_path: &Path, cache_dir: &Path) -> Vec<String> {
+    let mut archive = OpenOptions::new()
+        .cache_dir(cache_dir)
+        .open(archive_path)
+        .unwrap();
+    let mut names: Vec<String> = archive
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    names
+}
+
+fn cache_files(cache_dir: &Path) -> Vec<std::fs::DirEntry> {
+    std::fs::read_dir(cache_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect()
+}
+
+#[test]
+fn test_metadata_cache() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("data.mpq");
+    let cache_dir = temp_dir.path().join("cache");
+    build(&archive_path, &["a.txt", "b.txt"]);
+
+    // The first open writes one cache file
+    assert_eq!(
+        listed(&archive_path, &cache_dir),
+        ["(listfile)", "a.txt", "b.txt"]
+    );
+    let files = cache_files(&cache_dir);
+    assert_eq!(files.len(), 1);
+    let written = files[0].metadata().unwrap().modified().unwrap();
+
+    // Later opens read it without rewriting it, and still read file data
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(
+        listed(&archive_path, &cache_dir),
+        ["(listfile)", "a.txt", "b.txt"]
+    );
+    let archive = OpenOptions::new()
+        .cache_dir(&cache_dir)
+        .open(&archive_path)
+        .unwrap();
+    assert_eq!(archive.read_file("b.txt").unwrap(), b"b.txt");
+    assert_eq!(
+        cache_files(&cache_dir)[0]
+            .metadata()
+            .unwrap()
+            .modified()
+            .unwrap(),
+        written
+    );
+
+    // Changing the archive makes the cache stale
+    build(&archive_path, &["a.txt", "b.txt", "c.txt"]);
+    assert_eq!(
+        listed(&archive_path, &cache_dir),
+        ["(listfile)", "a.txt", "b.txt", "c.txt"]
+    );
+    let files = cache_files(&cache_dir);
+    assert_eq!(files.len(), 1);
+    assert_ne!(files[0].metadata().unwrap().modified().unwrap(), written);
+
+    // A corrupt cache file is ignored
+    std::fs::write(files[0].path(), b"MPQCACH1 garbage").unwrap();
+    assert_eq!(
+        listed(&archive_path, &cache_dir),
+        ["(listfile)", "a.txt", "b.txt", "c.txt"]
+    );
+}
+
+#[test]
+fn test_metadata_cache_open_options() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("data.mpq");
+    let cache_dir = temp_dir.path().join("cache");
+    build(&archive_path, &["a.txt", "b.txt"]);
+    listed(&archive_path, &cache_dir);
+
+    // Options that change how tables are read need their own cache entry
+    let counters = Arc::new(MetricsCounters::new());
+    let open = |options: OpenOptions| {
+        options
+            .cache_dir(&cache_dir)
+            .metrics(counters.clone())
+            .open(&archive_path)
+            .unwrap()
+    };
+    open(OpenOptions::new().table_key_override(TableKey::Recover, TableKey::Recover));
+    open(OpenOptions::new().table_key_override(TableKey::Recover, TableKey::Recover));
+    open(OpenOptions::new().name_hashing(NameHashingPolicy {
+        separator: Some(b'/'),
+        ..NameHashingPolicy::BLIZZARD
+    }));
+    assert_eq!(counters.cache_misses(), 2);
+    assert_eq!(counters.cache_hits(), 1);
+}
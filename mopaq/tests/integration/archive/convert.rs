@@ -0,0 +1,226 @@
+//! Tests for converting between MPQ archives and ZIP or TAR files
+
+use flate2::write::GzEncoder;
+use mopaq::convert::{self, ConvertOptions, ZipMethod};
+use mopaq::{Archive, ArchiveBuilder, Error, ListOptions};
+use std::io::Write;
+use std::path::Path;
+
+fn build(path: &Path) -> Vec<u8> {
+    let content: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    ArchiveBuilder::new()
+        .add_file_data(content.clone(), "Data\\units.bin")
+        .add_file_data(b"hello".to_vec(), "readme.txt")
+        .add_file_data(Vec::new(), "empty.txt")
+        .build(path)
+        .unwrap();
+    content
+}
+
+/// A ustar header block followed by `data` padded to 512 bytes
+fn tar_entry(name: &str, kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..107].copy_from_slice(b"0000644");
+    header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[148..156].copy_from_slice(b"        ");
+    let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", sum).as_bytes());
+
+    let mut entry = header.to_vec();
+    entry.extend_from_slice(data);
+    entry.resize(entry.len().div_ceil(512) * 512, 0);
+    entry
+}
+
+fn tar(entries: &[Vec<u8>]) -> Vec<u8> {
+    let mut tar = entries.concat();
+    tar.extend_from_slice(&[0; 1024]);
+    tar
+}
+
+#[test]
+fn test_mpq_zip_round_trip() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("source.mpq");
+    let content = build(&source_path);
+
+    for method in [ZipMethod::Deflated, ZipMethod::Stored] {
+        let zip_path = temp_dir.path().join("export.zip");
+        let mut source = Archive::open(&source_path).unwrap();
+        let options = ConvertOptions::new()
+            .zip_method(method)
+            .add_prefix("MyMod/");
+        let summary = convert::mpq_to_zip(&mut source, &zip_path, &options).unwrap();
+        assert_eq!(summary.files, 3);
+        assert_eq!(summary.bytes, content.len() as u64 + 5);
+        assert!(summary.skipped.is_empty());
+
+        let mpq_path = temp_dir.path().join("import.mpq");
+        let options = ConvertOptions::new().strip_prefix("mymod\\");
+        let summary = convert::zip_to_mpq(&zip_path, &mpq_path, &options).unwrap();
+        assert_eq!(summary.files, 3);
+
+        let mut imported = Archive::open(&mpq_path).unwrap();
+        assert_eq!(imported.read_file("data\\units.bin").unwrap(), content);
+        assert_eq!(imported.read_file("readme.txt").unwrap(), b"hello");
+        assert!(imported.read_file("empty.txt").unwrap().is_empty());
+        assert_eq!(imported.list_with(&ListOptions::new()).unwrap().len(), 3);
+    }
+}
+
+#[test]
+fn test_zip_export_is_reproducible() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("source.mpq");
+    build(&source_path);
+
+    let mut source = Archive::open(&source_path).unwrap();
+    let first = temp_dir.path().join("first.zip");
+    let second = temp_dir.path().join("second.zip");
+    convert::mpq_to_zip(&mut source, &first, &ConvertOptions::new()).unwrap();
+    convert::mpq_to_zip(&mut source, &second, &ConvertOptions::new()).unwrap();
+    assert_eq!(
+        std::fs::read(first).unwrap(),
+        std::fs::read(second).unwrap()
+    );
+}
+
+#[test]
+fn test_zip_crc_mismatch() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("source.mpq");
+    build(&source_path);
+
+    let zip_path = temp_dir.path().join("export.zip");
+    let mut source = Archive::open(&source_path).unwrap();
+    let options = ConvertOptions::new().zip_method(ZipMethod::Stored);
+    convert::mpq_to_zip(&mut source, &zip_path, &options).unwrap();
+
+    // Flip a byte of the stored data of the first entry
+    let mut zip = std::fs::read(&zip_path).unwrap();
+    let name_len = u16::from_le_bytes([zip[26], zip[27]]) as usize;
+    zip[30 + name_len] ^= 0xff;
+    std::fs::write(&zip_path, zip).unwrap();
+
+    let result = convert::zip_to_mpq(
+        &zip_path,
+        temp_dir.path().join("import.mpq"),
+        &ConvertOptions::new(),
+    );
+    assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+}
+
+#[test]
+fn test_zip_entry_larger_than_declared() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("source.mpq");
+    build(&source_path);
+
+    let zip_path = temp_dir.path().join("export.zip");
+    let mut source = Archive::open(&source_path).unwrap();
+    convert::mpq_to_zip(&mut source, &zip_path, &ConvertOptions::new()).unwrap();
+
+    // Shrink the declared size of the deflated units.bin entry
+    let mut zip = std::fs::read(&zip_path).unwrap();
+    let entry = zip
+        .windows(4)
+        .enumerate()
+        .filter(|(_, sig)| *sig == b"PK\x01\x02")
+        .map(|(pos, _)| pos)
+        .find(|&pos| zip[pos + 46..].starts_with(b"Data/units.bin"))
+        .unwrap();
+    zip[entry + 24..entry + 28].copy_from_slice(&100u32.to_le_bytes());
+    std::fs::write(&zip_path, zip).unwrap();
+
+    let result = convert::zip_to_mpq(
+        &zip_path,
+        temp_dir.path().join("import.mpq"),
+        &ConvertOptions::new(),
+    );
+    assert!(matches!(
+        result,
+        Err(Error::InvalidFileSize {
+            expected: 100,
+            actual: 101
+        })
+    ));
+}
+
+#[test]
+fn test_tar_to_mpq() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let long_name = format!("mod/{}/deep.txt", "nested".repeat(20));
+    let tar = tar(&[
+        tar_entry("mod/", b'5', &[]),
+        tar_entry("mod/units.txt", b'0', b"unit data"),
+        tar_entry("././@LongLink", b'L', format!("{}\0", long_name).as_bytes()),
+        tar_entry("mod/truncated", b'0', b"long"),
+        tar_entry("mod/link", b'2', &[]),
+    ]);
+
+    let plain_path = temp_dir.path().join("mod.tar");
+    std::fs::write(&plain_path, &tar).unwrap();
+    let gz_path = temp_dir.path().join("mod.tar.gz");
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&tar).unwrap();
+    std::fs::write(&gz_path, encoder.finish().unwrap()).unwrap();
+
+    for tar_path in [plain_path, gz_path] {
+        let mpq_path = temp_dir.path().join("mod.mpq");
+        let options = ConvertOptions::new()
+            .strip_prefix("mod/")
+            .add_prefix("Interface\\");
+        let summary = convert::tar_to_mpq(&tar_path, &mpq_path, &options).unwrap();
+        assert_eq!(summary.files, 2);
+        assert_eq!(summary.bytes, 13);
+        assert_eq!(summary.skipped, vec!["mod/link".to_string()]);
+
+        let archive = Archive::open(&mpq_path).unwrap();
+        assert_eq!(
+            archive.read_file("Interface\\units.txt").unwrap(),
+            b"unit data"
+        );
+        let deep = long_name.replace("mod/", "Interface\\").replace('/', "\\");
+        assert_eq!(archive.read_file(&deep).unwrap(), b"long");
+    }
+}
+
+#[test]
+fn test_tar_bad_checksum() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let mut tar = tar(&[tar_entry("file.txt", b'0', b"data")]);
+    tar[0] = b'F';
+    let tar_path = temp_dir.path().join("bad.tar");
+    std::fs::write(&tar_path, tar).unwrap();
+
+    let result = convert::tar_to_mpq(
+        &tar_path,
+        temp_dir.path().join("bad.mpq"),
+        &ConvertOptions::new(),
+    );
+    assert!(matches!(result, Err(Error::InvalidFormat(_))));
+}
+
+#[test]
+fn test_tar_oversized_long_name() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    for kind in [b'L', b'x'] {
+        let tar = tar(&[
+            tar_entry("././@LongLink", kind, &vec![b'a'; (1 << 20) + 1]),
+            tar_entry("file.txt", b'0', b"data"),
+        ]);
+        let tar_path = temp_dir.path().join("long.tar");
+        std::fs::write(&tar_path, tar).unwrap();
+
+        let result = convert::tar_to_mpq(
+            &tar_path,
+            temp_dir.path().join("long.mpq"),
+            &ConvertOptions::new(),
+        );
+        assert!(matches!(result, Err(Error::InvalidFormat(_))), "{result:?}");
+    }
+}
@@ -0,0 +1,88 @@
+//! Tests for delta patches between archives
+
+use mopaq::compression::CompressionMethod;
+use mopaq::delta::DeltaChange;
+use mopaq::{Archive, ArchiveBuilder, DeltaPatch, Error};
+
+fn level_data(version: u8) -> Vec<u8> {
+    let mut data: Vec<u8> = (0..50_000u32).map(|i| (i % 97) as u8 ^ 0x5A).collect();
+    data[20_000] = version;
+    data
+}
+
+#[test]
+fn test_delta_patch_upgrade() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let old_path = temp_dir.path().join("old.mpq");
+    let new_path = temp_dir.path().join("new.mpq");
+    let patch_path = temp_dir.path().join("update.mpqdelta");
+
+    ArchiveBuilder::new()
+        .add_file_data(level_data(1), "maps\\level.dat")
+        .add_file_data(b"unchanged".to_vec(), "readme.txt")
+        .add_file_data(b"obsolete".to_vec(), "old.txt")
+        .build(&old_path)
+        .unwrap();
+    ArchiveBuilder::new()
+        .generate_crcs(true)
+        .add_file_data(level_data(2), "maps\\level.dat")
+        .add_file_data(b"unchanged".to_vec(), "readme.txt")
+        .add_file_data_with_encryption(b"brand new".to_vec(), "new.txt", 0, true, 0)
+        .add_file_data_with_options(vec![7; 10_000], "raw.bin", 0, false, 0)
+        .build(&new_path)
+        .unwrap();
+
+    let patch = DeltaPatch::generate(
+        &mut Archive::open(&old_path).unwrap(),
+        &mut Archive::open(&new_path).unwrap(),
+    )
+    .unwrap();
+
+    let names: Vec<&str> = patch.entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names.len(), 4);
+    assert!(!names.contains(&"readme.txt"));
+    let level = patch
+        .entries
+        .iter()
+        .find(|e| e.name == "maps\\level.dat")
+        .unwrap();
+    assert!(matches!(&level.change, DeltaChange::Diff { delta, .. } if delta.len() < 100));
+
+    // The container round-trips and is much smaller than the changed file
+    patch.save(&patch_path).unwrap();
+    assert!(std::fs::metadata(&patch_path).unwrap().len() < 1_000);
+    let loaded = DeltaPatch::load(&patch_path).unwrap();
+    assert_eq!(loaded, patch);
+
+    loaded.apply(&old_path).unwrap();
+    let upgraded = Archive::open(&old_path).unwrap();
+    assert_eq!(
+        upgraded.read_file("maps\\level.dat").unwrap(),
+        level_data(2)
+    );
+    assert_eq!(upgraded.read_file("readme.txt").unwrap(), b"unchanged");
+    assert_eq!(upgraded.read_file("new.txt").unwrap(), b"brand new");
+
+    // Files keep the flags and compression they have in the new archive
+    let new_txt = upgraded.find_file("new.txt").unwrap().unwrap();
+    assert!(new_txt.is_encrypted() && new_txt.has_fix_key());
+    assert!(!new_txt.is_compressed());
+    let raw = upgraded.find_file("raw.bin").unwrap().unwrap();
+    assert!(raw.has_sector_crc());
+    assert_eq!(raw.compressed_size, 10_000 + 4 * 4 + 3 * 4);
+    let level = upgraded.find_file("maps\\level.dat").unwrap().unwrap();
+    assert!(level.is_compressed() && level.has_sector_crc());
+    assert_eq!(
+        upgraded.compression_method("maps\\level.dat").unwrap(),
+        CompressionMethod::Zlib
+    );
+    assert!(upgraded.find_file("old.txt").unwrap().is_none());
+
+    // Applying again hits a base that no longer matches and changes nothing
+    let before = std::fs::read(&old_path).unwrap();
+    assert!(matches!(
+        loaded.apply(&old_path),
+        Err(Error::ChecksumMismatch { .. })
+    ));
+    assert_eq!(std::fs::read(&old_path).unwrap(), before);
+}
@@ -0,0 +1,241 @@
+//! Tests for extracting whole archives
+
+use mopaq::special_files::{AttributeFlags, Attributes, FileAttributes};
+use mopaq::{Archive, ArchiveBuilder, CollisionPolicy, Error, ExtractOptions, ListfileOption};
+use std::fs;
+
+#[test]
+fn test_extract_all() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("extract.mpq");
+
+    let large: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    ArchiveBuilder::new()
+        .add_file_data(b"local x = 1".to_vec(), "scripts\\main.lua")
+        .add_file_data(large.clone(), "data\\large.bin")
+        .add_file_data(Vec::new(), "empty.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    for prefetch in [true, false] {
+        let out = temp_dir.path().join(format!("out_{prefetch}"));
+        let mut archive = Archive::open(&archive_path).unwrap();
+        let summary = archive
+            .extract_all_with(&out, &ExtractOptions::new().prefetch(prefetch))
+            .unwrap();
+
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+        // The generated (listfile) is extracted along with the files
+        assert_eq!(summary.extracted, 4);
+        assert_eq!(
+            fs::read(out.join("scripts").join("main.lua")).unwrap(),
+            b"local x = 1"
+        );
+        assert_eq!(fs::read(out.join("data").join("large.bin")).unwrap(), large);
+        assert_eq!(fs::read(out.join("empty.txt")).unwrap(), b"");
+    }
+}
+
+#[test]
+fn test_extract_all_refuses_escaping_names() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("escape.mpq");
+
+    ArchiveBuilder::new()
+        .add_file_data(b"safe".to_vec(), "safe.txt")
+        .add_file_data(b"evil".to_vec(), "..\\evil.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let out = temp_dir.path().join("out");
+    let mut archive = Archive::open(&archive_path).unwrap();
+    let summary = archive.extract_all(&out).unwrap();
+
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].name, "..\\evil.txt");
+    assert!(matches!(summary.failed[0].error, Error::InvalidFormat(_)));
+    assert_eq!(fs::read(out.join("safe.txt")).unwrap(), b"safe");
+    assert!(!temp_dir.path().join("evil.txt").exists());
+}
+
+#[test]
+fn test_extract_all_in_archive_layout_order() {
+    use mopaq::ExtractionOrder;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("layout.mpq");
+
+    // The listfile names the files in the opposite order to their data
+    let listfile_path = temp_dir.path().join("listfile.txt");
+    let names: Vec<_> = (0..16u8).map(|i| format!("file_{i:02}.bin")).collect();
+    let reversed: Vec<_> = names.iter().rev().cloned().collect();
+    fs::write(&listfile_path, reversed.join("\r\n")).unwrap();
+
+    let mut builder =
+        ArchiveBuilder::new().listfile_option(ListfileOption::External(listfile_path));
+    for (i, name) in names.iter().enumerate() {
+        builder = builder.add_file_data(vec![i as u8; 5_000], name);
+    }
+    builder.build(&archive_path).unwrap();
+
+    let mut archive = Archive::open(&archive_path).unwrap();
+    let listing = archive
+        .extract_all_with(temp_dir.path().join("listing"), &ExtractOptions::new())
+        .unwrap();
+    let layout = archive
+        .extract_all_with(
+            temp_dir.path().join("layout"),
+            &ExtractOptions::new().order(ExtractionOrder::ArchiveLayout),
+        )
+        .unwrap();
+
+    assert_eq!(layout.extracted, 16);
+    assert_eq!(listing.extracted, 16);
+    assert_eq!(layout.listing_seek_distance, listing.seek_distance);
+    assert_eq!(listing.seek_distance_saved(), 0);
+    assert!(listing.seek_distance > 0);
+    // Files are stored back to back, so reading them in order never seeks
+    assert_eq!(layout.seek_distance, 0);
+    assert_eq!(layout.seek_distance_saved(), listing.seek_distance);
+    for (i, name) in names.iter().enumerate() {
+        assert_eq!(
+            fs::read(temp_dir.path().join("layout").join(name)).unwrap(),
+            vec![i as u8; 5_000]
+        );
+    }
+}
+
+#[test]
+fn test_extract_all_collision_policies() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("collide.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"new".to_vec(), "maps\\a.txt")
+        .add_file_data(b"other".to_vec(), "maps\\b.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let extract = |policy| {
+        let out = temp_dir.path().join(format!("{policy:?}"));
+        fs::create_dir_all(out.join("maps")).unwrap();
+        fs::write(out.join("maps").join("a.txt"), b"old").unwrap();
+
+        let mut archive = Archive::open(&archive_path).unwrap();
+        let summary = archive
+            .extract_all_with(&out, &ExtractOptions::new().collision_policy(policy))
+            .unwrap();
+        (out.join("maps"), summary)
+    };
+
+    // The (listfile) is extracted as well
+    let (out, summary) = extract(CollisionPolicy::Overwrite);
+    assert_eq!(summary.extracted, 3);
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"new");
+
+    let (out, summary) = extract(CollisionPolicy::Skip);
+    assert_eq!(summary.extracted, 2);
+    assert_eq!(summary.skipped, ["maps\\a.txt"]);
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+    assert_eq!(fs::read(out.join("b.txt")).unwrap(), b"other");
+
+    let (out, summary) = extract(CollisionPolicy::Rename);
+    assert_eq!(summary.extracted, 3);
+    assert_eq!(
+        summary.renamed,
+        [("maps\\a.txt".to_string(), out.join("a (1).txt"))]
+    );
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+    assert_eq!(fs::read(out.join("a (1).txt")).unwrap(), b"new");
+}
+
+#[test]
+fn test_extract_all_if_different_and_resume() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("repeat.mpq");
+    let files: [(&[u8], &str); 3] = [
+        (b"new", "maps\\a.txt"),
+        (b"other", "maps\\b.txt"),
+        (b"same", "maps\\c.txt"),
+    ];
+    let build = |attributes: Option<Vec<u8>>| {
+        let mut builder = ArchiveBuilder::new();
+        for (data, name) in files {
+            builder = builder.add_file_data(data.to_vec(), name);
+        }
+        if let Some(attributes) = attributes {
+            builder = builder.add_file_data(attributes, "(attributes)");
+        }
+        builder.build(&archive_path).unwrap();
+    };
+    let extract = |options: ExtractOptions| {
+        let out = temp_dir.path().join("out");
+        let _ = fs::remove_dir_all(&out);
+        let maps = out.join("maps");
+        fs::create_dir_all(&maps).unwrap();
+        fs::write(maps.join("a.txt"), b"old").unwrap();
+        fs::write(maps.join("b.txt"), b"longer text").unwrap();
+        fs::write(maps.join("c.txt"), b"same").unwrap();
+
+        let mut archive = Archive::open(&archive_path).unwrap();
+        let summary = archive.extract_all_with(&out, &options).unwrap();
+        (maps, summary)
+    };
+
+    // Without (attributes), files of equal size are compared byte by byte
+    build(None);
+    let if_different = ExtractOptions::new().collision_policy(CollisionPolicy::IfDifferent);
+    let (maps, summary) = extract(if_different.clone());
+    assert_eq!(summary.unchanged, ["maps\\c.txt"]);
+    assert_eq!(summary.extracted, 3);
+    assert_eq!(fs::read(maps.join("a.txt")).unwrap(), b"new");
+    assert_eq!(fs::read(maps.join("b.txt")).unwrap(), b"other");
+
+    // Resuming only compares sizes
+    let (maps, summary) = extract(ExtractOptions::new().resume(true));
+    assert_eq!(summary.unchanged, ["maps\\a.txt", "maps\\c.txt"]);
+    assert_eq!(fs::read(maps.join("a.txt")).unwrap(), b"old");
+    assert_eq!(fs::read(maps.join("b.txt")).unwrap(), b"other");
+
+    // With checksums, those are trusted; a wrong one for c.txt makes it differ
+    let archive = Archive::open(&archive_path).unwrap();
+    let mut entries = vec![FileAttributes::new(); archive.block_table().unwrap().entries().len()];
+    for (data, name) in files {
+        let index = archive.find_file(name).unwrap().unwrap().block_index;
+        entries[index].crc32 = Some(crc32fast::hash(if data == b"same" {
+            b"sane"
+        } else {
+            data
+        }));
+    }
+    drop(archive);
+    let attributes = Attributes::new(AttributeFlags::new(AttributeFlags::CRC32), entries);
+    build(Some(attributes.to_bytes().unwrap()));
+
+    let (maps, summary) = extract(if_different);
+    assert!(summary.unchanged.is_empty(), "{:?}", summary.unchanged);
+    assert_eq!(fs::read(maps.join("c.txt")).unwrap(), b"same");
+}
+
+#[test]
+fn test_extract_all_long_paths() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("long.mpq");
+
+    // Well past the 260 characters of MAX_PATH on Windows
+    let name = format!(
+        "{}\\file.txt",
+        ["a_directory_name_of_forty_characters_lo"; 8].join("\\")
+    );
+    ArchiveBuilder::new()
+        .add_file_data(b"deep".to_vec(), &name)
+        .build(&archive_path)
+        .unwrap();
+
+    let out = temp_dir.path().join("out");
+    let mut archive = Archive::open(&archive_path).unwrap();
+    let summary = archive.extract_all(&out).unwrap();
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    let path = mopaq::extract::output_path(&out, &name).unwrap();
+    assert!(path.as_os_str().len() > 260);
+    assert_eq!(fs::read(path).unwrap(), b"deep");
+}
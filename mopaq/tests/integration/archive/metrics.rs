@@ -0,0 +1,115 @@
+//! Tests for the I/O counters reported to a metrics sink
+
+use mopaq::compression::flags;
+use mopaq::{ArchiveBuilder, Metrics, MetricsCounters, OpenOptions};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+fn build(path: &Path) -> Vec<u8> {
+    let content: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+    ArchiveBuilder::new()
+        .block_size(3)
+        .add_file_data_with_options(content.clone(), "data.bin", flags::ZLIB, false, 0)
+        .add_file_data_with_options(b"stored".to_vec(), "stored.txt", 0, false, 0)
+        .build(path)
+        .unwrap();
+    content
+}
+
+#[test]
+fn test_metrics_counters() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("metrics.mpq");
+    let content = build(&archive_path);
+
+    let counters = Arc::new(MetricsCounters::new());
+    let archive = OpenOptions::new()
+        .metrics(counters.clone())
+        .open(&archive_path)
+        .unwrap();
+    let opened = counters.bytes_read();
+    assert!(opened > 0);
+    assert_eq!(counters.files_read(), 0);
+
+    // Five 4 KiB sectors, each decompressed once
+    assert_eq!(archive.read_file("data.bin").unwrap(), content);
+    assert_eq!(counters.sectors_decompressed(), 5);
+    assert_eq!(counters.bytes_decompressed(), content.len() as u64);
+    assert_eq!(counters.files_read(), 1);
+    assert!(counters.bytes_read() > opened);
+
+    // Stored files are read without decompressing
+    archive.read_file("stored.txt").unwrap();
+    assert_eq!(counters.sectors_decompressed(), 5);
+    assert_eq!(counters.files_read(), 2);
+
+    // A file read in pieces counts once it has been read to the end
+    archive.read_file_chunks("data.bin", |_| Ok(false)).unwrap();
+    assert_eq!(counters.files_read(), 2);
+    archive.read_file_chunks("data.bin", |_| Ok(true)).unwrap();
+    assert_eq!(counters.files_read(), 3);
+
+    // Clones report to the same sink
+    let before = counters.bytes_read();
+    archive
+        .try_clone()
+        .unwrap()
+        .read_file("stored.txt")
+        .unwrap();
+    assert_eq!(counters.files_read(), 4);
+    assert!(counters.bytes_read() > before);
+
+    assert_eq!(counters.cache_hits() + counters.cache_misses(), 0);
+}
+
+#[test]
+fn test_custom_metrics_sink() {
+    #[derive(Default)]
+    struct Names(Mutex<Vec<(String, u64)>>);
+
+    impl Metrics for Names {
+        fn on_file_read(&self, name: &str, size: u64) {
+            self.0.lock().unwrap().push((name.to_string(), size));
+        }
+    }
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("metrics.mpq");
+    build(&archive_path);
+
+    let names = Arc::new(Names::default());
+    let archive = OpenOptions::new()
+        .metrics(names.clone())
+        .open(&archive_path)
+        .unwrap();
+    archive.read_file("stored.txt").unwrap();
+    archive.read_file_partial("data.bin").unwrap();
+
+    assert_eq!(
+        *names.0.lock().unwrap(),
+        [
+            ("stored.txt".to_string(), 6),
+            ("data.bin".to_string(), 20_000)
+        ]
+    );
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn test_metrics_cache_lookups() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("metrics.mpq");
+    let cache_dir = temp_dir.path().join("cache");
+    build(&archive_path);
+
+    let counters = Arc::new(MetricsCounters::new());
+    for _ in 0..2 {
+        OpenOptions::new()
+            .cache_dir(&cache_dir)
+            .metrics(counters.clone())
+            .open(&archive_path)
+            .unwrap();
+    }
+    assert_eq!(counters.cache_misses(), 1);
+    assert_eq!(counters.cache_hits(), 1);
+}
@@ -2,6 +2,18 @@
 //!
 //! Tests complete archive functionality including creation, reading, and modification.
 
+mod analysis;
 mod attributes;
 mod basic;
 mod builder;
+#[cfg(feature = "cache")]
+mod cache;
+mod convert;
+mod delta;
+mod extract;
+mod metrics;
+mod modification;
+mod name_hashing;
+mod patch_chain;
+mod split;
+mod vfs;
@@ -0,0 +1,337 @@
+//! Integration tests for in-place archive modification
+
+use mopaq::special_files::{AttributeFlags, Attributes, FileAttributes};
+use mopaq::{
+    AddFileOptions, Archive, ArchiveBuilder, Error, FormatVersion, ListfileOption, Locale,
+    MutableArchive,
+};
+use std::fs;
+use tempfile::TempDir;
+
+fn sorted_names(archive: &mut Archive) -> Vec<String> {
+    let mut names: Vec<String> = archive
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_update_listfile_in_place() {
+    let temp_dir = TempDir::new().unwrap();
+
+    for version in [FormatVersion::V1, FormatVersion::V2] {
+        let archive_path = temp_dir.path().join("listfile.mpq");
+        let listfile_path = temp_dir.path().join("partial.txt");
+
+        // Ship a listfile that misses one file and names one that does not exist
+        fs::write(&listfile_path, "kept.txt\r\nghost.txt\r\n").unwrap();
+        ArchiveBuilder::new()
+            .version(version)
+            .listfile_option(ListfileOption::External(listfile_path))
+            .add_file_data(b"Kept".to_vec(), "kept.txt")
+            .add_file_data(b"Unlisted".to_vec(), "units\\unlisted.mdx")
+            .build(&archive_path)
+            .unwrap();
+
+        let original_kept = {
+            let archive = Archive::open(&archive_path).unwrap();
+            archive.find_file("kept.txt").unwrap().unwrap()
+        };
+
+        let mut mutable = MutableArchive::open(&archive_path).unwrap();
+        mutable
+            .update_listfile(&["UNITS/unlisted.mdx", "kept.txt"], &["ghost.txt"])
+            .unwrap();
+        mutable.flush().unwrap();
+        assert_eq!(
+            mutable.listfile_names().unwrap(),
+            ["kept.txt", "UNITS/unlisted.mdx"]
+        );
+        drop(mutable);
+
+        let mut archive = Archive::open(&archive_path).unwrap();
+        assert_eq!(
+            sorted_names(&mut archive),
+            ["UNITS/unlisted.mdx", "kept.txt"]
+        );
+        assert_eq!(archive.read_file("kept.txt").unwrap(), b"Kept");
+        assert_eq!(
+            archive.read_file("units\\unlisted.mdx").unwrap(),
+            b"Unlisted"
+        );
+
+        // Other files keep their exact placement
+        let kept = archive.find_file("kept.txt").unwrap().unwrap();
+        assert_eq!(kept.file_pos, original_kept.file_pos);
+        assert_eq!(kept.compressed_size, original_kept.compressed_size);
+
+        fs::remove_file(&archive_path).unwrap();
+    }
+}
+
+#[test]
+fn test_add_and_remove_files_in_place() {
+    let temp_dir = TempDir::new().unwrap();
+    let listfile_path = temp_dir.path().join("partial.txt");
+    fs::write(&listfile_path, "kept.txt\r\nold.txt\r\n").unwrap();
+    let replacement: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+
+    for version in [
+        FormatVersion::V1,
+        FormatVersion::V2,
+        FormatVersion::V3,
+        FormatVersion::V4,
+    ] {
+        let archive_path = temp_dir.path().join(format!("in_place_{:?}.mpq", version));
+
+        // Blocks: kept.txt, old.txt, hidden.bin, (attributes), (listfile)
+        let flags = AttributeFlags::new(AttributeFlags::CRC32 | AttributeFlags::MD5);
+        let attributes = Attributes::new(flags, vec![FileAttributes::new(); 5]);
+        ArchiveBuilder::new()
+            .version(version)
+            .listfile_option(ListfileOption::External(listfile_path.clone()))
+            .add_file_data(b"Kept".to_vec(), "kept.txt")
+            .add_file_data(b"Old".to_vec(), "old.txt")
+            .add_file_data(b"Unlisted".to_vec(), "hidden.bin")
+            .add_file_data(attributes.to_bytes().unwrap(), "(attributes)")
+            .build(&archive_path)
+            .unwrap();
+
+        let mut mutable = MutableArchive::open(&archive_path).unwrap();
+        let options = AddFileOptions::new()
+            .encrypt(true)
+            .fix_key(true)
+            .sector_crc(true);
+        mutable
+            .add_file_data(&replacement, "KEPT.TXT", &options)
+            .unwrap();
+        mutable
+            .add_file_data(b"New", "new.txt", &AddFileOptions::new())
+            .unwrap();
+        mutable
+            .add_file_data(b"Neu", "new.txt", &AddFileOptions::new().locale(0x407))
+            .unwrap();
+        mutable.remove_file("old.txt").unwrap();
+        assert!(matches!(
+            mutable.remove_file("old.txt"),
+            Err(Error::FileNotFound(_))
+        ));
+        mutable.flush().unwrap();
+        drop(mutable);
+
+        let mut archive = Archive::open(&archive_path).unwrap();
+        assert_eq!(
+            sorted_names(&mut archive),
+            ["kept.txt", "new.txt"],
+            "{version:?}"
+        );
+        assert_eq!(archive.read_file("kept.txt").unwrap(), replacement);
+        assert_eq!(archive.read_file("new.txt").unwrap(), b"New");
+        assert_eq!(
+            archive.read_file_with_locale("new.txt", 0x407).unwrap(),
+            b"Neu"
+        );
+        assert!(archive.find_file("old.txt").unwrap().is_none());
+
+        // The file the listfile does not name is untouched
+        assert_eq!(archive.read_file("hidden.bin").unwrap(), b"Unlisted");
+
+        let kept = archive.find_file("kept.txt").unwrap().unwrap();
+        assert_eq!(kept.block_index, 0, "replaced files keep their block");
+        assert!(kept.is_encrypted() && kept.has_fix_key() && kept.has_sector_crc());
+
+        archive.load_attributes().unwrap();
+        let kept_attributes = archive.get_file_attributes(kept.block_index).unwrap();
+        assert_eq!(kept_attributes.crc32, Some(crc32fast::hash(&replacement)));
+        let new = archive.find_file("new.txt").unwrap().unwrap();
+        let new_attributes = archive.get_file_attributes(new.block_index).unwrap();
+        assert_eq!(new_attributes.crc32, Some(crc32fast::hash(b"New")));
+        let old_attributes = archive.get_file_attributes(1).unwrap();
+        assert_eq!(old_attributes.crc32, Some(0));
+
+        // Removing one locale keeps the other and the listfile entry
+        let mut mutable = MutableArchive::open(&archive_path).unwrap();
+        mutable
+            .remove_file_with_locale("new.txt", Locale::NEUTRAL)
+            .unwrap();
+        mutable.flush().unwrap();
+        assert!(mutable
+            .listfile_names()
+            .unwrap()
+            .contains(&"new.txt".to_string()));
+        drop(mutable);
+
+        let archive = Archive::open(&archive_path).unwrap();
+        assert_eq!(
+            archive.file_locales("new.txt").unwrap(),
+            [Locale::from(0x407)]
+        );
+    }
+}
+
+#[test]
+fn test_update_listfile_creates_missing_listfile() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("no_listfile.mpq");
+
+    ArchiveBuilder::new()
+        .version(FormatVersion::V2)
+        .listfile_option(ListfileOption::None)
+        .add_file_data(b"First".to_vec(), "first.txt")
+        .add_file_data(b"Second".to_vec(), "second.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let mut mutable = MutableArchive::open(&archive_path).unwrap();
+    assert!(mutable.listfile_names().unwrap().is_empty());
+    mutable
+        .update_listfile(&["first.txt", "second.txt"], &[])
+        .unwrap();
+    mutable.flush().unwrap();
+    drop(mutable);
+
+    // The block table grew by one entry and moved to the end of the file
+    let mut archive = Archive::open(&archive_path).unwrap();
+    assert_eq!(archive.header().block_table_size, 3);
+    assert_eq!(
+        sorted_names(&mut archive),
+        ["(listfile)", "first.txt", "second.txt"]
+    );
+    assert_eq!(archive.read_file("second.txt").unwrap(), b"Second");
+}
+
+#[test]
+fn test_export_and_import_listfile() {
+    use mopaq::special_files::ListfileFormat;
+
+    let temp_dir = TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("source.mpq");
+    let target_path = temp_dir.path().join("target.mpq");
+
+    ArchiveBuilder::new()
+        .add_file_data(b"Footman".to_vec(), "Units\\Footman.mdx")
+        .add_file_data(b"Peasant".to_vec(), "Units\\Peasant.mdx")
+        .build(&source_path)
+        .unwrap();
+
+    let mut exported = Vec::new();
+    let mut source = Archive::open(&source_path).unwrap();
+    let count = source
+        .export_listfile(&mut exported, ListfileFormat::NameSizeCrc)
+        .unwrap();
+    assert_eq!(count, 3);
+    let exported = String::from_utf8(exported).unwrap();
+    assert!(exported.contains(&format!(
+        "Units\\Footman.mdx;7;{:08X}\r\n",
+        crc32fast::hash(b"Footman")
+    )));
+
+    // The target only has one of the exported files and no listfile
+    ArchiveBuilder::new()
+        .version(FormatVersion::V2)
+        .listfile_option(ListfileOption::None)
+        .add_file_data(b"Peasant".to_vec(), "units\\peasant.mdx")
+        .build(&target_path)
+        .unwrap();
+
+    let mut mutable = MutableArchive::open(&target_path).unwrap();
+    assert_eq!(mutable.import_listfile(exported.as_bytes()).unwrap(), 1);
+    mutable.flush().unwrap();
+    assert_eq!(mutable.import_listfile(exported.as_bytes()).unwrap(), 0);
+    drop(mutable);
+
+    let mut target = Archive::open(&target_path).unwrap();
+    assert_eq!(
+        sorted_names(&mut target),
+        ["(listfile)", "Units\\Peasant.mdx"]
+    );
+}
+
+#[test]
+fn test_update_listfile_rejects_unknown_names() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("unknown.mpq");
+
+    ArchiveBuilder::new()
+        .add_file_data(b"Data".to_vec(), "data.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    let mut mutable = MutableArchive::open(&archive_path).unwrap();
+    assert!(matches!(
+        mutable.update_listfile(&["missing.txt"], &[]),
+        Err(Error::FileNotFound(_))
+    ));
+}
+
+#[test]
+fn test_update_listfile_rebuilds_het_bet() {
+    let temp_dir = TempDir::new().unwrap();
+
+    for version in [FormatVersion::V3, FormatVersion::V4] {
+        let archive_path = temp_dir.path().join(format!("het_bet_{:?}.mpq", version));
+
+        ArchiveBuilder::new()
+            .version(version)
+            .listfile_option(ListfileOption::None)
+            .add_file_data(b"First".to_vec(), "first.txt")
+            .add_file_data(b"Second".to_vec(), "units\\second.mdx")
+            .build(&archive_path)
+            .unwrap();
+
+        let mut mutable = MutableArchive::open(&archive_path).unwrap();
+        mutable
+            .update_listfile(&["first.txt", "units\\second.mdx"], &[])
+            .unwrap();
+        mutable.flush().unwrap();
+        drop(mutable);
+
+        let mut archive = Archive::open(&archive_path).unwrap();
+        assert_eq!(archive.header().block_table_size, 3);
+
+        // The rebuilt HET/BET tables know the new listfile and the old files
+        let het = archive.het_table().unwrap();
+        let bet = archive.bet_table().unwrap();
+        assert_eq!({ bet.header.file_count }, 3);
+        for name in ["(listfile)", "first.txt", "units\\second.mdx"] {
+            assert!(het.find_file(name, bet).is_some(), "{name} in {version:?}");
+        }
+
+        assert_eq!(
+            sorted_names(&mut archive),
+            ["(listfile)", "first.txt", "units\\second.mdx"]
+        );
+        assert_eq!(archive.read_file("units\\second.mdx").unwrap(), b"Second");
+
+        let info = archive.get_info().unwrap();
+        assert!(!info.is_truncated);
+        if version == FormatVersion::V4 {
+            let md5 = info.md5_status.unwrap();
+            assert!(md5.header_valid);
+            assert!(md5.hash_table_valid && md5.block_table_valid);
+            assert!(md5.het_table_valid && md5.bet_table_valid);
+        }
+    }
+}
+
+#[test]
+fn test_mutable_archive_rejects_missing_classic_tables() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("v3.mpq");
+
+    ArchiveBuilder::new()
+        .version(FormatVersion::V3)
+        .classic_tables(false)
+        .add_file_data(b"Data".to_vec(), "data.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    assert!(matches!(
+        MutableArchive::open(&archive_path),
+        Err(Error::OperationNotSupported { version: 3, .. })
+    ));
+}
@@ -0,0 +1,125 @@
+//! Tests for archives whose names are hashed with a non-standard policy
+
+use mopaq::{
+    Archive, ArchiveBuilder, FormatVersion, MutableArchive, NameHashingPolicy, OpenOptions,
+    PrecomputedName,
+};
+
+const CASE_SENSITIVE_SLASH: NameHashingPolicy = NameHashingPolicy {
+    case_sensitive: true,
+    separator: Some(b'/'),
+};
+
+#[test]
+fn test_case_sensitive_name_hashing() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+
+    for version in [FormatVersion::V1, FormatVersion::V3] {
+        let path = temp_dir.path().join(format!("{:?}.mpq", version));
+        ArchiveBuilder::new()
+            .version(version)
+            .name_hashing(CASE_SENSITIVE_SLASH)
+            .add_file_data(b"upper".to_vec(), "Data/README.txt")
+            .add_file_data(b"lower".to_vec(), "Data/readme.txt")
+            .build(&path)
+            .unwrap();
+
+        // The default policy hashes the names differently
+        let archive = Archive::open(&path).unwrap();
+        assert!(archive.find_file("Data/README.txt").unwrap().is_none());
+
+        let mut archive = OpenOptions::new()
+            .name_hashing(CASE_SENSITIVE_SLASH)
+            .open(&path)
+            .unwrap();
+        assert_eq!(archive.name_hashing(), CASE_SENSITIVE_SLASH);
+        assert_eq!(archive.read_file("Data/README.txt").unwrap(), b"upper");
+        assert_eq!(archive.read_file("Data\\readme.txt").unwrap(), b"lower");
+        assert!(archive.find_file("data/readme.TXT").unwrap().is_none());
+        assert_eq!(archive.list().unwrap().len(), 3, "{:?}", version);
+    }
+
+    // Rebuilds and modifications keep comparing names the same way
+    let path = temp_dir.path().join("V1.mpq");
+    let mut archive = OpenOptions::new()
+        .name_hashing(CASE_SENSITIVE_SLASH)
+        .open(&path)
+        .unwrap();
+    let rebuilt = temp_dir.path().join("rebuilt.mpq");
+    ArchiveBuilder::from_archive(&mut archive)
+        .unwrap()
+        .remove_file("Data\\readme.txt")
+        .build(&rebuilt)
+        .unwrap();
+    drop(archive);
+    let archive = OpenOptions::new()
+        .name_hashing(CASE_SENSITIVE_SLASH)
+        .open(&rebuilt)
+        .unwrap();
+    assert_eq!(archive.read_file("Data/README.txt").unwrap(), b"upper");
+    assert!(archive.find_file("Data/readme.txt").unwrap().is_none());
+
+    let mut mutable = MutableArchive::open_with_options(
+        &path,
+        OpenOptions::new().name_hashing(CASE_SENSITIVE_SLASH),
+    )
+    .unwrap();
+    mutable.remove_file("Data\\readme.txt").unwrap();
+    mutable.flush().unwrap();
+    assert_eq!(
+        mutable.listfile_names().unwrap(),
+        ["Data/README.txt", "(listfile)"]
+    );
+    drop(mutable);
+
+    let archive = OpenOptions::new()
+        .name_hashing(CASE_SENSITIVE_SLASH)
+        .open(&path)
+        .unwrap();
+    assert_eq!(archive.read_file("Data/README.txt").unwrap(), b"upper");
+    assert!(archive.find_file("Data/readme.txt").unwrap().is_none());
+}
+
+#[test]
+fn test_find_file_precomputed() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+
+    for version in [FormatVersion::V1, FormatVersion::V3] {
+        let path = temp_dir.path().join(format!("{:?}.mpq", version));
+        ArchiveBuilder::new()
+            .version(version)
+            .add_file_data(b"script".to_vec(), "scripts\\war3map.j")
+            .build(&path)
+            .unwrap();
+
+        let archive = Archive::open(&path).unwrap();
+        let name = PrecomputedName::new("Scripts/War3map.j");
+        let info = archive.find_file_pre(&name).unwrap().unwrap();
+        let expected = archive.find_file("Scripts/War3map.j").unwrap().unwrap();
+        assert_eq!(info.block_index, expected.block_index, "{:?}", version);
+        assert_eq!(info.filename, "Scripts/War3map.j");
+        assert!(archive
+            .find_file_pre(&PrecomputedName::new("war3map.j"))
+            .unwrap()
+            .is_none());
+    }
+
+    // Names hashed for another policy are rehashed for the archive's
+    let path = temp_dir.path().join("case.mpq");
+    ArchiveBuilder::new()
+        .name_hashing(CASE_SENSITIVE_SLASH)
+        .add_file_data(b"upper".to_vec(), "Data/README.txt")
+        .build(&path)
+        .unwrap();
+    let archive = OpenOptions::new()
+        .name_hashing(CASE_SENSITIVE_SLASH)
+        .open(&path)
+        .unwrap();
+    let name = PrecomputedName::new("Data/README.txt");
+    assert_eq!(name.policy(), NameHashingPolicy::BLIZZARD);
+    assert!(archive.find_file_pre(&name).unwrap().is_some());
+    assert!(archive
+        .find_file_pre(&PrecomputedName::new("Data/readme.txt"))
+        .unwrap()
+        .is_none());
+}
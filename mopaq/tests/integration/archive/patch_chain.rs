@@ -0,0 +1,161 @@
+//! Tests for patch chain discovery and lookups
+
+use mopaq::{ArchiveBuilder, Error, PatchChain};
+use std::fs;
+use std::path::Path;
+
+fn build(path: &Path, files: &[(&str, &[u8])]) {
+    let mut builder = ArchiveBuilder::new();
+    for (name, data) in files {
+        builder = builder.add_file_data(data.to_vec(), name);
+    }
+    builder.build(path).unwrap();
+}
+
+#[test]
+fn test_discover_orders_archives() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let data_dir = temp_dir.path();
+    let locale_dir = data_dir.join("enUS");
+    let other_locale_dir = data_dir.join("deDE");
+    fs::create_dir(&locale_dir).unwrap();
+    fs::create_dir(&other_locale_dir).unwrap();
+
+    build(
+        &data_dir.join("common.MPQ"),
+        &[
+            ("a.txt", b"common"),
+            ("b.txt", b"common"),
+            ("c.txt", b"common"),
+        ],
+    );
+    build(&data_dir.join("patch.MPQ"), &[("a.txt", b"patch")]);
+    build(&data_dir.join("patch-2.mpq"), &[("b.txt", b"patch-2")]);
+    build(
+        &locale_dir.join("locale-enUS.MPQ"),
+        &[("a.txt", b"locale"), ("d.txt", b"locale")],
+    );
+    build(
+        &locale_dir.join("patch-enUS.MPQ"),
+        &[("d.txt", b"patch-enUS")],
+    );
+    build(
+        &data_dir.join("wow-update-13164.MPQ"),
+        &[("c.txt", b"update")],
+    );
+    build(
+        &other_locale_dir.join("locale-deDE.MPQ"),
+        &[("d.txt", b"deDE")],
+    );
+    fs::write(data_dir.join("readme.txt"), b"not an archive").unwrap();
+
+    let mut chain = PatchChain::discover(data_dir, "enUS").unwrap();
+    let names: Vec<_> = chain
+        .archive_paths()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "common.MPQ",
+            "locale-enUS.MPQ",
+            "patch.MPQ",
+            "patch-2.mpq",
+            "patch-enUS.MPQ",
+            "wow-update-13164.MPQ",
+        ]
+    );
+
+    for (name, expected) in [
+        ("a.txt", &b"patch"[..]),
+        ("b.txt", b"patch-2"),
+        ("c.txt", b"update"),
+        ("d.txt", b"patch-enUS"),
+    ] {
+        assert_eq!(chain.read_file(name).unwrap(), expected, "{}", name);
+    }
+
+    let listing: Vec<_> = chain
+        .list()
+        .unwrap()
+        .into_iter()
+        .map(|entry| {
+            let archive = entry.archive.file_name().unwrap().to_string_lossy();
+            (entry.name, archive.into_owned())
+        })
+        .collect();
+    assert_eq!(
+        listing,
+        [
+            ("a.txt".to_string(), "patch.MPQ".to_string()),
+            ("b.txt".to_string(), "patch-2.mpq".to_string()),
+            ("c.txt".to_string(), "wow-update-13164.MPQ".to_string()),
+            ("d.txt".to_string(), "patch-enUS.MPQ".to_string()),
+        ]
+    );
+
+    let (path, _) = chain.find_file("b.txt").unwrap().unwrap();
+    assert!(path.ends_with("patch-2.mpq"));
+    assert!(chain.find_file("missing.txt").unwrap().is_none());
+    assert!(matches!(
+        chain.read_file("missing.txt"),
+        Err(Error::FileNotFound(_))
+    ));
+}
+
+#[test]
+fn test_discover_without_locale_dir() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    build(&temp_dir.path().join("base.mpq"), &[("a.txt", b"base")]);
+
+    let chain = PatchChain::discover(temp_dir.path(), "enUS").unwrap();
+    assert_eq!(chain.len(), 1);
+    assert_eq!(chain.read_file("a.txt").unwrap(), b"base");
+}
+
+#[test]
+fn test_patch_directory_overrides_archives() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let data_dir = temp_dir.path().join("Data");
+    let patch_dir = temp_dir.path().join("Patch");
+    let late_dir = temp_dir.path().join("Late");
+    fs::create_dir_all(&data_dir).unwrap();
+    fs::create_dir_all(patch_dir.join("Scripts")).unwrap();
+    fs::create_dir_all(&late_dir).unwrap();
+
+    build(
+        &data_dir.join("common.MPQ"),
+        &[("Scripts\\main.lua", b"archive"), ("a.txt", b"archive")],
+    );
+    fs::write(patch_dir.join("Scripts").join("Main.lua"), b"loose").unwrap();
+    fs::write(patch_dir.join("b.txt"), b"patch").unwrap();
+    fs::write(late_dir.join("B.TXT"), b"late").unwrap();
+
+    let mut chain = PatchChain::discover(&data_dir, "enUS").unwrap();
+    chain.push_directory(&patch_dir).unwrap();
+    chain.push_directory(&late_dir).unwrap();
+    assert_eq!(chain.directory_paths().count(), 2);
+
+    assert_eq!(chain.read_file("scripts/MAIN.lua").unwrap(), b"loose");
+    assert_eq!(chain.read_file("a.txt").unwrap(), b"archive");
+    assert_eq!(chain.read_file("b.txt").unwrap(), b"late");
+    // Loose files have no archive entry
+    let (archive, _) = chain.find_file("Scripts\\main.lua").unwrap().unwrap();
+    assert_eq!(archive, data_dir.join("common.MPQ"));
+
+    let entries = chain.list().unwrap();
+    let found: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.archive.clone()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("B.TXT", late_dir.clone()),
+            ("Scripts\\Main.lua", patch_dir.clone()),
+            ("a.txt", data_dir.join("common.MPQ")),
+        ]
+    );
+
+    assert!(chain.push_directory(data_dir.join("common.MPQ")).is_err());
+}
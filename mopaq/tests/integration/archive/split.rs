@@ -0,0 +1,67 @@
+//! Tests for splitting archives by size
+
+use mopaq::split::{split_archive, SplitManifest};
+use mopaq::{Archive, ArchiveBuilder, Error};
+
+/// Incompressible data so stored sizes are predictable
+fn noise(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_split_archive_by_size() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let source_path = temp_dir.path().join("source.mpq");
+
+    let mut builder = ArchiveBuilder::new();
+    for i in 0..10 {
+        builder = builder.add_file_data(noise(i, 10_000), &format!("data\\file{i}.bin"));
+    }
+    builder.build(&source_path).unwrap();
+
+    let max_size = 35_000;
+    let mut source = Archive::open(&source_path).unwrap();
+    let manifest = split_archive(&mut source, max_size, |index| {
+        temp_dir.path().join(format!("part{index}.mpq"))
+    })
+    .unwrap();
+
+    assert!(manifest.parts.len() >= 4);
+    let mut seen = 0;
+    for part in &manifest.parts {
+        assert!(part.size <= max_size);
+        assert_eq!(std::fs::metadata(&part.path).unwrap().len(), part.size);
+
+        let archive = Archive::open(&part.path).unwrap();
+        for name in &part.files {
+            let i: u32 = name["data\\file".len()..name.len() - 4].parse().unwrap();
+            assert_eq!(archive.read_file(name).unwrap(), noise(i, 10_000));
+        }
+        seen += part.files.len();
+    }
+    assert_eq!(seen, 10);
+    assert_eq!(
+        manifest.part_of("DATA/FILE9.BIN"),
+        Some(manifest.parts.len() - 1)
+    );
+    assert_eq!(manifest.part_of("missing.bin"), None);
+
+    let manifest_path = temp_dir.path().join("split.manifest");
+    manifest.save(&manifest_path).unwrap();
+    assert_eq!(SplitManifest::load(&manifest_path).unwrap(), manifest);
+
+    // A file that cannot fit any part is reported rather than written
+    let result = split_archive(&mut source, 5_000, |index| {
+        temp_dir.path().join(format!("tiny{index}.mpq"))
+    });
+    assert!(matches!(result, Err(Error::CapacityExceeded(_))));
+    assert!(!temp_dir.path().join("tiny0.mpq").exists());
+}
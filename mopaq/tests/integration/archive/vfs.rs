@@ -0,0 +1,131 @@
+//! Tests for lookups across mounted archives and directories
+
+use mopaq::{Archive, ArchiveBuilder, Error, MpqVfs, VfsSource};
+use std::fs;
+use std::path::Path;
+
+fn build(path: &Path, files: &[(&str, &[u8])]) {
+    let mut builder = ArchiveBuilder::new();
+    for (name, data) in files {
+        builder = builder.add_file_data(data.to_vec(), name);
+    }
+    builder.build(path).unwrap();
+}
+
+#[test]
+fn test_priorities_and_overrides() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    let base = dir.join("base.mpq");
+    let patch = dir.join("patch.mpq");
+    let late = dir.join("late.mpq");
+    let loose = dir.join("loose");
+    fs::create_dir_all(loose.join("Scripts")).unwrap();
+
+    build(
+        &base,
+        &[
+            ("a.txt", b"base"),
+            ("b.txt", b"base"),
+            ("Scripts\\main.lua", b"base"),
+        ],
+    );
+    build(&patch, &[("a.txt", b"patch"), ("b.txt", b"patch")]);
+    build(&late, &[("b.txt", b"late")]);
+    fs::write(loose.join("Scripts").join("main.lua"), b"loose").unwrap();
+
+    let mut vfs = MpqVfs::new();
+    vfs.mount_directory(&loose, -10).unwrap();
+    vfs.mount_archive(&patch, 1).unwrap();
+    vfs.mount_archive(&base, 0).unwrap();
+    // Same priority as the patch, but mounted after it
+    vfs.mount_archive(&late, 1).unwrap();
+    assert_eq!(vfs.len(), 4);
+
+    assert_eq!(vfs.read("A.TXT").unwrap(), b"patch");
+    assert_eq!(vfs.read("b.txt").unwrap(), b"late");
+    // Loose files win over archives, whatever the priorities
+    assert_eq!(vfs.read("Scripts\\main.lua").unwrap(), b"loose");
+    assert_eq!(
+        vfs.locate("Scripts/main.lua").unwrap(),
+        Some(VfsSource::Directory(loose.clone()))
+    );
+    assert_eq!(
+        vfs.locate("a.txt").unwrap(),
+        Some(VfsSource::Archive(patch.clone()))
+    );
+
+    assert!(vfs.exists("b.txt").unwrap());
+    assert!(!vfs.exists("missing.txt").unwrap());
+    assert!(matches!(
+        vfs.read("missing.txt"),
+        Err(Error::FileNotFound(_))
+    ));
+
+    // Loose names never leave their directory
+    fs::write(dir.join("outside.txt"), b"outside").unwrap();
+    assert!(!vfs.exists("..\\outside.txt").unwrap());
+
+    let order: Vec<_> = vfs.mounts().map(|(_, priority)| priority).collect();
+    assert_eq!(order, [0, 1, 1, -10]);
+
+    assert!(vfs.unmount(&late));
+    assert!(!vfs.unmount(&late));
+    assert_eq!(vfs.read("b.txt").unwrap(), b"patch");
+}
+
+#[test]
+fn test_list() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    let base = dir.join("base.mpq");
+    let loose = dir.join("loose");
+    fs::create_dir_all(loose.join("Units")).unwrap();
+
+    build(
+        &base,
+        &[("units\\footman.mdx", b"base"), ("war3map.j", b"j")],
+    );
+    fs::write(loose.join("Units").join("footman.mdx"), b"loose!").unwrap();
+    fs::write(loose.join("readme.txt"), b"read me").unwrap();
+
+    let mut vfs = MpqVfs::new();
+    vfs.mount_opened(&base, Archive::open(&base).unwrap(), 0);
+    vfs.mount_directory(&loose, 0).unwrap();
+
+    let entries = vfs.list().unwrap();
+    let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+    assert_eq!(names, ["Units\\footman.mdx", "readme.txt", "war3map.j"]);
+    assert_eq!(entries[0].source, VfsSource::Directory(loose.clone()));
+    assert_eq!(entries[0].size, 6);
+    assert_eq!(entries[2].source, VfsSource::Archive(base.clone()));
+
+    assert!(vfs.mount_directory(dir.join("missing"), 0).is_err());
+    assert!(vfs.mount_directory(&base, 0).is_err());
+}
+
+#[test]
+fn test_loose_names_ignore_case_and_separators() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let loose = temp_dir.path();
+    fs::create_dir_all(loose.join("Units").join("Human")).unwrap();
+    fs::write(
+        loose.join("Units").join("Human").join("Footman.mdx"),
+        b"footman",
+    )
+    .unwrap();
+
+    let mut vfs = MpqVfs::new();
+    vfs.mount_directory(loose, 0).unwrap();
+    for name in [
+        "Units\\Human\\Footman.mdx",
+        "units/human/footman.mdx",
+        "UNITS\\HUMAN/FOOTMAN.MDX",
+        "\\units\\\\human\\footman.mdx",
+    ] {
+        assert_eq!(vfs.read(name).unwrap(), b"footman", "{name}");
+    }
+    assert!(!vfs.exists("units\\human").unwrap());
+    assert!(!vfs.exists("units\\human\\.\\footman.mdx").unwrap());
+    assert!(!vfs.exists("units\\orc\\grunt.mdx").unwrap());
+}
@@ -2,4 +2,6 @@
 //!
 //! Tests digital signatures and advanced security features.
 
+mod integrity;
 mod signature;
+mod signature_policy;
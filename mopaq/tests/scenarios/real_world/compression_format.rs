@@ -28,6 +28,7 @@ fn test_zlib_compression_format() {
 }
 
 #[test]
+#[cfg(feature = "compression-bzip2")]
 fn test_bzip2_compression_format() {
     let test_data = vec![b'A'; 1000]; // Highly compressible
 
@@ -118,7 +119,12 @@ fn test_no_compression_for_small_data() {
     let test_data = b"abc";
 
     // Try to compress with various methods
-    for &method in &[flags::ZLIB, flags::BZIP2, flags::SPARSE] {
+    for &method in &[
+        flags::ZLIB,
+        #[cfg(feature = "compression-bzip2")]
+        flags::BZIP2,
+        flags::SPARSE,
+    ] {
         let compressed = compress(test_data, method).unwrap();
 
         // Should return original data (no compression beneficial)
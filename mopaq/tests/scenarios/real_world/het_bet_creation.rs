@@ -24,7 +24,7 @@ fn test_het_bet_table_creation() {
         .expect("Failed to create archive");
 
     // Open the archive and verify files can be read
-    let mut archive = Archive::open(&archive_path).expect("Failed to open archive");
+    let archive = Archive::open(&archive_path).expect("Failed to open archive");
 
     // Verify header has HET/BET table positions
     assert_eq!(
@@ -67,7 +67,7 @@ fn test_het_bet_with_compression() {
         .expect("Failed to create archive");
 
     // Open the archive and verify file can be read
-    let mut archive = Archive::open(&archive_path).expect("Failed to open archive");
+    let archive = Archive::open(&archive_path).expect("Failed to open archive");
 
     let data = archive
         .read_file("compressed_file.txt")
@@ -94,7 +94,7 @@ fn test_het_bet_with_many_files() {
         .expect("Failed to create archive");
 
     // Open the archive and verify a few files
-    let mut archive = Archive::open(&archive_path).expect("Failed to open archive");
+    let archive = Archive::open(&archive_path).expect("Failed to open archive");
 
     // Check first, middle, and last files
     for i in [0, 25, 49] {
@@ -107,3 +107,97 @@ fn test_het_bet_with_many_files() {
         assert_eq!(data, expected_content.as_bytes());
     }
 }
+
+#[test]
+fn test_het_bet_only_archives() {
+    use mopaq::compression::flags;
+
+    let dir = tempdir().unwrap();
+    let big: Vec<u8> = (0..50_000u32)
+        .flat_map(|i| (i % 251).to_le_bytes())
+        .collect();
+
+    for version in [FormatVersion::V3, FormatVersion::V4] {
+        let build = |classic: bool, path: &std::path::Path| {
+            ArchiveBuilder::new()
+                .version(version)
+                .classic_tables(classic)
+                .add_file_data(b"Hello".to_vec(), "readme.txt")
+                .add_file_data(big.clone(), "Data\\big.bin")
+                .add_file_data_with_options(big.clone(), "secret.bin", flags::ZLIB, true, 0)
+                .build(path)
+                .unwrap();
+        };
+        let full_path = dir.path().join("full.mpq");
+        let modern_path = dir.path().join("modern.mpq");
+        build(true, &full_path);
+        build(false, &modern_path);
+
+        // The difference is exactly the classic tables
+        let full_header = Archive::open(&full_path).unwrap().header().clone();
+        let saved = std::fs::metadata(&full_path).unwrap().len()
+            - std::fs::metadata(&modern_path).unwrap().len();
+        assert_eq!(
+            saved,
+            (full_header.hash_table_size + full_header.block_table_size) as u64 * 16
+        );
+
+        let mut archive = Archive::open(&modern_path).unwrap();
+        assert_eq!(archive.header().hash_table_size, 0);
+        assert_eq!(archive.header().block_table_size, 0);
+        assert!(archive.hash_table().is_none());
+        assert!(archive.block_table().is_none());
+        assert!(archive.het_table().is_some());
+        assert!(archive.bet_table().is_some());
+
+        assert_eq!(archive.read_file("readme.txt").unwrap(), b"Hello");
+        assert_eq!(archive.read_file("Data\\big.bin").unwrap(), big);
+        assert_eq!(archive.read_file("secret.bin").unwrap(), big);
+        let mut names: Vec<String> = archive
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["(listfile)", "Data\\big.bin", "readme.txt", "secret.bin"]
+        );
+
+        // Rebuilding keeps working without classic tables to copy from
+        let rebuilt_path = dir.path().join("rebuilt.mpq");
+        ArchiveBuilder::from_archive(&mut archive)
+            .unwrap()
+            .classic_tables(false)
+            .remove_file("readme.txt")
+            .build(&rebuilt_path)
+            .unwrap();
+        let rebuilt = Archive::open(&rebuilt_path).unwrap();
+        assert!(rebuilt.find_file("readme.txt").unwrap().is_none());
+        assert_eq!(rebuilt.read_file("secret.bin").unwrap(), big);
+    }
+
+    // Classic tables are always written below v3
+    let v2_path = dir.path().join("v2.mpq");
+    ArchiveBuilder::new()
+        .version(FormatVersion::V2)
+        .classic_tables(false)
+        .add_file_data(b"Hello".to_vec(), "readme.txt")
+        .build(&v2_path)
+        .unwrap();
+    let archive = Archive::open(&v2_path).unwrap();
+    assert!(archive.hash_table().is_some());
+    assert_eq!(archive.read_file("readme.txt").unwrap(), b"Hello");
+
+    // An empty archive has nothing in either kind of table
+    let empty_path = dir.path().join("empty.mpq");
+    ArchiveBuilder::new()
+        .version(FormatVersion::V4)
+        .classic_tables(false)
+        .listfile_option(mopaq::ListfileOption::None)
+        .build(&empty_path)
+        .unwrap();
+    let mut archive = Archive::open(&empty_path).unwrap();
+    assert!(archive.list().unwrap().is_empty());
+}
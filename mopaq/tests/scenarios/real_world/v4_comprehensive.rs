@@ -31,7 +31,7 @@ fn test_v4_comprehensive() {
         .add_file_with_options(
             &file2_path,
             "files/test2.bin",
-            compression::flags::BZIP2,
+            compression::flags::PKWARE,
             false,
             0,
         )
@@ -152,7 +152,7 @@ fn test_v4_large_file_support() {
     builder.build(path).unwrap();
 
     // Verify all files can be read
-    let mut archive = Archive::open(path).unwrap();
+    let archive = Archive::open(path).unwrap();
     for i in 0..10 {
         let data = archive.read_file(&format!("large_{}.dat", i)).unwrap();
         assert_eq!(data.len(), 1024 * 1024);
@@ -0,0 +1,215 @@
+//! Property-based build→open round trips
+//!
+//! Random file sets are built with every combination of format version,
+//! compression, encryption, sector size and checksum settings, then read
+//! back and compared byte for byte.
+
+use std::collections::HashSet;
+
+use mopaq::compression::flags;
+use mopaq::{Archive, ArchiveBuilder, FormatVersion};
+use proptest::prelude::*;
+use proptest::sample::select;
+use tempfile::TempDir;
+
+/// Lossless codecs the builder can write; ADPCM is lossy and Huffman has
+/// no encoder
+fn compressions() -> Vec<u8> {
+    vec![
+        0,
+        flags::ZLIB,
+        #[cfg(feature = "compression-bzip2")]
+        flags::BZIP2,
+        #[cfg(feature = "compression-lzma")]
+        flags::LZMA,
+        flags::PKWARE,
+        flags::SPARSE,
+    ]
+}
+
+const LOCALES: [u16; 4] = [0, 0x409, 0x407, 0x40C];
+
+#[derive(Debug, Clone, Copy)]
+enum Encryption {
+    None,
+    Encrypted,
+    FixKey,
+}
+
+#[derive(Debug, Clone)]
+struct FileSpec {
+    name: String,
+    data: Vec<u8>,
+    locale: u16,
+    encryption: Encryption,
+}
+
+#[derive(Debug, Clone)]
+struct Settings {
+    version: FormatVersion,
+    compression: u8,
+    block_size: u16,
+    generate_crcs: bool,
+    compress_tables: bool,
+}
+
+fn contents() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        // Incompressible data is stored as is
+        prop::collection::vec(any::<u8>(), 0..10_000),
+        // Runs compress well and span several sectors
+        (any::<u8>(), 0..40_000usize).prop_map(|(byte, len)| vec![byte; len]),
+        // Text with zero padding, which every codec shrinks
+        (1..400usize, 0..4_000usize).prop_map(|(lines, zeros)| {
+            let mut data: Vec<u8> = (0..lines)
+                .flat_map(|i| format!("line {} of the test file\r\n", i).into_bytes())
+                .collect();
+            data.resize(data.len() + zeros, 0);
+            data
+        }),
+    ]
+}
+
+fn file_spec() -> impl Strategy<Value = FileSpec> {
+    (
+        "[A-Za-z0-9_]{1,12}(\\\\[A-Za-z0-9_ ]{1,12}){0,2}\\.[a-z]{3}",
+        contents(),
+        select(&LOCALES[..]),
+        prop_oneof![
+            Just(Encryption::None),
+            Just(Encryption::Encrypted),
+            Just(Encryption::FixKey),
+        ],
+    )
+        .prop_map(|(name, data, locale, encryption)| FileSpec {
+            name,
+            data,
+            locale,
+            encryption,
+        })
+}
+
+/// Files with distinct names; lookups ignore case
+fn file_set() -> impl Strategy<Value = Vec<FileSpec>> {
+    prop::collection::vec(file_spec(), 0..12).prop_map(|files| {
+        let mut seen = HashSet::new();
+        files
+            .into_iter()
+            .filter(|file| seen.insert(file.name.to_ascii_uppercase()))
+            .collect()
+    })
+}
+
+fn settings() -> impl Strategy<Value = Settings> {
+    (
+        select(
+            &[
+                FormatVersion::V1,
+                FormatVersion::V2,
+                FormatVersion::V3,
+                FormatVersion::V4,
+            ][..],
+        ),
+        select(compressions()),
+        0..=5u16,
+        any::<bool>(),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(version, compression, block_size, generate_crcs, compress_tables)| Settings {
+                version,
+                compression,
+                block_size,
+                generate_crcs,
+                compress_tables,
+            },
+        )
+}
+
+fn build_and_read(settings: &Settings, files: &[FileSpec]) -> Result<(), TestCaseError> {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("roundtrip.mpq");
+
+    let mut builder = ArchiveBuilder::new()
+        .version(settings.version)
+        .block_size(settings.block_size)
+        .generate_crcs(settings.generate_crcs)
+        .compress_tables(settings.compress_tables);
+    for file in files {
+        builder = match file.encryption {
+            Encryption::None => builder.add_file_data_with_options(
+                file.data.clone(),
+                &file.name,
+                settings.compression,
+                false,
+                file.locale,
+            ),
+            Encryption::Encrypted | Encryption::FixKey => builder.add_file_data_with_encryption(
+                file.data.clone(),
+                &file.name,
+                settings.compression,
+                matches!(file.encryption, Encryption::FixKey),
+                file.locale,
+            ),
+        };
+    }
+    builder
+        .build(&path)
+        .map_err(|e| TestCaseError::fail(format!("build failed: {}", e)))?;
+
+    let mut archive =
+        Archive::open(&path).map_err(|e| TestCaseError::fail(format!("open failed: {}", e)))?;
+    for file in files {
+        let data = archive
+            .read_file(&file.name)
+            .map_err(|e| TestCaseError::fail(format!("reading {}: {}", file.name, e)))?;
+        prop_assert!(
+            data == file.data,
+            "{} read back {} bytes, expected {}",
+            file.name,
+            data.len(),
+            file.data.len()
+        );
+    }
+
+    let listed = archive
+        .list()
+        .map_err(|e| TestCaseError::fail(format!("list failed: {}", e)))?;
+    let names: HashSet<String> = listed.iter().map(|entry| entry.name.clone()).collect();
+    for file in files {
+        prop_assert!(names.contains(&file.name), "{} not listed", file.name);
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_build_open_round_trip(settings in settings(), files in file_set()) {
+        build_and_read(&settings, &files)?;
+    }
+}
+
+#[test]
+fn test_pkware_sectors_above_4k() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("pkware.mpq");
+    let text: Vec<u8> = b"PKWare sectors larger than the dictionary. "
+        .iter()
+        .cycle()
+        .take(100_000)
+        .enumerate()
+        .map(|(i, &b)| if i % 997 == 0 { (i >> 3) as u8 } else { b })
+        .collect();
+
+    for block_size in [4, 5, 7] {
+        ArchiveBuilder::new()
+            .block_size(block_size)
+            .add_file_data_with_options(text.clone(), "text.txt", flags::PKWARE, false, 0)
+            .build(&path)
+            .unwrap();
+        let archive = Archive::open(&path).unwrap();
+        assert_eq!(archive.read_file("text.txt").unwrap(), text);
+    }
+}
@@ -2,4 +2,5 @@
 //!
 //! Tests that verify data integrity through complete create-extract cycles.
 
+mod archives;
 mod tests;
@@ -18,6 +18,7 @@ fn test_zlib_round_trip() {
 }
 
 #[test]
+#[cfg(feature = "compression-bzip2")]
 fn test_bzip2_round_trip() {
     let test_cases = vec![
         b"Hello, World!".to_vec(),
@@ -31,6 +32,7 @@ fn test_bzip2_round_trip() {
 }
 
 #[test]
+#[cfg(feature = "compression-lzma")]
 fn test_lzma_round_trip() {
     let test_cases = vec![
         b"Hello, World!".to_vec(),
@@ -44,6 +46,16 @@ fn test_lzma_round_trip() {
     }
 }
 
+#[test]
+fn test_pkware_round_trip_above_4k() {
+    for len in [8 << 10, 16 << 10, 64 << 10] {
+        let original: Vec<u8> = (0..len as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 15) as u8 % 64)
+            .collect();
+        test_round_trip(&original, flags::PKWARE).expect("PKWare round trip failed");
+    }
+}
+
 #[test]
 fn test_sparse_round_trip() {
     let test_cases = vec![
@@ -0,0 +1,240 @@
+//! Expansion of command aliases from the config file
+//!
+//! An alias maps a name to the start of a command line:
+//!
+//! ```toml
+//! [aliases]
+//! pack = "archive create -V 2 -c zlib $1 $2"
+//! ls = "file list"
+//! ```
+//!
+//! `$1`, `$2`, ... are replaced with the arguments after the alias name and
+//! `$@` with all of them. Arguments not used by a placeholder are appended,
+//! so `storm-cli ls war3.mpq -p "*.j"` runs `file list war3.mpq -p "*.j"`.
+
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Global options that take a value as the next word
+const GLOBAL_VALUE_OPTIONS: [&str; 5] = ["-o", "--output", "-C", "--config", "--profile"];
+
+/// The `--config` path given on the command line, if any
+///
+/// Aliases come from the config file, so it has to be found before clap
+/// parses the arguments.
+pub fn config_path(args: &[String]) -> Option<String> {
+    let mut words = args.iter().take_while(|word| *word != "--");
+    while let Some(word) = words.next() {
+        if word == "-C" || word == "--config" {
+            return words.next().cloned();
+        }
+        if let Some(path) = word.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+        if let Some(path) = word.strip_prefix("-C").filter(|path| !path.is_empty()) {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+/// Replace an alias at the command position of `args` with its expansion
+///
+/// `args` excludes the program name. Names in `builtins` are never treated
+/// as aliases, and an expansion is not expanded again.
+pub fn expand(
+    args: Vec<String>,
+    aliases: &HashMap<String, String>,
+    builtins: &[&str],
+) -> Result<Vec<String>> {
+    let Some(position) = alias_position(&args, builtins) else {
+        return Ok(args);
+    };
+    let name = &args[position];
+    let Some(definition) = aliases.get(name) else {
+        return Ok(args);
+    };
+
+    let words =
+        split(definition).map_err(|e| anyhow::anyhow!("Invalid alias '{}': {}", name, e))?;
+    let arguments = &args[position + 1..];
+
+    let mut expanded = args[..position].to_vec();
+    let mut used = 0;
+    let mut used_all = false;
+    for word in words {
+        if word == "$@" {
+            expanded.extend(arguments.iter().cloned());
+            used_all = true;
+        } else {
+            let (word, highest) = substitute(&word, arguments).map_err(|index| {
+                anyhow::anyhow!(
+                    "Alias '{}' uses ${} but only {} argument(s) were given",
+                    name,
+                    index,
+                    arguments.len()
+                )
+            })?;
+            used = used.max(highest);
+            expanded.push(word);
+        }
+    }
+    if !used_all {
+        expanded.extend(arguments[used..].iter().cloned());
+    }
+    Ok(expanded)
+}
+
+/// Whether the command word of `args` is not in `builtins` and so may be
+/// an alias
+///
+/// Only then is the config file needed before parsing.
+pub fn may_be_alias(args: &[String], builtins: &[&str]) -> bool {
+    alias_position(args, builtins).is_some()
+}
+
+/// Index of the command word if it is not one of `builtins`
+fn alias_position(args: &[String], builtins: &[&str]) -> Option<usize> {
+    command_position(args).filter(|&position| !builtins.contains(&args[position].as_str()))
+}
+
+/// Index of the first word that is not a global option or its value
+fn command_position(args: &[String]) -> Option<usize> {
+    let mut skip_value = false;
+    for (index, word) in args.iter().enumerate() {
+        if skip_value {
+            skip_value = false;
+        } else if word.starts_with('-') {
+            skip_value = GLOBAL_VALUE_OPTIONS.contains(&word.as_str());
+        } else {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Replace `$N` placeholders in `word`
+///
+/// Returns the word and the highest placeholder used, or the placeholder
+/// that has no argument.
+fn substitute(word: &str, arguments: &[String]) -> std::result::Result<(String, usize), usize> {
+    let mut result = String::with_capacity(word.len());
+    let mut highest = 0;
+    let mut chars = word.char_indices();
+    while let Some((start, c)) = chars.next() {
+        let digits = word[start + 1..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .count();
+        if c != '$' || digits == 0 {
+            result.push(c);
+            continue;
+        }
+        let index: usize = word[start + 1..start + 1 + digits].parse().unwrap_or(0);
+        if index == 0 || index > arguments.len() {
+            return Err(index);
+        }
+        result.push_str(&arguments[index - 1]);
+        highest = highest.max(index);
+        for _ in 0..digits {
+            chars.next();
+        }
+    }
+    Ok((result, highest))
+}
+
+/// Split an alias definition into words, honoring single and double quotes
+fn split(definition: &str) -> std::result::Result<Vec<String>, &'static str> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    for c in definition.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quote");
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        let aliases: HashMap<String, String> = [
+            ("pack", "archive create -V 2 $1 $2"),
+            ("ls", "file list"),
+            ("each", "file extract $@ --preserve-path"),
+            ("named", "file list -p '*.j' $1"),
+            ("file", "archive info"),
+        ]
+        .into_iter()
+        .map(|(name, definition)| (name.to_string(), definition.to_string()))
+        .collect();
+        let builtins = ["archive", "file"];
+        let expand = |line: &str| expand(args(line), &aliases, &builtins);
+
+        assert_eq!(
+            expand("-v pack out.mpq src --no-listfile").unwrap(),
+            args("-v archive create -V 2 out.mpq src --no-listfile")
+        );
+        assert_eq!(
+            expand("-o json ls war3.mpq").unwrap(),
+            args("-o json file list war3.mpq")
+        );
+        assert_eq!(
+            expand("each a.mpq x").unwrap(),
+            args("file extract a.mpq x --preserve-path")
+        );
+        assert_eq!(
+            expand("named a.mpq").unwrap(),
+            ["file", "list", "-p", "*.j", "a.mpq"]
+        );
+        // Built-in commands win over aliases
+        assert_eq!(expand("file list a.mpq").unwrap(), args("file list a.mpq"));
+        assert_eq!(expand("other x").unwrap(), args("other x"));
+
+        let error = expand("pack out.mpq").unwrap_err().to_string();
+        assert!(error.contains("$2"), "{}", error);
+    }
+
+    #[test]
+    fn test_config_path() {
+        assert_eq!(
+            config_path(&args("-C my.toml pack")).as_deref(),
+            Some("my.toml")
+        );
+        assert_eq!(
+            config_path(&args("pack --config=a.toml")).as_deref(),
+            Some("a.toml")
+        );
+        assert_eq!(config_path(&args("pack -- -C x")), None);
+    }
+}
@@ -1,15 +1,34 @@
 //! Archive-level operations
 
 use anyhow::{Context, Result};
+use clap::ValueEnum;
 use colored::Colorize;
-use mopaq::compression::CompressionMethod;
-use mopaq::{Archive, ArchiveBuilder, FormatVersion, ListfileOption, OpenOptions, SignatureStatus};
+use indicatif::{ProgressBar, ProgressStyle};
+use mopaq::analysis::{
+    analyze_compression, suggest_sector_size, CompressionAnalysis, SectorSizeEstimate,
+    SectorSizeSuggestion,
+};
+use mopaq::convert::{self, ConvertOptions};
+use mopaq::special_files::{parse_listfile, SpecialFile};
+use mopaq::{
+    AddFileOptions, Archive, ArchiveBuilder, BuildManifest, BuildObserver, FileInfo, FormatVersion,
+    ListfileOption, Locale, Md5Status, MutableArchive, OpenOptions, SectorChecksum,
+    SignatureStatus,
+};
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::Serialize;
 use serde_json;
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
 use walkdir::WalkDir;
 
-use crate::output::{print_archive_info, print_json};
+use crate::commands::file::block_file_name;
+use crate::exit::{CheckFailed, FailOn};
+use crate::output::{format_size, print_archive_info, print_records, print_structured};
 use crate::{OutputFormat, GLOBAL_OPTS};
 
 #[derive(Debug, Clone)]
@@ -21,6 +40,8 @@ pub struct CreateOptions {
     pub recursive: bool,
     pub follow_symlinks: bool,
     pub ignore_patterns: Vec<String>,
+    pub threads: usize,
+    pub locale: Locale,
 }
 
 impl Default for CreateOptions {
@@ -33,10 +54,44 @@ impl Default for CreateOptions {
             recursive: true,
             follow_symlinks: false,
             ignore_patterns: vec![],
+            threads: 0,
+            locale: Locale::NEUTRAL,
         }
     }
 }
 
+/// Drives a progress bar from archive builder events
+struct BuildProgress {
+    bar: ProgressBar,
+}
+
+impl BuildProgress {
+    fn new() -> Self {
+        let bar = ProgressBar::new(0);
+        if let Ok(style) = ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} {msg}")
+        {
+            bar.set_style(style.progress_chars("#>-"));
+        }
+        Self { bar }
+    }
+}
+
+impl BuildObserver for BuildProgress {
+    fn on_file_start(&mut self, name: &str, _index: usize, total: usize) {
+        self.bar.set_length(total as u64);
+        self.bar.set_message(name.to_string());
+    }
+
+    fn on_file_done(&mut self, _name: &str, _compressed_size: u64, _ratio: f64) {
+        self.bar.inc(1);
+    }
+
+    fn on_finish(&mut self, _archive_size: u64, _file_count: usize, _error: Option<&mopaq::Error>) {
+        self.bar.finish_and_clear();
+    }
+}
+
 /// Create a new MPQ archive
 pub fn create(archive_path: &str, source: &str, options: CreateOptions) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
@@ -50,7 +105,8 @@ pub fn create(archive_path: &str, source: &str, options: CreateOptions) -> Resul
         .version(options.version)
         .default_compression(options.compression as u8)
         .block_size(options.block_size)
-        .listfile_option(options.listfile.clone());
+        .listfile_option(options.listfile.clone())
+        .threads(options.threads);
 
     let source_path = Path::new(source);
 
@@ -60,7 +116,13 @@ pub fn create(archive_path: &str, source: &str, options: CreateOptions) -> Resul
             .file_name()
             .context("Invalid file name")?
             .to_string_lossy();
-        builder = builder.add_file(source, &file_name);
+        builder = builder.add_file_with_options(
+            source,
+            &file_name,
+            options.compression as u8,
+            false,
+            options.locale,
+        );
         if !global_opts.quiet {
             println!("Added: {}", file_name);
         }
@@ -71,11 +133,117 @@ pub fn create(archive_path: &str, source: &str, options: CreateOptions) -> Resul
         anyhow::bail!("Source path does not exist: {}", source);
     }
 
-    // Build the archive
-    builder.build(archive_path)?;
+    build_with_progress(builder, archive_path)
+}
+
+/// Build an archive from a TOML manifest
+pub fn build(archive_path: &str, manifest: &str, threads: usize) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    if !global_opts.quiet {
+        println!(
+            "Building archive: {} from {}",
+            archive_path.cyan(),
+            manifest
+        );
+    }
+    let builder = ArchiveBuilder::from_manifest(manifest)
+        .with_context(|| format!("Failed to load manifest: {}", manifest))?
+        .threads(threads);
+    build_with_progress(builder, archive_path)
+}
+
+/// Validate a build manifest without reading its sources
+pub fn check_manifest(manifest: &str) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
+    let parsed = BuildManifest::load(manifest)
+        .and_then(|parsed| parsed.validate().map(|()| parsed))
+        .with_context(|| format!("Invalid manifest: {}", manifest))?;
     if !global_opts.quiet {
-        println!("{} Archive created successfully", "✓".green());
+        println!(
+            "{} Manifest is valid: {} files",
+            "✓".green(),
+            parsed.files.len()
+        );
+    }
+    Ok(())
+}
+
+/// Print a build manifest describing an existing archive
+pub fn manifest(archive_path: &str, data_dir: Option<&str>) -> Result<()> {
+    let mut archive = Archive::open(archive_path)
+        .with_context(|| format!("Failed to open archive: {}", archive_path))?;
+    let mut manifest = BuildManifest::from_archive(&mut archive, Path::new(archive_path))?;
+    if let Some(dir) = data_dir {
+        manifest = manifest.with_data_dir(Path::new(dir));
+    }
+    print!("{}", manifest.to_toml()?);
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct CkeyRecord {
+    path: String,
+    ckey: String,
+    size: u64,
+}
+
+/// Print the content key of every file in an archive
+pub fn ckeys(archive_path: &str) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let mut archive = Archive::open(archive_path)
+        .with_context(|| format!("Failed to open archive: {}", archive_path))?;
+    let manifest = archive.export_manifest()?;
+
+    if global_opts.output == OutputFormat::Text {
+        manifest.write_ckeys(std::io::stdout().lock())?;
+    } else {
+        let records: Vec<CkeyRecord> = manifest
+            .entries
+            .iter()
+            .filter(|entry| SpecialFile::from_name(&entry.name).is_none())
+            .map(|entry| CkeyRecord {
+                path: entry.name.replace('\\', "/"),
+                ckey: entry.ckey(),
+                size: entry.size,
+            })
+            .collect();
+        print_records(&records, global_opts.output)?;
+    }
+    Ok(())
+}
+
+/// Build the archive, showing progress and a summary unless quiet
+fn build_with_progress(mut builder: ArchiveBuilder, archive_path: &str) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    if !global_opts.quiet {
+        builder = builder.observer(BuildProgress::new());
+    }
+    let summary = builder.build(archive_path)?;
+
+    if !global_opts.quiet {
+        println!(
+            "{} Archive created successfully: {} files, {} ({:.1}% of {})",
+            "✓".green(),
+            summary.files.len(),
+            format_size(summary.archive_size),
+            summary.ratio() * 100.0,
+            format_size(summary.total_file_size())
+        );
+        if global_opts.verbose > 0 {
+            for (table, size) in &summary.tables {
+                println!("  {:?} table: {}", table, format_size(*size));
+            }
+            println!(
+                "  Time: {:.2}s (files {:.2}s, tables {:.2}s)",
+                summary.total_time.as_secs_f64(),
+                summary.file_time.as_secs_f64(),
+                summary.table_time.as_secs_f64()
+            );
+        }
     }
 
     Ok(())
@@ -109,15 +277,7 @@ fn add_directory_to_archive(
         }
 
         // Check ignore patterns
-        let should_ignore = options.ignore_patterns.iter().any(|pattern| {
-            path.to_string_lossy().contains(pattern)
-                || path
-                    .file_name()
-                    .map(|name| name.to_string_lossy().contains(pattern))
-                    .unwrap_or(false)
-        });
-
-        if should_ignore {
+        if is_ignored(path, &options.ignore_patterns) {
             continue;
         }
 
@@ -135,7 +295,13 @@ fn add_directory_to_archive(
         // Normalize path separators
         let archive_path = archive_path.replace('\\', "/");
 
-        builder = builder.add_file(path.to_str().unwrap(), &archive_path);
+        builder = builder.add_file_with_options(
+            path,
+            &archive_path,
+            options.compression as u8,
+            false,
+            options.locale,
+        );
 
         if !quiet {
             println!("Added: {}", archive_path);
@@ -145,6 +311,155 @@ fn add_directory_to_archive(
     Ok(builder)
 }
 
+/// Check a path against the user's ignore patterns
+fn is_ignored(path: &Path, patterns: &[String]) -> bool {
+    patterns.iter().any(|pattern| {
+        path.to_string_lossy().contains(pattern)
+            || path
+                .file_name()
+                .map(|name| name.to_string_lossy().contains(pattern))
+                .unwrap_or(false)
+    })
+}
+
+/// Watch a directory and repack changed files into an archive
+///
+/// The archive is created from the directory if it does not exist yet. After
+/// that, every batch of filesystem changes (collected until `debounce` passes
+/// without a new event) is applied to the archive in place: changed files are
+/// appended and replace their old versions, and deleted files are removed.
+/// Other files, named in the listfile or not, are left as they are.
+pub fn watch(
+    source: &str,
+    archive_path: &str,
+    options: CreateOptions,
+    debounce: Duration,
+) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let source_dir = Path::new(source)
+        .canonicalize()
+        .with_context(|| format!("Cannot watch {}", source))?;
+    if !source_dir.is_dir() {
+        anyhow::bail!("Source path is not a directory: {}", source);
+    }
+
+    // Writing the archive would retrigger the watcher forever if it lived
+    // inside the source
+    let archive_dir = Path::new(archive_path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .canonicalize()?;
+    if archive_dir.starts_with(&source_dir) {
+        anyhow::bail!("The archive must not be inside the watched directory");
+    }
+
+    if !Path::new(archive_path).exists() {
+        create(archive_path, source, options.clone())?;
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&source_dir, RecursiveMode::Recursive)?;
+
+    if !global_opts.quiet {
+        println!(
+            "Watching {} (press Ctrl-C to stop)",
+            source_dir.display().to_string().cyan()
+        );
+    }
+
+    // Block for the first event of a batch, then drain until things settle
+    while let Ok(event) = rx.recv() {
+        let mut changes = BTreeMap::new();
+        collect_changes(event, &source_dir, &options, &mut changes);
+        while let Ok(event) = rx.recv_timeout(debounce) {
+            collect_changes(event, &source_dir, &options, &mut changes);
+        }
+
+        if changes.is_empty() {
+            continue;
+        }
+
+        match repack(archive_path, &changes, &options) {
+            Ok(()) if !global_opts.quiet => {
+                for (name, path) in &changes {
+                    if path.is_file() {
+                        println!("{} {}", "Updated:".green(), name);
+                    } else {
+                        println!("{} {}", "Removed:".yellow(), name);
+                    }
+                }
+            }
+            Ok(()) => {}
+            // Keep watching; the next save usually fixes a half-written file
+            Err(e) => eprintln!("{} Repack failed: {:#}", "✗".red(), e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Record the archive names touched by a filesystem event
+///
+/// Watcher errors, such as an overflowing event queue, are reported and
+/// otherwise ignored so that watching goes on.
+fn collect_changes(
+    event: notify::Result<notify::Event>,
+    source_dir: &Path,
+    options: &CreateOptions,
+    changes: &mut BTreeMap<String, PathBuf>,
+) {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            eprintln!("{} Watch error: {}", "✗".red(), e);
+            return;
+        }
+    };
+    if matches!(event.kind, EventKind::Access(_)) {
+        return;
+    }
+
+    for path in event.paths {
+        if path.is_dir() || is_ignored(&path, &options.ignore_patterns) {
+            continue;
+        }
+        if let Ok(relative) = path.strip_prefix(source_dir) {
+            let name = relative.to_string_lossy().replace('\\', "/");
+            changes.insert(name, path);
+        }
+    }
+}
+
+/// Apply a batch of changes to the archive in place
+fn repack(
+    archive_path: &str,
+    changes: &BTreeMap<String, PathBuf>,
+    options: &CreateOptions,
+) -> Result<()> {
+    let mut archive = MutableArchive::open(archive_path)?;
+    let add_options = AddFileOptions::new()
+        .compression(options.compression as u8)
+        .locale(options.locale);
+
+    for (name, path) in changes {
+        if path.is_file() {
+            archive.add_file(path, name, &add_options)?;
+        } else {
+            // Files deleted before they were ever packed are not an error
+            match archive.remove_file(name) {
+                Ok(()) | Err(mopaq::Error::FileNotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    archive.flush()?;
+    Ok(())
+}
+
 /// Show detailed archive information
 pub fn info(archive_path: &str) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
@@ -156,14 +471,23 @@ pub fn info(archive_path: &str) -> Result<()> {
 }
 
 /// Verify archive integrity
-pub fn verify(archive_path: &str, check_crc: bool, check_contents: bool) -> Result<()> {
+///
+/// Fails with [`CheckFailed`] when errors are found, or warnings too if
+/// `fail_on` is [`FailOn::Warn`].
+pub fn verify(
+    archive_path: &str,
+    check_crc: bool,
+    check_contents: bool,
+    checksum: Option<SectorChecksum>,
+    fail_on: FailOn,
+) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
     if !global_opts.quiet && global_opts.output == OutputFormat::Text {
         println!("Verifying archive: {}", archive_path.cyan());
     }
 
-    let options = OpenOptions::new();
+    let options = OpenOptions::new().sector_checksum(checksum);
     let mut archive = Archive::open_with_options(archive_path, options)?;
 
     // Get archive info for detailed verification information
@@ -203,18 +527,33 @@ pub fn verify(archive_path: &str, check_crc: bool, check_contents: bool) -> Resu
     }
 
     // Check MD5 checksums if available (v4 archives)
-    if let Some(md5_status) = &archive_info.md5_status {
-        verification_results.table_checks.md5_checksums = Some(Md5Checks {
-            header_valid: md5_status.header_valid,
-            hash_table_valid: md5_status.hash_table_valid,
-            block_table_valid: md5_status.block_table_valid,
-            hi_block_table_valid: md5_status.hi_block_table_valid,
-            het_table_valid: md5_status.het_table_valid,
-            bet_table_valid: md5_status.bet_table_valid,
-        });
+    if let Some(md5) = &archive_info.md5_status {
+        for (table, valid) in [
+            ("header", md5.header_valid),
+            ("hash table", md5.hash_table_valid),
+            ("block table", md5.block_table_valid),
+            ("hi-block table", md5.hi_block_table_valid),
+            ("HET table", md5.het_table_valid),
+            ("BET table", md5.bet_table_valid),
+        ] {
+            if !valid {
+                verification_results
+                    .warnings
+                    .push(format!("MD5 checksum of the {} does not match", table));
+            }
+        }
     }
+    verification_results.table_checks.md5_checksums = archive_info.md5_status.clone();
 
     // Check digital signature
+    if matches!(
+        archive_info.signature_status,
+        SignatureStatus::WeakInvalid | SignatureStatus::StrongInvalid
+    ) {
+        verification_results
+            .warnings
+            .push("Digital signature is invalid".to_string());
+    }
     verification_results.header_checks.signature_status =
         Some(archive_info.signature_status.clone());
 
@@ -291,11 +630,15 @@ pub fn verify(archive_path: &str, check_crc: bool, check_contents: bool) -> Resu
     // Print detailed verification results
     print_detailed_verify_result(&verification_results, global_opts.output, global_opts.quiet)?;
 
-    if !verification_results.errors.is_empty() {
-        anyhow::bail!(
-            "Verification failed with {} errors",
-            verification_results.errors.len()
-        );
+    let errors = verification_results.errors.len();
+    let warnings = verification_results.warnings.len();
+    if fail_on.fails(errors, warnings) {
+        let message = if errors > 0 {
+            format!("Verification failed with {} errors", errors)
+        } else {
+            format!("Verification failed with {} warnings", warnings)
+        };
+        return Err(CheckFailed(message).into());
     }
 
     Ok(())
@@ -327,17 +670,7 @@ struct TableChecks {
     block_table_loaded: bool,
     het_table_loaded: Option<bool>,
     bet_table_loaded: Option<bool>,
-    md5_checksums: Option<Md5Checks>,
-}
-
-#[derive(Debug)]
-struct Md5Checks {
-    header_valid: bool,
-    hash_table_valid: bool,
-    block_table_valid: bool,
-    hi_block_table_valid: bool,
-    het_table_valid: bool,
-    bet_table_valid: bool,
+    md5_checksums: Option<Md5Status>,
 }
 
 #[derive(Debug, Default)]
@@ -555,7 +888,7 @@ fn print_detailed_verify_result(
                 println!("{} Archive verification FAILED", "✗".red());
             }
         }
-        OutputFormat::Json => {
+        OutputFormat::Json | OutputFormat::Jsonl => {
             let json_result = serde_json::json!({
                 "archive": results.archive_path,
                 "format_version": results.format_version as u16 + 1,
@@ -564,23 +897,14 @@ fn print_detailed_verify_result(
                 "header_checks": {
                     "signature_valid": results.header_checks.signature_valid,
                     "version_supported": results.header_checks.version_supported,
-                    "signature_status": format!("{:?}", results.header_checks.signature_status),
+                    "signature_status": results.header_checks.signature_status,
                 },
                 "table_checks": {
                     "hash_table_loaded": results.table_checks.hash_table_loaded,
                     "block_table_loaded": results.table_checks.block_table_loaded,
                     "het_table_loaded": results.table_checks.het_table_loaded,
                     "bet_table_loaded": results.table_checks.bet_table_loaded,
-                    "md5_checksums": results.table_checks.md5_checksums.as_ref().map(|md5| {
-                        serde_json::json!({
-                            "header_valid": md5.header_valid,
-                            "hash_table_valid": md5.hash_table_valid,
-                            "block_table_valid": md5.block_table_valid,
-                            "hi_block_table_valid": md5.hi_block_table_valid,
-                            "het_table_valid": md5.het_table_valid,
-                            "bet_table_valid": md5.bet_table_valid,
-                        })
-                    }),
+                    "md5_checksums": results.table_checks.md5_checksums,
                 },
                 "file_checks": {
                     "files_found": results.file_checks.files_found,
@@ -594,7 +918,7 @@ fn print_detailed_verify_result(
                 }).collect::<Vec<_>>(),
                 "passed": results.errors.is_empty(),
             });
-            print_json(&json_result)?;
+            print_structured(&json_result, format)?;
         }
         OutputFormat::Csv => {
             println!("metric,value");
@@ -611,13 +935,415 @@ fn print_detailed_verify_result(
     Ok(())
 }
 
+/// What [`salvage`] managed to do with one archive entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum SalvageStatus {
+    /// Extracted intact
+    Recovered,
+    /// Extracted with some sectors zero-filled or failing their checksums
+    Partial,
+    /// Nothing could be extracted
+    Failed,
+}
+
+#[derive(Serialize)]
+struct SalvageEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    block_index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    size: u64,
+    status: SalvageStatus,
+    /// Bytes replaced by zeros
+    bytes_lost: u64,
+    errors: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SalvageReport {
+    archive: String,
+    output_dir: PathBuf,
+    truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listfile_error: Option<String>,
+    recovered: usize,
+    partial: usize,
+    failed: usize,
+    entries: Vec<SalvageEntry>,
+}
+
+/// Extract everything readable from a damaged archive
+///
+/// Combines the tolerant reading paths of the library: truncated archives
+/// open with whatever tables remain, named files are read with
+/// [`Archive::read_file_partial`] so that one bad sector only costs that
+/// sector, and files that fail by name or have no name are read by block
+/// index, which recovers encryption keys where it can. Every entry is
+/// recorded in `salvage_report.json` in `output_dir`; the command only fails
+/// if the archive cannot be opened or the output cannot be written.
+pub fn salvage(archive_path: &str, output_dir: &str) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+    let text = global_opts.output == OutputFormat::Text;
+
+    let archive = Archive::open(archive_path)
+        .with_context(|| format!("Failed to open archive: {}", archive_path))?;
+    let output_dir = PathBuf::from(output_dir);
+    fs::create_dir_all(&output_dir).with_context(|| {
+        format!(
+            "Failed to create output directory: {}",
+            output_dir.display()
+        )
+    })?;
+
+    if archive.is_truncated() && text && !global_opts.quiet {
+        eprintln!(
+            "{} Archive is truncated; files past its end are lost",
+            "⚠".yellow()
+        );
+    }
+
+    let (mut names, listfile_error) = salvage_listfile(&archive);
+    names.extend(SpecialFile::ALL.map(|special| special.as_str().to_string()));
+
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    for name in names {
+        let Ok(Some(file_info)) = archive.find_file(&name) else {
+            continue;
+        };
+        if !seen.insert(file_info.block_index) {
+            continue;
+        }
+        let path = salvage_path(&output_dir, &name)
+            .unwrap_or_else(|| output_dir.join(block_file_name(file_info.block_index)));
+        entries.push(salvage_named(&archive, &name, &file_info, path)?);
+    }
+
+    // Without a readable listfile every entry is anonymous
+    let anonymous: Vec<usize> = match archive.anonymous_entries() {
+        Ok(anonymous) => anonymous.iter().map(|entry| entry.block_index).collect(),
+        Err(_) => {
+            let count = match (archive.block_table(), archive.bet_table()) {
+                (Some(block_table), _) => block_table.entries().len(),
+                (None, Some(bet)) => bet.header.file_count as usize,
+                (None, None) => 0,
+            };
+            (0..count).collect()
+        }
+    };
+    for block_index in anonymous {
+        if !seen.insert(block_index) {
+            continue;
+        }
+        let path = output_dir.join(block_file_name(block_index));
+        match archive.read_file_by_index(block_index) {
+            Ok(data) => {
+                fs::write(&path, &data)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                entries.push(SalvageEntry {
+                    name: None,
+                    block_index,
+                    path: Some(path),
+                    size: data.len() as u64,
+                    status: SalvageStatus::Recovered,
+                    bytes_lost: 0,
+                    errors: Vec::new(),
+                });
+            }
+            // Deleted and unused block table slots are not entries
+            Err(mopaq::Error::FileNotFound(_)) => {}
+            Err(e) => entries.push(SalvageEntry {
+                name: None,
+                block_index,
+                path: None,
+                size: 0,
+                status: SalvageStatus::Failed,
+                bytes_lost: 0,
+                errors: vec![e.to_string()],
+            }),
+        }
+    }
+
+    let count = |status| entries.iter().filter(|e| e.status == status).count();
+    let report = SalvageReport {
+        archive: archive_path.to_string(),
+        output_dir: output_dir.clone(),
+        truncated: archive.is_truncated(),
+        listfile_error,
+        recovered: count(SalvageStatus::Recovered),
+        partial: count(SalvageStatus::Partial),
+        failed: count(SalvageStatus::Failed),
+        entries,
+    };
+    let report_path = output_dir.join("salvage_report.json");
+    fs::write(&report_path, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write {}", report_path.display()))?;
+
+    if !text {
+        print_structured(&report, global_opts.output)?;
+    } else if !global_opts.quiet {
+        for entry in &report.entries {
+            let label = entry
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("block {}", entry.block_index));
+            match entry.status {
+                SalvageStatus::Recovered if global_opts.verbose > 0 => {
+                    println!("{} {}", "Recovered:".green(), label)
+                }
+                SalvageStatus::Recovered => {}
+                SalvageStatus::Partial => println!(
+                    "{} {} ({} bytes lost)",
+                    "Partial:".yellow(),
+                    label,
+                    entry.bytes_lost
+                ),
+                SalvageStatus::Failed => {
+                    println!("{} {}: {}", "Failed:".red(), label, entry.errors.join("; "))
+                }
+            }
+        }
+        println!(
+            "{} Salvaged {} of {} files ({} partial, {} failed); report written to {}",
+            "✓".green(),
+            report.recovered + report.partial,
+            report.entries.len(),
+            report.partial,
+            report.failed,
+            report_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Names from the archive's `(listfile)`, read around damaged sectors
+fn salvage_listfile(archive: &Archive) -> (Vec<String>, Option<String>) {
+    match archive.find_file("(listfile)") {
+        Ok(Some(_)) => {}
+        Ok(None) => return (Vec::new(), None),
+        Err(e) => return (Vec::new(), Some(e.to_string())),
+    }
+    let (data, errors) = match archive.read_file_partial("(listfile)") {
+        Ok(result) => result,
+        Err(e) => return (Vec::new(), Some(e.to_string())),
+    };
+    let error = errors.first().map(|e| e.error.to_string());
+    match parse_listfile(&data) {
+        // Zero-filled sectors leave NULs behind, never part of a real name
+        Ok(names) => (
+            names
+                .into_iter()
+                .filter(|name| !name.contains('\0'))
+                .collect(),
+            error,
+        ),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    }
+}
+
+/// Salvage a file the listfile names
+fn salvage_named(
+    archive: &Archive,
+    name: &str,
+    file_info: &FileInfo,
+    path: PathBuf,
+) -> Result<SalvageEntry> {
+    let mut entry = SalvageEntry {
+        name: Some(name.to_string()),
+        block_index: file_info.block_index,
+        path: None,
+        size: file_info.file_size,
+        status: SalvageStatus::Failed,
+        bytes_lost: 0,
+        errors: Vec::new(),
+    };
+
+    let data = match archive.read_file_partial(name) {
+        Ok((data, errors)) => {
+            entry.bytes_lost = errors
+                .iter()
+                .filter(|e| !e.recovered)
+                .map(|e| e.size as u64)
+                .sum();
+            entry.errors = errors
+                .iter()
+                .map(|e| {
+                    format!(
+                        "bytes {}..{}: {}",
+                        e.offset,
+                        e.offset + e.size as u64,
+                        e.error
+                    )
+                })
+                .collect();
+            if errors.is_empty() {
+                entry.status = SalvageStatus::Recovered;
+                Some(data)
+            } else if entry.bytes_lost < data.len() as u64 {
+                entry.status = SalvageStatus::Partial;
+                Some(data)
+            } else {
+                None
+            }
+        }
+        Err(e) => {
+            entry.errors.push(e.to_string());
+            None
+        }
+    };
+
+    // A wrong key or a broken offset table by name may still read by index
+    let data = match data {
+        Some(data) => data,
+        None => match archive.read_file_by_index(file_info.block_index) {
+            Ok(data) => {
+                entry.status = SalvageStatus::Recovered;
+                entry.bytes_lost = 0;
+                entry.errors.clear();
+                data
+            }
+            Err(_) => return Ok(entry),
+        },
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, &data).with_context(|| format!("Failed to write {}", path.display()))?;
+    entry.path = Some(path);
+    Ok(entry)
+}
+
+/// Path below `dir` for an archived name, or `None` if it would leave `dir`
+fn salvage_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(name.replace('\\', "/"));
+    let mut path = dir.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (path != dir && !name.contains(':')).then_some(path)
+}
+
+/// Formats `archive convert` reads and writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConvertFormat {
+    Mpq,
+    Zip,
+    Tar,
+}
+
+impl ConvertFormat {
+    /// Detect the format of a file from its first bytes
+    ///
+    /// Gzip files are taken to be compressed TARs.
+    fn detect(path: &str) -> Result<Self> {
+        let mut file = fs::File::open(path).with_context(|| format!("Failed to open {}", path))?;
+        let mut head = Vec::with_capacity(512);
+        (&mut file).take(512).read_to_end(&mut head)?;
+        if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            Ok(ConvertFormat::Zip)
+        } else if head.starts_with(&[0x1f, 0x8b]) || head.get(257..262) == Some(b"ustar") {
+            Ok(ConvertFormat::Tar)
+        } else if mopaq::header::find_header(&mut file).is_ok() {
+            Ok(ConvertFormat::Mpq)
+        } else {
+            anyhow::bail!("{} is not an MPQ, ZIP or TAR file", path)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ConvertReport {
+    input: String,
+    output: String,
+    from: ConvertFormat,
+    to: ConvertFormat,
+    files: usize,
+    bytes: u64,
+    skipped: Vec<String>,
+}
+
+/// Convert between MPQ archives and ZIP or TAR files
+///
+/// The input format is detected from its contents. ZIPs and TARs are
+/// imported into a new MPQ and MPQs are exported to a ZIP; without `to` the
+/// output is the other side of that pair.
+pub fn convert(
+    input: &str,
+    output: &str,
+    to: Option<ConvertFormat>,
+    options: ConvertOptions,
+) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let from = ConvertFormat::detect(input)?;
+    let to = to.unwrap_or(match from {
+        ConvertFormat::Mpq => ConvertFormat::Zip,
+        _ => ConvertFormat::Mpq,
+    });
+    let summary = match (from, to) {
+        (ConvertFormat::Zip, ConvertFormat::Mpq) => convert::zip_to_mpq(input, output, &options),
+        (ConvertFormat::Tar, ConvertFormat::Mpq) => convert::tar_to_mpq(input, output, &options),
+        (ConvertFormat::Mpq, ConvertFormat::Zip) => {
+            let mut archive = Archive::open(input)
+                .with_context(|| format!("Failed to open archive: {}", input))?;
+            convert::mpq_to_zip(&mut archive, output, &options)
+        }
+        (from, to) => anyhow::bail!(
+            "Cannot convert {:?} to {:?}; ZIP and TAR files convert to MPQ and MPQs to ZIP",
+            from,
+            to
+        ),
+    }
+    .with_context(|| format!("Failed to convert {} to {}", input, output))?;
+
+    let report = ConvertReport {
+        input: input.to_string(),
+        output: output.to_string(),
+        from,
+        to,
+        files: summary.files,
+        bytes: summary.bytes,
+        skipped: summary.skipped,
+    };
+    if global_opts.output != OutputFormat::Text {
+        print_structured(&report, global_opts.output)?;
+    } else if !global_opts.quiet {
+        for name in &report.skipped {
+            println!("{} {}", "Skipped:".yellow(), name);
+        }
+        println!(
+            "{} Converted {} files ({}) to {}",
+            "✓".green(),
+            report.files,
+            format_size(report.bytes),
+            output
+        );
+    }
+    Ok(())
+}
+
 /// Analyze compression methods used in an archive
+///
+/// With `fail_on`, files using compression methods this build cannot
+/// decompress count as errors, and files stored larger than their contents
+/// as warnings.
 pub fn analyze(
     archive_path: &str,
     detailed: bool,
     by_extension: bool,
     unsupported_only: bool,
     show_stats: bool,
+    fail_on: Option<FailOn>,
+    suggest: bool,
 ) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
@@ -626,293 +1352,212 @@ pub fn analyze(
     }
 
     let mut archive = Archive::open(archive_path)?;
+    let analysis = analyze_compression(&mut archive)?;
+    let suggestion = if suggest {
+        Some(suggest_sector_size(&mut archive)?)
+    } else {
+        None
+    };
 
-    // Get file entries with detailed info
-    let file_entries = archive.list_with_hashes()?;
-
-    if file_entries.is_empty() {
+    if analysis.files.is_empty() {
         if global_opts.output == OutputFormat::Text {
             println!("No files found in archive");
         }
         return Ok(());
     }
 
-    // Statistics tracking
-    let mut compression_stats: HashMap<String, usize> = HashMap::new();
-    let mut extension_stats: HashMap<String, HashMap<String, usize>> = HashMap::new();
-    let mut unsupported_files = Vec::new();
-    let mut total_compressed_size = 0u64;
-    let mut total_uncompressed_size = 0u64;
-
-    // Analyze each file
-    for entry in &file_entries {
-        if let Some(file_info) = archive.find_file(&entry.name)? {
-            let compression_method = CompressionMethod::from_flags(file_info.flags as u8);
-            let method_name = format_compression_method(&compression_method);
-            let is_supported = is_compression_supported(&compression_method);
-
-            // Update compression method statistics
-            *compression_stats.entry(method_name.clone()).or_insert(0) += 1;
-
-            // Track file extension statistics if requested
-            if by_extension {
-                let extension = get_file_extension(&entry.name);
-                let ext_entry = extension_stats.entry(extension).or_default();
-                *ext_entry.entry(method_name.clone()).or_insert(0) += 1;
+    match global_opts.output {
+        OutputFormat::Text => {
+            if detailed {
+                print_text_file_details(&analysis, unsupported_only, show_stats);
             }
-
-            // Track unsupported files
-            if !is_supported {
-                unsupported_files.push((entry.name.clone(), method_name.clone()));
+            print_text_analysis_results(&analysis, by_extension, unsupported_only, show_stats);
+            if let Some(suggestion) = &suggestion {
+                print_text_sector_suggestion(suggestion);
             }
-
-            // Update size statistics
-            total_compressed_size += file_info.compressed_size;
-            total_uncompressed_size += file_info.file_size;
-
-            // Print detailed info if requested
-            if detailed && (!unsupported_only || !is_supported) {
-                let support_status = if is_supported {
-                    "✓".green()
-                } else {
-                    "✗".red()
-                };
-
-                if global_opts.output == OutputFormat::Text {
-                    if show_stats {
-                        let ratio = if file_info.file_size > 0 {
-                            (file_info.compressed_size as f64 / file_info.file_size as f64 * 100.0)
-                                as u32
-                        } else {
-                            100
-                        };
-                        println!(
-                            "{} {} {} ({} -> {} bytes, {}%)",
-                            support_status,
-                            entry.name,
-                            method_name.cyan(),
-                            file_info.file_size,
-                            file_info.compressed_size,
-                            ratio
-                        );
-                    } else {
-                        println!("{} {} {}", support_status, entry.name, method_name.cyan());
-                    }
-                }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let mut result = json_analysis_results(&analysis, detailed, unsupported_only);
+            if let Some(suggestion) = &suggestion {
+                result["sector_size_suggestion"] = json_sector_suggestion(suggestion);
+            }
+            print_structured(&result, global_opts.output)?;
+        }
+        OutputFormat::Csv => {
+            println!("compression_method,file_count,supported");
+            for entry in &analysis.methods {
+                println!(
+                    "{},{},{}",
+                    entry.method,
+                    entry.count,
+                    entry.method.is_supported()
+                );
             }
         }
     }
 
-    // Output results based on format
-    match global_opts.output {
-        OutputFormat::Text => {
-            let results = AnalysisResults {
-                compression_stats: &compression_stats,
-                extension_stats: &extension_stats,
-                unsupported_files: &unsupported_files,
-                by_extension,
-                unsupported_only,
-                show_stats,
-                total_compressed_size,
-                total_uncompressed_size,
-                total_files: file_entries.len(),
+    if let Some(fail_on) = fail_on {
+        let unsupported = analysis.unsupported_files().count();
+        let inflated = analysis
+            .files
+            .iter()
+            .filter(|file| file.compressed_size > file.file_size)
+            .count();
+        if fail_on.fails(unsupported, inflated) {
+            let message = if unsupported > 0 {
+                format!("{} files use unsupported compression methods", unsupported)
+            } else {
+                format!("{} files are stored larger than their contents", inflated)
             };
-            print_text_analysis_results(&results);
-        }
-        OutputFormat::Json => {
-            print_json_analysis_results(
-                &compression_stats,
-                &extension_stats,
-                &unsupported_files,
-                total_compressed_size,
-                total_uncompressed_size,
-                file_entries.len(),
-            )?;
-        }
-        OutputFormat::Csv => {
-            print_csv_analysis_results(&compression_stats)?;
+            return Err(CheckFailed(message).into());
         }
     }
 
     Ok(())
 }
 
-fn is_compression_supported(method: &CompressionMethod) -> bool {
-    match method {
-        CompressionMethod::None => true,
-        CompressionMethod::Zlib => true,
-        CompressionMethod::BZip2 => true,
-        CompressionMethod::Lzma => true,
-        CompressionMethod::Sparse => true,
-        CompressionMethod::AdpcmMono => true,
-        CompressionMethod::AdpcmStereo => true,
-        CompressionMethod::Huffman => false, // Not implemented
-        CompressionMethod::Implode => false, // Not implemented
-        CompressionMethod::PKWare => false,  // Not implemented
-        CompressionMethod::Multiple(_) => false, // May contain unsupported methods
-    }
-}
-
-fn format_compression_method(method: &CompressionMethod) -> String {
-    match method {
-        CompressionMethod::None => "None".to_string(),
-        CompressionMethod::Huffman => "Huffman".to_string(),
-        CompressionMethod::Zlib => "Zlib".to_string(),
-        CompressionMethod::Implode => "PKWare Implode".to_string(),
-        CompressionMethod::PKWare => "PKWare DCL".to_string(),
-        CompressionMethod::BZip2 => "BZip2".to_string(),
-        CompressionMethod::Sparse => "Sparse".to_string(),
-        CompressionMethod::AdpcmMono => "ADPCM Mono".to_string(),
-        CompressionMethod::AdpcmStereo => "ADPCM Stereo".to_string(),
-        CompressionMethod::Lzma => "LZMA".to_string(),
-        CompressionMethod::Multiple(flags) => format!("Multiple (0x{:02X})", flags),
-    }
-}
-
-fn is_compression_method_supported(method_name: &str) -> bool {
-    match method_name {
-        "None" => true,
-        "Zlib" => true,
-        "BZip2" => true,
-        "LZMA" => true,
-        "Sparse" => true,
-        "ADPCM Mono" => true,
-        "ADPCM Stereo" => true,
-        "Huffman" => false,        // Not implemented
-        "PKWare Implode" => false, // Not implemented
-        "PKWare DCL" => false,     // Not implemented
-        _ => false,                // Multiple or unknown
-    }
-}
-
-fn get_file_extension(filename: &str) -> String {
-    std::path::Path::new(filename)
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .map(|ext| ext.to_lowercase())
-        .unwrap_or_else(|| "(no extension)".to_string())
-}
-
-struct AnalysisResults<'a> {
-    compression_stats: &'a HashMap<String, usize>,
-    extension_stats: &'a HashMap<String, HashMap<String, usize>>,
-    unsupported_files: &'a [(String, String)],
-    by_extension: bool,
+fn support_indicator(supported: bool) -> colored::ColoredString {
+    if supported {
+        "✓".green()
+    } else {
+        "✗".red()
+    }
+}
+
+fn percentage(part: u64, whole: u64) -> u32 {
+    if whole > 0 {
+        (part as f64 / whole as f64 * 100.0) as u32
+    } else {
+        100
+    }
+}
+
+fn print_text_file_details(
+    analysis: &CompressionAnalysis,
     unsupported_only: bool,
     show_stats: bool,
-    total_compressed_size: u64,
-    total_uncompressed_size: u64,
-    total_files: usize,
+) {
+    for file in &analysis.files {
+        let supported = file.method.is_supported();
+        if unsupported_only && supported {
+            continue;
+        }
+
+        let method_name = file.method.to_string();
+        if show_stats {
+            println!(
+                "{} {} {} ({} -> {} bytes, {}%)",
+                support_indicator(supported),
+                file.name,
+                method_name.cyan(),
+                file.file_size,
+                file.compressed_size,
+                percentage(file.compressed_size, file.file_size)
+            );
+        } else {
+            println!(
+                "{} {} {}",
+                support_indicator(supported),
+                file.name,
+                method_name.cyan()
+            );
+        }
+    }
 }
 
-fn print_text_analysis_results(results: &AnalysisResults) {
-    if !results.unsupported_only {
+fn print_text_analysis_results(
+    analysis: &CompressionAnalysis,
+    by_extension: bool,
+    unsupported_only: bool,
+    show_stats: bool,
+) {
+    let total_files = analysis.files.len() as u64;
+
+    if !unsupported_only {
         println!("\n{}", "Compression Method Summary".bold());
         println!("{}", "=".repeat(60));
 
-        let mut sorted_methods: Vec<_> = results.compression_stats.iter().collect();
-        sorted_methods.sort_by(|a, b| b.1.cmp(a.1)); // Sort by count descending
-
-        for (method_name, count) in sorted_methods {
-            let supported = is_compression_method_supported(method_name);
-            let support_indicator = if supported {
-                "✓".green()
-            } else {
-                "✗".red()
-            };
-            let percentage = (*count as f64 / results.total_files as f64 * 100.0) as u32;
+        for entry in &analysis.methods {
             println!(
                 "{} {:20} {:6} files ({:3}%)",
-                support_indicator, method_name, count, percentage
+                support_indicator(entry.method.is_supported()),
+                entry.method.to_string(),
+                entry.count,
+                percentage(entry.count as u64, total_files)
             );
         }
 
-        if results.show_stats {
+        if show_stats {
             println!("\n{}", "Archive Statistics".bold());
             println!("{}", "-".repeat(60));
-            println!("Total files:           {}", results.total_files);
+            println!("Total files:           {}", total_files);
             println!(
                 "Total uncompressed:    {} bytes",
-                results.total_uncompressed_size
+                analysis.total_uncompressed_size
             );
             println!(
                 "Total compressed:      {} bytes",
-                results.total_compressed_size
+                analysis.total_compressed_size
+            );
+            println!(
+                "Overall compression:   {}%",
+                analysis.compression_ratio() as u32
             );
-            let overall_ratio = if results.total_uncompressed_size > 0 {
-                (results.total_compressed_size as f64 / results.total_uncompressed_size as f64
-                    * 100.0) as u32
-            } else {
-                100
-            };
-            println!("Overall compression:   {}%", overall_ratio);
         }
     }
 
-    if results.by_extension && !results.extension_stats.is_empty() {
+    if by_extension && !analysis.by_extension.is_empty() {
         println!("\n{}", "Compression by File Extension".bold());
         println!("{}", "=".repeat(60));
 
-        let mut sorted_extensions: Vec<_> = results.extension_stats.iter().collect();
-        sorted_extensions.sort_by(|a, b| {
-            let a_total: usize = a.1.values().sum();
-            let b_total: usize = b.1.values().sum();
-            b_total.cmp(&a_total)
+        let mut sorted_extensions: Vec<_> = analysis.by_extension.iter().collect();
+        sorted_extensions.sort_by_key(|(_, methods)| {
+            std::cmp::Reverse(methods.iter().map(|entry| entry.count).sum::<usize>())
         });
 
         for (ext, methods) in sorted_extensions {
-            let total_for_ext: usize = methods.values().sum();
+            let total_for_ext: usize = methods.iter().map(|entry| entry.count).sum();
             println!("\n{} ({} files):", ext.cyan(), total_for_ext);
 
-            let mut sorted_methods: Vec<_> = methods.iter().collect();
-            sorted_methods.sort_by(|a, b| b.1.cmp(a.1));
-
-            for (method_name, count) in sorted_methods {
-                let supported = is_compression_method_supported(method_name);
-                let support_indicator = if supported {
-                    "✓".green()
-                } else {
-                    "✗".red()
-                };
-                let percentage = (*count as f64 / total_for_ext as f64 * 100.0) as u32;
+            for entry in methods {
                 println!(
                     "  {} {:18} {:4} files ({:3}%)",
-                    support_indicator, method_name, count, percentage
+                    support_indicator(entry.method.is_supported()),
+                    entry.method.to_string(),
+                    entry.count,
+                    percentage(entry.count as u64, total_for_ext as u64)
                 );
             }
         }
     }
 
-    if !results.unsupported_files.is_empty() {
+    let unsupported_methods = analysis.unsupported_methods();
+    if !unsupported_methods.is_empty() {
         println!("\n{}", "Unsupported Compression Methods".yellow().bold());
         println!("{}", "=".repeat(60));
 
-        let unsupported_count = results.unsupported_files.len();
-        let unsupported_percentage =
-            (unsupported_count as f64 / results.total_files as f64 * 100.0) as u32;
-
+        let unsupported_count: usize = unsupported_methods.iter().map(|entry| entry.count).sum();
         println!(
             "Found {} files ({:3}%) using unsupported compression methods:",
-            unsupported_count, unsupported_percentage
+            unsupported_count,
+            percentage(unsupported_count as u64, total_files)
         );
 
-        if results.unsupported_only {
-            for (filename, method_name) in results.unsupported_files {
-                println!("✗ {} {}", filename, method_name.red());
+        if unsupported_only {
+            for file in analysis.unsupported_files() {
+                println!("✗ {} {}", file.name, file.method.to_string().red());
             }
         }
 
-        // Group unsupported methods
-        let mut unsupported_method_counts = HashMap::new();
-        for (_, method_name) in results.unsupported_files {
-            *unsupported_method_counts.entry(method_name).or_insert(0) += 1;
-        }
-
         println!("\nUnsupported method breakdown:");
-        for (method_name, count) in &unsupported_method_counts {
-            println!("  {} - {} files", method_name.red(), count);
+        for entry in &unsupported_methods {
+            println!(
+                "  {} - {} files",
+                entry.method.to_string().red(),
+                entry.count
+            );
         }
-    } else if !results.unsupported_only {
+    } else if !unsupported_only {
         println!(
             "\n{} All compression methods in this archive are supported!",
             "✓".green()
@@ -920,68 +1565,148 @@ fn print_text_analysis_results(results: &AnalysisResults) {
     }
 }
 
-fn print_json_analysis_results(
-    compression_stats: &HashMap<String, usize>,
-    extension_stats: &HashMap<String, HashMap<String, usize>>,
-    unsupported_files: &[(String, String)],
-    total_compressed_size: u64,
-    total_uncompressed_size: u64,
-    total_files: usize,
-) -> Result<()> {
-    let compression_methods: HashMap<String, serde_json::Value> = compression_stats
+fn print_text_sector_suggestion(suggestion: &SectorSizeSuggestion) {
+    println!("\n{}", "Sector Size Suggestion".bold());
+    println!("{}", "=".repeat(60));
+
+    let share = |estimate: &SectorSizeEstimate| {
+        percentage(
+            estimate.single_sector_files as u64,
+            suggestion.file_count as u64,
+        )
+    };
+    let describe = |estimate: &SectorSizeEstimate| {
+        format!(
+            "{} sectors (block size {}): {}% of files in one sector, slack {}, offset tables {}",
+            format_size(estimate.sector_size),
+            estimate.block_size,
+            share(estimate),
+            format_size(estimate.slack_bytes),
+            format_size(estimate.offset_table_bytes)
+        )
+    };
+    let current = &suggestion.current;
+    let suggested = &suggestion.suggested;
+    println!("Current:    {}", describe(current));
+    println!("Suggested:  {}", describe(suggested).green());
+
+    if !suggestion.is_change() {
+        println!("\nThe current sector size is already the suggested one");
+        return;
+    }
+    if suggested.sector_size < current.sector_size {
+        println!(
+            "\n{}% of files are at most {}; the current {} sectors leave ~{} more unused",
+            share(suggested),
+            format_size(suggested.sector_size),
+            format_size(current.sector_size),
+            format_size(current.slack_bytes - suggested.slack_bytes)
+        );
+    } else {
+        println!(
+            "\nOnly {}% of files fit in one of the current {} sectors, {}% fit in {} sectors",
+            share(current),
+            format_size(current.sector_size),
+            share(suggested),
+            format_size(suggested.sector_size)
+        );
+    }
+    println!(
+        "Create the archive with --block-size {} to apply the suggestion",
+        suggested.block_size.to_string().cyan()
+    );
+}
+
+fn json_sector_suggestion(suggestion: &SectorSizeSuggestion) -> serde_json::Value {
+    let estimate = |estimate: &SectorSizeEstimate| {
+        serde_json::json!({
+            "block_size": estimate.block_size,
+            "sector_size": estimate.sector_size,
+            "single_sector_files": estimate.single_sector_files,
+            "offset_table_bytes": estimate.offset_table_bytes,
+            "slack_bytes": estimate.slack_bytes,
+        })
+    };
+    serde_json::json!({
+        "file_count": suggestion.file_count,
+        "current": estimate(&suggestion.current),
+        "suggested": estimate(&suggestion.suggested),
+        "candidates": suggestion.candidates.iter().map(estimate).collect::<Vec<_>>(),
+    })
+}
+
+fn json_analysis_results(
+    analysis: &CompressionAnalysis,
+    detailed: bool,
+    unsupported_only: bool,
+) -> serde_json::Value {
+    let total_files = analysis.files.len();
+
+    let compression_methods: HashMap<String, serde_json::Value> = analysis
+        .methods
         .iter()
-        .map(|(method_name, count)| {
-            let supported = is_compression_method_supported(method_name);
+        .map(|entry| {
             (
-                method_name.clone(),
+                entry.method.to_string(),
                 serde_json::json!({
-                    "count": count,
-                    "percentage": (*count as f64 / total_files as f64 * 100.0),
-                    "supported": supported
+                    "count": entry.count,
+                    "percentage": (entry.count as f64 / total_files as f64 * 100.0),
+                    "supported": entry.method.is_supported()
                 }),
             )
         })
         .collect();
 
-    let by_extension: HashMap<String, HashMap<String, usize>> = extension_stats
+    let by_extension: BTreeMap<&String, BTreeMap<String, usize>> = analysis
+        .by_extension
         .iter()
-        .map(|(ext, methods)| (ext.clone(), methods.clone()))
+        .map(|(ext, methods)| {
+            (
+                ext,
+                methods
+                    .iter()
+                    .map(|entry| (entry.method.to_string(), entry.count))
+                    .collect(),
+            )
+        })
         .collect();
 
-    let unsupported: Vec<serde_json::Value> = unsupported_files
-        .iter()
-        .map(|(filename, method_name)| {
+    let unsupported: Vec<serde_json::Value> = analysis
+        .unsupported_files()
+        .map(|file| {
             serde_json::json!({
-                "filename": filename,
-                "compression_method": method_name
+                "filename": file.name,
+                "compression_method": file.method.to_string()
             })
         })
         .collect();
 
-    let result = serde_json::json!({
+    let files: Option<Vec<serde_json::Value>> = detailed.then(|| {
+        analysis
+            .files
+            .iter()
+            .filter(|file| !unsupported_only || !file.method.is_supported())
+            .map(|file| {
+                serde_json::json!({
+                    "filename": file.name,
+                    "compression_method": file.method.to_string(),
+                    "supported": file.method.is_supported(),
+                    "size": file.file_size,
+                    "compressed_size": file.compressed_size,
+                })
+            })
+            .collect()
+    });
+
+    serde_json::json!({
         "total_files": total_files,
-        "total_compressed_size": total_compressed_size,
-        "total_uncompressed_size": total_uncompressed_size,
-        "overall_compression_ratio": if total_uncompressed_size > 0 {
-            total_compressed_size as f64 / total_uncompressed_size as f64 * 100.0
-        } else {
-            100.0
-        },
+        "total_compressed_size": analysis.total_compressed_size,
+        "total_uncompressed_size": analysis.total_uncompressed_size,
+        "overall_compression_ratio": analysis.compression_ratio(),
         "compression_methods": compression_methods,
         "by_extension": by_extension,
         "unsupported_files": unsupported,
-        "unsupported_count": unsupported_files.len()
-    });
-
-    print_json(&result)?;
-    Ok(())
-}
-
-fn print_csv_analysis_results(compression_stats: &HashMap<String, usize>) -> Result<()> {
-    println!("compression_method,file_count,supported");
-    for (method_name, count) in compression_stats {
-        let supported = is_compression_method_supported(method_name);
-        println!("{},{},{}", method_name, count, supported);
-    }
-    Ok(())
+        "unsupported_count": unsupported.len(),
+        "files": files,
+    })
 }
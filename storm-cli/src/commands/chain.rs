@@ -0,0 +1,98 @@
+//! Operations on patch chains of a game's data directory
+
+use anyhow::{Context, Result};
+use mopaq::pattern::Pattern;
+use mopaq::PatchChain;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::output::{print_chain_list, print_structured, ChainFileRecord};
+use crate::{OutputFormat, GLOBAL_OPTS};
+
+/// Extract the version of a file that the patch chain resolves to
+pub fn read(data_dir: &str, locale: &str, filename: &str, target: Option<&str>) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let chain = open_chain(data_dir, locale)?;
+    let (archive_path, _) = chain
+        .find_file(filename)?
+        .with_context(|| format!("{} is in none of the chain's archives", filename))?;
+    let archive = display_path(data_dir, archive_path);
+    let data = chain
+        .read_file(filename)
+        .context(format!("Failed to read file: {}", filename))?;
+
+    let output_path = match target {
+        Some(target) => PathBuf::from(target),
+        None => PathBuf::from(
+            Path::new(&filename.replace('\\', "/"))
+                .file_name()
+                .context("File name has no final component")?,
+        ),
+    };
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&output_path, data)?;
+
+    if global_opts.output != OutputFormat::Text {
+        let summary = serde_json::json!({
+            "file": filename,
+            "archive": archive,
+            "path": output_path,
+        });
+        print_structured(&summary, global_opts.output)?;
+    } else if !global_opts.quiet {
+        println!(
+            "Extracted: {} (from {}) -> {}",
+            filename,
+            archive,
+            output_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// List the files of a patch chain with the archive each is read from
+pub fn list(data_dir: &str, locale: &str, pattern: Option<&str>) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let mut chain = open_chain(data_dir, locale)?;
+    let mut entries = chain.list()?;
+    if let Some(pattern) = pattern {
+        let glob = Pattern::glob(pattern)?;
+        entries.retain(|entry| glob.is_match(&entry.name));
+    }
+
+    let records: Vec<ChainFileRecord> = entries
+        .into_iter()
+        .map(|entry| ChainFileRecord {
+            archive: display_path(data_dir, &entry.archive),
+            name: entry.name,
+            size: entry.size,
+        })
+        .collect();
+    print_chain_list(&records, chain.len(), global_opts.output)?;
+
+    Ok(())
+}
+
+/// Discover the patch chain of `data_dir`
+fn open_chain(data_dir: &str, locale: &str) -> Result<PatchChain> {
+    let chain = PatchChain::discover(data_dir, locale)
+        .with_context(|| format!("Failed to open the patch chain of {}", data_dir))?;
+    if chain.is_empty() {
+        anyhow::bail!("No MPQ archives found in {}", data_dir);
+    }
+    Ok(chain)
+}
+
+/// An archive path relative to the data directory it was found in
+fn display_path(data_dir: &str, archive: &Path) -> String {
+    archive
+        .strip_prefix(data_dir)
+        .unwrap_or(archive)
+        .display()
+        .to_string()
+}
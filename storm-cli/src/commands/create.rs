@@ -85,7 +85,7 @@ pub fn create(archive_path: &str, source: &str, options: CreateOptions) -> Resul
     println!(
         "  {}: {} bytes",
         "Sector size".bold(),
-        512 << options.block_size
+        mopaq::calculate_sector_size(options.block_size)
     );
     println!();
 
@@ -2,27 +2,50 @@
 
 use anyhow::{Context, Result};
 use colored::Colorize;
-use glob::Pattern;
-use mopaq::Archive;
-use regex::Regex;
+use mopaq::extract::{OutputDir, Placement};
+use mopaq::pattern::Pattern;
+use mopaq::special_files::ListfileFormat;
+use mopaq::{
+    AddFileOptions, Archive, CollisionPolicy, FileEntry, FileKind, Locale, MutableArchive,
+};
+use regex::RegexBuilder;
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
 use crate::output::{
     print_file_info, print_file_list, print_file_list_verbose, print_file_list_with_hashes,
+    print_file_list_with_kinds, print_grep_matches, print_structured, print_tree, GrepMatchRecord,
 };
-use crate::GLOBAL_OPTS;
+use crate::{text, OutputFormat, GLOBAL_OPTS};
+
+/// Which files `list` shows
+pub struct ListFilter<'a> {
+    /// Glob, or regex with `regex`, that names must match
+    pub pattern: Option<&'a str>,
+    pub regex: bool,
+    /// Content type that files must have
+    pub kind: Option<&'a str>,
+    /// Show special files such as (listfile) without --all as well
+    pub include_special: bool,
+}
 
 /// List files in an archive
 pub fn list(
     archive_path: &str,
     all: bool,
-    pattern: Option<&str>,
-    regex: bool,
+    filter: &ListFilter,
     show_hashes: bool,
+    detect: bool,
 ) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
+    let kind_filter = filter
+        .kind
+        .map(|kind| kind.parse::<FileKind>())
+        .transpose()
+        .context("Invalid --kind")?;
+
     let mut archive = Archive::open(archive_path)?;
 
     // Use list_all() when --all is specified to enumerate all table entries
@@ -42,30 +65,45 @@ pub fn list(
 
     let mut file_entries = file_entries;
 
+    // Special files are part of the table entries --all shows
+    if !all && !filter.include_special {
+        file_entries.retain(|e| e.special_file().is_none());
+    }
+
     // Apply pattern filter if provided
-    if let Some(pat) = pattern {
-        if regex {
-            let re = Regex::new(pat).context("Invalid regex pattern")?;
-            file_entries.retain(|e| re.is_match(&e.name));
-        } else {
-            let glob = Pattern::new(pat).context("Invalid glob pattern")?;
-            file_entries.retain(|e| glob.matches(&e.name));
-        }
+    if let Some(pat) = filter.pattern {
+        let pattern = compile_pattern(pat, filter.regex)?;
+        file_entries.retain(|e| pattern.is_match(&e.name));
     }
 
     // Sort files by name
     file_entries.sort_by(|a, b| a.name.cmp(&b.name));
 
+    if detect || kind_filter.is_some() {
+        // Files that cannot be read are listed as unknown rather than failing the listing
+        let mut detected: Vec<(FileEntry, FileKind)> = file_entries
+            .into_iter()
+            .map(|entry| {
+                let kind = archive.file_kind(&entry.name).unwrap_or(FileKind::Unknown);
+                (entry, kind)
+            })
+            .collect();
+        if let Some(wanted) = kind_filter {
+            detected.retain(|(_, kind)| *kind == wanted);
+        }
+        print_file_list_with_kinds(&detected, global_opts.output)?;
+        return Ok(());
+    }
+
     // In verbose mode, show detailed information
-    if global_opts.verbose > 0 && global_opts.output == crate::OutputFormat::Text {
+    if global_opts.verbose > 0 && global_opts.output == OutputFormat::Text {
         print_file_list_verbose(&file_entries)?;
     } else if show_hashes {
         // Show hashes in non-verbose mode
         print_file_list_with_hashes(&file_entries, global_opts.output)?;
     } else {
         // Normal mode - just show names
-        let files: Vec<String> = file_entries.into_iter().map(|e| e.name).collect();
-        print_file_list(&files, global_opts.output)?;
+        print_file_list(&file_entries, global_opts.output)?;
     }
 
     Ok(())
@@ -77,23 +115,73 @@ pub fn extract(
     file: Option<&str>,
     output: Option<&str>,
     preserve_path: bool,
+    locale: Option<Locale>,
+    on_collision: CollisionPolicy,
+    resume: bool,
 ) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
     let mut archive = Archive::open(archive_path)?;
+    let read = |archive: &Archive, filename: &str| match locale {
+        Some(locale) => archive.read_file_with_locale(filename, locale),
+        None => archive.read_file(filename),
+    };
+    // Whether the file at `path` already holds `filename`; with --resume a
+    // matching size is enough
+    let compare = resume || on_collision == CollisionPolicy::IfDifferent;
+    let unchanged = |archive: &Archive, filename: &str, path: &Path| -> Result<bool> {
+        let size = match locale {
+            Some(locale) => archive.find_file_with_locale(filename, locale)?,
+            None => archive.find_file(filename)?,
+        }
+        .map(|info| info.file_size);
+        if size != Some(fs::metadata(path)?.len()) {
+            return Ok(false);
+        }
+        Ok(resume || fs::read(path)? == read(archive, filename)?)
+    };
+    // Without --preserve-path, files go directly into the output directory
+    let output_name = |filename: &'_ str| -> String {
+        if preserve_path {
+            filename.to_string()
+        } else {
+            filename
+                .rsplit(['\\', '/'])
+                .next()
+                .unwrap_or(filename)
+                .to_string()
+        }
+    };
 
     if let Some(filename) = file {
         // Extract single file
-        let data = archive
-            .read_file(filename)
-            .context(format!("Failed to read file: {}", filename))?;
-
-        let output_path = if let Some(out) = output {
-            PathBuf::from(out)
-        } else if preserve_path {
-            PathBuf::from(filename)
-        } else {
-            PathBuf::from(Path::new(filename).file_name().unwrap())
+        let data =
+            read(&archive, filename).context(format!("Failed to read file: {}", filename))?;
+
+        let mut output_dir = OutputDir::new(".", on_collision);
+        let name = output_name(filename);
+        let existing = output_dir
+            .existing(&name)
+            .filter(|_| compare && output.is_none());
+        if let Some(existing) = existing {
+            if unchanged(&archive, filename, &existing)? {
+                if !global_opts.quiet {
+                    println!("Unchanged: {}", filename);
+                }
+                return Ok(());
+            }
+        }
+        let output_path = match output {
+            Some(out) => PathBuf::from(out),
+            None => match output_dir.place(&name)? {
+                Placement::Path(path) | Placement::Renamed(path) => path,
+                Placement::Skipped => {
+                    if !global_opts.quiet {
+                        println!("Skipped: {} (output file exists)", filename);
+                    }
+                    return Ok(());
+                }
+            },
         };
 
         // Create parent directories if needed
@@ -103,28 +191,80 @@ pub fn extract(
 
         fs::write(&output_path, data)?;
 
-        if !global_opts.quiet {
+        if global_opts.output != OutputFormat::Text {
+            let summary = serde_json::json!({
+                "extracted": [{ "file": filename, "path": output_path }],
+                "failed": [],
+            });
+            print_structured(&summary, global_opts.output)?;
+        } else if !global_opts.quiet {
             println!("Extracted: {} -> {}", filename, output_path.display());
         }
     } else {
         // Extract all files
-        let output_dir = output.unwrap_or(".");
+        let mut output_dir = OutputDir::new(output.unwrap_or("."), on_collision);
         let file_entries = archive.list()?;
         let files: Vec<String> = file_entries.into_iter().map(|e| e.name).collect();
+        let structured = global_opts.output != OutputFormat::Text;
+        let mut extracted = Vec::new();
+        let mut extracted_count = 0;
+        let mut skipped = Vec::new();
+        let mut unchanged_files = Vec::new();
+        let mut failed = Vec::new();
 
         for filename in &files {
-            let data = match archive.read_file(filename) {
-                Ok(data) => data,
+            // Files only stored for other locales have no version to extract
+            if let Some(locale) = locale {
+                if archive.find_file_with_locale(filename, locale)?.is_none() {
+                    continue;
+                }
+            }
+
+            let name = output_name(filename);
+            if let Some(existing) = output_dir.existing(&name).filter(|_| compare) {
+                match unchanged(&archive, filename, &existing) {
+                    Ok(true) => {
+                        if !structured && !global_opts.quiet {
+                            println!("Unchanged: {}", filename);
+                        }
+                        output_dir.keep(&existing);
+                        unchanged_files.push(filename.clone());
+                        continue;
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        eprintln!("Failed to extract {}: {}", filename, e);
+                        failed
+                            .push(serde_json::json!({ "file": filename, "error": e.to_string() }));
+                        continue;
+                    }
+                }
+            }
+
+            let (output_path, renamed) = match output_dir.place(&name) {
+                Ok(Placement::Path(path)) => (path, false),
+                Ok(Placement::Renamed(path)) => (path, true),
+                Ok(Placement::Skipped) => {
+                    if !structured && !global_opts.quiet {
+                        println!("Skipped: {} (output file exists)", filename);
+                    }
+                    skipped.push(filename.clone());
+                    continue;
+                }
                 Err(e) => {
                     eprintln!("Failed to extract {}: {}", filename, e);
+                    failed.push(serde_json::json!({ "file": filename, "error": e.to_string() }));
                     continue;
                 }
             };
 
-            let output_path = if preserve_path {
-                PathBuf::from(output_dir).join(filename)
-            } else {
-                PathBuf::from(output_dir).join(Path::new(filename).file_name().unwrap())
+            let data = match read(&archive, filename) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Failed to extract {}: {}", filename, e);
+                    failed.push(serde_json::json!({ "file": filename, "error": e.to_string() }));
+                    continue;
+                }
             };
 
             // Create parent directories if needed
@@ -133,80 +273,241 @@ pub fn extract(
             }
 
             fs::write(&output_path, data)?;
+            extracted_count += 1;
 
-            if !global_opts.quiet {
+            if structured {
+                extracted.push(serde_json::json!({ "file": filename, "path": output_path }));
+            } else if renamed && !global_opts.quiet {
+                println!("Extracted: {} -> {}", filename, output_path.display());
+            } else if !global_opts.quiet {
                 println!("Extracted: {}", filename);
             }
         }
 
-        if !global_opts.quiet {
-            println!("{} Extracted {} files", "✓".green(), files.len());
+        if structured {
+            let summary = serde_json::json!({
+                "extracted": extracted,
+                "skipped": skipped,
+                "unchanged": unchanged_files,
+                "failed": failed,
+            });
+            print_structured(&summary, global_opts.output)?;
+        } else if !global_opts.quiet {
+            println!("{} Extracted {} files", "✓".green(), extracted_count);
         }
     }
 
     Ok(())
 }
 
+/// Name an entry without a known name is extracted as
+pub fn block_file_name(block_index: usize) -> String {
+    format!("block_{:05}.bin", block_index)
+}
+
+/// Extract the file at a block index, whether or not its name is known
+pub fn extract_index(archive_path: &str, block_index: usize, output: Option<&str>) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let archive = Archive::open(archive_path)?;
+    let data = archive
+        .read_file_by_index(block_index)
+        .context(format!("Failed to read block {}", block_index))?;
+
+    let output_path = match output {
+        Some(out) => PathBuf::from(out),
+        None => PathBuf::from(block_file_name(block_index)),
+    };
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&output_path, data)?;
+
+    if global_opts.output != OutputFormat::Text {
+        let summary = serde_json::json!({
+            "extracted": [{ "block_index": block_index, "path": output_path }],
+            "failed": [],
+        });
+        print_structured(&summary, global_opts.output)?;
+    } else if !global_opts.quiet {
+        println!(
+            "Extracted: block {} -> {}",
+            block_index,
+            output_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Extract every file the listfile does not name, as `block_NNNNN.bin`
+pub fn extract_anonymous(archive_path: &str, output: Option<&str>) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let archive = Archive::open(archive_path)?;
+    let entries = archive.anonymous_entries()?;
+    let output_dir = PathBuf::from(output.unwrap_or("."));
+    fs::create_dir_all(&output_dir)?;
+
+    let structured = global_opts.output != OutputFormat::Text;
+    let mut extracted = Vec::new();
+    let mut failed = Vec::new();
+
+    for entry in &entries {
+        let data = match archive.read_file_by_index(entry.block_index) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to extract block {}: {}", entry.block_index, e);
+                failed.push(
+                    serde_json::json!({ "block_index": entry.block_index, "error": e.to_string() }),
+                );
+                continue;
+            }
+        };
+
+        let output_path = output_dir.join(block_file_name(entry.block_index));
+        fs::write(&output_path, data)?;
+
+        if structured {
+            extracted
+                .push(serde_json::json!({ "block_index": entry.block_index, "path": output_path }));
+        } else if !global_opts.quiet {
+            println!("Extracted: {}", output_path.display());
+        }
+    }
+
+    if structured {
+        let summary = serde_json::json!({ "extracted": extracted, "failed": failed });
+        print_structured(&summary, global_opts.output)?;
+    } else if !global_opts.quiet {
+        println!(
+            "{} Extracted {} of {} anonymous files",
+            "✓".green(),
+            entries.len() - failed.len(),
+            entries.len()
+        );
+    }
+
+    Ok(())
+}
+
 /// Add files to an existing archive
+///
+/// The files are appended in place. A file already stored under the same
+/// name and locale is replaced; its versions in other locales are kept.
 pub fn add(
-    _archive_path: &str,
-    _files: &[String],
-    _compression: Option<u16>,
-    _path: Option<&str>,
+    archive_path: &str,
+    files: &[String],
+    compression: Option<u16>,
+    path: Option<&str>,
+    locale: Locale,
 ) -> Result<()> {
-    // TODO: Implement file addition to existing archives
-    anyhow::bail!("Adding files to existing archives is not yet implemented");
-}
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
-/// Remove files from an archive
-pub fn remove(_archive_path: &str, _files: &[String]) -> Result<()> {
-    // TODO: Implement file removal from archives
-    anyhow::bail!("Removing files from archives is not yet implemented");
+    if path.is_some() && files.len() > 1 {
+        anyhow::bail!("--path can only be used when adding a single file");
+    }
+
+    let mut archive = MutableArchive::open(archive_path)?;
+    let options = AddFileOptions::new()
+        .compression(compression.map_or(mopaq::compression::flags::ZLIB, |c| c as u8))
+        .locale(locale);
+
+    for file in files {
+        let source = Path::new(file);
+        if !source.is_file() {
+            anyhow::bail!("Source file does not exist: {}", file);
+        }
+        let name = match path {
+            Some(path) => path.to_string(),
+            None => source
+                .file_name()
+                .context("Invalid file name")?
+                .to_string_lossy()
+                .to_string(),
+        };
+
+        archive.add_file(source, &name, &options)?;
+        if !global_opts.quiet {
+            println!("Added: {} ({})", name, locale);
+        }
+    }
+
+    archive.flush()?;
+    Ok(())
 }
 
-/// Find files in an archive
-pub fn find(archive_path: &str, pattern: &str, regex: bool, ignore_case: bool) -> Result<()> {
+/// Remove files from an archive
+///
+/// The files are removed in place; their data stays behind as unused space.
+/// With a `locale`, only the version stored for it is removed.
+pub fn remove(archive_path: &str, files: &[String], locale: Option<Locale>) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
-    let mut archive = Archive::open(archive_path)?;
-    let file_entries = archive.list()?;
-    let files: Vec<String> = file_entries.into_iter().map(|e| e.name).collect();
+    {
+        let archive = Archive::open(archive_path)?;
+        for file in files {
+            let locales = archive.file_locales(file)?;
+            match locale {
+                Some(locale) if !locales.contains(&locale) => {
+                    anyhow::bail!("File not found: {} ({})", file, locale)
+                }
+                None if locales.is_empty() => anyhow::bail!("File not found: {}", file),
+                _ => {}
+            }
+        }
+    }
 
-    let matches: Vec<String> = if regex {
-        let re = if ignore_case {
-            Regex::new(&format!("(?i){}", pattern))
-        } else {
-            Regex::new(pattern)
+    let mut archive = MutableArchive::open(archive_path)?;
+    for file in files {
+        match locale {
+            Some(locale) => archive.remove_file_with_locale(file, locale)?,
+            None => archive.remove_file(file)?,
         }
-        .context("Invalid regex pattern")?;
+        if !global_opts.quiet {
+            match locale {
+                Some(locale) => println!("Removed: {} ({})", file, locale),
+                None => println!("Removed: {}", file),
+            }
+        }
+    }
+
+    archive.flush()?;
+    Ok(())
+}
 
-        files.into_iter().filter(|f| re.is_match(f)).collect()
+/// Compile a name pattern the way the library matches names
+///
+/// Matching ignores case and treats `/` and `\` alike, as archive lookups do.
+fn compile_pattern(pattern: &str, regex: bool) -> Result<Pattern> {
+    Ok(if regex {
+        Pattern::regex(pattern)?
     } else {
-        let glob = if ignore_case {
-            Pattern::new(&pattern.to_lowercase()).context("Invalid glob pattern")?
-        } else {
-            Pattern::new(pattern).context("Invalid glob pattern")?
-        };
+        Pattern::glob(pattern)?
+    })
+}
 
-        files
-            .into_iter()
-            .filter(|f| {
-                if ignore_case {
-                    glob.matches(&f.to_lowercase())
-                } else {
-                    glob.matches(f)
-                }
-            })
-            .collect()
-    };
+/// Find files in an archive
+pub fn find(archive_path: &str, pattern: &str, regex: bool) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
-    if matches.is_empty() {
+    let mut archive = Archive::open(archive_path)?;
+    let compiled = compile_pattern(pattern, regex)?;
+    let matches: Vec<FileEntry> = archive
+        .list()?
+        .into_iter()
+        .filter(|f| compiled.is_match(&f.name))
+        .collect();
+
+    if global_opts.output != OutputFormat::Text {
+        print_file_list(&matches, global_opts.output)?;
+    } else if matches.is_empty() {
         if !global_opts.quiet {
             println!("No files found matching pattern: {}", pattern);
         }
     } else {
         for file in &matches {
-            println!("{}", file);
+            println!("{}", file.name);
         }
         if !global_opts.quiet && global_opts.verbose > 0 {
             println!("\nFound {} matching files", matches.len());
@@ -216,19 +517,340 @@ pub fn find(archive_path: &str, pattern: &str, regex: bool, ignore_case: bool) -
     Ok(())
 }
 
-/// Show detailed file information
-pub fn info(archive_path: &str, filename: &str) -> Result<()> {
+/// Search the contents of an archive's files for a regular expression
+///
+/// Files are scanned line by line as they are decompressed, so large
+/// archives are searched without extracting them. Files that look binary
+/// are skipped, as are the archive's special files.
+pub fn grep(
+    archive_path: &str,
+    pattern: &str,
+    glob: Option<&str>,
+    ignore_case: bool,
+) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
+    let re = RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .context("Invalid regex pattern")?;
+    let glob = glob.map(Pattern::glob).transpose()?;
+
     let mut archive = Archive::open(archive_path)?;
+    let mut names: Vec<String> = archive
+        .list()?
+        .into_iter()
+        .map(|entry| entry.name)
+        .filter(|name| !name.starts_with('('))
+        .filter(|name| glob.as_ref().is_none_or(|glob| glob.is_match(name)))
+        .collect();
+    names.sort();
+
+    let mut matches = Vec::new();
+    for name in &names {
+        let result = archive.scan_lines(name, |number, line| {
+            if line.contains(&0) {
+                // Binary data; stop scanning this file
+                return false;
+            }
+            let text = String::from_utf8_lossy(line);
+            if re.is_match(&text) {
+                matches.push(GrepMatchRecord {
+                    file: name.clone(),
+                    line: number,
+                    text: text.into_owned(),
+                });
+            }
+            true
+        });
+        if let Err(e) = result {
+            if !global_opts.quiet {
+                eprintln!("{} {}: {}", "Warning:".yellow(), name, e);
+            }
+        }
+    }
+
+    print_grep_matches(&matches, names.len(), global_opts.quiet, global_opts.output)?;
+
+    Ok(())
+}
+
+/// Show detailed file information
+pub fn info(archive_path: &str, filename: &str, locale: Option<Locale>) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let archive = Archive::open(archive_path)?;
+
+    let (file_info, data) = match locale {
+        Some(locale) => (
+            archive.find_file_with_locale(filename, locale)?,
+            archive.read_file_with_locale(filename, locale),
+        ),
+        None => (archive.find_file(filename)?, archive.read_file(filename)),
+    };
+    let file_info = file_info.context(format!("File not found: {}", filename))?;
+    let kind =
+        mopaq::detect::file_kind(&data.context(format!("Failed to read file: {}", filename))?);
+    let locales = archive.file_locales(filename)?;
+
+    print_file_info(&file_info, kind, &locales, archive_path, global_opts.output)?;
+
+    Ok(())
+}
+
+/// Write a file to stdout
+///
+/// Text is decoded from `encoding`, or a detected encoding when `None`, and
+/// written as UTF-8. With `raw` the stored bytes are written unchanged.
+pub fn cat(
+    archive_path: &str,
+    filename: &str,
+    encoding: Option<text::Encoding>,
+    raw: bool,
+) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let archive = Archive::open(archive_path)?;
+    let data = archive
+        .read_file(filename)
+        .context(format!("Failed to read file: {}", filename))?;
+
+    let (output, encoding) = if raw {
+        (data, None)
+    } else {
+        let detected = encoding.unwrap_or_else(|| text::detect(&data));
+        let decoded = text::decode(&data, detected);
+        if encoding.is_none() && text::looks_binary(&decoded) {
+            anyhow::bail!(
+                "{} looks like binary data; use --raw to write it unchanged",
+                filename
+            );
+        }
+        (decoded.into_bytes(), Some(detected))
+    };
+
+    if let (Some(encoding), OutputFormat::Json | OutputFormat::Jsonl) =
+        (encoding, global_opts.output)
+    {
+        let record = serde_json::json!({
+            "archive": archive_path,
+            "file": filename,
+            "encoding": encoding.to_string(),
+            "text": String::from_utf8_lossy(&output),
+        });
+        print_structured(&record, global_opts.output)?;
+        return Ok(());
+    }
+
+    // A closed pipe (e.g. `| head`) just means the reader has seen enough
+    match io::stdout().lock().write_all(&output) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Preview the header and first rows of a DBC/DB2 file
+#[cfg(feature = "dbc")]
+pub fn show(archive_path: &str, filename: &str, rows: usize) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
-    // Get file info by reading the file
-    let file_data = archive
+    let archive = Archive::open(archive_path)?;
+    let data = archive
         .read_file(filename)
-        .context(format!("File not found: {}", filename))?;
-    let file_size = file_data.len() as u64;
+        .context(format!("Failed to read file: {}", filename))?;
+    let preview = crate::dbc::DbcPreview::parse(&data, rows)
+        .with_context(|| format!("Cannot preview {}", filename))?;
+
+    crate::output::print_dbc_preview(&preview, filename, global_opts.output)?;
+
+    Ok(())
+}
+
+/// Check whether an archived file matches a file on disk
+///
+/// Exits with an error when the contents differ, so scripts can use this to
+/// validate a deployed patch.
+pub fn compare(archive_path: &str, filename: &str, disk_path: &str) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
-    print_file_info(filename, file_size, global_opts.output)?;
+    let archive = Archive::open(archive_path)?;
+    let disk_file =
+        fs::File::open(disk_path).with_context(|| format!("Cannot open {}", disk_path))?;
+    let disk_size = disk_file.metadata()?.len();
+    let archive_size = archive
+        .find_file(filename)?
+        .context(format!("File not found: {}", filename))?
+        .file_size;
+
+    let matches = archive.file_matches(filename, io::BufReader::new(disk_file))?;
+
+    match global_opts.output {
+        OutputFormat::Text => {
+            if matches {
+                println!("{} {} matches {}", "✓".green(), filename, disk_path);
+            } else {
+                println!("{} {} differs from {}", "✗".red(), filename, disk_path);
+                if !global_opts.quiet {
+                    println!("  Archive size: {} bytes", archive_size);
+                    println!("  Disk size:    {} bytes", disk_size);
+                }
+            }
+        }
+        OutputFormat::Csv => {
+            println!("file,disk_path,archive_size,disk_size,matches");
+            println!(
+                "{},{},{},{},{}",
+                filename, disk_path, archive_size, disk_size, matches
+            );
+        }
+        format => {
+            let record = serde_json::json!({
+                "archive": archive_path,
+                "file": filename,
+                "disk_path": disk_path,
+                "archive_size": archive_size,
+                "disk_size": disk_size,
+                "matches": matches,
+            });
+            print_structured(&record, format)?;
+        }
+    }
+
+    if !matches {
+        anyhow::bail!("Contents differ");
+    }
+
+    Ok(())
+}
+
+/// Show the archive's files as a directory tree
+pub fn tree(archive_path: &str, depth: Option<usize>, dirs_only: bool, sizes: bool) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let mut archive = Archive::open(archive_path)?;
+    let root = archive.tree()?;
+    print_tree(&root, depth, dirs_only, sizes, global_opts.output)?;
+
+    Ok(())
+}
+
+/// Add and remove (listfile) entries in place
+pub fn touch_listfile(archive_path: &str, add: &[String], remove: &[String]) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    if add.is_empty() && remove.is_empty() {
+        anyhow::bail!("Nothing to do: pass --add and/or --remove");
+    }
+
+    let mut archive = MutableArchive::open(archive_path)?;
+    let before = archive.listfile_names()?.len();
+
+    let add: Vec<&str> = add.iter().map(String::as_str).collect();
+    let remove: Vec<&str> = remove.iter().map(String::as_str).collect();
+    archive.update_listfile(&add, &remove)?;
+    archive.flush()?;
+
+    let after = archive.listfile_names()?.len();
+
+    match global_opts.output {
+        OutputFormat::Text => {
+            if !global_opts.quiet {
+                println!(
+                    "{} Updated (listfile): {} -> {} entries",
+                    "✓".green(),
+                    before,
+                    after
+                );
+            }
+        }
+        OutputFormat::Csv => {
+            println!("archive,entries_before,entries_after");
+            println!("{},{},{}", archive_path, before, after);
+        }
+        format => {
+            let record = serde_json::json!({
+                "archive": archive_path,
+                "entries_before": before,
+                "entries_after": after,
+            });
+            print_structured(&record, format)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write an archive's file names to a listfile
+pub fn export_listfile(
+    archive_path: &str,
+    listfile_path: &str,
+    format: ListfileFormat,
+) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let mut archive = Archive::open(archive_path)?;
+    let file = fs::File::create(listfile_path)
+        .with_context(|| format!("Cannot create {}", listfile_path))?;
+    let count = archive.export_listfile(io::BufWriter::new(file), format)?;
+
+    match global_opts.output {
+        OutputFormat::Text => {
+            if !global_opts.quiet {
+                println!("{} Wrote {} names to {}", "✓".green(), count, listfile_path);
+            }
+        }
+        OutputFormat::Csv => {
+            println!("archive,listfile,names");
+            println!("{},{},{}", archive_path, listfile_path, count);
+        }
+        format => {
+            let record = serde_json::json!({
+                "archive": archive_path,
+                "listfile": listfile_path,
+                "names": count,
+            });
+            print_structured(&record, format)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Add the names of an external listfile that exist in an archive to its (listfile)
+pub fn import_listfile(archive_path: &str, listfile_path: &str) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let data = fs::read(listfile_path).with_context(|| format!("Cannot read {}", listfile_path))?;
+    let mut archive = MutableArchive::open(archive_path)?;
+    let added = archive.import_listfile(&data)?;
+    archive.flush()?;
+    let entries = archive.listfile_names()?.len();
+
+    match global_opts.output {
+        OutputFormat::Text => {
+            if !global_opts.quiet {
+                println!(
+                    "{} Added {} names to (listfile), now {} entries",
+                    "✓".green(),
+                    added,
+                    entries
+                );
+            }
+        }
+        OutputFormat::Csv => {
+            println!("archive,listfile,added,entries");
+            println!("{},{},{},{}", archive_path, listfile_path, added, entries);
+        }
+        format => {
+            let record = serde_json::json!({
+                "archive": archive_path,
+                "listfile": listfile_path,
+                "added": added,
+                "entries": entries,
+            });
+            print_structured(&record, format)?;
+        }
+    }
 
     Ok(())
 }
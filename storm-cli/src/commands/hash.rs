@@ -1,11 +1,67 @@
 //! Hash generation and comparison utilities
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use colored::Colorize;
-use mopaq::crypto::{hash_string, hash_type};
+use mopaq::crypto::{hash_string, hash_type, PrecomputedName};
+use mopaq::special_files::parse_listfile;
+use mopaq::Archive;
+use serde::Serialize;
+use std::path::Path;
 
-use crate::HashType;
+use crate::output::{csv_field, format_hash, print_records, print_structured, HashEntryRecord};
 use crate::GLOBAL_OPTS;
+use crate::{HashType, OutputFormat};
+
+/// All MPQ hash values for one filename
+#[derive(Debug, Serialize)]
+struct HashSet {
+    filename: String,
+    table_offset: String,
+    name_a: String,
+    name_b: String,
+    file_key: String,
+    key2_mix: String,
+}
+
+impl HashSet {
+    fn new(filename: &str) -> Self {
+        Self {
+            filename: filename.to_string(),
+            table_offset: format_hash(hash_string(filename, hash_type::TABLE_OFFSET)),
+            name_a: format_hash(hash_string(filename, hash_type::NAME_A)),
+            name_b: format_hash(hash_string(filename, hash_type::NAME_B)),
+            file_key: format_hash(hash_string(filename, hash_type::FILE_KEY)),
+            key2_mix: format_hash(hash_string(filename, hash_type::KEY2_MIX)),
+        }
+    }
+}
+
+/// Where a name's hashes were found in an archive
+#[derive(Debug, Serialize)]
+struct HashLookup {
+    archive: String,
+    filename: String,
+    table_offset: String,
+    name_a: String,
+    name_b: String,
+    /// Slot the lookup starts probing at, if there is a hash table
+    home_slot: Option<usize>,
+    /// Slots holding the name's hash pair
+    slots: Vec<HashEntryRecord>,
+    /// BET index found through the HET table, if there is one
+    het_index: Option<u32>,
+    found: bool,
+}
+
+/// One hash type compared between two filenames
+#[derive(Debug, Serialize)]
+struct HashComparison {
+    #[serde(rename = "type")]
+    hash_type: &'static str,
+    value1: String,
+    value2: String,
+    matches: bool,
+}
 
 /// Generate hash values for a filename
 pub fn generate(filename: &str, hash_type: Option<HashType>, all: bool) -> Result<()> {
@@ -13,27 +69,31 @@ pub fn generate(filename: &str, hash_type: Option<HashType>, all: bool) -> Resul
 
     if all || hash_type.is_none() {
         // Generate all hash types
-        println!("{}", "Hash values:".bold());
-        println!(
-            "  Table offset: {:#010x}",
-            hash_string(filename, hash_type::TABLE_OFFSET)
-        );
-        println!(
-            "  Name A:       {:#010x}",
-            hash_string(filename, hash_type::NAME_A)
-        );
-        println!(
-            "  Name B:       {:#010x}",
-            hash_string(filename, hash_type::NAME_B)
-        );
-        println!(
-            "  File key:     {:#010x}",
-            hash_string(filename, hash_type::FILE_KEY)
-        );
-        println!(
-            "  Key2 mix:     {:#010x}",
-            hash_string(filename, hash_type::KEY2_MIX)
-        );
+        if global_opts.output == OutputFormat::Text {
+            println!("{}", "Hash values:".bold());
+            println!(
+                "  Table offset: {:#010x}",
+                hash_string(filename, hash_type::TABLE_OFFSET)
+            );
+            println!(
+                "  Name A:       {:#010x}",
+                hash_string(filename, hash_type::NAME_A)
+            );
+            println!(
+                "  Name B:       {:#010x}",
+                hash_string(filename, hash_type::NAME_B)
+            );
+            println!(
+                "  File key:     {:#010x}",
+                hash_string(filename, hash_type::FILE_KEY)
+            );
+            println!(
+                "  Key2 mix:     {:#010x}",
+                hash_string(filename, hash_type::KEY2_MIX)
+            );
+        } else {
+            print_structured(&HashSet::new(filename), global_opts.output)?;
+        }
     } else if let Some(ht) = hash_type {
         let hash_type_id = match ht {
             HashType::TableOffset => hash_type::TABLE_OFFSET,
@@ -45,13 +105,141 @@ pub fn generate(filename: &str, hash_type: Option<HashType>, all: bool) -> Resul
 
         let value = hash_string(filename, hash_type_id);
 
-        if global_opts.output == crate::OutputFormat::Json {
+        if global_opts.output == OutputFormat::Text {
+            println!("{:#010x}", value);
+        } else {
+            let record = serde_json::json!({
+                "filename": filename,
+                "type": format!("{:?}", ht),
+                "value": format_hash(value),
+            });
+            print_structured(&record, global_opts.output)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Generate all hash values for every name in a listfile
+pub fn generate_from_file(path: &Path) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let hashes: Vec<HashSet> = parse_listfile(&data)?
+        .iter()
+        .map(|name| HashSet::new(name))
+        .collect();
+
+    match global_opts.output {
+        OutputFormat::Text => {
             println!(
-                r#"{{"filename":"{}","type":"{:?}","value":"0x{:08x}"}}"#,
-                filename, ht, value
+                "{:<10} {:<10} {:<10} {:<10} {:<10} {}",
+                "Offset".bold(),
+                "Name A".bold(),
+                "Name B".bold(),
+                "File key".bold(),
+                "Key2 mix".bold(),
+                "Filename".bold()
+            );
+            for set in &hashes {
+                println!(
+                    "{} {} {} {} {} {}",
+                    set.table_offset,
+                    set.name_a,
+                    set.name_b,
+                    set.file_key,
+                    set.key2_mix,
+                    set.filename
+                );
+            }
+        }
+        OutputFormat::Csv => {
+            println!("filename,table_offset,name_a,name_b,file_key,key2_mix");
+            for set in &hashes {
+                println!(
+                    "{},{},{},{},{},{}",
+                    csv_field(&set.filename),
+                    set.table_offset,
+                    set.name_a,
+                    set.name_b,
+                    set.file_key,
+                    set.key2_mix
+                );
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            print_records(&hashes, global_opts.output)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Report whether a name's hashes occupy a slot in an archive
+///
+/// Only the hashes are compared, so this also finds names that collide
+/// with a stored file, which is what name recovery needs to know.
+pub fn lookup(archive_path: &str, filename: &str) -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let archive = Archive::open(archive_path)?;
+    let name = PrecomputedName::with_policy(filename, archive.name_hashing());
+
+    let (home_slot, slots) = match archive.hash_table() {
+        Some(table) => (
+            Some(name.table_offset() as usize & (table.size() - 1)),
+            table
+                .find_all_pre(&name)
+                .into_iter()
+                .map(|(index, entry)| HashEntryRecord::new(index, entry))
+                .collect(),
+        ),
+        None => (None, Vec::new()),
+    };
+    let het_index = match (archive.het_table(), archive.bet_table()) {
+        (Some(het), Some(bet)) => het.find_file_pre(&name, bet),
+        _ => None,
+    };
+
+    let result = HashLookup {
+        archive: archive_path.to_string(),
+        filename: filename.to_string(),
+        table_offset: format_hash(name.table_offset()),
+        name_a: format_hash(name.name_a()),
+        name_b: format_hash(name.name_b()),
+        home_slot,
+        found: !slots.is_empty() || het_index.is_some(),
+        slots,
+        het_index,
+    };
+
+    if global_opts.output != OutputFormat::Text {
+        print_structured(&result, global_opts.output)?;
+        return Ok(());
+    }
+
+    println!("{} {}", "Filename:".bold(), result.filename);
+    println!("  Name A:    {}", result.name_a);
+    println!("  Name B:    {}", result.name_b);
+    match (result.home_slot, archive.hash_table()) {
+        (Some(slot), Some(table)) => println!("  Home slot: {} of {}", slot, table.size()),
+        _ => println!("  Home slot: no hash table"),
+    }
+    if let Some(index) = result.het_index {
+        println!("  HET table: BET index {}", index);
+    }
+
+    if result.slots.is_empty() {
+        if archive.hash_table().is_some() {
+            println!("{}", "Not in the hash table".yellow());
+        }
+    } else {
+        println!("{}", "Found in the hash table:".green());
+        for slot in &result.slots {
+            println!(
+                "  Slot {}: locale 0x{:04x}, platform {}, block {}",
+                slot.index, slot.locale, slot.platform, slot.block_index
             );
-        } else {
-            println!("{:#010x}", value);
         }
     }
 
@@ -62,20 +250,43 @@ pub fn generate(filename: &str, hash_type: Option<HashType>, all: bool) -> Resul
 pub fn compare(filename1: &str, filename2: &str) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
+    let types = [
+        ("Table offset", hash_type::TABLE_OFFSET),
+        ("Name A", hash_type::NAME_A),
+        ("Name B", hash_type::NAME_B),
+        ("File key", hash_type::FILE_KEY),
+        ("Key2 mix", hash_type::KEY2_MIX),
+    ];
+
+    if global_opts.output != OutputFormat::Text {
+        let hashes: Vec<HashComparison> = types
+            .iter()
+            .map(|&(name, hash_type)| {
+                let hash1 = hash_string(filename1, hash_type);
+                let hash2 = hash_string(filename2, hash_type);
+                HashComparison {
+                    hash_type: name,
+                    value1: format_hash(hash1),
+                    value2: format_hash(hash2),
+                    matches: hash1 == hash2,
+                }
+            })
+            .collect();
+        let record = serde_json::json!({
+            "filename1": filename1,
+            "filename2": filename2,
+            "hashes": hashes,
+        });
+        print_structured(&record, global_opts.output)?;
+        return Ok(());
+    }
+
     println!("{}", "Hash comparison:".bold());
     println!("\nFilenames:");
     println!("  1: {}", filename1);
     println!("  2: {}", filename2);
     println!();
 
-    let types = [
-        ("Table offset", 0),
-        ("Name A", 1),
-        ("Name B", 2),
-        ("File key", 3),
-        ("Key2 mix", 4),
-    ];
-
     for (name, hash_type) in types {
         let hash1 = hash_string(filename1, hash_type);
         let hash2 = hash_string(filename2, hash_type);
@@ -102,13 +313,19 @@ pub fn compare(filename1: &str, filename2: &str) -> Result<()> {
 }
 
 /// Generate Jenkins hash (for HET tables)
-pub fn jenkins(_filename: &str) -> Result<()> {
+pub fn jenkins(filename: &str) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
-    // TODO: Implement Jenkins hash when HET support is added
-    if !global_opts.quiet {
-        println!("Jenkins hash generation not yet implemented");
-        println!("This will be available when HET table support is added");
+    let hash = mopaq::jenkins_hash(filename);
+
+    if global_opts.output == OutputFormat::Text {
+        println!("{} {:#018x}", "Jenkins hash:".bold(), hash);
+    } else {
+        let record = serde_json::json!({
+            "filename": filename,
+            "jenkins": format!("0x{:016x}", hash),
+        });
+        print_structured(&record, global_opts.output)?;
     }
 
     Ok(())
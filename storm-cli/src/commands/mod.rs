@@ -1,7 +1,9 @@
 //! CLI command implementations
 
 pub mod archive;
+pub mod chain;
 pub mod crypto;
 pub mod file;
 pub mod hash;
+pub mod selftest;
 pub mod table;
@@ -0,0 +1,326 @@
+//! Built-in checks of the codecs, hashing, encryption and archive I/O
+//!
+//! `storm-cli selftest` exercises the library the binary was built with, so
+//! packagers can confirm that a build with a given set of cargo features
+//! actually works before shipping it.
+
+use anyhow::Result;
+use colored::*;
+use mopaq::compression::flags;
+use mopaq::crypto::{decrypt_block, encrypt_block, hash_string, hash_type};
+use mopaq::{Archive, ArchiveBuilder, FormatVersion};
+use serde::Serialize;
+
+use crate::exit::CheckFailed;
+use crate::output::{csv_field, print_records};
+use crate::{OutputFormat, GLOBAL_OPTS};
+
+/// Size of the codec sample: one sector at the default block size
+const SAMPLE_SIZE: usize = 4096;
+
+/// Largest sample difference accepted from the lossy ADPCM codec
+const ADPCM_TOLERANCE: i32 = 256;
+
+/// Known hash values shared with StormLib
+const HASH_VECTORS: [(&str, u32, u32); 5] = [
+    ("(listfile)", hash_type::TABLE_OFFSET, 0x5F3D_E859),
+    ("(hash table)", hash_type::FILE_KEY, 0xC3AF_3770),
+    ("(block table)", hash_type::FILE_KEY, 0xEC83_B3A3),
+    ("file.txt", hash_type::TABLE_OFFSET, 0x3EA9_8D7A),
+    ("path\\to\\file", hash_type::TABLE_OFFSET, 0x534C_C8EE),
+];
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Status::Pass => "pass",
+            Status::Fail => "fail",
+            Status::Skip => "skip",
+        })
+    }
+}
+
+/// One row of the self-test matrix
+#[derive(Debug, Serialize)]
+struct CheckRecord {
+    category: &'static str,
+    check: String,
+    status: Status,
+    detail: String,
+}
+
+impl CheckRecord {
+    fn new(category: &'static str, check: impl Into<String>, outcome: Outcome) -> Self {
+        let (status, detail) = match outcome {
+            Outcome::Pass(detail) => (Status::Pass, detail),
+            Outcome::Fail(detail) => (Status::Fail, detail),
+            Outcome::Skip(detail) => (Status::Skip, detail),
+        };
+        Self {
+            category,
+            check: check.into(),
+            status,
+            detail,
+        }
+    }
+}
+
+enum Outcome {
+    Pass(String),
+    Fail(String),
+    Skip(String),
+}
+
+impl<E: std::fmt::Display> From<Result<String, E>> for Outcome {
+    fn from(result: Result<String, E>) -> Self {
+        match result {
+            Ok(detail) => Outcome::Pass(detail),
+            Err(e) => Outcome::Fail(e.to_string()),
+        }
+    }
+}
+
+/// Run all checks and report them as a matrix
+///
+/// Fails if any check fails; checks of codecs left out of the build are
+/// reported as skipped.
+pub fn run() -> Result<()> {
+    let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
+
+    let mut records = codec_checks();
+    records.extend(hash_checks());
+    records.extend(encryption_checks());
+    records.push(CheckRecord::new(
+        "archive",
+        "build and read",
+        archive_check().into(),
+    ));
+
+    match global_opts.output {
+        OutputFormat::Text => print_matrix(&records),
+        OutputFormat::Json | OutputFormat::Jsonl => print_records(&records, global_opts.output)?,
+        OutputFormat::Csv => {
+            println!("category,check,status,detail");
+            for record in &records {
+                println!(
+                    "{},{},{},{}",
+                    record.category,
+                    record.check,
+                    record.status,
+                    csv_field(&record.detail)
+                );
+            }
+        }
+    }
+
+    let failed = records.iter().filter(|r| r.status == Status::Fail).count();
+    if failed > 0 {
+        return Err(CheckFailed(format!(
+            "{} of {} self-test checks failed",
+            failed,
+            records.len()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn print_matrix(records: &[CheckRecord]) {
+    println!("{}", "Self-test".bold());
+    println!("{}", "=".repeat(60));
+
+    let mut category = "";
+    for record in records {
+        if record.category != category {
+            category = record.category;
+            println!("\n{}", category.cyan());
+        }
+        let status = match record.status {
+            Status::Pass => "pass".green(),
+            Status::Fail => "FAIL".red().bold(),
+            Status::Skip => "skip".yellow(),
+        };
+        println!("  {:<22} {}  {}", record.check, status, record.detail);
+    }
+
+    let count = |status| records.iter().filter(|r| r.status == status).count();
+    println!(
+        "\n{} passed, {} failed, {} skipped",
+        count(Status::Pass),
+        count(Status::Fail),
+        count(Status::Skip)
+    );
+}
+
+/// Round-trip a sample through every codec the build reports
+fn codec_checks() -> Vec<CheckRecord> {
+    let caps = mopaq::capabilities();
+    caps.codecs()
+        .into_iter()
+        .map(|(name, built)| {
+            let outcome = if !built {
+                Outcome::Skip("not in this build".to_string())
+            } else {
+                match name {
+                    "zlib" => round_trip(flags::ZLIB).into(),
+                    "bzip2" => round_trip(flags::BZIP2).into(),
+                    "lzma" => round_trip(flags::LZMA).into(),
+                    "pkware" => round_trip(flags::PKWARE).into(),
+                    "sparse" => round_trip(flags::SPARSE).into(),
+                    "adpcm" => adpcm_round_trip().into(),
+                    // The crate reads Huffman-compressed sectors but has no encoder
+                    "huffman" => Outcome::Skip("decompression only".to_string()),
+                    _ => Outcome::Skip("no check for this codec".to_string()),
+                }
+            };
+            CheckRecord::new("compression", name, outcome)
+        })
+        .collect()
+}
+
+/// Text followed by a run of zeros, so that every lossless codec shrinks it
+fn sample() -> Vec<u8> {
+    let mut data: Vec<u8> = b"The quick brown fox jumps over the lazy dog. "
+        .iter()
+        .cycle()
+        .take(SAMPLE_SIZE / 2)
+        .copied()
+        .collect();
+    data.resize(SAMPLE_SIZE, 0);
+    data
+}
+
+fn round_trip(method: u8) -> Result<String> {
+    let data = sample();
+    let compressed = mopaq::compress(&data, method)?;
+    if compressed.len() >= data.len() {
+        anyhow::bail!("sample did not compress");
+    }
+    let decompressed = mopaq::decompress(&compressed[1..], compressed[0], data.len())?;
+    if decompressed != data {
+        anyhow::bail!("decompressed data differs from the original");
+    }
+    Ok(format!("{} -> {} bytes", data.len(), compressed.len()))
+}
+
+fn adpcm_round_trip() -> Result<String> {
+    let samples: Vec<i16> = (0..SAMPLE_SIZE / 2)
+        .map(|i| ((i as f64 * 0.05).sin() * 12000.0) as i16)
+        .collect();
+    let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+
+    let mut sizes = Vec::new();
+    for method in [flags::ADPCM_MONO, flags::ADPCM_STEREO] {
+        let compressed = mopaq::compress(&data, method)?;
+        if compressed.len() >= data.len() {
+            anyhow::bail!("sample did not compress");
+        }
+        let decompressed = mopaq::decompress(&compressed[1..], compressed[0], data.len())?;
+        if decompressed.len() != data.len() {
+            anyhow::bail!(
+                "decompressed {} bytes instead of {}",
+                decompressed.len(),
+                data.len()
+            );
+        }
+        let max_error = samples
+            .iter()
+            .zip(decompressed.chunks_exact(2))
+            .map(|(&s, pair)| (s as i32 - i16::from_le_bytes([pair[0], pair[1]]) as i32).abs())
+            .max()
+            .unwrap_or(0);
+        if max_error > ADPCM_TOLERANCE {
+            anyhow::bail!("sample error {} exceeds {}", max_error, ADPCM_TOLERANCE);
+        }
+        sizes.push(compressed.len().to_string());
+    }
+    Ok(format!(
+        "{} -> {} bytes (mono/stereo)",
+        data.len(),
+        sizes.join("/")
+    ))
+}
+
+fn hash_checks() -> Vec<CheckRecord> {
+    HASH_VECTORS
+        .iter()
+        .map(|&(input, hash_type, expected)| {
+            let actual = hash_string(input, hash_type);
+            let detail = format!("{:#010x}", actual);
+            let outcome = if actual == expected {
+                Outcome::Pass(detail)
+            } else {
+                Outcome::Fail(format!("{}, expected {:#010x}", detail, expected))
+            };
+            CheckRecord::new("hash", input, outcome)
+        })
+        .collect()
+}
+
+fn encryption_checks() -> Vec<CheckRecord> {
+    let key = hash_string("(hash table)", hash_type::FILE_KEY);
+    let plain = [0u32, 1, 2, 3];
+    let expected = [0x863C_CFCC, 0x67CD_26D9, 0x6090_8C64, 0x16B1_7BD0];
+
+    let mut encrypted = plain;
+    encrypt_block(&mut encrypted, key);
+    let vector = if encrypted == expected {
+        Outcome::Pass(format!("{:08x?}", encrypted))
+    } else {
+        Outcome::Fail(format!("{:08x?}, expected {:08x?}", encrypted, expected))
+    };
+
+    let mut decrypted = encrypted;
+    decrypt_block(&mut decrypted, key);
+    let round_trip = if decrypted == plain {
+        Outcome::Pass(format!("key {:#010x}", key))
+    } else {
+        Outcome::Fail(format!("decrypted to {:08x?}", decrypted))
+    };
+
+    vec![
+        CheckRecord::new("encryption", "known vector", vector),
+        CheckRecord::new("encryption", "round trip", round_trip),
+    ]
+}
+
+/// Build a small v4 archive in a temporary directory and read it back
+fn archive_check() -> Result<String> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("selftest.mpq");
+
+    let text = sample();
+    let binary: Vec<u8> = (0..3 * SAMPLE_SIZE as u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect();
+
+    ArchiveBuilder::new()
+        .version(FormatVersion::V4)
+        .generate_crcs(true)
+        .add_file_data_with_options(text.clone(), "selftest\\text.txt", flags::ZLIB, false, 0)
+        .add_file_data_with_encryption(binary.clone(), "selftest\\data.bin", flags::ZLIB, true, 0)
+        .build(&path)?;
+
+    let archive = Archive::open(&path)?;
+    for (name, expected) in [
+        ("selftest\\text.txt", &text),
+        ("selftest\\data.bin", &binary),
+    ] {
+        if archive.read_file(name)? != *expected {
+            anyhow::bail!("{} read back differently", name);
+        }
+    }
+    Ok(format!(
+        "v4, {} bytes, compressed and encrypted files",
+        std::fs::metadata(&path)?.len()
+    ))
+}
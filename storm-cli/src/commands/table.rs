@@ -2,50 +2,209 @@
 
 use anyhow::Result;
 use colored::Colorize;
-use mopaq::Archive;
+use mopaq::{Archive, BlockEntry};
 
-use crate::TableType;
+use crate::output::{print_records, print_structured, BlockEntryRecord, HashEntryRecord};
 use crate::GLOBAL_OPTS;
+use crate::{OutputFormat, TableType};
 
 /// Display table contents
 pub fn show(
     archive_path: &str,
     table_type: Option<TableType>,
-    _limit: Option<usize>,
-    _occupied_only: bool,
+    limit: Option<usize>,
+    occupied_only: bool,
 ) -> Result<()> {
     let global_opts = GLOBAL_OPTS.get().expect("Global options not set");
 
-    let _archive = Archive::open(archive_path)?;
+    let archive = Archive::open(archive_path)?;
     let table = table_type.unwrap_or(TableType::Hash);
+    let limit = limit.unwrap_or(usize::MAX);
 
     match table {
         TableType::Hash => {
-            // TODO: Access hash table data
-            if !global_opts.quiet {
-                println!("Hash table display not yet implemented");
-            }
+            let hash_table = archive
+                .hash_table()
+                .ok_or_else(|| anyhow::anyhow!("Archive has no hash table"))?;
+            let records: Vec<HashEntryRecord> = hash_table
+                .entries()
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| !occupied_only || entry.is_valid())
+                .take(limit)
+                .map(|(index, entry)| HashEntryRecord::new(index, entry))
+                .collect();
+            print_hash_entries(&records, hash_table.size(), global_opts.output)?;
         }
         TableType::Block => {
-            // TODO: Access block table data
-            if !global_opts.quiet {
-                println!("Block table display not yet implemented");
-            }
+            let block_table = archive
+                .block_table()
+                .ok_or_else(|| anyhow::anyhow!("Archive has no block table"))?;
+            let records: Vec<BlockEntryRecord> = block_table
+                .entries()
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| !occupied_only || entry.exists())
+                .take(limit)
+                .map(|(index, entry)| BlockEntryRecord {
+                    index,
+                    file_pos: entry.file_pos as u64,
+                    compressed_size: entry.compressed_size as u64,
+                    file_size: entry.file_size as u64,
+                    flags: entry.flags,
+                })
+                .collect();
+            print_block_entries(&records, block_table.size(), global_opts.output)?;
         }
         TableType::Het => {
-            // TODO: Access HET table data
-            if !global_opts.quiet {
-                println!("HET table display not yet implemented");
+            let het_table = archive
+                .het_table()
+                .ok_or_else(|| anyhow::anyhow!("Archive has no HET table"))?;
+            // The header is packed, so copy fields out before formatting them
+            let header = &het_table.header;
+            let max_file_count = header.max_file_count;
+            let hash_table_size = header.hash_table_size;
+            let hash_entry_size = header.hash_entry_size;
+            let total_index_size = header.total_index_size;
+            let index_size = header.index_size;
+            let summary = serde_json::json!({
+                "table": "het",
+                "max_file_count": max_file_count,
+                "hash_table_size": hash_table_size,
+                "hash_entry_size": hash_entry_size,
+                "total_index_size": total_index_size,
+                "index_size": index_size,
+            });
+            match global_opts.output {
+                OutputFormat::Text => {
+                    println!("{}", "HET Table".bold());
+                    println!("  Max file count:   {}", max_file_count);
+                    println!("  Hash table size:  {}", hash_table_size);
+                    println!("  Hash entry size:  {} bits", hash_entry_size);
+                    println!("  Index size:       {} bits", index_size);
+                }
+                OutputFormat::Csv => {
+                    println!("max_file_count,hash_table_size,hash_entry_size,index_size");
+                    println!(
+                        "{},{},{},{}",
+                        max_file_count, hash_table_size, hash_entry_size, index_size
+                    );
+                }
+                format => print_structured(&summary, format)?,
             }
         }
         TableType::Bet => {
-            // TODO: Access BET table data
-            if !global_opts.quiet {
-                println!("BET table display not yet implemented");
+            let bet_table = archive
+                .bet_table()
+                .ok_or_else(|| anyhow::anyhow!("Archive has no BET table"))?;
+            let file_count = { bet_table.header.file_count };
+            let records: Vec<BlockEntryRecord> = (0..file_count)
+                .filter_map(|index| {
+                    bet_table.get_file_info(index).map(|info| BlockEntryRecord {
+                        index: index as usize,
+                        file_pos: info.file_pos,
+                        compressed_size: info.compressed_size,
+                        file_size: info.file_size,
+                        flags: info.flags,
+                    })
+                })
+                .filter(|record| !occupied_only || record.flags & BlockEntry::FLAG_EXISTS != 0)
+                .take(limit)
+                .collect();
+            print_block_entries(&records, file_count as usize, global_opts.output)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn print_hash_entries(
+    records: &[HashEntryRecord],
+    table_size: usize,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            println!("{} ({} entries)", "Hash Table".bold(), table_size);
+            println!(
+                "{:>6}  {:10}  {:10}  {:6}  {:8}  {:>11}",
+                "Index", "Name A", "Name B", "Locale", "Platform", "Block"
+            );
+            for record in records {
+                let block = match record.state {
+                    "empty" => "EMPTY".dimmed().to_string(),
+                    "deleted" => "DELETED".yellow().to_string(),
+                    _ => record.block_index.to_string(),
+                };
+                println!(
+                    "{:>6}  {}  {}  {:#06x}  {:8}  {:>11}",
+                    record.index,
+                    record.name_1,
+                    record.name_2,
+                    record.locale,
+                    record.platform,
+                    block
+                );
             }
         }
+        OutputFormat::Csv => {
+            println!("index,name_1,name_2,locale,platform,block_index,state");
+            for record in records {
+                println!(
+                    "{},{},{},{},{},{},{}",
+                    record.index,
+                    record.name_1,
+                    record.name_2,
+                    record.locale,
+                    record.platform,
+                    record.block_index,
+                    record.state
+                );
+            }
+        }
+        format => print_records(records, format)?,
     }
+    Ok(())
+}
 
+fn print_block_entries(
+    records: &[BlockEntryRecord],
+    table_size: usize,
+    format: OutputFormat,
+) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            println!("{} ({} entries)", "Block Table".bold(), table_size);
+            println!(
+                "{:>6}  {:>12}  {:>12}  {:>12}  {:10}",
+                "Index", "Offset", "Compressed", "Size", "Flags"
+            );
+            for record in records {
+                println!(
+                    "{:>6}  {:>#12x}  {:>12}  {:>12}  {:#010x}",
+                    record.index,
+                    record.file_pos,
+                    record.compressed_size,
+                    record.file_size,
+                    record.flags
+                );
+            }
+        }
+        OutputFormat::Csv => {
+            println!("index,file_pos,compressed_size,file_size,flags");
+            for record in records {
+                println!(
+                    "{},{},{},{},{:#010x}",
+                    record.index,
+                    record.file_pos,
+                    record.compressed_size,
+                    record.file_size,
+                    record.flags
+                );
+            }
+        }
+        format => print_records(records, format)?,
+    }
     Ok(())
 }
 
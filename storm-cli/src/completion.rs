@@ -0,0 +1,184 @@
+//! Dynamic completion of file names inside archives
+//!
+//! The scripts from `storm-cli completion` only know the static command
+//! line. For bash, zsh and fish a wrapper is appended that calls the hidden
+//! `__complete-files` helper with the words typed so far; when the word
+//! being completed names a file inside an archive, it prints the matching
+//! names from the archive's listfile and the shell offers those instead.
+
+use clap_complete::Shell;
+use mopaq::Archive;
+
+/// Name of the helper the completion scripts call
+pub const HELPER: &str = "__complete-files";
+
+/// Options that take a value as the next word, so it is not mistaken for a
+/// positional argument
+const VALUE_OPTIONS: [&str; 10] = [
+    "-o",
+    "--output",
+    "-C",
+    "--config",
+    "-t",
+    "--target-directory",
+    "-e",
+    "--encoding",
+    "-n",
+    "--rows",
+];
+
+/// Candidates for `current`, given the words before it without the program
+/// name
+///
+/// Names are completed one directory level at a time with `/` as the
+/// separator, which archive lookups accept and shells do not treat as an
+/// escape character. Nothing is returned when `current` is not a file
+/// argument or the archive cannot be read.
+pub fn archive_file_candidates(words: &[String], current: &str) -> Vec<String> {
+    let Some(archive) = archive_for_file_argument(words) else {
+        return Vec::new();
+    };
+    let Ok(mut archive) = Archive::open(archive) else {
+        return Vec::new();
+    };
+    let Ok(entries) = archive.list() else {
+        return Vec::new();
+    };
+
+    let typed = current.replace('\\', "/");
+    let prefix = typed.to_ascii_lowercase();
+    let mut candidates: Vec<String> = Vec::new();
+    for entry in entries {
+        let name = entry.name.replace('\\', "/");
+        if !name.to_ascii_lowercase().starts_with(&prefix) {
+            continue;
+        }
+        // Keep what was typed so shells that filter by prefix accept the
+        // candidate regardless of case
+        let rest = &name[typed.len()..];
+        let candidate = match rest.find('/') {
+            Some(slash) => format!("{}{}", typed, &rest[..=slash]),
+            None => format!("{}{}", typed, rest),
+        };
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+    candidates.sort();
+    candidates
+}
+
+/// The archive argument if the next word is a file inside that archive
+fn archive_for_file_argument(words: &[String]) -> Option<&str> {
+    let mut positionals = Vec::new();
+    let mut skip_value = false;
+    for word in words {
+        if skip_value {
+            skip_value = false;
+        } else if word.starts_with('-') {
+            skip_value = VALUE_OPTIONS.contains(&word.as_str());
+        } else {
+            positionals.push(word.as_str());
+        }
+    }
+
+    let [command, subcommand, archive, args @ ..] = positionals.as_slice() else {
+        return None;
+    };
+    if *command != "file" {
+        return None;
+    }
+    let takes_file = match *subcommand {
+        "extract" | "info" | "cat" | "show" | "compare" => args.is_empty(),
+        "remove" => true,
+        _ => false,
+    };
+    takes_file.then_some(*archive)
+}
+
+/// Script lines that hook archive file completion into a generated script
+///
+/// They are appended after clap's script and register a wrapper that falls
+/// back to the generated completion. PowerShell and Elvish only get the
+/// static completion.
+pub fn dynamic_script(shell: Shell) -> Option<&'static str> {
+    match shell {
+        Shell::Bash => Some(BASH),
+        Shell::Zsh => Some(ZSH),
+        Shell::Fish => Some(FISH),
+        _ => None,
+    }
+}
+
+const BASH: &str = r#"
+_storm-cli_archive_files() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local -a files=()
+    local file
+    while IFS= read -r file; do
+        files+=("$file")
+    done < <("${COMP_WORDS[0]}" __complete-files "$cur" "${COMP_WORDS[@]:1:COMP_CWORD-1}" 2>/dev/null)
+    if [[ ${#files[@]} -gt 0 ]]; then
+        COMPREPLY=("${files[@]}")
+        if [[ ${#files[@]} -eq 1 && ${files[0]} == */ ]]; then
+            compopt -o nospace
+        fi
+        return 0
+    fi
+    _storm-cli "$@"
+}
+
+complete -F _storm-cli_archive_files -o bashdefault -o default storm-cli
+"#;
+
+const ZSH: &str = r#"
+_storm-cli_archive_files() {
+    local -a files dirs
+    files=(${(f)"$(${words[1]} __complete-files "${words[CURRENT]}" "${(@)words[2,CURRENT-1]}" 2>/dev/null)"})
+    if (( ${#files} )); then
+        dirs=(${(M)files:#*/})
+        files=(${files:#*/})
+        (( ${#dirs} )) && compadd -S '' -- "${dirs[@]}"
+        (( ${#files} )) && compadd -- "${files[@]}"
+        return 0
+    fi
+    _storm-cli "$@"
+}
+
+compdef _storm-cli_archive_files storm-cli
+"#;
+
+const FISH: &str = r#"
+function __storm_cli_archive_files
+    set -l tokens (commandline -opc)
+    set -l current (commandline -ct)
+    $tokens[1] __complete-files "$current" $tokens[2..-1] 2>/dev/null
+end
+
+complete -c storm-cli -a '(__storm_cli_archive_files)'
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_archive_for_file_argument() {
+        let archive = |line: &str| archive_for_file_argument(&words(line)).map(String::from);
+
+        assert_eq!(archive("file extract a.mpq").as_deref(), Some("a.mpq"));
+        assert_eq!(
+            archive("-o json file cat -e utf8 a.mpq").as_deref(),
+            Some("a.mpq")
+        );
+        assert_eq!(archive("file remove a.mpq x y").as_deref(), Some("a.mpq"));
+        assert_eq!(archive("file compare a.mpq x").as_deref(), None);
+        assert_eq!(archive("file extract").as_deref(), None);
+        assert_eq!(archive("file list a.mpq").as_deref(), None);
+        assert_eq!(archive("archive info a.mpq").as_deref(), None);
+    }
+}
@@ -2,6 +2,7 @@
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -21,6 +22,35 @@ pub struct Config {
 
     /// Default output format
     pub default_output: Option<String>,
+
+    /// Patterns `archive create` always ignores
+    pub default_ignore_patterns: Option<Vec<String>>,
+
+    /// Named sets of defaults, selected with `--profile`
+    #[serde(
+        default,
+        rename = "profile",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Defaults for one game or project, written as `[profile.<name>]`
+///
+/// Settings a profile leaves out fall back to the top-level defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    /// Compression method
+    pub compression: Option<String>,
+
+    /// MPQ format version
+    pub version: Option<u16>,
+
+    /// Block size
+    pub block_size: Option<u16>,
+
+    /// Patterns to ignore, in addition to the top-level ones
+    pub ignore_patterns: Option<Vec<String>>,
 }
 
 impl Default for Config {
@@ -31,7 +61,43 @@ impl Default for Config {
             default_block_size: Some(3),
             aliases: None,
             default_output: Some("text".to_string()),
+            default_ignore_patterns: None,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// The defaults in effect with profile `name` selected
+    pub fn with_profile(mut self, name: &str) -> Result<Self> {
+        let Some(profile) = self.profiles.get(name).cloned() else {
+            let available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            anyhow::bail!(
+                "Unknown profile '{}'. Available profiles: {}",
+                name,
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            );
+        };
+
+        if profile.compression.is_some() {
+            self.default_compression = profile.compression;
+        }
+        if profile.version.is_some() {
+            self.default_version = profile.version;
+        }
+        if profile.block_size.is_some() {
+            self.default_block_size = profile.block_size;
+        }
+        if let Some(patterns) = profile.ignore_patterns {
+            self.default_ignore_patterns
+                .get_or_insert_with(Vec::new)
+                .extend(patterns);
         }
+        Ok(self)
     }
 }
 
@@ -80,3 +146,45 @@ pub fn save_config(config: &Config, path: &Path) -> Result<()> {
     fs::write(path, contents)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles() {
+        let config: Config = toml::from_str(
+            r#"
+            default_compression = "zlib"
+            default_version = 2
+            default_ignore_patterns = ["*.bak"]
+
+            [profile.wc3]
+            compression = "pkware"
+            version = 1
+            block_size = 3
+            ignore_patterns = ["*.psd"]
+
+            [profile.wow]
+            compression = "bzip2"
+            "#,
+        )
+        .unwrap();
+
+        let wc3 = config.clone().with_profile("wc3").unwrap();
+        assert_eq!(wc3.default_compression.as_deref(), Some("pkware"));
+        assert_eq!(wc3.default_version, Some(1));
+        assert_eq!(
+            wc3.default_ignore_patterns,
+            Some(vec!["*.bak".to_string(), "*.psd".to_string()])
+        );
+
+        // Settings the profile leaves out keep the top-level value
+        let wow = config.clone().with_profile("wow").unwrap();
+        assert_eq!(wow.default_compression.as_deref(), Some("bzip2"));
+        assert_eq!(wow.default_version, Some(2));
+
+        let error = config.with_profile("sc2").unwrap_err().to_string();
+        assert!(error.contains("wc3, wow"));
+    }
+}
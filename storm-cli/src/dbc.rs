@@ -0,0 +1,229 @@
+//! Quick preview of client database (DBC/DB2) files
+//!
+//! Only the parts needed to show a table at a glance are decoded: the
+//! header, the fixed-size records and the string block. DBC files carry no
+//! column types, so each column is guessed from the previewed rows as an
+//! integer, a float or an offset into the string block.
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// Header fields shared by every WDB* layout
+#[derive(Debug, Clone, Serialize)]
+pub struct DbcHeader {
+    /// File signature, e.g. `WDBC` or `WDB2`
+    pub format: String,
+    pub record_count: u32,
+    pub field_count: u32,
+    pub record_size: u32,
+    pub string_block_size: u32,
+}
+
+/// Guessed type of a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnKind {
+    Int,
+    Float,
+    String,
+}
+
+/// A single decoded cell
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Int(i64),
+    Float(f32),
+    String(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Header and first rows of a database file
+#[derive(Debug, Clone, Serialize)]
+pub struct DbcPreview {
+    #[serde(flatten)]
+    pub header: DbcHeader,
+    /// Column types, one per 4-byte word of a record
+    pub columns: Vec<ColumnKind>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl DbcPreview {
+    /// Decode the header and up to `max_rows` records of `data`
+    ///
+    /// `WDBC` and `WDB2` records are decoded; for later DB2 layouts, whose
+    /// records may be bit-packed, only the header is returned.
+    pub fn parse(data: &[u8], max_rows: usize) -> Result<Self> {
+        let magic = data.get(..4).unwrap_or_default();
+        let header_size = match magic {
+            b"WDBC" => 20,
+            b"WDB2" => wdb2_header_size(data)?,
+            [b'W', b'D', b'B', version] if version.is_ascii_digit() => 0,
+            _ => bail!("Not a DBC/DB2 file"),
+        };
+
+        let header = DbcHeader {
+            format: String::from_utf8_lossy(magic).into_owned(),
+            record_count: read_u32(data, 4)?,
+            field_count: read_u32(data, 8)?,
+            record_size: read_u32(data, 12)?,
+            string_block_size: read_u32(data, 16)?,
+        };
+        if header_size == 0 {
+            return Ok(Self {
+                header,
+                columns: Vec::new(),
+                rows: Vec::new(),
+            });
+        }
+
+        let record_size = header.record_size as usize;
+        let records_end = header_size + header.record_count as usize * record_size;
+        let strings = data
+            .get(records_end..records_end + header.string_block_size as usize)
+            .unwrap_or_default();
+
+        let words: Vec<Vec<u32>> = (0..(header.record_count as usize).min(max_rows))
+            .map(|row| {
+                let start = header_size + row * record_size;
+                (0..record_size / 4)
+                    .map(|word| read_u32(data, start + word * 4))
+                    .collect::<Result<Vec<u32>>>()
+            })
+            .collect::<Result<_>>()?;
+
+        let columns: Vec<ColumnKind> = (0..record_size / 4)
+            .map(|column| guess_column(words.iter().map(|row| row[column]), strings))
+            .collect();
+        let rows = words
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(&columns)
+                    .map(|(&raw, &kind)| decode(raw, kind, strings))
+                    .collect()
+            })
+            .collect();
+
+        Ok(Self {
+            header,
+            columns,
+            rows,
+        })
+    }
+}
+
+/// WDB2 has a longer header, followed by index arrays in newer builds
+fn wdb2_header_size(data: &[u8]) -> Result<usize> {
+    let build = read_u32(data, 24)?;
+    let min_id = read_u32(data, 36)?;
+    let max_id = read_u32(data, 40)?;
+    let mut size = 48;
+    if build > 12880 && max_id != 0 {
+        // u32 record index plus u16 string length per id
+        size += (max_id.saturating_sub(min_id) as usize + 1) * 6;
+    }
+    Ok(size)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes(bytes.try_into().unwrap())),
+        None => bail!("File is truncated at offset {}", offset),
+    }
+}
+
+/// The string starting at `offset` of the string block, if it looks like one
+fn string_at(strings: &[u8], offset: u32) -> Option<&str> {
+    let offset = offset as usize;
+    if offset == 0 || offset >= strings.len() || strings[offset - 1] != 0 {
+        return None;
+    }
+    let end = strings[offset..].iter().position(|&b| b == 0)? + offset;
+    std::str::from_utf8(&strings[offset..end])
+        .ok()
+        .filter(|s| !s.is_empty() && !s.chars().any(char::is_control))
+}
+
+fn is_float_like(raw: u32) -> bool {
+    let exponent = (raw >> 23) & 0xFF;
+    (100..=160).contains(&exponent)
+}
+
+fn guess_column(values: impl Iterator<Item = u32> + Clone, strings: &[u8]) -> ColumnKind {
+    let nonzero = values.filter(|&raw| raw != 0);
+    if nonzero.clone().next().is_none() {
+        ColumnKind::Int
+    } else if nonzero.clone().all(|raw| string_at(strings, raw).is_some()) {
+        ColumnKind::String
+    } else if nonzero.clone().all(is_float_like) {
+        ColumnKind::Float
+    } else {
+        ColumnKind::Int
+    }
+}
+
+fn decode(raw: u32, kind: ColumnKind, strings: &[u8]) -> Value {
+    match kind {
+        ColumnKind::Int => Value::Int(raw as i32 as i64),
+        ColumnKind::Float => Value::Float(f32::from_bits(raw)),
+        ColumnKind::String => {
+            Value::String(string_at(strings, raw).unwrap_or_default().to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wdbc(records: &[[u32; 3]], strings: &[u8]) -> Vec<u8> {
+        let mut data = b"WDBC".to_vec();
+        for value in [records.len() as u32, 3, 12, strings.len() as u32] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for record in records {
+            for value in record {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        data.extend_from_slice(strings);
+        data
+    }
+
+    #[test]
+    fn test_parse_wdbc() {
+        let strings = b"\0Fireball\0Frostbolt\0";
+        let data = wdbc(
+            &[
+                [133, 1, 2.5f32.to_bits()],
+                [116, 10, (-1.0f32).to_bits()],
+                [(-1i32) as u32, 0, 0],
+            ],
+            strings,
+        );
+
+        let preview = DbcPreview::parse(&data, 2).unwrap();
+        assert_eq!(preview.header.record_count, 3);
+        assert_eq!(
+            preview.columns,
+            [ColumnKind::Int, ColumnKind::String, ColumnKind::Float]
+        );
+        assert_eq!(preview.rows.len(), 2);
+        assert_eq!(preview.rows[0][1], Value::String("Fireball".to_string()));
+        assert_eq!(preview.rows[1][1], Value::String("Frostbolt".to_string()));
+        assert_eq!(preview.rows[1][2], Value::Float(-1.0));
+
+        assert!(DbcPreview::parse(b"BLP2", 10).is_err());
+        assert!(DbcPreview::parse(&data[..30], 10).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Process exit codes
+//!
+//! | Code | Meaning                                                  |
+//! |------|----------------------------------------------------------|
+//! | 0    | Success                                                  |
+//! | 1    | Any other error                                          |
+//! | 2    | Checks failed (`verify`, `analyze --fail-on`, `selftest`) |
+//! | 3    | Archive or file not found                                |
+//! | 4    | Archive is corrupt                                       |
+//! | 5    | Format version, codec or operation not supported         |
+//! | 64   | Invalid command line                                     |
+
+use clap::ValueEnum;
+use std::fmt;
+use std::io;
+
+/// Any other error
+pub const FAILURE: u8 = 1;
+/// Checks ran but did not pass
+pub const CHECK_FAILED: u8 = 2;
+/// The archive or a file in it does not exist
+pub const NOT_FOUND: u8 = 3;
+/// The archive is damaged
+pub const CORRUPT: u8 = 4;
+/// The archive needs something this build cannot do
+pub const UNSUPPORTED: u8 = 5;
+/// The command line could not be parsed, as `EX_USAGE` in sysexits.h
+pub const USAGE: u8 = 64;
+
+/// Findings that make a check command fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FailOn {
+    /// Fail on warnings as well as errors
+    Warn,
+    /// Fail on errors only
+    Error,
+}
+
+impl FailOn {
+    /// Check whether `errors` and `warnings` found make the command fail
+    pub fn fails(self, errors: usize, warnings: usize) -> bool {
+        errors > 0 || (self == FailOn::Warn && warnings > 0)
+    }
+}
+
+/// Error of a command whose checks did not pass; exits with [`CHECK_FAILED`]
+#[derive(Debug)]
+pub struct CheckFailed(pub String);
+
+impl fmt::Display for CheckFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CheckFailed {}
+
+/// Exit code for an error returned by a command
+pub fn code_for(error: &anyhow::Error) -> u8 {
+    for cause in error.chain() {
+        if cause.is::<CheckFailed>() {
+            return CHECK_FAILED;
+        }
+        if let Some(error) = cause.downcast_ref::<mopaq::Error>() {
+            return match error {
+                mopaq::Error::FileNotFound(_) => NOT_FOUND,
+                mopaq::Error::InvalidPattern(_) => USAGE,
+                mopaq::Error::Io(io) if io.kind() == io::ErrorKind::NotFound => NOT_FOUND,
+                mopaq::Error::UnsupportedVersion(_)
+                | mopaq::Error::OperationNotSupported { .. }
+                | mopaq::Error::Incompatible { .. } => UNSUPPORTED,
+                mopaq::Error::Compression(_)
+                | mopaq::Error::HashTable(_)
+                | mopaq::Error::BlockTable(_)
+                | mopaq::Error::Crypto(_)
+                | mopaq::Error::InvalidFileSize { .. } => CORRUPT,
+                error if error.is_corruption() => CORRUPT,
+                _ => FAILURE,
+            };
+        }
+        if let Some(io) = cause.downcast_ref::<io::Error>() {
+            if io.kind() == io::ErrorKind::NotFound {
+                return NOT_FOUND;
+            }
+        }
+    }
+    FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let code = |error: anyhow::Error| code_for(&error);
+
+        assert_eq!(code(CheckFailed("2 errors".into()).into()), CHECK_FAILED);
+        assert_eq!(
+            code(mopaq::Error::FileNotFound("war3map.j".into()).into()),
+            NOT_FOUND
+        );
+        assert_eq!(
+            code(io::Error::from(io::ErrorKind::NotFound).into()),
+            NOT_FOUND
+        );
+        assert_eq!(code(mopaq::Error::invalid_format("bad").into()), CORRUPT);
+        assert_eq!(
+            code(mopaq::Error::UnsupportedVersion(9).into()),
+            UNSUPPORTED
+        );
+        assert_eq!(
+            code(anyhow::Error::from(mopaq::Error::FileNotFound("x".into())).context("extracting")),
+            NOT_FOUND
+        );
+        assert_eq!(code(anyhow::anyhow!("something else")), FAILURE);
+
+        assert!(FailOn::Warn.fails(0, 1));
+        assert!(!FailOn::Error.fails(0, 1));
+        assert!(FailOn::Error.fails(1, 0));
+    }
+}
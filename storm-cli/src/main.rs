@@ -3,17 +3,24 @@
 //! The binary is named `storm-cli` to avoid conflicts with the `storm` library crate.
 
 use anyhow::Result;
-use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use clap_complete::{generate, Shell};
 use std::io;
 use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::process::ExitCode;
+use std::sync::{LazyLock, OnceLock};
 
+mod alias;
 mod commands;
+mod completion;
 mod config;
+#[cfg(feature = "dbc")]
+mod dbc;
+mod exit;
 mod output;
+mod text;
 
-use mopaq::{FormatVersion, ListfileOption};
+use mopaq::{FormatVersion, ListfileOption, Locale};
 
 // Global context for commands to access
 pub static GLOBAL_OPTS: OnceLock<GlobalOptions> = OnceLock::new();
@@ -31,6 +38,8 @@ pub enum OutputFormat {
     Text,
     Json,
     Csv,
+    /// JSON Lines: one compact JSON object per line, for streaming large listings
+    Jsonl,
 }
 
 #[derive(Parser)]
@@ -61,6 +70,10 @@ struct Cli {
     #[arg(global = true, short = 'C', long)]
     config: Option<PathBuf>,
 
+    /// Config profile to take defaults from, e.g. a `[profile.wc3]` section
+    #[arg(global = true, long)]
+    profile: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -79,6 +92,10 @@ enum Commands {
     #[command(subcommand)]
     Table(TableCommands),
 
+    /// Patch chain operations on a game's data directory
+    #[command(subcommand)]
+    Chain(ChainCommands),
+
     /// Hash utilities
     #[command(subcommand)]
     Hash(HashCommands),
@@ -87,6 +104,9 @@ enum Commands {
     #[command(subcommand)]
     Crypto(CryptoCommands),
 
+    /// Run built-in checks of codecs, hashing, encryption and archive I/O
+    Selftest,
+
     /// Generate shell completion scripts
     #[command(about = "Generate completion scripts for your shell")]
     Completion {
@@ -142,8 +162,8 @@ enum ArchiveCommands {
         #[arg(short = 'c', long, value_enum)]
         compression: Option<CompressionMethod>,
 
-        /// Block size (0-23, sector size = 512 * 2^n)
-        #[arg(short = 'b', long, value_parser = clap::value_parser!(u16).range(0..=23))]
+        /// Block size (0-22, sector size = 512 * 2^n)
+        #[arg(short = 'b', long, value_parser = clap::value_parser!(u16).range(0..=mopaq::MAX_BLOCK_SIZE as i64))]
         block_size: Option<u16>,
 
         /// Don't include a (listfile)
@@ -165,6 +185,63 @@ enum ArchiveCommands {
         /// Additional patterns to ignore (can be used multiple times)
         #[arg(short = 'i', long = "ignore")]
         ignore_patterns: Vec<String>,
+
+        /// Compression threads (0 = one per CPU core)
+        #[arg(short = 'j', long, default_value = "0")]
+        threads: usize,
+
+        /// Locale of the added files (e.g. enUS, deDE, 0x409)
+        #[arg(short = 'l', long, default_value = "neutral")]
+        locale: Locale,
+    },
+
+    /// Build an archive from a TOML manifest
+    ///
+    /// The manifest lists every file with its source (a path on disk, a
+    /// file in another archive, or inline base64 data) and how it is
+    /// stored. Relative paths are resolved against the manifest's directory.
+    Build {
+        /// Path to the new MPQ archive
+        #[arg(required_unless_present = "check")]
+        archive: Option<String>,
+
+        /// Manifest describing the archive
+        #[arg(short = 'm', long)]
+        manifest: String,
+
+        /// Only validate the manifest, without reading its sources
+        #[arg(long)]
+        check: bool,
+
+        /// Compression threads (0 = one per CPU core)
+        #[arg(short = 'j', long, default_value = "0")]
+        threads: usize,
+    },
+
+    /// Print a TOML manifest describing an existing archive
+    ///
+    /// Every file is copied from the archive, in each of its locales, with
+    /// its compression and encryption spelled out, ready to edit and pass
+    /// to `archive build`. Paths are written as given, so run this from
+    /// the directory the manifest is saved in.
+    Manifest {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Read files from this directory instead, as extracted with
+        /// `file extract -p -t <dir>`
+        #[arg(short = 'd', long)]
+        data_dir: Option<String>,
+    },
+
+    /// Print the content key (MD5 of the contents) of every file
+    ///
+    /// Text output is a pipe-separated CKey manifest with a
+    /// `Path!STRING:0|CKey!HEX:16|Size!DEC:8` header. Paths use `/` as
+    /// separator in every output format.
+    Ckeys {
+        /// Path to the MPQ archive
+        archive: String,
     },
 
     /// Show detailed archive information
@@ -185,6 +262,14 @@ enum ArchiveCommands {
         /// Check file contents
         #[arg(long)]
         check_contents: bool,
+
+        /// Sector checksum algorithm the archive uses
+        #[arg(long, value_enum, default_value = "auto")]
+        checksum: ChecksumAlgorithm,
+
+        /// Exit with an error on warnings as well as errors
+        #[arg(long, value_enum, default_value = "error")]
+        fail_on: exit::FailOn,
     },
 
     /// List files in an archive (alias for 'file list')
@@ -207,6 +292,18 @@ enum ArchiveCommands {
         /// Show file name hashes
         #[arg(long)]
         show_hashes: bool,
+
+        /// Detect and show each file's content type
+        #[arg(long)]
+        detect: bool,
+
+        /// Only list files of this content type (blp, m2, dbc, wav, lua, ...)
+        #[arg(long, value_name = "KIND")]
+        kind: Option<String>,
+
+        /// List special files such as (listfile) and (attributes) as well
+        #[arg(long)]
+        include_special: bool,
     },
 
     /// Analyze compression methods used in an archive
@@ -229,6 +326,91 @@ enum ArchiveCommands {
         /// Show compression ratio statistics
         #[arg(short = 's', long)]
         show_stats: bool,
+
+        /// Exit with an error when unsupported compression methods (error)
+        /// or files stored larger than their contents (warn) are found
+        #[arg(long, value_enum)]
+        fail_on: Option<exit::FailOn>,
+
+        /// Suggest a sector size based on the file size distribution
+        #[arg(long)]
+        suggest: bool,
+    },
+
+    /// Watch a directory and repack changed files into an archive
+    Watch {
+        /// Directory to watch
+        source: String,
+
+        /// Path to the MPQ archive (created if it does not exist)
+        archive: String,
+
+        /// Compression method for added files
+        #[arg(short = 'c', long, value_enum)]
+        compression: Option<CompressionMethod>,
+
+        /// Patterns to ignore (can be used multiple times)
+        #[arg(short = 'i', long = "ignore")]
+        ignore_patterns: Vec<String>,
+
+        /// Milliseconds to wait for further changes before repacking
+        #[arg(long, default_value = "300")]
+        debounce: u64,
+    },
+
+    /// Extract everything readable from a damaged archive
+    ///
+    /// Named files are read sector by sector, with unreadable sectors
+    /// zero-filled; files without names are extracted as block_NNNNN.bin,
+    /// recovering encryption keys where possible. What happened to each
+    /// entry is written to salvage_report.json in the output directory.
+    Salvage {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Directory to extract into
+        output_dir: String,
+    },
+
+    /// Convert between MPQ archives and ZIP or TAR files
+    ///
+    /// The input format is detected from its contents. ZIP and TAR files
+    /// (plain or gzipped) are imported into a new MPQ, and MPQs are exported
+    /// to ZIP.
+    Convert {
+        /// Archive to convert (MPQ, ZIP, TAR or .tar.gz)
+        input: String,
+
+        /// Path of the converted archive
+        destination: String,
+
+        /// Format to write (defaults to zip for MPQs and mpq otherwise)
+        #[arg(long, value_enum)]
+        to: Option<commands::archive::ConvertFormat>,
+
+        /// MPQ format version (1-4)
+        #[arg(short = 'V', long, value_parser = clap::value_parser!(u16).range(1..=4))]
+        version: Option<u16>,
+
+        /// Compression method for files added to an MPQ
+        #[arg(short = 'c', long, value_enum)]
+        compression: Option<CompressionMethod>,
+
+        /// Store ZIP entries without compressing them
+        #[arg(long)]
+        stored: bool,
+
+        /// Remove this prefix from file names, e.g. a ZIP's top-level folder
+        #[arg(long)]
+        strip_prefix: Option<String>,
+
+        /// Put this prefix in front of every file name
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// Convert special files such as (listfile) and (attributes) as well
+        #[arg(long)]
+        include_special: bool,
     },
 }
 
@@ -254,6 +436,18 @@ enum FileCommands {
         /// Show file name hashes
         #[arg(long)]
         show_hashes: bool,
+
+        /// Detect and show each file's content type
+        #[arg(long)]
+        detect: bool,
+
+        /// Only list files of this content type (blp, m2, dbc, wav, lua, ...)
+        #[arg(long, value_name = "KIND")]
+        kind: Option<String>,
+
+        /// List special files such as (listfile) and (attributes) as well
+        #[arg(long)]
+        include_special: bool,
     },
 
     /// Extract files from an archive
@@ -271,6 +465,29 @@ enum FileCommands {
         /// Preserve directory structure
         #[arg(short = 'p', long)]
         preserve_path: bool,
+
+        /// Extract the file at this block index instead of by name
+        #[arg(long, conflicts_with_all = ["file", "all_anonymous"])]
+        index: Option<usize>,
+
+        /// Extract every file the listfile does not name as block_NNNNN.bin
+        #[arg(long, conflicts_with = "file")]
+        all_anonymous: bool,
+
+        /// Extract the version stored for this locale (e.g. frFR), falling
+        /// back to the neutral one
+        #[arg(short = 'l', long, conflicts_with_all = ["index", "all_anonymous"])]
+        locale: Option<Locale>,
+
+        /// What to do when an output file exists, or differs from an earlier
+        /// one only in case
+        #[arg(long, value_enum, default_value = "overwrite")]
+        on_collision: OnCollision,
+
+        /// Keep output files that already have the size of the archived
+        /// file, to continue an interrupted extraction
+        #[arg(long, conflicts_with_all = ["index", "all_anonymous"])]
+        resume: bool,
     },
 
     /// Add files to an existing archive
@@ -289,6 +506,10 @@ enum FileCommands {
         /// Archive path for the file
         #[arg(short = 'p', long)]
         path: Option<String>,
+
+        /// Locale to store the files under (e.g. frFR, 0x40C)
+        #[arg(short = 'l', long, default_value = "neutral")]
+        locale: Locale,
     },
 
     /// Remove files from an archive
@@ -299,6 +520,28 @@ enum FileCommands {
         /// Files to remove
         #[arg(required = true)]
         files: Vec<String>,
+
+        /// Only remove the version stored for this locale (default: all)
+        #[arg(short = 'l', long)]
+        locale: Option<Locale>,
+    },
+
+    /// Show files as a directory tree
+    Tree {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Only descend this many directory levels
+        #[arg(short = 'L', long)]
+        depth: Option<usize>,
+
+        /// Show directories only
+        #[arg(short = 'd', long)]
+        dirs_only: bool,
+
+        /// Show file counts and cumulative sizes
+        #[arg(short = 's', long)]
+        sizes: bool,
     },
 
     /// Find files in an archive
@@ -313,6 +556,23 @@ enum FileCommands {
         #[arg(short = 'r', long)]
         regex: bool,
 
+        /// Accepted for compatibility; names always match case-insensitively
+        #[arg(short = 'i', long, hide = true)]
+        ignore_case: bool,
+    },
+
+    /// Search file contents for a regular expression
+    Grep {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Regular expression to search for
+        pattern: String,
+
+        /// Only search files whose names match this glob (e.g. "*.j")
+        #[arg(short, long)]
+        glob: Option<String>,
+
         /// Case insensitive search
         #[arg(short = 'i', long)]
         ignore_case: bool,
@@ -325,6 +585,90 @@ enum FileCommands {
 
         /// File to inspect
         file: String,
+
+        /// Inspect the version stored for this locale, falling back to the
+        /// neutral one
+        #[arg(short = 'l', long)]
+        locale: Option<Locale>,
+    },
+
+    /// Print a file to stdout, decoding text to UTF-8
+    Cat {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// File inside the archive
+        file: String,
+
+        /// Text encoding of the file
+        #[arg(short, long, value_enum, default_value = "auto")]
+        encoding: TextEncoding,
+
+        /// Write the file's bytes unchanged, for binary data
+        #[arg(long, conflicts_with = "encoding")]
+        raw: bool,
+    },
+
+    /// Preview the header and first rows of a DBC/DB2 file
+    #[cfg(feature = "dbc")]
+    Show {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Database file inside the archive
+        file: String,
+
+        /// Number of rows to show
+        #[arg(short = 'n', long, default_value = "10")]
+        rows: usize,
+    },
+
+    /// Check whether a file in an archive matches a file on disk
+    Compare {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// File inside the archive
+        file: String,
+
+        /// File on disk to compare against
+        disk_path: String,
+    },
+
+    /// Edit (listfile) entries without modifying file data
+    TouchListfile {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Name to add (must exist in the archive; can be used multiple times)
+        #[arg(long = "add")]
+        add: Vec<String>,
+
+        /// Name to remove (can be used multiple times)
+        #[arg(long = "remove")]
+        remove: Vec<String>,
+    },
+
+    /// Write an archive's file names to a listfile
+    ExportListfile {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Listfile to write
+        listfile: String,
+
+        /// Metadata to write after each name
+        #[arg(short, long, value_enum, default_value = "names")]
+        format: ListfileLayout,
+    },
+
+    /// Add the names of an external listfile that exist in an archive to its (listfile)
+    ImportListfile {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Listfile to read names from
+        listfile: String,
     },
 }
 
@@ -359,12 +703,49 @@ enum TableCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum ChainCommands {
+    /// Extract the version of a file that the patch chain resolves to
+    Read {
+        /// Game data directory, e.g. "World of Warcraft/Data"
+        #[arg(short = 'd', long)]
+        data_dir: String,
+
+        /// Locale subdirectory to include, e.g. enUS
+        #[arg(short = 'l', long, default_value = "enUS")]
+        locale: String,
+
+        /// File to extract
+        file: String,
+
+        /// Target file (defaults to the file name in the current directory)
+        #[arg(short = 't', long)]
+        target: Option<String>,
+    },
+
+    /// List files with the archive that wins for each
+    List {
+        /// Game data directory, e.g. "World of Warcraft/Data"
+        #[arg(short = 'd', long)]
+        data_dir: String,
+
+        /// Locale subdirectory to include, e.g. enUS
+        #[arg(short = 'l', long, default_value = "enUS")]
+        locale: String,
+
+        /// Filter by glob pattern
+        #[arg(short = 'p', long)]
+        pattern: Option<String>,
+    },
+}
+
 #[derive(Subcommand)]
 enum HashCommands {
     /// Generate hash values for a filename
     Generate {
         /// Filename to hash
-        filename: String,
+        #[arg(required_unless_present = "from_file")]
+        filename: Option<String>,
 
         /// Hash type (table-offset, name-a, name-b, file-key, key2-mix)
         #[arg(short = 't', long)]
@@ -373,6 +754,19 @@ enum HashCommands {
         /// Generate all hash types
         #[arg(short, long)]
         all: bool,
+
+        /// Hash every name in a listfile, printing all hash types as a table
+        #[arg(short, long, conflicts_with_all = ["filename", "hash_type"])]
+        from_file: Option<PathBuf>,
+    },
+
+    /// Check whether a name's hashes occupy a slot in an archive's hash table
+    Lookup {
+        /// Path to the MPQ archive
+        archive: String,
+
+        /// Filename to look up
+        filename: String,
     },
 
     /// Compare hash values for two filenames
@@ -413,6 +807,103 @@ enum CompressionMethod {
     AdpcmStereo,
 }
 
+impl CompressionMethod {
+    /// The MPQ compression flag for this method
+    fn flag(self) -> u16 {
+        match self {
+            CompressionMethod::None => 0,
+            CompressionMethod::Zlib => mopaq::compression::flags::ZLIB as u16,
+            CompressionMethod::Bzip2 => mopaq::compression::flags::BZIP2 as u16,
+            CompressionMethod::Lzma => mopaq::compression::flags::LZMA as u16,
+            CompressionMethod::Sparse => mopaq::compression::flags::SPARSE as u16,
+            CompressionMethod::Pkware => mopaq::compression::flags::PKWARE as u16,
+            CompressionMethod::AdpcmMono => mopaq::compression::flags::ADPCM_MONO as u16,
+            CompressionMethod::AdpcmStereo => mopaq::compression::flags::ADPCM_STEREO as u16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum OnCollision {
+    /// Replace the existing file
+    Overwrite,
+    /// Write to "name (1).ext" and so on
+    Rename,
+    /// Keep the existing file
+    Skip,
+    /// Replace the existing file only if its contents differ
+    IfDifferent,
+}
+
+impl From<OnCollision> for mopaq::CollisionPolicy {
+    fn from(on_collision: OnCollision) -> Self {
+        match on_collision {
+            OnCollision::Overwrite => Self::Overwrite,
+            OnCollision::Rename => Self::Rename,
+            OnCollision::Skip => Self::Skip,
+            OnCollision::IfDifferent => Self::IfDifferent,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ChecksumAlgorithm {
+    Auto,
+    Adler32,
+    Crc32,
+}
+
+impl From<ChecksumAlgorithm> for Option<mopaq::SectorChecksum> {
+    fn from(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Auto => None,
+            ChecksumAlgorithm::Adler32 => Some(mopaq::SectorChecksum::Adler32),
+            ChecksumAlgorithm::Crc32 => Some(mopaq::SectorChecksum::Crc32),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum TextEncoding {
+    Auto,
+    Utf8,
+    Utf16le,
+    Utf16be,
+    Cp1252,
+}
+
+impl From<TextEncoding> for Option<text::Encoding> {
+    fn from(encoding: TextEncoding) -> Self {
+        match encoding {
+            TextEncoding::Auto => None,
+            TextEncoding::Utf8 => Some(text::Encoding::Utf8),
+            TextEncoding::Utf16le => Some(text::Encoding::Utf16Le),
+            TextEncoding::Utf16be => Some(text::Encoding::Utf16Be),
+            TextEncoding::Cp1252 => Some(text::Encoding::Cp1252),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ListfileLayout {
+    /// One name per line
+    Names,
+    /// name;size
+    Size,
+    /// name;size;crc32
+    SizeCrc,
+}
+
+impl From<ListfileLayout> for mopaq::special_files::ListfileFormat {
+    fn from(layout: ListfileLayout) -> Self {
+        match layout {
+            ListfileLayout::Names => Self::Names,
+            ListfileLayout::Size => Self::NameSize,
+            ListfileLayout::SizeCrc => Self::NameSizeCrc,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
 enum TableType {
     Hash,
@@ -430,14 +921,91 @@ enum HashType {
     Key2Mix,
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// `--version` text: the version plus the codecs and features mopaq was built with
+static LONG_VERSION: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "{}\nmopaq {}",
+        env!("CARGO_PKG_VERSION"),
+        mopaq::capabilities()
+    )
+});
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            ExitCode::from(exit::code_for(&error))
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    // The completion helper is not a clap subcommand: it must accept any
+    // words the shell passes, and clap_complete cannot generate scripts for
+    // subcommand names containing `__`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [helper, current, words @ ..] = args.as_slice() {
+        if helper == completion::HELPER {
+            for candidate in completion::archive_file_candidates(words, current) {
+                println!("{}", candidate);
+            }
+            return Ok(());
+        }
+    }
 
-    // Load config if specified
-    let config = if let Some(config_path) = &cli.config {
-        config::load_config(Some(config_path))?
+    // Aliases come from the config, which is read before parsing only when
+    // the command word is not built in, so a broken config does not get in
+    // the way of --help, --version or the `config` commands
+    let command = Cli::command().long_version(LONG_VERSION.as_str());
+    let builtins: Vec<String> = command
+        .get_subcommands()
+        .map(|c| c.get_name().to_string())
+        .chain(["help".to_string()])
+        .collect();
+    let builtins: Vec<&str> = builtins.iter().map(String::as_str).collect();
+    let mut config = None;
+    let args = if alias::may_be_alias(&args, &builtins) {
+        let config_path = alias::config_path(&args).map(PathBuf::from);
+        let loaded = config::load_config(config_path.as_ref())?;
+        let args = match &loaded.aliases {
+            Some(aliases) => alias::expand(args, aliases, &builtins)?,
+            None => args,
+        };
+        config = Some(loaded);
+        args
     } else {
-        config::load_config(None)?
+        args
+    };
+    let program = std::env::args().next().unwrap_or_default();
+    let cli = match command
+        .try_get_matches_from(std::iter::once(program).chain(args))
+        .and_then(|matches| Cli::from_arg_matches(&matches))
+    {
+        Ok(cli) => cli,
+        // clap exits with 2 on usage errors, which is taken by failed checks
+        Err(e) => {
+            let _ = e.print();
+            std::process::exit(if e.use_stderr() {
+                exit::USAGE.into()
+            } else {
+                0
+            });
+        }
+    };
+
+    // `config` commands read the file themselves and edit profiles rather
+    // than use them
+    let config = match (&cli.command, config) {
+        (Commands::Config { .. }, _) => config::Config::default(),
+        (_, Some(config)) => config,
+        (_, None) => config::load_config(cli.config.as_ref())?,
+    };
+    let config = match &cli.profile {
+        Some(name) if !matches!(cli.command, Commands::Config { .. }) => {
+            config.with_profile(name)?
+        }
+        _ => config,
     };
 
     // Set up colored output based on flags
@@ -484,6 +1052,8 @@ fn main() -> Result<()> {
                 no_recursive,
                 follow_symlinks,
                 ignore_patterns,
+                threads,
+                locale,
             } => {
                 let mut options = commands::archive::CreateOptions {
                     version: if let Some(v) = version {
@@ -506,20 +1076,7 @@ fn main() -> Result<()> {
                         FormatVersion::V1
                     },
                     compression: if let Some(comp) = compression {
-                        match comp {
-                            CompressionMethod::None => 0,
-                            CompressionMethod::Zlib => mopaq::compression::flags::ZLIB as u16,
-                            CompressionMethod::Bzip2 => mopaq::compression::flags::BZIP2 as u16,
-                            CompressionMethod::Lzma => mopaq::compression::flags::LZMA as u16,
-                            CompressionMethod::Sparse => mopaq::compression::flags::SPARSE as u16,
-                            CompressionMethod::Pkware => mopaq::compression::flags::PKWARE as u16,
-                            CompressionMethod::AdpcmMono => {
-                                mopaq::compression::flags::ADPCM_MONO as u16
-                            }
-                            CompressionMethod::AdpcmStereo => {
-                                mopaq::compression::flags::ADPCM_STEREO as u16
-                            }
-                        }
+                        comp.flag()
                     } else if let Some(comp_str) = &config.default_compression {
                         match comp_str.as_str() {
                             "none" => 0,
@@ -545,15 +1102,35 @@ fn main() -> Result<()> {
                     },
                     recursive: !no_recursive,
                     follow_symlinks,
+                    threads,
+                    locale,
                     ..Default::default()
                 };
 
+                if let Some(patterns) = config.default_ignore_patterns {
+                    options.ignore_patterns.extend(patterns);
+                }
                 if !ignore_patterns.is_empty() {
                     options.ignore_patterns.extend(ignore_patterns);
                 }
 
                 commands::archive::create(&archive, &source, options)?;
             }
+            ArchiveCommands::Build {
+                archive,
+                manifest,
+                check,
+                threads,
+            } => match archive {
+                Some(archive) if !check => commands::archive::build(&archive, &manifest, threads)?,
+                _ => commands::archive::check_manifest(&manifest)?,
+            },
+            ArchiveCommands::Manifest { archive, data_dir } => {
+                commands::archive::manifest(&archive, data_dir.as_deref())?;
+            }
+            ArchiveCommands::Ckeys { archive } => {
+                commands::archive::ckeys(&archive)?;
+            }
             ArchiveCommands::Info { archive } => {
                 commands::archive::info(&archive)?;
             }
@@ -561,8 +1138,16 @@ fn main() -> Result<()> {
                 archive,
                 check_crc,
                 check_contents,
+                checksum,
+                fail_on,
             } => {
-                commands::archive::verify(&archive, check_crc, check_contents)?;
+                commands::archive::verify(
+                    &archive,
+                    check_crc,
+                    check_contents,
+                    checksum.into(),
+                    fail_on,
+                )?;
             }
             ArchiveCommands::List {
                 archive,
@@ -570,9 +1155,18 @@ fn main() -> Result<()> {
                 pattern,
                 regex,
                 show_hashes,
+                detect,
+                kind,
+                include_special,
             } => {
                 // Delegate to the file list command
-                commands::file::list(&archive, all, pattern.as_deref(), regex, show_hashes)?;
+                let filter = commands::file::ListFilter {
+                    pattern: pattern.as_deref(),
+                    regex,
+                    kind: kind.as_deref(),
+                    include_special,
+                };
+                commands::file::list(&archive, all, &filter, show_hashes, detect)?;
             }
 
             ArchiveCommands::Analyze {
@@ -581,6 +1175,8 @@ fn main() -> Result<()> {
                 by_extension,
                 unsupported_only,
                 show_stats,
+                fail_on,
+                suggest,
             } => {
                 commands::archive::analyze(
                     &archive,
@@ -588,8 +1184,77 @@ fn main() -> Result<()> {
                     by_extension,
                     unsupported_only,
                     show_stats,
+                    fail_on,
+                    suggest,
+                )?;
+            }
+            ArchiveCommands::Watch {
+                source,
+                archive,
+                compression,
+                ignore_patterns,
+                debounce,
+            } => {
+                let mut options = commands::archive::CreateOptions {
+                    ignore_patterns,
+                    ..Default::default()
+                };
+                if let Some(comp) = compression {
+                    options.compression = comp.flag();
+                }
+
+                commands::archive::watch(
+                    &source,
+                    &archive,
+                    options,
+                    std::time::Duration::from_millis(debounce),
                 )?;
             }
+            ArchiveCommands::Salvage {
+                archive,
+                output_dir,
+            } => {
+                commands::archive::salvage(&archive, &output_dir)?;
+            }
+            ArchiveCommands::Convert {
+                input,
+                destination,
+                to,
+                version,
+                compression,
+                stored,
+                strip_prefix,
+                prefix,
+                include_special,
+            } => {
+                let mut options = mopaq::convert::ConvertOptions::new()
+                    .include_special(include_special)
+                    .zip_method(if stored {
+                        mopaq::convert::ZipMethod::Stored
+                    } else {
+                        mopaq::convert::ZipMethod::Deflated
+                    });
+                if let Some(v) = version {
+                    options = options.version(match v {
+                        1 => FormatVersion::V1,
+                        2 => FormatVersion::V2,
+                        3 => FormatVersion::V3,
+                        4 => FormatVersion::V4,
+                        _ => unreachable!(),
+                    });
+                }
+                if let Some(comp) = compression {
+                    options = options.compression(comp.flag() as u8);
+                }
+                if let Some(strip) = strip_prefix {
+                    options = options.strip_prefix(strip);
+                }
+                if let Some(prefix) = prefix {
+                    options = options.add_prefix(prefix);
+                }
+
+                commands::archive::convert(&input, &destination, to, options)?;
+            }
         },
 
         Commands::File(cmd) => match cmd {
@@ -599,27 +1264,51 @@ fn main() -> Result<()> {
                 pattern,
                 regex,
                 show_hashes,
+                detect,
+                kind,
+                include_special,
             } => {
-                commands::file::list(&archive, all, pattern.as_deref(), regex, show_hashes)?;
+                let filter = commands::file::ListFilter {
+                    pattern: pattern.as_deref(),
+                    regex,
+                    kind: kind.as_deref(),
+                    include_special,
+                };
+                commands::file::list(&archive, all, &filter, show_hashes, detect)?;
             }
             FileCommands::Extract {
                 archive,
                 file,
                 target_directory,
                 preserve_path,
+                index,
+                all_anonymous,
+                locale,
+                on_collision,
+                resume,
             } => {
-                commands::file::extract(
-                    &archive,
-                    file.as_deref(),
-                    target_directory.as_deref(),
-                    preserve_path,
-                )?;
+                if let Some(index) = index {
+                    commands::file::extract_index(&archive, index, target_directory.as_deref())?;
+                } else if all_anonymous {
+                    commands::file::extract_anonymous(&archive, target_directory.as_deref())?;
+                } else {
+                    commands::file::extract(
+                        &archive,
+                        file.as_deref(),
+                        target_directory.as_deref(),
+                        preserve_path,
+                        locale,
+                        on_collision.into(),
+                        resume,
+                    )?;
+                }
             }
             FileCommands::Add {
                 archive,
                 files,
                 compression,
                 path,
+                locale,
             } => {
                 let comp = compression.map(|c| match c {
                     CompressionMethod::None => 0u16,
@@ -633,21 +1322,85 @@ fn main() -> Result<()> {
                         mopaq::compression::flags::ADPCM_STEREO as u16
                     }
                 });
-                commands::file::add(&archive, &files, comp, path.as_deref())?;
+                commands::file::add(&archive, &files, comp, path.as_deref(), locale)?;
             }
-            FileCommands::Remove { archive, files } => {
-                commands::file::remove(&archive, &files)?;
+            FileCommands::Remove {
+                archive,
+                files,
+                locale,
+            } => {
+                commands::file::remove(&archive, &files, locale)?;
+            }
+            FileCommands::Tree {
+                archive,
+                depth,
+                dirs_only,
+                sizes,
+            } => {
+                commands::file::tree(&archive, depth, dirs_only, sizes)?;
             }
             FileCommands::Find {
                 archive,
                 pattern,
                 regex,
+                ignore_case: _,
+            } => {
+                commands::file::find(&archive, &pattern, regex)?;
+            }
+            FileCommands::Grep {
+                archive,
+                pattern,
+                glob,
                 ignore_case,
             } => {
-                commands::file::find(&archive, &pattern, regex, ignore_case)?;
+                commands::file::grep(&archive, &pattern, glob.as_deref(), ignore_case)?;
+            }
+            FileCommands::Info {
+                archive,
+                file,
+                locale,
+            } => {
+                commands::file::info(&archive, &file, locale)?;
+            }
+            FileCommands::Cat {
+                archive,
+                file,
+                encoding,
+                raw,
+            } => {
+                commands::file::cat(&archive, &file, encoding.into(), raw)?;
+            }
+            #[cfg(feature = "dbc")]
+            FileCommands::Show {
+                archive,
+                file,
+                rows,
+            } => {
+                commands::file::show(&archive, &file, rows)?;
             }
-            FileCommands::Info { archive, file } => {
-                commands::file::info(&archive, &file)?;
+            FileCommands::Compare {
+                archive,
+                file,
+                disk_path,
+            } => {
+                commands::file::compare(&archive, &file, &disk_path)?;
+            }
+            FileCommands::TouchListfile {
+                archive,
+                add,
+                remove,
+            } => {
+                commands::file::touch_listfile(&archive, &add, &remove)?;
+            }
+            FileCommands::ExportListfile {
+                archive,
+                listfile,
+                format,
+            } => {
+                commands::file::export_listfile(&archive, &listfile, format.into())?;
+            }
+            FileCommands::ImportListfile { archive, listfile } => {
+                commands::file::import_listfile(&archive, &listfile)?;
             }
         },
 
@@ -665,13 +1418,37 @@ fn main() -> Result<()> {
             }
         },
 
+        Commands::Chain(cmd) => match cmd {
+            ChainCommands::Read {
+                data_dir,
+                locale,
+                file,
+                target,
+            } => {
+                commands::chain::read(&data_dir, &locale, &file, target.as_deref())?;
+            }
+            ChainCommands::List {
+                data_dir,
+                locale,
+                pattern,
+            } => {
+                commands::chain::list(&data_dir, &locale, pattern.as_deref())?;
+            }
+        },
+
         Commands::Hash(cmd) => match cmd {
             HashCommands::Generate {
                 filename,
                 hash_type,
                 all,
-            } => {
-                commands::hash::generate(&filename, hash_type, all)?;
+                from_file,
+            } => match (from_file, filename) {
+                (Some(path), _) => commands::hash::generate_from_file(&path)?,
+                (None, Some(filename)) => commands::hash::generate(&filename, hash_type, all)?,
+                (None, None) => unreachable!("clap requires a filename or --from-file"),
+            },
+            HashCommands::Lookup { archive, filename } => {
+                commands::hash::lookup(&archive, &filename)?;
             }
             HashCommands::Compare {
                 filename1,
@@ -690,15 +1467,22 @@ fn main() -> Result<()> {
             }
         },
 
+        Commands::Selftest => {
+            commands::selftest::run()?;
+        }
+
         Commands::Completion { shell } => {
             // Generate completion script for the specified shell
             let mut cmd = Cli::command();
             let name = cmd.get_name().to_string();
             generate(shell, &mut cmd, name, &mut io::stdout());
+            if let Some(script) = completion::dynamic_script(shell) {
+                print!("{}", script);
+            }
         }
 
         Commands::Config { command } => {
-            handle_config_command(command, &cli.config)?;
+            handle_config_command(command, &cli.config, cli.profile.as_deref())?;
         }
     }
 
@@ -706,9 +1490,13 @@ fn main() -> Result<()> {
 }
 
 /// Handle config commands
+///
+/// With a `profile`, `set` changes that profile, creating it if needed, and
+/// `show` prints the defaults it results in.
 fn handle_config_command(
     command: ConfigCommands,
     config_path_override: &Option<PathBuf>,
+    profile: Option<&str>,
 ) -> Result<()> {
     use colored::Colorize;
 
@@ -734,8 +1522,17 @@ fn handle_config_command(
 
     match command {
         ConfigCommands::Show => {
-            let config = config::load_config(config_path_override.as_ref())?;
-            println!("{}", "Current Configuration:".green().bold());
+            let mut config = config::load_config(config_path_override.as_ref())?;
+            if let Some(name) = profile {
+                config = config.with_profile(name)?;
+                println!(
+                    "{} {}",
+                    "Current Configuration for profile".green().bold(),
+                    name.cyan()
+                );
+            } else {
+                println!("{}", "Current Configuration:".green().bold());
+            }
             println!(
                 "  Default compression: {}",
                 config
@@ -757,6 +1554,10 @@ fn handle_config_command(
                 config.default_output.as_deref().unwrap_or("text").cyan()
             );
 
+            if let Some(patterns) = &config.default_ignore_patterns {
+                println!("  Ignore patterns: {}", patterns.join(", ").cyan());
+            }
+
             if let Some(aliases) = &config.aliases {
                 if !aliases.is_empty() {
                     println!("\n{}:", "Aliases".green().bold());
@@ -765,51 +1566,67 @@ fn handle_config_command(
                     }
                 }
             }
+
+            if profile.is_none() && !config.profiles.is_empty() {
+                println!("\n{}:", "Profiles".green().bold());
+                for (name, profile) in &config.profiles {
+                    let mut settings = Vec::new();
+                    if let Some(compression) = &profile.compression {
+                        settings.push(format!("compression={}", compression));
+                    }
+                    if let Some(version) = profile.version {
+                        settings.push(format!("version={}", version));
+                    }
+                    if let Some(block_size) = profile.block_size {
+                        settings.push(format!("block_size={}", block_size));
+                    }
+                    if let Some(patterns) = &profile.ignore_patterns {
+                        settings.push(format!("ignore={}", patterns.join(",")));
+                    }
+                    println!("  {} {}", name.yellow(), settings.join(" "));
+                }
+            }
         }
 
         ConfigCommands::Set { key, value } => {
             let mut config = config::load_config(config_path_override.as_ref())?;
 
-            match key.as_str() {
-                "default_compression" => {
-                    // Validate compression method
-                    match value.as_str() {
-                        "none" | "zlib" | "bzip2" | "lzma" | "sparse" | "pkware" | "adpcm-mono" | "adpcm-stereo" => {
-                            config.default_compression = Some(value.clone());
-                        }
-                        _ => anyhow::bail!(
-                            "Invalid compression method. Valid values: none, zlib, bzip2, lzma, sparse, pkware, adpcm-mono, adpcm-stereo"
-                        ),
+            if let Some(name) = profile {
+                let profile = config.profiles.entry(name.to_string()).or_default();
+                match key.as_str() {
+                    "default_compression" => profile.compression = Some(parse_compression(&value)?),
+                    "default_version" => profile.version = Some(parse_version(&value)?),
+                    "default_block_size" => profile.block_size = Some(parse_block_size(&value)?),
+                    "default_ignore_patterns" => {
+                        profile.ignore_patterns = Some(parse_patterns(&value))
                     }
+                    _ => anyhow::bail!("Unknown profile setting: {}", key),
                 }
-                "default_version" => {
-                    // Validate version
-                    match value.parse::<u16>() {
-                        Ok(v) if (1..=4).contains(&v) => {
-                            config.default_version = Some(v);
-                        }
-                        _ => anyhow::bail!("Invalid version. Valid values: 1, 2, 3, 4"),
+            } else {
+                match key.as_str() {
+                    "default_compression" => {
+                        config.default_compression = Some(parse_compression(&value)?)
                     }
-                }
-                "default_block_size" => {
-                    // Validate block size
-                    match value.parse::<u16>() {
-                        Ok(bs) if bs <= 23 => {
-                            config.default_block_size = Some(bs);
-                        }
-                        _ => anyhow::bail!("Invalid block size. Valid range: 0-23"),
+                    "default_version" => config.default_version = Some(parse_version(&value)?),
+                    "default_block_size" => {
+                        config.default_block_size = Some(parse_block_size(&value)?)
                     }
-                }
-                "default_output" => {
-                    // Validate output format
-                    match value.as_str() {
-                        "text" | "json" | "csv" => {
-                            config.default_output = Some(value.clone());
+                    "default_ignore_patterns" => {
+                        config.default_ignore_patterns = Some(parse_patterns(&value))
+                    }
+                    "default_output" => {
+                        // Validate output format
+                        match value.as_str() {
+                            "text" | "json" | "csv" => {
+                                config.default_output = Some(value.clone());
+                            }
+                            _ => anyhow::bail!(
+                                "Invalid output format. Valid values: text, json, csv"
+                            ),
                         }
-                        _ => anyhow::bail!("Invalid output format. Valid values: text, json, csv"),
                     }
+                    _ => anyhow::bail!("Unknown configuration key: {}", key),
                 }
-                _ => anyhow::bail!("Unknown configuration key: {}", key),
             }
 
             // Save the updated config
@@ -849,3 +1666,44 @@ fn handle_config_command(
 
     Ok(())
 }
+
+/// Validate a compression method name for the config file
+fn parse_compression(value: &str) -> Result<String> {
+    match value {
+        "none" | "zlib" | "bzip2" | "lzma" | "sparse" | "pkware" | "adpcm-mono" | "adpcm-stereo" => {
+            Ok(value.to_string())
+        }
+        _ => anyhow::bail!(
+            "Invalid compression method. Valid values: none, zlib, bzip2, lzma, sparse, pkware, adpcm-mono, adpcm-stereo"
+        ),
+    }
+}
+
+/// Validate an MPQ format version for the config file
+fn parse_version(value: &str) -> Result<u16> {
+    match value.parse::<u16>() {
+        Ok(v) if (1..=4).contains(&v) => Ok(v),
+        _ => anyhow::bail!("Invalid version. Valid values: 1, 2, 3, 4"),
+    }
+}
+
+/// Validate a block size for the config file
+fn parse_block_size(value: &str) -> Result<u16> {
+    match value.parse::<u16>() {
+        Ok(bs) if bs <= mopaq::MAX_BLOCK_SIZE => Ok(bs),
+        _ => anyhow::bail!(
+            "Invalid block size. Valid range: 0-{}",
+            mopaq::MAX_BLOCK_SIZE
+        ),
+    }
+}
+
+/// Split a comma-separated list of ignore patterns
+fn parse_patterns(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -1,6 +1,8 @@
 use crate::{OutputFormat, GLOBAL_OPTS};
 use colored::*;
-use mopaq::{Archive, ArchiveInfo, FileEntry, SignatureStatus};
+use mopaq::{
+    Archive, ArchiveInfo, DirNode, FileEntry, FileInfo, FileKind, Locale, SignatureStatus,
+};
 use serde::Serialize;
 use std::io;
 
@@ -11,6 +13,131 @@ pub fn print_json<T: Serialize>(data: &T) -> Result<(), io::Error> {
     Ok(())
 }
 
+/// Print a single compact JSON object on its own line
+pub fn print_jsonl<T: Serialize>(data: &T) -> Result<(), io::Error> {
+    let json = serde_json::to_string(data)?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Print one structured value as pretty JSON or as a single JSON line
+///
+/// Any format other than `Jsonl` falls back to pretty-printed JSON, so
+/// commands without a text/CSV rendering can call this unconditionally.
+pub fn print_structured<T: Serialize>(data: &T, format: OutputFormat) -> Result<(), io::Error> {
+    match format {
+        OutputFormat::Jsonl => print_jsonl(data),
+        _ => print_json(data),
+    }
+}
+
+/// Print a list of records as a JSON array, or one record per line for `Jsonl`
+pub fn print_records<T: Serialize>(records: &[T], format: OutputFormat) -> Result<(), io::Error> {
+    match format {
+        OutputFormat::Jsonl => {
+            for record in records {
+                print_jsonl(record)?;
+            }
+            Ok(())
+        }
+        _ => print_json(&records),
+    }
+}
+
+/// Structured form of a file entry, shared by list, find and info output
+#[derive(Debug, Serialize)]
+pub struct FileRecord {
+    pub name: String,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub flags: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash1: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash2: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<&'static str>,
+}
+
+impl From<&FileEntry> for FileRecord {
+    fn from(entry: &FileEntry) -> Self {
+        Self {
+            name: entry.name.clone(),
+            size: entry.size,
+            compressed_size: entry.compressed_size,
+            flags: entry.flags,
+            hash1: entry.hashes.map(|(hash1, _)| format_hash(hash1)),
+            hash2: entry.hashes.map(|(_, hash2)| format_hash(hash2)),
+            kind: None,
+        }
+    }
+}
+
+/// A file of a patch chain and the archive it is read from
+#[derive(Debug, Serialize)]
+pub struct ChainFileRecord {
+    pub name: String,
+    pub archive: String,
+    pub size: u64,
+}
+
+/// A line matched by `file grep`
+#[derive(Debug, Serialize)]
+pub struct GrepMatchRecord {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/// Structured form of one hash table slot
+#[derive(Debug, Serialize)]
+pub struct HashEntryRecord {
+    pub index: usize,
+    pub name_1: String,
+    pub name_2: String,
+    pub locale: u16,
+    pub platform: u16,
+    pub block_index: u32,
+    pub state: &'static str,
+}
+
+impl HashEntryRecord {
+    /// Build a record for the hash table slot at `index`
+    pub fn new(index: usize, entry: &mopaq::tables::HashEntry) -> Self {
+        let state = if entry.is_empty() {
+            "empty"
+        } else if entry.is_deleted() {
+            "deleted"
+        } else {
+            "occupied"
+        };
+        Self {
+            index,
+            name_1: format_hash(entry.name_1),
+            name_2: format_hash(entry.name_2),
+            locale: entry.locale,
+            platform: entry.platform,
+            block_index: entry.block_index,
+            state,
+        }
+    }
+}
+
+/// Structured form of one block table (or BET) entry
+#[derive(Debug, Serialize)]
+pub struct BlockEntryRecord {
+    pub index: usize,
+    pub file_pos: u64,
+    pub compressed_size: u64,
+    pub file_size: u64,
+    pub flags: u32,
+}
+
+/// Format a 32-bit hash value the way all structured output shows hashes
+pub fn format_hash(value: u32) -> String {
+    format!("0x{:08x}", value)
+}
+
 /// Print archive information
 pub fn print_archive_info(archive: &mut Archive, format: OutputFormat) -> Result<(), io::Error> {
     let info = archive.get_info().map_err(|e| {
@@ -22,7 +149,7 @@ pub fn print_archive_info(archive: &mut Archive, format: OutputFormat) -> Result
 
     match format {
         OutputFormat::Text => print_archive_info_text(&info),
-        OutputFormat::Json => print_archive_info_json(&info),
+        OutputFormat::Json | OutputFormat::Jsonl => print_archive_info_json(&info, format),
         OutputFormat::Csv => print_archive_info_csv(&info),
     }
 }
@@ -48,6 +175,20 @@ fn print_archive_info_text(info: &ArchiveInfo) -> Result<(), io::Error> {
         "Format version".bright_cyan(),
         info.format_version as u16 + 1
     );
+    if info.is_truncated {
+        println!(
+            "{}: {}",
+            "Truncated".bright_cyan(),
+            "Yes, the file ends before the archive does".red()
+        );
+    }
+    if let Some(reconciliation) = &info.header_reconciliation {
+        println!(
+            "{}: {}",
+            "Header".bright_cyan(),
+            reconciliation.to_string().yellow()
+        );
+    }
 
     // File statistics
     println!("\n{}", "File Statistics".bold());
@@ -186,7 +327,8 @@ fn print_md5_status(name: &str, valid: bool) {
     );
 }
 
-fn format_size(bytes: u64) -> String {
+/// Format a byte count with a binary unit, e.g. `1.50 KB`
+pub fn format_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
     let mut size = bytes as f64;
     let mut unit_index = 0;
@@ -214,69 +356,37 @@ fn format_signature_status(status: &SignatureStatus) -> ColoredString {
     }
 }
 
-fn print_archive_info_json(info: &ArchiveInfo) -> Result<(), io::Error> {
+fn print_archive_info_json(info: &ArchiveInfo, format: OutputFormat) -> Result<(), io::Error> {
     let json_info = serde_json::json!({
         "path": info.path.display().to_string(),
         "file_size": info.file_size,
         "archive_offset": info.archive_offset,
+        "computed_archive_size": info.computed_archive_size,
         "format_version": info.format_version as u16 + 1,
         "file_count": info.file_count,
         "max_file_count": info.max_file_count,
         "sector_size": info.sector_size,
+        "is_truncated": info.is_truncated,
+        "header_reconciliation": info.header_reconciliation,
         "is_encrypted": info.is_encrypted,
         "has_signature": info.has_signature,
-        "signature_status": format!("{:?}", info.signature_status),
+        "signature_status": info.signature_status,
         "tables": {
-            "hash_table": {
-                "size": info.hash_table_info.size,
-                "offset": info.hash_table_info.offset,
-                "compressed_size": info.hash_table_info.compressed_size,
-                "failed_to_load": info.hash_table_info.failed_to_load,
-            },
-            "block_table": {
-                "size": info.block_table_info.size,
-                "offset": info.block_table_info.offset,
-                "compressed_size": info.block_table_info.compressed_size,
-                "failed_to_load": info.block_table_info.failed_to_load,
-            },
-            "het_table": info.het_table_info.as_ref().map(|t| serde_json::json!({
-                "size": t.size,
-                "offset": t.offset,
-                "compressed_size": t.compressed_size,
-                "failed_to_load": t.failed_to_load,
-            })),
-            "bet_table": info.bet_table_info.as_ref().map(|t| serde_json::json!({
-                "size": t.size,
-                "offset": t.offset,
-                "compressed_size": t.compressed_size,
-                "failed_to_load": t.failed_to_load,
-            })),
-            "hi_block_table": info.hi_block_table_info.as_ref().map(|t| serde_json::json!({
-                "size": t.size,
-                "offset": t.offset,
-                "compressed_size": t.compressed_size,
-                "failed_to_load": t.failed_to_load,
-            })),
+            "hash_table": info.hash_table_info,
+            "block_table": info.block_table_info,
+            "het_table": info.het_table_info,
+            "bet_table": info.bet_table_info,
+            "hi_block_table": info.hi_block_table_info,
         },
         "special_files": {
             "has_attributes": info.has_attributes,
             "has_listfile": info.has_listfile,
         },
-        "user_data": info.user_data_info.as_ref().map(|ud| serde_json::json!({
-            "header_size": ud.header_size,
-            "data_size": ud.data_size,
-        })),
-        "md5_status": info.md5_status.as_ref().map(|md5| serde_json::json!({
-            "header_valid": md5.header_valid,
-            "hash_table_valid": md5.hash_table_valid,
-            "block_table_valid": md5.block_table_valid,
-            "hi_block_table_valid": md5.hi_block_table_valid,
-            "het_table_valid": md5.het_table_valid,
-            "bet_table_valid": md5.bet_table_valid,
-        })),
+        "user_data": info.user_data_info,
+        "md5_status": info.md5_status,
     });
 
-    print_json(&json_info)
+    print_structured(&json_info, format)
 }
 
 fn print_archive_info_csv(info: &ArchiveInfo) -> Result<(), io::Error> {
@@ -284,10 +394,18 @@ fn print_archive_info_csv(info: &ArchiveInfo) -> Result<(), io::Error> {
     println!("path,{}", info.path.display());
     println!("file_size,{}", info.file_size);
     println!("archive_offset,{}", info.archive_offset);
+    println!("computed_archive_size,{}", info.computed_archive_size);
     println!("format_version,{}", info.format_version as u16 + 1);
     println!("file_count,{}", info.file_count);
     println!("max_file_count,{}", info.max_file_count);
     println!("sector_size,{}", info.sector_size);
+    println!("is_truncated,{}", info.is_truncated);
+    println!(
+        "header_reconciliation,{}",
+        info.header_reconciliation
+            .map(|reconciliation| reconciliation.to_string())
+            .unwrap_or_default()
+    );
     println!("is_encrypted,{}", info.is_encrypted);
     println!("has_signature,{}", info.has_signature);
     println!("signature_status,{:?}", info.signature_status);
@@ -312,24 +430,9 @@ pub fn print_file_list_with_hashes(
             }
             println!("\nTotal: {} files", files.len());
         }
-        OutputFormat::Json => {
-            let json_files: Vec<serde_json::Value> = files
-                .iter()
-                .map(|entry| {
-                    if let Some((hash1, hash2)) = entry.hashes {
-                        serde_json::json!({
-                            "name": entry.name,
-                            "hash1": format!("{:08X}", hash1),
-                            "hash2": format!("{:08X}", hash2),
-                        })
-                    } else {
-                        serde_json::json!({
-                            "name": entry.name,
-                        })
-                    }
-                })
-                .collect();
-            print_json(&json_files)?;
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let records: Vec<FileRecord> = files.iter().map(FileRecord::from).collect();
+            print_records(&records, format)?;
         }
         OutputFormat::Csv => {
             if files.iter().any(|f| f.hashes.is_some()) {
@@ -353,27 +456,356 @@ pub fn print_file_list_with_hashes(
 }
 
 /// Print file list
-pub fn print_file_list(files: &[String], format: OutputFormat) -> Result<(), io::Error> {
+pub fn print_file_list(files: &[FileEntry], format: OutputFormat) -> Result<(), io::Error> {
     match format {
         OutputFormat::Text => {
             for file in files {
-                println!("{}", file);
+                println!("{}", file.name);
             }
             println!("\nTotal: {} files", files.len());
         }
-        OutputFormat::Json => {
-            print_json(&files)?;
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let records: Vec<FileRecord> = files.iter().map(FileRecord::from).collect();
+            print_records(&records, format)?;
         }
         OutputFormat::Csv => {
             println!("filename");
             for file in files {
-                println!("{}", file);
+                println!("{}", file.name);
             }
         }
     }
     Ok(())
 }
 
+/// Print the files of a patch chain with the archive each is read from
+pub fn print_chain_list(
+    files: &[ChainFileRecord],
+    archive_count: usize,
+    format: OutputFormat,
+) -> Result<(), io::Error> {
+    match format {
+        OutputFormat::Text => {
+            let width = files.iter().map(|file| file.name.len()).max().unwrap_or(0);
+            for file in files {
+                println!("{:<width$}  {}", file.name, file.archive.cyan());
+            }
+            println!(
+                "\nTotal: {} files from {} archives",
+                files.len(),
+                archive_count
+            );
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => print_records(files, format)?,
+        OutputFormat::Csv => {
+            println!("filename,archive,size");
+            for file in files {
+                println!("{},{},{}", file.name, file.archive, file.size);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print a file list with the detected content type of each file
+pub fn print_file_list_with_kinds(
+    files: &[(FileEntry, FileKind)],
+    format: OutputFormat,
+) -> Result<(), io::Error> {
+    match format {
+        OutputFormat::Text => {
+            for (entry, kind) in files {
+                println!("{:<8} {}", kind.id().cyan(), entry.name);
+            }
+            println!("\nTotal: {} files", files.len());
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let records: Vec<FileRecord> = files
+                .iter()
+                .map(|(entry, kind)| FileRecord {
+                    kind: Some(kind.id()),
+                    ..FileRecord::from(entry)
+                })
+                .collect();
+            print_records(&records, format)?;
+        }
+        OutputFormat::Csv => {
+            println!("filename,kind");
+            for (entry, kind) in files {
+                println!("{},{}", entry.name, kind.id());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the lines matched by `file grep`
+pub fn print_grep_matches(
+    matches: &[GrepMatchRecord],
+    files_searched: usize,
+    quiet: bool,
+    format: OutputFormat,
+) -> Result<(), io::Error> {
+    match format {
+        OutputFormat::Text => {
+            for record in matches {
+                println!(
+                    "{}:{}:{}",
+                    record.file.magenta(),
+                    record.line.to_string().green(),
+                    record.text
+                );
+            }
+            if !quiet {
+                println!(
+                    "\n{} matches in {} files searched",
+                    matches.len(),
+                    files_searched
+                );
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            print_records(matches, format)?;
+        }
+        OutputFormat::Csv => {
+            println!("file,line,text");
+            for record in matches {
+                println!(
+                    "{},{},{}",
+                    csv_field(&record.file),
+                    record.line,
+                    csv_field(&record.text)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the header and first rows of a database file
+#[cfg(feature = "dbc")]
+pub fn print_dbc_preview(
+    preview: &crate::dbc::DbcPreview,
+    filename: &str,
+    format: OutputFormat,
+) -> Result<(), io::Error> {
+    let header = &preview.header;
+    match format {
+        OutputFormat::Text => {
+            println!("{}", filename.bold());
+            println!("{}", "=".repeat(50));
+            println!("Format:       {}", header.format);
+            println!("Records:      {}", header.record_count);
+            println!("Fields:       {}", header.field_count);
+            println!("Record size:  {} bytes", header.record_size);
+            println!("String block: {} bytes", header.string_block_size);
+
+            if preview.columns.is_empty() {
+                println!("\n{}", "Rows of this format cannot be previewed".yellow());
+                return Ok(());
+            }
+
+            let titles: Vec<String> = (0..preview.columns.len())
+                .map(|column| format!("f{}", column))
+                .collect();
+            let cells: Vec<Vec<String>> = preview
+                .rows
+                .iter()
+                .map(|row| row.iter().map(ToString::to_string).collect())
+                .collect();
+            let widths: Vec<usize> = titles
+                .iter()
+                .enumerate()
+                .map(|(column, title)| {
+                    cells
+                        .iter()
+                        .map(|row| row[column].chars().count())
+                        .chain([title.len()])
+                        .max()
+                        .unwrap_or_default()
+                })
+                .collect();
+
+            println!();
+            let line: Vec<String> = titles
+                .iter()
+                .zip(&widths)
+                .map(|(title, &width)| format!("{:>width$}", title))
+                .collect();
+            println!("{}", line.join("  ").bold());
+            for row in &cells {
+                let line: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, &width)| format!("{:>width$}", cell))
+                    .collect();
+                println!("{}", line.join("  "));
+            }
+            if preview.rows.len() < header.record_count as usize {
+                println!(
+                    "\nShowing {} of {} records",
+                    preview.rows.len(),
+                    header.record_count
+                );
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            print_structured(preview, format)?;
+        }
+        OutputFormat::Csv => {
+            let titles: Vec<String> = (0..preview.columns.len())
+                .map(|column| format!("f{}", column))
+                .collect();
+            println!("{}", titles.join(","));
+            for row in &preview.rows {
+                let line: Vec<String> = row
+                    .iter()
+                    .map(|value| match value {
+                        crate::dbc::Value::String(text) => csv_field(text),
+                        other => other.to_string(),
+                    })
+                    .collect();
+                println!("{}", line.join(","));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+pub fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Print a directory tree
+///
+/// `max_depth` limits how many directory levels below the root are shown;
+/// files are left out when `dirs_only` is set and cumulative sizes are added
+/// when `sizes` is set. Structured formats always include the whole tree
+/// with sizes.
+pub fn print_tree(
+    root: &DirNode,
+    max_depth: Option<usize>,
+    dirs_only: bool,
+    sizes: bool,
+    format: OutputFormat,
+) -> Result<(), io::Error> {
+    match format {
+        OutputFormat::Text => {
+            let options = TreeOptions {
+                max_depth,
+                dirs_only,
+                sizes,
+            };
+            println!("{}{}", ".".bold(), options.dir_summary(root));
+            print_tree_children(root, "", 1, &options);
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            print_structured(&tree_json(root, dirs_only), format)?;
+        }
+        OutputFormat::Csv => {
+            println!("directory,file_count,total_size,total_compressed_size");
+            root.walk(|dir, _| {
+                println!(
+                    "{},{},{},{}",
+                    dir.path, dir.file_count, dir.total_size, dir.total_compressed_size
+                );
+            });
+        }
+    }
+    Ok(())
+}
+
+struct TreeOptions {
+    max_depth: Option<usize>,
+    dirs_only: bool,
+    sizes: bool,
+}
+
+impl TreeOptions {
+    fn dir_summary(&self, dir: &DirNode) -> String {
+        if self.sizes {
+            format!(
+                " ({} files, {})",
+                dir.file_count,
+                format_size(dir.total_size)
+            )
+        } else {
+            String::new()
+        }
+    }
+}
+
+fn print_tree_children(dir: &DirNode, prefix: &str, depth: usize, options: &TreeOptions) {
+    if options.max_depth.is_some_and(|max| depth > max) {
+        return;
+    }
+
+    let file_count = if options.dirs_only {
+        0
+    } else {
+        dir.files.len()
+    };
+    let total = dir.dirs.len() + file_count;
+    for (index, child) in dir.dirs.iter().enumerate() {
+        let last = index + 1 == total;
+        println!(
+            "{}{} {}{}",
+            prefix,
+            if last { "└──" } else { "├──" },
+            child.name.blue().bold(),
+            options.dir_summary(child)
+        );
+        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        print_tree_children(child, &child_prefix, depth + 1, options);
+    }
+    for (index, file) in dir.files.iter().take(file_count).enumerate() {
+        let last = dir.dirs.len() + index + 1 == total;
+        let size = if options.sizes {
+            format!(" ({})", format_size(file.size))
+        } else {
+            String::new()
+        };
+        println!(
+            "{}{} {}{}",
+            prefix,
+            if last { "└──" } else { "├──" },
+            file.name,
+            size
+        );
+    }
+}
+
+fn tree_json(dir: &DirNode, dirs_only: bool) -> serde_json::Value {
+    let mut node = serde_json::json!({
+        "name": dir.name,
+        "path": dir.path,
+        "file_count": dir.file_count,
+        "total_size": dir.total_size,
+        "total_compressed_size": dir.total_compressed_size,
+        "dirs": dir.dirs.iter().map(|child| tree_json(child, dirs_only)).collect::<Vec<_>>(),
+    });
+    if !dirs_only {
+        node["files"] = dir
+            .files
+            .iter()
+            .map(|file| {
+                serde_json::json!({
+                    "name": file.name,
+                    "path": file.path,
+                    "size": file.size,
+                    "compressed_size": file.compressed_size,
+                })
+            })
+            .collect();
+    }
+    node
+}
+
 /// Print file list with verbose information
 pub fn print_file_list_verbose(files: &[FileEntry]) -> Result<(), io::Error> {
     use mopaq::BlockEntry;
@@ -550,24 +982,59 @@ pub fn print_file_list_verbose(files: &[FileEntry]) -> Result<(), io::Error> {
 }
 
 /// Print file information
-pub fn print_file_info(filename: &str, size: u64, format: OutputFormat) -> Result<(), io::Error> {
+pub fn print_file_info(
+    info: &FileInfo,
+    kind: FileKind,
+    locales: &[Locale],
+    archive_path: &str,
+    format: OutputFormat,
+) -> Result<(), io::Error> {
+    let record = serde_json::json!({
+        "archive": archive_path,
+        "filename": info.filename,
+        "size": info.file_size,
+        "compressed_size": info.compressed_size,
+        "flags": info.flags,
+        "file_pos": info.file_pos,
+        "hash_index": info.hash_index,
+        "block_index": info.block_index,
+        "locale": info.locale,
+        "locales": locales,
+        "platform": info.platform,
+        "compressed": info.is_compressed(),
+        "encrypted": info.is_encrypted(),
+        "content_type": kind.id(),
+    });
+
     match format {
         OutputFormat::Text => {
             println!("{}", "File Information".bold());
             println!("{}", "=".repeat(50));
-            println!("Filename: {}", filename);
-            println!("Size:     {} bytes", size);
+            println!("Filename:   {}", info.filename);
+            println!("Type:       {}", kind);
+            println!("Size:       {} bytes", info.file_size);
+            println!("Compressed: {} bytes", info.compressed_size);
+            println!("Flags:      0x{:08X}", info.flags);
+            println!("Position:   0x{:X}", info.file_pos);
+            println!("Locale:     0x{:04X} ({})", info.locale.code(), info.locale);
+            if locales.len() > 1 {
+                let names: Vec<String> = locales.iter().map(ToString::to_string).collect();
+                println!("Stored in:  {}", names.join(", "));
+            }
         }
-        OutputFormat::Json => {
-            let info = serde_json::json!({
-                "filename": filename,
-                "size": size
-            });
-            print_json(&info)?;
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            print_structured(&record, format)?;
         }
         OutputFormat::Csv => {
-            println!("filename,size");
-            println!("{},{}", filename, size);
+            println!("filename,size,compressed_size,flags,content_type");
+            println!(
+                "{},{},{},{},{}",
+                info.filename,
+                info.file_size,
+                info.compressed_size,
+                info.flags,
+                kind.id()
+            );
         }
     }
     Ok(())
@@ -0,0 +1,157 @@
+//! Text encoding detection and decoding for `file cat`
+//!
+//! Script and data files in Blizzard archives predate any encoding
+//! convention: `war3map.j` and friends are usually UTF-8 or Windows-1252,
+//! while some tools saved UTF-16. Only these encodings are handled, which
+//! keeps the CLI free of a full encoding library.
+
+use std::fmt;
+
+/// A text encoding `file cat` can decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Windows-1252, the western code page; a superset of Latin-1
+    Cp1252,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Cp1252 => "cp1252",
+        })
+    }
+}
+
+/// Windows-1252 characters for bytes 0x80-0x9F; the rest match Latin-1
+///
+/// The five unassigned bytes map to the C1 control with the same value.
+const CP1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// Guess the encoding of `data`
+///
+/// A byte order mark wins; otherwise UTF-16 is recognized by the zero
+/// bytes of ASCII characters, valid UTF-8 is taken as UTF-8 and anything
+/// else falls back to Windows-1252.
+pub fn detect(data: &[u8]) -> Encoding {
+    if data.starts_with(b"\xEF\xBB\xBF") {
+        return Encoding::Utf8;
+    }
+    if data.starts_with(b"\xFF\xFE") {
+        return Encoding::Utf16Le;
+    }
+    if data.starts_with(b"\xFE\xFF") {
+        return Encoding::Utf16Be;
+    }
+
+    let sample = &data[..data.len().min(1024) & !1];
+    if sample.len() >= 4 {
+        let pairs = sample.len() / 2;
+        let zero_at = |parity: usize| {
+            sample
+                .chunks_exact(2)
+                .filter(|pair| pair[parity] == 0 && pair[1 - parity] != 0)
+                .count()
+        };
+        if zero_at(1) * 10 >= pairs * 9 {
+            return Encoding::Utf16Le;
+        }
+        if zero_at(0) * 10 >= pairs * 9 {
+            return Encoding::Utf16Be;
+        }
+    }
+
+    if std::str::from_utf8(data).is_ok() {
+        Encoding::Utf8
+    } else {
+        Encoding::Cp1252
+    }
+}
+
+/// Decode `data` as `encoding`, dropping a byte order mark
+///
+/// Invalid sequences become U+FFFD rather than failing.
+pub fn decode(data: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Utf8 => {
+            let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+            String::from_utf8_lossy(data).into_owned()
+        }
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let units = data.chunks_exact(2).map(|pair| match encoding {
+                Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+            let mut text: String = char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+            if text.starts_with('\u{FEFF}') {
+                text.remove(0);
+            }
+            if data.len() % 2 == 1 {
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+            text
+        }
+        Encoding::Cp1252 => data
+            .iter()
+            .map(|&byte| match byte {
+                0x80..=0x9F => CP1252_HIGH[byte as usize - 0x80],
+                _ => byte as char,
+            })
+            .collect(),
+    }
+}
+
+/// Check whether decoded text contains control characters that plain text
+/// would not
+pub fn looks_binary(text: &str) -> bool {
+    text.chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0C' | '\x1A'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_and_decode() {
+        let utf16le: Vec<u8> = "function main takes nothing\r\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(detect(&utf16le), Encoding::Utf16Le);
+        assert_eq!(
+            decode(&utf16le, Encoding::Utf16Le),
+            "function main takes nothing\r\n"
+        );
+
+        let utf16be: Vec<u8> = [0xFE, 0xFF, 0x00, b'h', 0x00, b'i'].to_vec();
+        assert_eq!(detect(&utf16be), Encoding::Utf16Be);
+        assert_eq!(decode(&utf16be, Encoding::Utf16Be), "hi");
+
+        let utf8 = "\u{FEFF}Ürün".as_bytes();
+        assert_eq!(detect(utf8), Encoding::Utf8);
+        assert_eq!(decode(utf8, Encoding::Utf8), "Ürün");
+
+        let cp1252 = b"caf\xE9 \x80 \x93quoted\x94";
+        assert_eq!(detect(cp1252), Encoding::Cp1252);
+        assert_eq!(
+            decode(cp1252, Encoding::Cp1252),
+            "caf\u{e9} \u{20AC} \u{201C}quoted\u{201D}"
+        );
+
+        assert!(looks_binary(&decode(b"BLP2\x00\x01", Encoding::Utf8)));
+        assert!(!looks_binary("line\r\n\tindented"));
+    }
+}
@@ -0,0 +1,45 @@
+//! Integration tests for archive analysis
+
+use assert_cmd::Command;
+use mopaq::ArchiveBuilder;
+use predicates::prelude::*;
+use tempfile::TempDir;
+
+#[test]
+fn test_analyze_suggest_sector_size() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("small_files.mpq");
+
+    // Small files in 64 KB sectors
+    let mut builder = ArchiveBuilder::new().block_size(7);
+    for i in 0..20 {
+        builder = builder.add_file_data(vec![i as u8; 3_000], &format!("file{}.dat", i));
+    }
+    builder.build(&archive_path).unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "analyze", "--suggest"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Sector Size Suggestion"))
+        .stdout(predicate::str::contains("--block-size 3"));
+
+    let output = Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["--output", "json", "archive", "analyze", "--suggest"])
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let suggestion = &result["sector_size_suggestion"];
+    assert_eq!(suggestion["current"]["block_size"], 7);
+    assert_eq!(suggestion["suggested"]["block_size"], 3);
+    assert_eq!(suggestion["suggested"]["sector_size"], 4096);
+    assert!(
+        suggestion["suggested"]["slack_bytes"].as_u64().unwrap()
+            < suggestion["current"]["slack_bytes"].as_u64().unwrap()
+    );
+}
@@ -0,0 +1,70 @@
+//! Integration tests for the file cat command
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_file_cat() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    let script: Vec<u8> = "\u{FEFF}function main takes nothing returns nothing\r\nendfunction\r\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let binary = b"BLP2\x01\x00\x00\x00\x00\xFF".to_vec();
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("war3map.j"), &script).unwrap();
+    fs::write(source_dir.join("legacy.txt"), b"caf\xE9\n").unwrap();
+    fs::write(source_dir.join("texture.blp"), &binary).unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "create"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "cat"])
+        .arg(&archive_path)
+        .arg("war3map.j")
+        .assert()
+        .success()
+        .stdout("function main takes nothing returns nothing\r\nendfunction\r\n");
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "cat", "--output", "json"])
+        .arg(&archive_path)
+        .arg("legacy.txt")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"encoding\": \"cp1252\""))
+        .stdout(predicate::str::contains("café"));
+
+    // Binary data needs --raw and then comes out unchanged
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "cat"])
+        .arg(&archive_path)
+        .arg("texture.blp")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--raw"));
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "cat", "--raw"])
+        .arg(&archive_path)
+        .arg("texture.blp")
+        .assert()
+        .success()
+        .stdout(binary);
+}
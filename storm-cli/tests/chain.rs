@@ -0,0 +1,102 @@
+//! Integration tests for patch chain commands
+
+use assert_cmd::Command;
+use mopaq::ArchiveBuilder;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// A data directory with a base archive, a patch and a locale patch
+fn data_dir() -> TempDir {
+    let temp_dir = TempDir::new().unwrap();
+    let build = |path: &Path, files: &[(&str, &[u8])]| {
+        let mut builder = ArchiveBuilder::new();
+        for (name, data) in files {
+            builder = builder.add_file_data(data.to_vec(), name);
+        }
+        builder.build(path).unwrap();
+    };
+    fs::create_dir(temp_dir.path().join("enUS")).unwrap();
+    build(
+        &temp_dir.path().join("common.MPQ"),
+        &[("Data\\a.txt", b"base"), ("Data\\b.txt", b"base")],
+    );
+    build(
+        &temp_dir.path().join("patch.MPQ"),
+        &[("Data\\a.txt", b"patched")],
+    );
+    build(
+        &temp_dir.path().join("enUS").join("patch-enUS.MPQ"),
+        &[("Data\\c.txt", b"locale")],
+    );
+    temp_dir
+}
+
+#[test]
+fn test_chain_read() {
+    let data_dir = data_dir();
+    let target = data_dir.path().join("out").join("a.txt");
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["chain", "read", "--locale", "enUS", "--data-dir"])
+        .arg(data_dir.path())
+        .arg("data\\A.TXT")
+        .arg("--target")
+        .arg(&target)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("patch.MPQ"));
+    assert_eq!(fs::read(&target).unwrap(), b"patched");
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["chain", "read", "--data-dir"])
+        .arg(data_dir.path())
+        .arg("Data\\missing.txt")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_chain_list() {
+    let data_dir = data_dir();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["chain", "list", "--data-dir"])
+        .arg(data_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Total: 3 files from 3 archives"));
+
+    let output = Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["--output", "json", "chain", "list", "--data-dir"])
+        .arg(data_dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let records: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let winners: Vec<(&str, &str)> = records
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|record| {
+            (
+                record["name"].as_str().unwrap(),
+                record["archive"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    let locale_patch = Path::new("enUS").join("patch-enUS.MPQ");
+    assert_eq!(
+        winners,
+        [
+            ("Data\\a.txt", "patch.MPQ"),
+            ("Data\\b.txt", "common.MPQ"),
+            ("Data\\c.txt", locale_patch.to_str().unwrap()),
+        ]
+    );
+}
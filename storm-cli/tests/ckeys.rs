@@ -0,0 +1,39 @@
+//! Integration tests for printing content keys
+
+use assert_cmd::Command;
+use mopaq::ArchiveBuilder;
+use predicates::prelude::*;
+use tempfile::TempDir;
+
+#[test]
+fn test_archive_ckeys() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("ckeys.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"hello".to_vec(), "Data\\readme.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    // MD5 of "hello"
+    let ckey = "5d41402abc4b2a76b9719d911017c592";
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "ckeys"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(format!(
+            "Path!STRING:0|CKey!HEX:16|Size!DEC:8\nData/readme.txt|{}|5\n",
+            ckey
+        ));
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["-o", "json", "archive", "ckeys"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("\"ckey\": \"{}\"", ckey)))
+        .stdout(predicate::str::contains("\"path\": \"Data/readme.txt\""))
+        .stdout(predicate::str::contains("(listfile)").not());
+}
@@ -20,7 +20,8 @@ fn test_cli_version() {
     cmd.arg("--version")
         .assert()
         .success()
-        .stdout(predicate::str::contains("storm-cli"));
+        .stdout(predicate::str::contains("storm-cli"))
+        .stdout(predicate::str::contains("codecs: zlib"));
 }
 
 #[test]
@@ -144,3 +145,18 @@ fn test_hash_generate_invalid_type() {
         .assert()
         .failure();
 }
+
+#[test]
+fn test_archive_watch_rejects_archive_inside_source() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("inside.mpq");
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("archive")
+        .arg("watch")
+        .arg(temp_dir.path())
+        .arg(&archive_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("must not be inside"));
+}
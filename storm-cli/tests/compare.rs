@@ -0,0 +1,61 @@
+//! Integration tests for the file compare command
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+fn create_archive(temp_dir: &TempDir) -> std::path::PathBuf {
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("patch.txt"), "Deployed content").unwrap();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("archive")
+        .arg("create")
+        .arg(archive_path.to_str().unwrap())
+        .arg(source_dir.to_str().unwrap())
+        .assert()
+        .success();
+
+    archive_path
+}
+
+#[test]
+fn test_compare_matching_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = create_archive(&temp_dir);
+    let disk_path = temp_dir.path().join("source").join("patch.txt");
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("compare")
+        .arg(&archive_path)
+        .arg("patch.txt")
+        .arg(&disk_path)
+        .arg("--output")
+        .arg("json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"matches\": true"));
+}
+
+#[test]
+fn test_compare_different_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = create_archive(&temp_dir);
+    let disk_path = temp_dir.path().join("other.txt");
+    fs::write(&disk_path, "Stale content").unwrap();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("compare")
+        .arg(&archive_path)
+        .arg("patch.txt")
+        .arg(&disk_path)
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("differs"));
+}
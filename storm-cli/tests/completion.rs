@@ -2,6 +2,8 @@
 
 use assert_cmd::Command;
 use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
 
 #[test]
 fn test_completion_command_exists() {
@@ -21,7 +23,8 @@ fn test_generate_bash_completion() {
         .assert()
         .success()
         .stdout(predicate::str::contains("_storm-cli()"))
-        .stdout(predicate::str::contains("complete -F"));
+        .stdout(predicate::str::contains("complete -F"))
+        .stdout(predicate::str::contains("__complete-files"));
 }
 
 #[test]
@@ -53,3 +56,57 @@ fn test_generate_powershell_completion() {
         .success()
         .stdout(predicate::str::contains("storm-cli"));
 }
+
+#[test]
+fn test_complete_archive_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(source_dir.join("Units").join("Human")).unwrap();
+    fs::create_dir_all(source_dir.join("Units").join("Orc")).unwrap();
+    fs::write(
+        source_dir.join("Units").join("Human").join("Footman.mdx"),
+        b"a",
+    )
+    .unwrap();
+    fs::write(source_dir.join("Units").join("Orc").join("Grunt.mdx"), b"b").unwrap();
+    fs::write(source_dir.join("war3map.j"), b"c").unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "create"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    let complete = |current: &str, words: &[&str]| {
+        let output = Command::cargo_bin("storm-cli")
+            .unwrap()
+            .arg("__complete-files")
+            .arg(current)
+            .args(words)
+            .arg(&archive_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // Directories complete one level at a time, keeping the typed case
+    assert_eq!(complete("uni", &["file", "extract"]), "units/\n");
+    assert_eq!(
+        complete("Units/", &["-o", "json", "file", "cat"]),
+        "Units/Human/\nUnits/Orc/\n"
+    );
+    assert_eq!(
+        complete("units\\orc\\", &["file", "info"]),
+        "units/orc/Grunt.mdx\n"
+    );
+    assert!(complete("", &["file", "remove"]).contains("war3map.j"));
+
+    // Not a file argument
+    assert_eq!(complete("", &["file", "list"]), "");
+    assert_eq!(complete("", &["archive", "info"]), "");
+}
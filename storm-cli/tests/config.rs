@@ -0,0 +1,224 @@
+//! Integration tests for config profiles
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_profile_defaults_for_create() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let config_path = temp_dir.path().join("config.toml");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("readme.txt"), b"hello").unwrap();
+    fs::write(source_dir.join("artwork.psd"), b"layers").unwrap();
+    fs::write(
+        &config_path,
+        r#"
+default_version = 1
+
+[profile.wow]
+version = 2
+block_size = 4
+ignore_patterns = [".psd"]
+"#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .args(["--profile", "wow", "archive", "create"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    let output = Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "info", "-o", "json"])
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["format_version"], 2);
+    assert_eq!(info["sector_size"], 8192);
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "list"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("readme.txt"))
+        .stdout(predicate::str::contains("artwork.psd").not());
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .args(["--profile", "wc3", "archive", "create"])
+        .arg(temp_dir.path().join("other.mpq"))
+        .arg(&source_dir)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Available profiles: wow"));
+}
+
+#[test]
+fn test_config_set_profile() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .args([
+            "--profile",
+            "wc3",
+            "config",
+            "set",
+            "default_compression",
+            "pkware",
+        ])
+        .assert()
+        .success();
+
+    let contents = fs::read_to_string(&config_path).unwrap();
+    assert!(contents.contains("[profile.wc3]"));
+    assert!(contents.contains("compression = \"pkware\""));
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .args(["--profile", "wc3", "config", "show"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Default compression: pkware"));
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .args([
+            "--profile",
+            "wc3",
+            "config",
+            "set",
+            "default_output",
+            "json",
+        ])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_alias_expansion() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let config_path = temp_dir.path().join("config.toml");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("readme.txt"), b"hello").unwrap();
+    fs::write(
+        &config_path,
+        r#"
+[aliases]
+pack = "archive create -V 2 $1 $2"
+ls = "file list"
+"#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .arg("pack")
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .arg("--no-listfile")
+        .assert()
+        .success();
+
+    // The appended --no-listfile leaves only the one file
+    let output = Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("--config")
+        .arg(&config_path)
+        .args(["-o", "json", "ls"])
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let files: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(files.len(), 1);
+
+    let output = Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "info", "-o", "json"])
+        .arg(&archive_path)
+        .output()
+        .unwrap();
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["format_version"], 2);
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .arg("pack")
+        .arg(&archive_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("uses $2"));
+}
+
+#[test]
+fn test_malformed_config_only_blocks_aliases() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("config.toml");
+    fs::write(&config_path, "[aliases\nls = ").unwrap();
+
+    for args in [&["--version"][..], &["--help"], &["file", "list", "--help"]] {
+        Command::cargo_bin("storm-cli")
+            .unwrap()
+            .arg("-C")
+            .arg(&config_path)
+            .args(args)
+            .assert()
+            .success();
+    }
+
+    // A word that may be an alias needs the config
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .arg("ls")
+        .assert()
+        .failure();
+
+    // The config commands can repair the file
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .args(["config", "reset"])
+        .assert()
+        .success();
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .arg("-C")
+        .arg(&config_path)
+        .args(["config", "show"])
+        .assert()
+        .success();
+}
@@ -0,0 +1,69 @@
+//! Integration tests for converting archives to and from ZIP
+
+use assert_cmd::Command;
+use mopaq::{Archive, ArchiveBuilder};
+use predicates::prelude::*;
+use tempfile::TempDir;
+
+#[test]
+fn test_archive_convert_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("source.mpq");
+    let zip_path = temp_dir.path().join("export.zip");
+    let imported_path = temp_dir.path().join("imported.mpq");
+
+    ArchiveBuilder::new()
+        .add_file_data(b"hello".to_vec(), "readme.txt")
+        .add_file_data(vec![7; 10_000], "Data\\big.bin")
+        .build(&archive_path)
+        .unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "convert"])
+        .arg(&archive_path)
+        .arg(&zip_path)
+        .args(["--to", "zip", "--prefix", "MyMod/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Converted 2 files"));
+    assert!(fs_starts_with(&zip_path, b"PK\x03\x04"));
+
+    // The input format is detected, and ZIPs convert to MPQ by default
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "convert"])
+        .arg(&zip_path)
+        .arg(&imported_path)
+        .args(["--strip-prefix", "MyMod/", "-c", "bzip2"])
+        .assert()
+        .success();
+
+    let archive = Archive::open(&imported_path).unwrap();
+    assert_eq!(archive.read_file("readme.txt").unwrap(), b"hello");
+    assert_eq!(archive.read_file("Data\\big.bin").unwrap(), vec![7; 10_000]);
+}
+
+#[test]
+fn test_archive_convert_rejects_unsupported_pair() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("source.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"hello".to_vec(), "readme.txt")
+        .build(&archive_path)
+        .unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "convert"])
+        .arg(&archive_path)
+        .arg(temp_dir.path().join("out.tar"))
+        .args(["--to", "tar"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Cannot convert"));
+}
+
+fn fs_starts_with(path: &std::path::Path, prefix: &[u8]) -> bool {
+    std::fs::read(path).unwrap().starts_with(prefix)
+}
@@ -48,7 +48,16 @@ fn test_create_simple_archive() {
         .assert()
         .success()
         .stdout(predicate::str::contains("file1.txt"))
-        .stdout(predicate::str::contains("file2.txt"));
+        .stdout(predicate::str::contains("file2.txt"))
+        .stdout(predicate::str::contains("(listfile)").not());
+
+    // Special files are listed on request
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.args(["file", "list", "--include-special"])
+        .arg(archive_path.to_str().unwrap())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(listfile)"));
 }
 
 #[test]
@@ -151,7 +160,10 @@ fn test_archive_info_json_output() {
         .stdout(predicate::str::contains("\"file_count\""))
         .stdout(predicate::str::contains("\"format_version\""))
         .stdout(predicate::str::contains("\"hash_table\""))
-        .stdout(predicate::str::contains("\"block_table\""));
+        .stdout(predicate::str::contains("\"block_table\""))
+        .stdout(predicate::str::contains("\"failed_to_load\": false"))
+        .stdout(predicate::str::contains("\"signature_status\": \"None\""))
+        .stdout(predicate::str::contains("\"is_truncated\": false"));
 }
 
 #[test]
@@ -182,3 +194,262 @@ fn test_create_no_listfile() {
         .success()
         .stdout(predicate::str::contains("file_"));
 }
+
+#[test]
+fn test_file_list_jsonl_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.txt"), "First").unwrap();
+    fs::write(source_dir.join("b.txt"), "Second").unwrap();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("archive")
+        .arg("create")
+        .arg(archive_path.to_str().unwrap())
+        .arg(source_dir.to_str().unwrap())
+        .assert()
+        .success();
+
+    // Every line must be a standalone JSON object
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    let output = cmd
+        .arg("file")
+        .arg("list")
+        .arg(archive_path.to_str().unwrap())
+        .arg("--output")
+        .arg("jsonl")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let names: Vec<String> = stdout
+        .lines()
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            record["name"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert!(names.contains(&"a.txt".to_string()));
+    assert!(names.contains(&"b.txt".to_string()));
+
+    // Hashes are formatted like every other JSON record
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    let output = cmd
+        .arg("file")
+        .arg("list")
+        .arg(archive_path.to_str().unwrap())
+        .arg("--show-hashes")
+        .arg("--output")
+        .arg("jsonl")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let line = stdout.lines().next().unwrap();
+    let record: serde_json::Value = serde_json::from_str(line).unwrap();
+    for key in ["hash1", "hash2"] {
+        let hash = record[key].as_str().unwrap();
+        assert!(hash.starts_with("0x") && hash.len() == 10);
+        assert_eq!(hash, hash.to_lowercase());
+    }
+}
+
+#[test]
+fn test_table_show_json_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("test.txt"), "Test").unwrap();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("archive")
+        .arg("create")
+        .arg(archive_path.to_str().unwrap())
+        .arg(source_dir.to_str().unwrap())
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    let output = cmd
+        .arg("table")
+        .arg("show")
+        .arg(archive_path.to_str().unwrap())
+        .arg("--occupied-only")
+        .arg("--output")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let entries: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    // test.txt plus the listfile
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|entry| entry["state"] == "occupied"));
+}
+
+#[test]
+fn test_create_with_locale() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("strings.txt"), "Hallo, MPQ!").unwrap();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.args(["archive", "create", "--locale", "deDE"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.args(["file", "info"])
+        .arg(&archive_path)
+        .arg("strings.txt")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0x0407 (deDE)"));
+
+    // Unknown locale names are rejected
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.args(["archive", "create", "--locale", "xxXX"])
+        .arg(temp_dir.path().join("other.mpq"))
+        .arg(&source_dir)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown locale"));
+}
+
+#[test]
+fn test_archive_build_manifest() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    fs::write(dir.join("war3map.j"), "function main takes nothing").unwrap();
+    fs::write(
+        dir.join("manifest.toml"),
+        r#"
+        [archive]
+        version = 2
+
+        [[file]]
+        name = "scripts\\war3map.j"
+        path = "war3map.j"
+
+        [[file]]
+        name = "readme.txt"
+        data = "aGk="
+        "#,
+    )
+    .unwrap();
+    let archive_path = dir.join("out.mpq");
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.args(["archive", "build"])
+        .arg(&archive_path)
+        .arg("--manifest")
+        .arg(dir.join("manifest.toml"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Archive created successfully"));
+
+    let archive = mopaq::Archive::open(&archive_path).unwrap();
+    assert_eq!(archive.read_file("readme.txt").unwrap(), b"hi");
+    assert_eq!(
+        archive.read_file("scripts\\war3map.j").unwrap(),
+        b"function main takes nothing"
+    );
+
+    // Unknown keys are reported rather than ignored
+    fs::write(
+        dir.join("manifest.toml"),
+        "[[file]]\nname = \"a\"\npaht = \"a\"",
+    )
+    .unwrap();
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.args(["archive", "build"])
+        .arg(&archive_path)
+        .arg("-m")
+        .arg(dir.join("manifest.toml"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("paht"));
+}
+
+#[test]
+fn test_archive_manifest_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let dir = temp_dir.path();
+    mopaq::ArchiveBuilder::new()
+        .add_file_data(
+            b"function main takes nothing".to_vec(),
+            "scripts\\war3map.j",
+        )
+        .add_file_data_with_options(
+            b"french".to_vec(),
+            "war3map.wts",
+            0,
+            false,
+            mopaq::Locale::FR_FR,
+        )
+        .build(dir.join("base.mpq"))
+        .unwrap();
+
+    let storm = || {
+        let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+        cmd.current_dir(dir);
+        cmd
+    };
+    storm()
+        .args(["file", "extract", "base.mpq", "-p", "-t", "data"])
+        .assert()
+        .success();
+    let output = storm()
+        .args(["archive", "manifest", "base.mpq", "--data-dir", "data"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let manifest = String::from_utf8(output.stdout).unwrap();
+    assert!(manifest.contains("path = "), "{manifest}");
+    assert!(manifest.contains("locale = \"frFR\""), "{manifest}");
+    fs::write(dir.join("manifest.toml"), manifest).unwrap();
+
+    fs::write(dir.join("data/scripts/war3map.j"), "edited").unwrap();
+    storm()
+        .args(["archive", "build", "--check", "-m", "manifest.toml"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Manifest is valid: 2 files"));
+    storm()
+        .args(["archive", "build", "new.mpq", "-m", "manifest.toml"])
+        .assert()
+        .success();
+
+    let archive = mopaq::Archive::open(dir.join("new.mpq")).unwrap();
+    assert_eq!(archive.read_file("scripts\\war3map.j").unwrap(), b"edited");
+    assert_eq!(
+        archive
+            .read_file_with_locale("war3map.wts", mopaq::Locale::FR_FR)
+            .unwrap(),
+        b"french"
+    );
+
+    // Checking reports problems without building
+    fs::write(
+        dir.join("manifest.toml"),
+        "[[file]]\nname = \"a\"\ndata = \"\"\nlocale = \"xxXX\"",
+    )
+    .unwrap();
+    storm()
+        .args(["archive", "build", "--check", "-m", "manifest.toml"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid manifest"));
+}
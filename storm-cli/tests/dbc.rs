@@ -0,0 +1,64 @@
+//! Integration tests for the DBC preview command
+
+#![cfg(feature = "dbc")]
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_file_show_dbc() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    // Two records of (id, name offset), followed by the string block
+    let strings = b"\0Fireball\0Frostbolt\0";
+    let mut dbc = b"WDBC".to_vec();
+    for value in [2u32, 2, 8, strings.len() as u32, 133, 1, 116, 10] {
+        dbc.extend_from_slice(&value.to_le_bytes());
+    }
+    dbc.extend_from_slice(strings);
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("Spell.dbc"), &dbc).unwrap();
+    fs::write(source_dir.join("readme.txt"), "not a database").unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "create"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "show"])
+        .arg(&archive_path)
+        .arg("Spell.dbc")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Records:      2"))
+        .stdout(predicate::str::contains("Fireball"))
+        .stdout(predicate::str::contains("Frostbolt"));
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "show", "-n", "1", "--output", "csv"])
+        .arg(&archive_path)
+        .arg("Spell.dbc")
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("f0,f1\n133,Fireball\n"));
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "show"])
+        .arg(&archive_path)
+        .arg("readme.txt")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Not a DBC/DB2 file"));
+}
@@ -0,0 +1,70 @@
+//! Integration tests for content type detection in file list and info
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_file_list_detect() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(
+        source_dir.join("texture.bin"),
+        b"BLP2\x01\x02\x00\x00\x00\x00",
+    )
+    .unwrap();
+    fs::write(
+        source_dir.join("addon.lua"),
+        "local frame = CreateFrame(\"Frame\")\nif frame then\n  frame:Show()\nend\n",
+    )
+    .unwrap();
+    fs::write(source_dir.join("items.dbc"), b"WDBC\x01\x00\x00\x00").unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "create"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "list", "--detect", "--output", "csv"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("texture.bin,blp"))
+        .stdout(predicate::str::contains("addon.lua,lua"))
+        .stdout(predicate::str::contains("items.dbc,dbc"));
+
+    // Filtering by kind implies detection
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "list", "--kind", "blp", "--output", "json"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"kind\": \"blp\""))
+        .stdout(predicate::str::contains("addon.lua").not());
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "list", "--kind", "exe"])
+        .arg(&archive_path)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "info"])
+        .arg(&archive_path)
+        .arg("items.dbc")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("DBC database"));
+}
@@ -0,0 +1,98 @@
+//! Integration tests for exit codes and --fail-on policies
+
+use assert_cmd::Command;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn storm_cli() -> Command {
+    Command::cargo_bin("storm-cli").unwrap()
+}
+
+fn create_archive(dir: &Path) -> PathBuf {
+    let source = dir.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("readme.txt"), "Hello, MPQ!").unwrap();
+
+    let archive = dir.join("test.mpq");
+    storm_cli()
+        .args(["archive", "create"])
+        .arg(&archive)
+        .arg(&source)
+        .assert()
+        .success();
+    archive
+}
+
+#[test]
+fn test_exit_code_not_found() {
+    let temp_dir = TempDir::new().unwrap();
+    storm_cli()
+        .args(["archive", "info"])
+        .arg(temp_dir.path().join("missing.mpq"))
+        .assert()
+        .code(3);
+
+    let archive = create_archive(temp_dir.path());
+    storm_cli()
+        .args(["file", "extract"])
+        .arg(&archive)
+        .arg("missing.txt")
+        .arg("-t")
+        .arg(temp_dir.path())
+        .assert()
+        .code(3);
+}
+
+#[test]
+fn test_exit_code_corrupt() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("garbage.mpq");
+    fs::write(&path, vec![0x5Au8; 4096]).unwrap();
+
+    storm_cli()
+        .args(["archive", "verify"])
+        .arg(&path)
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn test_exit_code_usage() {
+    storm_cli()
+        .args(["archive", "verify", "--no-such-flag"])
+        .assert()
+        .code(64);
+    storm_cli().arg("--help").assert().code(0);
+}
+
+#[test]
+fn test_verify_fail_on() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = create_archive(temp_dir.path());
+
+    // The CRC check reports a warning for every file it cannot check
+    storm_cli()
+        .args(["archive", "verify", "--check-contents", "--check-crc"])
+        .arg(&archive)
+        .assert()
+        .code(0);
+    storm_cli()
+        .args(["archive", "verify", "--check-contents", "--check-crc"])
+        .args(["--fail-on", "warn"])
+        .arg(&archive)
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_analyze_fail_on() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = create_archive(temp_dir.path());
+
+    storm_cli()
+        .args(["archive", "analyze", "--fail-on", "warn"])
+        .arg(&archive)
+        .assert()
+        .code(0);
+}
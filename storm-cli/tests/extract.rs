@@ -0,0 +1,115 @@
+//! Integration tests for extracting files by name
+
+use assert_cmd::Command;
+use mopaq::ArchiveBuilder;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn storm() -> Command {
+    Command::cargo_bin("storm-cli").unwrap()
+}
+
+fn build_archive(dir: &Path) -> String {
+    let path = dir.join("maps.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"new".to_vec(), "Maps\\a.txt")
+        .add_file_data(b"other".to_vec(), "Maps\\b.txt")
+        .add_file_data(b"evil".to_vec(), "..\\evil.txt")
+        .build(&path)
+        .unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_extract_preserves_archive_directories() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = build_archive(temp_dir.path());
+    let out = temp_dir.path().join("out");
+
+    storm()
+        .args(["file", "extract", &archive, "-p", "-t"])
+        .arg(&out)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Failed to extract ..\\evil.txt"));
+
+    assert_eq!(fs::read(out.join("Maps").join("a.txt")).unwrap(), b"new");
+    assert!(!temp_dir.path().join("evil.txt").exists());
+}
+
+#[test]
+fn test_extract_on_collision() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = build_archive(temp_dir.path());
+    let out = temp_dir.path().join("out");
+    fs::create_dir_all(&out).unwrap();
+    fs::write(out.join("a.txt"), b"old").unwrap();
+
+    storm()
+        .args(["file", "extract", &archive, "--on-collision", "skip", "-t"])
+        .arg(&out)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Skipped: Maps\\a.txt"));
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+    assert_eq!(fs::read(out.join("b.txt")).unwrap(), b"other");
+
+    let output = storm()
+        .args(["-o", "json", "file", "extract", &archive])
+        .args(["--on-collision", "rename", "-t"])
+        .arg(&out)
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["skipped"], serde_json::json!([]));
+    assert_eq!(fs::read(out.join("a (1).txt")).unwrap(), b"new");
+    assert_eq!(fs::read(out.join("b (1).txt")).unwrap(), b"other");
+
+    storm()
+        .args(["file", "extract", &archive, "-t"])
+        .arg(&out)
+        .assert()
+        .success();
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"new");
+}
+
+#[test]
+fn test_extract_if_different_and_resume() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = build_archive(temp_dir.path());
+    let out = temp_dir.path().join("out");
+    fs::create_dir_all(&out).unwrap();
+    fs::write(out.join("a.txt"), b"old").unwrap();
+    fs::write(out.join("b.txt"), b"other").unwrap();
+
+    storm()
+        .args([
+            "file",
+            "extract",
+            &archive,
+            "--on-collision",
+            "if-different",
+        ])
+        .arg("-t")
+        .arg(&out)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Unchanged: Maps\\b.txt"))
+        .stdout(predicate::str::contains("Extracted: Maps\\a.txt"));
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"new");
+
+    // Resuming keeps any file of the right size
+    fs::write(out.join("a.txt"), b"old").unwrap();
+    let output = storm()
+        .args(["-o", "json", "file", "extract", &archive, "--resume", "-t"])
+        .arg(&out)
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let unchanged = json["unchanged"].as_array().unwrap();
+    assert!(unchanged.contains(&serde_json::json!("Maps\\a.txt")));
+    assert!(unchanged.contains(&serde_json::json!("Maps\\b.txt")));
+    assert_eq!(fs::read(out.join("a.txt")).unwrap(), b"old");
+}
@@ -0,0 +1,63 @@
+//! Integration tests for extracting files by block index
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_file_extract_anonymous() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+    let out_dir = temp_dir.path().join("out");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.txt"), b"first file").unwrap();
+    fs::write(source_dir.join("b.txt"), b"second file").unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "create", "--no-listfile"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "extract", "--all-anonymous", "-t"])
+        .arg(&out_dir)
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Extracted 2 of 2 anonymous files"));
+
+    let mut contents: Vec<Vec<u8>> = ["block_00000.bin", "block_00001.bin"]
+        .iter()
+        .map(|name| fs::read(out_dir.join(name)).unwrap())
+        .collect();
+    contents.sort();
+    assert_eq!(contents, [b"first file".to_vec(), b"second file".to_vec()]);
+
+    let single = temp_dir.path().join("single.bin");
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "extract", "--index", "1", "-t"])
+        .arg(&single)
+        .arg(&archive_path)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read(&single).unwrap(),
+        fs::read(out_dir.join("block_00001.bin")).unwrap()
+    );
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "extract", "--index", "99"])
+        .arg(&archive_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("block 99"));
+}
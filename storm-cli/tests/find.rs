@@ -31,3 +31,39 @@ fn test_find_nonexistent_archive() {
         .failure()
         .stderr(predicate::str::contains("I/O error"));
 }
+
+#[test]
+fn test_find_matches_like_lookups() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("find.mpq");
+    mopaq::ArchiveBuilder::new()
+        .add_file_data(b"model".to_vec(), "Units\\Human\\Footman.mdx")
+        .add_file_data(b"script".to_vec(), "war3map.j")
+        .build(&archive_path)
+        .unwrap();
+
+    // Case and separators do not matter, for globs or regexes
+    for args in [
+        vec!["units/human/*.MDX"],
+        vec!["--regex", "^UNITS/.*footman"],
+    ] {
+        Command::cargo_bin("storm-cli")
+            .unwrap()
+            .args(["file", "find"])
+            .arg(&archive_path)
+            .args(&args)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Units\\Human\\Footman.mdx"))
+            .stdout(predicate::str::contains("war3map.j").not());
+    }
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "find"])
+        .arg(&archive_path)
+        .arg("[unclosed")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid pattern"));
+}
@@ -0,0 +1,66 @@
+//! Integration tests for the file grep command
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_file_grep() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(source_dir.join("scripts")).unwrap();
+    fs::write(
+        source_dir.join("war3map.j"),
+        "function main takes nothing returns nothing\n    call InitBlizzard()\nendfunction\n",
+    )
+    .unwrap();
+    fs::write(
+        source_dir.join("scripts").join("common.j"),
+        "native initblizzard takes nothing returns nothing\n",
+    )
+    .unwrap();
+    fs::write(
+        source_dir.join("notes.txt"),
+        "InitBlizzard is called first\n",
+    )
+    .unwrap();
+    fs::write(source_dir.join("data.bin"), b"InitBlizzard\x00\x01\x02").unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "create"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "grep", "--quiet"])
+        .arg(&archive_path)
+        .arg("InitBlizzard")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "war3map.j:2:    call InitBlizzard()",
+        ))
+        .stdout(predicate::str::contains("notes.txt:1:"))
+        .stdout(predicate::str::contains("common.j").not())
+        .stdout(predicate::str::contains("data.bin").not());
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "grep", "-i", "--glob", "*.J", "--output", "csv"])
+        .arg(&archive_path)
+        .arg("initblizzard")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "common.j,1,native initblizzard takes nothing returns nothing",
+        ))
+        .stdout(predicate::str::contains("war3map.j,2,"))
+        .stdout(predicate::str::contains("notes.txt").not());
+}
@@ -1,7 +1,9 @@
 //! Integration tests for hash commands
 
 use assert_cmd::Command;
+use mopaq::{ArchiveBuilder, FormatVersion};
 use predicates::prelude::*;
+use tempfile::TempDir;
 
 #[test]
 fn test_hash_generate_help() {
@@ -42,3 +44,114 @@ fn test_hash_generate_specific_type() {
         .success()
         .stdout(predicate::str::contains("0x82c45239"));
 }
+
+#[test]
+fn test_hash_generate_json_output() {
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    let output = cmd
+        .arg("hash")
+        .arg("generate")
+        .arg("test.txt")
+        .arg("--all")
+        .arg("--output")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let record: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(record["filename"], "test.txt");
+    assert_eq!(record["file_key"], "0x82c45239");
+}
+
+#[test]
+fn test_hash_jenkins_jsonl() {
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("hash")
+        .arg("jenkins")
+        .arg("test.txt")
+        .arg("--output")
+        .arg("jsonl")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "{\"filename\":\"test.txt\",\"jenkins\":\"0x",
+        ));
+}
+
+#[test]
+fn test_hash_generate_from_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let listfile = temp_dir.path().join("names.txt");
+    std::fs::write(&listfile, "test.txt\r\nunits\\human\\footman.mdx\n").unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["--output", "csv", "hash", "generate", "--from-file"])
+        .arg(&listfile)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "filename,table_offset,name_a,name_b,file_key,key2_mix\n",
+        ))
+        .stdout(predicate::str::contains("test.txt,0x"))
+        .stdout(predicate::str::contains(",0x82c45239,"));
+
+    let output = Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["--output", "json", "hash", "generate", "--from-file"])
+        .arg(&listfile)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let records: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(records.as_array().unwrap().len(), 2);
+    assert_eq!(records[0]["filename"], "test.txt");
+    assert_eq!(records[0]["file_key"], "0x82c45239");
+    assert_eq!(records[1]["filename"], "units\\human\\footman.mdx");
+
+    // A filename and a listfile are mutually exclusive
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["hash", "generate", "test.txt", "--from-file"])
+        .arg(&listfile)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_hash_lookup() {
+    let temp_dir = TempDir::new().unwrap();
+    for version in [FormatVersion::V1, FormatVersion::V3] {
+        let archive_path = temp_dir.path().join(format!("lookup_{version:?}.mpq"));
+        ArchiveBuilder::new()
+            .version(version)
+            .add_file_data(b"present".to_vec(), "present.txt")
+            .build(&archive_path)
+            .unwrap();
+
+        let lookup = |name: &str| {
+            let output = Command::cargo_bin("storm-cli")
+                .unwrap()
+                .args(["--output", "json", "hash", "lookup"])
+                .arg(&archive_path)
+                .arg(name)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+        };
+
+        // Lookups fold case like the archive does
+        let found = lookup("PRESENT.TXT");
+        assert_eq!(found["found"], true);
+        assert_eq!(found["slots"].as_array().unwrap().len(), 1);
+        assert_eq!(found["slots"][0]["state"], "occupied");
+        assert_eq!(found["het_index"].is_u64(), version == FormatVersion::V3);
+
+        let missing = lookup("absent.txt");
+        assert_eq!(missing["found"], false);
+        assert!(missing["slots"].as_array().unwrap().is_empty());
+        assert!(missing["home_slot"].is_u64());
+    }
+}
@@ -0,0 +1,154 @@
+//! Integration tests for the listfile commands
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_touch_listfile_add_and_remove() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.txt"), "First").unwrap();
+    fs::write(source_dir.join("b.txt"), "Second").unwrap();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("archive")
+        .arg("create")
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("touch-listfile")
+        .arg(&archive_path)
+        .arg("--remove")
+        .arg("b.txt")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("3 -> 2 entries"));
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("list")
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("a.txt"))
+        .stdout(predicate::str::contains("b.txt").not());
+
+    // The data is untouched, so the name can be restored
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("touch-listfile")
+        .arg(&archive_path)
+        .arg("--add")
+        .arg("b.txt")
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("list")
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("b.txt"));
+}
+
+#[test]
+fn test_touch_listfile_unknown_name() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.txt"), "First").unwrap();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("archive")
+        .arg("create")
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("touch-listfile")
+        .arg(&archive_path)
+        .arg("--add")
+        .arg("missing.txt")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("missing.txt"));
+}
+
+#[test]
+fn test_export_and_import_listfile() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+    let listfile_path = temp_dir.path().join("names.txt");
+
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.txt"), "First").unwrap();
+    fs::write(source_dir.join("b.txt"), "Second").unwrap();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("archive")
+        .arg("create")
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("export-listfile")
+        .arg(&archive_path)
+        .arg(&listfile_path)
+        .arg("--format")
+        .arg("size-crc")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Wrote 3 names"));
+
+    let exported = fs::read_to_string(&listfile_path).unwrap();
+    assert!(exported.contains(&format!(
+        "a.txt;5;{:08X}\r\n",
+        mopaq::SectorChecksum::Crc32.compute(b"First")
+    )));
+
+    // Drop a name, then recover it from the exported listfile
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("touch-listfile")
+        .arg(&archive_path)
+        .arg("--remove")
+        .arg("b.txt")
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("import-listfile")
+        .arg(&archive_path)
+        .arg(&listfile_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Added 1 names"));
+
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("file")
+        .arg("list")
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("b.txt"));
+}
@@ -0,0 +1,145 @@
+//! Integration tests for per-locale file operations
+
+use assert_cmd::Command;
+use mopaq::{Archive, ArchiveBuilder, Locale};
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// An archive with a string file in the neutral locale and in frFR
+fn multi_locale_archive(dir: &Path) -> String {
+    let path = dir.join("strings.mpq");
+    ArchiveBuilder::new()
+        .add_file_data(b"neutral".to_vec(), "war3map.wts")
+        .add_file_data_with_options(b"french".to_vec(), "war3map.wts", 0, false, Locale::FR_FR)
+        .build(&path)
+        .unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn storm() -> Command {
+    Command::cargo_bin("storm-cli").unwrap()
+}
+
+#[test]
+fn test_extract_locale() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = multi_locale_archive(temp_dir.path());
+
+    let target = temp_dir.path().join("fr.wts");
+    storm()
+        .args([
+            "file",
+            "extract",
+            &archive,
+            "war3map.wts",
+            "--locale",
+            "frFR",
+        ])
+        .arg("-t")
+        .arg(&target)
+        .assert()
+        .success();
+    assert_eq!(fs::read(&target).unwrap(), b"french");
+
+    // Locales without a version fall back to the neutral one
+    let target = temp_dir.path().join("de.wts");
+    storm()
+        .args(["file", "extract", &archive, "war3map.wts", "-l", "deDE"])
+        .arg("-t")
+        .arg(&target)
+        .assert()
+        .success();
+    assert_eq!(fs::read(&target).unwrap(), b"neutral");
+}
+
+#[test]
+fn test_info_locale() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = multi_locale_archive(temp_dir.path());
+
+    storm()
+        .args(["file", "info", &archive, "war3map.wts", "--locale", "frFR"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0x040C (frFR)"))
+        .stdout(predicate::str::contains("Stored in:  neutral, frFR"));
+
+    let output = storm()
+        .args(["file", "info", &archive, "war3map.wts", "-o", "json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["locales"], serde_json::json!([0, 0x40C]));
+}
+
+#[test]
+fn test_add_and_remove_locale() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive = multi_locale_archive(temp_dir.path());
+    let source = temp_dir.path().join("war3map.wts");
+    fs::write(&source, "deutsch").unwrap();
+
+    storm()
+        .args(["file", "add", &archive, "--locale", "deDE"])
+        .arg(&source)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Added: war3map.wts (deDE)"));
+
+    let opened = Archive::open(&archive).unwrap();
+    assert_eq!(
+        opened.file_locales("war3map.wts").unwrap(),
+        [Locale::NEUTRAL, Locale::DE_DE, Locale::FR_FR]
+    );
+    assert_eq!(
+        opened
+            .read_file_with_locale("war3map.wts", Locale::DE_DE)
+            .unwrap(),
+        b"deutsch"
+    );
+    drop(opened);
+
+    storm()
+        .args([
+            "file",
+            "remove",
+            &archive,
+            "war3map.wts",
+            "--locale",
+            "frFR",
+        ])
+        .assert()
+        .success();
+    let opened = Archive::open(&archive).unwrap();
+    assert_eq!(
+        opened.file_locales("war3map.wts").unwrap(),
+        [Locale::NEUTRAL, Locale::DE_DE]
+    );
+    drop(opened);
+
+    // Removing a version that is not there fails and leaves the archive alone
+    storm()
+        .args([
+            "file",
+            "remove",
+            &archive,
+            "war3map.wts",
+            "--locale",
+            "frFR",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "File not found: war3map.wts (frFR)",
+        ));
+
+    // Without a locale, every version is removed
+    storm()
+        .args(["file", "remove", &archive, "war3map.wts"])
+        .assert()
+        .success();
+    let opened = Archive::open(&archive).unwrap();
+    assert!(opened.file_locales("war3map.wts").unwrap().is_empty());
+}
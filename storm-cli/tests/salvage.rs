@@ -0,0 +1,92 @@
+//! Integration tests for salvaging damaged archives
+
+use assert_cmd::Command;
+use mopaq::compression::flags;
+use mopaq::{Archive, ArchiveBuilder, ListfileOption};
+use predicates::prelude::*;
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use tempfile::TempDir;
+
+#[test]
+fn test_archive_salvage() {
+    let temp_dir = TempDir::new().unwrap();
+    let archive_path = temp_dir.path().join("damaged.mpq");
+    let listfile_path = temp_dir.path().join("listfile.txt");
+    let out_dir = temp_dir.path().join("out");
+
+    let text: Vec<u8> = (0..20_000u32)
+        .flat_map(|i| (i % 97).to_le_bytes())
+        .collect();
+    // secret.bin is left out of the listfile
+    fs::write(&listfile_path, "readme.txt\r\nData\\big.bin\r\n").unwrap();
+    ArchiveBuilder::new()
+        .listfile_option(ListfileOption::External(listfile_path))
+        .add_file_data(b"hello".to_vec(), "readme.txt")
+        .add_file_data_with_options(text.clone(), "Data\\big.bin", flags::ZLIB, false, 0)
+        .add_file_data_with_options(text.clone(), "secret.bin", flags::ZLIB, true, 0)
+        .build(&archive_path)
+        .unwrap();
+
+    // Overwrite the second sector of big.bin with garbage
+    let (sector_pos, sector_len, sector_size) = {
+        let archive = Archive::open(&archive_path).unwrap();
+        let map = archive.sector_map("Data\\big.bin").unwrap();
+        let sector = &map.sectors[1];
+        (
+            map.file_pos + sector.offset,
+            sector.compressed_size as usize,
+            map.sector_size,
+        )
+    };
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .open(&archive_path)
+        .unwrap();
+    file.seek(SeekFrom::Start(sector_pos)).unwrap();
+    file.write_all(&vec![0xA5; sector_len]).unwrap();
+    drop(file);
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "salvage"])
+        .arg(&archive_path)
+        .arg(&out_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Partial: Data\\big.bin"))
+        .stdout(predicate::str::contains("1 partial, 0 failed"));
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(out_dir.join("salvage_report.json")).unwrap()).unwrap();
+    assert_eq!(report["partial"], 1);
+    assert_eq!(report["failed"], 0);
+    let entry = |name: Option<&str>| {
+        report["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["name"].as_str() == name)
+            .unwrap()
+            .clone()
+    };
+
+    assert_eq!(entry(Some("readme.txt"))["status"], "recovered");
+    assert_eq!(fs::read(out_dir.join("readme.txt")).unwrap(), b"hello");
+
+    let big = entry(Some("Data\\big.bin"));
+    assert_eq!(big["status"], "partial");
+    assert_eq!(big["bytes_lost"], sector_size as u64);
+    let salvaged = fs::read(out_dir.join("Data").join("big.bin")).unwrap();
+    assert_eq!(salvaged.len(), text.len());
+    assert_eq!(salvaged[..sector_size], text[..sector_size]);
+    assert!(salvaged[sector_size..2 * sector_size]
+        .iter()
+        .all(|&b| b == 0));
+    assert_eq!(salvaged[2 * sector_size..], text[2 * sector_size..]);
+
+    // The unnamed file's key is recovered from its sector offset table
+    let secret = entry(None);
+    assert_eq!(secret["status"], "recovered");
+    assert_eq!(fs::read(secret["path"].as_str().unwrap()).unwrap(), text);
+}
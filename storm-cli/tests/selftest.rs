@@ -0,0 +1,36 @@
+//! Integration tests for the selftest command
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+#[test]
+fn test_selftest_text() {
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    cmd.arg("selftest")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("compression"))
+        .stdout(predicate::str::contains("0 failed"));
+}
+
+#[test]
+fn test_selftest_json_matrix() {
+    let mut cmd = Command::cargo_bin("storm-cli").unwrap();
+    let output = cmd.args(["selftest", "-o", "json"]).output().unwrap();
+    assert!(output.status.success());
+
+    let records: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let records = records.as_array().unwrap();
+    let status = |category: &str, check: &str| {
+        records
+            .iter()
+            .find(|r| r["category"] == category && r["check"] == check)
+            .map(|r| r["status"].as_str().unwrap().to_string())
+    };
+
+    assert!(records.iter().all(|r| r["status"] != "fail"));
+    assert_eq!(status("compression", "zlib").as_deref(), Some("pass"));
+    assert_eq!(status("compression", "huffman").as_deref(), Some("skip"));
+    assert_eq!(status("hash", "(listfile)").as_deref(), Some("pass"));
+    assert_eq!(status("archive", "build and read").as_deref(), Some("pass"));
+}
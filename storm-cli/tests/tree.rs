@@ -0,0 +1,76 @@
+//! Integration tests for the file tree command
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn test_file_tree() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    let archive_path = temp_dir.path().join("test.mpq");
+
+    fs::create_dir_all(source_dir.join("units").join("human")).unwrap();
+    fs::write(
+        source_dir.join("units").join("human").join("footman.txt"),
+        "a",
+    )
+    .unwrap();
+    fs::write(source_dir.join("units").join("peasant.txt"), "bb").unwrap();
+    fs::write(source_dir.join("readme.txt"), "ccc").unwrap();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["archive", "create"])
+        .arg(&archive_path)
+        .arg(&source_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "tree"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("units"))
+        .stdout(predicate::str::contains("footman.txt"))
+        .stdout(predicate::str::contains("files").not());
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "tree", "--sizes"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("units (2 files, 3)"))
+        .stdout(predicate::str::contains("readme.txt (3)"));
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "tree", "--output", "json"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"total_size\": 3"));
+
+    // Depth limit and directories only
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "tree", "-L", "1", "-d"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("units"))
+        .stdout(predicate::str::contains("human").not())
+        .stdout(predicate::str::contains("readme.txt").not());
+
+    Command::cargo_bin("storm-cli")
+        .unwrap()
+        .args(["file", "tree", "--output", "csv"])
+        .arg(&archive_path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("units\\human,1,1,"));
+}
@@ -16,6 +16,11 @@ fn main() {
                 .to_string(),
         ),
         include_version: true,
+        // Items that only exist on some platforms are wrapped in the matching
+        // preprocessor condition
+        defines: [("windows".to_string(), "_WIN32".to_string())]
+            .into_iter()
+            .collect(),
         namespace: None,
         namespaces: None,
         braces: cbindgen::Braces::SameLine,
@@ -49,6 +54,8 @@ extern "C" {
         export: cbindgen::ExportConfig {
             include: vec![
                 "SFileOpenArchive".to_string(),
+                "SFileOpenArchiveFromFd".to_string(),
+                "SFileOpenArchiveFromHandle".to_string(),
                 "SFileCloseArchive".to_string(),
                 "SFileOpenFileEx".to_string(),
                 "SFileCloseFile".to_string(),
@@ -58,12 +65,56 @@ extern "C" {
                 "SFileHasFile".to_string(),
                 "SFileGetArchiveName".to_string(),
                 "SFileGetFileName".to_string(),
+                "SFileGetFileNameEx".to_string(),
                 "SFileGetFileInfo".to_string(),
+                "SFileGetFileChecksums".to_string(),
                 "SFileEnumFiles".to_string(),
                 "SFileSetLocale".to_string(),
                 "SFileGetLocale".to_string(),
                 "SFileGetLastError".to_string(),
                 "SFileSetLastError".to_string(),
+                "GetLastError".to_string(),
+                "SetLastError".to_string(),
+                "SFileGetStormBuffer".to_string(),
+                "SFileAddFileEx".to_string(),
+                "SFileCompactArchive".to_string(),
+                "SFileSetAddFileCallback".to_string(),
+                "SFileSetCompactCallback".to_string(),
+                "SVfsCreate".to_string(),
+                "SVfsClose".to_string(),
+                "SVfsMountArchive".to_string(),
+                "SVfsMountDirectory".to_string(),
+                "SVfsUnmount".to_string(),
+                "SVfsHasFile".to_string(),
+                "SVfsOpenFile".to_string(),
+                "SVfsEnumFiles".to_string(),
+            ],
+            // Rust types pulled in from mopaq, and their associated constants,
+            // that have no C representation
+            exclude: vec![
+                "NameHashingPolicy".to_string(),
+                "BLIZZARD".to_string(),
+                "VERBATIM".to_string(),
+                "Locale".to_string(),
+                "NEUTRAL".to_string(),
+                "ZH_TW".to_string(),
+                "CS_CZ".to_string(),
+                "DE_DE".to_string(),
+                "EN_US".to_string(),
+                "ES_ES".to_string(),
+                "FR_FR".to_string(),
+                "IT_IT".to_string(),
+                "JA_JP".to_string(),
+                "KO_KR".to_string(),
+                "PL_PL".to_string(),
+                "PT_BR".to_string(),
+                "RU_RU".to_string(),
+                "ZH_CN".to_string(),
+                "EN_GB".to_string(),
+                "ES_MX".to_string(),
+                "PT_PT".to_string(),
+                "UserDataHeader".to_string(),
+                "SIZE".to_string(),
             ],
             ..Default::default()
         },
@@ -18,6 +18,111 @@ extern "C" {
 #include <stdint.h>
 #include <stdlib.h>
 
+// The operation completed successfully
+#define ERROR_SUCCESS 0
+
+// The file does not exist, in the archive or on disk
+#define ERROR_FILE_NOT_FOUND 2
+
+// The file could not be opened or written, or the archive is read-only
+#define ERROR_ACCESS_DENIED 5
+
+// The handle is not a valid archive, file or VFS handle
+#define ERROR_INVALID_HANDLE 6
+
+// Not enough memory to complete the operation
+#define ERROR_NOT_ENOUGH_MEMORY 8
+
+// The file is not an MPQ archive or uses an unsupported format version
+#define ERROR_BAD_FORMAT 11
+
+// The operation is not supported for this archive or file
+#define ERROR_NOT_SUPPORTED 50
+
+// An argument was null, malformed or out of range
+#define ERROR_INVALID_PARAMETER 87
+
+// The disk is full, or the archive cannot hold more files
+#define ERROR_DISK_FULL 112
+
+// The buffer passed in is too small for the result
+#define ERROR_INSUFFICIENT_BUFFER 122
+
+// The file being created already exists
+#define ERROR_ALREADY_EXISTS 183
+
+// The operation failed for a reason not covered by a more specific code
+#define ERROR_CAN_NOT_COMPLETE 1003
+
+// The archive or file data is corrupt
+#define ERROR_FILE_CORRUPT 1392
+
+// The file is an AVI video rather than an MPQ archive
+#define ERROR_AVI_FILE 10000
+
+// The key of an encrypted file could not be determined
+#define ERROR_UNKNOWN_FILE_KEY 10001
+
+// A sector or file checksum does not match the data
+#define ERROR_CHECKSUM_ERROR 10002
+
+// The operation is not allowed on an internal file such as `(listfile)`
+#define ERROR_INTERNAL_FILE 10003
+
+// The archive ends before the data it refers to
+#define ERROR_FILE_INCOMPLETE 10006
+
+// Buffer size `SFileGetFileName` assumes, in bytes
+#define MAX_PATH 260
+
+// File handle info class: FILETIME from `(attributes)` as a `uint64_t`
+#define SFILE_INFO_FILE_TIME 13
+
+// File handle info class: CRC32 from `(attributes)` as a `uint32_t`
+#define SFILE_INFO_CRC32 14
+
+// File handle info class: MD5 from `(attributes)` as 16 bytes
+#define SFILE_INFO_MD5 15
+
+// `SFileAddFileEx` flag: compress the file with PKWare DCL, imploded
+#define MPQ_FILE_IMPLODE 256
+
+// `SFileAddFileEx` flag: compress the file
+#define MPQ_FILE_COMPRESS 512
+
+// `SFileAddFileEx` flag: encrypt the file
+#define MPQ_FILE_ENCRYPTED 65536
+
+// `SFileAddFileEx` flag: adjust the encryption key by the file position
+#define MPQ_FILE_FIX_KEY 131072
+
+// `SFileAddFileEx` flag: store the file in one piece instead of in sectors
+#define MPQ_FILE_SINGLE_UNIT 16777216
+
+// `SFileAddFileEx` flag: write sector checksums
+#define MPQ_FILE_SECTOR_CRC 67108864
+
+// `SFileAddFileEx` flag: replace a file that already exists
+#define MPQ_FILE_REPLACEEXISTING 2147483648
+
+// `SFileAddFileEx` `compression_next`: compress every sector like the first
+#define MPQ_COMPRESSION_NEXT_SAME 4294967295
+
+// Compaction stage: reading the files to keep
+#define CCB_CHECKING_FILES 1
+
+// Compaction stage: checking the hash table (not reported by this library)
+#define CCB_CHECKING_HASH_TABLE 2
+
+// Compaction stage: copying data outside the MPQ (not reported by this library)
+#define CCB_COPYING_NON_MPQ_DATA 3
+
+// Compaction stage: writing the files into the compacted archive
+#define CCB_COMPACTING_FILES 4
+
+// Compaction stage: writing the tables and closing the archive
+#define CCB_CLOSING_ARCHIVE 5
+
 // CRC32 checksums are present
 #define AttributeFlags_CRC32 1
 
@@ -39,6 +144,18 @@ extern "C" {
 // Archive handle type
 typedef void *HANDLE;
 
+// Progress callback for `SFileAddFileEx`
+//
+// Called with the user data, the bytes of the file processed so far, the
+// file's total size, and whether this is the final call for the file.
+typedef void (*SFILE_ADDFILE_CALLBACK)(void*, uint32_t, uint32_t, bool);
+
+// Progress callback for `SFileCompactArchive`
+//
+// Called with the user data, the current `CCB_*` work type, and the bytes
+// processed so far out of the total for that stage.
+typedef void (*SFILE_COMPACT_CALLBACK)(void*, uint32_t, uint64_t, uint64_t);
+
 #ifdef __cplusplus
 extern "C" {
 #endif // __cplusplus
@@ -51,6 +168,39 @@ extern "C" {
 // - `handle` must be a valid pointer to write the output handle
 bool SFileOpenArchive(const char *filename, uint32_t _priority, uint32_t _flags, HANDLE *handle);
 
+#if !defined(_WIN32)
+// Open an MPQ archive from a file descriptor
+//
+// The library owns `fd` from the call on, even if it fails: it is closed
+// by `SFileCloseArchive`, or right away when the archive cannot be opened.
+// The archive has no name, so `SFileGetArchiveName` returns an empty
+// string, and `SFileAddFileEx` and `SFileCompactArchive`, which rewrite the
+// archive at its path, fail with `ERROR_NOT_SUPPORTED`.
+//
+// # Safety
+//
+// - `fd` must be an open, readable file descriptor that the caller does not close
+// - `handle` must be a valid pointer to write the output handle
+bool SFileOpenArchiveFromFd(int fd, uint32_t _flags, HANDLE *handle);
+#endif
+
+#if defined(_WIN32)
+// Open an MPQ archive from a Win32 file handle
+//
+// The library owns `file` from the call on, even if it fails: it is closed
+// by `SFileCloseArchive`, or right away when the archive cannot be opened.
+// The handle may be opened with any sharing flags but needs read access.
+// The archive has no name, so `SFileGetArchiveName` returns an empty
+// string, and `SFileAddFileEx` and `SFileCompactArchive`, which rewrite the
+// archive at its path, fail with `ERROR_NOT_SUPPORTED`.
+//
+// # Safety
+//
+// - `file` must be an open file handle that the caller does not close
+// - `handle` must be a valid pointer to write the output handle
+bool SFileOpenArchiveFromHandle(HANDLE file, uint32_t _flags, HANDLE *handle);
+#endif
+
 // Create a new MPQ archive
 //
 // # Safety
@@ -81,6 +231,9 @@ bool SFileCloseFile(HANDLE file);
 
 // Read from a file
 //
+// At most `to_read` bytes are written to `buffer`. `*read` is set on every
+// path, to 0 when the call fails.
+//
 // # Safety
 //
 // - `buffer` must be a valid pointer with at least `to_read` bytes available
@@ -123,6 +276,19 @@ bool SFileGetFileInfo(HANDLE file_or_archive,
                       uint32_t buffer_size,
                       uint32_t *size_needed);
 
+// Get the CRC32 and MD5 recorded for a file in `(attributes)`
+//
+// Patchers can compare these against their own manifests without reading and
+// hashing the file. Values the archive does not record are reported as zero,
+// as StormLib does.
+//
+// # Safety
+//
+// - `filename` must be a valid null-terminated C string
+// - `crc32` if not null, must be a valid pointer to write the CRC32
+// - `md5` if not null, must be a valid pointer with at least 16 bytes available
+bool SFileGetFileChecksums(HANDLE archive, const char *filename, uint32_t *crc32, uint8_t *md5);
+
 // Get archive name from handle
 //
 // # Safety
@@ -144,24 +310,74 @@ bool SFileEnumFiles(HANDLE archive,
                     void *user_data);
 
 // Set locale for file operations
+//
+// Only the low 16 bits of the LCID are kept, since that is what the hash
+// table stores.
 uint32_t SFileSetLocale(uint32_t locale);
 
 // Get current locale
 uint32_t SFileGetLocale(void);
 
 // Get last error
+//
+// The error code is stored per thread: every API call sets it on the thread
+// that made the call, and it is never changed by calls on other threads. On
+// Windows the code is also passed to the system's `SetLastError`, so
+// `GetLastError()` from kernel32 returns the same value.
 uint32_t SFileGetLastError(void);
 
 // Set last error
+//
+// Only affects the calling thread.
 void SFileSetLastError(uint32_t error);
 
+#if !defined(_WIN32)
+// Get last error (StormLib-compatible alias of `SFileGetLastError`)
+//
+// StormLib provides this on non-Windows platforms for source compatibility.
+// It is not exported on Windows, where it would clash with kernel32.
+uint32_t GetLastError(void);
+#endif
+
+#if !defined(_WIN32)
+// Set last error (StormLib-compatible alias of `SFileSetLastError`)
+//
+// Not exported on Windows, where it would clash with kernel32.
+void SetLastError(uint32_t error);
+#endif
+
+// Get the MPQ encryption table
+//
+// Returns a pointer to the 0x500 `uint32_t` entries of the table StormLib
+// calls `StormBuffer`. The table is static and read-only; the pointer stays
+// valid for the lifetime of the process and must not be freed or written.
+const uint32_t *SFileGetStormBuffer(void);
+
 // Get file name from handle
 //
+// StormLib's signature has no buffer size, so the buffer is taken to hold
+// `MAX_PATH` bytes: longer names fail with `ERROR_INSUFFICIENT_BUFFER`
+// instead of overflowing it. Use `SFileGetFileNameEx` for names of any
+// length.
+//
 // # Safety
 //
-// - `buffer` must be a valid pointer with sufficient space for the filename
+// - `buffer` must be a valid pointer with at least `MAX_PATH` bytes available
 bool SFileGetFileName(HANDLE file, char *buffer);
 
+// Get file name from handle into a buffer of known size
+//
+// `needed` receives the size of the name including its terminating null.
+// Pass a null `buffer` with a `buffer_size` of 0 to only query that size;
+// the call then fails with `ERROR_INSUFFICIENT_BUFFER` like any buffer
+// that is too small, and nothing is written to `buffer`.
+//
+// # Safety
+//
+// - `buffer` if not null, must be a valid pointer with at least `buffer_size` bytes available
+// - `needed` if not null, must be a valid pointer to write the required size
+bool SFileGetFileNameEx(HANDLE file, char *buffer, uint32_t buffer_size, uint32_t *needed);
+
 // Extract a file from archive to disk
 //
 // # Safety
@@ -190,6 +406,158 @@ bool SFileVerifyFile(HANDLE archive, const char *filename, uint32_t flags);
 // - Must be called with valid archive handles obtained from `SFileOpenArchive`
 bool SFileVerifyArchive(HANDLE archive, uint32_t flags);
 
+// Register a progress callback for `SFileAddFileEx`
+//
+// Pass a null callback to remove it. The callback is called on the thread
+// that adds the file, while the archive is locked, so it must not call back
+// into the API for the same archive.
+bool SFileSetAddFileCallback(HANDLE archive, SFILE_ADDFILE_CALLBACK callback, void *user_data);
+
+// Register a progress callback for `SFileCompactArchive`
+//
+// Pass a null callback to remove it. The same restrictions as for
+// `SFileSetAddFileCallback` apply.
+bool SFileSetCompactCallback(HANDLE archive, SFILE_COMPACT_CALLBACK callback, void *user_data);
+
+// Add a file from disk to an archive
+//
+// The file's data is appended to the archive in place, replacing the file
+// stored for the same locale if `MPQ_FILE_REPLACEEXISTING` is set, and the
+// name is added to the `(listfile)` if there is one. Compression is applied
+// when `MPQ_FILE_COMPRESS` is set in `flags`, using the `MPQ_COMPRESSION_*`
+// mask in `compression` for a single-unit file or the first sector and the
+// one in `compression_next` for the sectors after it, unless that is
+// `MPQ_COMPRESSION_NEXT_SAME`. `MPQ_FILE_IMPLODE` instead implodes the file
+// with PKWare DCL, ignoring both masks, and cannot be combined with
+// `MPQ_FILE_COMPRESS`. `MPQ_FILE_ENCRYPTED`, `MPQ_FILE_FIX_KEY`,
+// `MPQ_FILE_SINGLE_UNIT` and `MPQ_FILE_SECTOR_CRC` are honoured as well;
+// any other flag fails with `ERROR_INVALID_PARAMETER`. The file is stored with the locale set by
+// `SFileSetLocale`. Progress is reported to the callback registered with
+// `SFileSetAddFileCallback`. `(listfile)`, `(attributes)` and `(signature)`
+// are maintained by the library and fail with `ERROR_INTERNAL_FILE`.
+//
+// # Safety
+//
+// - `file_name` must be a valid null-terminated C string
+// - `archived_name` must be a valid null-terminated C string
+bool SFileAddFileEx(HANDLE archive,
+                    const char *file_name,
+                    const char *archived_name,
+                    uint32_t flags,
+                    uint32_t compression,
+                    uint32_t compression_next);
+
+// Remove a file from an archive
+//
+// The version of the file stored for the locale set by `SFileSetLocale` is
+// removed in place and its name dropped from the `(listfile)` unless other
+// locales remain; its data stays behind as unused space until the archive
+// is compacted. `(listfile)`, `(attributes)` and `(signature)` are
+// maintained by the library and fail with `ERROR_INTERNAL_FILE`.
+//
+// # Safety
+//
+// - `file_name` must be a valid null-terminated C string
+// - `_search_scope` is ignored
+bool SFileRemoveFile(HANDLE archive, const char *file_name, uint32_t _search_scope);
+
+// Compact an archive, removing unused space
+//
+// The archive is rebuilt from the files named in its `(listfile)`, dropping
+// deleted files and any gaps between blocks. Fails with `ERROR_NOT_SUPPORTED`
+// if there is no `(listfile)` or it does not name every file, since those
+// files could not be copied. Progress is reported to the callback registered
+// with `SFileSetCompactCallback`.
+//
+// # Safety
+//
+// - `_list_file` is ignored; only the archive's own `(listfile)` is used
+bool SFileCompactArchive(HANDLE archive, const char *_list_file, bool _reserved);
+
+// Create an empty virtual file system
+//
+// Mount archives and directories with `SVfsMountArchive` and
+// `SVfsMountDirectory`, then open files with `SVfsOpenFile`. Loose files
+// override archives; among archives, and among directories, the higher
+// priority wins, and at equal priority the source mounted later. Names are
+// matched as in archives, ignoring case and treating `/` as `\`, for loose
+// files too.
+//
+// # Safety
+//
+// - `handle` must be a valid pointer to write the output handle
+bool SVfsCreate(HANDLE *handle);
+
+// Close a virtual file system and every file opened through it
+//
+// Archives mounted in it are closed as well.
+bool SVfsClose(HANDLE vfs);
+
+// Open the archive at `filename` and mount it with `priority`
+//
+// The archive is opened by the VFS and stays open until it is unmounted or
+// the VFS is closed.
+//
+// # Safety
+//
+// - `filename` must be a valid null-terminated C string
+bool SVfsMountArchive(HANDLE vfs, const char *filename, int32_t priority);
+
+// Mount the loose files under the directory at `path` with `priority`
+//
+// The directory is read at lookup time, so files added to it later are
+// found too. Fails with `ERROR_INVALID_PARAMETER` if `path` is not a
+// directory.
+//
+// # Safety
+//
+// - `path` must be a valid null-terminated C string
+bool SVfsMountDirectory(HANDLE vfs, const char *path, int32_t priority);
+
+// Unmount every archive and directory mounted from `path`
+//
+// Files already opened from them stay readable. Fails with
+// `ERROR_FILE_NOT_FOUND` if nothing was mounted from `path`.
+//
+// # Safety
+//
+// - `path` must be a valid null-terminated C string
+bool SVfsUnmount(HANDLE vfs, const char *path);
+
+// Check if any source mounted in a virtual file system has a file
+//
+// # Safety
+//
+// - `filename` must be a valid null-terminated C string
+bool SVfsHasFile(HANDLE vfs, const char *filename);
+
+// Open a file from the source of a virtual file system that takes
+// precedence
+//
+// The handle works with the `SFile*` file functions and is closed with
+// `SFileCloseFile`, or with the VFS. Files read from loose directories
+// have no `(attributes)`, so `SFileGetFileInfo` reports
+// `ERROR_NOT_SUPPORTED` for `SFILE_INFO_FILE_TIME`, `SFILE_INFO_CRC32` and
+// `SFILE_INFO_MD5` on any file opened this way.
+//
+// # Safety
+//
+// - `filename` must be a valid null-terminated C string
+// - `file_handle` must be a valid pointer to write the output handle
+bool SVfsOpenFile(HANDLE vfs, const char *filename, HANDLE *file_handle);
+
+// Enumerate the files of a virtual file system
+//
+// Each name is reported once, as listed by the source it is read from, in
+// sorted order. Archives contribute the names in their `(listfile)`,
+// leaving out special files; directories contribute every file below them.
+// Enumeration stops when `callback` returns false.
+//
+// # Safety
+//
+// - `callback` function pointer must be valid for the duration of enumeration
+bool SVfsEnumFiles(HANDLE vfs, bool (*callback)(const char*, void*), void *user_data);
+
 #ifdef __cplusplus
 }  // extern "C"
 #endif  // __cplusplus
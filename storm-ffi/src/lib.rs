@@ -1,15 +1,21 @@
 //! StormLib-compatible C API for the storm MPQ archive library
 
-use libc::{c_char, c_void};
+use libc::{c_char, c_int, c_void};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::fs;
 use std::path::Path;
 use std::ptr;
-use std::sync::{LazyLock, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
 
-use mopaq::{Archive, ArchiveBuilder, FormatVersion, ListfileOption};
+use mopaq::compression::flags as compression_flags;
+use mopaq::special_files::{FileAttributes, SpecialFile};
+use mopaq::{
+    AddFileOptions, Archive, ArchiveBuilder, BuildObserver, FormatVersion, ListfileOption, Locale,
+    MpqVfs, MutableArchive,
+};
 
 /// Archive handle type
 pub type HANDLE = *mut c_void;
@@ -17,47 +23,174 @@ pub type HANDLE = *mut c_void;
 /// Invalid handle value
 pub const INVALID_HANDLE_VALUE: HANDLE = ptr::null_mut();
 
+/// Progress callback for `SFileAddFileEx`
+///
+/// Called with the user data, the bytes of the file processed so far, the
+/// file's total size, and whether this is the final call for the file.
+#[allow(non_camel_case_types)]
+pub type SFILE_ADDFILE_CALLBACK = Option<extern "C" fn(*mut c_void, u32, u32, bool)>;
+
+/// Progress callback for `SFileCompactArchive`
+///
+/// Called with the user data, the current `CCB_*` work type, and the bytes
+/// processed so far out of the total for that stage.
+#[allow(non_camel_case_types)]
+pub type SFILE_COMPACT_CALLBACK = Option<extern "C" fn(*mut c_void, u32, u64, u64)>;
+
 // Thread-safe handle management with lazy initialization
-static NEXT_HANDLE: LazyLock<Mutex<usize>> = LazyLock::new(|| Mutex::new(1));
-static ARCHIVES: LazyLock<Mutex<HashMap<usize, ArchiveHandle>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
-static FILES: LazyLock<Mutex<HashMap<usize, FileHandle>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
+//
+// The maps are only locked long enough to look up, insert or remove a handle.
+// Each handle has its own lock, so long operations on one archive do not block
+// other archives or files. Archive and VFS handles are read-write locked:
+// calls that only read file data share the lock, while calls that load
+// attributes, mount sources or modify the archive take it exclusively. Locks
+// are always taken in the order map, then handle; a handle lock is never held
+// while taking the same map's lock. File handles are stored with the archive
+// or VFS handle they were opened from, so closing that owner drops them
+// without locking any of them.
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(1);
+static ARCHIVES: LazyLock<RwLock<HashMap<usize, Arc<RwLock<ArchiveHandle>>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+static FILES: LazyLock<RwLock<HashMap<usize, OwnedFile>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+static VFSES: LazyLock<RwLock<HashMap<usize, Arc<RwLock<MpqVfs>>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
 
 // Thread-local error storage
 thread_local! {
     static LAST_ERROR: RefCell<u32> = const { RefCell::new(ERROR_SUCCESS) };
-    static LOCALE: RefCell<u32> = const { RefCell::new(LOCALE_NEUTRAL) };
+    static LOCALE: RefCell<Locale> = const { RefCell::new(Locale::NEUTRAL) };
 }
 
 // Internal handle structures
 struct ArchiveHandle {
     archive: Archive,
     path: String,
+    add_file_callback: Option<Callback<extern "C" fn(*mut c_void, u32, u32, bool)>>,
+    compact_callback: Option<Callback<extern "C" fn(*mut c_void, u32, u64, u64)>>,
 }
 
+// A registered progress callback and the user data handed back to it
+#[derive(Clone, Copy)]
+struct Callback<F> {
+    func: F,
+    user_data: *mut c_void,
+}
+
+// SAFETY: the user data is never dereferenced here, only passed back to the
+// caller's own callback. As with StormLib, making it usable from the thread
+// that runs the operation is the caller's responsibility.
+unsafe impl<F: Send> Send for Callback<F> {}
+// SAFETY: shared access only copies the pointer out, never dereferences it.
+unsafe impl<F: Sync> Sync for Callback<F> {}
+
+// A file handle and the archive or VFS handle it was opened from
+type OwnedFile = (usize, Arc<Mutex<FileHandle>>);
+
 struct FileHandle {
-    archive_handle: usize,
     filename: String,
     data: Vec<u8>,
     position: usize,
     size: u64,
+    attributes: Option<FileAttributes>,
 }
 
 // Error codes (matching Windows/StormLib error codes)
-const ERROR_SUCCESS: u32 = 0;
-const ERROR_FILE_NOT_FOUND: u32 = 2;
-const ERROR_ACCESS_DENIED: u32 = 5;
-const ERROR_INVALID_HANDLE: u32 = 6;
-const _ERROR_NOT_ENOUGH_MEMORY: u32 = 8;
-const ERROR_INVALID_PARAMETER: u32 = 87;
-const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
-const ERROR_ALREADY_EXISTS: u32 = 183;
-const ERROR_FILE_CORRUPT: u32 = 1392;
-const ERROR_NOT_SUPPORTED: u32 = 50;
-
-// Locale constants
-const LOCALE_NEUTRAL: u32 = 0;
+
+/// The operation completed successfully
+pub const ERROR_SUCCESS: u32 = 0;
+/// The file does not exist, in the archive or on disk
+pub const ERROR_FILE_NOT_FOUND: u32 = 2;
+/// The file could not be opened or written, or the archive is read-only
+pub const ERROR_ACCESS_DENIED: u32 = 5;
+/// The handle is not a valid archive, file or VFS handle
+pub const ERROR_INVALID_HANDLE: u32 = 6;
+/// Not enough memory to complete the operation
+pub const ERROR_NOT_ENOUGH_MEMORY: u32 = 8;
+/// The file is not an MPQ archive or uses an unsupported format version
+pub const ERROR_BAD_FORMAT: u32 = 11;
+/// The operation is not supported for this archive or file
+pub const ERROR_NOT_SUPPORTED: u32 = 50;
+/// An argument was null, malformed or out of range
+pub const ERROR_INVALID_PARAMETER: u32 = 87;
+/// The disk is full, or the archive cannot hold more files
+pub const ERROR_DISK_FULL: u32 = 112;
+/// The buffer passed in is too small for the result
+pub const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
+/// The file being created already exists
+pub const ERROR_ALREADY_EXISTS: u32 = 183;
+/// The operation failed for a reason not covered by a more specific code
+pub const ERROR_CAN_NOT_COMPLETE: u32 = 1003;
+/// The archive or file data is corrupt
+pub const ERROR_FILE_CORRUPT: u32 = 1392;
+/// The file is an AVI video rather than an MPQ archive
+pub const ERROR_AVI_FILE: u32 = 10000;
+/// The key of an encrypted file could not be determined
+pub const ERROR_UNKNOWN_FILE_KEY: u32 = 10001;
+/// A sector or file checksum does not match the data
+pub const ERROR_CHECKSUM_ERROR: u32 = 10002;
+/// The operation is not allowed on an internal file such as `(listfile)`
+pub const ERROR_INTERNAL_FILE: u32 = 10003;
+/// The archive ends before the data it refers to
+pub const ERROR_FILE_INCOMPLETE: u32 = 10006;
+
+/// Map a mopaq error to the StormLib error code callers branch on
+///
+/// Every variant is listed explicitly so that new variants have to be given a
+/// code here rather than silently falling into a default.
+fn error_code(error: &mopaq::Error) -> u32 {
+    use mopaq::Error;
+
+    match error {
+        Error::Io(e) => match e.kind() {
+            std::io::ErrorKind::NotFound => ERROR_FILE_NOT_FOUND,
+            std::io::ErrorKind::AlreadyExists => ERROR_ALREADY_EXISTS,
+            std::io::ErrorKind::UnexpectedEof => ERROR_FILE_INCOMPLETE,
+            std::io::ErrorKind::OutOfMemory => ERROR_NOT_ENOUGH_MEMORY,
+            std::io::ErrorKind::StorageFull => ERROR_DISK_FULL,
+            std::io::ErrorKind::InvalidInput => ERROR_INVALID_PARAMETER,
+            std::io::ErrorKind::NotADirectory => ERROR_INVALID_PARAMETER,
+            _ => ERROR_ACCESS_DENIED,
+        },
+        Error::InvalidFormat(_) => ERROR_FILE_CORRUPT,
+        Error::UnsupportedVersion(_) => ERROR_BAD_FORMAT,
+        Error::FileNotFound(_) => ERROR_FILE_NOT_FOUND,
+        Error::HashTable(_) => ERROR_FILE_CORRUPT,
+        Error::BlockTable(_) => ERROR_FILE_CORRUPT,
+        Error::Crypto(_) => ERROR_UNKNOWN_FILE_KEY,
+        Error::Compression(_) => ERROR_FILE_CORRUPT,
+        Error::SignatureVerification(_) => ERROR_FILE_CORRUPT,
+        Error::InvalidHeader(_) => ERROR_BAD_FORMAT,
+        Error::ReadOnly => ERROR_ACCESS_DENIED,
+        Error::OperationNotSupported { .. } => ERROR_NOT_SUPPORTED,
+        Error::InvalidFileSize { .. } => ERROR_FILE_CORRUPT,
+        Error::MemoryMap(_) => ERROR_CAN_NOT_COMPLETE,
+        Error::InvalidUtf8 => ERROR_INVALID_PARAMETER,
+        Error::CapacityExceeded(_) => ERROR_DISK_FULL,
+        Error::ChecksumMismatch { .. } => ERROR_CHECKSUM_ERROR,
+        Error::MD5Mismatch { .. } => ERROR_CHECKSUM_ERROR,
+        Error::CorruptSector { .. } => ERROR_FILE_CORRUPT,
+        Error::ExpansionLimit { .. } => ERROR_FILE_CORRUPT,
+        Error::InvalidPattern(_) => ERROR_INVALID_PARAMETER,
+        Error::Incompatible { .. } => ERROR_NOT_SUPPORTED,
+    }
+}
+
+/// Check whether a file that failed to open as an MPQ is an AVI video
+///
+/// Some games ship intro videos with the `.mpq` extension, and StormLib
+/// reports these with a dedicated code.
+fn is_avi_file(path: &str) -> bool {
+    use std::io::Read;
+
+    let mut magic = [0u8; 12];
+    fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok_and(|_| &magic[0..4] == b"RIFF" && &magic[8..12] == b"AVI ")
+}
+
+/// Buffer size `SFileGetFileName` assumes, in bytes
+pub const MAX_PATH: u32 = 260;
 
 // Search scope flags (for SFileOpenFileEx)
 const _SFILE_OPEN_FROM_MPQ: u32 = 0x00000000;
@@ -84,6 +217,12 @@ const _SFILE_INFO_FLAGS: u32 = 9;
 const SFILE_INFO_POSITION: u32 = 10;
 const _SFILE_INFO_KEY: u32 = 11;
 const _SFILE_INFO_KEY_UNFIXED: u32 = 12;
+/// File handle info class: FILETIME from `(attributes)` as a `uint64_t`
+pub const SFILE_INFO_FILE_TIME: u32 = 13;
+/// File handle info class: CRC32 from `(attributes)` as a `uint32_t`
+pub const SFILE_INFO_CRC32: u32 = 14;
+/// File handle info class: MD5 from `(attributes)` as 16 bytes
+pub const SFILE_INFO_MD5: u32 = 15;
 
 // Archive open flags
 const _MPQ_OPEN_NO_LISTFILE: u32 = 0x0001;
@@ -104,9 +243,71 @@ const _MPQ_CREATE_ARCHIVE_V2: u32 = 0x01000000;
 const _MPQ_CREATE_ARCHIVE_V3: u32 = 0x02000000;
 const _MPQ_CREATE_ARCHIVE_V4: u32 = 0x03000000;
 
+/// `SFileAddFileEx` flag: compress the file with PKWare DCL, imploded
+pub const MPQ_FILE_IMPLODE: u32 = 0x00000100;
+/// `SFileAddFileEx` flag: compress the file
+pub const MPQ_FILE_COMPRESS: u32 = 0x00000200;
+/// `SFileAddFileEx` flag: encrypt the file
+pub const MPQ_FILE_ENCRYPTED: u32 = 0x00010000;
+/// `SFileAddFileEx` flag: adjust the encryption key by the file position
+pub const MPQ_FILE_FIX_KEY: u32 = 0x00020000;
+/// `SFileAddFileEx` flag: store the file in one piece instead of in sectors
+pub const MPQ_FILE_SINGLE_UNIT: u32 = 0x01000000;
+/// `SFileAddFileEx` flag: write sector checksums
+pub const MPQ_FILE_SECTOR_CRC: u32 = 0x04000000;
+/// `SFileAddFileEx` flag: replace a file that already exists
+pub const MPQ_FILE_REPLACEEXISTING: u32 = 0x80000000;
+// Flags `SFileAddFileEx` understands
+const ADD_FILE_FLAGS: u32 = MPQ_FILE_IMPLODE
+    | MPQ_FILE_COMPRESS
+    | MPQ_FILE_ENCRYPTED
+    | MPQ_FILE_FIX_KEY
+    | MPQ_FILE_SINGLE_UNIT
+    | MPQ_FILE_SECTOR_CRC
+    | MPQ_FILE_REPLACEEXISTING;
+/// `SFileAddFileEx` `compression_next`: compress every sector like the first
+pub const MPQ_COMPRESSION_NEXT_SAME: u32 = 0xFFFFFFFF;
+
+/// Compaction stage: reading the files to keep
+pub const CCB_CHECKING_FILES: u32 = 1;
+/// Compaction stage: checking the hash table (not reported by this library)
+pub const CCB_CHECKING_HASH_TABLE: u32 = 2;
+/// Compaction stage: copying data outside the MPQ (not reported by this library)
+pub const CCB_COPYING_NON_MPQ_DATA: u32 = 3;
+/// Compaction stage: writing the files into the compacted archive
+pub const CCB_COMPACTING_FILES: u32 = 4;
+/// Compaction stage: writing the tables and closing the archive
+pub const CCB_CLOSING_ARCHIVE: u32 = 5;
+
 // Helper functions
+fn next_handle_id() -> usize {
+    NEXT_HANDLE.fetch_add(1, Ordering::Relaxed)
+}
+
+fn get_archive(id: usize) -> Option<Arc<RwLock<ArchiveHandle>>> {
+    ARCHIVES.read().unwrap().get(&id).cloned()
+}
+
+fn get_file(id: usize) -> Option<Arc<Mutex<FileHandle>>> {
+    FILES.read().unwrap().get(&id).map(|(_, file)| file.clone())
+}
+
+fn get_vfs(id: usize) -> Option<Arc<RwLock<MpqVfs>>> {
+    VFSES.read().unwrap().get(&id).cloned()
+}
+
 fn set_last_error(error: u32) {
     LAST_ERROR.with(|e| *e.borrow_mut() = error);
+
+    // Keep the Win32 thread error in sync so kernel32's GetLastError works too
+    #[cfg(windows)]
+    {
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn SetLastError(code: u32);
+        }
+        unsafe { SetLastError(error) };
+    }
 }
 
 fn handle_to_id(handle: HANDLE) -> Option<usize> {
@@ -152,38 +353,124 @@ pub unsafe extern "C" fn SFileOpenArchive(
     // Open the archive
     match Archive::open(filename_str) {
         Ok(archive) => {
-            // Generate new handle ID
-            let mut next_id = NEXT_HANDLE.lock().unwrap();
-            let handle_id = *next_id;
-            *next_id += 1;
-            drop(next_id);
-
-            // Store archive
-            let archive_handle = ArchiveHandle {
-                archive,
-                path: filename_str.to_string(),
+            *handle = insert_archive(archive, filename_str.to_string());
+            set_last_error(ERROR_SUCCESS);
+            true
+        }
+        Err(e) => {
+            let code = match e {
+                mopaq::Error::InvalidFormat(_) | mopaq::Error::InvalidHeader(_)
+                    if is_avi_file(filename_str) =>
+                {
+                    ERROR_AVI_FILE
+                }
+                e => error_code(&e),
             };
-            ARCHIVES.lock().unwrap().insert(handle_id, archive_handle);
+            set_last_error(code);
+            false
+        }
+    }
+}
+
+// Store an open archive under a new handle
+fn insert_archive(archive: Archive, path: String) -> HANDLE {
+    let handle_id = next_handle_id();
+    let archive_handle = ArchiveHandle {
+        archive,
+        path,
+        add_file_callback: None,
+        compact_callback: None,
+    };
+    ARCHIVES
+        .write()
+        .unwrap()
+        .insert(handle_id, Arc::new(RwLock::new(archive_handle)));
+    id_to_handle(handle_id)
+}
 
-            // Return handle
-            *handle = id_to_handle(handle_id);
+// Open an archive from a file the caller already opened
+fn open_archive_file(file: fs::File, handle: *mut HANDLE) -> bool {
+    match Archive::from_file(file) {
+        Ok(archive) => {
+            unsafe { *handle = insert_archive(archive, String::new()) };
             set_last_error(ERROR_SUCCESS);
             true
         }
         Err(e) => {
-            // Map mopaq errors to Windows error codes
-            let error_code = match e {
-                mopaq::Error::FileNotFound(_) => ERROR_FILE_NOT_FOUND,
-                mopaq::Error::InvalidFormat(_) => ERROR_FILE_CORRUPT,
-                mopaq::Error::Io(_) => ERROR_ACCESS_DENIED,
-                _ => ERROR_FILE_CORRUPT,
-            };
-            set_last_error(error_code);
+            set_last_error(error_code(&e));
             false
         }
     }
 }
 
+/// Open an MPQ archive from a file descriptor
+///
+/// The library owns `fd` from the call on, even if it fails: it is closed
+/// by `SFileCloseArchive`, or right away when the archive cannot be opened.
+/// The archive has no name, so `SFileGetArchiveName` returns an empty
+/// string, and `SFileAddFileEx` and `SFileCompactArchive`, which rewrite the
+/// archive at its path, fail with `ERROR_NOT_SUPPORTED`.
+///
+/// # Safety
+///
+/// - `fd` must be an open, readable file descriptor that the caller does not close
+/// - `handle` must be a valid pointer to write the output handle
+#[cfg(not(windows))]
+#[no_mangle]
+pub unsafe extern "C" fn SFileOpenArchiveFromFd(
+    fd: c_int,
+    _flags: u32, // Archive open flags
+    handle: *mut HANDLE,
+) -> bool {
+    use std::os::fd::FromRawFd;
+
+    if fd < 0 {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+    let file = fs::File::from_raw_fd(fd);
+    if handle.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+    open_archive_file(file, handle)
+}
+
+/// Open an MPQ archive from a Win32 file handle
+///
+/// The library owns `file` from the call on, even if it fails: it is closed
+/// by `SFileCloseArchive`, or right away when the archive cannot be opened.
+/// The handle may be opened with any sharing flags but needs read access.
+/// The archive has no name, so `SFileGetArchiveName` returns an empty
+/// string, and `SFileAddFileEx` and `SFileCompactArchive`, which rewrite the
+/// archive at its path, fail with `ERROR_NOT_SUPPORTED`.
+///
+/// # Safety
+///
+/// - `file` must be an open file handle that the caller does not close
+/// - `handle` must be a valid pointer to write the output handle
+#[cfg(windows)]
+#[no_mangle]
+pub unsafe extern "C" fn SFileOpenArchiveFromHandle(
+    file: HANDLE,
+    _flags: u32, // Archive open flags
+    handle: *mut HANDLE,
+) -> bool {
+    use std::os::windows::io::FromRawHandle;
+
+    // INVALID_HANDLE_VALUE from CreateFile is -1, not null
+    if file.is_null() || file as isize == -1 {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+    let file = fs::File::from_raw_handle(file);
+    if handle.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+    open_archive_file(file, handle)
+}
+
 /// Create a new MPQ archive
 ///
 /// # Safety
@@ -273,14 +560,7 @@ pub unsafe extern "C" fn SFileCreateArchive(
             SFileOpenArchive(filename, 0, 0, handle)
         }
         Err(e) => {
-            // Map creation errors to Windows error codes
-            let error_code = match e {
-                mopaq::Error::FileNotFound(_) => ERROR_FILE_NOT_FOUND,
-                mopaq::Error::InvalidFormat(_) => ERROR_INVALID_PARAMETER,
-                mopaq::Error::Io(_) => ERROR_ACCESS_DENIED,
-                _ => ERROR_ACCESS_DENIED,
-            };
-            set_last_error(error_code);
+            set_last_error(error_code(&e));
             false
         }
     }
@@ -292,12 +572,12 @@ pub extern "C" fn SFileCloseArchive(handle: HANDLE) -> bool {
     if let Some(handle_id) = handle_to_id(handle) {
         // Remove any open files from this archive
         FILES
-            .lock()
+            .write()
             .unwrap()
-            .retain(|_, file| file.archive_handle != handle_id);
+            .retain(|_, (owner, _)| *owner != handle_id);
 
         // Close the archive
-        if ARCHIVES.lock().unwrap().remove(&handle_id).is_some() {
+        if ARCHIVES.write().unwrap().remove(&handle_id).is_some() {
             set_last_error(ERROR_SUCCESS);
             true
         } else {
@@ -344,11 +624,11 @@ pub unsafe extern "C" fn SFileOpenFileEx(
     };
 
     // Get the archive
-    let mut archives = ARCHIVES.lock().unwrap();
-    let Some(archive_handle) = archives.get_mut(&archive_id) else {
+    let Some(archive_lock) = get_archive(archive_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return false;
     };
+    let archive_handle = archive_lock.read().unwrap();
 
     // Try to find and read the file
     match archive_handle.archive.find_file(filename_str) {
@@ -356,31 +636,33 @@ pub unsafe extern "C" fn SFileOpenFileEx(
             // Read the file data
             match archive_handle.archive.read_file(filename_str) {
                 Ok(data) => {
+                    drop(archive_handle);
+
                     // Generate file handle
-                    let mut next_id = NEXT_HANDLE.lock().unwrap();
-                    let file_id = *next_id;
-                    *next_id += 1;
-                    drop(next_id);
+                    let file_id = next_handle_id();
 
                     // Create file handle
                     let file = FileHandle {
-                        archive_handle: archive_id,
                         filename: filename_str.to_string(),
                         data,
                         position: 0,
                         size: file_info.file_size,
+                        attributes: file_attributes(&archive_lock, file_info.block_index),
                     };
 
                     // Store file handle
-                    FILES.lock().unwrap().insert(file_id, file);
+                    FILES
+                        .write()
+                        .unwrap()
+                        .insert(file_id, (archive_id, Arc::new(Mutex::new(file))));
 
                     // Return handle
                     *file_handle = id_to_handle(file_id);
                     set_last_error(ERROR_SUCCESS);
                     true
                 }
-                Err(_) => {
-                    set_last_error(ERROR_FILE_CORRUPT);
+                Err(e) => {
+                    set_last_error(error_code(&e));
                     false
                 }
             }
@@ -389,8 +671,8 @@ pub unsafe extern "C" fn SFileOpenFileEx(
             set_last_error(ERROR_FILE_NOT_FOUND);
             false
         }
-        Err(_) => {
-            set_last_error(ERROR_FILE_CORRUPT);
+        Err(e) => {
+            set_last_error(error_code(&e));
             false
         }
     }
@@ -400,7 +682,7 @@ pub unsafe extern "C" fn SFileOpenFileEx(
 #[no_mangle]
 pub extern "C" fn SFileCloseFile(file: HANDLE) -> bool {
     if let Some(file_id) = handle_to_id(file) {
-        if FILES.lock().unwrap().remove(&file_id).is_some() {
+        if FILES.write().unwrap().remove(&file_id).is_some() {
             set_last_error(ERROR_SUCCESS);
             true
         } else {
@@ -415,6 +697,9 @@ pub extern "C" fn SFileCloseFile(file: HANDLE) -> bool {
 
 /// Read from a file
 ///
+/// At most `to_read` bytes are written to `buffer`. `*read` is set on every
+/// path, to 0 when the call fails.
+///
 /// # Safety
 ///
 /// - `buffer` must be a valid pointer with at least `to_read` bytes available
@@ -427,6 +712,10 @@ pub unsafe extern "C" fn SFileReadFile(
     read: *mut u32,
     _overlapped: *mut c_void, // Ignored - no async I/O support
 ) -> bool {
+    if !read.is_null() {
+        *read = 0;
+    }
+
     // Validate parameters
     if buffer.is_null() {
         set_last_error(ERROR_INVALID_PARAMETER);
@@ -439,20 +728,17 @@ pub unsafe extern "C" fn SFileReadFile(
     };
 
     // Get file handle
-    let mut files = FILES.lock().unwrap();
-    let Some(file_handle) = files.get_mut(&file_id) else {
+    let Some(file_handle) = get_file(file_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return false;
     };
+    let mut file_handle = file_handle.lock().unwrap();
 
     // Calculate how much we can read
     let remaining = file_handle.data.len().saturating_sub(file_handle.position);
     let bytes_to_read = (to_read as usize).min(remaining);
 
     if bytes_to_read == 0 {
-        if !read.is_null() {
-            *read = 0;
-        }
         set_last_error(ERROR_SUCCESS);
         return true;
     }
@@ -485,11 +771,11 @@ pub unsafe extern "C" fn SFileGetFileSize(file: HANDLE, high: *mut u32) -> u32 {
         return 0xFFFFFFFF; // INVALID_FILE_SIZE
     };
 
-    let files = FILES.lock().unwrap();
-    let Some(file_handle) = files.get(&file_id) else {
+    let Some(file_handle) = get_file(file_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return 0xFFFFFFFF;
     };
+    let file_handle = file_handle.lock().unwrap();
 
     let size = file_handle.size;
 
@@ -519,18 +805,20 @@ pub unsafe extern "C" fn SFileSetFilePointer(
         return 0xFFFFFFFF; // INVALID_SET_FILE_POINTER
     };
 
-    let mut files = FILES.lock().unwrap();
-    let Some(file_handle) = files.get_mut(&file_id) else {
+    let Some(file_handle) = get_file(file_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return 0xFFFFFFFF;
     };
+    let mut file_handle = file_handle.lock().unwrap();
 
-    // Combine high and low parts into 64-bit offset
-    let mut offset = file_pos as i64;
-    if !file_pos_high.is_null() {
-        let high = *file_pos_high as i64;
-        offset |= high << 32;
-    }
+    // Combine high and low parts into 64-bit offset. Without a high part
+    // the low part is a signed distance; with one it is the unsigned low
+    // DWORD, which must not be sign-extended into the high part.
+    let offset = if file_pos_high.is_null() {
+        file_pos as i64
+    } else {
+        ((*file_pos_high as i64) << 32) | (file_pos as u32 as i64)
+    };
 
     // Calculate new position
     let new_pos = match move_method {
@@ -576,11 +864,16 @@ pub unsafe extern "C" fn SFileHasFile(archive: HANDLE, filename: *const c_char)
         Err(_) => return false,
     };
 
-    let archives = ARCHIVES.lock().unwrap();
-    if let Some(archive_handle) = archives.get(&archive_id) {
-        matches!(archive_handle.archive.find_file(filename_str), Ok(Some(_)))
-    } else {
-        false
+    match get_archive(archive_id) {
+        Some(archive_handle) => matches!(
+            archive_handle
+                .read()
+                .unwrap()
+                .archive
+                .find_file(filename_str),
+            Ok(Some(_))
+        ),
+        None => false,
     }
 }
 
@@ -613,13 +906,21 @@ pub unsafe extern "C" fn SFileGetFileInfo(
     };
 
     // Try as file first
-    if let Some(file_handle) = FILES.lock().unwrap().get(&handle_id) {
-        return get_file_info(file_handle, info_class, buffer, buffer_size, size_needed);
+    if let Some(file_handle) = get_file(handle_id) {
+        let file_handle = file_handle.lock().unwrap();
+        return get_file_info(&file_handle, info_class, buffer, buffer_size, size_needed);
     }
 
     // Try as archive
-    if let Some(archive_handle) = ARCHIVES.lock().unwrap().get(&handle_id) {
-        return get_archive_info(archive_handle, info_class, buffer, buffer_size, size_needed);
+    if let Some(archive_handle) = get_archive(handle_id) {
+        let archive_handle = archive_handle.read().unwrap();
+        return get_archive_info(
+            &archive_handle,
+            info_class,
+            buffer,
+            buffer_size,
+            size_needed,
+        );
     }
 
     set_last_error(ERROR_INVALID_HANDLE);
@@ -663,6 +964,36 @@ unsafe fn get_file_info(
                 false
             }
         }
+        SFILE_INFO_FILE_TIME | SFILE_INFO_CRC32 | SFILE_INFO_MD5 => {
+            let attributes = file_handle.attributes.as_ref();
+            let value = match info_class {
+                SFILE_INFO_FILE_TIME => attributes
+                    .and_then(|a| a.filetime)
+                    .map(|t| t.to_le_bytes().to_vec()),
+                SFILE_INFO_CRC32 => attributes
+                    .and_then(|a| a.crc32)
+                    .map(|c| c.to_le_bytes().to_vec()),
+                _ => attributes.and_then(|a| a.md5).map(|m| m.to_vec()),
+            };
+            let Some(value) = value else {
+                // The archive has no (attributes) entry of this kind
+                set_last_error(ERROR_NOT_SUPPORTED);
+                return false;
+            };
+
+            let needed = value.len() as u32;
+            if !size_needed.is_null() {
+                *size_needed = needed;
+            }
+            if buffer_size >= needed {
+                ptr::copy_nonoverlapping(value.as_ptr(), buffer as *mut u8, value.len());
+                set_last_error(ERROR_SUCCESS);
+                true
+            } else {
+                set_last_error(ERROR_INSUFFICIENT_BUFFER);
+                false
+            }
+        }
         _ => {
             set_last_error(ERROR_NOT_SUPPORTED);
             false
@@ -670,6 +1001,99 @@ unsafe fn get_file_info(
     }
 }
 
+// Look up the (attributes) entry for a block, loading the attributes on first use
+//
+// Only the load itself takes the write lock, so lookups on an archive whose
+// attributes are already loaded don't block readers of other files.
+fn file_attributes(
+    archive_handle: &RwLock<ArchiveHandle>,
+    block_index: usize,
+) -> Option<FileAttributes> {
+    {
+        let archive_handle = archive_handle.read().unwrap();
+        if archive_handle.archive.attributes().is_some() {
+            return archive_handle
+                .archive
+                .get_file_attributes(block_index)
+                .cloned();
+        }
+    }
+
+    let mut archive_handle = archive_handle.write().unwrap();
+    // Archives without (attributes) simply have nothing to report
+    let _ = archive_handle.archive.load_attributes();
+    archive_handle
+        .archive
+        .get_file_attributes(block_index)
+        .cloned()
+}
+
+/// Get the CRC32 and MD5 recorded for a file in `(attributes)`
+///
+/// Patchers can compare these against their own manifests without reading and
+/// hashing the file. Values the archive does not record are reported as zero,
+/// as StormLib does.
+///
+/// # Safety
+///
+/// - `filename` must be a valid null-terminated C string
+/// - `crc32` if not null, must be a valid pointer to write the CRC32
+/// - `md5` if not null, must be a valid pointer with at least 16 bytes available
+#[no_mangle]
+pub unsafe extern "C" fn SFileGetFileChecksums(
+    archive: HANDLE,
+    filename: *const c_char,
+    crc32: *mut u32,
+    md5: *mut u8,
+) -> bool {
+    if filename.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+
+    let Some(archive_id) = handle_to_id(archive) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    let filename_str = match CStr::from_ptr(filename).to_str() {
+        Ok(s) => s,
+        Err(_) => {
+            set_last_error(ERROR_INVALID_PARAMETER);
+            return false;
+        }
+    };
+
+    let Some(archive_lock) = get_archive(archive_id) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    let file_info = match archive_lock.read().unwrap().archive.find_file(filename_str) {
+        Ok(Some(info)) => info,
+        Ok(None) => {
+            set_last_error(ERROR_FILE_NOT_FOUND);
+            return false;
+        }
+        Err(e) => {
+            set_last_error(error_code(&e));
+            return false;
+        }
+    };
+
+    let attributes = file_attributes(&archive_lock, file_info.block_index);
+    if !crc32.is_null() {
+        *crc32 = attributes.as_ref().and_then(|a| a.crc32).unwrap_or(0);
+    }
+    if !md5.is_null() {
+        let digest = attributes.as_ref().and_then(|a| a.md5).unwrap_or([0; 16]);
+        ptr::copy_nonoverlapping(digest.as_ptr(), md5, digest.len());
+    }
+
+    set_last_error(ERROR_SUCCESS);
+    true
+}
+
 // Helper function for archive info
 unsafe fn get_archive_info(
     archive_handle: &ArchiveHandle,
@@ -765,11 +1189,11 @@ pub unsafe extern "C" fn SFileGetArchiveName(
         return false;
     };
 
-    let archives = ARCHIVES.lock().unwrap();
-    let Some(archive_handle) = archives.get(&archive_id) else {
+    let Some(archive_handle) = get_archive(archive_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return false;
     };
+    let archive_handle = archive_handle.read().unwrap();
 
     // Convert path to C string
     let c_path = match CString::new(archive_handle.path.as_str()) {
@@ -832,14 +1256,18 @@ pub unsafe extern "C" fn SFileEnumFiles(
     };
 
     // Get archive
-    let mut archives = ARCHIVES.lock().unwrap();
-    let Some(archive_handle) = archives.get_mut(&archive_id) else {
+    let Some(archive_handle) = get_archive(archive_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return false;
     };
+    let mut archive_handle = archive_handle.write().unwrap();
+
+    // List files, releasing the archive before calling back so the callback
+    // can use the API on the same archive
+    let listing = archive_handle.archive.list();
+    drop(archive_handle);
 
-    // List files
-    match archive_handle.archive.list() {
+    match listing {
         Ok(entries) => {
             for entry in entries {
                 // Simple pattern matching (just * for now)
@@ -868,44 +1296,105 @@ pub unsafe extern "C" fn SFileEnumFiles(
 }
 
 /// Set locale for file operations
+///
+/// Only the low 16 bits of the LCID are kept, since that is what the hash
+/// table stores.
 #[no_mangle]
 pub extern "C" fn SFileSetLocale(locale: u32) -> u32 {
-    let old_locale = LOCALE.with(|l| {
-        let old = *l.borrow();
-        *l.borrow_mut() = locale;
-        old
-    });
-    old_locale
+    let old_locale = LOCALE.with(|l| l.replace(Locale::from(locale as u16)));
+    old_locale.code() as u32
 }
 
 /// Get current locale
 #[no_mangle]
 pub extern "C" fn SFileGetLocale() -> u32 {
-    LOCALE.with(|l| *l.borrow())
+    LOCALE.with(|l| l.borrow().code() as u32)
 }
 
 /// Get last error
+///
+/// The error code is stored per thread: every API call sets it on the thread
+/// that made the call, and it is never changed by calls on other threads. On
+/// Windows the code is also passed to the system's `SetLastError`, so
+/// `GetLastError()` from kernel32 returns the same value.
 #[no_mangle]
 pub extern "C" fn SFileGetLastError() -> u32 {
     LAST_ERROR.with(|e| *e.borrow())
 }
 
 /// Set last error
+///
+/// Only affects the calling thread.
 #[no_mangle]
 pub extern "C" fn SFileSetLastError(error: u32) {
     set_last_error(error);
 }
 
+/// Get last error (StormLib-compatible alias of `SFileGetLastError`)
+///
+/// StormLib provides this on non-Windows platforms for source compatibility.
+/// It is not exported on Windows, where it would clash with kernel32.
+#[cfg(not(windows))]
+#[no_mangle]
+pub extern "C" fn GetLastError() -> u32 {
+    SFileGetLastError()
+}
+
+/// Set last error (StormLib-compatible alias of `SFileSetLastError`)
+///
+/// Not exported on Windows, where it would clash with kernel32.
+#[cfg(not(windows))]
+#[no_mangle]
+pub extern "C" fn SetLastError(error: u32) {
+    SFileSetLastError(error);
+}
+
+/// Get the MPQ encryption table
+///
+/// Returns a pointer to the 0x500 `uint32_t` entries of the table StormLib
+/// calls `StormBuffer`. The table is static and read-only; the pointer stays
+/// valid for the lifetime of the process and must not be freed or written.
+#[no_mangle]
+pub extern "C" fn SFileGetStormBuffer() -> *const u32 {
+    mopaq::crypto::ENCRYPTION_TABLE.as_ptr()
+}
+
 // Additional utility functions
 
 /// Get file name from handle
 ///
+/// StormLib's signature has no buffer size, so the buffer is taken to hold
+/// `MAX_PATH` bytes: longer names fail with `ERROR_INSUFFICIENT_BUFFER`
+/// instead of overflowing it. Use `SFileGetFileNameEx` for names of any
+/// length.
+///
 /// # Safety
 ///
-/// - `buffer` must be a valid pointer with sufficient space for the filename
+/// - `buffer` must be a valid pointer with at least `MAX_PATH` bytes available
 #[no_mangle]
 pub unsafe extern "C" fn SFileGetFileName(file: HANDLE, buffer: *mut c_char) -> bool {
-    if buffer.is_null() {
+    SFileGetFileNameEx(file, buffer, MAX_PATH, ptr::null_mut())
+}
+
+/// Get file name from handle into a buffer of known size
+///
+/// `needed` receives the size of the name including its terminating null.
+/// Pass a null `buffer` with a `buffer_size` of 0 to only query that size;
+/// the call then fails with `ERROR_INSUFFICIENT_BUFFER` like any buffer
+/// that is too small, and nothing is written to `buffer`.
+///
+/// # Safety
+///
+/// - `buffer` if not null, must be a valid pointer with at least `buffer_size` bytes available
+/// - `needed` if not null, must be a valid pointer to write the required size
+#[no_mangle]
+pub unsafe extern "C" fn SFileGetFileNameEx(
+    file: HANDLE,
+    buffer: *mut c_char,
+    buffer_size: u32,
+    needed: *mut u32,
+) -> bool {
+    if buffer.is_null() && buffer_size != 0 {
         set_last_error(ERROR_INVALID_PARAMETER);
         return false;
     }
@@ -915,11 +1404,11 @@ pub unsafe extern "C" fn SFileGetFileName(file: HANDLE, buffer: *mut c_char) ->
         return false;
     };
 
-    let files = FILES.lock().unwrap();
-    let Some(file_handle) = files.get(&file_id) else {
+    let Some(file_handle) = get_file(file_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return false;
     };
+    let file_handle = file_handle.lock().unwrap();
 
     let c_name = match CString::new(file_handle.filename.as_str()) {
         Ok(s) => s,
@@ -929,7 +1418,16 @@ pub unsafe extern "C" fn SFileGetFileName(file: HANDLE, buffer: *mut c_char) ->
         }
     };
 
-    std::ptr::copy_nonoverlapping(c_name.as_ptr(), buffer, c_name.as_bytes_with_nul().len());
+    let name_bytes = c_name.as_bytes_with_nul();
+    if !needed.is_null() {
+        *needed = name_bytes.len() as u32;
+    }
+    if name_bytes.len() > buffer_size as usize {
+        set_last_error(ERROR_INSUFFICIENT_BUFFER);
+        return false;
+    }
+
+    std::ptr::copy_nonoverlapping(name_bytes.as_ptr(), buffer as *mut u8, name_bytes.len());
 
     set_last_error(ERROR_SUCCESS);
     true
@@ -977,11 +1475,11 @@ pub unsafe extern "C" fn SFileExtractFile(
     };
 
     // Get the archive
-    let mut archives = ARCHIVES.lock().unwrap();
-    let Some(archive_handle) = archives.get_mut(&archive_id) else {
+    let Some(archive_handle) = get_archive(archive_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return false;
     };
+    let archive_handle = archive_handle.read().unwrap();
 
     // Try to read the file from the archive
     match archive_handle.archive.read_file(source_filename) {
@@ -1008,14 +1506,7 @@ pub unsafe extern "C" fn SFileExtractFile(
             }
         }
         Err(e) => {
-            // Map mopaq errors to Windows error codes
-            let error_code = match e {
-                mopaq::Error::FileNotFound(_) => ERROR_FILE_NOT_FOUND,
-                mopaq::Error::InvalidFormat(_) => ERROR_FILE_CORRUPT,
-                mopaq::Error::Io(_) => ERROR_ACCESS_DENIED,
-                _ => ERROR_FILE_CORRUPT,
-            };
-            set_last_error(error_code);
+            set_last_error(error_code(&e));
             false
         }
     }
@@ -1053,11 +1544,11 @@ pub unsafe extern "C" fn SFileVerifyFile(
     };
 
     // Get the archive
-    let mut archives = ARCHIVES.lock().unwrap();
-    let Some(archive_handle) = archives.get_mut(&archive_id) else {
+    let Some(archive_handle) = get_archive(archive_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return false;
     };
+    let mut archive_handle = archive_handle.write().unwrap();
 
     // Find the file first to get file info
     let file_info = match archive_handle.archive.find_file(filename_str) {
@@ -1066,8 +1557,8 @@ pub unsafe extern "C" fn SFileVerifyFile(
             set_last_error(ERROR_FILE_NOT_FOUND);
             return false;
         }
-        Err(_) => {
-            set_last_error(ERROR_FILE_CORRUPT);
+        Err(e) => {
+            set_last_error(error_code(&e));
             return false;
         }
     };
@@ -1082,12 +1573,8 @@ pub unsafe extern "C" fn SFileVerifyFile(
             Ok(_) => {
                 // File read successfully, CRCs validated automatically
             }
-            Err(mopaq::Error::ChecksumMismatch { .. }) => {
-                set_last_error(ERROR_FILE_CORRUPT);
-                return false;
-            }
-            Err(_) => {
-                set_last_error(ERROR_FILE_CORRUPT);
+            Err(e) => {
+                set_last_error(error_code(&e));
                 return false;
             }
         }
@@ -1108,12 +1595,12 @@ pub unsafe extern "C" fn SFileVerifyFile(
                     Ok(data) => {
                         let actual_crc = crc32fast::hash(&data);
                         if actual_crc != expected_crc {
-                            set_last_error(ERROR_FILE_CORRUPT);
+                            set_last_error(ERROR_CHECKSUM_ERROR);
                             return false;
                         }
                     }
-                    Err(_) => {
-                        set_last_error(ERROR_FILE_CORRUPT);
+                    Err(e) => {
+                        set_last_error(error_code(&e));
                         return false;
                     }
                 }
@@ -1139,12 +1626,12 @@ pub unsafe extern "C" fn SFileVerifyFile(
                         hasher.update(&data);
                         let actual_md5: [u8; 16] = hasher.finalize().into();
                         if actual_md5 != expected_md5 {
-                            set_last_error(ERROR_FILE_CORRUPT);
+                            set_last_error(ERROR_CHECKSUM_ERROR);
                             return false;
                         }
                     }
-                    Err(_) => {
-                        set_last_error(ERROR_FILE_CORRUPT);
+                    Err(e) => {
+                        set_last_error(error_code(&e));
                         return false;
                     }
                 }
@@ -1172,11 +1659,11 @@ pub unsafe extern "C" fn SFileVerifyArchive(archive: HANDLE, flags: u32) -> bool
     };
 
     // Get the archive
-    let mut archives = ARCHIVES.lock().unwrap();
-    let Some(archive_handle) = archives.get_mut(&archive_id) else {
+    let Some(archive_handle) = get_archive(archive_id) else {
         set_last_error(ERROR_INVALID_HANDLE);
         return false;
     };
+    let mut archive_handle = archive_handle.write().unwrap();
 
     // If no flags specified, verify signature by default
     let verify_flags = if flags == 0 {
@@ -1203,8 +1690,8 @@ pub unsafe extern "C" fn SFileVerifyArchive(archive: HANDLE, flags: u32) -> bool
             Ok(mopaq::archive::SignatureStatus::StrongNoKey) => {
                 // Strong signature present but no key - treat as warning, continue
             }
-            Err(_) => {
-                set_last_error(ERROR_FILE_CORRUPT);
+            Err(e) => {
+                set_last_error(error_code(&e));
                 return false;
             }
         }
@@ -1219,14 +1706,17 @@ pub unsafe extern "C" fn SFileVerifyArchive(archive: HANDLE, flags: u32) -> bool
                 // If we can't get the file list, try to enumerate from tables
                 match archive_handle.archive.list_all() {
                     Ok(list) => list,
-                    Err(_) => {
-                        set_last_error(ERROR_FILE_CORRUPT);
+                    Err(e) => {
+                        set_last_error(error_code(&e));
                         return false;
                     }
                 }
             }
         };
 
+        // SFileVerifyFile locks the archive itself
+        drop(archive_handle);
+
         // Verify each file individually
         for file_entry in file_list {
             // Skip special files and directories
@@ -1258,7 +1748,7 @@ pub unsafe extern "C" fn SFileVerifyArchive(archive: HANDLE, flags: u32) -> bool
                 if !SFileVerifyFile(archive, filename_cstr.as_ptr(), all_verify_flags) {
                     let last_error = SFileGetLastError();
                     // Only fail on corruption, not missing attributes
-                    if last_error == ERROR_FILE_CORRUPT {
+                    if last_error == ERROR_FILE_CORRUPT || last_error == ERROR_CHECKSUM_ERROR {
                         return false;
                     }
                 }
@@ -1270,34 +1760,951 @@ pub unsafe extern "C" fn SFileVerifyArchive(archive: HANDLE, flags: u32) -> bool
     true
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Register a progress callback for `SFileAddFileEx`
+///
+/// Pass a null callback to remove it. The callback is called on the thread
+/// that adds the file, while the archive is locked, so it must not call back
+/// into the API for the same archive.
+#[no_mangle]
+pub extern "C" fn SFileSetAddFileCallback(
+    archive: HANDLE,
+    callback: SFILE_ADDFILE_CALLBACK,
+    user_data: *mut c_void,
+) -> bool {
+    let Some(archive_handle) = handle_to_id(archive).and_then(get_archive) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
 
-    #[test]
-    fn test_handle_conversion() {
-        let id = 42usize;
-        let handle = id_to_handle(id);
-        assert_eq!(handle_to_id(handle), Some(id));
-        assert_eq!(handle_to_id(ptr::null_mut()), None);
-    }
+    archive_handle.write().unwrap().add_file_callback =
+        callback.map(|func| Callback { func, user_data });
+    set_last_error(ERROR_SUCCESS);
+    true
+}
 
-    #[test]
-    fn test_error_handling() {
-        set_last_error(ERROR_FILE_NOT_FOUND);
-        assert_eq!(SFileGetLastError(), ERROR_FILE_NOT_FOUND);
+/// Register a progress callback for `SFileCompactArchive`
+///
+/// Pass a null callback to remove it. The same restrictions as for
+/// `SFileSetAddFileCallback` apply.
+#[no_mangle]
+pub extern "C" fn SFileSetCompactCallback(
+    archive: HANDLE,
+    callback: SFILE_COMPACT_CALLBACK,
+    user_data: *mut c_void,
+) -> bool {
+    let Some(archive_handle) = handle_to_id(archive).and_then(get_archive) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
 
-        SFileSetLastError(ERROR_SUCCESS);
-        assert_eq!(SFileGetLastError(), ERROR_SUCCESS);
-    }
+    archive_handle.write().unwrap().compact_callback =
+        callback.map(|func| Callback { func, user_data });
+    set_last_error(ERROR_SUCCESS);
+    true
+}
 
-    #[test]
-    fn test_locale() {
-        let old = SFileSetLocale(0x409); // US English
-        assert_eq!(old, LOCALE_NEUTRAL);
-        assert_eq!(SFileGetLocale(), 0x409);
+// Reports progress of one added file to an SFILE_ADDFILE_CALLBACK
+//
+// The callback takes 32-bit sizes as in StormLib, so sizes of 4 GiB and
+// more are reported as u32::MAX
+struct AddFileProgress {
+    callback: Callback<extern "C" fn(*mut c_void, u32, u32, bool)>,
+    name: String,
+    total: u32,
+}
 
-        SFileSetLocale(old); // Restore
+impl BuildObserver for AddFileProgress {
+    fn on_file_start(&mut self, name: &str, _index: usize, _total: usize) {
+        if name == self.name {
+            (self.callback.func)(self.callback.user_data, 0, self.total, false);
+        }
+    }
+
+    fn on_file_progress(&mut self, name: &str, processed: u64, _total: u64) {
+        if name == self.name {
+            let processed = u32::try_from(processed).unwrap_or(u32::MAX);
+            (self.callback.func)(self.callback.user_data, processed, self.total, false);
+        }
+    }
+
+    fn on_file_done(&mut self, name: &str, _compressed_size: u64, _ratio: f64) {
+        if name == self.name {
+            (self.callback.func)(self.callback.user_data, self.total, self.total, true);
+        }
+    }
+}
+
+// Reports progress of a compaction to an SFILE_COMPACT_CALLBACK
+struct CompactProgress {
+    callback: Callback<extern "C" fn(*mut c_void, u32, u64, u64)>,
+    sizes: HashMap<String, u64>,
+    processed: u64,
+    total: u64,
+}
+
+impl CompactProgress {
+    fn report(&self, work_type: u32, processed: u64) {
+        (self.callback.func)(self.callback.user_data, work_type, processed, self.total);
+    }
+}
+
+impl BuildObserver for CompactProgress {
+    fn on_file_progress(&mut self, _name: &str, processed: u64, _total: u64) {
+        self.report(CCB_COMPACTING_FILES, self.processed + processed);
+    }
+
+    fn on_file_done(&mut self, name: &str, _compressed_size: u64, _ratio: f64) {
+        self.processed += self.sizes.get(name).copied().unwrap_or(0);
+        self.report(CCB_COMPACTING_FILES, self.processed);
+    }
+
+    fn on_finish(&mut self, _archive_size: u64, _file_count: usize, error: Option<&mopaq::Error>) {
+        if error.is_none() {
+            self.report(CCB_CLOSING_ARCHIVE, self.total);
+        }
+    }
+}
+
+// Rewrite an open archive from a builder and reopen it in place
+fn rebuild_archive(
+    archive_handle: &mut ArchiveHandle,
+    builder: ArchiveBuilder,
+) -> mopaq::Result<()> {
+    builder.build(&archive_handle.path)?;
+    archive_handle.archive = Archive::open(&archive_handle.path)?;
+    Ok(())
+}
+
+// Append a file to an open archive in place and reopen it
+fn add_file_in_place(
+    archive_handle: &mut ArchiveHandle,
+    name: &str,
+    data: &[u8],
+    options: &AddFileOptions,
+) -> mopaq::Result<()> {
+    let mut archive = MutableArchive::open(&archive_handle.path)?;
+    if let Some(callback) = archive_handle.add_file_callback {
+        archive.set_observer(AddFileProgress {
+            callback,
+            name: name.to_string(),
+            total: u32::try_from(data.len()).unwrap_or(u32::MAX),
+        });
+    }
+    archive.add_file_data(data, name, options)?;
+    archive.flush()?;
+    archive_handle.archive = Archive::open(&archive_handle.path)?;
+    Ok(())
+}
+
+// Remove one locale's version of a file in place and reopen the archive
+fn remove_file_in_place(
+    archive_handle: &mut ArchiveHandle,
+    name: &str,
+    locale: Locale,
+) -> mopaq::Result<()> {
+    let mut archive = MutableArchive::open(&archive_handle.path)?;
+    archive.remove_file_with_locale(name, locale)?;
+    archive.flush()?;
+    archive_handle.archive = Archive::open(&archive_handle.path)?;
+    Ok(())
+}
+
+// Whether `name` is one of the files the library keeps up to date itself
+fn is_internal_file(name: &str) -> bool {
+    SpecialFile::from_name(name).is_some_and(SpecialFile::is_derived)
+}
+
+// Whether the (listfile) names every file, so a rebuild can copy them all
+fn listfile_is_complete(archive: &Archive) -> mopaq::Result<bool> {
+    Ok(archive.find_file("(listfile)")?.is_some() && archive.anonymous_entries()?.is_empty())
+}
+
+/// Add a file from disk to an archive
+///
+/// The file's data is appended to the archive in place, replacing the file
+/// stored for the same locale if `MPQ_FILE_REPLACEEXISTING` is set, and the
+/// name is added to the `(listfile)` if there is one. Compression is applied
+/// when `MPQ_FILE_COMPRESS` is set in `flags`, using the `MPQ_COMPRESSION_*`
+/// mask in `compression` for a single-unit file or the first sector and the
+/// one in `compression_next` for the sectors after it, unless that is
+/// `MPQ_COMPRESSION_NEXT_SAME`. `MPQ_FILE_IMPLODE` instead implodes the file
+/// with PKWare DCL, ignoring both masks, and cannot be combined with
+/// `MPQ_FILE_COMPRESS`. `MPQ_FILE_ENCRYPTED`, `MPQ_FILE_FIX_KEY`,
+/// `MPQ_FILE_SINGLE_UNIT` and `MPQ_FILE_SECTOR_CRC` are honoured as well;
+/// any other flag fails with `ERROR_INVALID_PARAMETER`. The file is stored with the locale set by
+/// `SFileSetLocale`. Progress is reported to the callback registered with
+/// `SFileSetAddFileCallback`. `(listfile)`, `(attributes)` and `(signature)`
+/// are maintained by the library and fail with `ERROR_INTERNAL_FILE`.
+///
+/// # Safety
+///
+/// - `file_name` must be a valid null-terminated C string
+/// - `archived_name` must be a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn SFileAddFileEx(
+    archive: HANDLE,
+    file_name: *const c_char,
+    archived_name: *const c_char,
+    flags: u32,
+    compression: u32,
+    compression_next: u32,
+) -> bool {
+    let compress_mask = MPQ_FILE_IMPLODE | MPQ_FILE_COMPRESS;
+    if file_name.is_null()
+        || archived_name.is_null()
+        || flags & !ADD_FILE_FLAGS != 0
+        || flags & compress_mask == compress_mask
+    {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+
+    let Some(archive_id) = handle_to_id(archive) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    let (Ok(source), Ok(name)) = (
+        CStr::from_ptr(file_name).to_str(),
+        CStr::from_ptr(archived_name).to_str(),
+    ) else {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    };
+    if is_internal_file(name) {
+        set_last_error(ERROR_INTERNAL_FILE);
+        return false;
+    }
+
+    let data = match fs::read(source) {
+        Ok(data) => data,
+        Err(e) => {
+            set_last_error(error_code(&e.into()));
+            return false;
+        }
+    };
+
+    let Some(archive_handle) = get_archive(archive_id) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+    let mut archive_handle = archive_handle.write().unwrap();
+    if archive_handle.path.is_empty() {
+        // Opened from a descriptor, so there is no path to write to
+        set_last_error(ERROR_NOT_SUPPORTED);
+        return false;
+    }
+
+    let locale = LOCALE.with(|l| *l.borrow());
+    match archive_handle.archive.file_locales(name) {
+        Ok(locales) if locales.contains(&locale) && flags & MPQ_FILE_REPLACEEXISTING == 0 => {
+            set_last_error(ERROR_ALREADY_EXISTS);
+            return false;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            set_last_error(error_code(&e));
+            return false;
+        }
+    }
+
+    let options = if flags & MPQ_FILE_IMPLODE != 0 {
+        AddFileOptions::new()
+            .compression(compression_flags::PKWARE)
+            .implode(true)
+    } else if flags & MPQ_FILE_COMPRESS != 0 {
+        let options = AddFileOptions::new().compression(compression as u8);
+        if compression_next == MPQ_COMPRESSION_NEXT_SAME {
+            options
+        } else {
+            options.compression_next(compression_next as u8)
+        }
+    } else {
+        AddFileOptions::new().compression(0)
+    };
+    let options = options
+        .encrypt(flags & MPQ_FILE_ENCRYPTED != 0)
+        .fix_key(flags & MPQ_FILE_FIX_KEY != 0)
+        .sector_crc(flags & MPQ_FILE_SECTOR_CRC != 0)
+        .single_unit(flags & MPQ_FILE_SINGLE_UNIT != 0)
+        .locale(locale);
+
+    match add_file_in_place(&mut archive_handle, name, &data, &options) {
+        Ok(()) => {
+            set_last_error(ERROR_SUCCESS);
+            true
+        }
+        Err(e) => {
+            set_last_error(error_code(&e));
+            false
+        }
+    }
+}
+
+/// Remove a file from an archive
+///
+/// The version of the file stored for the locale set by `SFileSetLocale` is
+/// removed in place and its name dropped from the `(listfile)` unless other
+/// locales remain; its data stays behind as unused space until the archive
+/// is compacted. `(listfile)`, `(attributes)` and `(signature)` are
+/// maintained by the library and fail with `ERROR_INTERNAL_FILE`.
+///
+/// # Safety
+///
+/// - `file_name` must be a valid null-terminated C string
+/// - `_search_scope` is ignored
+#[no_mangle]
+pub unsafe extern "C" fn SFileRemoveFile(
+    archive: HANDLE,
+    file_name: *const c_char,
+    _search_scope: u32,
+) -> bool {
+    if file_name.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+    let Ok(name) = CStr::from_ptr(file_name).to_str() else {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    };
+    if is_internal_file(name) {
+        set_last_error(ERROR_INTERNAL_FILE);
+        return false;
+    }
+
+    let Some(archive_handle) = handle_to_id(archive).and_then(get_archive) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+    let mut archive_handle = archive_handle.write().unwrap();
+    if archive_handle.path.is_empty() {
+        set_last_error(ERROR_NOT_SUPPORTED);
+        return false;
+    }
+
+    let locale = LOCALE.with(|l| *l.borrow());
+    match remove_file_in_place(&mut archive_handle, name, locale) {
+        Ok(()) => {
+            set_last_error(ERROR_SUCCESS);
+            true
+        }
+        Err(e) => {
+            set_last_error(error_code(&e));
+            false
+        }
+    }
+}
+
+/// Compact an archive, removing unused space
+///
+/// The archive is rebuilt from the files named in its `(listfile)`, dropping
+/// deleted files and any gaps between blocks. Fails with `ERROR_NOT_SUPPORTED`
+/// if there is no `(listfile)` or it does not name every file, since those
+/// files could not be copied. Progress is reported to the callback registered
+/// with `SFileSetCompactCallback`.
+///
+/// # Safety
+///
+/// - `_list_file` is ignored; only the archive's own `(listfile)` is used
+#[no_mangle]
+pub unsafe extern "C" fn SFileCompactArchive(
+    archive: HANDLE,
+    _list_file: *const c_char,
+    _reserved: bool,
+) -> bool {
+    let Some(archive_handle) = handle_to_id(archive).and_then(get_archive) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+    let mut archive_handle = archive_handle.write().unwrap();
+    if archive_handle.path.is_empty() {
+        set_last_error(ERROR_NOT_SUPPORTED);
+        return false;
+    }
+
+    match listfile_is_complete(&archive_handle.archive) {
+        Ok(true) => {}
+        Ok(false) => {
+            set_last_error(ERROR_NOT_SUPPORTED);
+            return false;
+        }
+        Err(e) => {
+            set_last_error(error_code(&e));
+            return false;
+        }
+    }
+
+    let mut progress = match archive_handle.compact_callback {
+        Some(callback) => {
+            let sizes: HashMap<String, u64> = match archive_handle.archive.list() {
+                Ok(entries) => entries.into_iter().map(|e| (e.name, e.size)).collect(),
+                Err(e) => {
+                    set_last_error(error_code(&e));
+                    return false;
+                }
+            };
+            let total = sizes.values().sum();
+            Some(CompactProgress {
+                callback,
+                sizes,
+                processed: 0,
+                total,
+            })
+        }
+        None => None,
+    };
+
+    if let Some(progress) = &progress {
+        progress.report(CCB_CHECKING_FILES, 0);
+    }
+    let mut builder = match ArchiveBuilder::from_archive(&mut archive_handle.archive) {
+        Ok(builder) => builder,
+        Err(e) => {
+            set_last_error(error_code(&e));
+            return false;
+        }
+    };
+    if let Some(progress) = progress.take() {
+        progress.report(CCB_CHECKING_FILES, progress.total);
+        builder = builder.observer(progress);
+    }
+
+    match rebuild_archive(&mut archive_handle, builder) {
+        Ok(()) => {
+            set_last_error(ERROR_SUCCESS);
+            true
+        }
+        Err(e) => {
+            set_last_error(error_code(&e));
+            false
+        }
+    }
+}
+
+// Virtual file system handles
+//
+// A VFS handle searches several archives and loose-file directories as one,
+// so engines get the lookup order of the game without managing archive
+// handles themselves. Files opened through a VFS are ordinary file handles.
+
+/// Create an empty virtual file system
+///
+/// Mount archives and directories with `SVfsMountArchive` and
+/// `SVfsMountDirectory`, then open files with `SVfsOpenFile`. Loose files
+/// override archives; among archives, and among directories, the higher
+/// priority wins, and at equal priority the source mounted later. Names are
+/// matched as in archives, ignoring case and treating `/` as `\`, for loose
+/// files too.
+///
+/// # Safety
+///
+/// - `handle` must be a valid pointer to write the output handle
+#[no_mangle]
+pub unsafe extern "C" fn SVfsCreate(handle: *mut HANDLE) -> bool {
+    if handle.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+
+    let handle_id = next_handle_id();
+    VFSES
+        .write()
+        .unwrap()
+        .insert(handle_id, Arc::new(RwLock::new(MpqVfs::new())));
+    *handle = id_to_handle(handle_id);
+    set_last_error(ERROR_SUCCESS);
+    true
+}
+
+/// Close a virtual file system and every file opened through it
+///
+/// Archives mounted in it are closed as well.
+#[no_mangle]
+pub extern "C" fn SVfsClose(vfs: HANDLE) -> bool {
+    let Some(vfs_id) = handle_to_id(vfs) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    FILES
+        .write()
+        .unwrap()
+        .retain(|_, (owner, _)| *owner != vfs_id);
+
+    if VFSES.write().unwrap().remove(&vfs_id).is_some() {
+        set_last_error(ERROR_SUCCESS);
+        true
+    } else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        false
+    }
+}
+
+/// Open the archive at `filename` and mount it with `priority`
+///
+/// The archive is opened by the VFS and stays open until it is unmounted or
+/// the VFS is closed.
+///
+/// # Safety
+///
+/// - `filename` must be a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn SVfsMountArchive(
+    vfs: HANDLE,
+    filename: *const c_char,
+    priority: i32,
+) -> bool {
+    if filename.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+
+    let Some(vfs_handle) = handle_to_id(vfs).and_then(get_vfs) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    let filename_str = match CStr::from_ptr(filename).to_str() {
+        Ok(s) => s,
+        Err(_) => {
+            set_last_error(ERROR_INVALID_PARAMETER);
+            return false;
+        }
+    };
+
+    // Open before taking the lock, so lookups go on while the tables load
+    let archive = match Archive::open(filename_str) {
+        Ok(archive) => archive,
+        Err(e) => {
+            let code = match e {
+                mopaq::Error::InvalidFormat(_) | mopaq::Error::InvalidHeader(_)
+                    if is_avi_file(filename_str) =>
+                {
+                    ERROR_AVI_FILE
+                }
+                e => error_code(&e),
+            };
+            set_last_error(code);
+            return false;
+        }
+    };
+    vfs_handle
+        .write()
+        .unwrap()
+        .mount_opened(filename_str, archive, priority);
+
+    set_last_error(ERROR_SUCCESS);
+    true
+}
+
+/// Mount the loose files under the directory at `path` with `priority`
+///
+/// The directory is read at lookup time, so files added to it later are
+/// found too. Fails with `ERROR_INVALID_PARAMETER` if `path` is not a
+/// directory.
+///
+/// # Safety
+///
+/// - `path` must be a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn SVfsMountDirectory(
+    vfs: HANDLE,
+    path: *const c_char,
+    priority: i32,
+) -> bool {
+    if path.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+
+    let Some(vfs_handle) = handle_to_id(vfs).and_then(get_vfs) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    let path_str = match CStr::from_ptr(path).to_str() {
+        Ok(s) => s,
+        Err(_) => {
+            set_last_error(ERROR_INVALID_PARAMETER);
+            return false;
+        }
+    };
+
+    let mounted = vfs_handle
+        .write()
+        .unwrap()
+        .mount_directory(path_str, priority);
+    match mounted {
+        Ok(()) => {
+            set_last_error(ERROR_SUCCESS);
+            true
+        }
+        Err(e) => {
+            set_last_error(error_code(&e));
+            false
+        }
+    }
+}
+
+/// Unmount every archive and directory mounted from `path`
+///
+/// Files already opened from them stay readable. Fails with
+/// `ERROR_FILE_NOT_FOUND` if nothing was mounted from `path`.
+///
+/// # Safety
+///
+/// - `path` must be a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn SVfsUnmount(vfs: HANDLE, path: *const c_char) -> bool {
+    if path.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+
+    let Some(vfs_handle) = handle_to_id(vfs).and_then(get_vfs) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    let path_str = match CStr::from_ptr(path).to_str() {
+        Ok(s) => s,
+        Err(_) => {
+            set_last_error(ERROR_INVALID_PARAMETER);
+            return false;
+        }
+    };
+
+    if vfs_handle.write().unwrap().unmount(path_str) {
+        set_last_error(ERROR_SUCCESS);
+        true
+    } else {
+        set_last_error(ERROR_FILE_NOT_FOUND);
+        false
+    }
+}
+
+/// Check if any source mounted in a virtual file system has a file
+///
+/// # Safety
+///
+/// - `filename` must be a valid null-terminated C string
+#[no_mangle]
+pub unsafe extern "C" fn SVfsHasFile(vfs: HANDLE, filename: *const c_char) -> bool {
+    if filename.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+
+    let Some(vfs_handle) = handle_to_id(vfs).and_then(get_vfs) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    let filename_str = match CStr::from_ptr(filename).to_str() {
+        Ok(s) => s,
+        Err(_) => {
+            set_last_error(ERROR_INVALID_PARAMETER);
+            return false;
+        }
+    };
+
+    let found = vfs_handle.read().unwrap().exists(filename_str);
+    match found {
+        Ok(true) => {
+            set_last_error(ERROR_SUCCESS);
+            true
+        }
+        Ok(false) => {
+            set_last_error(ERROR_FILE_NOT_FOUND);
+            false
+        }
+        Err(e) => {
+            set_last_error(error_code(&e));
+            false
+        }
+    }
+}
+
+/// Open a file from the source of a virtual file system that takes
+/// precedence
+///
+/// The handle works with the `SFile*` file functions and is closed with
+/// `SFileCloseFile`, or with the VFS. Files read from loose directories
+/// have no `(attributes)`, so `SFileGetFileInfo` reports
+/// `ERROR_NOT_SUPPORTED` for `SFILE_INFO_FILE_TIME`, `SFILE_INFO_CRC32` and
+/// `SFILE_INFO_MD5` on any file opened this way.
+///
+/// # Safety
+///
+/// - `filename` must be a valid null-terminated C string
+/// - `file_handle` must be a valid pointer to write the output handle
+#[no_mangle]
+pub unsafe extern "C" fn SVfsOpenFile(
+    vfs: HANDLE,
+    filename: *const c_char,
+    file_handle: *mut HANDLE,
+) -> bool {
+    if filename.is_null() || file_handle.is_null() {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    }
+
+    let Some(vfs_id) = handle_to_id(vfs) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+    let Some(vfs_handle) = get_vfs(vfs_id) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    let filename_str = match CStr::from_ptr(filename).to_str() {
+        Ok(s) => s,
+        Err(_) => {
+            set_last_error(ERROR_INVALID_PARAMETER);
+            return false;
+        }
+    };
+
+    let data = vfs_handle.read().unwrap().read(filename_str);
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => {
+            set_last_error(error_code(&e));
+            return false;
+        }
+    };
+
+    let file_id = next_handle_id();
+    let file = FileHandle {
+        filename: filename_str.to_string(),
+        size: data.len() as u64,
+        data,
+        position: 0,
+        attributes: None,
+    };
+    FILES
+        .write()
+        .unwrap()
+        .insert(file_id, (vfs_id, Arc::new(Mutex::new(file))));
+
+    *file_handle = id_to_handle(file_id);
+    set_last_error(ERROR_SUCCESS);
+    true
+}
+
+/// Enumerate the files of a virtual file system
+///
+/// Each name is reported once, as listed by the source it is read from, in
+/// sorted order. Archives contribute the names in their `(listfile)`,
+/// leaving out special files; directories contribute every file below them.
+/// Enumeration stops when `callback` returns false.
+///
+/// # Safety
+///
+/// - `callback` function pointer must be valid for the duration of enumeration
+#[no_mangle]
+pub unsafe extern "C" fn SVfsEnumFiles(
+    vfs: HANDLE,
+    callback: Option<extern "C" fn(*const c_char, *mut c_void) -> bool>,
+    user_data: *mut c_void,
+) -> bool {
+    let Some(callback_fn) = callback else {
+        set_last_error(ERROR_INVALID_PARAMETER);
+        return false;
+    };
+
+    let Some(vfs_handle) = handle_to_id(vfs).and_then(get_vfs) else {
+        set_last_error(ERROR_INVALID_HANDLE);
+        return false;
+    };
+
+    // List files, releasing the VFS before calling back so the callback can
+    // use the API on the same VFS
+    let listing = vfs_handle.write().unwrap().list();
+    let entries = match listing {
+        Ok(entries) => entries,
+        Err(e) => {
+            set_last_error(error_code(&e));
+            return false;
+        }
+    };
+
+    for entry in entries {
+        let Ok(c_name) = CString::new(entry.name) else {
+            continue;
+        };
+        if !callback_fn(c_name.as_ptr(), user_data) {
+            break;
+        }
+    }
+
+    set_last_error(ERROR_SUCCESS);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_conversion() {
+        let id = 42usize;
+        let handle = id_to_handle(id);
+        assert_eq!(handle_to_id(handle), Some(id));
+        assert_eq!(handle_to_id(ptr::null_mut()), None);
+    }
+
+    #[test]
+    fn test_error_handling() {
+        set_last_error(ERROR_FILE_NOT_FOUND);
+        assert_eq!(SFileGetLastError(), ERROR_FILE_NOT_FOUND);
+
+        SFileSetLastError(ERROR_SUCCESS);
+        assert_eq!(SFileGetLastError(), ERROR_SUCCESS);
+    }
+
+    #[test]
+    fn test_error_code_mapping() {
+        use mopaq::Error;
+        use std::io::{self, ErrorKind};
+
+        let cases = [
+            (
+                Error::Io(io::Error::from(ErrorKind::NotFound)),
+                ERROR_FILE_NOT_FOUND,
+            ),
+            (
+                Error::Io(io::Error::from(ErrorKind::PermissionDenied)),
+                ERROR_ACCESS_DENIED,
+            ),
+            (
+                Error::Io(io::Error::from(ErrorKind::AlreadyExists)),
+                ERROR_ALREADY_EXISTS,
+            ),
+            (
+                Error::Io(io::Error::from(ErrorKind::UnexpectedEof)),
+                ERROR_FILE_INCOMPLETE,
+            ),
+            (
+                Error::Io(io::Error::from(ErrorKind::OutOfMemory)),
+                ERROR_NOT_ENOUGH_MEMORY,
+            ),
+            (
+                Error::Io(io::Error::from(ErrorKind::StorageFull)),
+                ERROR_DISK_FULL,
+            ),
+            (
+                Error::Io(io::Error::from(ErrorKind::InvalidInput)),
+                ERROR_INVALID_PARAMETER,
+            ),
+            (Error::invalid_format("bad"), ERROR_FILE_CORRUPT),
+            (Error::UnsupportedVersion(5), ERROR_BAD_FORMAT),
+            (Error::FileNotFound("a".into()), ERROR_FILE_NOT_FOUND),
+            (Error::hash_table("bad"), ERROR_FILE_CORRUPT),
+            (Error::block_table("bad"), ERROR_FILE_CORRUPT),
+            (Error::crypto("bad"), ERROR_UNKNOWN_FILE_KEY),
+            (Error::compression("bad"), ERROR_FILE_CORRUPT),
+            (
+                Error::SignatureVerification("bad".into()),
+                ERROR_FILE_CORRUPT,
+            ),
+            (Error::InvalidHeader("bad".into()), ERROR_BAD_FORMAT),
+            (Error::ReadOnly, ERROR_ACCESS_DENIED),
+            (
+                Error::OperationNotSupported {
+                    version: 3,
+                    operation: "op".into(),
+                },
+                ERROR_NOT_SUPPORTED,
+            ),
+            (
+                Error::InvalidFileSize {
+                    expected: 1,
+                    actual: 2,
+                },
+                ERROR_FILE_CORRUPT,
+            ),
+            (Error::MemoryMap("bad".into()), ERROR_CAN_NOT_COMPLETE),
+            (Error::InvalidUtf8, ERROR_INVALID_PARAMETER),
+            (Error::CapacityExceeded("full".into()), ERROR_DISK_FULL),
+            (
+                Error::ChecksumMismatch {
+                    file: "a".into(),
+                    expected: 1,
+                    actual: 2,
+                },
+                ERROR_CHECKSUM_ERROR,
+            ),
+            (
+                Error::MD5Mismatch {
+                    table: "hash".into(),
+                },
+                ERROR_CHECKSUM_ERROR,
+            ),
+        ];
+
+        for (error, code) in cases {
+            assert_eq!(error_code(&error), code, "{error}");
+
+            // The code survives the trip through the thread-local last error
+            set_last_error(error_code(&error));
+            assert_eq!(SFileGetLastError(), code);
+        }
+    }
+
+    #[test]
+    fn test_open_avi_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("intro.mpq");
+        let mut avi = b"RIFF\x00\x10\x00\x00AVI LIST".to_vec();
+        avi.resize(1024, 0);
+        fs::write(&path, avi).unwrap();
+
+        let filename = CString::new(path.to_str().unwrap()).unwrap();
+        let mut handle = ptr::null_mut();
+        assert!(!unsafe { SFileOpenArchive(filename.as_ptr(), 0, 0, &mut handle) });
+        assert_eq!(SFileGetLastError(), ERROR_AVI_FILE);
+    }
+
+    #[test]
+    fn test_storm_buffer() {
+        let table = unsafe { std::slice::from_raw_parts(SFileGetStormBuffer(), 0x500) };
+        assert_eq!(table[0], 0x55C6_36E2);
+        assert_eq!(table[0x4FF], 0x7303_286C);
+        assert_eq!(SFileGetStormBuffer(), SFileGetStormBuffer());
+    }
+
+    #[test]
+    fn test_last_error_is_per_thread() {
+        SFileSetLastError(ERROR_FILE_CORRUPT);
+
+        std::thread::spawn(|| {
+            assert_eq!(SFileGetLastError(), ERROR_SUCCESS);
+            SFileSetLastError(ERROR_ACCESS_DENIED);
+            assert_eq!(SFileGetLastError(), ERROR_ACCESS_DENIED);
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(SFileGetLastError(), ERROR_FILE_CORRUPT);
+
+        #[cfg(not(windows))]
+        {
+            SetLastError(ERROR_INVALID_HANDLE);
+            assert_eq!(GetLastError(), ERROR_INVALID_HANDLE);
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_HANDLE);
+        }
+        SFileSetLastError(ERROR_SUCCESS);
+    }
+
+    #[test]
+    fn test_locale() {
+        let old = SFileSetLocale(0x409); // US English
+        assert_eq!(old, Locale::NEUTRAL.code() as u32);
+        assert_eq!(SFileGetLocale(), 0x409);
+
+        SFileSetLocale(old); // Restore
     }
 
     #[test]
@@ -1355,6 +2762,637 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_file_checksums() {
+        use mopaq::special_files::AttributeFlags;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("checksums.mpq");
+        let content = b"checksummed content".to_vec();
+        let crc = crc32fast::hash(&content);
+        let md5: [u8; 16] = <md5::Md5 as md5::Digest>::digest(&content).into();
+        let filetime = 0x01D9_0000_1234_5678u64;
+
+        // Blocks: 0 = data.txt, 1 = (attributes)
+        let mut attributes = Vec::new();
+        attributes.extend_from_slice(&100u32.to_le_bytes());
+        let flags = AttributeFlags::CRC32 | AttributeFlags::FILETIME | AttributeFlags::MD5;
+        attributes.extend_from_slice(&flags.to_le_bytes());
+        attributes.extend_from_slice(&crc.to_le_bytes());
+        attributes.extend_from_slice(&0u32.to_le_bytes());
+        attributes.extend_from_slice(&filetime.to_le_bytes());
+        attributes.extend_from_slice(&0u64.to_le_bytes());
+        attributes.extend_from_slice(&md5);
+        attributes.extend_from_slice(&[0u8; 16]);
+
+        ArchiveBuilder::new()
+            .listfile_option(ListfileOption::None)
+            .add_file_data(content, "data.txt")
+            .add_file_data(attributes, "(attributes)")
+            .build(&path)
+            .unwrap();
+
+        let filename = CString::new(path.to_str().unwrap()).unwrap();
+        let mut archive = ptr::null_mut();
+        unsafe {
+            assert!(SFileOpenArchive(filename.as_ptr(), 0, 0, &mut archive));
+
+            let mut actual_crc = 0u32;
+            let mut actual_md5 = [0u8; 16];
+            assert!(SFileGetFileChecksums(
+                archive,
+                c"data.txt".as_ptr(),
+                &mut actual_crc,
+                actual_md5.as_mut_ptr()
+            ));
+            assert_eq!(actual_crc, crc);
+            assert_eq!(actual_md5, md5);
+
+            assert!(!SFileGetFileChecksums(
+                archive,
+                c"missing.txt".as_ptr(),
+                &mut actual_crc,
+                ptr::null_mut()
+            ));
+            assert_eq!(SFileGetLastError(), ERROR_FILE_NOT_FOUND);
+
+            // The same values are available through file handles
+            let mut file = ptr::null_mut();
+            assert!(SFileOpenFileEx(archive, c"data.txt".as_ptr(), 0, &mut file));
+            let mut time = 0u64;
+            let mut needed = 0u32;
+            assert!(SFileGetFileInfo(
+                file,
+                SFILE_INFO_FILE_TIME,
+                &mut time as *mut u64 as *mut c_void,
+                8,
+                &mut needed
+            ));
+            assert_eq!((time, needed), (filetime, 8));
+
+            let mut small = [0u8; 8];
+            assert!(!SFileGetFileInfo(
+                file,
+                SFILE_INFO_MD5,
+                small.as_mut_ptr() as *mut c_void,
+                8,
+                &mut needed
+            ));
+            assert_eq!(SFileGetLastError(), ERROR_INSUFFICIENT_BUFFER);
+            assert_eq!(needed, 16);
+
+            assert!(SFileCloseFile(file));
+            assert!(SFileCloseArchive(archive));
+        }
+    }
+
+    fn build_test_archive(dir: &Path, name: &str, content: &[u8]) -> CString {
+        let path = dir.join(name);
+        ArchiveBuilder::new()
+            .add_file_data(content.to_vec(), "data.bin")
+            .build(&path)
+            .unwrap();
+        CString::new(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_concurrent_handles() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+
+        let threads: Vec<_> = (0..4u8)
+            .map(|i| {
+                let content = vec![i; 100_000];
+                let path = build_test_archive(temp_dir.path(), &format!("{i}.mpq"), &content);
+                std::thread::spawn(move || unsafe {
+                    let mut archive = ptr::null_mut();
+                    assert!(SFileOpenArchive(path.as_ptr(), 0, 0, &mut archive));
+
+                    for _ in 0..10 {
+                        let mut file = ptr::null_mut();
+                        assert!(SFileOpenFileEx(archive, c"data.bin".as_ptr(), 0, &mut file));
+                        let mut buffer = vec![0u8; content.len()];
+                        let mut read = 0u32;
+                        assert!(SFileReadFile(
+                            file,
+                            buffer.as_mut_ptr() as *mut c_void,
+                            buffer.len() as u32,
+                            &mut read,
+                            ptr::null_mut()
+                        ));
+                        assert_eq!(buffer, content);
+                        assert!(SFileCloseFile(file));
+                    }
+
+                    assert!(SFileCloseArchive(archive));
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reentrant_calls_do_not_deadlock() {
+        extern "C" fn has_file(name: *const c_char, archive: *mut c_void) -> bool {
+            // Calls back into the API on the archive being enumerated
+            assert!(unsafe { SFileHasFile(archive, name) });
+            true
+        }
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = build_test_archive(temp_dir.path(), "reentrant.mpq", b"data");
+
+        unsafe {
+            let mut archive = ptr::null_mut();
+            assert!(SFileOpenArchive(path.as_ptr(), 0, 0, &mut archive));
+            assert!(SFileEnumFiles(
+                archive,
+                ptr::null(),
+                ptr::null(),
+                Some(has_file),
+                archive
+            ));
+            assert!(SFileVerifyArchive(archive, SFILE_VERIFY_ALL_FILES));
+            assert!(SFileCloseArchive(archive));
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_open_archive_from_fd() {
+        use std::os::fd::IntoRawFd;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = build_test_archive(temp_dir.path(), "fd.mpq", b"fd data");
+        let fd = fs::File::open(path.to_str().unwrap())
+            .unwrap()
+            .into_raw_fd();
+
+        unsafe {
+            let mut archive = ptr::null_mut();
+            assert!(!SFileOpenArchiveFromFd(-1, 0, &mut archive));
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_PARAMETER);
+
+            assert!(SFileOpenArchiveFromFd(fd, 0, &mut archive));
+            assert!(SFileHasFile(archive, c"data.bin".as_ptr()));
+
+            let mut file = ptr::null_mut();
+            assert!(SFileOpenFileEx(archive, c"data.bin".as_ptr(), 0, &mut file));
+            let mut buffer = [0u8; 16];
+            let mut read = 0u32;
+            assert!(SFileReadFile(
+                file,
+                buffer.as_mut_ptr() as *mut c_void,
+                buffer.len() as u32,
+                &mut read,
+                ptr::null_mut()
+            ));
+            assert_eq!(&buffer[..read as usize], b"fd data");
+            assert!(SFileCloseFile(file));
+
+            // No name, and nothing to rebuild at
+            let mut name = [0xAAu8; 4];
+            assert!(SFileGetArchiveName(
+                archive,
+                name.as_mut_ptr() as *mut c_char,
+                name.len() as u32
+            ));
+            assert_eq!(name[0], 0);
+            assert!(!SFileCompactArchive(archive, ptr::null(), false));
+            assert_eq!(SFileGetLastError(), ERROR_NOT_SUPPORTED);
+
+            assert!(SFileCloseArchive(archive));
+        }
+    }
+
+    #[test]
+    fn test_buffer_bounds() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("bounds.mpq");
+        let long_name = format!("data\\{}.txt", "x".repeat(300));
+        ArchiveBuilder::new()
+            .default_compression(0)
+            .add_file_data(b"short".to_vec(), "short.txt")
+            .add_file_data(b"long".to_vec(), &long_name)
+            .build(&path)
+            .unwrap();
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let long_name = CString::new(long_name).unwrap();
+
+        unsafe {
+            let mut archive = ptr::null_mut();
+            assert!(SFileOpenArchive(path.as_ptr(), 0, 0, &mut archive));
+
+            // Reads never go past `to_read`, and `read` is reset on failure
+            let mut file = ptr::null_mut();
+            assert!(SFileOpenFileEx(
+                archive,
+                c"short.txt".as_ptr(),
+                0,
+                &mut file
+            ));
+            let mut buffer = [0xAAu8; 8];
+            let mut read = 99u32;
+            assert!(SFileReadFile(
+                file,
+                buffer.as_mut_ptr() as *mut c_void,
+                3,
+                &mut read,
+                ptr::null_mut()
+            ));
+            assert_eq!((read, &buffer[..4]), (3, &b"sho\xAA"[..]));
+            read = 99;
+            assert!(!SFileReadFile(
+                file,
+                ptr::null_mut(),
+                3,
+                &mut read,
+                ptr::null_mut()
+            ));
+            assert_eq!((read, SFileGetLastError()), (0, ERROR_INVALID_PARAMETER));
+
+            // Query the size, then fill an exact buffer
+            let mut needed = 0u32;
+            assert!(!SFileGetFileNameEx(file, ptr::null_mut(), 0, &mut needed));
+            assert_eq!(SFileGetLastError(), ERROR_INSUFFICIENT_BUFFER);
+            assert_eq!(needed, 10);
+            let mut name = vec![0xAAu8; needed as usize + 1];
+            assert!(!SFileGetFileNameEx(
+                file,
+                name.as_mut_ptr() as *mut c_char,
+                needed - 1,
+                &mut needed
+            ));
+            assert!(name.iter().all(|&b| b == 0xAA));
+            assert!(SFileGetFileNameEx(
+                file,
+                name.as_mut_ptr() as *mut c_char,
+                needed,
+                ptr::null_mut()
+            ));
+            assert_eq!(&name, b"short.txt\0\xAA");
+            assert!(!SFileGetFileNameEx(file, ptr::null_mut(), 4, &mut needed));
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_PARAMETER);
+            assert!(SFileCloseFile(file));
+
+            // Names longer than MAX_PATH are refused by the classic call
+            assert!(SFileOpenFileEx(archive, long_name.as_ptr(), 0, &mut file));
+            let mut name = vec![0xAAu8; MAX_PATH as usize];
+            assert!(!SFileGetFileName(file, name.as_mut_ptr() as *mut c_char));
+            assert_eq!(SFileGetLastError(), ERROR_INSUFFICIENT_BUFFER);
+            assert!(name.iter().all(|&b| b == 0xAA));
+            let mut needed = 0u32;
+            assert!(!SFileGetFileNameEx(file, ptr::null_mut(), 0, &mut needed));
+            assert_eq!(needed as usize, long_name.as_bytes_with_nul().len());
+            assert!(SFileCloseFile(file));
+
+            let mut archive_name = vec![0u8; path.as_bytes().len()];
+            assert!(!SFileGetArchiveName(
+                archive,
+                archive_name.as_mut_ptr() as *mut c_char,
+                archive_name.len() as u32
+            ));
+            assert_eq!(SFileGetLastError(), ERROR_INSUFFICIENT_BUFFER);
+
+            assert!(SFileCloseArchive(archive));
+        }
+    }
+
+    #[test]
+    fn test_add_file_and_compact_callbacks() {
+        extern "C" fn on_add(user_data: *mut c_void, written: u32, total: u32, last: bool) {
+            let calls = unsafe { &mut *(user_data as *mut Vec<(u32, u32, bool)>) };
+            calls.push((written, total, last));
+        }
+
+        extern "C" fn on_compact(user_data: *mut c_void, work: u32, done: u64, total: u64) {
+            let calls = unsafe { &mut *(user_data as *mut Vec<(u32, u64, u64)>) };
+            calls.push((work, done, total));
+        }
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let archive_path =
+            CString::new(temp_dir.path().join("callbacks.mpq").to_str().unwrap()).unwrap();
+        let source = temp_dir.path().join("source.bin");
+        fs::write(&source, vec![0x5Au8; 10_000]).unwrap();
+        let source = CString::new(source.to_str().unwrap()).unwrap();
+
+        let mut add_calls: Vec<(u32, u32, bool)> = Vec::new();
+        let mut compact_calls: Vec<(u32, u64, u64)> = Vec::new();
+
+        unsafe {
+            let mut archive = ptr::null_mut();
+            assert!(SFileCreateArchive(
+                archive_path.as_ptr(),
+                CREATE_ALWAYS,
+                16,
+                &mut archive
+            ));
+            assert!(SFileSetAddFileCallback(
+                archive,
+                Some(on_add),
+                &mut add_calls as *mut _ as *mut c_void
+            ));
+            assert!(SFileAddFileEx(
+                archive,
+                source.as_ptr(),
+                c"data\\source.bin".as_ptr(),
+                MPQ_FILE_COMPRESS,
+                0x02, // zlib
+                0
+            ));
+            assert!(SFileHasFile(archive, c"data\\source.bin".as_ptr()));
+
+            // Adding it again requires MPQ_FILE_REPLACEEXISTING
+            assert!(!SFileAddFileEx(
+                archive,
+                source.as_ptr(),
+                c"data\\source.bin".as_ptr(),
+                0,
+                0,
+                0
+            ));
+            assert_eq!(SFileGetLastError(), ERROR_ALREADY_EXISTS);
+
+            assert!(SFileSetCompactCallback(
+                archive,
+                Some(on_compact),
+                &mut compact_calls as *mut _ as *mut c_void
+            ));
+            assert!(SFileCompactArchive(archive, ptr::null(), false));
+            assert!(SFileHasFile(archive, c"data\\source.bin".as_ptr()));
+            assert!(SFileCloseArchive(archive));
+        }
+
+        // Start, one call per 4 KiB sector, then the final call
+        assert_eq!(
+            add_calls,
+            [
+                (0, 10_000, false),
+                (4096, 10_000, false),
+                (8192, 10_000, false),
+                (10_000, 10_000, false),
+                (10_000, 10_000, true)
+            ]
+        );
+
+        let total = compact_calls[0].2;
+        assert!(total >= 10_000);
+        assert_eq!(compact_calls.first(), Some(&(CCB_CHECKING_FILES, 0, total)));
+        assert!(compact_calls.contains(&(CCB_COMPACTING_FILES, total, total)));
+        assert_eq!(
+            compact_calls.last(),
+            Some(&(CCB_CLOSING_ARCHIVE, total, total))
+        );
+    }
+
+    #[test]
+    fn test_internal_files_cannot_be_added_or_removed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let archive_path =
+            CString::new(temp_dir.path().join("internal.mpq").to_str().unwrap()).unwrap();
+        let source = temp_dir.path().join("names.txt");
+        fs::write(&source, "fake.txt\r\n").unwrap();
+        let source = CString::new(source.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let mut archive = ptr::null_mut();
+            assert!(SFileCreateArchive(
+                archive_path.as_ptr(),
+                CREATE_ALWAYS,
+                16,
+                &mut archive
+            ));
+
+            for name in [c"(listfile)", c"(ATTRIBUTES)", c"(signature)"] {
+                assert!(!SFileAddFileEx(
+                    archive,
+                    source.as_ptr(),
+                    name.as_ptr(),
+                    MPQ_FILE_REPLACEEXISTING,
+                    0,
+                    0
+                ));
+                assert_eq!(SFileGetLastError(), ERROR_INTERNAL_FILE);
+                assert!(!SFileRemoveFile(archive, name.as_ptr(), 0));
+                assert_eq!(SFileGetLastError(), ERROR_INTERNAL_FILE);
+            }
+
+            // Ordinary files can be added and removed again
+            assert!(SFileAddFileEx(
+                archive,
+                source.as_ptr(),
+                c"names.txt".as_ptr(),
+                0,
+                0,
+                0
+            ));
+            assert!(SFileRemoveFile(archive, c"names.txt".as_ptr(), 0));
+            assert!(!SFileHasFile(archive, c"names.txt".as_ptr()));
+            assert!(!SFileRemoveFile(archive, c"names.txt".as_ptr(), 0));
+            assert_eq!(SFileGetLastError(), ERROR_FILE_NOT_FOUND);
+            assert!(SFileCloseArchive(archive));
+        }
+    }
+
+    #[test]
+    fn test_add_file_keeps_unlisted_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("unlisted.mpq");
+        let listfile = temp_dir.path().join("listfile.txt");
+        fs::write(&listfile, "known.txt\r\n").unwrap();
+        ArchiveBuilder::new()
+            .listfile_option(ListfileOption::External(listfile))
+            .add_file_data(b"known".to_vec(), "known.txt")
+            .add_file_data(b"hidden".to_vec(), "hidden.txt")
+            .build(&path)
+            .unwrap();
+
+        let source = temp_dir.path().join("source.bin");
+        let data = vec![0x5Au8; 10_000];
+        fs::write(&source, &data).unwrap();
+        let source = CString::new(source.to_str().unwrap()).unwrap();
+        let filename = CString::new(path.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let mut archive = ptr::null_mut();
+            assert!(SFileOpenArchive(filename.as_ptr(), 0, 0, &mut archive));
+            // Only the first 4 KiB sector is compressed
+            assert!(SFileAddFileEx(
+                archive,
+                source.as_ptr(),
+                c"new.bin".as_ptr(),
+                MPQ_FILE_COMPRESS,
+                0x02, // zlib
+                0
+            ));
+            assert!(SFileHasFile(archive, c"hidden.txt".as_ptr()));
+
+            assert!(SFileAddFileEx(
+                archive,
+                source.as_ptr(),
+                c"crc.bin".as_ptr(),
+                MPQ_FILE_COMPRESS | MPQ_FILE_SECTOR_CRC,
+                0x02, // zlib
+                MPQ_COMPRESSION_NEXT_SAME
+            ));
+            // Flags the library does not know are not silently dropped
+            assert!(!SFileAddFileEx(
+                archive,
+                source.as_ptr(),
+                c"bad.bin".as_ptr(),
+                0x0010_0000,
+                0,
+                0
+            ));
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_PARAMETER);
+
+            // Compacting would lose the unlisted file
+            assert!(!SFileCompactArchive(archive, ptr::null(), false));
+            assert_eq!(SFileGetLastError(), ERROR_NOT_SUPPORTED);
+            assert!(SFileHasFile(archive, c"hidden.txt".as_ptr()));
+            assert!(SFileCloseArchive(archive));
+        }
+
+        let mut archive = Archive::open(&path).unwrap();
+        assert_eq!(archive.read_file("hidden.txt").unwrap(), b"hidden");
+        assert_eq!(archive.read_file("new.bin").unwrap(), data);
+        let info = archive.find_file("new.bin").unwrap().unwrap();
+        assert!(info.compressed_size > 10_000 - 4096);
+        let crc = archive.find_file("crc.bin").unwrap().unwrap();
+        assert!(crc.has_sector_crc() && crc.compressed_size < 1_000);
+        assert_eq!(archive.read_file("crc.bin").unwrap(), data);
+        assert!(archive.find_file("bad.bin").unwrap().is_none());
+        assert!(archive
+            .list()
+            .unwrap()
+            .iter()
+            .any(|entry| entry.name == "new.bin"));
+    }
+
+    #[test]
+    fn test_add_file_single_unit_and_implode() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("layouts.mpq");
+        ArchiveBuilder::new().build(&path).unwrap();
+
+        let source = temp_dir.path().join("source.bin");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &data).unwrap();
+        let source = CString::new(source.to_str().unwrap()).unwrap();
+        let filename = CString::new(path.to_str().unwrap()).unwrap();
+
+        let layouts = [
+            (c"single.bin", MPQ_FILE_COMPRESS | MPQ_FILE_SINGLE_UNIT),
+            (c"imploded.bin", MPQ_FILE_IMPLODE),
+            (c"both.bin", MPQ_FILE_IMPLODE | MPQ_FILE_SINGLE_UNIT),
+        ];
+        unsafe {
+            let mut archive = ptr::null_mut();
+            assert!(SFileOpenArchive(filename.as_ptr(), 0, 0, &mut archive));
+            for (name, flags) in layouts {
+                assert!(SFileAddFileEx(
+                    archive,
+                    source.as_ptr(),
+                    name.as_ptr(),
+                    flags,
+                    0x02, // zlib, ignored when imploding
+                    MPQ_COMPRESSION_NEXT_SAME
+                ));
+            }
+            // A file is either compressed or imploded
+            assert!(!SFileAddFileEx(
+                archive,
+                source.as_ptr(),
+                c"bad.bin".as_ptr(),
+                MPQ_FILE_COMPRESS | MPQ_FILE_IMPLODE,
+                0x02,
+                0
+            ));
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_PARAMETER);
+            assert!(SFileCloseArchive(archive));
+        }
+
+        let archive = Archive::open(&path).unwrap();
+        for (name, flags) in layouts {
+            let name = name.to_str().unwrap();
+            let info = archive.find_file(name).unwrap().unwrap();
+            assert_eq!(info.flags & flags, flags, "{name}");
+            assert!(info.compressed_size < data.len() as u64, "{name}");
+            assert_eq!(archive.read_file(name).unwrap(), data, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_close_does_not_wait_for_busy_files() {
+        let file_id = next_handle_id();
+        let file = Arc::new(Mutex::new(FileHandle {
+            filename: "busy.bin".to_string(),
+            data: Vec::new(),
+            position: 0,
+            size: 0,
+            attributes: None,
+        }));
+        FILES
+            .write()
+            .unwrap()
+            .insert(file_id, (next_handle_id(), file.clone()));
+
+        // Hold the file's lock the way a long SFileReadFile would
+        let busy = file.lock().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let unknown = id_to_handle(next_handle_id());
+            sender.send(SFileCloseArchive(unknown)).unwrap();
+        });
+        assert_eq!(
+            receiver.recv_timeout(std::time::Duration::from_secs(10)),
+            Ok(false)
+        );
+
+        drop(busy);
+        FILES.write().unwrap().remove(&file_id);
+    }
+
+    #[test]
+    fn test_file_size_above_4gib() {
+        // Sizes come from the archive's tables; the data is not needed
+        let file_id = next_handle_id();
+        FILES.write().unwrap().insert(
+            file_id,
+            (
+                0,
+                Arc::new(Mutex::new(FileHandle {
+                    filename: "huge.bin".to_string(),
+                    data: vec![0; 16],
+                    position: 0,
+                    size: (1 << 32) + 7,
+                    attributes: None,
+                })),
+            ),
+        );
+        let file = id_to_handle(file_id);
+
+        unsafe {
+            let mut high = 0u32;
+            assert_eq!(SFileGetFileSize(file, &mut high), 7);
+            assert_eq!(high, 1);
+
+            let mut size = 0u64;
+            assert!(SFileGetFileInfo(
+                file,
+                SFILE_INFO_FILE_SIZE,
+                &mut size as *mut u64 as *mut c_void,
+                8,
+                ptr::null_mut()
+            ));
+            assert_eq!(size, (1 << 32) + 7);
+            assert!(SFileCloseFile(file));
+        }
+    }
+
     #[test]
     fn test_verify_archive_invalid_params() {
         // Test SFileVerifyArchive with invalid parameters
@@ -1408,4 +3446,108 @@ mod tests {
             assert_eq!(SFileGetLastError(), ERROR_INVALID_PARAMETER);
         }
     }
+
+    #[test]
+    fn test_vfs() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let base = temp_dir.path().join("base.mpq");
+        ArchiveBuilder::new()
+            .add_file_data(b"base".to_vec(), "data.bin")
+            .add_file_data(b"base only".to_vec(), "Units\\Footman.mdx")
+            .build(&base)
+            .unwrap();
+        let base = CString::new(base.to_str().unwrap()).unwrap();
+        let patch = build_test_archive(temp_dir.path(), "patch.mpq", b"patch");
+        let loose = temp_dir.path().join("Loose");
+        fs::create_dir_all(loose.join("Units")).unwrap();
+        fs::write(loose.join("Units").join("Peasant.mdx"), b"loose").unwrap();
+        let loose = CString::new(loose.to_str().unwrap()).unwrap();
+
+        extern "C" fn collect(name: *const c_char, user_data: *mut c_void) -> bool {
+            let names = unsafe { &mut *(user_data as *mut Vec<String>) };
+            names.push(
+                unsafe { CStr::from_ptr(name) }
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            );
+            true
+        }
+
+        unsafe {
+            let read_all = |vfs: HANDLE, name: &CStr| {
+                let mut file = ptr::null_mut();
+                assert!(SVfsOpenFile(vfs, name.as_ptr(), &mut file));
+                let mut buffer = [0u8; 16];
+                let mut read = 0u32;
+                assert!(SFileReadFile(
+                    file,
+                    buffer.as_mut_ptr() as *mut c_void,
+                    buffer.len() as u32,
+                    &mut read,
+                    ptr::null_mut()
+                ));
+                assert_eq!(SFileGetFileSize(file, ptr::null_mut()), read);
+                assert!(SFileCloseFile(file));
+                buffer[..read as usize].to_vec()
+            };
+
+            assert!(!SVfsCreate(ptr::null_mut()));
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_PARAMETER);
+
+            let mut vfs = ptr::null_mut();
+            assert!(SVfsCreate(&mut vfs));
+            assert!(!SVfsHasFile(vfs, c"data.bin".as_ptr()));
+            assert_eq!(SFileGetLastError(), ERROR_FILE_NOT_FOUND);
+
+            // The higher priority wins regardless of mount order
+            assert!(SVfsMountArchive(vfs, patch.as_ptr(), 1));
+            assert!(SVfsMountArchive(vfs, base.as_ptr(), 0));
+            assert!(SVfsMountDirectory(vfs, loose.as_ptr(), 0));
+            assert!(!SVfsMountDirectory(vfs, patch.as_ptr(), 0));
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_PARAMETER);
+            assert!(!SVfsMountArchive(vfs, c"missing.mpq".as_ptr(), 0));
+            assert_eq!(SFileGetLastError(), ERROR_FILE_NOT_FOUND);
+
+            assert_eq!(read_all(vfs, c"data.bin"), b"patch");
+            assert_eq!(read_all(vfs, c"units/footman.mdx"), b"base only");
+            assert_eq!(read_all(vfs, c"UNITS\\PEASANT.MDX"), b"loose");
+            assert!(SVfsHasFile(vfs, c"units\\peasant.mdx".as_ptr()));
+
+            let mut file = ptr::null_mut();
+            assert!(!SVfsOpenFile(vfs, c"missing.bin".as_ptr(), &mut file));
+            assert_eq!(SFileGetLastError(), ERROR_FILE_NOT_FOUND);
+
+            let mut names: Vec<String> = Vec::new();
+            assert!(SVfsEnumFiles(
+                vfs,
+                Some(collect),
+                &mut names as *mut Vec<String> as *mut c_void
+            ));
+            assert_eq!(
+                names,
+                ["Units\\Footman.mdx", "Units\\Peasant.mdx", "data.bin"]
+            );
+
+            // VFS handles are not archive handles, and the other way round
+            assert!(!SFileHasFile(vfs, c"data.bin".as_ptr()));
+            let mut archive = ptr::null_mut();
+            assert!(SFileOpenArchive(base.as_ptr(), 0, 0, &mut archive));
+            assert!(!SVfsHasFile(archive, c"data.bin".as_ptr()));
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_HANDLE);
+            assert!(SFileCloseArchive(archive));
+
+            assert!(SVfsUnmount(vfs, patch.as_ptr()));
+            assert!(!SVfsUnmount(vfs, patch.as_ptr()));
+            assert_eq!(SFileGetLastError(), ERROR_FILE_NOT_FOUND);
+            assert_eq!(read_all(vfs, c"data.bin"), b"base");
+
+            // Closing the VFS closes the files opened through it
+            assert!(SVfsOpenFile(vfs, c"data.bin".as_ptr(), &mut file));
+            assert!(SVfsClose(vfs));
+            assert!(!SFileCloseFile(file));
+            assert!(!SVfsClose(vfs));
+            assert_eq!(SFileGetLastError(), ERROR_INVALID_HANDLE);
+        }
+    }
 }
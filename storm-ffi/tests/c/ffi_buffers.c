@@ -0,0 +1,199 @@
+/*
+ * Buffer handling of the C API, meant to run under AddressSanitizer
+ *
+ * Every buffer handed to the library is heap allocated with the exact size
+ * under test, so any write past its end is reported by ASAN. Run through
+ * run.sh, which builds the library and compiles this file with
+ * -fsanitize=address.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include "../../include/StormLib.h"
+
+#define CREATE_ALWAYS 2
+
+static int failures = 0;
+
+#define CHECK(cond)                                                          \
+    do {                                                                     \
+        if (!(cond)) {                                                       \
+            fprintf(stderr, "%s:%d: check failed: %s (last error %u)\n",     \
+                    __FILE__, __LINE__, #cond, SFileGetLastError());         \
+            failures++;                                                      \
+        }                                                                    \
+    } while (0)
+
+static const char CONTENT[] = "The quick brown fox jumps over the lazy dog";
+
+static void write_source(const char *path)
+{
+    FILE *f = fopen(path, "wb");
+    if (f == NULL) {
+        perror(path);
+        exit(2);
+    }
+    fwrite(CONTENT, 1, sizeof(CONTENT) - 1, f);
+    fclose(f);
+}
+
+static void test_read_file(HANDLE archive)
+{
+    HANDLE file = NULL;
+    CHECK(SFileOpenFileEx(archive, "data\\fox.txt", 0, &file));
+
+    uint32_t size = SFileGetFileSize(file, NULL);
+    CHECK(size == sizeof(CONTENT) - 1);
+
+    /* One byte at a time into a one byte buffer */
+    char *one = malloc(1);
+    uint32_t read = 0;
+    for (uint32_t i = 0; i < size; i++) {
+        CHECK(SFileReadFile(file, one, 1, &read, NULL));
+        CHECK(read == 1 && *one == CONTENT[i]);
+    }
+    CHECK(SFileReadFile(file, one, 1, &read, NULL));
+    CHECK(read == 0);
+    free(one);
+
+    /* The whole file into an exact buffer, asking for more than is left */
+    CHECK(SFileSetFilePointer(file, 0, NULL, 0) == 0);
+    char *exact = malloc(size);
+    CHECK(SFileReadFile(file, exact, size, &read, NULL));
+    CHECK(read == size && memcmp(exact, CONTENT, size) == 0);
+    free(exact);
+
+    /* Failed reads report zero bytes */
+    read = 1234;
+    CHECK(!SFileReadFile(file, NULL, 16, &read, NULL));
+    CHECK(read == 0);
+    read = 1234;
+    CHECK(!SFileReadFile(NULL, &read, 0, &read, NULL));
+    CHECK(read == 0);
+
+    CHECK(SFileCloseFile(file));
+}
+
+static void test_file_name(HANDLE archive, const char *name)
+{
+    HANDLE file = NULL;
+    CHECK(SFileOpenFileEx(archive, name, 0, &file));
+    uint32_t length = (uint32_t)strlen(name) + 1;
+
+    /* Size query */
+    uint32_t needed = 0;
+    CHECK(!SFileGetFileNameEx(file, NULL, 0, &needed));
+    CHECK(SFileGetLastError() == ERROR_INSUFFICIENT_BUFFER);
+    CHECK(needed == length);
+
+    /* One byte short leaves the buffer untouched */
+    char *small = malloc(length - 1);
+    memset(small, 'X', length - 1);
+    CHECK(!SFileGetFileNameEx(file, small, length - 1, &needed));
+    CHECK(SFileGetLastError() == ERROR_INSUFFICIENT_BUFFER);
+    CHECK(small[0] == 'X' && small[length - 2] == 'X');
+    free(small);
+
+    char *exact = malloc(length);
+    CHECK(SFileGetFileNameEx(file, exact, length, NULL));
+    CHECK(strcmp(exact, name) == 0);
+    free(exact);
+
+    /* The classic call fills at most MAX_PATH bytes */
+    char *classic = malloc(MAX_PATH);
+    if (length <= MAX_PATH) {
+        CHECK(SFileGetFileName(file, classic));
+        CHECK(strcmp(classic, name) == 0);
+    } else {
+        CHECK(!SFileGetFileName(file, classic));
+        CHECK(SFileGetLastError() == ERROR_INSUFFICIENT_BUFFER);
+    }
+    free(classic);
+
+    CHECK(SFileCloseFile(file));
+}
+
+static void test_archive_name(HANDLE archive, const char *path)
+{
+    uint32_t length = (uint32_t)strlen(path) + 1;
+
+    char *small = malloc(length - 1);
+    CHECK(!SFileGetArchiveName(archive, small, length - 1));
+    CHECK(SFileGetLastError() == ERROR_INSUFFICIENT_BUFFER);
+    free(small);
+
+    char *exact = malloc(length);
+    CHECK(SFileGetArchiveName(archive, exact, length));
+    CHECK(strcmp(exact, path) == 0);
+    free(exact);
+}
+
+static void test_file_info(HANDLE archive)
+{
+    HANDLE file = NULL;
+    CHECK(SFileOpenFileEx(archive, "data\\fox.txt", 0, &file));
+
+    uint32_t needed = 0;
+    uint64_t *size = malloc(sizeof(uint64_t));
+    CHECK(SFileGetFileInfo(file, 7 /* SFILE_INFO_FILE_SIZE */, size, sizeof(uint64_t), &needed));
+    CHECK(needed == sizeof(uint64_t) && *size == sizeof(CONTENT) - 1);
+    free(size);
+
+    uint32_t *small = malloc(sizeof(uint32_t));
+    CHECK(!SFileGetFileInfo(file, 7, small, sizeof(uint32_t), &needed));
+    CHECK(SFileGetLastError() == ERROR_INSUFFICIENT_BUFFER);
+    free(small);
+
+    CHECK(SFileCloseFile(file));
+}
+
+int main(void)
+{
+    char dir[] = "/tmp/storm-ffi-XXXXXX";
+    if (mkdtemp(dir) == NULL) {
+        perror("mkdtemp");
+        return 2;
+    }
+
+    char archive_path[64];
+    char source_path[64];
+    snprintf(archive_path, sizeof(archive_path), "%s/buffers.mpq", dir);
+    snprintf(source_path, sizeof(source_path), "%s/fox.txt", dir);
+    write_source(source_path);
+
+    /* A name that does not fit in MAX_PATH */
+    char long_name[400];
+    memset(long_name, 'n', sizeof(long_name));
+    memcpy(long_name, "data\\", 5);
+    long_name[sizeof(long_name) - 1] = '\0';
+
+    HANDLE archive = NULL;
+    CHECK(SFileCreateArchive(archive_path, CREATE_ALWAYS, 16, &archive));
+    CHECK(SFileAddFileEx(archive, source_path, "data\\fox.txt", MPQ_FILE_COMPRESS, 0x02, 0));
+    CHECK(SFileAddFileEx(archive, source_path, long_name, 0, 0, 0));
+    CHECK(SFileCloseArchive(archive));
+
+    archive = NULL;
+    CHECK(SFileOpenArchive(archive_path, 0, 0, &archive));
+    if (archive != NULL) {
+        test_read_file(archive);
+        test_file_name(archive, "data\\fox.txt");
+        test_file_name(archive, long_name);
+        test_archive_name(archive, archive_path);
+        test_file_info(archive);
+        CHECK(SFileCloseArchive(archive));
+    }
+
+    unlink(archive_path);
+    unlink(source_path);
+    rmdir(dir);
+
+    if (failures != 0) {
+        fprintf(stderr, "%d check(s) failed\n", failures);
+        return 1;
+    }
+    printf("ffi_buffers: all checks passed\n");
+    return 0;
+}
@@ -0,0 +1,26 @@
+#!/usr/bin/env bash
+# Build storm-ffi and run the C test suite under AddressSanitizer
+#
+# Usage: storm-ffi/tests/c/run.sh [cc]
+set -euo pipefail
+
+CC="${1:-${CC:-cc}}"
+TESTS_DIR="$(cd "$(dirname "$0")" && pwd)"
+ROOT="$(cd "$TESTS_DIR/../../.." && pwd)"
+TARGET_DIR="${CARGO_TARGET_DIR:-$ROOT/target}"
+OUT_DIR="$TARGET_DIR/ffi-c-tests"
+
+cargo build --manifest-path "$ROOT/Cargo.toml" -p storm-ffi
+mkdir -p "$OUT_DIR"
+
+export ASAN_OPTIONS="${ASAN_OPTIONS:-detect_leaks=1:abort_on_error=1}"
+
+for test in "$TESTS_DIR"/*.c; do
+    name="$(basename "$test" .c)"
+    echo "==> $name"
+    "$CC" -std=c11 -D_DEFAULT_SOURCE -Wall -Wextra -Werror -g -O1 \
+        -fsanitize=address,undefined -fno-omit-frame-pointer \
+        -o "$OUT_DIR/$name" "$test" \
+        "$TARGET_DIR/debug/libstorm.a" -lpthread -ldl -lm
+    "$OUT_DIR/$name"
+done